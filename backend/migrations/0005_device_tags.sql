@@ -0,0 +1,9 @@
+-- Migration: Device tags for grouping cluster devices (e.g. "gpu-lab")
+
+CREATE TABLE IF NOT EXISTS device_tags (
+    device_id TEXT NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (device_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_tags_tag ON device_tags (tag);
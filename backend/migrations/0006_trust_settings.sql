@@ -0,0 +1,9 @@
+-- Migration: Trust thresholds for cluster participation
+-- Devices whose role trust_level is below min_cluster_trust can't be used as
+-- RPC devices; below min_stats_trust their /api/gpu endpoint is not polled.
+
+INSERT INTO settings (key, value) VALUES ('min_cluster_trust', '1')
+    ON CONFLICT(key) DO NOTHING;
+
+INSERT INTO settings (key, value) VALUES ('min_stats_trust', '1')
+    ON CONFLICT(key) DO NOTHING;
@@ -0,0 +1,10 @@
+-- Migration: Outgoing webhook notifications
+
+CREATE TABLE IF NOT EXISTS webhooks (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL DEFAULT '',
+    event_filter TEXT NOT NULL DEFAULT '[]',  -- JSON array of event types; [] = all notable events
+    enabled INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
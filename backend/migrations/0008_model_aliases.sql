@@ -0,0 +1,7 @@
+-- Migration: Friendly model names for the OpenAI-compatible API
+
+CREATE TABLE IF NOT EXISTS model_aliases (
+    alias TEXT PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,  -- absolute path to the .gguf file
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
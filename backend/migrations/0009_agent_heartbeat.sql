@@ -0,0 +1,8 @@
+-- Migration: Authenticated agent heartbeats
+-- Agents authenticate with the shared enrollment token (embedded in the
+-- generated install scripts) or a per-device secret issued on approval.
+
+ALTER TABLE devices ADD COLUMN agent_secret_hash TEXT;  -- SHA-256 hex of the device secret
+
+INSERT INTO settings (key, value) VALUES ('agent_enrollment_token', lower(hex(randomblob(16))))
+    ON CONFLICT(key) DO NOTHING;
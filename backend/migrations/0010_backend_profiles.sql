@@ -0,0 +1,11 @@
+-- Migration: Saved inference backend configurations for quick switching
+
+CREATE TABLE IF NOT EXISTS backend_profiles (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    backend_type TEXT NOT NULL,  -- llamacpp | ollama | lmstudio | vllm | openai | custom
+    url TEXT NOT NULL DEFAULT '',
+    model TEXT NOT NULL DEFAULT '',
+    api_key TEXT NOT NULL DEFAULT '',  -- write-only through the API
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
@@ -0,0 +1,23 @@
+-- Migration: Inference reservations
+-- A running llama-server session reserves memory through the allocations
+-- table. Local reservations have no device, so device_id becomes nullable
+-- (SQLite can't drop NOT NULL in place — rebuild the table).
+
+CREATE TABLE allocations_new (
+    id TEXT PRIMARY KEY,
+    device_id TEXT REFERENCES devices(id) ON DELETE CASCADE,  -- NULL = this host
+    memory_mb INTEGER NOT NULL,
+    provider TEXT NOT NULL,  -- provider id for local reservations, else nvidia | amd | apple | intel | system_ram
+    purpose TEXT NOT NULL DEFAULT 'device',  -- device | inference
+    session_id TEXT,  -- inference session holding the reservation
+    granted_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    revoked_at TEXT
+);
+
+INSERT INTO allocations_new (id, device_id, memory_mb, provider, granted_at, revoked_at)
+    SELECT id, device_id, memory_mb, provider, granted_at, revoked_at FROM allocations;
+
+DROP TABLE allocations;
+ALTER TABLE allocations_new RENAME TO allocations;
+
+CREATE INDEX IF NOT EXISTS idx_allocations_session ON allocations(session_id);
@@ -0,0 +1,59 @@
+-- Migration: Device revisions
+-- Every change to a device (or its tags) stamps the row with the next value of
+-- a global revision counter, so clients can ask for "devices changed since
+-- rev N". Deleted devices leave a tombstone carrying the revision of the delete.
+-- A migration adding a devices column must add it to devices_rev_update.
+
+CREATE TABLE IF NOT EXISTS revision (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    value INTEGER NOT NULL
+);
+INSERT OR IGNORE INTO revision (id, value) VALUES (1, 1);
+
+ALTER TABLE devices ADD COLUMN rev INTEGER NOT NULL DEFAULT 0;
+UPDATE devices SET rev = 1;
+
+CREATE TABLE IF NOT EXISTS device_tombstones (
+    id TEXT PRIMARY KEY,
+    rev INTEGER NOT NULL
+);
+
+CREATE TRIGGER IF NOT EXISTS devices_rev_insert AFTER INSERT ON devices
+BEGIN
+    UPDATE revision SET value = value + 1;
+    UPDATE devices SET rev = (SELECT value FROM revision) WHERE id = NEW.id;
+    DELETE FROM device_tombstones WHERE id = NEW.id;
+END;
+
+-- Only real changes bump: re-writing the same probe result is not a change
+CREATE TRIGGER IF NOT EXISTS devices_rev_update AFTER UPDATE ON devices
+WHEN NEW.rev = OLD.rev
+    AND (NEW.name, NEW.ip, NEW.mac, NEW.hostname, NEW.platform, NEW.role_id, NEW.status,
+         NEW.discovery_method, NEW.allocated_memory_mb, NEW.last_seen, NEW.first_seen,
+         NEW.created_at, NEW.rpc_port, NEW.rpc_status, NEW.memory_total_mb, NEW.memory_free_mb)
+    IS NOT
+        (OLD.name, OLD.ip, OLD.mac, OLD.hostname, OLD.platform, OLD.role_id, OLD.status,
+         OLD.discovery_method, OLD.allocated_memory_mb, OLD.last_seen, OLD.first_seen,
+         OLD.created_at, OLD.rpc_port, OLD.rpc_status, OLD.memory_total_mb, OLD.memory_free_mb)
+BEGIN
+    UPDATE revision SET value = value + 1;
+    UPDATE devices SET rev = (SELECT value FROM revision) WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS devices_rev_delete AFTER DELETE ON devices
+BEGIN
+    UPDATE revision SET value = value + 1;
+    INSERT OR REPLACE INTO device_tombstones (id, rev) VALUES (OLD.id, (SELECT value FROM revision));
+END;
+
+CREATE TRIGGER IF NOT EXISTS device_tags_rev_insert AFTER INSERT ON device_tags
+BEGIN
+    UPDATE revision SET value = value + 1;
+    UPDATE devices SET rev = (SELECT value FROM revision) WHERE id = NEW.device_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS device_tags_rev_delete AFTER DELETE ON device_tags
+BEGIN
+    UPDATE revision SET value = value + 1;
+    UPDATE devices SET rev = (SELECT value FROM revision) WHERE id = OLD.device_id;
+END;
@@ -0,0 +1,24 @@
+-- Migration: When a device's RPC server was last seen becoming ready
+-- Lets cluster status tell "never worked" (install problem) apart from
+-- "worked before" (crash or firewall). Stamped only on the transition to
+-- ready, so repeated probes don't bump the device revision.
+
+ALTER TABLE devices ADD COLUMN last_rpc_ready_at TEXT;
+UPDATE devices SET last_rpc_ready_at = last_seen WHERE rpc_status = 'ready';
+
+DROP TRIGGER IF EXISTS devices_rev_update;
+CREATE TRIGGER devices_rev_update AFTER UPDATE ON devices
+WHEN NEW.rev = OLD.rev
+    AND (NEW.name, NEW.ip, NEW.mac, NEW.hostname, NEW.platform, NEW.role_id, NEW.status,
+         NEW.discovery_method, NEW.allocated_memory_mb, NEW.last_seen, NEW.first_seen,
+         NEW.created_at, NEW.rpc_port, NEW.rpc_status, NEW.memory_total_mb, NEW.memory_free_mb,
+         NEW.last_rpc_ready_at)
+    IS NOT
+        (OLD.name, OLD.ip, OLD.mac, OLD.hostname, OLD.platform, OLD.role_id, OLD.status,
+         OLD.discovery_method, OLD.allocated_memory_mb, OLD.last_seen, OLD.first_seen,
+         OLD.created_at, OLD.rpc_port, OLD.rpc_status, OLD.memory_total_mb, OLD.memory_free_mb,
+         OLD.last_rpc_ready_at)
+BEGIN
+    UPDATE revision SET value = value + 1;
+    UPDATE devices SET rev = (SELECT value FROM revision) WHERE id = NEW.id;
+END;
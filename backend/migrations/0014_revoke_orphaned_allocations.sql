@@ -0,0 +1,11 @@
+-- Migration: Revoke device allocations that outlived their device's approval
+-- Denying or suspending a device now revokes its allocations; clean up rows
+-- left active by earlier builds, including ones whose device row is gone.
+
+UPDATE allocations SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+WHERE purpose = 'device' AND revoked_at IS NULL
+  AND (device_id NOT IN (SELECT id FROM devices)
+       OR device_id IN (SELECT id FROM devices WHERE status IN ('denied', 'suspended')));
+
+UPDATE devices SET allocated_memory_mb = 0
+WHERE status IN ('denied', 'suspended') AND allocated_memory_mb != 0;
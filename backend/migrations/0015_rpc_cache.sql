@@ -0,0 +1,7 @@
+-- Migration: llama-rpc-server tensor cache on agents
+-- Agents started with --cache report the cache size in their heartbeat, so
+-- model-check can tell which devices will have to be sent the weights again.
+-- Not part of the revision trigger: heartbeats already bump last_seen.
+
+ALTER TABLE devices ADD COLUMN rpc_cache_enabled INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE devices ADD COLUMN rpc_cache_mb INTEGER NOT NULL DEFAULT 0;
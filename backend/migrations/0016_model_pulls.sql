@@ -0,0 +1,20 @@
+-- Migration: Queued Ollama model pulls
+-- Pulls scheduled for the off-peak window or a later time are kept here and
+-- run one at a time by a background worker. Finished rows stay for the
+-- queue listing.
+
+CREATE TABLE IF NOT EXISTS model_pulls (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',  -- pending | active | completed | failed | cancelled
+    off_peak INTEGER NOT NULL DEFAULT 0,     -- only start inside offpeak_start..offpeak_end
+    not_before TEXT,                         -- RFC 3339; don't start earlier
+    completed_bytes INTEGER,
+    total_bytes INTEGER,
+    error TEXT,
+    created_at TEXT NOT NULL,
+    started_at TEXT,
+    finished_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_model_pulls_status ON model_pulls(status, created_at);
@@ -0,0 +1,22 @@
+-- Migration: Several RPC endpoints per device
+-- A host with two GPUs may run one llama-rpc-server per GPU. rpc_port stays
+-- the first endpoint; extra_rpc_ports is a JSON array of the others.
+
+ALTER TABLE devices ADD COLUMN extra_rpc_ports TEXT NOT NULL DEFAULT '[]';
+
+DROP TRIGGER IF EXISTS devices_rev_update;
+CREATE TRIGGER devices_rev_update AFTER UPDATE ON devices
+WHEN NEW.rev = OLD.rev
+    AND (NEW.name, NEW.ip, NEW.mac, NEW.hostname, NEW.platform, NEW.role_id, NEW.status,
+         NEW.discovery_method, NEW.allocated_memory_mb, NEW.last_seen, NEW.first_seen,
+         NEW.created_at, NEW.rpc_port, NEW.rpc_status, NEW.memory_total_mb, NEW.memory_free_mb,
+         NEW.last_rpc_ready_at, NEW.extra_rpc_ports)
+    IS NOT
+        (OLD.name, OLD.ip, OLD.mac, OLD.hostname, OLD.platform, OLD.role_id, OLD.status,
+         OLD.discovery_method, OLD.allocated_memory_mb, OLD.last_seen, OLD.first_seen,
+         OLD.created_at, OLD.rpc_port, OLD.rpc_status, OLD.memory_total_mb, OLD.memory_free_mb,
+         OLD.last_rpc_ready_at, OLD.extra_rpc_ports)
+BEGIN
+    UPDATE revision SET value = value + 1;
+    UPDATE devices SET rev = (SELECT value FROM revision) WHERE id = NEW.id;
+END;
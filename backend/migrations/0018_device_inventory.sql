@@ -0,0 +1,28 @@
+-- Migration: Hardware inventory reported by agents
+-- All nullable: agents from before this change send no inventory.
+-- disk_free_mb moves with every download, so it doesn't bump the revision.
+
+ALTER TABLE devices ADD COLUMN cpu_model TEXT;
+ALTER TABLE devices ADD COLUMN cpu_cores INTEGER;
+ALTER TABLE devices ADD COLUMN os_version TEXT;
+ALTER TABLE devices ADD COLUMN gpu_driver TEXT;
+ALTER TABLE devices ADD COLUMN disk_free_mb INTEGER;
+
+DROP TRIGGER IF EXISTS devices_rev_update;
+CREATE TRIGGER devices_rev_update AFTER UPDATE ON devices
+WHEN NEW.rev = OLD.rev
+    AND (NEW.name, NEW.ip, NEW.mac, NEW.hostname, NEW.platform, NEW.role_id, NEW.status,
+         NEW.discovery_method, NEW.allocated_memory_mb, NEW.last_seen, NEW.first_seen,
+         NEW.created_at, NEW.rpc_port, NEW.rpc_status, NEW.memory_total_mb, NEW.memory_free_mb,
+         NEW.last_rpc_ready_at, NEW.extra_rpc_ports, NEW.cpu_model, NEW.cpu_cores,
+         NEW.os_version, NEW.gpu_driver)
+    IS NOT
+        (OLD.name, OLD.ip, OLD.mac, OLD.hostname, OLD.platform, OLD.role_id, OLD.status,
+         OLD.discovery_method, OLD.allocated_memory_mb, OLD.last_seen, OLD.first_seen,
+         OLD.created_at, OLD.rpc_port, OLD.rpc_status, OLD.memory_total_mb, OLD.memory_free_mb,
+         OLD.last_rpc_ready_at, OLD.extra_rpc_ports, OLD.cpu_model, OLD.cpu_cores,
+         OLD.os_version, OLD.gpu_driver)
+BEGIN
+    UPDATE revision SET value = value + 1;
+    UPDATE devices SET rev = (SELECT value FROM revision) WHERE id = NEW.id;
+END;
@@ -0,0 +1,27 @@
+-- Migration: Normalize device status values
+-- devices.status and devices.rpc_status are read into enums now, so a row
+-- with any other value would fail to load. Fold case and whitespace first;
+-- a status still unknown goes back to pending approval, an RPC status to offline.
+
+UPDATE devices SET status = lower(trim(status)) WHERE status != lower(trim(status));
+UPDATE devices SET status = 'pending'
+WHERE status NOT IN ('pending', 'approved', 'denied', 'suspended', 'offline');
+
+UPDATE devices SET rpc_status = lower(trim(rpc_status)) WHERE rpc_status != lower(trim(rpc_status));
+UPDATE devices SET rpc_status = 'offline'
+WHERE rpc_status NOT IN ('offline', 'connecting', 'ready', 'error');
+
+-- Keep them that way
+CREATE TRIGGER devices_status_insert_check BEFORE INSERT ON devices
+WHEN NEW.status NOT IN ('pending', 'approved', 'denied', 'suspended', 'offline')
+    OR NEW.rpc_status NOT IN ('offline', 'connecting', 'ready', 'error')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid device status');
+END;
+
+CREATE TRIGGER devices_status_update_check BEFORE UPDATE OF status, rpc_status ON devices
+WHEN NEW.status NOT IN ('pending', 'approved', 'denied', 'suspended', 'offline')
+    OR NEW.rpc_status NOT IN ('offline', 'connecting', 'ready', 'error')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid device status');
+END;
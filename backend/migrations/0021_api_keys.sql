@@ -0,0 +1,16 @@
+-- Migration: Per-client API keys for the OpenAI-compatible endpoints
+-- Only a salted SHA-256 of each key is stored; the key itself is shown once.
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    key_prefix TEXT NOT NULL,                 -- first characters of the key, to tell keys apart
+    salt TEXT NOT NULL,
+    hashed_key TEXT NOT NULL,                 -- hex SHA-256 of salt || key
+    rate_limit_rpm INTEGER,                   -- requests per minute; NULL = unlimited
+    allowed_models TEXT NOT NULL DEFAULT '[]', -- JSON array of model IDs; [] = every model
+    requests INTEGER NOT NULL DEFAULT 0,
+    last_used_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    revoked_at TEXT
+);
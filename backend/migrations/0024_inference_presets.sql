@@ -0,0 +1,16 @@
+-- Migration: named inference presets
+-- A saved set of inference start parameters, started again with one request.
+
+CREATE TABLE IF NOT EXISTS inference_presets (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    model_path TEXT NOT NULL,                  -- alias or absolute .gguf path, as given
+    device_ids TEXT NOT NULL DEFAULT '[]',     -- JSON array of device IDs
+    device_tags TEXT NOT NULL DEFAULT '[]',    -- JSON array of tags, expanded at start
+    n_gpu_layers INTEGER,
+    ctx_size INTEGER,
+    options TEXT NOT NULL DEFAULT '{}',        -- JSON: parallel, cont_batching, gpu_selector, draft model
+    saved_devices TEXT NOT NULL DEFAULT '[]',  -- JSON: [{id, name, ip, rpc_ports}] of device_ids when saved
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
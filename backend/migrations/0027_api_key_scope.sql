@@ -0,0 +1,6 @@
+-- Migration: API key scopes
+-- What a key may do: `inference` (the OpenAI-compatible endpoints), `viewer`
+-- (read-only dashboard) or `admin` (everything). Existing keys were made for
+-- /v1, so they start as inference keys.
+
+ALTER TABLE api_keys ADD COLUMN scope TEXT NOT NULL DEFAULT 'inference';
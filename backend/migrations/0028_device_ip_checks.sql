@@ -0,0 +1,19 @@
+-- Migration: Canonical device IPs
+-- Registration now stores IPs in canonical form (no whitespace, no leading
+-- zeros, compressed lowercase IPv6), so idx_devices_ip (0004) catches one
+-- machine registered under two spellings. SQL can't parse addresses, so rows
+-- written before are rewritten, and their duplicates merged, by the server
+-- at startup (permissions::address::normalize_stored). These triggers keep
+-- the spellings SQL can recognize out from now on.
+
+CREATE TRIGGER devices_ip_insert_check BEFORE INSERT ON devices
+WHEN NEW.ip != lower(trim(NEW.ip)) OR NEW.ip = ''
+BEGIN
+    SELECT RAISE(ABORT, 'device ip must be canonical');
+END;
+
+CREATE TRIGGER devices_ip_update_check BEFORE UPDATE OF ip ON devices
+WHEN NEW.ip != lower(trim(NEW.ip)) OR NEW.ip = ''
+BEGIN
+    SELECT RAISE(ABORT, 'device ip must be canonical');
+END;
@@ -0,0 +1,11 @@
+-- Migration: SHA-256 of shared model files
+-- Hashing a multi-gigabyte GGUF takes a while, so each file is hashed once,
+-- when peers first list it, and hashed again only if its size or mtime changes.
+
+CREATE TABLE IF NOT EXISTS model_file_hashes (
+    path TEXT PRIMARY KEY,
+    size INTEGER NOT NULL,
+    modified_at TEXT NOT NULL,  -- file mtime (RFC 3339) when hashed
+    sha256 TEXT NOT NULL,       -- lowercase hex
+    hashed_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
@@ -0,0 +1,15 @@
+-- Migration: model store
+-- inference_sessions gets a row per llama.cpp session from now on, so the
+-- model usage report can say when a model file was last used. `devices`
+-- holds the session's RPC endpoints (ip:port).
+-- Deleted model files are moved to a .trash directory beside them and
+-- listed in model_trash until they are purged after model_trash_days.
+
+CREATE INDEX IF NOT EXISTS idx_inference_sessions_model_path ON inference_sessions (model_path);
+
+CREATE TABLE IF NOT EXISTS model_trash (
+    trashed_path TEXT PRIMARY KEY,
+    original_path TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    deleted_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
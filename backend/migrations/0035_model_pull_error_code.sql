@@ -0,0 +1,6 @@
+-- Migration: model pull error codes
+-- What kind of failure ended a pull (not_found, disk_full, network or
+-- unknown), read from Ollama's message. Pulls streamed straight to the
+-- caller are recorded here too once they end.
+
+ALTER TABLE model_pulls ADD COLUMN error_code TEXT;
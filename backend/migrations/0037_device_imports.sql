@@ -0,0 +1,13 @@
+-- Migration: device import history
+-- One row per POST /api/devices/import that got past its checks, with how
+-- its rows turned out.
+
+CREATE TABLE IF NOT EXISTS device_imports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    format TEXT NOT NULL,       -- json | csv
+    total INTEGER NOT NULL,
+    imported INTEGER NOT NULL,  -- new devices
+    existing INTEGER NOT NULL,  -- rows whose address was already registered
+    failed INTEGER NOT NULL,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))
+);
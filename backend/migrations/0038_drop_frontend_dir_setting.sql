@@ -0,0 +1,5 @@
+-- Migration: frontend_dir is no longer a setting
+-- The frontend directory is served without auth, so it now comes only from
+-- the FRONTEND_DIR env var. A saved value would be ignored; drop it.
+
+DELETE FROM settings WHERE key = 'frontend_dir';
@@ -1,40 +1,115 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
 use std::sync::Arc;
 
-use crate::AppState;
+use super::openapi::ErrorResponse;
+use crate::{
+    db::queries,
+    federation,
+    inventory::HostInventory,
+    llama_cpp::{RpcBackend, RpcLaunch},
+    permissions::{address::parse_ip, presence, DeviceKind, DeviceStatus, PermissionService, RpcStatus, PAIRING_CODE_TTL_MINUTES},
+    ws::WsEvent,
+    AppState,
+};
+
+/// How often the generated heartbeat loop reports in.
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 30;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct HeartbeatRequest {
+    /// Identify the device by ID (preferred) or by the IP it registered with
+    pub device_id: Option<String>,
+    pub ip: Option<String>,
+    pub memory_total_mb: i64,
+    pub memory_free_mb: i64,
+    pub rpc_port: Option<i64>,
+    #[serde(default)]
+    pub rpc_running: bool,
+    /// Size of llama-rpc-server's tensor cache. Agents that don't report it
+    /// run without `--cache`.
+    pub rpc_cache_mb: Option<i64>,
+    /// Hardware summary; agents that don't send one keep what was stored
+    pub inventory: Option<HostInventory>,
+    /// llama-rpc-server runs as a service the agent can restart when the
+    /// answer says `restart_rpc`
+    #[serde(default)]
+    pub can_restart_rpc: bool,
+}
 
 /// GET /agent/install
 ///
 /// Returns an OS-specific shell script that installs and starts llama-rpc-server.
-/// Query param: ?os=linux|macos|windows (defaults to linux)
+/// Query params: ?os=linux|macos|windows (defaults to linux);
+/// ?persist=true installs a boot-time service (systemd / launchd / Scheduled Task)
+/// instead of a one-off background process;
+/// ?host=<ip or hostname> overrides the address agents use to reach this server;
+/// ?backend=cuda|metal|cpu and ?mem_mb= are passed to llama-rpc-server as
+/// `--device` and `--mem`, and recorded when the agent registers.
+#[utoipa::path(
+    get,
+    path = "/agent/install",
+    tag = "agent",
+    params(
+        ("os" = Option<String>, Query, description = "linux (default), macos or windows"),
+        ("persist" = Option<bool>, Query, description = "Install a boot-time service"),
+        ("host" = Option<String>, Query, description = "Address agents use to reach this server"),
+        ("backend" = Option<RpcBackend>, Query, description = "Backend llama-rpc-server serves; cuda on Linux needs a local CUDA build"),
+        ("mem_mb" = Option<u64>, Query, description = "Memory llama-rpc-server may hand out, in MB"),
+    ),
+    responses(
+        (status = 200, description = "Install script for the requested OS", content_type = "text/plain", body = String),
+        (status = 400, body = ErrorResponse),
+    )
+)]
 pub async fn install_script(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
 ) -> impl IntoResponse {
     let os = params
         .get("os")
         .map(|s| s.as_str())
         .unwrap_or("linux");
+    let persist = params
+        .get("persist")
+        .is_some_and(|v| v == "true" || v == "1");
 
-    // Detect the host's local IP for display purposes
-    let host_ip = local_ip_address::local_ip()
-        .map(|ip| ip.to_string())
-        .unwrap_or_else(|_| "YOUR_HOST_IP".to_string());
+    let launch = match rpc_launch(os, &params) {
+        Ok(l) => l,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e })))
+                .into_response()
+        }
+    };
+
+    let target = match agent_host(&state, &headers, &params).await {
+        Ok(t) => t,
+        Err(e) => return e.into_response(),
+    };
 
     let rpc_port = state.llama_cpp.rpc_port;
-    let dashboard_port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+    let token = PermissionService::new(state.pool.clone(), state.event_tx.clone())
+        .enrollment_token()
+        .await
+        .unwrap_or_else(|| "YOUR_ENROLLMENT_TOKEN".to_string());
 
     let (script, content_type) = match os {
         "macos" => (
-            macos_script(&host_ip, dashboard_port.as_str(), rpc_port),
+            macos_script(&target, rpc_port, &launch, persist, &token),
             "application/x-sh",
         ),
         "windows" => (
-            windows_script(&host_ip, dashboard_port.as_str(), rpc_port),
+            windows_script(&target, rpc_port, &launch, persist, &token),
             "text/plain",
         ),
         _ => (
-            linux_script(&host_ip, dashboard_port.as_str(), rpc_port),
+            linux_script(&target, rpc_port, &launch, persist, &token),
             "application/x-sh",
         ),
     };
@@ -44,58 +119,601 @@ pub async fn install_script(
         [(axum::http::header::CONTENT_TYPE, content_type)],
         script,
     )
+        .into_response()
 }
 
-/// GET /agent/info
+/// The `?backend=` / `?mem_mb=` of an install request, refusing a backend
+/// the OS has no build for.
+fn rpc_launch(
+    os: &str,
+    params: &std::collections::HashMap<String, String>,
+) -> Result<RpcLaunch, String> {
+    let launch = RpcLaunch::from_query(params)?;
+    match (os, launch.backend) {
+        ("macos", Some(RpcBackend::Cuda)) => Err("backend=cuda isn't available on macOS".into()),
+        (_, Some(RpcBackend::Metal)) if os != "macos" => {
+            Err(format!("backend=metal is only available on macOS, not {}", os))
+        }
+        _ => Ok(launch),
+    }
+}
+
+/// GET /agent/uninstall
 ///
-/// Returns JSON info for the Agent page UI.
-pub async fn agent_info(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let host_ip = local_ip_address::local_ip()
-        .map(|ip| ip.to_string())
-        .unwrap_or_else(|_| "YOUR_HOST_IP".to_string());
+/// Returns an OS-specific script that removes the agent service (if any),
+/// stops llama-rpc-server and deletes the install directory.
+/// Query params: ?os=linux|macos|windows (defaults to linux); ?host= as for install.
+#[utoipa::path(
+    get,
+    path = "/agent/uninstall",
+    tag = "agent",
+    params(
+        ("os" = Option<String>, Query, description = "linux (default), macos or windows"),
+        ("host" = Option<String>, Query, description = "Address agents use to reach this server"),
+    ),
+    responses(
+        (status = 200, description = "Uninstall script for the requested OS", content_type = "text/plain", body = String),
+    )
+)]
+pub async fn uninstall_script(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> impl IntoResponse {
+    let os = params
+        .get("os")
+        .map(|s| s.as_str())
+        .unwrap_or("linux");
+
+    let host_url = match agent_host(&state, &headers, &params).await {
+        Ok(t) => t.url(),
+        Err(e) => return e.into_response(),
+    };
+
+    let (script, content_type) = match os {
+        "macos" => (macos_uninstall_script(&host_url), "application/x-sh"),
+        "windows" => (windows_uninstall_script(&host_url), "text/plain"),
+        _ => (linux_uninstall_script(&host_url), "application/x-sh"),
+    };
+
+    (
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, content_type)],
+        script,
+    )
+        .into_response()
+}
 
-    let dashboard_port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
+/// POST /api/agent/heartbeat
+///
+/// Periodic report from an agent's heartbeat loop. Authenticated with
+/// `Authorization: Bearer <token>`, where the token is the enrollment token
+/// or the device's own secret (issued on approval). Unknown devices get 404,
+/// denied or suspended ones 403. The enrollment token also lifts a lock left
+/// by too many wrong pairing codes.
+#[utoipa::path(
+    post,
+    path = "/api/agent/heartbeat",
+    tag = "agent",
+    request_body = HeartbeatRequest,
+    responses(
+        (status = 200, description = "`{ok, device_id, status, interval_secs, restart_rpc}`", body = serde_json::Value),
+        (status = 400, body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn heartbeat(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<HeartbeatRequest>,
+) -> impl IntoResponse {
+    let token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("")
+        .trim();
+
+    let lookup = match (&req.device_id, &req.ip) {
+        (Some(id), _) => queries::get_device(&state.pool, id).await,
+        (None, Some(ip)) => {
+            // Devices are stored under their canonical IP
+            let ip = parse_ip(ip).map_or_else(|| ip.trim().to_string(), |ip| ip.to_string());
+            queries::get_device_by_ip(&state.pool, &ip).await
+        }
+        (None, None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": "device_id or ip is required" })),
+            )
+                .into_response()
+        }
+    };
+    let device = match lookup {
+        Ok(d) => d,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    // Authenticate before revealing anything about the device
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    if !svc.authenticate_agent(device.as_ref(), token).await {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({ "error": "Invalid or missing agent token" })),
+        )
+            .into_response();
+    }
+
+    let Some(mut device) = device else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Unknown device; register it first" })),
+        )
+            .into_response();
+    };
+    // It answered: a device marked offline gets its old status back
+    match presence::seen(&state.pool, &state.event_tx, &device).await {
+        Ok(status) => device.status = status,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    }
+    match device.status {
+        DeviceStatus::Denied | DeviceStatus::Suspended => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({ "error": format!("Device is {}", device.status) })),
+            )
+                .into_response();
+        }
+        DeviceStatus::Pending | DeviceStatus::Approved | DeviceStatus::Offline => {}
+    }
+
+    // A device locked out by wrong pairing codes falls back to the enrollment token
+    if device.pairing_locked && svc.is_enrollment_token(token).await {
+        if let Err(e) = svc.unlock_pairing(&device).await {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    }
+
+    let rpc_port = req.rpc_port.unwrap_or(device.rpc_port);
+    if req.memory_total_mb < 0
+        || req.memory_free_mb < 0
+        || req.memory_free_mb > req.memory_total_mb
+        || req.rpc_cache_mb.is_some_and(|mb| mb < 0)
+        || !(1..=65535).contains(&rpc_port)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "Invalid memory figures or rpc_port" })),
+        )
+            .into_response();
+    }
+
+    let rpc_status = if req.rpc_running { RpcStatus::Ready } else { RpcStatus::Offline };
+    if let Err(e) = queries::record_device_heartbeat(
+        &state.pool,
+        &device.id,
+        req.memory_total_mb,
+        req.memory_free_mb,
+        rpc_port,
+        rpc_status,
+        req.rpc_cache_mb,
+    )
+    .await
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response();
+    }
+    if let Some(inventory) = req.inventory {
+        if let Err(e) =
+            queries::update_device_inventory(&state.pool, &device.id, &inventory.normalized()).await
+        {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    }
+
+    // The device just reached us, so a probe that finds no backend is final
+    federation::detect_kind(state.pool.clone(), state.llama_cpp.client.clone(), device.clone(), true);
+
+    // A peer host's model list is kept fresh on the back of its heartbeats
+    if device.status == DeviceStatus::Approved
+        && device.is_peer
+        && device.device_kind == DeviceKind::Peer
+        && state.peers.needs_refresh(&device.id)
+    {
+        state.peers.refresh(state.llama_cpp.client.clone(), device.clone());
+    }
+
+    // Only approved devices take part in the cluster, so only they produce RPC events
+    if device.status == DeviceStatus::Approved {
+        if req.rpc_running {
+            let (memory_total_mb, memory_free_mb) = if device.memory_manual_override {
+                (device.memory_total_mb, device.memory_free_mb)
+            } else {
+                (req.memory_total_mb, req.memory_free_mb)
+            };
+            let _ = state.event_tx.send(WsEvent::RpcDeviceReady {
+                device_id: device.id.clone(),
+                memory_total_mb,
+                memory_free_mb,
+            });
+        } else if device.rpc_status != RpcStatus::Offline {
+            let _ = state.event_tx.send(WsEvent::RpcDeviceOffline {
+                device_id: device.id.clone(),
+            });
+        }
+    }
+
+    let restart_rpc = state.rpc_restarts.heartbeat(&device.id, req.can_restart_rpc);
+    if restart_rpc {
+        tracing::info!("Device {} picked up an RPC server restart", device.name);
+    }
+
+    Json(serde_json::json!({
+        "ok": true,
+        "device_id": device.id,
+        "status": device.status,
+        "interval_secs": HEARTBEAT_INTERVAL_SECS,
+        "restart_rpc": restart_rpc,
+    }))
+    .into_response()
+}
+
+/// GET /agent/info
+///
+/// Returns JSON info for the Agent page UI. Accepts the same ?host=,
+/// ?backend= and ?mem_mb= as /agent/install, carried into the commands.
+#[utoipa::path(
+    get,
+    path = "/agent/info",
+    tag = "agent",
+    params(
+        ("host" = Option<String>, Query, description = "Address agents use to reach this server"),
+        ("backend" = Option<RpcBackend>, Query, description = "Backend llama-rpc-server serves"),
+        ("mem_mb" = Option<u64>, Query, description = "Memory llama-rpc-server may hand out, in MB"),
+    ),
+    responses(
+        (status = 200, description = "Dashboard address and install commands for the Agent page", body = serde_json::Value),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+pub async fn agent_info(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
+) -> impl IntoResponse {
+    let target = match agent_host(&state, &headers, &params).await {
+        Ok(t) => t,
+        Err(e) => return e.into_response(),
+    };
     let rpc_port = state.llama_cpp.rpc_port;
+    let token_set = PermissionService::new(state.pool.clone(), state.event_tx.clone())
+        .enrollment_token()
+        .await
+        .is_some();
 
-    let linux_cmd = format!(
-        r#"curl -fsSL "http://{}:{}/agent/install?os=linux" | bash"#,
-        host_ip, dashboard_port
-    );
-    let macos_cmd = format!(
-        r#"curl -fsSL "http://{}:{}/agent/install?os=macos" | bash"#,
-        host_ip, dashboard_port
-    );
-    let windows_cmd = format!(
-        "irm \"http://{}:{}/agent/install?os=windows\" | iex",
-        host_ip, dashboard_port
-    );
+    let launch = match RpcLaunch::from_query(&params) {
+        Ok(l) => l,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e })))
+                .into_response()
+        }
+    };
+
+    // Carry an explicit override into the generated commands
+    let host_param = params
+        .get("host")
+        .filter(|h| !h.trim().is_empty())
+        .map(|_| format!("host={}&", target.host))
+        .unwrap_or_default();
+    let mut launch_param = String::new();
+    if let Some(backend) = launch.backend {
+        launch_param.push_str(&format!("backend={}&", backend.as_str()));
+    }
+    if let Some(mb) = launch.mem_mb {
+        launch_param.push_str(&format!("mem_mb={}&", mb));
+    }
+
+    // One command per OS for a given path + query, e.g. "install?persist=true&"
+    let commands = |path: &str, launch_param: &str| {
+        let base = format!("{}/agent/{}{}{}", target.url(), path, host_param, launch_param);
+        serde_json::json!({
+            "linux": format!(r#"curl -fsSL "{}os=linux" | bash"#, base),
+            "macos": format!(r#"curl -fsSL "{}os=macos" | bash"#, base),
+            "windows": format!("irm \"{}os=windows\" | iex", base),
+        })
+    };
 
     Json(serde_json::json!({
-        "host_ip": host_ip,
-        "dashboard_port": dashboard_port,
+        "host_ip": target.host,
+        "dashboard_port": target.port,
+        "dashboard_url": target.url(),
         "rpc_port": rpc_port,
-        "install_commands": {
-            "linux": linux_cmd,
-            "macos": macos_cmd,
-            "windows": windows_cmd,
-        },
+        "tls_fingerprint": target.tls_fingerprint,
+        "enrollment_token_set": token_set,
+        "install_commands": commands("install?", &launch_param),
+        "persistent_install_commands": commands("install?persist=true&", &launch_param),
+        "uninstall_commands": commands("uninstall?", ""),
         "rpc_server_bin_available": crate::llama_cpp::LlamaCppManager::find_rpc_server_bin().is_some(),
     }))
+    .into_response()
+}
+
+// ─── Agent host ───────────────────────────────────────────────────────────────
+
+/// Setting holding the SHA-256 fingerprint of the certificate agents should
+/// expect when the dashboard sits behind a TLS-terminating proxy.
+pub const TLS_FINGERPRINT_KEY: &str = "tls_fingerprint";
+
+/// Where agents reach this server, and the certificate they should pin.
+struct AgentHost {
+    host: String,
+    port: String,
+    /// Upper-case, colon-separated SHA-256 (openssl's format); implies https
+    tls_fingerprint: Option<String>,
+}
+
+impl AgentHost {
+    /// `host:port`, with IPv6 literals bracketed.
+    fn authority(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    fn url(&self) -> String {
+        let scheme = if self.tls_fingerprint.is_some() { "https" } else { "http" };
+        format!("{}://{}", scheme, self.authority())
+    }
+}
+
+/// Resolve the agent host for a request, answering 400 for a bad `?host=`.
+async fn agent_host(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: &std::collections::HashMap<String, String>,
+) -> Result<AgentHost, (StatusCode, Json<serde_json::Value>)> {
+    let dashboard_port = state.port.to_string();
+    let host_header = headers
+        .get(axum::http::header::HOST)
+        .and_then(|v| v.to_str().ok());
+    let (host, port) = resolve_host(
+        params.get("host").map(|s| s.as_str()),
+        host_header,
+        // On loopback a reverse proxy on this host is the way in, so the LAN IP
+        state.bind.reachable_ip().or_else(|| local_ip_address::local_ip().ok()),
+        &dashboard_port,
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))))?;
+
+    let tls_fingerprint = state
+        .settings
+        .get(TLS_FINGERPRINT_KEY)
+        .and_then(|fp| normalize_fingerprint(&fp));
+
+    Ok(AgentHost { host, port, tls_fingerprint })
+}
+
+/// Pick the address agents should use, returning `(host, port)`:
+/// an explicit `?host=` wins, then the request's Host header (ignored when it
+/// is malformed or a loopback name, since agents can't reach those), then the
+/// detected LAN IP.
+fn resolve_host(
+    query_host: Option<&str>,
+    host_header: Option<&str>,
+    local_ip: Option<std::net::IpAddr>,
+    dashboard_port: &str,
+) -> Result<(String, String), String> {
+    if let Some(h) = query_host.map(str::trim).filter(|h| !h.is_empty()) {
+        let h = h.trim_start_matches('[').trim_end_matches(']');
+        if !is_valid_host(h) {
+            return Err(format!("Invalid host '{}': expected an IP address or hostname", h));
+        }
+        return Ok((h.to_ascii_lowercase(), dashboard_port.to_string()));
+    }
+
+    if let Some((host, port)) = host_header.and_then(split_host_header) {
+        if is_valid_host(&host) && !is_loopback_host(&host) {
+            return Ok((host, port.unwrap_or_else(|| dashboard_port.to_string())));
+        }
+    }
+
+    let host = local_ip
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| "YOUR_HOST_IP".to_string());
+    Ok((host, dashboard_port.to_string()))
+}
+
+/// Split a Host header into host and optional port (`[v6]:port` aware).
+fn split_host_header(value: &str) -> Option<(String, Option<String>)> {
+    let value = value.trim();
+    let (host, port) = if let Some(rest) = value.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        match after {
+            "" => (host, None),
+            _ => (host, Some(after.strip_prefix(':')?)),
+        }
+    } else {
+        match value.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (value, None),
+        }
+    };
+    if let Some(p) = port {
+        if !p.parse::<u16>().is_ok_and(|p| p > 0) {
+            return None;
+        }
+    }
+    Some((host.to_ascii_lowercase(), port.map(str::to_string)))
+}
+
+/// An IP address or an RFC 1123 hostname — nothing that could break out of
+/// the quoting in the generated scripts.
+fn is_valid_host(host: &str) -> bool {
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return true;
+    }
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn is_loopback_host(host: &str) -> bool {
+    match host.parse::<std::net::IpAddr>() {
+        Ok(ip) => ip.is_loopback() || ip.is_unspecified(),
+        Err(_) => host == "localhost" || host.ends_with(".localhost"),
+    }
+}
+
+/// Accept a SHA-256 fingerprint with or without colons, in any case.
+fn normalize_fingerprint(fp: &str) -> Option<String> {
+    let hex: String = fp.chars().filter(|c| *c != ':' && !c.is_whitespace()).collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let hex = hex.to_ascii_uppercase();
+    Some(
+        hex.as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
+
+/// `-k ` when the certificate is pinned: the pin check replaces CA
+/// validation, so self-signed proxies work.
+fn curl_tls_flag(target: &AgentHost) -> &'static str {
+    if target.tls_fingerprint.is_some() {
+        "-k "
+    } else {
+        ""
+    }
+}
+
+/// sh `verify_host` function comparing the server certificate with the pin;
+/// with `abort`, the installer also refuses to continue on a mismatch.
+fn sh_pin_check(target: &AgentHost, abort: bool) -> String {
+    let Some(fp) = &target.tls_fingerprint else {
+        return String::new();
+    };
+    let mut out = format!(
+        r#"
+# Refuse to talk to a host whose TLS certificate doesn't match the pin
+EXPECTED_FP={fp}
+verify_host() {{
+  ACTUAL_FP=$(openssl s_client -connect {authority} -servername {host} </dev/null 2>/dev/null     | openssl x509 -noout -fingerprint -sha256 2>/dev/null | cut -d= -f2)
+  [ "$ACTUAL_FP" = "$EXPECTED_FP" ]
+}}
+"#,
+        fp = fp,
+        authority = target.authority(),
+        host = target.host,
+    );
+    if abort {
+        out.push_str(&format!(
+            r#"if ! verify_host; then
+  echo "[SharedLLM] TLS certificate of {} does not match the pinned fingerprint. Aborting." >&2
+  exit 1
+fi
+"#,
+            target.authority()
+        ));
+    }
+    out
+}
+
+/// PowerShell certificate callback accepting only the pinned certificate for
+/// the dashboard host; other hosts (GitHub downloads) keep normal validation.
+fn ps_pin_check(target: &AgentHost) -> String {
+    let Some(fp) = &target.tls_fingerprint else {
+        return String::new();
+    };
+    format!(
+        r#"
+# Only accept the pinned TLS certificate from the dashboard host
+$PinnedHost = {host}
+$ExpectedFp = {fp}
+[Net.ServicePointManager]::ServerCertificateValidationCallback = {{
+    param($Request, $Cert, $Chain, $Errors)
+    if ($Request -is [Net.HttpWebRequest] -and $Request.RequestUri.DnsSafeHost -eq $PinnedHost) {{
+        $Hash = [Security.Cryptography.SHA256]::Create().ComputeHash($Cert.GetRawCertData())
+        return ([BitConverter]::ToString($Hash) -replace '-', '') -eq $ExpectedFp
+    }}
+    return $Errors -eq [Net.Security.SslPolicyErrors]::None
+}}
+"#,
+        host = ps_quote(&target.host),
+        fp = ps_quote(&fp.replace(':', "")),
+    )
 }
 
 // ─── Script templates ─────────────────────────────────────────────────────────
 
-fn linux_script(host_ip: &str, dashboard_port: &str, rpc_port: u16) -> String {
+fn linux_script(
+    target: &AgentHost,
+    rpc_port: u16,
+    launch: &RpcLaunch,
+    persist: bool,
+    token: &str,
+) -> String {
+    let install = if launch.backend == Some(RpcBackend::Cuda) {
+        LINUX_CUDA_INSTALL
+    } else {
+        LINUX_RELEASE_INSTALL
+    };
+    let start = if persist {
+        linux_persistent_start(rpc_port, launch)
+    } else {
+        LINUX_TRANSIENT_START.to_string()
+    };
+    let heartbeat = linux_heartbeat_block(
+        &linux_heartbeat_script(target, token, rpc_port, persist),
+        persist,
+    );
     format!(
         r#"#!/usr/bin/env bash
 # SharedLLM RPC Agent Installer - Linux
 # This script installs llama-rpc-server and starts it as an agent for the SharedLLM cluster.
-# The host at {host_ip}:{dashboard_port} will distribute model layers to this machine.
+# The host at {host_url} will distribute model layers to this machine.
 
 set -euo pipefail
-
+{pin}
 INSTALL_DIR="$HOME/.sharedmem/bin"
 RPC_PORT={rpc_port}
+RPC_EXTRA_ARGS="{extra_args}"
 
 echo "[SharedLLM] Installing RPC agent..."
 
@@ -107,78 +725,74 @@ case "$ARCH" in
   *)       echo "Unsupported architecture: $ARCH"; exit 1 ;;
 esac
 
-# Get latest llama.cpp release (repo moved to ggml-org)
-echo "[SharedLLM] Fetching latest llama.cpp release info..."
-LATEST_TAG=$(curl -fsSL https://api.github.com/repos/ggml-org/llama.cpp/releases/latest | grep '"tag_name"' | sed 's/.*"tag_name": *"\([^"]*\)".*/\1/')
-
-DOWNLOAD_URL="https://github.com/ggml-org/llama.cpp/releases/download/$LATEST_TAG/llama-$LATEST_TAG-bin-ubuntu-$LLAMA_ARCH.zip"
-
-mkdir -p "$INSTALL_DIR"
-TMPDIR=$(mktemp -d)
-trap 'rm -rf "$TMPDIR"' EXIT
-
-echo "[SharedLLM] Downloading llama.cpp $LATEST_TAG..."
-curl -fsSL -o "$TMPDIR/llama.zip" "$DOWNLOAD_URL" || {{
-  echo "[SharedLLM] Download failed. Please install llama.cpp manually."
-  echo "  https://github.com/ggml-org/llama.cpp/releases"
-  exit 1
-}}
-
-cd "$TMPDIR"
-unzip -q llama.zip
-
-# Binary may be named 'rpc-server' in recent releases or 'llama-rpc-server' in older ones
-RPC_BIN=$(find . -name "rpc-server" -o -name "llama-rpc-server" 2>/dev/null | head -1)
-if [ -z "$RPC_BIN" ]; then
-  echo "[SharedLLM] Could not find rpc-server binary in archive."
-  exit 1
-fi
-cp "$RPC_BIN" "$INSTALL_DIR/llama-rpc-server"
-chmod +x "$INSTALL_DIR/llama-rpc-server"
-
-mkdir -p "$HOME/.sharedmem"
-echo "[SharedLLM] Starting llama-rpc-server on port $RPC_PORT..."
-nohup "$INSTALL_DIR/llama-rpc-server" --host 0.0.0.0 --port "$RPC_PORT" > "$HOME/.sharedmem/rpc-server.log" 2>&1 &
-echo $! > "$HOME/.sharedmem/rpc-server.pid"
-
-echo ""
-echo "[SharedLLM] RPC agent started!"
-echo "  Listening: 0.0.0.0:$RPC_PORT"
-echo "  Log:       $HOME/.sharedmem/rpc-server.log"
-echo "  PID file:  $HOME/.sharedmem/rpc-server.pid"
-echo ""
-
-# Self-register with the host dashboard
+{install}
+{start}# Self-register with the host dashboard
 MY_IP=$(ip route get 8.8.8.8 2>/dev/null | grep -oP 'src \K\S+' || hostname -I 2>/dev/null | awk '{{print $1}}' || echo "")
 MY_NAME=$(hostname)
 if [ -n "$MY_IP" ]; then
-  echo "[SharedLLM] Registering with host at {host_ip}:{dashboard_port}..."
-  curl -fsSL -X POST "http://{host_ip}:{dashboard_port}/api/devices" \
+  echo "[SharedLLM] Registering with host at {host_url}..."
+  if RESPONSE=$(curl -fsSL {curl_tls}-X POST "{host_url}/api/devices" \
     -H "Content-Type: application/json" \
-    -d "{{\"name\": \"$MY_NAME\", \"ip\": \"$MY_IP\"}}" \
-    -o /dev/null 2>/dev/null \
-    && echo "[SharedLLM] Registered! Go to http://{host_ip}:{dashboard_port}/devices to approve this device." \
-    || echo "[SharedLLM] Could not auto-register. Add manually at http://{host_ip}:{dashboard_port}/devices (Name=$MY_NAME, IP=$MY_IP)"
+    -d "{{\"name\": \"$MY_NAME\", \"ip\": \"$MY_IP\", \"rpc_launch\": {launch_json}}}" \
+    2>/dev/null); then
+    echo "[SharedLLM] Registered! Go to {host_url}/devices to approve this device."
+    PAIRING_CODE=$(printf '%s' "$RESPONSE" | sed -n 's/.*"pairing_code":"\([0-9]*\)".*/\1/p')
+    if [ -n "$PAIRING_CODE" ]; then
+      echo ""
+      echo "  ============================================"
+      echo "    Pairing code for $MY_NAME:  $PAIRING_CODE"
+      echo "  ============================================"
+      echo "  Enter it in the dashboard when approving this device."
+      echo "  It expires in {ttl} minutes; re-run this installer for a new one."
+      echo ""
+    fi
+  else
+    echo "[SharedLLM] Could not auto-register. Add manually at {host_url}/devices (Name=$MY_NAME, IP=$MY_IP)"
+  fi
 else
-  echo "[SharedLLM] Could not detect local IP. Add this device manually at http://{host_ip}:{dashboard_port}/devices"
+  echo "[SharedLLM] Could not detect local IP. Add this device manually at {host_url}/devices"
 fi
+{heartbeat}
 "#,
-        host_ip = host_ip,
-        dashboard_port = dashboard_port,
+        host_url = target.url(),
+        pin = sh_pin_check(target, true),
+        curl_tls = curl_tls_flag(target),
         rpc_port = rpc_port,
+        extra_args = launch.args().join(" "),
+        launch_json = sh_launch_json(launch),
+        ttl = PAIRING_CODE_TTL_MINUTES,
+        install = install,
+        start = start,
+        heartbeat = heartbeat,
     )
 }
 
-fn macos_script(host_ip: &str, dashboard_port: &str, rpc_port: u16) -> String {
+fn macos_script(
+    target: &AgentHost,
+    rpc_port: u16,
+    launch: &RpcLaunch,
+    persist: bool,
+    token: &str,
+) -> String {
+    let start = if persist {
+        macos_persistent_start(rpc_port, launch)
+    } else {
+        MACOS_TRANSIENT_START.to_string()
+    };
+    let heartbeat = macos_heartbeat_block(
+        &macos_heartbeat_script(target, token, rpc_port, persist),
+        persist,
+    );
     format!(
         r#"#!/usr/bin/env bash
 # SharedLLM RPC Agent Installer - macOS
 # Installs llama-rpc-server and starts it as a cluster agent.
 
 set -euo pipefail
-
+{pin}
 INSTALL_DIR="$HOME/.sharedmem/bin"
 RPC_PORT={rpc_port}
+RPC_EXTRA_ARGS="{extra_args}"
 
 echo "[SharedLLM] Installing RPC agent for macOS..."
 
@@ -224,75 +838,180 @@ else
   LLAMA_RPC="$INSTALL_DIR/llama-rpc-server"
 fi
 
-mkdir -p "$HOME/.sharedmem"
-echo "[SharedLLM] Starting llama-rpc-server on port $RPC_PORT..."
-nohup "${{LLAMA_RPC}}" --host 0.0.0.0 --port "$RPC_PORT" \
-  > "$HOME/.sharedmem/rpc-server.log" 2>&1 &
-RPC_PID=$!
-echo "$RPC_PID" > "$HOME/.sharedmem/rpc-server.pid"
-
-# Verify the process actually started (Gatekeeper or a missing dependency can kill it immediately)
-sleep 2
-if kill -0 "$RPC_PID" 2>/dev/null; then
-  echo ""
-  echo "[SharedLLM] RPC agent running (PID: $RPC_PID)"
-  echo "  Listening: 0.0.0.0:$RPC_PORT"
-  echo "  Log:       $HOME/.sharedmem/rpc-server.log"
-  echo ""
-  echo "  FIREWALL NOTE: If this Mac's firewall is on, allow incoming connections on port $RPC_PORT:"
-  echo "  System Settings → Network → Firewall → Options → add '${{LLAMA_RPC}}'"
-  echo ""
-else
-  echo ""
-  echo "[SharedLLM] ERROR: Process exited immediately after launch."
-  echo "  Last log output:"
-  cat "$HOME/.sharedmem/rpc-server.log" 2>/dev/null | tail -10 || echo "  (log empty)"
-  echo ""
-  echo "  Common causes on macOS:"
-  echo "  1. Gatekeeper blocked the binary — go to System Settings → Privacy & Security"
-  echo "     and click 'Allow Anyway' next to the llama-rpc-server message."
-  echo "  2. Missing dependency — try: brew install llama.cpp"
-  echo ""
-  exit 1
-fi
-
-# Self-register with the host dashboard
+{start}# Self-register with the host dashboard
 MY_IP=$(ipconfig getifaddr en0 2>/dev/null || ipconfig getifaddr en1 2>/dev/null || ifconfig 2>/dev/null | grep 'inet ' | grep -v 127.0.0.1 | awk '{{print $2}}' | head -1 || echo "")
 MY_NAME=$(hostname)
 if [ -n "$MY_IP" ]; then
-  echo "[SharedLLM] Registering with host at {host_ip}:{dashboard_port}..."
-  curl -fsSL -X POST "http://{host_ip}:{dashboard_port}/api/devices" \
+  echo "[SharedLLM] Registering with host at {host_url}..."
+  if RESPONSE=$(curl -fsSL {curl_tls}-X POST "{host_url}/api/devices" \
     -H "Content-Type: application/json" \
-    -d "{{\"name\": \"$MY_NAME\", \"ip\": \"$MY_IP\"}}" \
-    -o /dev/null 2>/dev/null \
-    && echo "[SharedLLM] Registered! Go to http://{host_ip}:{dashboard_port}/devices to approve this device." \
-    || echo "[SharedLLM] Could not auto-register. Add manually at http://{host_ip}:{dashboard_port}/devices (Name=$MY_NAME, IP=$MY_IP)"
+    -d "{{\"name\": \"$MY_NAME\", \"ip\": \"$MY_IP\", \"rpc_launch\": {launch_json}}}" \
+    2>/dev/null); then
+    echo "[SharedLLM] Registered! Go to {host_url}/devices to approve this device."
+    PAIRING_CODE=$(printf '%s' "$RESPONSE" | sed -n 's/.*"pairing_code":"\([0-9]*\)".*/\1/p')
+    if [ -n "$PAIRING_CODE" ]; then
+      echo ""
+      echo "  ============================================"
+      echo "    Pairing code for $MY_NAME:  $PAIRING_CODE"
+      echo "  ============================================"
+      echo "  Enter it in the dashboard when approving this device."
+      echo "  It expires in {ttl} minutes; re-run this installer for a new one."
+      echo ""
+    fi
+  else
+    echo "[SharedLLM] Could not auto-register. Add manually at {host_url}/devices (Name=$MY_NAME, IP=$MY_IP)"
+  fi
 else
-  echo "[SharedLLM] Could not detect local IP. Add this device manually at http://{host_ip}:{dashboard_port}/devices"
+  echo "[SharedLLM] Could not detect local IP. Add this device manually at {host_url}/devices"
 fi
+{heartbeat}
 "#,
-        host_ip = host_ip,
-        dashboard_port = dashboard_port,
+        host_url = target.url(),
+        pin = sh_pin_check(target, true),
+        curl_tls = curl_tls_flag(target),
         rpc_port = rpc_port,
+        extra_args = launch.args().join(" "),
+        launch_json = sh_launch_json(launch),
+        ttl = PAIRING_CODE_TTL_MINUTES,
+        start = start,
+        heartbeat = heartbeat,
     )
 }
 
-fn windows_script(host_ip: &str, dashboard_port: &str, rpc_port: u16) -> String {
+fn windows_script(
+    target: &AgentHost,
+    rpc_port: u16,
+    launch: &RpcLaunch,
+    persist: bool,
+    token: &str,
+) -> String {
+    let install = if launch.backend == Some(RpcBackend::Cuda) {
+        WINDOWS_CUDA_INSTALL
+    } else {
+        WINDOWS_RELEASE_INSTALL
+    };
+    let (preflight, start) = if persist {
+        (WINDOWS_PERSIST_PREFLIGHT, windows_persistent_start(rpc_port, launch))
+    } else {
+        ("", WINDOWS_TRANSIENT_START.to_string())
+    };
+    let heartbeat = windows_heartbeat_block(
+        &windows_heartbeat_script(target, token, rpc_port, persist),
+        persist,
+    );
     format!(
         r#"# SharedLLM RPC Agent Installer - Windows (PowerShell)
-# Run with: irm http://{host_ip}:{dashboard_port}/agent/install?os=windows | iex
+# Run with: irm {host_url}/agent/install?os=windows | iex
 
 $InstallDir = "$env:USERPROFILE\.sharedmem\bin"
 $RpcPort = {rpc_port}
+$RpcExtraArgs = "{extra_args}"
 $LogFile = "$env:USERPROFILE\.sharedmem\rpc-server.log"
 
 Write-Host "[SharedLLM] Installing RPC agent for Windows..."
-
+{preflight}{pin}
 # Create install directory
 New-Item -ItemType Directory -Force -Path $InstallDir | Out-Null
 New-Item -ItemType Directory -Force -Path "$env:USERPROFILE\.sharedmem" | Out-Null
 
-# Get latest release (repo moved to ggml-org)
+{install}
+{start}Write-Host "  Dashboard: {host_url}"
+Write-Host ""
+
+# Self-register with the host dashboard
+$MyIp = (Get-NetIPAddress -AddressFamily IPv4 | Where-Object {{ $_.IPAddress -notmatch '^127' -and $_.IPAddress -notmatch '^169' }} | Select-Object -First 1).IPAddress
+$MyName = $env:COMPUTERNAME
+if ($MyIp) {{
+    Write-Host "[SharedLLM] Registering with host at {host_url}..."
+    try {{
+        $Body = @{{ name = $MyName; ip = $MyIp; rpc_launch = ('{launch_json}' | ConvertFrom-Json) }} | ConvertTo-Json -Compress
+        $Registered = Invoke-RestMethod -Uri "{host_url}/api/devices" -Method Post -ContentType "application/json" -Body $Body
+        Write-Host "[SharedLLM] Registered! Go to {host_url}/devices to approve this device."
+        if ($Registered.pairing_code) {{
+            Write-Host ""
+            Write-Host "  ============================================" -ForegroundColor Yellow
+            Write-Host "    Pairing code for ${{MyName}}:  $($Registered.pairing_code)" -ForegroundColor Yellow
+            Write-Host "  ============================================" -ForegroundColor Yellow
+            Write-Host "  Enter it in the dashboard when approving this device."
+            Write-Host "  It expires in {ttl} minutes; re-run this installer for a new one."
+            Write-Host ""
+        }}
+    }} catch {{
+        Write-Host "[SharedLLM] Could not auto-register. Add manually at {host_url}/devices (Name=$MyName, IP=$MyIp)"
+    }}
+}} else {{
+    Write-Host "[SharedLLM] Could not detect local IP. Add this device manually at {host_url}/devices"
+}}
+{heartbeat}
+"#,
+        host_url = target.url(),
+        pin = ps_pin_check(target),
+        rpc_port = rpc_port,
+        extra_args = launch.args().join(" "),
+        launch_json = launch_json(launch),
+        ttl = PAIRING_CODE_TTL_MINUTES,
+        install = install,
+        start = start,
+        heartbeat = heartbeat,
+        preflight = preflight,
+    )
+}
+
+// ─── Install blocks ───────────────────────────────────────────────────────────
+//
+// How each installer gets llama-rpc-server into the install directory: the
+// latest release build, or for `backend=cuda` a CUDA build.
+
+const LINUX_RELEASE_INSTALL: &str = r#"# Get latest llama.cpp release (repo moved to ggml-org)
+echo "[SharedLLM] Fetching latest llama.cpp release info..."
+LATEST_TAG=$(curl -fsSL https://api.github.com/repos/ggml-org/llama.cpp/releases/latest | grep '"tag_name"' | sed 's/.*"tag_name": *"\([^"]*\)".*/\1/')
+
+DOWNLOAD_URL="https://github.com/ggml-org/llama.cpp/releases/download/$LATEST_TAG/llama-$LATEST_TAG-bin-ubuntu-$LLAMA_ARCH.zip"
+
+mkdir -p "$INSTALL_DIR"
+TMPDIR=$(mktemp -d)
+trap 'rm -rf "$TMPDIR"' EXIT
+
+echo "[SharedLLM] Downloading llama.cpp $LATEST_TAG..."
+curl -fsSL -o "$TMPDIR/llama.zip" "$DOWNLOAD_URL" || {
+  echo "[SharedLLM] Download failed. Please install llama.cpp manually."
+  echo "  https://github.com/ggml-org/llama.cpp/releases"
+  exit 1
+}
+
+cd "$TMPDIR"
+unzip -q llama.zip
+
+# Binary may be named 'rpc-server' in recent releases or 'llama-rpc-server' in older ones
+RPC_BIN=$(find . -name "rpc-server" -o -name "llama-rpc-server" 2>/dev/null | head -1)
+if [ -z "$RPC_BIN" ]; then
+  echo "[SharedLLM] Could not find rpc-server binary in archive."
+  exit 1
+fi
+cp "$RPC_BIN" "$INSTALL_DIR/llama-rpc-server"
+chmod +x "$INSTALL_DIR/llama-rpc-server"
+"#;
+
+const LINUX_CUDA_INSTALL: &str = r#"# backend=cuda: llama.cpp publishes no Linux CUDA build, so use one built
+# locally (cmake -DGGML_CUDA=ON -DGGML_RPC=ON), linked so its libraries load
+LOCAL_RPC="${LLAMA_RPC_SERVER:-$(command -v rpc-server || command -v llama-rpc-server || true)}"
+if [ -z "$LOCAL_RPC" ] && [ -x "$INSTALL_DIR/llama-rpc-server" ]; then
+  LOCAL_RPC="$INSTALL_DIR/llama-rpc-server"
+fi
+if [ -z "$LOCAL_RPC" ]; then
+  echo "[SharedLLM] backend=cuda needs a CUDA build of llama.cpp's rpc-server; none is published for Linux."
+  echo "  Build one:  cmake -B build -DGGML_CUDA=ON -DGGML_RPC=ON && cmake --build build --target rpc-server"
+  echo "  Then re-run with its absolute path:  ... | LLAMA_RPC_SERVER=/path/to/build/bin/rpc-server bash"
+  exit 1
+fi
+mkdir -p "$INSTALL_DIR"
+if [ "$LOCAL_RPC" != "$INSTALL_DIR/llama-rpc-server" ]; then
+  ln -sf "$LOCAL_RPC" "$INSTALL_DIR/llama-rpc-server"
+fi
+echo "[SharedLLM] Using the CUDA build at $LOCAL_RPC"
+"#;
+
+const WINDOWS_RELEASE_INSTALL: &str = r#"# Get latest release (repo moved to ggml-org)
 $Release = Invoke-RestMethod "https://api.github.com/repos/ggml-org/llama.cpp/releases/latest"
 $Tag = $Release.tag_name
 
@@ -301,58 +1020,1239 @@ $DownloadUrl = "https://github.com/ggml-org/llama.cpp/releases/download/$Tag/lla
 $TmpZip = "$env:TEMP\llama-cpp.zip"
 
 Write-Host "[SharedLLM] Downloading llama.cpp $Tag..."
-try {{
+try {
     Invoke-WebRequest -Uri $DownloadUrl -OutFile $TmpZip -ErrorAction Stop
-}} catch {{
+} catch {
     $DownloadUrl = "https://github.com/ggml-org/llama.cpp/releases/download/$Tag/llama-$Tag-bin-win-cpu-x64.zip"
     Write-Host "[SharedLLM] avx2 build not found, trying cpu build..."
     Invoke-WebRequest -Uri $DownloadUrl -OutFile $TmpZip
-}}
+}
 
 $TmpDir = "$env:TEMP\llama-cpp-extract"
 Expand-Archive -Path $TmpZip -DestinationPath $TmpDir -Force
 
 # Binary may be named 'rpc-server.exe' in recent releases or 'llama-rpc-server.exe' in older ones
 $RpcBin = Get-ChildItem -Path $TmpDir -Recurse -Filter "rpc-server.exe" | Select-Object -First 1
-if (-not $RpcBin) {{
+if (-not $RpcBin) {
     $RpcBin = Get-ChildItem -Path $TmpDir -Recurse -Filter "llama-rpc-server.exe" | Select-Object -First 1
-}}
-if (-not $RpcBin) {{
+}
+if (-not $RpcBin) {
     Write-Host "[SharedLLM] Could not find rpc-server binary in archive. Aborting."
     exit 1
-}}
+}
 Copy-Item $RpcBin.FullName "$InstallDir\llama-rpc-server.exe"
+"#;
 
-Write-Host "[SharedLLM] Starting llama-rpc-server on port $RpcPort..."
-Start-Process -FilePath "$InstallDir\llama-rpc-server.exe" `
-  -ArgumentList "--host 0.0.0.0 --port $RpcPort" `
-  -RedirectStandardOutput $LogFile `
-  -WindowStyle Hidden
+const WINDOWS_CUDA_INSTALL: &str = r#"# Get latest release (repo moved to ggml-org)
+$Release = Invoke-RestMethod "https://api.github.com/repos/ggml-org/llama.cpp/releases/latest"
+$Tag = $Release.tag_name
+
+# backend=cuda: the CUDA build, plus the CUDA runtime DLLs published beside it
+$Asset = $Release.assets | Where-Object { $_.name -like "llama-*-bin-win-cuda*-x64.zip" } | Select-Object -First 1
+if (-not $Asset) {
+    Write-Host "[SharedLLM] Release $Tag has no Windows CUDA build. Aborting."
+    exit 1
+}
+$TmpZip = "$env:TEMP\llama-cpp.zip"
+$TmpDir = "$env:TEMP\llama-cpp-extract"
+Write-Host "[SharedLLM] Downloading llama.cpp $Tag ($($Asset.name))..."
+Invoke-WebRequest -Uri $Asset.browser_download_url -OutFile $TmpZip
+Expand-Archive -Path $TmpZip -DestinationPath $TmpDir -Force
+
+$CudaRtName = "cudart-llama-bin-" + ($Asset.name -replace '^llama-[^-]+-bin-', '')
+$CudaRt = $Release.assets | Where-Object { $_.name -eq $CudaRtName } | Select-Object -First 1
+if ($CudaRt) {
+    Invoke-WebRequest -Uri $CudaRt.browser_download_url -OutFile "$env:TEMP\llama-cudart.zip"
+    Expand-Archive -Path "$env:TEMP\llama-cudart.zip" -DestinationPath $TmpDir -Force
+}
+
+# Binary may be named 'rpc-server.exe' in recent releases or 'llama-rpc-server.exe' in older ones
+$RpcBin = Get-ChildItem -Path $TmpDir -Recurse -Filter "rpc-server.exe" | Select-Object -First 1
+if (-not $RpcBin) {
+    $RpcBin = Get-ChildItem -Path $TmpDir -Recurse -Filter "llama-rpc-server.exe" | Select-Object -First 1
+}
+if (-not $RpcBin) {
+    Write-Host "[SharedLLM] Could not find rpc-server binary in archive. Aborting."
+    exit 1
+}
+Copy-Item $RpcBin.FullName "$InstallDir\llama-rpc-server.exe"
+# The CUDA backend and runtime are DLLs loaded from beside the binary
+Get-ChildItem -Path $TmpDir -Recurse -Filter "*.dll" | Copy-Item -Destination $InstallDir -Force
+"#;
+
+// ─── Start blocks ─────────────────────────────────────────────────────────────
+//
+// Each installer downloads the binary, then runs one of these: a transient
+// start (nohup / Start-Process, gone after reboot) or a persistent service
+// install that starts the agent at boot and restarts it on crash.
+
+/// systemd user unit name (Linux).
+const SYSTEMD_UNIT: &str = "sharedllm-rpc.service";
+/// launchd job label (macOS).
+const LAUNCHD_LABEL: &str = "com.sharedllm.rpc-server";
+/// Scheduled Task name (Windows).
+const WINDOWS_TASK: &str = "SharedLLM RPC Agent";
+/// Heartbeat loop counterparts of the three service names above.
+const SYSTEMD_HEARTBEAT_UNIT: &str = "sharedllm-heartbeat.service";
+const LAUNCHD_HEARTBEAT_LABEL: &str = "com.sharedllm.heartbeat";
+const WINDOWS_HEARTBEAT_TASK: &str = "SharedLLM Agent Heartbeat";
+
+/// Placeholders in the Task Scheduler XML that PowerShell fills in at install
+/// time (escaped with `SecurityElement::Escape`).
+const TASK_USER_PLACEHOLDER: &str = "__SHAREDLLM_USER__";
+const TASK_DIR_PLACEHOLDER: &str = "__SHAREDLLM_INSTALL_DIR__";
+
+/// Terminators for the quoted heredocs / here-strings that embed unit files.
+/// Quoted, so the shell never expands anything inside them.
+const UNIT_HEREDOC: &str = "SHAREDLLM_UNIT";
+const PLIST_HEREDOC: &str = "SHAREDLLM_PLIST";
+const HEARTBEAT_HEREDOC: &str = "SHAREDLLM_HEARTBEAT";
+
+const LINUX_TRANSIENT_START: &str = r#"mkdir -p "$HOME/.sharedmem"
+echo "[SharedLLM] Starting llama-rpc-server on port $RPC_PORT..."
+nohup "$INSTALL_DIR/llama-rpc-server" --host 0.0.0.0 --port "$RPC_PORT" --cache $RPC_EXTRA_ARGS > "$HOME/.sharedmem/rpc-server.log" 2>&1 &
+echo $! > "$HOME/.sharedmem/rpc-server.pid"
+
+echo ""
+echo "[SharedLLM] RPC agent started!"
+echo "  Listening: 0.0.0.0:$RPC_PORT"
+echo "  Log:       $HOME/.sharedmem/rpc-server.log"
+echo "  PID file:  $HOME/.sharedmem/rpc-server.pid"
+echo ""
+
+"#;
+
+const MACOS_TRANSIENT_START: &str = r#"mkdir -p "$HOME/.sharedmem"
+echo "[SharedLLM] Starting llama-rpc-server on port $RPC_PORT..."
+nohup "${LLAMA_RPC}" --host 0.0.0.0 --port "$RPC_PORT" --cache $RPC_EXTRA_ARGS \
+  > "$HOME/.sharedmem/rpc-server.log" 2>&1 &
+RPC_PID=$!
+echo "$RPC_PID" > "$HOME/.sharedmem/rpc-server.pid"
+
+# Verify the process actually started (Gatekeeper or a missing dependency can kill it immediately)
+sleep 2
+if kill -0 "$RPC_PID" 2>/dev/null; then
+  echo ""
+  echo "[SharedLLM] RPC agent running (PID: $RPC_PID)"
+  echo "  Listening: 0.0.0.0:$RPC_PORT"
+  echo "  Log:       $HOME/.sharedmem/rpc-server.log"
+  echo ""
+  echo "  FIREWALL NOTE: If this Mac's firewall is on, allow incoming connections on port $RPC_PORT:"
+  echo "  System Settings → Network → Firewall → Options → add '${LLAMA_RPC}'"
+  echo ""
+else
+  echo ""
+  echo "[SharedLLM] ERROR: Process exited immediately after launch."
+  echo "  Last log output:"
+  cat "$HOME/.sharedmem/rpc-server.log" 2>/dev/null | tail -10 || echo "  (log empty)"
+  echo ""
+  echo "  Common causes on macOS:"
+  echo "  1. Gatekeeper blocked the binary — go to System Settings → Privacy & Security"
+  echo "     and click 'Allow Anyway' next to the llama-rpc-server message."
+  echo "  2. Missing dependency — try: brew install llama.cpp"
+  echo ""
+  exit 1
+fi
+
+"#;
+
+const WINDOWS_TRANSIENT_START: &str = r#"Write-Host "[SharedLLM] Starting llama-rpc-server on port $RpcPort..."
+Start-Process -FilePath "$InstallDir\llama-rpc-server.exe" `
+  -ArgumentList "--host 0.0.0.0 --port $RpcPort --cache $RpcExtraArgs" `
+  -RedirectStandardOutput $LogFile `
+  -WindowStyle Hidden
 
 Write-Host ""
 Write-Host "[SharedLLM] RPC agent started!"
 Write-Host "  Listening: 0.0.0.0:$RpcPort"
-Write-Host "  Dashboard: http://{host_ip}:{dashboard_port}"
+"#;
+
+/// Registering a boot trigger needs an elevated shell — fail before downloading.
+const WINDOWS_PERSIST_PREFLIGHT: &str = r#"
+$IsAdmin = ([Security.Principal.WindowsPrincipal][Security.Principal.WindowsIdentity]::GetCurrent()).IsInRole([Security.Principal.WindowsBuiltInRole]::Administrator)
+if (-not $IsAdmin) {
+    Write-Host "[SharedLLM] Installing a boot-time Scheduled Task requires an elevated PowerShell."
+    Write-Host "  Re-run this command from 'Run as Administrator'."
+    exit 1
+}
+"#;
+
+fn linux_persistent_start(rpc_port: u16, launch: &RpcLaunch) -> String {
+    format!(
+        r#"if ! command -v systemctl &>/dev/null; then
+  echo "[SharedLLM] systemd not found; re-run without persist=true for a one-off start."
+  exit 1
+fi
+
+mkdir -p "$HOME/.sharedmem" "$HOME/.config/systemd/user"
+
+# Replace an agent left running by a previous non-persistent install
+if [ -f "$HOME/.sharedmem/rpc-server.pid" ]; then
+  kill "$(cat "$HOME/.sharedmem/rpc-server.pid")" 2>/dev/null || true
+  rm -f "$HOME/.sharedmem/rpc-server.pid"
+fi
+
+echo "[SharedLLM] Installing systemd user service {unit}..."
+cat > "$HOME/.config/systemd/user/{unit}" <<'{heredoc}'
+{unit_file}{heredoc}
+systemctl --user daemon-reload
+systemctl --user enable --now {unit}
+
+# Lingering starts the user's services at boot, without a login
+loginctl enable-linger "$USER" 2>/dev/null \
+  || echo "[SharedLLM] Note: run 'sudo loginctl enable-linger $USER' so the agent starts at boot."
+
+echo ""
+echo "[SharedLLM] RPC agent installed as a service (starts at boot, restarts on crash)"
+echo "  Listening: 0.0.0.0:$RPC_PORT"
+echo "  Status:    systemctl --user status {unit}"
+echo "  Log:       $HOME/.sharedmem/rpc-server.log"
+echo ""
+
+"#,
+        unit = SYSTEMD_UNIT,
+        heredoc = UNIT_HEREDOC,
+        unit_file = rpc_systemd_unit(rpc_port, launch),
+    )
+}
+
+fn macos_persistent_start(rpc_port: u16, launch: &RpcLaunch) -> String {
+    format!(
+        r#"mkdir -p "$HOME/.sharedmem" "$INSTALL_DIR" "$HOME/Library/LaunchAgents"
+
+# The launchd job always runs $INSTALL_DIR/llama-rpc-server; link Homebrew's copy there
+if [ "$LLAMA_RPC" != "$INSTALL_DIR/llama-rpc-server" ]; then
+  ln -sf "$LLAMA_RPC" "$INSTALL_DIR/llama-rpc-server"
+fi
+
+# Replace an agent left running by a previous non-persistent install
+if [ -f "$HOME/.sharedmem/rpc-server.pid" ]; then
+  kill "$(cat "$HOME/.sharedmem/rpc-server.pid")" 2>/dev/null || true
+  rm -f "$HOME/.sharedmem/rpc-server.pid"
+fi
+
+PLIST="$HOME/Library/LaunchAgents/{label}.plist"
+echo "[SharedLLM] Installing launchd agent {label}..."
+cat > "$PLIST" <<'{heredoc}'
+{plist}{heredoc}
+launchctl bootout "gui/$(id -u)/{label}" 2>/dev/null || true
+launchctl bootstrap "gui/$(id -u)" "$PLIST"
+
+echo ""
+echo "[SharedLLM] RPC agent installed as a launchd agent (starts at login, restarts on crash)"
+echo "  Listening: 0.0.0.0:$RPC_PORT"
+echo "  Status:    launchctl print gui/$(id -u)/{label}"
+echo "  Log:       $HOME/.sharedmem/rpc-server.log"
+echo ""
+echo "  FIREWALL NOTE: If this Mac's firewall is on, allow incoming connections on port $RPC_PORT:"
+echo "  System Settings → Network → Firewall → Options → add '$LLAMA_RPC'"
+echo ""
+
+"#,
+        label = LAUNCHD_LABEL,
+        heredoc = PLIST_HEREDOC,
+        plist = rpc_launchd_plist(rpc_port, launch),
+    )
+}
+
+fn windows_persistent_start(rpc_port: u16, launch: &RpcLaunch) -> String {
+    format!(
+        r#"# Replace an agent left running by a previous non-persistent install
+Get-Process -Name "llama-rpc-server" -ErrorAction SilentlyContinue | Stop-Process -Force
+
+Write-Host "[SharedLLM] Registering Scheduled Task '{task}'..."
+$TaskXml = @'
+{xml}'@
+$TaskXml = $TaskXml.Replace('{user_ph}', [Security.SecurityElement]::Escape("$env:USERDOMAIN\$env:USERNAME"))
+$TaskXml = $TaskXml.Replace('{dir_ph}', [Security.SecurityElement]::Escape($InstallDir))
+Register-ScheduledTask -TaskName "{task}" -Xml $TaskXml -Force | Out-Null
+Start-ScheduledTask -TaskName "{task}"
+
 Write-Host ""
+Write-Host "[SharedLLM] RPC agent installed as a Scheduled Task (starts at boot, restarts on crash)"
+Write-Host "  Listening: 0.0.0.0:$RpcPort"
+Write-Host "  Status:    Get-ScheduledTask -TaskName '{task}'"
+"#,
+        task = WINDOWS_TASK,
+        xml = rpc_task_xml(rpc_port, launch),
+        user_ph = TASK_USER_PLACEHOLDER,
+        dir_ph = TASK_DIR_PLACEHOLDER,
+    )
+}
 
-# Self-register with the host dashboard
-$MyIp = (Get-NetIPAddress -AddressFamily IPv4 | Where-Object {{ $_.IPAddress -notmatch '^127' -and $_.IPAddress -notmatch '^169' }} | Select-Object -First 1).IPAddress
-$MyName = $env:COMPUTERNAME
-if ($MyIp) {{
-    Write-Host "[SharedLLM] Registering with host at {host_ip}:{dashboard_port}..."
+// ─── Service definitions ──────────────────────────────────────────────────────
+
+/// Arguments passed to llama-rpc-server by every service definition.
+/// `--cache` keeps received tensors on disk, so a model the device has seen
+/// before isn't streamed over the network again.
+fn rpc_args(rpc_port: u16, launch: &RpcLaunch) -> Vec<String> {
+    let mut args = vec![
+        "--host".to_string(),
+        "0.0.0.0".to_string(),
+        "--port".to_string(),
+        rpc_port.to_string(),
+        "--cache".to_string(),
+    ];
+    args.extend(launch.args());
+    args
+}
+
+/// systemd user unit running `exec_start` (already escaped; `%h` expands to
+/// the user's home directory) and appending output to ~/.sharedmem/`log_file`.
+fn systemd_unit(description: &str, exec_start: &str, log_file: &str) -> String {
+    format!(
+        "[Unit]
+Description={description}
+
+[Service]
+Type=simple
+ExecStart={exec_start}
+Restart=always
+RestartSec=5
+StandardOutput=append:%h/.sharedmem/{log_file}
+StandardError=append:%h/.sharedmem/{log_file}
+
+[Install]
+WantedBy=default.target
+",
+        description = description.replace('%', "%%"),
+        exec_start = exec_start,
+        log_file = log_file,
+    )
+}
+
+fn rpc_systemd_unit(rpc_port: u16, launch: &RpcLaunch) -> String {
+    let args: Vec<String> = rpc_args(rpc_port, launch).iter().map(|a| systemd_escape(a)).collect();
+    systemd_unit(
+        "SharedLLM RPC agent (llama-rpc-server)",
+        &format!("%h/.sharedmem/bin/llama-rpc-server {}", args.join(" ")),
+        "rpc-server.log",
+    )
+}
+
+fn heartbeat_systemd_unit() -> String {
+    systemd_unit(
+        "SharedLLM agent heartbeat",
+        "%h/.sharedmem/bin/heartbeat.sh",
+        "heartbeat.log",
+    )
+}
+
+/// launchd agent running `command` through `/bin/sh -c`. launchd doesn't
+/// expand `~` or `$HOME` in paths; the shell does.
+fn launchd_plist(label: &str, command: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{label}</string>
+  <key>ProgramArguments</key>
+  <array>
+    <string>/bin/sh</string>
+    <string>-c</string>
+    <string>{command}</string>
+  </array>
+  <key>RunAtLoad</key>
+  <true/>
+  <key>KeepAlive</key>
+  <true/>
+  <key>ThrottleInterval</key>
+  <integer>5</integer>
+</dict>
+</plist>
+"#,
+        label = xml_escape(label),
+        command = xml_escape(command),
+    )
+}
+
+fn rpc_launchd_plist(rpc_port: u16, launch: &RpcLaunch) -> String {
+    let args: Vec<String> = rpc_args(rpc_port, launch).iter().map(|a| sh_quote(a)).collect();
+    launchd_plist(
+        LAUNCHD_LABEL,
+        &format!(
+            r#"exec "$HOME/.sharedmem/bin/llama-rpc-server" {} >> "$HOME/.sharedmem/rpc-server.log" 2>&1"#,
+            args.join(" ")
+        ),
+    )
+}
+
+fn heartbeat_launchd_plist() -> String {
+    launchd_plist(
+        LAUNCHD_HEARTBEAT_LABEL,
+        r#"exec "$HOME/.sharedmem/bin/heartbeat.sh" >> "$HOME/.sharedmem/heartbeat.log" 2>&1"#,
+    )
+}
+
+/// Task Scheduler definition: boot trigger, S4U principal (runs without a
+/// logged-in user), unlimited run time, restart every minute after a failure.
+/// `command` / `arguments` may contain the install-dir placeholder.
+fn task_scheduler_xml(description: &str, command: &str, arguments: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>{description}</Description>
+  </RegistrationInfo>
+  <Triggers>
+    <BootTrigger>
+      <Enabled>true</Enabled>
+    </BootTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <UserId>{user}</UserId>
+      <LogonType>S4U</LogonType>
+      <RunLevel>LeastPrivilege</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <RestartOnFailure>
+      <Interval>PT1M</Interval>
+      <Count>999</Count>
+    </RestartOnFailure>
+    <Enabled>true</Enabled>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{command}</Command>
+      <Arguments>{arguments}</Arguments>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        description = xml_escape(description),
+        user = TASK_USER_PLACEHOLDER,
+        command = xml_escape(command),
+        arguments = xml_escape(arguments),
+    )
+}
+
+fn rpc_task_xml(rpc_port: u16, launch: &RpcLaunch) -> String {
+    task_scheduler_xml(
+        "SharedLLM RPC agent (llama-rpc-server)",
+        &format!(r"{}\llama-rpc-server.exe", TASK_DIR_PLACEHOLDER),
+        &rpc_args(rpc_port, launch).join(" "),
+    )
+}
+
+fn heartbeat_task_xml() -> String {
+    task_scheduler_xml(
+        "SharedLLM agent heartbeat",
+        "powershell.exe",
+        &format!(
+            r#"-NoProfile -ExecutionPolicy Bypass -WindowStyle Hidden -File "{}\heartbeat.ps1""#,
+            TASK_DIR_PLACEHOLDER
+        ),
+    )
+}
+
+// ─── Heartbeat ────────────────────────────────────────────────────────────────
+//
+// A small loop installed next to llama-rpc-server that POSTs memory figures
+// and RPC status to /api/agent/heartbeat, authenticated with the enrollment
+// token baked in at generation time.
+
+fn linux_heartbeat_script(target: &AgentHost, token: &str, rpc_port: u16, persist: bool) -> String {
+    format!(
+        r#"#!/usr/bin/env bash
+# SharedLLM agent heartbeat: reports memory and RPC status to the host every {interval} s.
+HOST_URL={host_url}
+TOKEN={token}
+RPC_PORT={rpc_port}
+CAN_RESTART={persist}
+{pin}
+# Hardware inventory; only the free disk space changes between heartbeats
+CPU_MODEL=$(awk -F': ' '/^model name/ {{print $2; exit}}' /proc/cpuinfo 2>/dev/null | tr -d '"\\')
+CPU_CORES=$(lscpu -p=core,socket 2>/dev/null | grep -v '^#' | sort -u | wc -l)
+OS_VERSION=$(. /etc/os-release 2>/dev/null && echo "$PRETTY_NAME" | tr -d '"\\')
+GPU_DRIVER=$(nvidia-smi --query-gpu=driver_version --format=csv,noheader 2>/dev/null | head -n1)
+[ -n "$GPU_DRIVER" ] || GPU_DRIVER=$(cat /opt/rocm/.info/version 2>/dev/null)
+while true; do
+{verify}  MY_IP=$(ip route get 8.8.8.8 2>/dev/null | grep -oP 'src \K\S+' || hostname -I 2>/dev/null | awk '{{print $1}}' || echo "")
+  read -r TOTAL FREE < <(free -m | awk '/^Mem:/ {{print $2, $7}}')
+  if pgrep -f "rpc-server" > /dev/null; then RUNNING=true; else RUNNING=false; fi
+  CACHE_MB=$(du -sm "${{XDG_CACHE_HOME:-$HOME/.cache}}/llama.cpp/rpc" 2>/dev/null | cut -f1)
+  DISK_FREE_MB=$(df -Pm "$HOME" 2>/dev/null | awk 'NR==2 {{print $4}}')
+  INVENTORY="{{\"cpu_model\": \"$CPU_MODEL\", \"cpu_cores\": ${{CPU_CORES:-0}}, \"os_version\": \"$OS_VERSION\", \"gpu_driver\": \"$GPU_DRIVER\", \"disk_free_mb\": ${{DISK_FREE_MB:-0}}}}"
+  REPLY=$(curl -fsS {curl_tls}-m 10 -X POST "$HOST_URL/api/agent/heartbeat" \
+    -H "Authorization: Bearer $TOKEN" \
+    -H "Content-Type: application/json" \
+    -d "{{\"ip\": \"$MY_IP\", \"memory_total_mb\": ${{TOTAL:-0}}, \"memory_free_mb\": ${{FREE:-0}}, \"rpc_port\": $RPC_PORT, \"rpc_running\": $RUNNING, \"rpc_cache_mb\": ${{CACHE_MB:-0}}, \"inventory\": $INVENTORY, \"can_restart_rpc\": $CAN_RESTART}}" \
+    || true)
+{restart}  sleep {interval}
+done
+"#,
+        host_url = sh_quote(&target.url()),
+        token = sh_quote(token),
+        pin = sh_pin_check(target, false),
+        verify = if target.tls_fingerprint.is_some() {
+            format!("  verify_host || {{ sleep {}; continue; }}\n", HEARTBEAT_INTERVAL_SECS)
+        } else {
+            String::new()
+        },
+        curl_tls = curl_tls_flag(target),
+        rpc_port = rpc_port,
+        persist = persist,
+        restart = sh_restart_rpc(persist, &format!("systemctl --user restart {}", SYSTEMD_UNIT)),
+        interval = HEARTBEAT_INTERVAL_SECS,
+    )
+}
+
+fn macos_heartbeat_script(target: &AgentHost, token: &str, rpc_port: u16, persist: bool) -> String {
+    format!(
+        r#"#!/usr/bin/env bash
+# SharedLLM agent heartbeat: reports memory and RPC status to the host every {interval} s.
+HOST_URL={host_url}
+TOKEN={token}
+RPC_PORT={rpc_port}
+CAN_RESTART={persist}
+{pin}
+# Hardware inventory; only the free disk space changes between heartbeats
+CPU_MODEL=$(sysctl -n machdep.cpu.brand_string 2>/dev/null | tr -d '"\\')
+CPU_CORES=$(sysctl -n hw.physicalcpu 2>/dev/null)
+OS_VERSION="macOS $(sw_vers -productVersion 2>/dev/null) ($(sw_vers -buildVersion 2>/dev/null))"
+GPU_DRIVER=$(sw_vers -buildVersion 2>/dev/null)
+while true; do
+{verify}  MY_IP=$(ipconfig getifaddr en0 2>/dev/null || ipconfig getifaddr en1 2>/dev/null || echo "")
+  TOTAL=$(( $(sysctl -n hw.memsize) / 1048576 ))
+  PAGE=$(vm_stat | awk '/page size of/ {{print $8}}')
+  PAGES=$(vm_stat | awk '/Pages (free|inactive|speculative)/ {{gsub(/\./, "", $NF); n += $NF}} END {{print n + 0}}')
+  FREE=$(( PAGES * ${{PAGE:-4096}} / 1048576 ))
+  if pgrep -f "rpc-server" > /dev/null; then RUNNING=true; else RUNNING=false; fi
+  CACHE_MB=$(du -sm "$HOME/Library/Caches/llama.cpp/rpc" 2>/dev/null | cut -f1)
+  DISK_FREE_MB=$(df -m "$HOME" 2>/dev/null | awk 'NR==2 {{print $4}}')
+  INVENTORY="{{\"cpu_model\": \"$CPU_MODEL\", \"cpu_cores\": ${{CPU_CORES:-0}}, \"os_version\": \"$OS_VERSION\", \"gpu_driver\": \"$GPU_DRIVER\", \"disk_free_mb\": ${{DISK_FREE_MB:-0}}}}"
+  REPLY=$(curl -fsS {curl_tls}-m 10 -X POST "$HOST_URL/api/agent/heartbeat" \
+    -H "Authorization: Bearer $TOKEN" \
+    -H "Content-Type: application/json" \
+    -d "{{\"ip\": \"$MY_IP\", \"memory_total_mb\": $TOTAL, \"memory_free_mb\": $FREE, \"rpc_port\": $RPC_PORT, \"rpc_running\": $RUNNING, \"rpc_cache_mb\": ${{CACHE_MB:-0}}, \"inventory\": $INVENTORY, \"can_restart_rpc\": $CAN_RESTART}}" \
+    || true)
+{restart}  sleep {interval}
+done
+"#,
+        host_url = sh_quote(&target.url()),
+        token = sh_quote(token),
+        pin = sh_pin_check(target, false),
+        verify = if target.tls_fingerprint.is_some() {
+            format!("  verify_host || {{ sleep {}; continue; }}\n", HEARTBEAT_INTERVAL_SECS)
+        } else {
+            String::new()
+        },
+        curl_tls = curl_tls_flag(target),
+        rpc_port = rpc_port,
+        persist = persist,
+        restart = sh_restart_rpc(persist, &format!("launchctl kickstart -k \"gui/$(id -u)/{}\"", LAUNCHD_LABEL)),
+        interval = HEARTBEAT_INTERVAL_SECS,
+    )
+}
+
+/// Heartbeat loop step of an agent installed as a service: restart
+/// llama-rpc-server with `command` when the answer asks, then report back
+/// soon instead of a whole interval later.
+fn sh_restart_rpc(persist: bool, command: &str) -> String {
+    if !persist {
+        return String::new();
+    }
+    format!(
+        r#"  case "$REPLY" in
+    *'"restart_rpc":true'*)
+      echo "[SharedLLM] Restarting llama-rpc-server as asked by the host"
+      {command} || true
+      sleep 5
+      continue ;;
+  esac
+"#,
+        command = command,
+    )
+}
+
+fn windows_heartbeat_script(target: &AgentHost, token: &str, rpc_port: u16, persist: bool) -> String {
+    format!(
+        r#"# SharedLLM agent heartbeat: reports memory and RPC status to the host every {interval} s.
+$HostUrl = {host_url}
+$Token = {token}
+$RpcPort = {rpc_port}
+$CanRestart = ${persist}
+{pin}
+# Hardware inventory; only the free disk space changes between heartbeats
+$Cpu = Get-CimInstance Win32_Processor | Select-Object -First 1
+$Gpu = Get-CimInstance Win32_VideoController | Select-Object -First 1
+while ($true) {{
     try {{
-        $Body = '{{\"name\": \"' + $MyName + '\", \"ip\": \"' + $MyIp + '\"}}'
-        Invoke-RestMethod -Uri "http://{host_ip}:{dashboard_port}/api/devices" -Method Post -ContentType "application/json" -Body $Body | Out-Null
-        Write-Host "[SharedLLM] Registered! Go to http://{host_ip}:{dashboard_port}/devices to approve this device."
-    }} catch {{
-        Write-Host "[SharedLLM] Could not auto-register. Add manually at http://{host_ip}:{dashboard_port}/devices (Name=$MyName, IP=$MyIp)"
-    }}
-}} else {{
-    Write-Host "[SharedLLM] Could not detect local IP. Add this device manually at http://{host_ip}:{dashboard_port}/devices"
+        $Os = Get-CimInstance Win32_OperatingSystem
+        $Disk = Get-PSDrive -Name $env:SystemDrive.TrimEnd(':')
+        $MyIp = (Get-NetIPAddress -AddressFamily IPv4 | Where-Object {{ $_.IPAddress -notmatch '^127' -and $_.IPAddress -notmatch '^169' }} | Select-Object -First 1).IPAddress
+        $Running = [bool](Get-Process -Name "llama-rpc-server" -ErrorAction SilentlyContinue)
+        $CacheBytes = (Get-ChildItem "$env:LOCALAPPDATA\llama.cpp\rpc" -Recurse -File -ErrorAction SilentlyContinue | Measure-Object -Property Length -Sum).Sum
+        $Body = @{{
+            ip = $MyIp
+            memory_total_mb = [int]($Os.TotalVisibleMemorySize / 1024)
+            memory_free_mb = [int]($Os.FreePhysicalMemory / 1024)
+            rpc_port = $RpcPort
+            rpc_running = $Running
+            rpc_cache_mb = [int64]($CacheBytes / 1MB)
+            can_restart_rpc = $CanRestart
+            inventory = @{{
+                cpu_model = "$($Cpu.Name)".Trim()
+                cpu_cores = [int]$Cpu.NumberOfCores
+                os_version = "$($Os.Caption) $($Os.Version)".Trim()
+                gpu_driver = "$($Gpu.DriverVersion)"
+                disk_free_mb = [int64]($Disk.Free / 1MB)
+            }}
+        }} | ConvertTo-Json -Compress
+        $Reply = Invoke-RestMethod -Uri "$HostUrl/api/agent/heartbeat" -Method Post -ContentType "application/json" `
+            -Headers @{{ Authorization = "Bearer $Token" }} -Body $Body -TimeoutSec 10
+{restart}    }} catch {{}}
+    Start-Sleep -Seconds {interval}
 }}
 "#,
-        host_ip = host_ip,
-        dashboard_port = dashboard_port,
+        host_url = ps_quote(&target.url()),
+        token = ps_quote(token),
+        pin = ps_pin_check(target),
         rpc_port = rpc_port,
+        persist = persist,
+        restart = if persist {
+            format!(
+                r#"        if ($CanRestart -and $Reply.restart_rpc) {{
+            Write-Host "[SharedLLM] Restarting llama-rpc-server as asked by the host"
+            Stop-ScheduledTask -TaskName "{task}" -ErrorAction SilentlyContinue
+            Get-Process -Name "llama-rpc-server" -ErrorAction SilentlyContinue | Stop-Process -Force
+            Start-ScheduledTask -TaskName "{task}"
+            Start-Sleep -Seconds 5
+            continue
+        }}
+"#,
+                task = WINDOWS_TASK,
+            )
+        } else {
+            String::new()
+        },
+        interval = HEARTBEAT_INTERVAL_SECS,
+    )
+}
+
+/// Installer tail: write the heartbeat script and start it (one-off, or as a
+/// second service next to the RPC server).
+fn linux_heartbeat_block(script: &str, persist: bool) -> String {
+    let start = if persist {
+        format!(
+            r#"cat > "$HOME/.config/systemd/user/{unit}" <<'{unit_heredoc}'
+{unit_file}{unit_heredoc}
+systemctl --user daemon-reload
+systemctl --user enable --now {unit}
+"#,
+            unit = SYSTEMD_HEARTBEAT_UNIT,
+            unit_heredoc = UNIT_HEREDOC,
+            unit_file = heartbeat_systemd_unit(),
+        )
+    } else {
+        r#"if [ -f "$HOME/.sharedmem/heartbeat.pid" ]; then
+  kill "$(cat "$HOME/.sharedmem/heartbeat.pid")" 2>/dev/null || true
+fi
+nohup "$INSTALL_DIR/heartbeat.sh" > "$HOME/.sharedmem/heartbeat.log" 2>&1 &
+echo $! > "$HOME/.sharedmem/heartbeat.pid"
+"#
+        .to_string()
+    };
+    format!(
+        r#"
+# Heartbeat: report memory and RPC status to the host every {interval} s
+cat > "$INSTALL_DIR/heartbeat.sh" <<'{heredoc}'
+{script}{heredoc}
+chmod +x "$INSTALL_DIR/heartbeat.sh"
+{start}echo "[SharedLLM] Heartbeat running (every {interval} s)."
+"#,
+        interval = HEARTBEAT_INTERVAL_SECS,
+        heredoc = HEARTBEAT_HEREDOC,
+        script = script,
+        start = start,
+    )
+}
+
+fn macos_heartbeat_block(script: &str, persist: bool) -> String {
+    let start = if persist {
+        format!(
+            r#"HB_PLIST="$HOME/Library/LaunchAgents/{label}.plist"
+cat > "$HB_PLIST" <<'{plist_heredoc}'
+{plist}{plist_heredoc}
+launchctl bootout "gui/$(id -u)/{label}" 2>/dev/null || true
+launchctl bootstrap "gui/$(id -u)" "$HB_PLIST"
+"#,
+            label = LAUNCHD_HEARTBEAT_LABEL,
+            plist_heredoc = PLIST_HEREDOC,
+            plist = heartbeat_launchd_plist(),
+        )
+    } else {
+        r#"if [ -f "$HOME/.sharedmem/heartbeat.pid" ]; then
+  kill "$(cat "$HOME/.sharedmem/heartbeat.pid")" 2>/dev/null || true
+fi
+nohup "$INSTALL_DIR/heartbeat.sh" > "$HOME/.sharedmem/heartbeat.log" 2>&1 &
+echo $! > "$HOME/.sharedmem/heartbeat.pid"
+"#
+        .to_string()
+    };
+    format!(
+        r#"
+# Heartbeat: report memory and RPC status to the host every {interval} s
+mkdir -p "$INSTALL_DIR"
+cat > "$INSTALL_DIR/heartbeat.sh" <<'{heredoc}'
+{script}{heredoc}
+chmod +x "$INSTALL_DIR/heartbeat.sh"
+{start}echo "[SharedLLM] Heartbeat running (every {interval} s)."
+"#,
+        interval = HEARTBEAT_INTERVAL_SECS,
+        heredoc = HEARTBEAT_HEREDOC,
+        script = script,
+        start = start,
+    )
+}
+
+fn windows_heartbeat_block(script: &str, persist: bool) -> String {
+    let start = if persist {
+        format!(
+            r#"$HeartbeatXml = @'
+{xml}'@
+$HeartbeatXml = $HeartbeatXml.Replace('{user_ph}', [Security.SecurityElement]::Escape("$env:USERDOMAIN\$env:USERNAME"))
+$HeartbeatXml = $HeartbeatXml.Replace('{dir_ph}', [Security.SecurityElement]::Escape($InstallDir))
+Register-ScheduledTask -TaskName "{task}" -Xml $HeartbeatXml -Force | Out-Null
+Start-ScheduledTask -TaskName "{task}"
+"#,
+            xml = heartbeat_task_xml(),
+            user_ph = TASK_USER_PLACEHOLDER,
+            dir_ph = TASK_DIR_PLACEHOLDER,
+            task = WINDOWS_HEARTBEAT_TASK,
+        )
+    } else {
+        r#"Get-CimInstance Win32_Process -Filter "Name = 'powershell.exe'" |
+  Where-Object { $_.CommandLine -like '*heartbeat.ps1*' } |
+  ForEach-Object { Stop-Process -Id $_.ProcessId -Force -ErrorAction SilentlyContinue }
+Start-Process -FilePath "powershell.exe" `
+  -ArgumentList "-NoProfile -ExecutionPolicy Bypass -File `"$InstallDir\heartbeat.ps1`"" `
+  -WindowStyle Hidden
+"#
+        .to_string()
+    };
+    format!(
+        r#"
+# Heartbeat: report memory and RPC status to the host every {interval} s
+$HeartbeatScript = @'
+{script}'@
+Set-Content -Path "$InstallDir\heartbeat.ps1" -Value $HeartbeatScript
+{start}Write-Host "[SharedLLM] Heartbeat running (every {interval} s)."
+"#,
+        interval = HEARTBEAT_INTERVAL_SECS,
+        script = script,
+        start = start,
+    )
+}
+
+// ─── Escaping ─────────────────────────────────────────────────────────────────
+
+/// Quote one argument for a systemd `ExecStart=` line: `%` specifiers are
+/// doubled, and anything with whitespace, quotes or backslashes is wrapped in
+/// double quotes with C-style escapes.
+fn systemd_escape(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    if !arg.is_empty()
+        && !arg.chars().any(|c| c.is_whitespace() || "\"'\\;$".contains(c))
+    {
+        return arg;
+    }
+    let mut out = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '$' => out.push_str("$$"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Single-quote an argument for POSIX sh.
+/// `launch` as the `rpc_launch` JSON object of a registration request.
+fn launch_json(launch: &RpcLaunch) -> String {
+    serde_json::to_string(launch).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// [`launch_json`] for a double-quoted shell string.
+fn sh_launch_json(launch: &RpcLaunch) -> String {
+    launch_json(launch).replace('"', "\\\"")
+}
+
+fn sh_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=".contains(c))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r#"'\''"#))
+}
+
+/// Single-quote a string for PowerShell.
+fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// ─── Uninstall scripts ────────────────────────────────────────────────────────
+
+fn linux_uninstall_script(host_url: &str) -> String {
+    format!(
+        r#"#!/usr/bin/env bash
+# SharedLLM RPC Agent Uninstaller - Linux
+# Removes the systemd user service (if installed), stops the agent and deletes ~/.sharedmem.
+
+set -uo pipefail
+
+for UNIT in {unit} {heartbeat_unit}; do
+  UNIT_FILE="$HOME/.config/systemd/user/$UNIT"
+  if [ -f "$UNIT_FILE" ]; then
+    echo "[SharedLLM] Removing systemd user service $UNIT..."
+    systemctl --user disable --now "$UNIT" 2>/dev/null || true
+    rm -f "$UNIT_FILE"
+  fi
+done
+systemctl --user daemon-reload 2>/dev/null || true
+
+for PID_FILE in "$HOME/.sharedmem/rpc-server.pid" "$HOME/.sharedmem/heartbeat.pid"; do
+  if [ -f "$PID_FILE" ]; then
+    kill "$(cat "$PID_FILE")" 2>/dev/null || true
+  fi
+done
+
+rm -rf "$HOME/.sharedmem"
+
+echo ""
+echo "[SharedLLM] RPC agent removed."
+echo "  Remove this device from the dashboard at {host_url}/devices"
+echo ""
+"#,
+        unit = SYSTEMD_UNIT,
+        heartbeat_unit = SYSTEMD_HEARTBEAT_UNIT,
+        host_url = host_url,
+    )
+}
+
+fn macos_uninstall_script(host_url: &str) -> String {
+    format!(
+        r#"#!/usr/bin/env bash
+# SharedLLM RPC Agent Uninstaller - macOS
+# Removes the launchd agent (if installed), stops the agent and deletes ~/.sharedmem.
+# A Homebrew-installed llama.cpp is left in place (brew uninstall llama.cpp).
+
+set -uo pipefail
+
+for LABEL in {label} {heartbeat_label}; do
+  PLIST="$HOME/Library/LaunchAgents/$LABEL.plist"
+  if [ -f "$PLIST" ]; then
+    echo "[SharedLLM] Removing launchd agent $LABEL..."
+    launchctl bootout "gui/$(id -u)/$LABEL" 2>/dev/null || true
+    rm -f "$PLIST"
+  fi
+done
+
+for PID_FILE in "$HOME/.sharedmem/rpc-server.pid" "$HOME/.sharedmem/heartbeat.pid"; do
+  if [ -f "$PID_FILE" ]; then
+    kill "$(cat "$PID_FILE")" 2>/dev/null || true
+  fi
+done
+
+rm -rf "$HOME/.sharedmem"
+
+echo ""
+echo "[SharedLLM] RPC agent removed."
+echo "  Remove this device from the dashboard at {host_url}/devices"
+echo ""
+"#,
+        label = LAUNCHD_LABEL,
+        heartbeat_label = LAUNCHD_HEARTBEAT_LABEL,
+        host_url = host_url,
+    )
+}
+
+fn windows_uninstall_script(host_url: &str) -> String {
+    format!(
+        r#"# SharedLLM RPC Agent Uninstaller - Windows (PowerShell)
+# Run with: irm {host_url}/agent/uninstall?os=windows | iex
+
+foreach ($Task in @("{task}", "{heartbeat_task}")) {{
+    if (Get-ScheduledTask -TaskName $Task -ErrorAction SilentlyContinue) {{
+        Write-Host "[SharedLLM] Removing Scheduled Task '$Task'..."
+        Stop-ScheduledTask -TaskName $Task -ErrorAction SilentlyContinue
+        Unregister-ScheduledTask -TaskName $Task -Confirm:$false
+    }}
+}}
+
+Get-Process -Name "llama-rpc-server" -ErrorAction SilentlyContinue | Stop-Process -Force
+Get-CimInstance Win32_Process -Filter "Name = 'powershell.exe'" |
+  Where-Object {{ $_.CommandLine -like '*heartbeat.ps1*' }} |
+  ForEach-Object {{ Stop-Process -Id $_.ProcessId -Force -ErrorAction SilentlyContinue }}
+
+Remove-Item -Recurse -Force "$env:USERPROFILE\.sharedmem" -ErrorAction SilentlyContinue
+
+Write-Host ""
+Write-Host "[SharedLLM] RPC agent removed."
+Write-Host "  Remove this device from the dashboard at {host_url}/devices"
+Write-Host ""
+"#,
+        task = WINDOWS_TASK,
+        heartbeat_task = WINDOWS_HEARTBEAT_TASK,
+        host_url = host_url,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const FP: &str = "ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89:ab:cd:ef:01:23:45:67:89";
+
+    fn lan_host() -> AgentHost {
+        AgentHost {
+            host: "10.0.0.1".to_string(),
+            port: "8080".to_string(),
+            tls_fingerprint: None,
+        }
+    }
+
+    fn resolve(query: Option<&str>, header: Option<&str>) -> Result<(String, String), String> {
+        resolve_host(query, header, Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5))), "8080")
+    }
+
+    /// Minimal well-formedness check: every open tag is closed in order and
+    /// text never contains a bare `<` or an unescaped `&`.
+    fn assert_balanced_xml(xml: &str) {
+        let mut stack: Vec<String> = Vec::new();
+        let mut rest = xml;
+        while let Some(start) = rest.find('<') {
+            let text = &rest[..start];
+            for (i, _) in text.match_indices('&') {
+                let entity = &text[i..];
+                assert!(
+                    ["&amp;", "&lt;", "&gt;", "&quot;", "&apos;"]
+                        .iter()
+                        .any(|e| entity.starts_with(e)),
+                    "unescaped & in {:?}",
+                    text
+                );
+            }
+            let end = rest[start..].find('>').expect("unterminated tag") + start;
+            let tag = &rest[start + 1..end];
+            rest = &rest[end + 1..];
+
+            if tag.starts_with('?') || tag.starts_with('!') || tag.ends_with('/') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(stack.pop().as_deref(), Some(name), "mismatched </{}>", name);
+            } else {
+                let name = tag.split_whitespace().next().unwrap();
+                stack.push(name.to_string());
+            }
+        }
+        assert!(stack.is_empty(), "unclosed tags: {:?}", stack);
+    }
+
+    /// Body of a quoted heredoc / here-string between `open` and a line equal to `close`.
+    fn embedded<'a>(script: &'a str, open: &str, close: &str) -> &'a str {
+        let start = script.find(open).expect("heredoc start") + open.len();
+        let body = &script[start..];
+        let body = body.strip_prefix('\n').unwrap_or(body);
+        let end = body
+            .find(&format!("\n{}\n", close))
+            .expect("heredoc terminator on its own line");
+        &body[..=end]
+    }
+
+    #[test]
+    fn systemd_unit_is_well_formed() {
+        let unit = rpc_systemd_unit(8181, &RpcLaunch::default());
+        let mut sections = Vec::new();
+        for line in unit.lines().filter(|l| !l.trim().is_empty()) {
+            if line.starts_with('[') {
+                assert!(line.ends_with(']'), "bad section header {:?}", line);
+                sections.push(line);
+                continue;
+            }
+            let (key, value) = line.split_once('=').expect("key=value line");
+            assert!(!key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric()));
+            assert!(!value.is_empty());
+        }
+        assert_eq!(sections, ["[Unit]", "[Service]", "[Install]"]);
+        assert!(unit.contains("\nExecStart=%h/.sharedmem/bin/llama-rpc-server --host 0.0.0.0 --port 8181 --cache\n"));
+        assert!(unit.contains("\nRestart=always\n"));
+        assert!(unit.contains("\nWantedBy=default.target\n"));
+    }
+
+    #[test]
+    fn launchd_plist_is_well_formed() {
+        let plist = rpc_launchd_plist(9000, &RpcLaunch::default());
+        assert_balanced_xml(&plist);
+        assert!(plist.contains(&format!("<string>{}</string>", LAUNCHD_LABEL)));
+        assert!(plist.contains("<key>KeepAlive</key>\n  <true/>"));
+        assert!(plist.contains("<key>RunAtLoad</key>\n  <true/>"));
+        assert!(plist.contains("--port 9000"));
+    }
+
+    #[test]
+    fn task_xml_is_well_formed() {
+        let xml = rpc_task_xml(8181, &RpcLaunch::default());
+        assert_balanced_xml(&xml);
+        assert!(xml.contains("<BootTrigger>"));
+        assert!(xml.contains("<RestartOnFailure>"));
+        assert!(xml.contains(TASK_USER_PLACEHOLDER));
+        assert!(xml.contains(TASK_DIR_PLACEHOLDER));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(systemd_escape("--port"), "--port");
+        assert_eq!(systemd_escape("100%"), "100%%");
+        assert_eq!(systemd_escape(r#"a "b" $c"#), r#""a \"b\" $$c""#);
+        assert_eq!(systemd_escape(""), r#""""#);
+        assert_eq!(sh_quote("0.0.0.0"), "0.0.0.0");
+        assert_eq!(sh_quote("it's here"), r#"'it'\''s here'"#);
+        assert_eq!(xml_escape(r#"<a & "b">"#), "&lt;a &amp; &quot;b&quot;&gt;");
+    }
+
+    #[test]
+    fn persistent_scripts_embed_service_files_verbatim() {
+        let linux = linux_script(&lan_host(), 8181, &RpcLaunch::default(), true, "tok");
+        assert_eq!(
+            embedded(&linux, &format!("<<'{}'", UNIT_HEREDOC), UNIT_HEREDOC),
+            rpc_systemd_unit(8181, &RpcLaunch::default())
+        );
+        assert!(linux.contains("systemctl --user enable --now sharedllm-rpc.service"));
+        assert!(!linux.contains("nohup"));
+
+        let macos = macos_script(&lan_host(), 8181, &RpcLaunch::default(), true, "tok");
+        assert_eq!(
+            embedded(&macos, &format!("<<'{}'", PLIST_HEREDOC), PLIST_HEREDOC),
+            rpc_launchd_plist(8181, &RpcLaunch::default())
+        );
+        assert!(macos.contains("launchctl bootstrap"));
+        assert!(!macos.contains("nohup"));
+
+        let windows = windows_script(&lan_host(), 8181, &RpcLaunch::default(), true, "tok");
+        assert_eq!(embedded(&windows, "$TaskXml = @'", "'@"), rpc_task_xml(8181, &RpcLaunch::default()));
+        assert!(windows.contains("Register-ScheduledTask"));
+        assert!(!windows.contains("Start-Process"));
+    }
+
+    #[test]
+    fn transient_scripts_are_unchanged_by_default() {
+        assert!(linux_script(&lan_host(), 8181, &RpcLaunch::default(), false, "tok").contains("nohup \"$INSTALL_DIR/llama-rpc-server\""));
+        assert!(macos_script(&lan_host(), 8181, &RpcLaunch::default(), false, "tok").contains("nohup \"${LLAMA_RPC}\""));
+        let windows = windows_script(&lan_host(), 8181, &RpcLaunch::default(), false, "tok");
+        assert!(windows.contains("Start-Process"));
+        assert!(!windows.contains("IsAdmin"));
+    }
+
+    #[test]
+    fn launch_options_reach_the_command_and_registration() {
+        let params = |q: &[(&str, &str)]| {
+            q.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let launch = rpc_launch("linux", &params(&[("backend", "CPU"), ("mem_mb", "4096")])).unwrap();
+        assert_eq!(launch, RpcLaunch { backend: Some(RpcBackend::Cpu), mem_mb: Some(4096) });
+        assert!(rpc_launch("macos", &params(&[("backend", "cuda")])).is_err());
+        assert!(rpc_launch("windows", &params(&[("backend", "metal")])).is_err());
+        assert!(rpc_launch("linux", &params(&[("mem_mb", "0")])).is_err());
+        assert!(rpc_launch("linux", &params(&[("backend", "rocm")])).is_err());
+
+        let linux = linux_script(&lan_host(), 8181, &launch, false, "tok");
+        assert!(linux.contains(r#"RPC_EXTRA_ARGS="--device CPU --mem 4096""#));
+        assert!(linux.contains(r#"\"rpc_launch\": {\"backend\":\"cpu\",\"mem_mb\":4096}"#));
+        assert!(rpc_systemd_unit(8181, &launch).contains("--cache --device CPU --mem 4096"));
+        assert!(rpc_task_xml(8181, &launch).contains("--cache --device CPU --mem 4096"));
+
+        let cuda = RpcLaunch { backend: Some(RpcBackend::Cuda), mem_mb: None };
+        assert!(linux_script(&lan_host(), 8181, &cuda, false, "tok").contains("LLAMA_RPC_SERVER"));
+        let windows = windows_script(&lan_host(), 8181, &cuda, true, "tok");
+        assert!(windows.contains("bin-win-cuda"));
+        assert!(windows.contains(r#"rpc_launch = ('{"backend":"cuda","mem_mb":null}' | ConvertFrom-Json)"#));
+    }
+
+    #[test]
+    fn registration_prints_the_pairing_code() {
+        let linux = linux_script(&lan_host(), 8181, &RpcLaunch::default(), false, "tok");
+        let extract = linux
+            .lines()
+            .find(|l| l.contains("PAIRING_CODE=$("))
+            .unwrap()
+            .trim()
+            .replacen("PAIRING_CODE=$(", "", 1);
+        let extract = extract.strip_suffix(')').unwrap();
+        assert!(linux.contains("It expires in 15 minutes"));
+        #[cfg(unix)]
+        {
+            let out = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(extract)
+                .env("RESPONSE", r#"{"id":"d1","pairing_attempts":0,"pairing_code":"042917"}"#)
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&out.stdout).trim(), "042917");
+        }
+
+        let windows = windows_script(&lan_host(), 8181, &RpcLaunch::default(), false, "tok");
+        assert!(windows.contains("$($Registered.pairing_code)"));
+        assert!(!windows.contains("-Body $Body | Out-Null"));
+    }
+
+    #[test]
+    fn uninstall_scripts_remove_services() {
+        let linux = linux_uninstall_script("http://h:1");
+        assert!(linux.contains("for UNIT in sharedllm-rpc.service sharedllm-heartbeat.service"));
+        assert!(linux.contains("heartbeat.pid"));
+        let macos = macos_uninstall_script("http://h:1");
+        assert!(macos.contains("for LABEL in com.sharedllm.rpc-server com.sharedllm.heartbeat"));
+        assert!(macos.contains("launchctl bootout"));
+        let windows = windows_uninstall_script("http://h:1");
+        assert!(windows.contains("@(\"SharedLLM RPC Agent\", \"SharedLLM Agent Heartbeat\")"));
+        assert!(windows.contains("Unregister-ScheduledTask -TaskName $Task"));
+    }
+
+    #[test]
+    fn heartbeat_service_files_are_well_formed() {
+        let unit = heartbeat_systemd_unit();
+        assert!(unit.contains("\nExecStart=%h/.sharedmem/bin/heartbeat.sh\n"));
+        assert_balanced_xml(&heartbeat_launchd_plist());
+        assert!(heartbeat_launchd_plist().contains("<string>com.sharedllm.heartbeat</string>"));
+        assert_balanced_xml(&heartbeat_task_xml());
+    }
+
+    #[test]
+    fn heartbeat_scripts_embed_quoted_token() {
+        let token = "ab'c";
+        let linux = linux_script(&lan_host(), 8181, &RpcLaunch::default(), false, token);
+        assert!(linux.contains(&format!("TOKEN={}\n", sh_quote(token))));
+        assert!(linux.contains("/api/agent/heartbeat"));
+        let windows = windows_script(&lan_host(), 8181, &RpcLaunch::default(), false, token);
+        assert!(windows.contains(&format!("$Token = {}\n", ps_quote(token))));
+    }
+
+    #[test]
+    fn only_service_agents_offer_rpc_restarts() {
+        let launch = RpcLaunch::default();
+        let linux = linux_script(&lan_host(), 8181, &launch, true, "tok");
+        assert!(linux.contains("CAN_RESTART=true\n"));
+        assert!(linux.contains("\"can_restart_rpc\\\": $CAN_RESTART"));
+        assert!(linux.contains("systemctl --user restart sharedllm-rpc.service || true"));
+        let macos = macos_script(&lan_host(), 8181, &launch, true, "tok");
+        assert!(macos.contains("launchctl kickstart -k \"gui/$(id -u)/com.sharedllm.rpc-server\""));
+        let windows = windows_script(&lan_host(), 8181, &launch, true, "tok");
+        assert!(windows.contains("$CanRestart = $true\n"));
+        assert!(windows.contains("Start-ScheduledTask -TaskName \"SharedLLM RPC Agent\"\n            Start-Sleep"));
+
+        let transient = linux_script(&lan_host(), 8181, &launch, false, "tok");
+        assert!(transient.contains("CAN_RESTART=false\n"));
+        assert!(!transient.contains("restart_rpc\":true"));
+        assert!(windows_script(&lan_host(), 8181, &launch, false, "tok").contains("$CanRestart = $false\n"));
+    }
+
+    #[test]
+    fn host_query_overrides_header_and_local_ip() {
+        assert_eq!(
+            resolve(Some("llm.example.lan"), Some("proxy.example.com:443")),
+            Ok(("llm.example.lan".to_string(), "8080".to_string()))
+        );
+        assert_eq!(
+            resolve(Some("[fd00::1]"), None),
+            Ok(("fd00::1".to_string(), "8080".to_string()))
+        );
+        assert!(resolve(Some("evil.com/x;rm -rf ~"), Some("ok.lan")).is_err());
+        assert!(resolve(Some("-bad.lan"), None).is_err());
+    }
+
+    #[test]
+    fn host_header_beats_local_ip() {
+        assert_eq!(
+            resolve(None, Some("Dashboard.Example.com:9000")),
+            Ok(("dashboard.example.com".to_string(), "9000".to_string()))
+        );
+        assert_eq!(
+            resolve(Some("  "), Some("203.0.113.7")),
+            Ok(("203.0.113.7".to_string(), "8080".to_string()))
+        );
+        assert_eq!(
+            resolve(None, Some("[2001:db8::2]:8443")),
+            Ok(("2001:db8::2".to_string(), "8443".to_string()))
+        );
+    }
+
+    #[test]
+    fn unusable_host_header_falls_back_to_local_ip() {
+        let local = Ok(("192.168.1.5".to_string(), "8080".to_string()));
+        for header in [
+            "localhost:5173",
+            "127.0.0.1:8080",
+            "[::1]:8080",
+            "0.0.0.0",
+            "evil\"$(id)\".com",
+            "host:notaport",
+            "host:0",
+            "",
+        ] {
+            assert_eq!(resolve(None, Some(header)), local, "header {:?}", header);
+        }
+        assert_eq!(resolve(None, None), local);
+        assert_eq!(
+            resolve_host(None, None, None, "8080"),
+            Ok(("YOUR_HOST_IP".to_string(), "8080".to_string()))
+        );
+    }
+
+    #[test]
+    fn pinned_host_uses_https_and_checks_fingerprint() {
+        let fp = normalize_fingerprint(FP).unwrap();
+        assert_eq!(fp, FP.to_ascii_uppercase());
+        assert_eq!(normalize_fingerprint(&FP.replace(':', "")), Some(fp.clone()));
+        assert_eq!(normalize_fingerprint("abcd"), None);
+
+        let target = AgentHost {
+            host: "fd00::1".to_string(),
+            port: "443".to_string(),
+            tls_fingerprint: Some(fp.clone()),
+        };
+        assert_eq!(target.url(), "https://[fd00::1]:443");
+
+        let linux = linux_script(&target, 8181, &RpcLaunch::default(), false, "tok");
+        assert!(linux.contains(&format!("EXPECTED_FP={}\n", fp)));
+        assert!(linux.contains("-connect [fd00::1]:443"));
+        assert!(linux.contains("curl -fsSL -k -X POST \"https://[fd00::1]:443/api/devices\""));
+        assert!(linux.contains("verify_host || { sleep 30; continue; }"));
+        let windows = windows_script(&target, 8181, &RpcLaunch::default(), false, "tok");
+        assert!(windows.contains(&format!("$ExpectedFp = '{}'", fp.replace(':', ""))));
+
+        let plain = linux_script(&lan_host(), 8181, &RpcLaunch::default(), false, "tok");
+        assert!(!plain.contains("verify_host"));
+        assert!(plain.contains("curl -fsSL -X POST \"http://10.0.0.1:8080/api/devices\""));
+    }
+}
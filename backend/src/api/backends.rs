@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Query, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
@@ -7,8 +7,15 @@ use axum::{
 use reqwest::header;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use uuid::Uuid;
 
-use crate::{db::queries, AppState};
+use super::openapi::{ErrorResponse, OkResponse};
+use crate::{
+    db::{models::BackendProfile, queries},
+    settings::Key,
+    ws::WsEvent,
+    AppState,
+};
 
 // ─── Types ────────────────────────────────────────────────────────────────────
 
@@ -16,7 +23,7 @@ use crate::{db::queries, AppState};
 /// The real key is stored in the database and never sent back to clients.
 /// On POST, send `null` / omit the field to keep the existing key,
 /// or send a new non-empty string to replace it.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, utoipa::ToSchema)]
 pub struct BackendConfig {
     pub backend_type: String, // "llamacpp" | "ollama" | "lmstudio" | "vllm" | "openai" | "custom"
     pub url: String,
@@ -28,6 +35,12 @@ pub struct BackendConfig {
     /// Indicates whether an API key is currently stored (returned on GET only).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub api_key_set: Option<bool>,
+    /// Name of the profile this configuration came from (GET only); null once
+    /// the configuration is edited directly.
+    #[serde(default, skip_deserializing)]
+    pub active_profile: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub active_profile_id: Option<String>,
 }
 
 impl Default for BackendConfig {
@@ -38,11 +51,33 @@ impl Default for BackendConfig {
             model: String::new(),
             api_key: None,
             api_key_set: Some(false),
+            active_profile: None,
+            active_profile_id: None,
         }
     }
 }
 
-#[derive(Deserialize)]
+/// Body for creating or updating a backend profile. As with the config
+/// endpoint, `api_key` is write-only: omit it (or send "" / "****") on update
+/// to keep the stored key.
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UpsertProfileRequest {
+    pub name: String,
+    pub backend_type: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub model: String,
+    pub api_key: Option<String>,
+}
+
+/// Setting: ID of the profile last copied into the active backend settings.
+pub const ACTIVE_PROFILE_KEY: &str = "active_backend_profile";
+
+const BACKEND_TYPES: &[&str] = &["llamacpp", "ollama", "lmstudio", "vllm", "openai", "custom"];
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ModelsQuery {
     #[serde(rename = "type")]
     pub backend_type: String,
@@ -54,28 +89,26 @@ pub struct ModelsQuery {
 
 // ─── GET /api/backends/config ─────────────────────────────────────────────────
 
+#[utoipa::path(
+    get,
+    path = "/api/backends/config",
+    tag = "backends",
+    responses(
+        (status = 200, body = BackendConfig),
+    )
+)]
 pub async fn get_backend_config(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let backend_type = queries::get_setting(&state.pool, "backend_type")
-        .await
-        .unwrap_or(None)
-        .unwrap_or_else(|| "llamacpp".to_string());
-
-    let url = queries::get_setting(&state.pool, "backend_url")
-        .await
-        .unwrap_or(None)
-        .unwrap_or_default();
-
-    let model = queries::get_setting(&state.pool, "backend_model")
-        .await
-        .unwrap_or(None)
-        .unwrap_or_default();
+    let backend_type = state.settings.get_string(Key::BackendType);
+    let url = state.settings.get_string(Key::BackendUrl);
+    let model = state.settings.get_string(Key::BackendModel);
 
     // SECURITY: Never return the actual API key — only signal whether one is set.
-    let api_key_set = queries::get_setting(&state.pool, "backend_api_key")
-        .await
-        .unwrap_or(None)
-        .map(|s| !s.is_empty())
-        .unwrap_or(false);
+    let api_key_set = !state.settings.get_string(Key::BackendSecret).is_empty();
+
+    let active_profile = match state.settings.get(ACTIVE_PROFILE_KEY).filter(|id| !id.is_empty()) {
+        Some(id) => queries::get_backend_profile(&state.pool, &id).await.unwrap_or(None),
+        None => None,
+    };
 
     Json(BackendConfig {
         backend_type,
@@ -83,18 +116,30 @@ pub async fn get_backend_config(State(state): State<Arc<AppState>>) -> impl Into
         model,
         api_key: None,           // never echoed back
         api_key_set: Some(api_key_set),
+        active_profile_id: active_profile.as_ref().map(|p| p.id.clone()),
+        active_profile: active_profile.map(|p| p.name),
     })
 }
 
 // ─── POST /api/backends/config ────────────────────────────────────────────────
 
+#[utoipa::path(
+    post,
+    path = "/api/backends/config",
+    tag = "backends",
+    request_body = BackendConfig,
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn set_backend_config(
     State(state): State<Arc<AppState>>,
     Json(cfg): Json<BackendConfig>,
 ) -> impl IntoResponse {
-    let pool = &state.pool;
+    let settings = &state.settings;
 
-    if let Err(e) = queries::set_setting(pool, "backend_type", &cfg.backend_type).await {
+    if let Err(e) = settings.set("backend_type", &cfg.backend_type).await {
         tracing::error!("Failed to save backend_type: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -102,7 +147,7 @@ pub async fn set_backend_config(
         )
             .into_response();
     }
-    if let Err(e) = queries::set_setting(pool, "backend_url", &cfg.url).await {
+    if let Err(e) = settings.set("backend_url", &cfg.url).await {
         tracing::error!("Failed to save backend_url: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -110,7 +155,7 @@ pub async fn set_backend_config(
         )
             .into_response();
     }
-    if let Err(e) = queries::set_setting(pool, "backend_model", &cfg.model).await {
+    if let Err(e) = settings.set("backend_model", &cfg.model).await {
         tracing::error!("Failed to save backend_model: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -124,7 +169,7 @@ pub async fn set_backend_config(
     // the key when the frontend sends back a masked placeholder.
     if let Some(key) = &cfg.api_key {
         if !key.is_empty() && key != "****" {
-            if let Err(e) = queries::set_setting(pool, "backend_api_key", key).await {
+            if let Err(e) = settings.set("backend_api_key", key).await {
                 tracing::error!("Failed to save backend_api_key: {}", e);
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -135,11 +180,282 @@ pub async fn set_backend_config(
         }
     }
 
+    // A hand-edited configuration no longer matches any profile
+    if let Err(e) = settings.set(ACTIVE_PROFILE_KEY, "").await {
+        tracing::error!("Failed to clear active backend profile: {}", e);
+    }
+
     Json(serde_json::json!({ "ok": true })).into_response()
 }
 
+// ─── Backend profiles ─────────────────────────────────────────────────────────
+
+fn profile_json(p: &BackendProfile, active_id: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "id": p.id,
+        "name": p.name,
+        "backend_type": p.backend_type,
+        "url": p.url,
+        "model": p.model,
+        "api_key_set": !p.api_key.is_empty(),
+        "active": active_id == Some(p.id.as_str()),
+        "created_at": p.created_at,
+    })
+}
+
+fn validate_profile(req: &UpsertProfileRequest) -> Result<(), String> {
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err("Profile name must be 1-64 characters".into());
+    }
+    if !BACKEND_TYPES.contains(&req.backend_type.as_str()) {
+        return Err(format!(
+            "backend_type must be one of: {}",
+            BACKEND_TYPES.join(", ")
+        ));
+    }
+    let url = req.url.trim();
+    if req.backend_type != "llamacpp" && url.is_empty() {
+        return Err("url is required for external backends".into());
+    }
+    if !url.is_empty() && !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err("url must start with http:// or https://".into());
+    }
+    Ok(())
+}
+
+/// A submitted key replaces the stored one only if it is a real value.
+fn submitted_key(key: &Option<String>) -> Option<&str> {
+    key.as_deref().filter(|k| !k.is_empty() && *k != "****")
+}
+
+fn profile_error(status: StatusCode, msg: impl Into<String>) -> axum::response::Response {
+    (status, Json(serde_json::json!({ "error": msg.into() }))).into_response()
+}
+
+fn active_profile_id(state: &AppState) -> Option<String> {
+    state.settings.get(ACTIVE_PROFILE_KEY).filter(|id| !id.is_empty())
+}
+
+/// Copy a profile into the settings the `/v1` proxy reads. The stored key is
+/// replaced even when the profile has none, so a key never leaks to another backend.
+async fn apply_profile(state: &AppState, p: &BackendProfile) -> anyhow::Result<()> {
+    state.settings.set("backend_type", &p.backend_type).await?;
+    state.settings.set("backend_url", &p.url).await?;
+    state.settings.set("backend_model", &p.model).await?;
+    state.settings.set("backend_api_key", &p.api_key).await?;
+    state.settings.set(ACTIVE_PROFILE_KEY, &p.id).await?;
+    Ok(())
+}
+
+/// GET /api/backends/profiles
+#[utoipa::path(
+    get,
+    path = "/api/backends/profiles",
+    tag = "backends",
+    responses(
+        (status = 200, description = "`{profiles: [BackendProfile]}`", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_profiles(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let active = active_profile_id(&state);
+    match queries::list_backend_profiles(&state.pool).await {
+        Ok(profiles) => {
+            let profiles: Vec<_> = profiles
+                .iter()
+                .map(|p| profile_json(p, active.as_deref()))
+                .collect();
+            Json(serde_json::json!({ "profiles": profiles })).into_response()
+        }
+        Err(e) => profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// POST /api/backends/profiles
+#[utoipa::path(
+    post,
+    path = "/api/backends/profiles",
+    tag = "backends",
+    request_body = UpsertProfileRequest,
+    responses(
+        (status = 201, body = BackendProfile),
+        (status = 400, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+    )
+)]
+pub async fn create_profile(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UpsertProfileRequest>,
+) -> impl IntoResponse {
+    if let Err(msg) = validate_profile(&req) {
+        return profile_error(StatusCode::BAD_REQUEST, msg);
+    }
+    match queries::get_backend_profile_by_name(&state.pool, req.name.trim()).await {
+        Ok(Some(_)) => {
+            return profile_error(
+                StatusCode::CONFLICT,
+                format!("A profile named '{}' already exists", req.name.trim()),
+            )
+        }
+        Ok(None) => {}
+        Err(e) => return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+
+    let profile = BackendProfile {
+        id: format!("profile-{}", Uuid::new_v4()),
+        name: req.name.trim().to_string(),
+        backend_type: req.backend_type.clone(),
+        url: req.url.trim().to_string(),
+        model: req.model.trim().to_string(),
+        api_key: submitted_key(&req.api_key).unwrap_or_default().to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+
+    match queries::upsert_backend_profile(&state.pool, &profile).await {
+        Ok(()) => (StatusCode::CREATED, Json(profile_json(&profile, None))).into_response(),
+        Err(e) => profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// PUT /api/backends/profiles/:id
+/// Updating the active profile re-applies it, so the proxy picks up the change.
+#[utoipa::path(
+    put,
+    path = "/api/backends/profiles/{id}",
+    tag = "backends",
+    params(("id" = String, Path, description = "Profile ID")),
+    request_body = UpsertProfileRequest,
+    responses(
+        (status = 200, body = BackendProfile),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+    )
+)]
+pub async fn update_profile(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<UpsertProfileRequest>,
+) -> impl IntoResponse {
+    if let Err(msg) = validate_profile(&req) {
+        return profile_error(StatusCode::BAD_REQUEST, msg);
+    }
+    let existing = match queries::get_backend_profile(&state.pool, &id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return profile_error(StatusCode::NOT_FOUND, "Profile not found"),
+        Err(e) => return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match queries::get_backend_profile_by_name(&state.pool, req.name.trim()).await {
+        Ok(Some(other)) if other.id != id => {
+            return profile_error(
+                StatusCode::CONFLICT,
+                format!("A profile named '{}' already exists", req.name.trim()),
+            )
+        }
+        Ok(_) => {}
+        Err(e) => return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+
+    let profile = BackendProfile {
+        name: req.name.trim().to_string(),
+        backend_type: req.backend_type.clone(),
+        url: req.url.trim().to_string(),
+        model: req.model.trim().to_string(),
+        api_key: submitted_key(&req.api_key)
+            .map(str::to_string)
+            .unwrap_or(existing.api_key.clone()),
+        ..existing
+    };
+    if let Err(e) = queries::upsert_backend_profile(&state.pool, &profile).await {
+        return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+
+    let active = active_profile_id(&state);
+    if active.as_deref() == Some(profile.id.as_str()) {
+        if let Err(e) = apply_profile(&state, &profile).await {
+            return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        }
+    }
+    Json(profile_json(&profile, active.as_deref())).into_response()
+}
+
+/// DELETE /api/backends/profiles/:id
+/// Deleting the active profile keeps its settings in place; it just stops
+/// being reported as active.
+#[utoipa::path(
+    delete,
+    path = "/api/backends/profiles/{id}",
+    tag = "backends",
+    params(("id" = String, Path, description = "Profile ID")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn delete_profile(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(e) = queries::delete_backend_profile(&state.pool, &id).await {
+        return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    if active_profile_id(&state).as_deref() == Some(id.as_str()) {
+        if let Err(e) = state.settings.set(ACTIVE_PROFILE_KEY, "").await {
+            return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        }
+    }
+    Json(serde_json::json!({ "ok": true })).into_response()
+}
+
+/// POST /api/backends/profiles/:id/activate
+/// Makes the profile the backend used by `/v1` and broadcasts
+/// `backend_profile_activated`.
+#[utoipa::path(
+    post,
+    path = "/api/backends/profiles/{id}/activate",
+    tag = "backends",
+    params(("id" = String, Path, description = "Profile ID")),
+    responses(
+        (status = 200, body = BackendProfile),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn activate_profile(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let profile = match queries::get_backend_profile(&state.pool, &id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return profile_error(StatusCode::NOT_FOUND, "Profile not found"),
+        Err(e) => return profile_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    if let Err(e) = apply_profile(&state, &profile).await {
+        tracing::error!("Failed to activate backend profile {}: {}", profile.id, e);
+        return profile_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save configuration");
+    }
+
+    let _ = state.event_tx.send(WsEvent::BackendProfileActivated {
+        profile_id: profile.id.clone(),
+        name: profile.name.clone(),
+        backend_type: profile.backend_type.clone(),
+    });
+    Json(profile_json(&profile, Some(&profile.id))).into_response()
+}
+
 // ─── GET /api/backends/models ─────────────────────────────────────────────────
 
+#[utoipa::path(
+    get,
+    path = "/api/backends/models",
+    tag = "backends",
+    params(ModelsQuery),
+    responses(
+        (status = 200, description = "`{models: [String]}` offered by the backend", body = serde_json::Value),
+        (status = 400, body = ErrorResponse),
+        (status = 502, body = ErrorResponse),
+    )
+)]
 pub async fn list_backend_models(
     Query(q): Query<ModelsQuery>,
 ) -> impl IntoResponse {
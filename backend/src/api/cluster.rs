@@ -1,44 +1,226 @@
 use axum::{
     body::Body,
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
-use futures::future::join_all;
-use serde::Deserialize;
+use futures::{future::join_all, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
+use super::agent::HEARTBEAT_INTERVAL_SECS;
+use super::devices::removed_since;
+use super::etag::json_with_etag;
+use super::idempotency;
+use super::models::{aliases_by_path, public_model_name, resolve_model_path};
+use super::openapi::{ErrorResponse, OkResponse, PortInUseError};
+use super::resume::{self, LastSession};
 use crate::{
-    db::queries,
-    llama_cpp::validate_model_path,
+    api_keys::KeyScope,
+    db::{models::{ApiKey, Device}, queries},
+    llama_cpp::{
+        ports::{self, PortInUse},
+        reservations::{self, Reserved},
+        split,
+        admission::{Permit, Priority, Rejected},
+        binary::{BinariesInfo, NoRpcSupport},
+        diagnosis::{RpcProbe, AGENT_API_PORT},
+        health,
+        lora::{self, LoraAdapter},
+        rpc_probe::{DeepProbe, ProbeDepth},
+        default_parallel, rpc_mem_cap, validate_chat_template, validate_mmproj, validate_model_path,
+        validate_system_prompt, DeviceMemory, DraftModel, FitStatus, GpuSelector, InFlightGuard,
+        InferenceSessionInfo, LaunchOptions, LlamaCppManager, ModelAnalysis, MAX_PARALLEL,
+    },
+    federation,
+    inventory::compare_versions,
+    memory::{host_reserved_total, remote::fetch_remote_memory},
+    ollama::native::{self as ollama_native, StreamTranslator},
+    permissions::{DeviceKind, DeviceStatus, PermissionService, RpcStatus},
+    settings::{Key, SettingsCache},
     AppState,
 };
 
 // ─── Request types ────────────────────────────────────────────────────────────
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct StartInferenceRequest {
+    /// Absolute .gguf path or a model alias
     pub model_path: String,
     /// Device IDs from the DB whose RPC servers should be included
+    #[serde(default)]
     pub device_ids: Vec<String>,
+    /// Tags that expand to every approved, reachable device carrying them
+    #[serde(default)]
+    pub device_tags: Vec<String>,
     /// Number of layers to put on GPU. -1 = all (default), 0 = CPU only.
     pub n_gpu_layers: Option<i32>,
     /// Context window size in tokens (default 4096).
     pub ctx_size: Option<u32>,
+    /// Requests llama-server decodes at once (`--parallel`, default 1, max 16).
+    /// Also the default `max_concurrent_completions`. `ctx_size` is shared
+    /// between the slots.
+    #[serde(alias = "parallel_slots")]
+    pub parallel: Option<u32>,
+    /// Pass `--cont-batching`, so a request joins the running batch instead
+    /// of waiting for it to finish
+    #[serde(default)]
+    pub cont_batching: bool,
+    /// Local GPU selection, e.g. `{"cuda_visible_devices": "1"}` or `{"main_gpu": 1}`
+    pub gpu_selector: Option<serde_json::Value>,
+    /// Draft model (absolute .gguf path or alias) for speculative decoding.
+    /// It is loaded on the local GPU and must fit next to the main model.
+    pub draft_model_path: Option<String>,
+    /// `--draft-max`: tokens drafted per step
+    pub draft_max: Option<u32>,
+    /// `--draft-min`: fewest drafted tokens worth verifying
+    pub draft_min: Option<u32>,
+    /// Multimodal projector (absolute .gguf path or alias) of a vision
+    /// model, in the model's directory. Chat requests may then carry images.
+    pub mmproj_path: Option<String>,
+    /// Built-in llama-server chat template (`--chat-template`), e.g. `llama3`
+    /// or `chatml`, for models whose own template is broken
+    pub chat_template: Option<String>,
+    /// Sent as the first message of chat requests that have no system
+    /// message, unless they carry `X-SharedLLM-No-System-Prompt`
+    pub system_prompt: Option<String>,
+    /// LoRA adapters (absolute `.gguf` or `.bin` paths) applied on top of
+    /// the model, at most 4
+    #[serde(default)]
+    pub lora_adapters: Vec<LoraAdapter>,
+    /// If llama-server runs out of memory while loading, start it again
+    /// with fewer GPU layers (`oom_retry_layers_pct`), down to CPU only
+    #[serde(default)]
+    pub retry_on_oom: bool,
+    /// Answer once llama-server is ready or has failed for good, after any
+    /// `retry_on_oom` retries, instead of as soon as it is launched
+    #[serde(default)]
+    pub wait: bool,
+    /// Leave out selected devices whose RPC server doesn't answer, as long
+    /// as the model still fits without them. By default the start fails.
+    #[serde(default)]
+    pub skip_unreachable: bool,
+    /// Validate and return the llama-server command line without starting it
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Like the `Idempotency-Key` header, which wins when both are given: a
+    /// start repeated with the same key within 10 minutes gets the first
+    /// one's response instead of starting again
+    pub idempotency_key: Option<String>,
+    /// Devices the caller already left out (e.g. a preset's deleted ones).
+    /// Like unreachable devices, the start only goes on if the model fits
+    /// without them.
+    #[serde(skip)]
+    pub left_out: Vec<String>,
+}
+
+/// RPC probe of one endpoint of a selected device, made before llama-server
+/// is spawned. A device with `extra_rpc_ports` gets one per port.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DeviceProbe {
+    pub device_id: String,
+    pub name: String,
+    /// `ip:port` of its RPC server
+    pub address: String,
+    pub reachable: bool,
+    /// Level the verdict came from: `tcp` when the server predates the RPC
+    /// handshake or a running session holds it
+    pub depth: ProbeDepth,
+    pub detail: String,
+}
+
+/// Body for POST /api/cluster/inference/stop (optional)
+#[derive(Deserialize, Default, utoipa::ToSchema)]
+pub struct StopInferenceRequest {
+    /// Kill llama-server immediately instead of draining in-flight requests
+    #[serde(default)]
+    pub force: bool,
+    /// Override the `inference_drain_timeout_secs` setting for this stop
+    pub timeout_secs: Option<u64>,
+}
+
+/// Query params for GET /api/cluster/status
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ClusterStatusParams {
+    /// Only list devices changed after this revision
+    pub since_rev: Option<i64>,
+    /// Order devices by hardware, highest first; devices that haven't
+    /// reported it come last
+    pub sort: Option<DeviceSort>,
+}
+
+/// `sort` values for GET /api/cluster/status
+#[derive(Debug, Clone, Copy, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceSort {
+    CpuCores,
+    DriverVersion,
+}
+
+/// Sort device status entries by `sort`, highest first, unknowns last. Ties
+/// keep their order.
+fn sort_devices(devices: &mut [serde_json::Value], sort: DeviceSort) {
+    devices.sort_by(|a, b| match sort {
+        DeviceSort::CpuCores => b["cpu_cores"].as_i64().cmp(&a["cpu_cores"].as_i64()),
+        DeviceSort::DriverVersion => match (a["gpu_driver"].as_str(), b["gpu_driver"].as_str()) {
+            (Some(x), Some(y)) => compare_versions(y, x),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        },
+    });
 }
 
 /// Query params for GET /api/cluster/model-check
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ModelCheckParams {
     pub path: String,
     /// Comma-separated device IDs to include in the memory pool.
     pub device_ids: Option<String>,
+    /// Draft model (path or alias) that will be loaded locally alongside
+    pub draft_path: Option<String>,
+    /// Comma-separated LoRA adapter paths that will be loaded with the model
+    pub lora_paths: Option<String>,
+    /// Ask every device for its free memory now instead of reusing readings
+    /// from the last few seconds
+    #[serde(default)]
+    pub fresh: bool,
+    /// Context size to size the KV cache for (default: `recommended_ctx_size`)
+    pub ctx_size: Option<u32>,
+    /// Server slots the context will be split between (default 1)
+    #[serde(alias = "parallel_slots")]
+    pub parallel: Option<u32>,
 }
 
 // ─── GET /api/cluster/status ──────────────────────────────────────────────────
 
-pub async fn cluster_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+/// Device probes run on every call; the response carries an `ETag` and `rev`
+/// (the device revision after probing). With `?since_rev=N` only devices
+/// changed after N are listed, and `removed` names devices deleted or no
+/// longer approved since. Devices whose RPC port doesn't answer carry a
+/// `diagnosis` from `RpcProbe`. `?sort=cpu_cores|driver_version` orders
+/// devices by the inventory their agents report.
+#[utoipa::path(
+    get,
+    path = "/api/cluster/status",
+    tag = "cluster",
+    params(ClusterStatusParams),
+    responses(
+        (status = 200, description = "Approved devices with live RPC status (and a `diagnosis` when RPC is unreachable), and the local llama.cpp state", body = serde_json::Value),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn cluster_status(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<ClusterStatusParams>,
+) -> impl IntoResponse {
     let devices = match queries::list_devices(&state.pool).await {
         Ok(d) => d,
         Err(e) => {
@@ -50,80 +232,182 @@ pub async fn cluster_status(State(state): State<Arc<AppState>>) -> impl IntoResp
         }
     };
 
-    let approved: Vec<_> = devices
-        .iter()
-        .filter(|d| d.status == "approved")
+    let approved: Vec<Device> = devices
+        .into_iter()
+        .filter(|d| d.status == DeviceStatus::Approved)
         .collect();
 
-    // Probe all approved devices in parallel (each with a 2-second timeout)
-    let probe_data: Vec<_> = approved
-        .iter()
-        .map(|d| {
-            (
-                d.id.clone(),
-                d.name.clone(),
-                d.ip.clone(),
-                d.rpc_port,
-                d.rpc_status.clone(),
-                d.memory_total_mb,
-                d.memory_free_mb,
-            )
-        })
-        .collect();
+    // Trust thresholds: low-trust devices are shown greyed out in the UI and
+    // their /api/gpu endpoint is not polled.
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let min_cluster_trust = svc.trust_threshold(Key::MinClusterTrust).await;
+    let min_stats_trust = svc.trust_threshold(Key::MinStatsTrust).await;
+    let role_trust = svc.role_trust_levels().await.unwrap_or_default();
 
     let llama_cpp = state.llama_cpp.clone();
     let pool = state.pool.clone();
     let http_client = state.llama_cpp.client.clone();
+    let remote_memory = state.remote_memory.clone();
+    let device_health = state.device_health.clone();
+    let deep = state.settings.get_bool(Key::RpcDeepProbe);
 
-    let probe_futs = probe_data.into_iter().map(
-        move |(id, name, ip, rpc_port, rpc_status, memory_total_mb, memory_free_mb)| {
-            let mgr = llama_cpp.clone();
-            let pool = pool.clone();
-            let ip_clone = ip.clone();
-            let id_clone = id.clone();
-            let client = http_client.clone();
-            async move {
-                let reachable = mgr.probe_rpc_device(&ip_clone, rpc_port as u16).await;
-                let live_status: String = if reachable {
-                    "ready".to_string()
-                } else {
-                    rpc_status.clone()
-                };
-                // Persist live probe result to DB so other pages see consistent status
-                let _ = queries::update_device_rpc_status(&pool, &id_clone, &live_status).await;
-
-                // When reachable, fetch real memory stats from the remote device
-                let (mem_total, mem_free) = if reachable {
-                    match fetch_remote_memory(&client, &ip_clone).await {
-                        Some((t, f)) => {
-                            let _ = queries::update_device_memory_stats(&pool, &id_clone, t, f)
-                                .await;
-                            (t, f)
-                        }
-                        None => (memory_total_mb, memory_free_mb),
-                    }
-                } else {
-                    (memory_total_mb, memory_free_mb)
-                };
-
-                serde_json::json!({
-                    "id": id,
-                    "name": name,
-                    "ip": ip,
-                    "rpc_port": rpc_port,
-                    "rpc_status": live_status,
-                    "memory_total_mb": mem_total,
-                    "memory_free_mb": mem_free,
+    // Probe all approved devices in parallel (each with a 2-second timeout)
+    let probe_futs = approved.into_iter().map(|d| {
+        let mgr = llama_cpp.clone();
+        let pool = pool.clone();
+        let client = http_client.clone();
+        let remote_memory = remote_memory.clone();
+        let device_health = device_health.clone();
+        let trust_level = PermissionService::effective_trust_level(&d, &role_trust);
+        async move {
+            let ports = d.rpc_ports();
+            let port_probes = join_all(ports.iter().map(|&port| mgr.probe_rpc(&d.ip, port, deep))).await;
+            let rpc_endpoints: Vec<serde_json::Value> = ports
+                .iter()
+                .zip(&port_probes)
+                .map(|(port, probe)| {
+                    serde_json::json!({
+                        "port": port,
+                        "reachable": probe.ok,
+                        "probe_depth": probe.depth,
+                        "probe_detail": probe.detail,
+                    })
                 })
+                .collect();
+            // One answering endpoint is enough for the device to take part
+            let probe = port_probes.iter().find(|p| p.ok).or(port_probes.first());
+            let reachable = probe.is_some_and(|p| p.ok);
+            // Took connections, then failed the RPC exchange
+            let wedged = probe.filter(|p| !p.ok && p.depth != ProbeDepth::Tcp);
+            if let Some(probe) = probe {
+                let _ = queries::record_rpc_probe(&pool, &d.id, probe).await;
             }
-        },
-    );
-    let device_statuses: Vec<_> = join_all(probe_futs).await;
+            device_health.observe(&pool, &d, reachable).await;
+            if reachable {
+                let _ = queries::update_device_last_seen(&pool, &d.id).await;
+            }
+            let live_status = match (reachable, wedged) {
+                (true, _) => RpcStatus::Ready,
+                (false, Some(_)) => RpcStatus::Error,
+                (false, None) => d.rpc_status,
+            };
+            // Persist live probe result to DB so other pages see consistent status
+            let _ = queries::update_device_rpc_status(&pool, &d.id, live_status).await;
+            // Same rule the update just applied to the row
+            let became_ready = d.rpc_status != RpcStatus::Ready || d.last_rpc_ready_at.is_none();
+            let last_rpc_ready_at = if reachable && became_ready {
+                Some(chrono::Utc::now().to_rfc3339())
+            } else {
+                d.last_rpc_ready_at.clone()
+            };
+
+            let diagnosis = if reachable {
+                None
+            } else if let Some(probe) = wedged {
+                Some(format!(
+                    "RPC server takes connections but fails RPC calls ({}); restart it",
+                    probe.detail
+                ))
+            } else {
+                let heartbeat_age = d
+                    .last_seen
+                    .as_deref()
+                    .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                    .and_then(|t| (chrono::Utc::now() - t.with_timezone(&chrono::Utc)).to_std().ok());
+                RpcProbe {
+                    rpc_port: d.rpc_port as u16,
+                    rpc_open: false,
+                    api_open: mgr.probe_rpc_device(&d.ip, AGENT_API_PORT).await,
+                    heartbeat_age,
+                    heartbeat_interval: std::time::Duration::from_secs(HEARTBEAT_INTERVAL_SECS),
+                    reported_ready: d.rpc_status == RpcStatus::Ready,
+                    ever_ready: d.last_rpc_ready_at.is_some(),
+                    platform: d.platform.as_deref(),
+                }
+                .diagnosis()
+            };
+
+            // When reachable (and trusted enough), fetch real memory stats from the remote
+            // device; an agent-only box has no /api/gpu and reports by heartbeat instead
+            let (mem_total, mem_free) = if reachable
+                && trust_level >= min_stats_trust
+                && d.device_kind != DeviceKind::Agent
+                && !d.memory_manual_override
+            {
+                match fetch_remote_memory(&client, &d.ip).await {
+                    Some((t, f)) => {
+                        remote_memory.put(&d.id, t, f);
+                        let _ = queries::update_device_memory_stats(&pool, &d.id, t, f).await;
+                        (t, f)
+                    }
+                    None => (d.memory_total_mb, d.memory_free_mb),
+                }
+            } else {
+                (d.memory_total_mb, d.memory_free_mb)
+            };
+
+            serde_json::json!({
+                "id": d.id,
+                "name": d.name,
+                "ip": d.ip,
+                "rpc_port": d.rpc_port,
+                "rpc_endpoints": rpc_endpoints,
+                "rpc_status": live_status,
+                "memory_total_mb": mem_total,
+                "memory_free_mb": mem_free,
+                "memory_manual_override": d.memory_manual_override,
+                "tags": d.tags,
+                "labels": d.labels,
+                "trust_level": trust_level,
+                "cluster_eligible": trust_level >= min_cluster_trust,
+                "last_rpc_ready_at": last_rpc_ready_at,
+                "rpc_probe_depth": probe.map(|p| p.depth),
+                "rpc_probe_detail": probe.map(|p| p.detail.as_str()),
+                "rpc_cache_enabled": d.rpc_cache_enabled,
+                "rpc_cache_mb": d.rpc_cache_mb,
+                "rpc_backend": d.rpc_backend,
+                "rpc_mem_mb": d.rpc_mem_mb,
+                "cpu_model": d.cpu_model,
+                "cpu_cores": d.cpu_cores,
+                "os_version": d.os_version,
+                "gpu_driver": d.gpu_driver,
+                "disk_free_mb": d.disk_free_mb,
+                "diagnosis": diagnosis,
+                "health_score": d.health_score,
+                "unreliable": health::is_unreliable(&d),
+            })
+        }
+    });
+    let mut device_statuses: Vec<_> = join_all(probe_futs).await;
+    if let Some(sort) = params.sort {
+        sort_devices(&mut device_statuses, sort);
+    }
+
+    // Probes may have bumped revisions, so read them only now
+    let rev = queries::device_revision(&state.pool).await.unwrap_or_default();
+    let mut removed = None;
+    if let Some(since) = params.since_rev {
+        let changed: HashSet<String> = queries::list_devices_changed_since(&state.pool, since)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        device_statuses.retain(|d| d["id"].as_str().is_some_and(|id| changed.contains(id)));
+        removed = Some(
+            removed_since(&state.pool, since, |_, status| status != DeviceStatus::Approved)
+                .await
+                .unwrap_or_default(),
+        );
+    }
 
     let llama_status = state.llama_cpp.get_status().await;
 
-    Json(serde_json::json!({
+    let mut body = serde_json::json!({
+        "rev": rev,
         "devices": device_statuses,
+        "min_cluster_trust": min_cluster_trust,
+        "min_stats_trust": min_stats_trust,
         "llama_cpp": {
             "rpc_server_running": llama_status.rpc_server_running,
             "inference_running": llama_status.inference_running,
@@ -131,52 +415,84 @@ pub async fn cluster_status(State(state): State<Arc<AppState>>) -> impl IntoResp
             "inference_server_bin": llama_status.inference_server_bin,
             "rpc_port": llama_status.rpc_port,
             "inference_port": llama_status.inference_port,
+            "starting": llama_status.starting,
+            "binaries": state.llama_cpp.binary_info().await,
         },
         "current_session": llama_status.current_session,
-    }))
-    .into_response()
-}
-
-/// Fetch total and free memory from a remote device's /api/gpu endpoint.
-/// Returns `None` if the request fails or the device reports no memory.
-async fn fetch_remote_memory(client: &reqwest::Client, ip: &str) -> Option<(i64, i64)> {
-    let url = format!("http://{}:8080/api/gpu", ip);
-    let data: serde_json::Value = client
-        .get(&url)
-        .timeout(std::time::Duration::from_secs(2))
-        .send()
-        .await
-        .ok()?
-        .json()
-        .await
-        .ok()?;
-
-    let providers = data["providers"].as_array()?;
-    if providers.is_empty() {
-        return None;
-    }
-    let total: i64 = providers
-        .iter()
-        .filter_map(|p| p["total_mb"].as_i64())
-        .sum();
-    let free: i64 = providers
-        .iter()
-        .filter_map(|p| p["free_mb"].as_i64())
-        .sum();
-    if total == 0 {
-        return None;
+    });
+    if let Some(removed) = removed {
+        body["since_rev"] = params.since_rev.into();
+        body["removed"] = removed.into();
     }
-    Some((total, free))
+    json_with_etag(&headers, &body)
 }
 
 // ─── POST /api/cluster/inference/start ───────────────────────────────────────
 
+/// How long a start with `wait` waits for llama-server to load the model.
+const START_WAIT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+#[utoipa::path(
+    post,
+    path = "/api/cluster/inference/start",
+    tag = "cluster",
+    request_body = StartInferenceRequest,
+    responses(
+        (status = 200, description = "`{ok, session, devices: [DeviceProbe], warnings}` (with `wait`, once llama-server is ready; a failed start is a 500 with `{error, session_id, devices}`), or `{ok, dry_run, plan: LaunchPlan, chat_template, system_prompt, lora_adapters, devices, warnings}` for a dry run. `warnings` names unreliable devices picked by ID", body = serde_json::Value),
+        (status = 400, description = "Invalid request, or the model doesn't fit without the unreachable devices (`{error, devices}`)", body = ErrorResponse),
+        (status = 403, description = "A device is below `min_cluster_trust`", body = ErrorResponse),
+        (status = 409, description = "A llama.cpp port is taken, or another start is in progress", body = PortInUseError),
+        (status = 422, description = "Devices were picked but llama-server was built without RPC support: `{error: \"no_rpc_support\", binary, version, message}`", body = serde_json::Value),
+        (status = 503, description = "Selected devices are unreachable: `{error, devices: [DeviceProbe]}`", body = ErrorResponse),
+    ),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Replay the response of an earlier start with this key (sent within 10 minutes) instead of starting again; the replay carries `Idempotent-Replayed: true`"),
+    )
+)]
 pub async fn start_inference(
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<StartInferenceRequest>,
-) -> impl IntoResponse {
-    // Validate model path before doing anything else (VULN-02)
-    if let Err(e) = validate_model_path(&req.model_path) {
+) -> Response {
+    // A dry run starts nothing, so it neither waits for nor blocks a start
+    if req.dry_run {
+        return start(state, req).await;
+    }
+    let key = match idempotency::request_key(&headers, req.idempotency_key.as_deref()) {
+        Ok(key) => key,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))).into_response(),
+    };
+    let exclusive = async {
+        // start() kills the running llama-server; an overlapping start would
+        // kill the one this launches
+        let Some(_starting) = state.llama_cpp.begin_start() else {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({ "error": "A start is already in progress" })),
+            )
+                .into_response());
+        };
+        Ok(start(state.clone(), req).await)
+    };
+    match key {
+        Some(key) => state.start_keys.run(&key, exclusive).await,
+        None => exclusive.await.unwrap_or_else(|refused| refused),
+    }
+}
+
+async fn start(state: Arc<AppState>, req: StartInferenceRequest) -> Response {
+    // Resolve an alias to its file, then validate before doing anything else (VULN-02)
+    let model_path = match resolve_model_path(&state.pool, &req.model_path).await {
+        Ok(p) => p,
+        Err(msg) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+                .into_response()
+        }
+    };
+    // A split model is started from its first part, but every part must be there
+    if let Err(e) = validate_model_path(&model_path)
+        .and_then(|()| split::model_files(std::path::Path::new(&model_path)))
+    {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": e.to_string() })),
@@ -184,22 +500,189 @@ pub async fn start_inference(
             .into_response();
     }
 
+    let gpu_selector = match req.gpu_selector.clone().map(GpuSelector::from_value) {
+        None => GpuSelector::default(),
+        Some(Ok(g)) => g,
+        Some(Err(e)) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e })))
+                .into_response()
+        }
+    };
+
+    let parallel = req.parallel.unwrap_or_else(default_parallel);
+    if !(1..=MAX_PARALLEL).contains(&parallel) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("parallel must be between 1 and {}", MAX_PARALLEL)
+            })),
+        )
+            .into_response();
+    }
+
+    let draft = match &req.draft_model_path {
+        None => None,
+        Some(requested) => {
+            let path = match resolve_model_path(&state.pool, requested).await {
+                Ok(p) => p,
+                Err(msg) => {
+                    return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+                        .into_response()
+                }
+            };
+            if let Err(e) = validate_model_path(&path)
+                .and_then(|()| split::model_files(std::path::Path::new(&path)))
+            {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": format!("Draft model: {}", e) })),
+                )
+                    .into_response();
+            }
+            if let (Some(min), Some(max)) = (req.draft_min, req.draft_max) {
+                if min > max {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(serde_json::json!({ "error": "draft_min must not exceed draft_max" })),
+                    )
+                        .into_response();
+                }
+            }
+            Some(DraftModel { path, max: req.draft_max, min: req.draft_min })
+        }
+    };
+
+    let mmproj = match &req.mmproj_path {
+        None => None,
+        Some(requested) => {
+            let path = match resolve_model_path(&state.pool, requested).await {
+                Ok(p) => p,
+                Err(msg) => {
+                    return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+                        .into_response()
+                }
+            };
+            if let Err(e) = validate_mmproj(&model_path, &path) {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": format!("Projector: {}", e) })),
+                )
+                    .into_response();
+            }
+            Some(path)
+        }
+    };
+
+    let chat_template = match req.chat_template.as_deref().map(validate_chat_template).transpose() {
+        Ok(t) => t,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() })))
+                .into_response()
+        }
+    };
+    let system_prompt = match req.system_prompt.as_deref().map(validate_system_prompt).transpose() {
+        Ok(p) => p.flatten(),
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() })))
+                .into_response()
+        }
+    };
+
+    if let Err(e) = lora::validate(&req.lora_adapters) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() })))
+            .into_response();
+    }
+
     // Limit device_ids to prevent DoS via excessive DB queries (VULN-12)
-    if req.device_ids.len() > 20 {
+    let max_devices = state.limits.get().max_cluster_devices;
+    if req.device_ids.len() > max_devices {
         return (
             StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({ "error": "Too many device IDs (max 20)" })),
+            Json(serde_json::json!({ "error": format!("Too many device IDs (max {})", max_devices) })),
         )
             .into_response();
     }
 
-    // Build the list of "ip:port" strings for the selected devices
-    let mut rpc_addresses = Vec::new();
+    // Expand tags into the approved, currently reachable devices carrying them
+    let mut device_ids = req.device_ids.clone();
+    if !req.device_tags.is_empty() {
+        match expand_device_tags(&state, &req.device_tags).await {
+            Ok(ids) => {
+                for id in ids {
+                    if !device_ids.contains(&id) {
+                        device_ids.push(id);
+                    }
+                }
+            }
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": e })),
+                )
+                    .into_response();
+            }
+        }
+        // Tag expansion is still subject to the same cap (VULN-12)
+        if device_ids.len() > max_devices {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": format!(
+                        "Tags expand to {} devices (max {}). Narrow the selection.",
+                        device_ids.len(),
+                        max_devices
+                    )
+                })),
+            )
+                .into_response();
+        }
+    }
+
+    // Devices whose role trust_level is below min_cluster_trust can't take part
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let min_cluster_trust = svc.trust_threshold(Key::MinClusterTrust).await;
+    let role_trust = match svc.role_trust_levels().await {
+        Ok(r) => r,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    };
+
+    // Look up the selected devices with the memory each can offer
+    let mut selected = Vec::new();
+    // Unreliable devices picked by ID still take part, with a warning
+    let mut warnings = Vec::new();
+    let reserved = Reserved::load(&state.pool).await;
 
-    for device_id in &req.device_ids {
+    for device_id in &device_ids {
         match queries::get_device(&state.pool, device_id).await {
             Ok(Some(device)) => {
-                rpc_addresses.push(format!("{}:{}", device.ip, device.rpc_port));
+                let trust_level = PermissionService::effective_trust_level(&device, &role_trust);
+                if trust_level < min_cluster_trust {
+                    return (
+                        StatusCode::FORBIDDEN,
+                        Json(serde_json::json!({
+                            "error": format!(
+                                "Device '{}' has trust level {} (cluster participation requires {})",
+                                device.name, trust_level, min_cluster_trust
+                            )
+                        })),
+                    )
+                        .into_response();
+                }
+                if health::is_unreliable(&device) {
+                    warnings.push(format!(
+                        "Device '{}' is unreliable (health {}); sessions with it have crashed early or its RPC server keeps dropping out",
+                        device.name, device.health_score
+                    ));
+                }
+                let free_mb =
+                    (device.memory_free_mb.max(0) as u64).saturating_sub(reserved.device(&device.id));
+                selected.push((device, free_mb));
             }
             Ok(None) => {
                 return (
@@ -218,25 +701,280 @@ pub async fn start_inference(
         }
     }
 
+    // A sleeping device would leave llama-server hanging on its address, and
+    // a wedged server would fail it once the model loads
+    let probes = selected.iter().flat_map(|(d, _)| {
+        d.rpc_ports().into_iter().map(|port| {
+            let mgr = state.llama_cpp.clone();
+            let ip = d.ip.clone();
+            async move { mgr.probe_rpc_deep(&ip, port).await }
+        })
+    });
+    let results = join_all(probes).await;
+    let (devices, selected) = split_unreachable(selected, results);
+    for (device_id, probe) in device_verdicts(&devices) {
+        if let Err(e) = queries::record_rpc_probe(&state.pool, device_id, &probe).await {
+            tracing::warn!("Failed to record the RPC probe of {}: {}", device_id, e);
+        }
+    }
+    let unreachable: Vec<String> = devices
+        .iter()
+        .filter(|p| !p.reachable)
+        .map(|p| match p.depth {
+            ProbeDepth::Tcp => format!("{} ({})", p.name, p.address),
+            _ => format!("{} ({}: {})", p.name, p.address, p.detail),
+        })
+        .collect();
+    if !unreachable.is_empty() && !req.skip_unreachable {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": format!(
+                    "Unreachable devices: {}. Wake or restart them, or set skip_unreachable to start without them.",
+                    unreachable.join(", ")
+                ),
+                "devices": devices,
+            })),
+        )
+            .into_response();
+    }
+
+    let device_ids: Vec<String> = selected.iter().map(|(d, _)| d.id.clone()).collect();
+    let rpc_addresses: Vec<String> = devices
+        .iter()
+        .filter(|p| p.reachable)
+        .map(|p| p.address.clone())
+        .collect();
+    let device_free_mbs: Vec<u64> = selected.iter().map(|(_, free)| *free).collect();
+    let host_reserved = state.settings.get_string(Key::HostReservedMb);
+    let local_free_mb = crate::memory::local_snapshots(&state.providers.load(), Some(&host_reserved))
+        .await
+        .iter()
+        .map(|s| s.usable_free_mb)
+        .sum::<u64>()
+        .saturating_sub(reserved.local_total());
+
+    // Dropping devices shrinks the pool; only go on if the model still fits
+    let mut missing = req.left_out.clone();
+    missing.extend(unreachable.iter().cloned());
+    if !missing.is_empty() {
+        let draft_path = draft.as_ref().map(|d| d.path.as_str());
+        let analysis = LlamaCppManager::analyze_model(&model_path, draft_path, local_free_mb, device_free_mbs.clone())
+            .map(|mut a| {
+                LlamaCppManager::check_lora_fit(&mut a, &req.lora_adapters);
+                a
+            });
+        match analysis {
+            Ok(a) if a.fit_status == FitStatus::TooLarge => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "error": format!(
+                            "Without {} the model ({} MB) no longer fits: {} MB available",
+                            missing.join(", "),
+                            a.model_size_mb + a.draft_size_mb + a.lora_size_mb,
+                            a.total_available_mb + a.draft_size_mb
+                        ),
+                        "devices": devices,
+                    })),
+                )
+                    .into_response();
+            }
+            Ok(_) => tracing::warn!("Starting without devices: {}", missing.join(", ")),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": e.to_string() })),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    // The draft lives on the local GPU; refuse rather than let llama-server OOM
+    if let Some(d) = &draft {
+        if let Err(e) =
+            LlamaCppManager::check_draft_fit(&model_path, &d.path, local_free_mb, device_free_mbs)
+        {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response();
+        }
+    }
+
+    let n_gpu_layers = req.n_gpu_layers.unwrap_or(-1);
+    let ctx_size = req.ctx_size.unwrap_or(4096);
+    let opts = LaunchOptions {
+        n_gpu_layers,
+        ctx_size,
+        parallel,
+        cont_batching: req.cont_batching,
+        gpu_selector: gpu_selector.clone(),
+        draft: draft.clone(),
+        mmproj,
+        chat_template: chat_template.clone(),
+        system_prompt: system_prompt.clone(),
+        lora_adapters: req.lora_adapters.clone(),
+        retry_on_oom: req.retry_on_oom,
+        api_key: Some(state.settings.get_string(Key::InferenceSecret)),
+    };
+    if req.dry_run {
+        let plan = state.llama_cpp.plan_inference(&model_path, rpc_addresses, opts);
+        return Json(serde_json::json!({
+            "ok": true,
+            "dry_run": true,
+            "plan": plan,
+            "chat_template": chat_template,
+            "system_prompt": system_prompt,
+            "lora_adapters": req.lora_adapters,
+            "devices": devices,
+            "warnings": warnings,
+        }))
+        .into_response();
+    }
+
+    let last = LastSession {
+        model_path: req.model_path.clone(),
+        device_ids,
+        n_gpu_layers,
+        ctx_size,
+        parallel,
+        cont_batching: req.cont_batching,
+        gpu_selector,
+        // Keep the draft as requested so an alias follows its target
+        draft: req.draft_model_path.clone().map(|path| DraftModel {
+            path,
+            max: req.draft_max,
+            min: req.draft_min,
+        }),
+        mmproj: req.mmproj_path.clone(),
+        chat_template,
+        system_prompt,
+        lora_adapters: req.lora_adapters.clone(),
+        retry_on_oom: req.retry_on_oom,
+        started_at: chrono::Utc::now().to_rfc3339(),
+    };
     match state
         .llama_cpp
-        .start_inference(
-            &req.model_path,
-            rpc_addresses,
-            req.n_gpu_layers.unwrap_or(-1),
-            req.ctx_size.unwrap_or(4096),
-        )
+        .start_inference(&model_path, rpc_addresses, opts)
         .await
     {
         Ok(()) => {
-            let session = state.llama_cpp.get_current_session().await;
+            resume::record(&state.settings, &last).await;
+            let mut session = state.llama_cpp.get_current_session().await;
+            if let (true, Some(started)) = (req.wait, &session) {
+                match state.llama_cpp.wait_until_ready(&started.id, START_WAIT).await {
+                    Ok(ready) => session = Some(ready),
+                    Err(reason) => {
+                        return (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(serde_json::json!({
+                                "error": reason,
+                                "session_id": started.id,
+                                "devices": devices,
+                            })),
+                        )
+                            .into_response()
+                    }
+                }
+            }
+            // Reserve now so the memory is taken before this response returns
+            if let Some(s) = &session {
+                let (local, _) = state.memory.cached();
+                if let Err(e) = reservations::reserve(&state.pool, &local, s).await {
+                    tracing::warn!("Failed to reserve memory for session {}: {}", s.id, e);
+                }
+            }
             Json(serde_json::json!({
                 "ok": true,
                 "session": session,
+                "devices": devices,
+                "warnings": warnings,
             }))
             .into_response()
         }
-        Err(e) => (
+        Err(e) => start_error(e),
+    }
+}
+
+/// Pair each RPC endpoint of the selected devices with its probe result
+/// (`results` is in [`Device::rpc_addresses`] order, device by device), and
+/// keep the devices with at least one reachable endpoint.
+fn split_unreachable(
+    selected: Vec<(Device, u64)>,
+    results: Vec<DeepProbe>,
+) -> (Vec<DeviceProbe>, Vec<(Device, u64)>) {
+    let mut probes = Vec::new();
+    let mut kept = Vec::new();
+    let mut results = results.into_iter();
+    for (device, free_mb) in selected {
+        let mut any = false;
+        for address in device.rpc_addresses() {
+            let result = results
+                .next()
+                .unwrap_or_else(|| DeepProbe::tcp(false, "not probed"));
+            any |= result.ok;
+            probes.push(DeviceProbe {
+                device_id: device.id.clone(),
+                name: device.name.clone(),
+                address,
+                reachable: result.ok,
+                depth: result.depth,
+                detail: result.detail,
+            });
+        }
+        if any {
+            kept.push((device, free_mb));
+        }
+    }
+    (probes, kept)
+}
+
+/// One probe per device to record: its first passing endpoint, else its first.
+fn device_verdicts(probes: &[DeviceProbe]) -> Vec<(&str, DeepProbe)> {
+    let mut verdicts: Vec<(&str, &DeviceProbe)> = Vec::new();
+    for p in probes {
+        match verdicts.iter_mut().find(|(id, _)| *id == p.device_id) {
+            Some(verdict) if !verdict.1.reachable && p.reachable => verdict.1 = p,
+            Some(_) => {}
+            None => verdicts.push((&p.device_id, p)),
+        }
+    }
+    verdicts
+        .into_iter()
+        .map(|(id, p)| (id, DeepProbe { ok: p.reachable, depth: p.depth, detail: p.detail.clone() }))
+        .collect()
+}
+
+/// Error response for a failed server start: a structured 409 when the port
+/// is taken, 500 otherwise.
+pub fn start_error(e: anyhow::Error) -> Response {
+    if let Some(no_rpc) = e.downcast_ref::<NoRpcSupport>() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "error": "no_rpc_support",
+                "binary": no_rpc.path,
+                "version": no_rpc.version,
+                "message": no_rpc.to_string(),
+            })),
+        )
+            .into_response();
+    }
+    match e.downcast_ref::<PortInUse>() {
+        Some(in_use) => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "error": "port_in_use",
+                "port": in_use.port,
+                "holder": in_use.holder,
+                "message": in_use.to_string(),
+            })),
+        )
+            .into_response(),
+        None => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": e.to_string() })),
         )
@@ -244,10 +982,87 @@ pub async fn start_inference(
     }
 }
 
+/// Resolve `tags` to the IDs of approved devices carrying any of them whose
+/// RPC server answers right now. Offline, low-trust and unreliable devices
+/// are skipped.
+async fn expand_device_tags(state: &AppState, tags: &[String]) -> Result<Vec<String>, String> {
+    let tags = crate::api::devices::normalize_tags(tags)?;
+
+    // Tag selection silently skips devices not trusted for cluster work
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let min_cluster_trust = svc.trust_threshold(Key::MinClusterTrust).await;
+    let role_trust = svc.role_trust_levels().await.map_err(|e| e.to_string())?;
+
+    let mut candidates: Vec<Device> = Vec::new();
+    for tag in &tags {
+        let devices = queries::list_devices_with_tag(&state.pool, tag)
+            .await
+            .map_err(|e| e.to_string())?;
+        for d in devices {
+            if d.status == DeviceStatus::Approved
+                && PermissionService::effective_trust_level(&d, &role_trust) >= min_cluster_trust
+                && !candidates.iter().any(|c| c.id == d.id)
+            {
+                if health::is_unreliable(&d) {
+                    tracing::info!("Leaving unreliable device {} (health {}) out of tag '{}'", d.name, d.health_score, tag);
+                    continue;
+                }
+                candidates.push(d);
+            }
+        }
+    }
+
+    // Any answering endpoint will do; start_inference drops the others
+    let probes = candidates.iter().map(|d| {
+        let mgr = state.llama_cpp.clone();
+        let ip = d.ip.clone();
+        let ports = d.rpc_ports();
+        async move {
+            for port in ports {
+                if mgr.probe_rpc_device(&ip, port).await {
+                    return true;
+                }
+            }
+            false
+        }
+    });
+    let reachable = join_all(probes).await;
+
+    Ok(candidates
+        .into_iter()
+        .zip(reachable)
+        .filter(|(_, online)| *online)
+        .map(|(d, _)| d.id)
+        .collect())
+}
+
 // ─── POST /api/cluster/inference/stop ────────────────────────────────────────
 
-pub async fn stop_inference(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match state.llama_cpp.stop_inference().await {
+#[utoipa::path(
+    post,
+    path = "/api/cluster/inference/stop",
+    tag = "cluster",
+    request_body = Option<StopInferenceRequest>,
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn stop_inference(
+    State(state): State<Arc<AppState>>,
+    body: Option<Json<StopInferenceRequest>>,
+) -> impl IntoResponse {
+    let req = body.map(|Json(r)| r).unwrap_or_default();
+    let timeout_secs = match req.timeout_secs {
+        Some(t) => t,
+        None => state.settings.get_u64(Key::DrainTimeoutSecs),
+    };
+
+    match state
+        .llama_cpp
+        .stop_inference(req.force, std::time::Duration::from_secs(timeout_secs.min(600)))
+        .await
+    {
         Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -259,25 +1074,102 @@ pub async fn stop_inference(State(state): State<Arc<AppState>>) -> impl IntoResp
 
 // ─── GET /api/cluster/inference/status ───────────────────────────────────────
 
+#[utoipa::path(
+    get,
+    path = "/api/cluster/inference/status",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "`{running, healthy, session: InferenceSessionInfo, inference_port, completions: {in_flight, queued, max_concurrent, queue_size}, slots: {total, busy, idle} | null, idle_for_secs: number | null, idle_timeout_minutes}`", body = serde_json::Value),
+    )
+)]
 pub async fn inference_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     let status = state.llama_cpp.get_status().await;
+    let (in_flight, queued) = state.llama_cpp.admission.counts();
+    let limits = AdmissionLimits::from_settings(&state.settings, status.current_session.as_ref());
+    // Live occupancy from llama-server's /slots, to see whether `parallel` is used
+    let slots = match state.llama_cpp.get_inference_metrics().await {
+        Ok(Some(m)) => serde_json::json!({
+            "total": m.slots_total,
+            "busy": m.slots_busy,
+            "idle": m.slots_idle,
+        }),
+        _ => serde_json::Value::Null,
+    };
     Json(serde_json::json!({
         "running": status.inference_running,
         "healthy": state.llama_cpp.inference_is_healthy().await,
         "session": status.current_session,
         "inference_port": status.inference_port,
+        "completions": {
+            "in_flight": in_flight,
+            "queued": queued,
+            "max_concurrent": limits.max_concurrent,
+            "queue_size": limits.queue_size,
+        },
+        "slots": slots,
+        // For a countdown to the idle stop; null without a session
+        "idle_for_secs": status.current_session.as_ref().map(|_| state.llama_cpp.idle_for_secs()),
+        "idle_timeout_minutes": state.settings.get_u64(Key::IdleTimeoutMinutes),
     }))
     .into_response()
 }
 
+// ─── GET /api/cluster/inference/metrics ──────────────────────────────────────
+
+#[utoipa::path(
+    get,
+    path = "/api/cluster/inference/metrics",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "`{running, metrics: InferenceMetrics | null}`", body = serde_json::Value),
+        (status = 502, body = ErrorResponse),
+    )
+)]
+pub async fn inference_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.llama_cpp.get_inference_metrics().await {
+        Ok(Some(metrics)) => Json(serde_json::json!({
+            "running": true,
+            "metrics": metrics,
+        }))
+        .into_response(),
+        Ok(None) => Json(serde_json::json!({
+            "running": false,
+            "metrics": null,
+        }))
+        .into_response(),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
 // ─── GET /api/cluster/model-check ────────────────────────────────────────────
 
+#[utoipa::path(
+    get,
+    path = "/api/cluster/model-check",
+    tag = "cluster",
+    params(ModelCheckParams),
+    responses(
+        (status = 200, body = ModelAnalysis),
+        (status = 400, body = ErrorResponse),
+    )
+)]
 pub async fn model_check(
     State(state): State<Arc<AppState>>,
     Query(params): Query<ModelCheckParams>,
 ) -> impl IntoResponse {
     // Validate model path (VULN-02 defense in depth)
-    if let Err(e) = validate_model_path(&params.path) {
+    let model_path = match resolve_model_path(&state.pool, &params.path).await {
+        Ok(p) => p,
+        Err(msg) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+                .into_response()
+        }
+    };
+    if let Err(e) = validate_model_path(&model_path) {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": e.to_string() })),
@@ -285,37 +1177,82 @@ pub async fn model_check(
             .into_response();
     }
 
-    // Get local free memory across all providers
-    let snapshots = crate::memory::aggregate_snapshot_async(&state.providers).await;
-    let local_free_mb: u64 = snapshots.iter().map(|s| s.free_mb).sum();
+    // Local memory usable across all providers, minus what running sessions hold
+    let reserved = Reserved::load(&state.pool).await;
+    let host_reserved = state.settings.get_string(Key::HostReservedMb);
+    let snapshots = crate::memory::local_snapshots(&state.providers.load(), Some(&host_reserved)).await;
+    let host_reserved_mb = host_reserved_total(&snapshots);
+    let local_free_mb: u64 = snapshots
+        .iter()
+        .map(|s| s.usable_free_mb)
+        .sum::<u64>()
+        .saturating_sub(reserved.local_total());
 
-    // Collect free memory from selected (or all approved) cluster devices
-    let device_free_mbs: Vec<u64> = if let Some(ids_str) = &params.device_ids {
+    // Collect free memory from the selected cluster devices
+    let mut devices = Vec::new();
+    if let Some(ids_str) = &params.device_ids {
         let ids: Vec<&str> = ids_str
             .split(',')
             .map(str::trim)
             .filter(|s| !s.is_empty())
-            .take(20)  // VULN-12: cap at 20 to prevent DoS
+            .take(state.limits.get().max_cluster_devices)  // VULN-12: cap to prevent DoS
             .collect();
-        let mut mbs = Vec::new();
         for id in ids {
+            if devices.iter().any(|d: &Device| d.id == id) {
+                continue;
+            }
             if let Ok(Some(device)) = queries::get_device(&state.pool, id).await {
-                if device.memory_free_mb > 0 {
-                    mbs.push(device.memory_free_mb as u64);
-                }
+                devices.push(device);
             }
         }
-        mbs
-    } else {
-        vec![]
+    }
+    let device_breakdown = device_memory(&state, devices, params.fresh).await;
+    let device_free_mbs: Vec<u64> = device_breakdown
+        .iter()
+        .map(|d| d.free_mb.saturating_sub(reserved.device(&d.device_id)))
+        .collect();
+
+    let draft_path = match &params.draft_path {
+        None => None,
+        Some(requested) => match resolve_model_path(&state.pool, requested).await {
+            Ok(p) => Some(p),
+            Err(msg) => {
+                return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+                    .into_response()
+            }
+        },
     };
 
-    match crate::llama_cpp::LlamaCppManager::analyze_model(
-        &params.path,
+    let lora_adapters: Vec<LoraAdapter> = params
+        .lora_paths
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|path| LoraAdapter { path: path.to_string(), scale: 1.0 })
+        .collect();
+    if let Err(e) = lora::validate(&lora_adapters) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() })))
+            .into_response();
+    }
+
+    match LlamaCppManager::analyze_model(
+        &model_path,
+        draft_path.as_deref(),
         local_free_mb,
         device_free_mbs,
     ) {
-        Ok(analysis) => Json(serde_json::to_value(analysis).unwrap_or_default()).into_response(),
+        Ok(mut analysis) => {
+            analysis.device_breakdown = device_breakdown;
+            LlamaCppManager::check_lora_fit(&mut analysis, &lora_adapters);
+            LlamaCppManager::check_host_reserve(&mut analysis, host_reserved_mb);
+            LlamaCppManager::estimate_distribution(&mut analysis);
+            let ctx_size = params.ctx_size.unwrap_or(analysis.recommended_ctx_size);
+            let parallel = params.parallel.unwrap_or_else(default_parallel).clamp(1, MAX_PARALLEL);
+            LlamaCppManager::check_kv_cache(&mut analysis, ctx_size, parallel);
+            Json(serde_json::to_value(analysis).unwrap_or_default()).into_response()
+        }
         Err(e) => (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": e.to_string() })),
@@ -324,25 +1261,149 @@ pub async fn model_check(
     }
 }
 
+/// Free memory of each device, asked for in parallel. A reading from the
+/// last few seconds is reused unless `fresh`; devices below `min_stats_trust`
+/// aren't asked. When a device can't be read, the stored value is used and
+/// marked stale.
+async fn device_memory(state: &AppState, devices: Vec<Device>, fresh: bool) -> Vec<DeviceMemory> {
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let min_stats_trust = svc.trust_threshold(Key::MinStatsTrust).await;
+    let role_trust = svc.role_trust_levels().await.unwrap_or_default();
+
+    let reads = devices.into_iter().map(|d| {
+        let cached = if fresh { None } else { state.remote_memory.get(&d.id) };
+        let may_ask = PermissionService::effective_trust_level(&d, &role_trust) >= min_stats_trust;
+        async move {
+            let (free_mb, live, stale) = match cached {
+                // Set by hand; nothing is asked
+                _ if d.memory_manual_override => (d.memory_free_mb, false, false),
+                Some((_, free)) => (free, false, false),
+                // No /api/gpu to ask; its heartbeat keeps the stored value current
+                None if d.device_kind == DeviceKind::Agent => (d.memory_free_mb, false, false),
+                None if may_ask => match fetch_remote_memory(&state.llama_cpp.client, &d.ip).await {
+                    Some((total, free)) => {
+                        state.remote_memory.put(&d.id, total, free);
+                        let _ = queries::update_device_memory_stats(&state.pool, &d.id, total, free).await;
+                        (free, true, false)
+                    }
+                    None => (d.memory_free_mb, false, true),
+                },
+                None => (d.memory_free_mb, false, true),
+            };
+            DeviceMemory {
+                device_id: d.id,
+                name: d.name,
+                free_mb: free_mb.max(0) as u64,
+                live,
+                stale,
+                rpc_cache_enabled: d.rpc_cache_enabled,
+                rpc_cache_mb: d.rpc_cache_mb,
+                cache_likely: false,
+                manual_override: d.memory_manual_override,
+            }
+        }
+    });
+    join_all(reads).await
+}
+
 // ─── POST /api/cluster/rpc/start ─────────────────────────────────────────────
 
+#[utoipa::path(
+    post,
+    path = "/api/cluster/rpc/start",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "`{ok, port}`", body = serde_json::Value),
+        (status = 409, description = "The RPC port is taken", body = PortInUseError),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn start_rpc_server(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match state.llama_cpp.start_rpc_server().await {
+    let mem_mb = rpc_mem_cap(state.settings.get_u64(Key::RpcMemMb));
+    match state.llama_cpp.start_rpc_server(mem_mb).await {
         Ok(()) => Json(serde_json::json!({
             "ok": true,
             "port": state.llama_cpp.rpc_port,
         }))
         .into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({ "error": e.to_string() })),
-        )
-            .into_response(),
+        Err(e) => start_error(e),
     }
 }
 
+// ─── GET /api/cluster/binaries ───────────────────────────────────────────────
+
+/// What the installed llama-server was built with (version, target,
+/// backends) and whether it can use RPC devices. Read by running it with
+/// `--version` and `--help`, then kept until a binary is replaced.
+#[utoipa::path(
+    get,
+    path = "/api/cluster/binaries",
+    tag = "cluster",
+    responses(
+        (status = 200, body = BinariesInfo),
+    )
+)]
+pub async fn cluster_binaries(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.llama_cpp.binary_info().await)
+}
+
+// ─── GET /api/cluster/ports ──────────────────────────────────────────────────
+
+/// Configured llama.cpp ports and whether they can be bound right now.
+/// `ours` marks a port held by a server this backend started.
+#[utoipa::path(
+    get,
+    path = "/api/cluster/ports",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "`{ports: [{name, port, available, ours, holder}]}`", body = serde_json::Value),
+    )
+)]
+pub async fn cluster_ports(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let status = state.llama_cpp.get_status().await;
+    let servers = [
+        ("rpc", state.llama_cpp.rpc_port, status.rpc_server_running),
+        ("inference", state.llama_cpp.inference_port, status.inference_running),
+    ];
+
+    // Identifying a holder walks /proc; keep it off the async workers
+    let report = tokio::task::spawn_blocking(move || {
+        servers
+            .iter()
+            .map(|(name, port, running)| {
+                let available = ports::is_free(*port);
+                let holder = if available {
+                    None
+                } else {
+                    ports::find_holder(*port).map(|(pid, cmd)| format!("{} (pid {})", cmd, pid))
+                };
+                serde_json::json!({
+                    "name": name,
+                    "port": port,
+                    "available": available,
+                    "ours": !available && *running,
+                    "holder": holder,
+                })
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    Json(serde_json::json!({ "ports": report }))
+}
+
 // ─── POST /api/cluster/rpc/stop ──────────────────────────────────────────────
 
+#[utoipa::path(
+    post,
+    path = "/api/cluster/rpc/stop",
+    tag = "cluster",
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn stop_rpc_server(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     match state.llama_cpp.stop_rpc_server().await {
         Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
@@ -356,91 +1417,541 @@ pub async fn stop_rpc_server(State(state): State<Arc<AppState>>) -> impl IntoRes
 
 // ─── POST /v1/chat/completions (proxy to active backend) ─────────────────────
 
+/// Backend the `/v1` proxy forwards to, read from the settings cache so a
+/// proxied request never waits on SQLite.
+struct ActiveBackend {
+    backend_type: String,
+    url: String,
+    api_key: Option<String>,
+}
+
+impl ActiveBackend {
+    fn from_settings(settings: &SettingsCache) -> Self {
+        ActiveBackend {
+            backend_type: settings.get_string(Key::BackendType),
+            url: settings.get_string(Key::BackendUrl),
+            api_key: Some(settings.get_string(Key::BackendSecret)).filter(|s| !s.is_empty()),
+        }
+    }
+}
+
+/// Setting: largest `/v1/chat/completions` body accepted, in MB.
+pub const MAX_BODY_MB_KEY: &str = "proxy_max_body_mb";
+/// Setting: largest image (decoded) a chat request may carry, in MB.
+pub const MAX_IMAGE_MB_KEY: &str = "proxy_max_image_mb";
+
+/// Setting: chat requests forwarded to llama-server at once (0 = the session's `--parallel`).
+pub const MAX_CONCURRENT_KEY: &str = "max_concurrent_completions";
+/// Setting: chat requests that may wait for a slot before getting 429.
+pub const QUEUE_SIZE_KEY: &str = "completion_queue_size";
+/// Setting: how long a queued chat request waits before getting 429.
+pub const QUEUE_TIMEOUT_KEY: &str = "completion_queue_timeout_secs";
+/// Setting: seconds after which a queued chat request goes ahead of higher
+/// priority lanes.
+pub const QUEUE_PROMOTE_KEY: &str = "completion_queue_promote_secs";
+/// Response header naming the admission lane a chat request was queued in.
+pub const PRIORITY_HEADER: &str = "x-sharedllm-priority";
+/// `Retry-After` sent with a 429 from the admission queue.
+const QUEUE_RETRY_AFTER_SECS: &str = "5";
+
+/// Admission settings for llama.cpp chat requests.
+struct AdmissionLimits {
+    max_concurrent: usize,
+    queue_size: usize,
+    queue_timeout: std::time::Duration,
+    promote_after: std::time::Duration,
+}
+
+impl AdmissionLimits {
+    fn from_settings(settings: &SettingsCache, session: Option<&InferenceSessionInfo>) -> Self {
+        let parallel = session.map_or_else(default_parallel, |s| s.parallel);
+        Self {
+            max_concurrent: match settings.get_u64(Key::MaxConcurrentCompletions) {
+                0 => parallel as usize,
+                n => n as usize,
+            },
+            queue_size: settings.get_u64(Key::CompletionQueueSize) as usize,
+            queue_timeout: std::time::Duration::from_secs(settings.get_u64(Key::CompletionQueueTimeoutSecs)),
+            promote_after: std::time::Duration::from_secs(settings.get_u64(Key::CompletionQueuePromoteSecs)),
+        }
+    }
+}
+
+/// Admission lane of a chat request: the higher of what its API key and
+/// its source device's role earn. An admin-scoped key counts as admin and
+/// any other key as user; the host itself is admin, and an approved device
+/// gets the lane of its role's trust level. Everyone else is anonymous.
+async fn request_priority(
+    state: &AppState,
+    api_key: Option<&ApiKey>,
+    peer: Option<SocketAddr>,
+) -> Priority {
+    let from_key = match api_key.map(|k| k.scope) {
+        Some(KeyScope::Admin) => Priority::Admin,
+        Some(_) => Priority::User,
+        None => Priority::Anonymous,
+    };
+    let from_device = match peer.map(|p| p.ip()) {
+        Some(ip) if ip.is_loopback() => Priority::Admin,
+        Some(ip) => PermissionService::new(state.pool.clone(), state.event_tx.clone())
+            .device_trust(ip)
+            .await
+            .ok()
+            .flatten()
+            .map_or(Priority::Anonymous, Priority::from_trust),
+        None => Priority::Anonymous,
+    };
+    from_key.max(from_device)
+}
+
+/// A request carrying this header (any value but `0` or `false`) is
+/// forwarded without the session's system prompt.
+pub const NO_SYSTEM_PROMPT_HEADER: &str = "x-sharedllm-no-system-prompt";
+
+/// Request fields stripped before forwarding, per backend type.
+const UNSUPPORTED_FIELDS: &[(&str, &[&str])] = &[("llamacpp", &["stream_options"])];
+
+/// Error body in the shape OpenAI clients expect:
+/// `{"error": {"message", "type", "param", "code"}}`.
+pub(crate) fn openai_error(status: StatusCode, message: &str, error_type: &str, code: Option<&str>) -> Response {
+    (
+        status,
+        Json(serde_json::json!({
+            "error": {
+                "message": message,
+                "type": error_type,
+                "param": null,
+                "code": code,
+            }
+        })),
+    )
+        .into_response()
+}
+
+/// Parse a chat request and check the fields every backend needs.
+fn parse_chat_request(body: &[u8]) -> Result<serde_json::Value, String> {
+    let json: serde_json::Value =
+        serde_json::from_slice(body).map_err(|e| format!("Request body is not valid JSON: {}", e))?;
+    if !json.is_object() {
+        return Err("Request body must be a JSON object".to_string());
+    }
+    match json.get("model").and_then(|m| m.as_str()) {
+        Some(m) if !m.trim().is_empty() => {}
+        _ => return Err("'model' is required and must be a non-empty string".to_string()),
+    }
+    if !json.get("messages").is_some_and(|m| m.is_array()) {
+        return Err("'messages' is required and must be an array".to_string());
+    }
+    Ok(json)
+}
+
+/// Put `prompt` first in a chat request's messages unless one of them is
+/// already a system message. Returns whether it was added.
+fn inject_system_prompt(json: &mut serde_json::Value, prompt: &str) -> bool {
+    let Some(messages) = json["messages"].as_array_mut() else {
+        return false;
+    };
+    if messages.iter().any(|m| m["role"] == "system") {
+        return false;
+    }
+    messages.insert(0, serde_json::json!({ "role": "system", "content": prompt }));
+    true
+}
+
+/// Whether the caller opted out of the session's system prompt.
+fn skips_system_prompt(headers: &HeaderMap) -> bool {
+    headers
+        .get(NO_SYSTEM_PROMPT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| !matches!(v.trim().to_ascii_lowercase().as_str(), "0" | "false"))
+}
+
+/// URLs of the images in a chat request's `image_url` content parts. Both
+/// `{"image_url": {"url": ...}}` and a bare string are accepted.
+fn image_urls(json: &serde_json::Value) -> Vec<&str> {
+    json["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m["content"].as_array())
+        .flatten()
+        .filter(|part| part["type"] == "image_url")
+        .filter_map(|part| part["image_url"]["url"].as_str().or_else(|| part["image_url"].as_str()))
+        .collect()
+}
+
+/// Decoded size of a base64 `data:` URL in bytes; `None` for other URLs,
+/// which are passed on as they are.
+fn data_url_bytes(url: &str) -> Option<usize> {
+    let (_, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    Some(data.trim_end_matches('=').len() * 3 / 4)
+}
+
+/// The first image larger than `max_mb`, as its size in MB.
+fn oversized_image(json: &serde_json::Value, max_mb: usize) -> Option<usize> {
+    image_urls(json)
+        .into_iter()
+        .filter_map(data_url_bytes)
+        .find(|bytes| *bytes > max_mb * 1024 * 1024)
+        .map(|bytes| bytes.div_ceil(1024 * 1024))
+}
+
+/// Drop fields the target backend would reject. `stream_options` is only
+/// meaningful (and only accepted upstream) alongside `"stream": true`.
+fn sanitize_chat_request(json: &mut serde_json::Value, backend_type: &str) {
+    let Some(obj) = json.as_object_mut() else {
+        return;
+    };
+    if obj.get("stream").and_then(|s| s.as_bool()) != Some(true) {
+        obj.remove("stream_options");
+    }
+    for (_, fields) in UNSUPPORTED_FIELDS.iter().filter(|(t, _)| *t == backend_type) {
+        for field in *fields {
+            obj.remove(*field);
+        }
+    }
+}
+
+/// OpenAI chat completions. A request this host can't serve, because the
+/// model isn't loaded or nothing is running, goes to a peer host serving
+/// the model, unless it already came through one (`x-sharedllm-hop`).
+#[utoipa::path(
+    post,
+    path = "/v1/chat/completions",
+    tag = "openai",
+    request_body = serde_json::Value,
+    responses(
+        (status = 200, description = "OpenAI chat completion (SSE when `stream` is true)", body = serde_json::Value),
+        (status = 400, description = "OpenAI-style error object", body = serde_json::Value),
+        (status = 404, description = "The model is not the one loaded", body = serde_json::Value),
+        (status = 401, description = "`require_api_keys` is on and the key is missing, unknown or revoked", body = serde_json::Value),
+        (status = 413, description = "Body exceeds `proxy_max_body_mb`", body = serde_json::Value),
+        (status = 429, description = "llama.cpp admission queue is full, the request waited longer than `completion_queue_timeout_secs`, or the API key's rate limit was reached", body = serde_json::Value),
+        (status = 503, description = "No backend is serving", body = serde_json::Value),
+    )
+)]
 pub async fn chat_completions_proxy(
     State(state): State<Arc<AppState>>,
-    body: axum::body::Bytes,
+    api_key: Option<Extension<ApiKey>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    body: Body,
 ) -> Response {
-    // Read active backend config from DB
-    let backend_type = queries::get_setting(&state.pool, "backend_type")
-        .await
-        .unwrap_or(None)
-        .unwrap_or_else(|| "llamacpp".to_string());
+    let backend = ActiveBackend::from_settings(&state.settings);
+    let hops = federation::hops(&headers);
+
+    // Read at most the configured limit; larger bodies are never buffered
+    let max_mb = state.settings.get_u64(Key::ProxyMaxBodyMb).max(1) as usize;
+    let Ok(body) = axum::body::to_bytes(body, max_mb * 1024 * 1024).await else {
+        return openai_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("Request body exceeds the {} MB limit", max_mb),
+            "invalid_request_error",
+            Some("request_too_large"),
+        );
+    };
+
+    let mut json = match parse_chat_request(&body) {
+        Ok(j) => j,
+        Err(e) => {
+            return openai_error(StatusCode::BAD_REQUEST, &e, "invalid_request_error", None)
+        }
+    };
+    if let Some(Extension(key)) = &api_key {
+        let requested = json["model"].as_str().unwrap_or_default();
+        if !key.allows(requested) {
+            return openai_error(
+                StatusCode::NOT_FOUND,
+                &format!("The model '{}' does not exist or you do not have access to it", requested),
+                "invalid_request_error",
+                Some("model_not_found"),
+            );
+        }
+    }
+    sanitize_chat_request(&mut json, &backend.backend_type);
+
+    let max_image_mb = state.settings.get_u64(Key::ProxyMaxImageMb).max(1) as usize;
+    if let Some(mb) = oversized_image(&json, max_image_mb) {
+        return openai_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("An image of {} MB exceeds the {} MB limit", mb, max_image_mb),
+            "invalid_request_error",
+            Some("image_too_large"),
+        );
+    }
 
     // ── llama.cpp path (existing behaviour) ──────────────────────────────────
-    if backend_type == "llamacpp" {
-        if !state.llama_cpp.is_inference_running().await {
-            return Response::builder()
-                .status(StatusCode::SERVICE_UNAVAILABLE)
-                .header("Content-Type", "application/json")
-                .body(Body::from(
-                    serde_json::json!({
-                        "error": "Inference server is not running. Start it from the Inference page first."
-                    })
-                    .to_string(),
-                ))
-                .unwrap_or_else(|_| {
-                    Response::builder()
-                        .status(StatusCode::SERVICE_UNAVAILABLE)
-                        .body(Body::empty())
-                        .unwrap()
-                });
+    if backend.backend_type == "llamacpp" {
+        let session = match state.llama_cpp.get_current_session().await {
+            Some(s) if state.llama_cpp.is_inference_running().await => s,
+            _ => {
+                let local = openai_error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Inference server is not running. Start it from the Inference page first.",
+                    "server_error",
+                    Some("no_session"),
+                );
+                return forward_to_peer(&state, hops, &json).await.unwrap_or(local);
+            }
+        };
+
+        // Only the loaded model can answer; refuse rather than run it under another name
+        let requested = json["model"].as_str().unwrap_or_default().to_string();
+        if !names_loaded_model(&state, &requested, &session.model_path).await {
+            let local = openai_error(
+                StatusCode::NOT_FOUND,
+                &format!("The model '{}' is not loaded", requested),
+                "invalid_request_error",
+                Some("model_not_found"),
+            );
+            return forward_to_peer(&state, hops, &json).await.unwrap_or(local);
+        }
+        if !session.supports_vision && !image_urls(&json).is_empty() {
+            return openai_error(
+                StatusCode::BAD_REQUEST,
+                &format!(
+                    "The model '{}' can't read images: the session was started without a vision projector (mmproj_path)",
+                    requested
+                ),
+                "invalid_request_error",
+                Some("vision_not_supported"),
+            );
+        }
+        if let Some(prompt) = session.system_prompt.as_deref().filter(|_| !skips_system_prompt(&headers)) {
+            inject_system_prompt(&mut json, prompt);
         }
+        let limits = AdmissionLimits::from_settings(&state.settings, Some(&session));
+        json["model"] = serde_json::Value::String(session.model_path);
+
+        // Wait for a slot in the caller's lane so a small GPU isn't handed
+        // more than it can decode
+        let priority =
+            request_priority(&state, api_key.as_ref().map(|Extension(k)| k), peer.map(|ConnectInfo(a)| a)).await;
+        let lane = axum::http::HeaderValue::from_static(priority.as_str());
+        let permit = match state
+            .llama_cpp
+            .admission
+            .acquire(priority, limits.max_concurrent, limits.queue_size, limits.queue_timeout, limits.promote_after)
+            .await
+        {
+            Ok(p) => p,
+            Err(rejected) => {
+                let message = match rejected {
+                    Rejected::QueueFull => "Too many concurrent requests; the queue is full",
+                    Rejected::TimedOut => "Timed out waiting for a free inference slot",
+                };
+                let mut resp = openai_error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    message,
+                    "rate_limit_error",
+                    Some("rate_limit_exceeded"),
+                );
+                resp.headers_mut().insert(
+                    "Retry-After",
+                    axum::http::HeaderValue::from_static(QUEUE_RETRY_AFTER_SECS),
+                );
+                resp.headers_mut().insert(PRIORITY_HEADER, lane);
+                return resp;
+            }
+        };
+
+        let Some(guard) = state.llama_cpp.track_request().await else {
+            let mut resp = openai_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Inference server is shutting down",
+                "server_error",
+                None,
+            );
+            resp.headers_mut()
+                .insert("Retry-After", axum::http::HeaderValue::from_static("30"));
+            return resp;
+        };
 
         let url = format!(
             "{}/v1/chat/completions",
             state.llama_cpp.inference_base_url()
         );
 
-        return proxy_request(&state.llama_cpp.client, &url, None, body).await;
+        let key = state.llama_cpp.inference_api_key().await;
+        let mut resp =
+            proxy_request(&state.llama_cpp.client, "llamacpp", &url, key.as_deref(), json_bytes(&json), Some((guard, permit)))
+                .await;
+        resp.headers_mut().insert(PRIORITY_HEADER, lane);
+        return resp;
     }
 
     // ── External backend path ─────────────────────────────────────────────────
-    let backend_url = queries::get_setting(&state.pool, "backend_url")
-        .await
-        .unwrap_or(None)
-        .unwrap_or_default();
-
-    let api_key = queries::get_setting(&state.pool, "backend_api_key")
-        .await
-        .unwrap_or(None)
-        .filter(|s| !s.is_empty());
+    if backend.url.is_empty() {
+        let local = openai_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No backend URL configured. Set a backend in the Inference page.",
+            "server_error",
+            Some("no_backend"),
+        );
+        return forward_to_peer(&state, hops, &json).await.unwrap_or(local);
+    }
 
-    if backend_url.is_empty() {
-        return Response::builder()
-            .status(StatusCode::SERVICE_UNAVAILABLE)
-            .header("Content-Type", "application/json")
-            .body(Body::from(
-                serde_json::json!({ "error": "No backend URL configured. Set a backend in the Inference page." })
-                    .to_string(),
-            ))
-            .unwrap_or_else(|_| {
-                Response::builder()
-                    .status(StatusCode::SERVICE_UNAVAILABLE)
-                    .body(Body::empty())
-                    .unwrap()
-            });
+    if backend.backend_type == "ollama" && state.settings.get_bool(Key::OllamaNativeMode) {
+        return ollama_native_chat(&state, &backend, &json).await;
     }
 
-    let chat_url = if backend_type == "ollama" {
+    let chat_url = if backend.backend_type == "ollama" {
         // Ollama supports OpenAI-compat endpoint too; use /v1/chat/completions
-        format!("{}/v1/chat/completions", backend_url.trim_end_matches('/'))
+        format!("{}/v1/chat/completions", backend.url.trim_end_matches('/'))
     } else {
-        format!("{}/v1/chat/completions", backend_url.trim_end_matches('/'))
+        format!("{}/v1/chat/completions", backend.url.trim_end_matches('/'))
     };
 
-    proxy_request(&state.llama_cpp.client, &chat_url, api_key.as_deref(), body).await
+    proxy_request(
+        &state.llama_cpp.client,
+        &backend.backend_type,
+        &chat_url,
+        backend.api_key.as_deref(),
+        json_bytes(&json),
+        None,
+    )
+    .await
+}
+
+fn json_bytes(json: &serde_json::Value) -> axum::body::Bytes {
+    serde_json::to_vec(json).unwrap_or_default().into()
+}
+
+/// Hand a chat request this host can't serve to a peer host whose last
+/// model list has the model. `None` when the hop limit is reached, no peer
+/// has it, or every peer that does failed, so the caller answers locally.
+async fn forward_to_peer(
+    state: &AppState,
+    hops: u32,
+    json: &serde_json::Value,
+) -> Option<Response> {
+    if hops >= federation::MAX_HOPS {
+        return None;
+    }
+    let model = json["model"].as_str()?;
+    let peers = queries::list_peer_devices(&state.pool).await.ok()?;
+    for peer in state.peers.serving(&peers, model) {
+        let sent = state
+            .llama_cpp
+            .client
+            .post(format!("{}/v1/chat/completions", federation::base_url(peer)))
+            .header("Content-Type", "application/json")
+            .header(federation::HOP_HEADER, (hops + 1).to_string())
+            .body(json_bytes(json))
+            .send()
+            .await;
+        match sent {
+            // The peer's own refusals (bad request, busy) are its answer; a
+            // peer requiring API keys can't take forwarded requests
+            Ok(resp)
+                if !resp.status().is_server_error()
+                    && resp.status() != StatusCode::NOT_FOUND
+                    && resp.status() != StatusCode::UNAUTHORIZED =>
+            {
+                tracing::debug!("Forwarded a request for '{}' to peer {}", model, peer.name);
+                return Some(streamed_response(resp, None));
+            }
+            Ok(resp) => {
+                tracing::warn!("Peer {} answered {} for '{}'", peer.name, resp.status(), model);
+                state.peers.forget(&peer.id);
+            }
+            Err(e) => {
+                tracing::warn!("Peer {} is unreachable: {}", peer.name, e);
+                state.peers.forget(&peer.id);
+            }
+        }
+    }
+    None
 }
 
 // ─── GET /v1/models ──────────────────────────────────────────────────────────
 /// OpenAI-compatible model list. Proxies to the active backend when inference
 /// is running; returns an empty list otherwise so Open WebUI stays connected.
+/// Peer hosts' models are appended unless the request came from a peer. An
+/// API key limited to some models only sees those.
+#[utoipa::path(
+    get,
+    path = "/v1/models",
+    tag = "openai",
+    responses(
+        (status = 200, description = "OpenAI model list, followed by the models peer hosts serve; empty while nothing is serving", body = serde_json::Value),
+        (status = 401, description = "`require_api_keys` is on and the key is missing, unknown or revoked", body = serde_json::Value),
+    )
+)]
 pub async fn models_proxy(
     State(state): State<Arc<AppState>>,
+    api_key: Option<Extension<ApiKey>>,
+    headers: HeaderMap,
 ) -> Response {
-    let backend_type = queries::get_setting(&state.pool, "backend_type")
-        .await
-        .unwrap_or(None)
-        .unwrap_or_else(|| "llamacpp".to_string());
+    let listed = listed_models(&state, &headers).await;
+    let Some(Extension(key)) = api_key.filter(|k| !k.allowed_models.0.is_empty()) else {
+        return listed;
+    };
+    if !listed.status().is_success() {
+        return listed;
+    }
+    let Ok(bytes) = axum::body::to_bytes(listed.into_body(), usize::MAX).await else {
+        return Json(serde_json::json!({ "object": "list", "data": [] })).into_response();
+    };
+    let mut list: serde_json::Value = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| serde_json::json!({ "object": "list", "data": [] }));
+    if let Some(data) = list["data"].as_array_mut() {
+        data.retain(|m| m["id"].as_str().is_some_and(|id| key.allows(id)));
+    }
+    Json(list).into_response()
+}
+
+/// Local models, then the peers' unless `headers` show a forwarded request.
+async fn listed_models(state: &AppState, headers: &HeaderMap) -> Response {
+    let local = local_models(state).await;
+    if federation::hops(headers) >= federation::MAX_HOPS || !local.status().is_success() {
+        return local;
+    }
+    let Ok(peers) = queries::list_peer_devices(&state.pool).await else {
+        return local;
+    };
+    let peer_models: Vec<(&Device, Vec<String>)> = peers
+        .iter()
+        .filter_map(|p| state.peers.models(&p.id).map(|m| (p, m)))
+        .collect();
+    if peer_models.is_empty() {
+        return local;
+    }
+    let Ok(bytes) = axum::body::to_bytes(local.into_body(), usize::MAX).await else {
+        return Json(serde_json::json!({ "object": "list", "data": [] })).into_response();
+    };
+    let mut list: serde_json::Value = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| serde_json::json!({ "object": "list", "data": [] }));
+    merge_peer_models(&mut list, &peer_models);
+    Json(list).into_response()
+}
+
+/// Append the peers' models to a `/v1/models` list. A model served here
+/// and on a peer is listed once, as this host's.
+fn merge_peer_models(list: &mut serde_json::Value, peers: &[(&Device, Vec<String>)]) {
+    if !list["data"].is_array() {
+        list["data"] = serde_json::json!([]);
+    }
+    let Some(data) = list["data"].as_array_mut() else {
+        return;
+    };
+    for (peer, models) in peers {
+        for model in models {
+            if data.iter().any(|m| m["id"].as_str() == Some(model)) {
+                continue;
+            }
+            data.push(serde_json::json!({
+                "id": model,
+                "object": "model",
+                "owned_by": format!("peer:{}", peer.name),
+            }));
+        }
+    }
+}
+
+/// The models this host serves itself.
+async fn local_models(state: &AppState) -> Response {
+    let backend = ActiveBackend::from_settings(&state.settings);
 
     // Helper: build an empty OpenAI models response
     let empty = || {
@@ -456,37 +1967,241 @@ pub async fn models_proxy(
     };
 
     // ── llama.cpp path ────────────────────────────────────────────────────────
-    if backend_type == "llamacpp" {
+    if backend.backend_type == "llamacpp" {
         if !state.llama_cpp.is_inference_running().await {
             return empty();
         }
         let url = format!("{}/v1/models", state.llama_cpp.inference_base_url());
-        return proxy_get(&state.llama_cpp.client, &url, None).await;
+        let vision = state
+            .llama_cpp
+            .get_current_session()
+            .await
+            .is_some_and(|s| s.supports_vision);
+        return llama_models_list(state, &url, vision).await;
     }
 
     // ── External backend path ─────────────────────────────────────────────────
-    let backend_url = queries::get_setting(&state.pool, "backend_url")
-        .await
-        .unwrap_or(None)
-        .unwrap_or_default();
-
-    if backend_url.is_empty() {
+    if backend.url.is_empty() {
         return empty();
     }
 
-    let api_key = queries::get_setting(&state.pool, "backend_api_key")
-        .await
-        .unwrap_or(None)
-        .filter(|s| !s.is_empty());
+    let url = format!("{}/v1/models", backend.url.trim_end_matches('/'));
+    proxy_get(&state.llama_cpp.client, &backend.backend_type, &url, backend.api_key.as_deref()).await
+}
+
+// ─── Model name translation (llama.cpp) ──────────────────────────────────────
+
+/// Fetch llama-server's model list and replace file paths with public names
+/// (alias, or file name without extension) so clients never see the filesystem.
+/// Each model is marked with `supports_vision`, so clients such as Open WebUI
+/// only offer image upload when the session has a projector.
+async fn llama_models_list(state: &AppState, url: &str, vision: bool) -> Response {
+    let key = state.llama_cpp.inference_api_key().await;
+    let mut request = state.llama_cpp.client.get(url);
+    if let Some(key) = &key {
+        request = request.bearer_auth(key);
+    }
+    let mut list: serde_json::Value = match request.send().await {
+        Ok(resp) => match resp.json().await {
+            Ok(v) => v,
+            Err(_) => return proxy_get(&state.llama_cpp.client, "llamacpp", url, key.as_deref()).await,
+        },
+        Err(_) => return proxy_get(&state.llama_cpp.client, "llamacpp", url, key.as_deref()).await,
+    };
+
+    let aliases = aliases_by_path(&state.pool).await;
+    let rename = |v: &mut serde_json::Value| {
+        if let Some(name) = v.as_str() {
+            *v = serde_json::Value::String(public_model_name(name, &aliases));
+        }
+    };
+    if let Some(data) = list.get_mut("data").and_then(|d| d.as_array_mut()) {
+        for entry in data {
+            if let Some(id) = entry.get_mut("id") {
+                rename(id);
+            }
+            if let Some(entry) = entry.as_object_mut() {
+                entry.insert("supports_vision".into(), vision.into());
+            }
+        }
+    }
+    // Newer llama-server builds also return an Ollama-style `models` array
+    if let Some(models) = list.get_mut("models").and_then(|m| m.as_array_mut()) {
+        for entry in models {
+            for key in ["name", "model"] {
+                if let Some(v) = entry.get_mut(key) {
+                    rename(v);
+                }
+            }
+        }
+    }
 
-    let url = format!("{}/v1/models", backend_url.trim_end_matches('/'));
-    proxy_get(&state.llama_cpp.client, &url, api_key.as_deref()).await
+    Json(list).into_response()
+}
+
+/// Whether `name` refers to the loaded model: its path, its alias, or its
+/// fallback public name (file stem).
+async fn names_loaded_model(state: &AppState, name: &str, model_path: &str) -> bool {
+    if name == model_path {
+        return true;
+    }
+    if let Ok(Some(alias)) = queries::get_model_alias(&state.pool, name).await {
+        return alias.path == model_path;
+    }
+    let aliases = aliases_by_path(&state.pool).await;
+    public_model_name(model_path, &aliases) == name
 }
 
 // ─── shared proxy helper ──────────────────────────────────────────────────────
 
+/// Seconds a client is told to wait while llama-server loads its model.
+const MODEL_LOADING_RETRY_AFTER_SECS: &str = "10";
+
+/// Why a proxied request got no answer from its backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UpstreamFailure {
+    Dns,
+    Refused,
+    Tls,
+    Timeout,
+    Unreachable,
+}
+
+impl UpstreamFailure {
+    /// Classify by the error's causes: reqwest only flags timeouts itself.
+    fn classify(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return UpstreamFailure::Timeout;
+        }
+        let mut source = std::error::Error::source(e);
+        while let Some(cause) = source {
+            if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                match io.kind() {
+                    std::io::ErrorKind::ConnectionRefused => return UpstreamFailure::Refused,
+                    std::io::ErrorKind::TimedOut => return UpstreamFailure::Timeout,
+                    _ => {}
+                }
+            }
+            let text = cause.to_string().to_ascii_lowercase();
+            if text.contains("dns error") || text.contains("failed to lookup address") {
+                return UpstreamFailure::Dns;
+            }
+            if ["tls", "ssl", "certificate", "handshake"].iter().any(|w| text.contains(w)) {
+                return UpstreamFailure::Tls;
+            }
+            source = cause.source();
+        }
+        UpstreamFailure::Unreachable
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            UpstreamFailure::Dns => "backend_dns_error",
+            UpstreamFailure::Refused => "backend_connection_refused",
+            UpstreamFailure::Tls => "backend_tls_error",
+            UpstreamFailure::Timeout => "backend_timeout",
+            UpstreamFailure::Unreachable => "backend_unreachable",
+        }
+    }
+
+    fn status(self) -> StatusCode {
+        match self {
+            UpstreamFailure::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            UpstreamFailure::Dns => "could not be resolved",
+            UpstreamFailure::Refused => "refused the connection",
+            UpstreamFailure::Tls => "failed the TLS handshake",
+            UpstreamFailure::Timeout => "did not answer in time",
+            UpstreamFailure::Unreachable => "could not be reached",
+        }
+    }
+}
+
+/// The name users know a backend type by.
+fn backend_label(backend_type: &str) -> &str {
+    match backend_type {
+        "llamacpp" => "llama.cpp",
+        "ollama" => "Ollama",
+        "lmstudio" => "LM Studio",
+        "vllm" => "vLLM",
+        "openai" => "OpenAI",
+        other => other,
+    }
+}
+
+/// OpenAI-style error for a request to `url` that got no answer, naming the
+/// backend so users can tell which layer failed.
+fn upstream_error(backend_type: &str, url: &str, e: &reqwest::Error) -> Response {
+    let failure = UpstreamFailure::classify(e);
+    tracing::warn!("Proxying to {} failed ({:?}): {}", url, failure, e);
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| u.port().map_or_else(|| h.to_string(), |p| format!("{}:{}", h, p))))
+        .unwrap_or_else(|| url.to_string());
+    openai_error(
+        failure.status(),
+        &format!("The {} backend at {} {}", backend_label(backend_type), host, failure.describe()),
+        "server_error",
+        Some(failure.code()),
+    )
+}
+
+/// llama-server answers 503 with "Loading model" until its model is loaded.
+fn model_loading() -> Response {
+    let mut resp = openai_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "llama.cpp is still loading the model; retry shortly",
+        "server_error",
+        Some("model_loading"),
+    );
+    resp.headers_mut().insert(
+        "Retry-After",
+        axum::http::HeaderValue::from_static(MODEL_LOADING_RETRY_AFTER_SECS),
+    );
+    resp
+}
+
+/// An upstream answer read in full and passed on as it is.
+async fn buffered_response(resp: reqwest::Response) -> Response {
+    let status = resp.status();
+    let ct = resp
+        .headers()
+        .get("content-type")
+        .cloned()
+        .unwrap_or_else(|| "application/json".parse().unwrap());
+    let bytes = resp.bytes().await.unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header("content-type", ct)
+        .body(Body::from(bytes))
+        .unwrap_or_else(|_| {
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap()
+        })
+}
+
+/// A 503 from llama-server. While the model loads it says "Loading model";
+/// other 503s, e.g. no free slot, pass through with their body.
+async fn llamacpp_unavailable(resp: reqwest::Response) -> Response {
+    let response = buffered_response(resp).await;
+    let (parts, body) = response.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap_or_default();
+    if String::from_utf8_lossy(&bytes).contains("Loading model") {
+        return model_loading();
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
 async fn proxy_get(
     client: &reqwest::Client,
+    backend_type: &str,
     url: &str,
     api_key: Option<&str>,
 ) -> Response {
@@ -495,46 +2210,21 @@ async fn proxy_get(
         req = req.header("Authorization", format!("Bearer {}", key));
     }
     match req.send().await {
-        Ok(resp) => {
-            let status = resp.status();
-            let ct = resp
-                .headers()
-                .get("content-type")
-                .cloned()
-                .unwrap_or_else(|| "application/json".parse().unwrap());
-            let bytes = resp.bytes().await.unwrap_or_default();
-            Response::builder()
-                .status(status)
-                .header("content-type", ct)
-                .body(Body::from(bytes))
-                .unwrap_or_else(|_| {
-                    Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::empty())
-                        .unwrap()
-                })
+        Ok(resp) if backend_type == "llamacpp" && resp.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+            llamacpp_unavailable(resp).await
         }
-        Err(_e) => Response::builder()
-            .status(StatusCode::BAD_GATEWAY)
-            .header("Content-Type", "application/json")
-            .body(Body::from(
-                serde_json::json!({ "error": "Backend unreachable" })
-                    .to_string(),
-            ))
-            .unwrap_or_else(|_| {
-                Response::builder()
-                    .status(StatusCode::BAD_GATEWAY)
-                    .body(Body::empty())
-                    .unwrap()
-            }),
+        Ok(resp) => buffered_response(resp).await,
+        Err(e) => upstream_error(backend_type, url, &e),
     }
 }
 
 async fn proxy_request(
     client: &reqwest::Client,
+    backend_type: &str,
     url: &str,
     api_key: Option<&str>,
     body: axum::body::Bytes,
+    in_flight: Option<(InFlightGuard, Permit)>,
 ) -> Response {
     let mut req = client
         .post(url)
@@ -545,36 +2235,377 @@ async fn proxy_request(
     }
 
     match req.body(body).send().await {
-        Ok(resp) => {
-            let status = resp.status();
-            let ct = resp
-                .headers()
-                .get("content-type")
-                .cloned()
-                .unwrap_or_else(|| "application/json".parse().unwrap());
-            let stream = resp.bytes_stream();
+        Ok(resp) if backend_type == "llamacpp" && resp.status() == reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+            llamacpp_unavailable(resp).await
+        }
+        Ok(resp) => streamed_response(resp, in_flight),
+        Err(e) => upstream_error(backend_type, url, &e),
+    }
+}
+
+/// Send a chat request to Ollama's native `/api/chat` and answer in OpenAI's
+/// format, streamed as SSE when the request asked for a stream.
+async fn ollama_native_chat(state: &AppState, backend: &ActiveBackend, json: &serde_json::Value) -> Response {
+    let native = match ollama_native::to_native_request(json) {
+        Ok(n) => n,
+        Err(e) => {
+            return openai_error(StatusCode::BAD_REQUEST, &e, "invalid_request_error", Some("unsupported_by_ollama_native"))
+        }
+    };
+    let url = format!("{}/api/chat", backend.url.trim_end_matches('/'));
+    let mut req = state.llama_cpp.client.post(&url).json(&native);
+    if let Some(key) = &backend.api_key {
+        req = req.bearer_auth(key);
+    }
+    let resp = match req.send().await {
+        Ok(r) => r,
+        Err(e) => return upstream_error("ollama", &url, &e),
+    };
+    let status = resp.status();
+    if !status.is_success() {
+        // Ollama's own errors are `{"error": "..."}`
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        let message = body["error"].as_str().unwrap_or("Ollama refused the request");
+        let status = StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        let error_type = if status.is_client_error() { "invalid_request_error" } else { "server_error" };
+        return openai_error(status, &format!("Ollama: {}", message), error_type, Some("ollama_error"));
+    }
+
+    let completion = ollama_native::Completion::start();
+    if native["stream"] != true {
+        return match resp.json::<serde_json::Value>().await {
+            Ok(reply) => Json(completion.to_openai_response(&reply)).into_response(),
+            Err(e) => upstream_error("ollama", &url, &e),
+        };
+    }
+    let include_usage = json["stream_options"]["include_usage"].as_bool().unwrap_or(false);
+    let translator = Arc::new(std::sync::Mutex::new(StreamTranslator::new(completion, include_usage)));
+    let tail = translator.clone();
+    let events = resp
+        .bytes_stream()
+        .map(move |chunk| chunk.map(|bytes| axum::body::Bytes::from(translator.lock().unwrap().push(&bytes))))
+        .chain(futures::stream::once(async move {
+            Ok::<_, reqwest::Error>(axum::body::Bytes::from(tail.lock().unwrap().finish()))
+        }));
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "text/event-stream")
+        .header("cache-control", "no-cache")
+        .body(Body::from_stream(events))
+        .unwrap_or_else(|_| {
             Response::builder()
-                .status(status)
-                .header("content-type", ct)
-                .body(Body::from_stream(stream))
-                .unwrap_or_else(|_| {
-                    Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::empty())
-                        .unwrap()
-                })
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap()
+        })
+}
+
+/// Pass an upstream response through as it arrives.
+fn streamed_response(resp: reqwest::Response, in_flight: Option<(InFlightGuard, Permit)>) -> Response {
+    let status = resp.status();
+    let ct = resp
+        .headers()
+        .get("content-type")
+        .cloned()
+        .unwrap_or_else(|| "application/json".parse().unwrap());
+    // The guard and admission permit ride along with the stream so a
+    // request counts as in flight until its last chunk has been forwarded.
+    let stream = resp.bytes_stream().map(move |chunk| {
+        let _ = &in_flight;
+        chunk
+    });
+    Response::builder()
+        .status(status)
+        .header("content-type", ct)
+        .body(Body::from_stream(stream))
+        .unwrap_or_else(|_| {
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn devices_sort_by_inventory() {
+        let device = |id: &str, cores: Option<i64>, driver: Option<&str>| {
+            serde_json::json!({ "id": id, "cpu_cores": cores, "gpu_driver": driver })
+        };
+        let ids = |devices: &[serde_json::Value]| -> Vec<String> {
+            devices.iter().map(|d| d["id"].as_str().unwrap().to_string()).collect()
+        };
+        let mut devices = vec![
+            device("a", None, Some("535.104.05")),
+            device("b", Some(8), None),
+            device("c", Some(32), Some("550.54.14")),
+            device("d", Some(8), Some("9.1")),
+        ];
+        sort_devices(&mut devices, DeviceSort::CpuCores);
+        assert_eq!(ids(&devices), ["c", "b", "d", "a"]);
+        sort_devices(&mut devices, DeviceSort::DriverVersion);
+        assert_eq!(ids(&devices), ["c", "a", "d", "b"]);
+    }
+
+    #[test]
+    fn split_models_get_one_public_name() {
+        let mut aliases = std::collections::HashMap::new();
+        assert_eq!(public_model_name("/models/qwen2-72b-00001-of-00003.gguf", &aliases), "qwen2-72b");
+        assert_eq!(public_model_name("/models/llama3-8b.gguf", &aliases), "llama3-8b");
+        aliases.insert("/models/qwen2-72b-00001-of-00003.gguf".to_string(), "qwen".to_string());
+        assert_eq!(public_model_name("/models/qwen2-72b-00001-of-00003.gguf", &aliases), "qwen");
+    }
+
+    #[test]
+    fn peer_models_are_merged_once() {
+        let mac = Device::new("mac".into(), "10.0.0.2".into(), None, "manual");
+        let mut list = serde_json::json!({ "object": "list", "data": [
+            { "id": "llama3-8b", "object": "model", "owned_by": "llamacpp" },
+        ]});
+        merge_peer_models(&mut list, &[(&mac, vec!["llama3-8b".into(), "qwen2-7b".into()])]);
+        let data = list["data"].as_array().unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0]["owned_by"], "llamacpp");
+        assert_eq!(data[1]["id"], "qwen2-7b");
+        assert_eq!(data[1]["owned_by"], "peer:mac");
+
+        let mut empty = serde_json::json!({ "object": "list" });
+        merge_peer_models(&mut empty, &[(&mac, vec!["qwen2-7b".into()])]);
+        assert_eq!(empty["data"][0]["id"], "qwen2-7b");
+    }
+
+    #[test]
+    fn chat_requests_need_model_and_messages() {
+        assert!(parse_chat_request(br#"{"model":"m","messages":[]}"#).is_ok());
+        assert!(parse_chat_request(b"not json").is_err());
+        assert!(parse_chat_request(br#"[1,2]"#).is_err());
+        assert!(parse_chat_request(br#"{"messages":[]}"#).is_err());
+        assert!(parse_chat_request(br#"{"model":7,"messages":[]}"#).is_err());
+        assert!(parse_chat_request(br#"{"model":" ","messages":[]}"#).is_err());
+        assert!(parse_chat_request(br#"{"model":"m","messages":"hi"}"#).is_err());
+    }
+
+    fn tcp(reachable: bool) -> DeepProbe {
+        DeepProbe::tcp(reachable, "")
+    }
+
+    #[test]
+    fn unreachable_devices_are_reported_and_dropped() {
+        let device = |name: &str, ip: &str| {
+            Device::new(name.to_string(), ip.to_string(), None, "manual")
+        };
+        let selected = vec![(device("awake", "10.0.0.2"), 4096), (device("asleep", "10.0.0.3"), 8192)];
+        let (probes, kept) = split_unreachable(selected, vec![tcp(true), tcp(false)]);
+
+        assert_eq!(probes.len(), 2);
+        assert!(probes[0].reachable);
+        assert!(!probes[1].reachable);
+        assert_eq!(probes[1].name, "asleep");
+        assert!(probes[1].address.starts_with("10.0.0.3:"));
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].0.name, "awake");
+        assert_eq!(kept[0].1, 4096);
+    }
+
+    #[test]
+    fn each_rpc_endpoint_is_probed() {
+        let mut two_gpus = Device::new("two-gpus".into(), "10.0.0.4".into(), None, "manual");
+        two_gpus.extra_rpc_ports = crate::db::models::RpcPorts(vec![8182]);
+        let asleep = Device::new("asleep".into(), "10.0.0.5".into(), None, "manual");
+        let selected = vec![(two_gpus, 16384), (asleep, 8192)];
+        let results = vec![tcp(false), DeepProbe::tcp(true, "in use by the running session"), tcp(false)];
+        let (probes, kept) = split_unreachable(selected, results);
+
+        let addresses: Vec<_> = probes.iter().map(|p| (p.address.as_str(), p.reachable)).collect();
+        assert_eq!(
+            addresses,
+            vec![("10.0.0.4:8181", false), ("10.0.0.4:8182", true), ("10.0.0.5:8181", false)]
+        );
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].0.name, "two-gpus");
+
+        // Each device records the endpoint that passed, else its first
+        let verdicts: Vec<_> = device_verdicts(&probes)
+            .into_iter()
+            .map(|(id, p)| (id == kept[0].0.id, p.ok, p.detail))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![(true, true, "in use by the running session".to_string()), (false, false, String::new())]
+        );
+    }
+
+    #[test]
+    fn stream_options_are_stripped_where_unsupported() {
+        let opts = serde_json::json!({ "include_usage": true });
+        let request = |stream: bool| {
+            serde_json::json!({ "model": "m", "messages": [], "stream": stream, "stream_options": opts })
+        };
+
+        let mut json = request(true);
+        sanitize_chat_request(&mut json, "openai");
+        assert_eq!(json["stream_options"], opts);
+
+        let mut json = request(false);
+        sanitize_chat_request(&mut json, "openai");
+        assert!(json.get("stream_options").is_none());
+
+        let mut json = request(true);
+        sanitize_chat_request(&mut json, "llamacpp");
+        assert!(json.get("stream_options").is_none());
+        assert_eq!(json["model"], "m");
+    }
+
+    #[tokio::test]
+    async fn backend_change_reaches_proxy_without_db_reads() {
+        let cache = SettingsCache::load(crate::settings::tests::test_pool().await)
+            .await
+            .unwrap();
+        let reads = cache.db_reads();
+
+        cache.set("backend_type", "openai").await.unwrap();
+        cache.set("backend_url", "http://10.0.0.7:8000/").await.unwrap();
+        cache.set("backend_api_key", "sk-test").await.unwrap();
+
+        for _ in 0..100 {
+            let backend = ActiveBackend::from_settings(&cache);
+            assert_eq!(backend.backend_type, "openai");
+            assert_eq!(backend.url, "http://10.0.0.7:8000/");
+            assert_eq!(backend.api_key.as_deref(), Some("sk-test"));
         }
-        Err(_e) => Response::builder()
-            .status(StatusCode::BAD_GATEWAY)
-            .header("Content-Type", "application/json")
-            .body(Body::from(
-                serde_json::json!({ "error": "Backend unreachable" }).to_string(),
-            ))
-            .unwrap_or_else(|_| {
-                Response::builder()
-                    .status(StatusCode::BAD_GATEWAY)
-                    .body(Body::empty())
-                    .unwrap()
+        assert_eq!(cache.db_reads(), reads);
+
+        cache.set("backend_api_key", "").await.unwrap();
+        assert_eq!(ActiveBackend::from_settings(&cache).api_key, None);
+        assert_eq!(cache.db_reads(), reads);
+    }
+
+    #[test]
+    fn images_are_found_and_measured() {
+        let png = format!("data:image/png;base64,{}", "A".repeat(4 * 1024 * 1024 / 3 * 2));
+        let json = serde_json::json!({
+            "model": "llava",
+            "messages": [
+                { "role": "system", "content": "Describe images." },
+                { "role": "user", "content": [
+                    { "type": "text", "text": "What is this?" },
+                    { "type": "image_url", "image_url": { "url": png } },
+                    { "type": "image_url", "image_url": "https://example.com/cat.jpg" },
+                ]},
+            ],
+        });
+        assert_eq!(image_urls(&json).len(), 2);
+        assert_eq!(data_url_bytes("https://example.com/cat.jpg"), None);
+        assert_eq!(data_url_bytes("data:image/png;base64,AAAA"), Some(3));
+        assert_eq!(oversized_image(&json, 2), None);
+        assert_eq!(oversized_image(&json, 1), Some(2));
+        // The payload survives the proxy's rewrite untouched
+        let mut forwarded = json.clone();
+        sanitize_chat_request(&mut forwarded, "llamacpp");
+        assert_eq!(image_urls(&forwarded), image_urls(&json));
+    }
+
+    #[test]
+    fn system_prompt_goes_first_unless_one_is_there() {
+        let mut json = serde_json::json!({ "messages": [{ "role": "user", "content": "hi" }] });
+        assert!(inject_system_prompt(&mut json, "Be brief."));
+        assert_eq!(json["messages"][0], serde_json::json!({ "role": "system", "content": "Be brief." }));
+        assert_eq!(json["messages"][1]["role"], "user");
+        assert!(!inject_system_prompt(&mut json, "Be verbose."));
+        assert_eq!(json["messages"].as_array().unwrap().len(), 2);
+
+        let mut headers = HeaderMap::new();
+        assert!(!skips_system_prompt(&headers));
+        headers.insert(NO_SYSTEM_PROMPT_HEADER, "1".parse().unwrap());
+        assert!(skips_system_prompt(&headers));
+        headers.insert(NO_SYSTEM_PROMPT_HEADER, "false".parse().unwrap());
+        assert!(!skips_system_prompt(&headers));
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_say_which_backend_failed_and_how() {
+        let client = reqwest::Client::builder().timeout(std::time::Duration::from_millis(300)).build().unwrap();
+        let failure = |resp: Response| async move {
+            let status = resp.status();
+            let retry = resp.headers().get("retry-after").cloned();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["error"]["type"], "server_error", "{}", json);
+            (status, json["error"]["code"].as_str().unwrap().to_string(), json["error"]["message"].to_string(), retry)
+        };
+        let post = |url: String, backend: &'static str| {
+            let client = client.clone();
+            async move { proxy_request(&client, backend, &url, None, "{}".into(), None).await }
+        };
+
+        // Nothing listening
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (status, code, message, _) = failure(post(format!("http://127.0.0.1:{}/v1/chat/completions", port), "ollama").await).await;
+        assert_eq!((status, code.as_str()), (StatusCode::BAD_GATEWAY, "backend_connection_refused"));
+        assert!(message.contains(&format!("Ollama backend at 127.0.0.1:{}", port)), "{}", message);
+
+        // A host name that can't resolve
+        let (status, code, _, _) = failure(post("http://backend.invalid/v1/chat/completions".into(), "vllm").await).await;
+        assert_eq!((status, code.as_str()), (StatusCode::BAD_GATEWAY, "backend_dns_error"));
+
+        // Plain HTTP where TLS was expected
+        let plain = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = plain.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = plain.accept().await {
+                use tokio::io::AsyncWriteExt;
+                let _ = socket.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        });
+        let (status, code, _, _) = failure(post(format!("https://127.0.0.1:{}/v1/chat/completions", port), "openai").await).await;
+        assert_eq!((status, code.as_str()), (StatusCode::BAD_GATEWAY, "backend_tls_error"));
+
+        // Accepts, then never answers
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = silent.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = silent.accept().await {
+                held.push(socket);
+            }
+        });
+        let (status, code, _, _) = failure(post(format!("http://127.0.0.1:{}/v1/chat/completions", port), "custom").await).await;
+        assert_eq!((status, code.as_str()), (StatusCode::GATEWAY_TIMEOUT, "backend_timeout"));
+
+        // llama-server still loading its model
+        let loading = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({ "error": { "message": "Loading model" } })))
             }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, loading).await });
+        let (status, code, _, retry) = failure(post(url.clone(), "llamacpp").await).await;
+        assert_eq!((status, code.as_str()), (StatusCode::SERVICE_UNAVAILABLE, "model_loading"));
+        assert_eq!(retry.unwrap(), MODEL_LOADING_RETRY_AFTER_SECS);
+        // Other backends' 503s are their own answer
+        let passed = axum::body::to_bytes(post(url, "vllm").await.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&passed).unwrap()["error"]["message"], "Loading model");
+
+        // A loaded llama-server with every slot taken is busy, not loading
+        let busy = axum::Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(|| async {
+                let error = serde_json::json!({ "error": { "code": 503, "message": "no slot available", "type": "unavailable_error" } });
+                (StatusCode::SERVICE_UNAVAILABLE, Json(error))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, busy).await });
+        let resp = post(url, "llamacpp").await;
+        assert_eq!((resp.status(), resp.headers().get("Retry-After")), (StatusCode::SERVICE_UNAVAILABLE, None));
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["error"]["message"], "no slot available");
     }
 }
@@ -1,54 +1,467 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
+use super::etag::json_with_etag;
+use super::openapi::{DeviceList, ErrorResponse, OkResponse};
 use crate::{
-    db::queries,
-    permissions::PermissionService,
+    db::{
+        models::{Allocation, Device, DeviceActivity, DeviceLabels, ProviderAllocation, Role, RpcPorts},
+        queries,
+    },
+    federation,
+    llama_cpp::{diagnosis::AGENT_API_PORT, health, RpcLaunch},
+    permissions::{
+        address::AddressError,
+        import::{self, ImportError, ImportRow},
+        restart::{self, RestartError},
+        ApproveError, DeviceDeleted, DeviceKind, DeviceStatus, PermissionService, RpcStatus,
+    },
     AppState,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct AddDeviceRequest {
     pub name: String,
     pub ip: String,
     pub mac: Option<String>,
+    /// Sent by agent install scripts: how they launch llama-rpc-server.
+    /// Replaces what an already-registered device had.
+    pub rpc_launch: Option<RpcLaunch>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct ApproveDeviceRequest {
     pub role_id: Option<String>,
+    /// The code the agent printed when it registered; needed for devices
+    /// that registered themselves
+    pub pairing_code: Option<String>,
+    /// Contact the agent right away (default). `false` approves without
+    /// checking, e.g. for a device that is switched off.
+    pub verify: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ApproveDeviceParams {
+    /// Same as `verify` in the body, which takes precedence
+    pub verify: Option<bool>,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct AllocateMemoryRequest {
     pub memory_mb: i64,
 }
 
-/// GET /api/devices
-pub async fn list_devices(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match queries::list_devices(&state.pool).await {
-        Ok(devices) => Json(serde_json::json!({ "devices": devices })).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({ "error": e.to_string() })),
-        )
-            .into_response(),
+/// `manual_override: true` needs both figures; `false` lets probes and
+/// heartbeats update them again, and ignores any given.
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct MemoryStatsRequest {
+    pub memory_total_mb: Option<i64>,
+    pub memory_free_mb: Option<i64>,
+    pub manual_override: bool,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct SetTagsRequest {
+    pub tags: Vec<String>,
+}
+
+/// Fields left out keep their current value.
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UpdateDeviceRequest {
+    pub name: Option<String>,
+    /// Port of the first llama-rpc-server
+    pub rpc_port: Option<i64>,
+    /// Ports of further llama-rpc-servers on the same host; replaces the list
+    pub extra_rpc_ports: Option<Vec<i64>>,
+    /// Forward chat requests for models only this device serves to its own
+    /// backend. Only approved devices whose backend answers on port 8080 qualify.
+    pub is_peer: Option<bool>,
+    /// Correct what the device runs; `unknown` has the next heartbeat probe it again
+    pub device_kind: Option<DeviceKind>,
+    /// Free-text notes; an empty string clears them
+    pub notes: Option<String>,
+    /// Key/value labels; replaces the set
+    pub labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListDevicesParams {
+    /// Only return devices carrying this tag
+    pub tag: Option<String>,
+    /// Only return devices whose name, hostname, IP, notes or labels contain
+    /// this text, ignoring case
+    pub search: Option<String>,
+    /// Only return devices changed after this revision (see `rev` in the response)
+    pub since_rev: Option<i64>,
+    /// Also return soft-deleted devices, after the others, with `deleted_at` set
+    pub include_deleted: Option<bool>,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteDeviceParams {
+    /// Delete the device for good instead of soft-deleting it
+    pub purge: Option<bool>,
+}
+
+/// `GET /api/devices/:id`: the device's own fields, with `detail` beside them.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DeviceWithDetail {
+    #[serde(flatten)]
+    pub device: Device,
+    pub detail: DeviceDetail,
+}
+
+/// What `allocated_memory_mb` is made of and what bounds it.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DeviceDetail {
+    /// Grants and inference reservations in force, newest first
+    pub allocations: Vec<Allocation>,
+    /// The same, summed per provider
+    pub by_provider: Vec<ProviderAllocation>,
+    /// The device's role and its limits
+    pub role: Option<Role>,
+    /// The role's `max_memory_mb` less `allocated_memory_mb`; `null` without a role
+    pub remaining_memory_mb: Option<i64>,
+    pub rpc: RpcFreshness,
+    /// Allocations granted and revoked and health score changes, newest first
+    pub recent_activity: Vec<DeviceActivity>,
+}
+
+/// How current the device's RPC and memory figures are.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RpcFreshness {
+    pub status: RpcStatus,
+    pub last_ready_at: Option<String>,
+    pub last_ready_secs_ago: Option<i64>,
+    /// Since the device was last heard from
+    pub last_seen_secs_ago: Option<i64>,
+    /// Since `memory_free_mb` was last read from the device; `null` when it
+    /// hasn't been since startup
+    pub memory_read_secs_ago: Option<u64>,
+}
+
+/// Entries of `recent_activity`.
+const RECENT_ACTIVITY: i64 = 20;
+
+/// Maximum number of tags a single device may carry.
+const MAX_TAGS_PER_DEVICE: usize = 16;
+/// Maximum number of RPC endpoints besides `rpc_port`.
+const MAX_EXTRA_RPC_PORTS: usize = 8;
+const MAX_DEVICE_NAME_LEN: usize = 64;
+const MAX_NOTES_LEN: usize = 2000;
+const MAX_LABELS_PER_DEVICE: usize = 16;
+const MAX_LABEL_VALUE_LEN: usize = 128;
+/// Largest memory figure accepted by hand: 16 TB.
+const MAX_MANUAL_MEMORY_MB: i64 = 16 * 1024 * 1024;
+
+/// Check hand-entered memory figures: `total ≥ free ≥ 0`, within reason.
+fn validate_memory_stats(total: Option<i64>, free: Option<i64>) -> Result<(i64, i64), String> {
+    let (Some(total), Some(free)) = (total, free) else {
+        return Err("memory_total_mb and memory_free_mb are both needed".to_string());
+    };
+    if free < 0 || free > total {
+        return Err("memory_free_mb must be between 0 and memory_total_mb".to_string());
+    }
+    if total > MAX_MANUAL_MEMORY_MB {
+        return Err(format!("memory_total_mb can be at most {}", MAX_MANUAL_MEMORY_MB));
+    }
+    Ok((total, free))
+}
+
+/// Normalise a tag list: trim, lowercase, dedupe, and reject anything that
+/// isn't a short `[a-z0-9_-]` identifier.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut out: Vec<String> = Vec::new();
+    for raw in tags {
+        let tag = raw.trim().to_ascii_lowercase();
+        if tag.is_empty() {
+            continue;
+        }
+        let valid = tag.len() <= 32
+            && tag
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!(
+                "Invalid tag '{}': use up to 32 letters, digits, '-' or '_'",
+                raw.trim()
+            ));
+        }
+        if !out.contains(&tag) {
+            out.push(tag);
+        }
+    }
+    if out.len() > MAX_TAGS_PER_DEVICE {
+        return Err(format!("Too many tags (max {})", MAX_TAGS_PER_DEVICE));
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// Trim notes and drop control characters other than newlines and tabs.
+/// Empty notes are none.
+pub fn normalize_notes(notes: &str) -> Result<Option<String>, String> {
+    let notes: String = notes
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect();
+    if notes.chars().count() > MAX_NOTES_LEN {
+        return Err(format!("notes must be at most {} characters", MAX_NOTES_LEN));
+    }
+    Ok((!notes.is_empty()).then_some(notes))
+}
+
+/// Normalise labels: keys follow the tag rules, values are trimmed single
+/// lines of at most 128 characters. A label with an empty value is dropped.
+pub fn normalize_labels(labels: &HashMap<String, String>) -> Result<DeviceLabels, String> {
+    let mut out = BTreeMap::new();
+    for (raw_key, raw_value) in labels {
+        let key = raw_key.trim().to_ascii_lowercase();
+        let valid = !key.is_empty()
+            && key.len() <= 32
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(format!(
+                "Invalid label '{}': use up to 32 letters, digits, '-' or '_'",
+                raw_key.trim()
+            ));
+        }
+        let value: String = raw_value.trim().chars().filter(|c| !c.is_control()).collect();
+        if value.chars().count() > MAX_LABEL_VALUE_LEN {
+            return Err(format!(
+                "Label '{}' must be at most {} characters",
+                key, MAX_LABEL_VALUE_LEN
+            ));
+        }
+        if !value.is_empty() && out.insert(key.clone(), value).is_some() {
+            return Err(format!("Label '{}' is given more than once", key));
+        }
+    }
+    if out.len() > MAX_LABELS_PER_DEVICE {
+        return Err(format!("Too many labels (max {})", MAX_LABELS_PER_DEVICE));
+    }
+    Ok(DeviceLabels(out))
+}
+
+/// Whether `device` matches a `?search=` term, already lowercased.
+fn matches_search(device: &Device, term: &str) -> bool {
+    let contains = |s: &str| s.to_lowercase().contains(term);
+    contains(&device.name)
+        || device.hostname.as_deref().is_some_and(contains)
+        || contains(&device.ip)
+        || device.notes.as_deref().is_some_and(contains)
+        || device.labels.0.iter().any(|(k, v)| contains(k) || contains(v))
+}
+
+/// Check a device's RPC ports: each in 1-65535, none repeated.
+pub fn validate_rpc_ports(rpc_port: i64, extra: &[i64]) -> Result<RpcPorts, String> {
+    if extra.len() > MAX_EXTRA_RPC_PORTS {
+        return Err(format!("Too many extra_rpc_ports (max {})", MAX_EXTRA_RPC_PORTS));
+    }
+    let mut seen: Vec<u16> = Vec::new();
+    for &port in std::iter::once(&rpc_port).chain(extra) {
+        let port = u16::try_from(port)
+            .ok()
+            .filter(|p| *p != 0)
+            .ok_or_else(|| format!("Invalid port {}: use 1-65535", port))?;
+        if seen.contains(&port) {
+            return Err(format!("Port {} is listed more than once", port));
+        }
+        seen.push(port);
     }
+    Ok(RpcPorts(seen.split_off(1)))
 }
 
-/// GET /api/devices/:id
+/// GET /api/devices  (optional ?tag=gpu-lab and ?search=basement filters, ?since_rev=N for changes only,
+/// ?include_deleted=true for soft-deleted devices too)
+///
+/// The envelope carries `rev`, the device revision the list reflects. With
+/// `since_rev`, only devices changed after it are returned, plus `removed`:
+/// IDs deleted since, or changed so they no longer match the filter.
+#[utoipa::path(
+    get,
+    path = "/api/devices",
+    tag = "devices",
+    params(ListDevicesParams),
+    responses(
+        (status = 200, description = "Devices and the revision they reflect", body = DeviceList),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_devices(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<ListDevicesParams>,
+) -> impl IntoResponse {
+    // Read the revision first: a change racing with the list is re-sent next time
+    let rev = match queries::device_revision(&state.pool).await {
+        Ok(r) => r,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    let tag = params
+        .tag
+        .as_deref()
+        .map(|t| t.trim().to_ascii_lowercase())
+        .filter(|t| !t.is_empty());
+    let mut result = match &tag {
+        Some(tag) => queries::list_devices_with_tag(&state.pool, tag).await,
+        None => queries::list_devices(&state.pool).await,
+    };
+    if params.include_deleted == Some(true) {
+        if let Ok(devices) = result.as_mut() {
+            match queries::list_deleted_devices(&state.pool).await {
+                Ok(deleted) => devices.extend(
+                    deleted
+                        .into_iter()
+                        .filter(|d| tag.as_ref().is_none_or(|t| d.tags.contains(t))),
+                ),
+                Err(e) => result = Err(e),
+            }
+        }
+    }
+    let search = params
+        .search
+        .as_deref()
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty());
+    let devices = match result {
+        Ok(mut d) => {
+            if let Some(term) = &search {
+                d.retain(|device| matches_search(device, term));
+            }
+            d
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    let body = match params.since_rev {
+        Some(since) => {
+            let visible: HashSet<&str> = devices.iter().map(|d| d.id.as_str()).collect();
+            let removed = match removed_since(&state.pool, since, |id, _| !visible.contains(id)).await {
+                // Soft-deleted devices stay when asked for
+                Ok(mut r) => {
+                    r.retain(|id| !visible.contains(id.as_str()));
+                    r
+                }
+                Err(e) => {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(serde_json::json!({ "error": e.to_string() })),
+                    )
+                        .into_response()
+                }
+            };
+            let changed: Vec<_> = devices.into_iter().filter(|d| d.rev > since).collect();
+            serde_json::json!({
+                "rev": rev,
+                "since_rev": since,
+                "devices": changed,
+                "removed": removed,
+            })
+        }
+        None => serde_json::json!({ "rev": rev, "devices": devices }),
+    };
+    json_with_etag(&headers, &body)
+}
+
+/// IDs a `since_rev` client must drop: devices deleted after `since`, and
+/// devices changed after it for which `hidden(id, status)` holds.
+pub async fn removed_since(
+    pool: &SqlitePool,
+    since: i64,
+    hidden: impl Fn(&str, DeviceStatus) -> bool,
+) -> anyhow::Result<Vec<String>> {
+    let mut removed = queries::list_deleted_devices_since(pool, since).await?;
+    for (id, status) in queries::list_devices_changed_since(pool, since).await? {
+        if hidden(&id, status) {
+            removed.push(id);
+        }
+    }
+    Ok(removed)
+}
+
+/// The device's allocations, role limits, probe freshness and recent activity.
+async fn device_detail(state: &AppState, device: &Device) -> anyhow::Result<DeviceDetail> {
+    let (allocations, by_provider, role, recent_activity) = tokio::try_join!(
+        queries::list_active_allocations_for_device(&state.pool, &device.id),
+        queries::device_allocations_by_provider(&state.pool, &device.id),
+        queries::get_device_role(&state.pool, &device.id),
+        queries::list_device_activity(&state.pool, &device.id, RECENT_ACTIVITY),
+    )?;
+    let secs_ago = |at: &str| {
+        chrono::DateTime::parse_from_rfc3339(at)
+            .ok()
+            .map(|t| (chrono::Utc::now() - t.with_timezone(&chrono::Utc)).num_seconds().max(0))
+    };
+    Ok(DeviceDetail {
+        remaining_memory_mb: role.as_ref().map(|r| (r.max_memory_mb - device.allocated_memory_mb).max(0)),
+        allocations,
+        by_provider,
+        role,
+        rpc: RpcFreshness {
+            status: device.rpc_status,
+            last_ready_at: device.last_rpc_ready_at.clone(),
+            last_ready_secs_ago: device.last_rpc_ready_at.as_deref().and_then(secs_ago),
+            last_seen_secs_ago: device.last_seen_secs_ago,
+            memory_read_secs_ago: state.remote_memory.age(&device.id).map(|d| d.as_secs()),
+        },
+        recent_activity,
+    })
+}
+
+/// GET /api/devices/:id  — the device, with its allocations and limits under `detail`
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    responses(
+        (status = 200, body = DeviceWithDetail),
+        (status = 404, body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn get_device(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     match queries::get_device(&state.pool, &id).await {
-        Ok(Some(device)) => Json(device).into_response(),
+        Ok(Some(device)) => match device_detail(&state, &device).await {
+            Ok(detail) => Json(DeviceWithDetail { device, detail }).into_response(),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response(),
+        },
         Ok(None) => (
             StatusCode::NOT_FOUND,
             Json(serde_json::json!({ "error": "Device not found" })),
@@ -63,18 +476,160 @@ pub async fn get_device(
 }
 
 /// POST /api/devices  (manual add)
+/// A new pending device gets a pairing code, returned only here as
+/// `pairing_code`; approving the device needs it.
+#[utoipa::path(
+    post,
+    path = "/api/devices",
+    tag = "devices",
+    request_body = AddDeviceRequest,
+    responses(
+        (status = 201, description = "The device, plus `pairing_code` when one was issued", body = Device),
+        (status = 400, body = ErrorResponse),
+        (status = 409, description = "A soft-deleted device holds the address; see `restore_deleted_devices`", body = ErrorResponse),
+        (status = 422, description = "`ip` is not a usable IP address or hostname", body = ErrorResponse),
+    )
+)]
 pub async fn add_device(
     State(state): State<Arc<AppState>>,
     Json(req): Json<AddDeviceRequest>,
 ) -> impl IntoResponse {
+    if let Some(Err(e)) = req.rpc_launch.as_ref().map(RpcLaunch::validate) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))).into_response();
+    }
     let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
-    match svc
+    let device = match svc
         .register_device(req.name, req.ip, req.mac, "manual")
         .await
     {
-        Ok(device) => (StatusCode::CREATED, Json(device)).into_response(),
+        Ok(device) => device,
+        Err(e) => {
+            if let Some(deleted) = e.downcast_ref::<DeviceDeleted>() {
+                return (
+                    StatusCode::CONFLICT,
+                    Json(serde_json::json!({ "error": e.to_string(), "device_id": deleted.device_id })),
+                )
+                    .into_response();
+            }
+            let status = if e.downcast_ref::<AddressError>().is_some() {
+                StatusCode::UNPROCESSABLE_ENTITY
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            return (
+                status,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    let internal_error = |e: anyhow::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response()
+    };
+    if let Some(launch) = &req.rpc_launch {
+        if let Err(e) = queries::update_device_rpc_launch(&state.pool, &device.id, launch).await {
+            return internal_error(e);
+        }
+    }
+    let pairing_code = match svc.issue_pairing_code(&device).await {
+        Ok(code) => code,
+        Err(e) => return internal_error(e),
+    };
+    let device = match queries::get_device(&state.pool, &device.id).await {
+        Ok(Some(device)) => device,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device was removed while registering" })),
+            )
+                .into_response()
+        }
+        Err(e) => return internal_error(e),
+    };
+
+    // Its services may not be up yet, so only a backend found now counts
+    federation::detect_kind(state.pool.clone(), state.llama_cpp.client.clone(), device.clone(), false);
+
+    let mut body = serde_json::to_value(&device).unwrap_or_default();
+    if let Some(code) = pairing_code {
+        body["pairing_code"] = serde_json::Value::String(code);
+    }
+    (StatusCode::CREATED, Json(body)).into_response()
+}
+
+/// POST /api/devices/import  — register devices before their agents come
+/// online. Takes a JSON array, or `text/csv` with the header
+/// `name,ip,mac,role_id,auto_approve,notes,labels`, and answers with a
+/// result per row.
+#[utoipa::path(
+    post,
+    path = "/api/devices/import",
+    tag = "devices",
+    request_body(
+        content = Vec<ImportRow>,
+        description = "A JSON array, or `text/csv` with the header `name,ip,mac,role_id,auto_approve,notes,labels`",
+    ),
+    responses(
+        (status = 200, description = "Per-row results; rows fail on their own", body = crate::permissions::import::ImportReport),
+        (status = 400, description = "Unreadable, empty or too large, or an address given twice", body = ErrorResponse),
+    )
+)]
+pub async fn import_devices(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let csv = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|t| t.trim_start().starts_with("text/csv"));
+    let rows = if csv {
+        std::str::from_utf8(&body)
+            .map_err(|_| "CSV must be UTF-8".to_string())
+            .and_then(import::parse_csv)
+    } else {
+        serde_json::from_slice::<Vec<ImportRow>>(&body)
+            .map_err(|e| format!("Expected a JSON array of devices: {}", e))
+    };
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))).into_response(),
+    };
+
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    match import::import(&svc, rows, if csv { "csv" } else { "json" }).await {
+        Ok(report) => Json(report).into_response(),
+        Err(ImportError::Db(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e.to_string() }))).into_response(),
+    }
+}
+
+/// Imports listed by GET /api/devices/imports.
+const IMPORT_HISTORY_LIMIT: i64 = 50;
+
+/// GET /api/devices/imports  — recent bulk imports and their counts
+#[utoipa::path(
+    get,
+    path = "/api/devices/imports",
+    tag = "devices",
+    responses(
+        (status = 200, description = "`{imports: [DeviceImport]}`, newest first", body = serde_json::Value),
+    )
+)]
+pub async fn list_device_imports(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match queries::list_device_imports(&state.pool, IMPORT_HISTORY_LIMIT).await {
+        Ok(imports) => Json(serde_json::json!({ "imports": imports })).into_response(),
         Err(e) => (
-            StatusCode::BAD_REQUEST,
+            StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": e.to_string() })),
         )
             .into_response(),
@@ -82,14 +637,82 @@ pub async fn add_device(
 }
 
 /// POST /api/devices/:id/approve
+/// A device that registered itself needs the `pairing_code` its agent
+/// printed; after 5 wrong codes it is locked (423) until the agent checks
+/// in with the enrollment token. Unless `verify` is false, the agent's RPC
+/// port is probed and its memory read, and the result returned as
+/// `verification`.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/approve",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID"), ApproveDeviceParams),
+    request_body = ApproveDeviceRequest,
+    responses(
+        (status = 200, description = "The approved device plus `agent_secret`, returned only here, and `verification` (a `Verification`) unless `verify` was false", body = Device),
+        (status = 400, description = "Pairing code missing, expired or wrong", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 423, description = "Locked after too many wrong pairing codes", body = ErrorResponse),
+    )
+)]
 pub async fn approve_device(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
+    Query(params): Query<ApproveDeviceParams>,
     Json(req): Json<ApproveDeviceRequest>,
 ) -> impl IntoResponse {
     let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
-    match svc.approve_device(&id, req.role_id.as_deref()).await {
-        Ok(device) => Json(device).into_response(),
+    let verify = req.verify.or(params.verify).unwrap_or(true);
+    match svc
+        .approve_device(
+            &id,
+            req.role_id.as_deref(),
+            req.pairing_code.as_deref(),
+            verify.then_some(state.llama_cpp.as_ref()),
+        )
+        .await
+    {
+        Ok(approval) => {
+            // Only returned here; the agent uses it to authenticate heartbeats
+            let mut body = serde_json::to_value(&approval.device).unwrap_or_default();
+            body["agent_secret"] = serde_json::Value::String(approval.agent_secret);
+            if let Some(verification) = approval.verification {
+                body["verification"] = serde_json::to_value(verification).unwrap_or_default();
+            }
+            Json(body).into_response()
+        }
+        Err(e) => {
+            let status = match e {
+                ApproveError::NotFound => StatusCode::NOT_FOUND,
+                ApproveError::PairingLocked => StatusCode::LOCKED,
+                ApproveError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                ApproveError::PairingCodeRequired
+                | ApproveError::PairingCodeExpired
+                | ApproveError::WrongPairingCode { .. } => StatusCode::BAD_REQUEST,
+            };
+            (status, Json(serde_json::json!({ "error": e.to_string() }))).into_response()
+        }
+    }
+}
+
+/// POST /api/devices/:id/deny
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/deny",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+pub async fn deny_device(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    match svc.deny_device(&id).await {
+        Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
         Err(e) => (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": e.to_string() })),
@@ -98,13 +721,23 @@ pub async fn approve_device(
     }
 }
 
-/// POST /api/devices/:id/deny
-pub async fn deny_device(
+/// POST /api/devices/:id/suspend
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/suspend",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+pub async fn suspend_device(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
-    match svc.deny_device(&id).await {
+    match svc.suspend_device(&id).await {
         Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
         Err(e) => (
             StatusCode::BAD_REQUEST,
@@ -115,6 +748,17 @@ pub async fn deny_device(
 }
 
 /// PATCH /api/devices/:id/memory
+#[utoipa::path(
+    patch,
+    path = "/api/devices/{id}/memory",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    request_body = AllocateMemoryRequest,
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 400, body = ErrorResponse),
+    )
+)]
 pub async fn allocate_memory(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
@@ -131,13 +775,501 @@ pub async fn allocate_memory(
     }
 }
 
-/// DELETE /api/devices/:id
+/// PATCH /api/devices/:id/memory-stats  — set the device's memory by hand
+/// when it can't be asked, or let probes and heartbeats take over again
+#[utoipa::path(
+    patch,
+    path = "/api/devices/{id}/memory-stats",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    request_body = MemoryStatsRequest,
+    responses(
+        (status = 200, body = Device),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn set_memory_stats(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<MemoryStatsRequest>,
+) -> impl IntoResponse {
+    let memory = if req.manual_override {
+        match validate_memory_stats(req.memory_total_mb, req.memory_free_mb) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": e })),
+                )
+                    .into_response()
+            }
+        }
+    } else {
+        None
+    };
+    match queries::set_device_memory_override(&state.pool, &id, memory).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    }
+    match queries::get_device(&state.pool, &id).await {
+        Ok(Some(device)) => {
+            tracing::info!(
+                "Memory of {} {} ({} / {} MB)",
+                device.name,
+                if device.memory_manual_override { "set by hand" } else { "updated automatically again" },
+                device.memory_free_mb,
+                device.memory_total_mb
+            );
+            Json(device).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Device not found" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// PATCH /api/devices/:id  — rename the device, change its RPC ports, notes or labels, or mark it a peer host
+#[utoipa::path(
+    patch,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    request_body = UpdateDeviceRequest,
+    responses(
+        (status = 200, body = Device),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn update_device(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<UpdateDeviceRequest>,
+) -> impl IntoResponse {
+    let device = match queries::get_device(&state.pool, &id).await {
+        Ok(Some(d)) => d,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    let name = req.name.as_deref().map(str::trim).unwrap_or(&device.name).to_string();
+    if name.is_empty() || name.chars().count() > MAX_DEVICE_NAME_LEN {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("name must be 1-{} characters", MAX_DEVICE_NAME_LEN)
+            })),
+        )
+            .into_response();
+    }
+    let rpc_port = req.rpc_port.unwrap_or(device.rpc_port);
+    let extra: Vec<i64> = match req.extra_rpc_ports {
+        Some(ports) => ports,
+        // Keep the current list, minus a port that just became rpc_port
+        None => device
+            .extra_rpc_ports
+            .0
+            .iter()
+            .map(|&p| p as i64)
+            .filter(|&p| p != rpc_port)
+            .collect(),
+    };
+    let extra_rpc_ports = match validate_rpc_ports(rpc_port, &extra) {
+        Ok(p) => p,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response()
+        }
+    };
+
+    let notes = match req.notes.as_deref().map(normalize_notes) {
+        Some(Ok(notes)) => notes,
+        None => device.notes.clone(),
+        Some(Err(e)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response()
+        }
+    };
+    let labels = match req.labels.as_ref().map(normalize_labels) {
+        Some(Ok(labels)) => labels,
+        None => device.labels.clone(),
+        Some(Err(e)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response()
+        }
+    };
+
+    let mut device_kind = req.device_kind.unwrap_or(device.device_kind);
+    // An agent-only box has no backend to forward to
+    let is_peer = req.is_peer.unwrap_or(device.is_peer && device_kind != DeviceKind::Agent);
+    let newly_peer = req.is_peer == Some(true) && !(device.is_peer && device_kind == DeviceKind::Peer);
+    if newly_peer {
+        let refusal = if device_kind == DeviceKind::Agent {
+            Some("An agent-only device can't be a peer host".to_string())
+        } else if device.status != DeviceStatus::Approved {
+            Some("Only approved devices can be peer hosts".to_string())
+        } else if !federation::runs_backend(&state.llama_cpp.client, &device.ip).await {
+            Some(format!(
+                "{} has no SharedLLM backend answering on port {}; start SharedLLM there first",
+                device.ip, AGENT_API_PORT
+            ))
+        } else {
+            None
+        };
+        if let Some(e) = refusal {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response();
+        }
+        device_kind = DeviceKind::Peer;
+    }
+
+    if let Err(e) = queries::update_device_settings(
+        &state.pool,
+        &id,
+        &name,
+        rpc_port,
+        &extra_rpc_ports,
+        is_peer,
+        device_kind,
+    )
+    .await
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response();
+    }
+    if let Err(e) = queries::update_device_notes(&state.pool, &id, notes.as_deref(), &labels).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response();
+    }
+
+    if is_peer {
+        state.peers.refresh(state.llama_cpp.client.clone(), device);
+    } else {
+        state.peers.forget(&id);
+    }
+
+    match queries::get_device(&state.pool, &id).await {
+        Ok(Some(device)) => Json(device).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Device not found" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// PATCH /api/devices/:id/tags  — replace the device's tag set
+#[utoipa::path(
+    patch,
+    path = "/api/devices/{id}/tags",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    request_body = SetTagsRequest,
+    responses(
+        (status = 200, body = Device),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn set_device_tags(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<SetTagsRequest>,
+) -> impl IntoResponse {
+    let tags = match normalize_tags(&req.tags) {
+        Ok(t) => t,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response()
+        }
+    };
+
+    match queries::get_device(&state.pool, &id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    }
+
+    if let Err(e) = queries::set_device_tags(&state.pool, &id, &tags).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response();
+    }
+
+    match queries::get_device(&state.pool, &id).await {
+        Ok(Some(device)) => Json(device).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Device not found" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// Health score changes shown by GET /api/devices/:id/health.
+const HEALTH_HISTORY_LIMIT: i64 = 50;
+
+/// GET /api/devices/:id/health  — score, `unreliable` flag and recent changes
+#[utoipa::path(
+    get,
+    path = "/api/devices/{id}/health",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    responses(
+        (status = 200, description = "`{device_id, health_score, unreliable, history: [DeviceHealthEvent]}`, newest change first", body = serde_json::Value),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn device_health(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let device = match queries::get_device(&state.pool, &id).await {
+        Ok(Some(d)) => d,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    match queries::list_device_health_events(&state.pool, &id, HEALTH_HISTORY_LIMIT).await {
+        Ok(history) => Json(serde_json::json!({
+            "device_id": device.id,
+            "health_score": device.health_score,
+            "unreliable": health::is_unreliable(&device),
+            "history": history,
+        }))
+        .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// POST /api/devices/:id/reset-health  — trust the device again
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/reset-health",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    responses(
+        (status = 200, body = Device),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn reset_device_health(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match health::reset(&state.pool, &id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    }
+    tracing::info!("Health of device {} reset", id);
+
+    match queries::get_device(&state.pool, &id).await {
+        Ok(Some(device)) => Json(device).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Device not found" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// POST /api/devices/:id/rpc/restart  — restart the device's llama-rpc-server
+/// and wait for its port to answer again. Peers are restarted through their
+/// own API; agents installed as a service on their next heartbeat.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/rpc/restart",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    responses(
+        (status = 200, body = crate::permissions::restart::RpcRestarted),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Not approved, or its agent can't restart the server", body = ErrorResponse),
+        (status = 502, description = "The peer refused or couldn't be reached", body = ErrorResponse),
+        (status = 504, description = "No heartbeat took the restart, or the port never answered", body = ErrorResponse),
+    )
+)]
+pub async fn restart_device_rpc(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let device = match queries::get_device(&state.pool, &id).await {
+        Ok(Some(d)) => d,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    match restart::restart(&state, &device, restart::RESTART_TIMEOUT).await {
+        Ok(restarted) => Json(restarted).into_response(),
+        Err(e) => {
+            let status = match e {
+                RestartError::NotApproved(_) | RestartError::Unsupported => StatusCode::CONFLICT,
+                RestartError::Failed(_) => StatusCode::BAD_GATEWAY,
+                RestartError::TimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+            };
+            (status, Json(serde_json::json!({ "error": e.to_string() }))).into_response()
+        }
+    }
+}
+
+/// DELETE /api/devices/:id  (?purge=true to delete for good)
+///
+/// Soft-deletes by default: the device keeps its status, role and tags for
+/// `POST /api/devices/:id/restore`, and only its memory grants are revoked.
+/// `purge` also removes devices already soft-deleted.
+#[utoipa::path(
+    delete,
+    path = "/api/devices/{id}",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID"), DeleteDeviceParams),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn delete_device(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
+    Query(params): Query<DeleteDeviceParams>,
 ) -> impl IntoResponse {
-    match queries::delete_device(&state.pool, &id).await {
-        Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let deleted = if params.purge == Some(true) {
+        svc.purge_device(&id).await
+    } else {
+        svc.delete_device(&id).await
+    };
+    match deleted {
+        Ok(true) => Json(serde_json::json!({ "ok": true })).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Device not found" })),
+        )
+            .into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": e.to_string() })),
@@ -145,3 +1277,106 @@ pub async fn delete_device(
             .into_response(),
     }
 }
+
+/// POST /api/devices/:id/restore  — undo a soft delete
+///
+/// The device comes back with the status, role, tags and memory grants it
+/// had when it was deleted.
+#[utoipa::path(
+    post,
+    path = "/api/devices/{id}/restore",
+    tag = "devices",
+    params(("id" = String, Path, description = "Device ID")),
+    responses(
+        (status = 200, body = Device),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "The device isn't deleted", body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn restore_device(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let internal_error = |e: anyhow::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response()
+    };
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    match svc.restore_device(&id).await {
+        Ok(Some(device)) => Json(device).into_response(),
+        Ok(None) => match queries::get_device(&state.pool, &id).await {
+            Ok(Some(_)) => (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({ "error": "Device is not deleted" })),
+            )
+                .into_response(),
+            Ok(None) => (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Device not found" })),
+            )
+                .into_response(),
+            Err(e) => internal_error(e),
+        },
+        Err(e) => internal_error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_ports_are_validated() {
+        assert_eq!(validate_rpc_ports(8181, &[8182, 8183]), Ok(RpcPorts(vec![8182, 8183])));
+        assert_eq!(validate_rpc_ports(8181, &[]), Ok(RpcPorts(vec![])));
+        assert!(validate_rpc_ports(0, &[]).is_err());
+        assert!(validate_rpc_ports(8181, &[65536]).is_err());
+        assert!(validate_rpc_ports(8181, &[-1]).is_err());
+        assert!(validate_rpc_ports(8181, &[8182, 8181]).is_err());
+        assert!(validate_rpc_ports(8181, &[8182, 8182]).is_err());
+        assert!(validate_rpc_ports(8181, &[1; 9]).is_err());
+    }
+
+    #[test]
+    fn manual_memory_is_validated() {
+        assert_eq!(validate_memory_stats(Some(16384), Some(12000)), Ok((16384, 12000)));
+        assert_eq!(validate_memory_stats(Some(0), Some(0)), Ok((0, 0)));
+        assert!(validate_memory_stats(Some(16384), None).is_err());
+        assert!(validate_memory_stats(Some(8192), Some(8193)).is_err());
+        assert!(validate_memory_stats(Some(8192), Some(-1)).is_err());
+        assert!(validate_memory_stats(Some(MAX_MANUAL_MEMORY_MB + 1), Some(0)).is_err());
+    }
+
+    #[test]
+    fn device_lists_every_rpc_endpoint() {
+        let mut device = Device::new("box".into(), "10.0.0.7".into(), None, "manual");
+        device.extra_rpc_ports = RpcPorts(vec![8182]);
+        assert_eq!(device.rpc_addresses(), vec!["10.0.0.7:8181", "10.0.0.7:8182"]);
+        assert_eq!(RpcPorts::from("not json".to_string()), RpcPorts::default());
+    }
+
+    #[test]
+    fn notes_and_labels_are_normalized() {
+        assert_eq!(normalize_notes("  flaky PSU\u{7}\n"), Ok(Some("flaky PSU".to_string())));
+        assert_eq!(normalize_notes("one\ntwo"), Ok(Some("one\ntwo".to_string())));
+        assert_eq!(normalize_notes("   "), Ok(None));
+        assert!(normalize_notes(&"x".repeat(MAX_NOTES_LEN + 1)).is_err());
+
+        let labels = |pairs: &[(&str, &str)]| {
+            normalize_labels(&pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect())
+        };
+        let normalized = labels(&[(" Location ", " basement\r\n"), ("psu", "")]).unwrap();
+        assert_eq!(
+            normalized.0.into_iter().collect::<Vec<_>>(),
+            vec![("location".to_string(), "basement".to_string())]
+        );
+        assert!(labels(&[("rack slot", "3")]).is_err());
+        assert!(labels(&[("rack", &"x".repeat(MAX_LABEL_VALUE_LEN + 1))]).is_err());
+        assert!(labels(&[("rack", "1"), ("RACK", "2")]).is_err());
+        assert_eq!(DeviceLabels::from("[]".to_string()), DeviceLabels::default());
+    }
+}
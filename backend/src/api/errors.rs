@@ -0,0 +1,18 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// GET /api/errors/recent
+/// The last 50 background problems (`error` events), newest first.
+#[utoipa::path(
+    get,
+    path = "/api/errors/recent",
+    tag = "logs",
+    responses(
+        (status = 200, description = "`{errors: [ErrorEntry]}`", body = serde_json::Value),
+    )
+)]
+pub async fn recent_errors(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(serde_json::json!({ "errors": state.errors.recent() }))
+}
@@ -0,0 +1,66 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+/// Strong ETag for a serialized body: a quoted, truncated SHA-256.
+pub fn etag_for(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(body)[..16]))
+}
+
+/// Whether an `If-None-Match` header names `etag` (or is `*`).
+/// Weak validators match too, as GET revalidation only needs weak comparison.
+pub fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+/// Serialize `body` as JSON with an `ETag`, or answer `304 Not Modified` when
+/// the client already holds this exact payload.
+pub fn json_with_etag(headers: &HeaderMap, body: &serde_json::Value) -> Response {
+    let bytes = serde_json::to_vec(body).unwrap_or_default();
+    let etag = etag_for(&bytes);
+    // Hex digest in quotes, always a valid header value
+    let etag_value = HeaderValue::from_str(&etag).expect("hex ETag is a valid header value");
+
+    if if_none_match(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag_value)]).into_response();
+    }
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/json")),
+            (header::ETAG, etag_value),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn if_none_match_accepts_lists_weak_tags_and_wildcard() {
+        let etag = etag_for(b"{}");
+        let headers = |v: &str| {
+            let mut h = HeaderMap::new();
+            h.insert(header::IF_NONE_MATCH, v.parse().unwrap());
+            h
+        };
+
+        assert!(if_none_match(&headers(&etag), &etag));
+        assert!(if_none_match(&headers(&format!("\"x\", W/{}", etag)), &etag));
+        assert!(if_none_match(&headers("*"), &etag));
+        assert!(!if_none_match(&headers("\"x\""), &etag));
+        assert!(!if_none_match(&HeaderMap::new(), &etag));
+        assert_ne!(etag, etag_for(b"[]"));
+    }
+}
@@ -1,42 +1,193 @@
-use axum::{extract::State, response::IntoResponse, Json};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
 use std::sync::Arc;
 
-use crate::{memory::aggregate_snapshot_async, AppState};
+use super::openapi::{ErrorResponse, GpuStats};
+use crate::{
+    llama_cpp::reservations::Reserved,
+    memory::{
+        hotplug::{self, ProviderInfo},
+        GpuKind, MemorySnapshot,
+    },
+    permissions::DeviceStatus,
+    AppState,
+};
 
-/// GET /api/gpu — current stats from all detected memory providers
+const MB: u64 = 1024 * 1024;
+
+/// `POST /api/gpu/redetect`
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RedetectResponse {
+    /// Every provider now in use
+    pub providers: Vec<ProviderInfo>,
+    pub added: Vec<ProviderInfo>,
+    pub removed: Vec<ProviderInfo>,
+}
+
+/// GET /api/gpu — latest cached stats from all detected memory providers.
+/// `stale_secs` is the age of the oldest snapshot; POST /api/gpu/refresh re-samples.
+#[utoipa::path(
+    get,
+    path = "/api/gpu",
+    tag = "gpu",
+    responses(
+        (status = 200, body = GpuStats),
+    )
+)]
 pub async fn get_gpu_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let mut snapshots = aggregate_snapshot_async(&state.providers).await;
+    let (mut snapshots, age) = state.memory.cached();
+    let stale_secs = if snapshots.is_empty() {
+        // Nothing sampled yet (just started) — sample once rather than report nothing
+        snapshots = state.memory.refresh_all().await;
+        0
+    } else {
+        age.as_secs()
+    };
+    fill_allocations(&state, &mut snapshots).await;
+
+    Json(serde_json::json!({
+        "providers": snapshots,
+        "count": snapshots.len(),
+        "stale_secs": stale_secs,
+    }))
+}
+
+/// POST /api/gpu/refresh — re-sample every provider now
+#[utoipa::path(
+    post,
+    path = "/api/gpu/refresh",
+    tag = "gpu",
+    responses(
+        (status = 200, body = GpuStats),
+    )
+)]
+pub async fn refresh_gpu_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut snapshots = state.memory.refresh_all().await;
+    if let Some(event) = state.memory.keyframe() {
+        let _ = state.event_tx.send(event);
+    }
+    fill_allocations(&state, &mut snapshots).await;
+
+    Json(serde_json::json!({
+        "providers": snapshots,
+        "count": snapshots.len(),
+        "stale_secs": 0,
+    }))
+}
+
+/// POST /api/gpu/redetect — run provider detection again, e.g. after
+/// plugging in an eGPU. A change is also broadcast as `providers_changed`.
+#[utoipa::path(
+    post,
+    path = "/api/gpu/redetect",
+    tag = "gpu",
+    responses(
+        (status = 200, body = RedetectResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn redetect_providers(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match hotplug::redetect(&state.providers, &state.memory, &state.event_tx).await {
+        Ok(change) => Json(RedetectResponse {
+            providers: state.providers.load().iter().map(|p| ProviderInfo::of(p.as_ref())).collect(),
+            added: change.added,
+            removed: change.removed,
+        })
+        .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
 
-    // Fill in allocated_mb from DB — distribute allocations across providers
-    // proportionally by total_mb, with GPU providers prioritised over system RAM.
+/// Fill in allocated_mb from DB — distribute device allocations across providers
+/// proportionally by total_mb, then add what running inference sessions and
+/// Ollama's loaded models hold on each provider.
+async fn fill_allocations(state: &AppState, snapshots: &mut [MemorySnapshot]) {
     if let Ok(devices) = crate::db::queries::list_devices(&state.pool).await {
         let total_allocated: u64 = devices
             .iter()
-            .filter(|d| d.status == "approved")
+            .filter(|d| d.status == DeviceStatus::Approved)
             .map(|d| d.allocated_memory_mb as u64)
             .sum();
 
         if total_allocated > 0 && !snapshots.is_empty() {
             let grand_total: u64 = snapshots.iter().map(|s| s.total_mb).sum();
-            if grand_total > 0 {
-                let mut remaining = total_allocated;
-                let last_idx = snapshots.len() - 1;
-                for (i, snap) in snapshots.iter_mut().enumerate() {
-                    let share = if i == last_idx {
-                        // Give all remaining to the last provider to avoid rounding loss
-                        remaining
-                    } else {
-                        (total_allocated * snap.total_mb / grand_total).min(snap.total_mb)
-                    };
-                    snap.allocated_mb = share.min(snap.total_mb);
-                    remaining = remaining.saturating_sub(share);
-                }
+            let mut remaining = total_allocated;
+            let last_idx = snapshots.len() - 1;
+            for (i, snap) in snapshots.iter_mut().enumerate() {
+                let share = if i == last_idx {
+                    // Give all remaining to the last provider to avoid rounding loss
+                    remaining
+                } else {
+                    (total_allocated * snap.total_mb)
+                        .checked_div(grand_total)
+                        .unwrap_or(0)
+                        .min(snap.total_mb)
+                };
+                snap.allocated_mb = share.min(snap.total_mb);
+                remaining = remaining.saturating_sub(share);
             }
         }
     }
 
-    Json(serde_json::json!({
-        "providers": snapshots,
-        "count": snapshots.len(),
-    }))
+    let reserved = Reserved::load(&state.pool).await;
+    for snap in snapshots.iter_mut() {
+        if let Some(mb) = reserved.local.get(&snap.provider_id) {
+            snap.allocated_mb = (snap.allocated_mb + mb).min(snap.total_mb);
+        }
+    }
+
+    let resident = state.ollama.resident();
+    let vram: u64 = resident.iter().map(|m| m.size_vram).sum();
+    let total: u64 = resident.iter().map(|m| m.size).sum();
+    charge_ollama(snapshots, vram / MB, total.saturating_sub(vram) / MB);
+}
+
+/// Charge what Ollama's loaded models hold as a pseudo consumer: the VRAM
+/// part to the first GPU, the rest to system RAM, either falling back to
+/// the other when the host has only one kind (unified memory).
+fn charge_ollama(snapshots: &mut [MemorySnapshot], vram_mb: u64, ram_mb: u64) {
+    let gpu = snapshots.iter().position(|s| s.kind != GpuKind::SystemRam);
+    let ram = snapshots.iter().position(|s| s.kind == GpuKind::SystemRam);
+    for (target, mb) in [(gpu.or(ram), vram_mb), (ram.or(gpu), ram_mb)] {
+        if let Some(snap) = target.map(|i| &mut snapshots[i]) {
+            snap.ollama_mb += mb;
+            snap.allocated_mb = (snap.allocated_mb + mb).min(snap.total_mb);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(kind: GpuKind, total_mb: u64) -> MemorySnapshot {
+        MemorySnapshot {
+            provider_id: format!("{:?}", kind),
+            name: "test".into(),
+            kind,
+            total_mb,
+            used_mb: 0,
+            free_mb: total_mb,
+            allocated_mb: 0,
+            ollama_mb: 0,
+            usable_free_mb: total_mb,
+            stale: false,
+        }
+    }
+
+    #[test]
+    fn ollama_is_charged_where_its_models_sit() {
+        let mut split = [snapshot(GpuKind::SystemRam, 32_000), snapshot(GpuKind::Nvidia, 8_000)];
+        charge_ollama(&mut split, 4_000, 1_000);
+        assert_eq!((split[0].ollama_mb, split[0].allocated_mb), (1_000, 1_000));
+        assert_eq!((split[1].ollama_mb, split[1].allocated_mb), (4_000, 4_000));
+
+        let mut unified = [snapshot(GpuKind::AppleSilicon, 16_000)];
+        charge_ollama(&mut unified, 4_000, 1_000);
+        assert_eq!(unified[0].ollama_mb, 5_000);
+    }
 }
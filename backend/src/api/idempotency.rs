@@ -0,0 +1,147 @@
+//! Idempotency keys for `POST /api/cluster/inference/start`. A double-click
+//! sends the same start twice; when both carry the same `Idempotency-Key`
+//! header (or `idempotency_key` field), the second gets the first's response,
+//! marked `Idempotent-Replayed: true`, instead of starting again. A start
+//! still running is waited for. Finished ones are kept for [`TTL`], in memory.
+
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tokio::time::Instant;
+
+pub const KEY_HEADER: &str = "idempotency-key";
+/// Set on a response replayed for a key seen before
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+/// How long a finished start is replayed for its key
+pub const TTL: Duration = Duration::from_secs(10 * 60);
+const MAX_KEY_LEN: usize = 255;
+
+/// The key a request carries: the header, else `field`. Blank is none.
+pub fn request_key(headers: &HeaderMap, field: Option<&str>) -> Result<Option<String>, String> {
+    let key = match headers.get(KEY_HEADER) {
+        Some(value) => Some(value.to_str().map_err(|_| "Idempotency-Key must be ASCII".to_string())?),
+        None => field,
+    };
+    match key.map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(key) if key.len() > MAX_KEY_LEN => {
+            Err(format!("Idempotency keys are at most {} characters", MAX_KEY_LEN))
+        }
+        Some(key) => Ok(Some(key.to_string())),
+    }
+}
+
+/// A finished response, kept for replay.
+struct Stored {
+    status: StatusCode,
+    body: Bytes,
+    at: Instant,
+}
+
+impl Stored {
+    fn response(&self, replayed: bool) -> Response {
+        let content_type = [(header::CONTENT_TYPE, HeaderValue::from_static("application/json"))];
+        let mut response = (self.status, content_type, self.body.clone()).into_response();
+        if replayed {
+            response.headers_mut().insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+        }
+        response
+    }
+}
+
+/// Responses of recent starts by key.
+#[derive(Default)]
+pub struct IdempotencyKeys {
+    entries: Mutex<HashMap<String, Arc<OnceCell<Stored>>>>,
+}
+
+impl IdempotencyKeys {
+    /// The cell for `key`, dropping responses older than [`TTL`] first.
+    fn entry(&self, key: &str, now: Instant) -> Arc<OnceCell<Stored>> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, cell| cell.get().is_none_or(|s| now.duration_since(s.at) < TTL));
+        entries.entry(key.to_string()).or_default().clone()
+    }
+
+    /// Run `start` for `key` unless it already ran, and answer with its
+    /// response. An `Err` response is returned but not kept, so the key can
+    /// be used again: a start refused because another was under way hasn't
+    /// happened.
+    pub async fn run<F>(&self, key: &str, start: F) -> Response
+    where
+        F: Future<Output = Result<Response, Response>>,
+    {
+        let cell = self.entry(key, Instant::now());
+        let ran = AtomicBool::new(false);
+        let stored = cell
+            .get_or_try_init(|| async {
+                ran.store(true, Ordering::SeqCst);
+                let response = start.await?;
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(serde_json::json!({ "error": e.to_string() })),
+                    )
+                        .into_response()
+                })?;
+                Ok(Stored { status, body, at: Instant::now() })
+            })
+            .await;
+        match stored {
+            Ok(stored) => stored.response(!ran.load(Ordering::SeqCst)),
+            Err(response) => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_key_runs_its_start_once() {
+        let keys = IdempotencyKeys::default();
+        let first = keys.run("k", async { Ok("started".into_response()) }).await;
+        assert!(first.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(body(first).await, "started");
+
+        let again = keys.run("k", async { Ok("started twice".into_response()) }).await;
+        assert_eq!(again.headers()[REPLAYED_HEADER], "true");
+        assert_eq!(body(again).await, "started");
+
+        // A refusal isn't kept
+        let busy = keys.run("b", async { Err(StatusCode::CONFLICT.into_response()) }).await;
+        assert_eq!(busy.status(), StatusCode::CONFLICT);
+        let retried = keys.run("b", async { Ok("started".into_response()) }).await;
+        assert!(retried.headers().get(REPLAYED_HEADER).is_none());
+
+        let later = Instant::now() + TTL;
+        assert!(keys.entry("k", later).get().is_none());
+        assert_eq!(keys.entries.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn keys_come_from_the_header_or_the_body() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_key(&headers, Some(" click-1 ")), Ok(Some("click-1".into())));
+        assert_eq!(request_key(&headers, Some("")), Ok(None));
+        headers.insert(KEY_HEADER, HeaderValue::from_static("click-2"));
+        assert_eq!(request_key(&headers, Some("click-1")), Ok(Some("click-2".into())));
+        assert!(request_key(&HeaderMap::new(), Some(&"x".repeat(256))).is_err());
+    }
+}
@@ -4,7 +4,7 @@ use std::sync::Arc;
 use tokio::io::AsyncWriteExt;
 use tokio_stream::wrappers::ReceiverStream;
 
-use crate::AppState;
+use crate::{disk, AppState};
 
 // ─── POST /api/cluster/install-binaries ──────────────────────────────────────
 
@@ -15,6 +15,14 @@ use crate::AppState;
 ///   {"status": "Downloading... 42%"}
 ///   {"status": "Done", "done": true}
 ///   {"error": "reason", "done": true}   ← on failure
+#[utoipa::path(
+    post,
+    path = "/api/cluster/install-binaries",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "Install progress", content_type = "application/x-ndjson", body = String),
+    )
+)]
 pub async fn install_binaries(State(_state): State<Arc<AppState>>) -> Response {
     let (tx, rx) = tokio::sync::mpsc::channel::<String>(32);
 
@@ -127,56 +135,65 @@ async fn run_install(tx: tokio::sync::mpsc::Sender<String>) -> anyhow::Result<()
     let asset_name = asset["name"].as_str().unwrap_or("llama.archive");
     let asset_size = asset["size"].as_u64().unwrap_or(0);
 
+    // ── 4. Check disk space ──────────────────────────────────────────────────
+    // The archive lands in the temp dir; the extracted binaries are at most
+    // about as large again
+    let tmp_dir = std::env::temp_dir();
+    let install_dir = disk::sharedmem_dir()?.join("bin");
+    disk::ensure_space(&[(&tmp_dir, asset_size), (&install_dir, asset_size)])?;
+
     send!(serde_json::json!({
         "status": format!("Downloading {asset_name}...")
     }));
 
-    // ── 4. Stream-download to a temp file ────────────────────────────────────
-    let tmp_path = std::env::temp_dir().join(format!("sharedllm_llama_cpp{archive_ext}"));
-    let mut resp = client
-        .get(asset_url)
-        .timeout(std::time::Duration::from_secs(600))
-        .send()
-        .await
-        .map_err(|e| anyhow::anyhow!("Download failed: {e}"))?;
-
-    let mut file = tokio::fs::File::create(&tmp_path).await?;
-    let mut downloaded: u64 = 0;
-    let mut last_reported_pct: u64 = 0;
-
-    while let Some(chunk) = resp.chunk().await? {
-        file.write_all(&chunk).await?;
-        downloaded += chunk.len() as u64;
-
-        if asset_size > 0 {
-            let pct = downloaded * 100 / asset_size;
-            // Report every 5%
-            if pct / 5 > last_reported_pct / 5 {
-                last_reported_pct = pct;
-                send!(serde_json::json!({
-                    "status": format!("Downloading... {pct}%"),
-                    "pct": pct
-                }));
+    // ── 5. Stream-download to a temp file ────────────────────────────────────
+    // Written as .partial and renamed when complete, so an interrupted
+    // download is never mistaken for an archive and can be cleaned up
+    let tmp_path = tmp_dir.join(format!("sharedllm_llama_cpp{archive_ext}"));
+    let partial_path = tmp_dir.join(format!("sharedllm_llama_cpp{archive_ext}.{}", disk::PARTIAL_EXT));
+    let download = async {
+        let mut resp = client
+            .get(asset_url)
+            .timeout(std::time::Duration::from_secs(600))
+            .send()
+            .await
+            .map_err(|e| anyhow::anyhow!("Download failed: {e}"))?;
+
+        let mut file = tokio::fs::File::create(&partial_path).await?;
+        let mut downloaded: u64 = 0;
+        let mut last_reported_pct: u64 = 0;
+
+        while let Some(chunk) = resp.chunk().await? {
+            file.write_all(&chunk).await?;
+            downloaded += chunk.len() as u64;
+
+            if let Some(pct) = (downloaded * 100).checked_div(asset_size) {
+                // Report every 5%
+                if pct / 5 > last_reported_pct / 5 {
+                    last_reported_pct = pct;
+                    send!(serde_json::json!({
+                        "status": format!("Downloading... {pct}%"),
+                        "pct": pct
+                    }));
+                }
             }
         }
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&partial_path, &tmp_path).await?;
+        anyhow::Ok(())
+    };
+    if let Err(e) = download.await {
+        let _ = tokio::fs::remove_file(&partial_path).await;
+        return Err(e);
     }
-    file.flush().await?;
-    drop(file);
 
     send!(serde_json::json!({ "status": "Download complete. Extracting binaries..." }));
 
-    // ── 5. Prepare install directory ─────────────────────────────────────────
-    let install_dir = {
-        let home = std::env::var("HOME")
-            .or_else(|_| std::env::var("USERPROFILE"))
-            .map_err(|_| anyhow::anyhow!("Cannot determine HOME directory"))?;
-        std::path::PathBuf::from(home)
-            .join(".sharedmem")
-            .join("bin")
-    };
+    // ── 6. Prepare install directory ─────────────────────────────────────────
     tokio::fs::create_dir_all(&install_dir).await?;
 
-    // ── 6. Extract target binaries (blocking I/O) ─────────────────────────────
+    // ── 7. Extract target binaries (blocking I/O) ─────────────────────────────
     let binary_ext = if os == "windows" { ".exe" } else { "" };
     let targets = vec![
         format!("llama-server{binary_ext}"),
@@ -187,20 +204,18 @@ async fn run_install(tx: tokio::sync::mpsc::Sender<String>) -> anyhow::Result<()
     let install_dir_b = install_dir.clone();
     let targets_b = targets.clone();
 
-    if is_zip {
-        tokio::task::spawn_blocking(move || {
+    let extracted = tokio::task::spawn_blocking(move || {
+        if is_zip {
             extract_zip(&tmp_path_b, &install_dir_b, &targets_b)
-        })
-        .await??;
-    } else {
-        tokio::task::spawn_blocking(move || {
+        } else {
             extract_tar_gz(&tmp_path_b, &install_dir_b, &targets_b)
-        })
-        .await??;
-    }
+        }
+    })
+    .await;
 
-    // ── 7. Cleanup temp file ─────────────────────────────────────────────────
+    // ── 8. Cleanup temp file (also when extraction failed) ───────────────────
     let _ = tokio::fs::remove_file(&tmp_path).await;
+    extracted??;
 
     let install_path = install_dir.display().to_string();
     send!(serde_json::json!({
@@ -0,0 +1,214 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::openapi::{ErrorResponse, OkResponse};
+use crate::{
+    api_keys::{self, KeyScope},
+    db::{
+        models::{ApiKey, ModelIds},
+        queries,
+    },
+    AppState,
+};
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UpsertApiKeyRequest {
+    pub name: String,
+    /// Requests per minute; omitted = unlimited
+    pub rate_limit_rpm: Option<i64>,
+    /// Model IDs the key may use; empty = every model
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// What the key may do; `inference` when created without one, and
+    /// unchanged when updated without one
+    pub scope: Option<KeyScope>,
+}
+
+/// 201 from `POST /api/keys`: the only time the key itself is returned.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    /// Send as `Authorization: Bearer <key>`
+    pub key: String,
+}
+
+fn validate(req: &UpsertApiKeyRequest) -> Result<(), String> {
+    let name = req.name.trim();
+    if name.is_empty() || name.len() > 64 {
+        return Err("'name' must be 1-64 characters".into());
+    }
+    if req.rate_limit_rpm.is_some_and(|n| n <= 0) {
+        return Err("'rate_limit_rpm' must be a positive number of requests per minute".into());
+    }
+    if req.allowed_models.iter().any(|m| m.trim().is_empty()) {
+        return Err("'allowed_models' entries must be non-empty model IDs".into());
+    }
+    Ok(())
+}
+
+fn allowed_models(req: &UpsertApiKeyRequest) -> ModelIds {
+    ModelIds(req.allowed_models.iter().map(|m| m.trim().to_string()).collect())
+}
+
+fn internal_error(e: anyhow::Error) -> axum::response::Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "error": e.to_string() })),
+    )
+        .into_response()
+}
+
+fn not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "API key not found" })),
+    )
+        .into_response()
+}
+
+/// GET /api/keys
+#[utoipa::path(
+    get,
+    path = "/api/keys",
+    tag = "api-keys",
+    responses(
+        (status = 200, description = "`{keys: [ApiKey]}`, revoked keys included", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_keys(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match queries::list_api_keys(&state.pool).await {
+        Ok(keys) => Json(serde_json::json!({ "keys": keys })).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// POST /api/keys
+#[utoipa::path(
+    post,
+    path = "/api/keys",
+    tag = "api-keys",
+    request_body = UpsertApiKeyRequest,
+    responses(
+        (status = 201, body = CreatedApiKey),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+pub async fn create_key(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UpsertApiKeyRequest>,
+) -> impl IntoResponse {
+    if let Err(msg) = validate(&req) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+            .into_response();
+    }
+
+    let new = api_keys::generate();
+    let key = ApiKey {
+        id: format!("key-{}", Uuid::new_v4()),
+        name: req.name.trim().to_string(),
+        key_prefix: new.key_prefix,
+        salt: new.salt,
+        hashed_key: new.hashed_key,
+        rate_limit_rpm: req.rate_limit_rpm,
+        allowed_models: allowed_models(&req),
+        scope: req.scope.unwrap_or_default(),
+        requests: 0,
+        last_used_at: None,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        revoked_at: None,
+    };
+
+    match queries::upsert_api_key(&state.pool, &key).await {
+        Ok(()) => (
+            StatusCode::CREATED,
+            Json(CreatedApiKey { api_key: key, key: new.key }),
+        )
+            .into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// PUT /api/keys/:id
+#[utoipa::path(
+    put,
+    path = "/api/keys/{id}",
+    tag = "api-keys",
+    params(("id" = String, Path, description = "API key ID")),
+    request_body = UpsertApiKeyRequest,
+    responses(
+        (status = 200, body = ApiKey),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn update_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<UpsertApiKeyRequest>,
+) -> impl IntoResponse {
+    if let Err(msg) = validate(&req) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+            .into_response();
+    }
+
+    let existing = match queries::get_api_key(&state.pool, &id).await {
+        Ok(Some(k)) => k,
+        Ok(None) => return not_found(),
+        Err(e) => return internal_error(e),
+    };
+
+    let key = ApiKey {
+        name: req.name.trim().to_string(),
+        rate_limit_rpm: req.rate_limit_rpm,
+        allowed_models: allowed_models(&req),
+        scope: req.scope.unwrap_or(existing.scope),
+        ..existing
+    };
+
+    match queries::upsert_api_key(&state.pool, &key).await {
+        Ok(()) => {
+            state.key_limiter.forget(&key.id);
+            Json(key).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// DELETE /api/keys/:id
+/// Revokes the key. It stays listed, with its usage, but is refused from now on.
+#[utoipa::path(
+    delete,
+    path = "/api/keys/{id}",
+    tag = "api-keys",
+    params(("id" = String, Path, description = "API key ID")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn revoke_key(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match queries::get_api_key(&state.pool, &id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(),
+        Err(e) => return internal_error(e),
+    }
+    match queries::revoke_api_key(&state.pool, &id).await {
+        Ok(_) => {
+            state.key_limiter.forget(&id);
+            Json(serde_json::json!({ "ok": true })).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
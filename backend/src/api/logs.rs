@@ -0,0 +1,43 @@
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Lines returned when `limit` is not given.
+const DEFAULT_LIMIT: usize = 200;
+const MAX_LIMIT: usize = 2000;
+
+/// Query params for GET /api/logs/recent
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecentLogsParams {
+    /// Only lines logged while handling this request (the `x-request-id` header)
+    pub request_id: Option<String>,
+    /// Newest lines to return (default 200, max 2000)
+    pub limit: Option<usize>,
+}
+
+/// GET /api/logs/recent
+/// Recent backend log lines, oldest first, from an in-memory buffer.
+#[utoipa::path(
+    get,
+    path = "/api/logs/recent",
+    tag = "logs",
+    params(RecentLogsParams),
+    responses(
+        (status = 200, description = "`{lines: [LogLine]}`", body = serde_json::Value),
+    )
+)]
+pub async fn recent_logs(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RecentLogsParams>,
+) -> impl IntoResponse {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let lines = state.logs.recent(params.request_id.as_deref(), limit);
+    Json(serde_json::json!({ "lines": lines }))
+}
@@ -2,9 +2,23 @@ pub mod agent;
 pub mod backends;
 pub mod cluster;
 pub mod devices;
+pub mod errors;
+pub mod etag;
 pub mod gpu;
+pub mod idempotency;
 pub mod install;
+pub mod keys;
+pub mod logs;
+pub mod model_files;
+pub mod model_store;
+pub mod model_uploads;
 pub mod models;
+pub mod openapi;
 pub mod permissions;
+pub mod presets;
+pub mod resume;
 pub mod settings;
+pub mod setup;
+pub mod system;
+pub mod webhooks;
 pub mod ws_handler;
@@ -0,0 +1,373 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Request, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use super::models::{alias_conflict, validate_alias};
+use super::openapi::ErrorResponse;
+use crate::{
+    db::{
+        models::{Device, ModelAlias, ModelFileHash},
+        queries,
+    },
+    disk, federation,
+    llama_cpp::split,
+    permissions::{DeviceKind, DeviceStatus, PermissionService},
+    ws::WsEvent,
+    settings::Key,
+    AppState,
+};
+
+/// Setting: when "true", approved devices may list and download the models
+/// this host has aliases for.
+pub const SHARE_MODELS_KEY: &str = "share_models";
+
+/// A model this host shares, from `GET /api/models/files`.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SharedModelFile {
+    pub alias: String,
+    /// File name, without the directory
+    pub file_name: String,
+    pub size: u64,
+    /// Lowercase hex SHA-256 of the file
+    pub sha256: String,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct FetchFromPeerRequest {
+    /// An approved device that runs a SharedLLM backend
+    pub peer_device_id: String,
+    /// The model's alias on the peer, which it gets here too
+    pub alias: String,
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(serde_json::json!({ "error": message.into() }))).into_response()
+}
+
+/// Why the caller may not fetch model files, if it may not.
+async fn refuse(state: &AppState, peer: Option<SocketAddr>) -> Option<Response> {
+    if !state.settings.get_bool(Key::ShareModels) {
+        return Some(error(StatusCode::FORBIDDEN, "This host does not share models (share_models)"));
+    }
+    let Some(ip) = peer.map(|a| a.ip()) else {
+        return Some(error(StatusCode::FORBIDDEN, "Unknown caller"));
+    };
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    match svc.may_fetch_model_files(ip).await {
+        Ok(true) => None,
+        Ok(false) => Some(error(StatusCode::FORBIDDEN, "Only approved devices may fetch models")),
+        Err(e) => Some(error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+/// Split models are several files, which this doesn't transfer.
+fn shareable(path: &FsPath) -> bool {
+    path.is_file() && path.file_name().and_then(|n| n.to_str()).is_some_and(|n| split::parse(n).is_none())
+}
+
+/// Lowercase hex SHA-256 of the file at `path`. Blocking.
+pub fn hash_file(path: &FsPath) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Size and SHA-256 of the file at `path`, hashed only when the cached hash
+/// is missing or the file changed since.
+async fn file_hash(pool: &SqlitePool, path: &FsPath) -> anyhow::Result<ModelFileHash> {
+    let meta = tokio::fs::metadata(path).await?;
+    let size = meta.len() as i64;
+    let modified_at = chrono::DateTime::<chrono::Utc>::from(meta.modified()?).to_rfc3339();
+    let key = path.display().to_string();
+    if let Some(cached) = queries::get_model_file_hash(pool, &key).await? {
+        if cached.size == size && cached.modified_at == modified_at {
+            return Ok(cached);
+        }
+    }
+    tracing::info!("Hashing {} for sharing", key);
+    let owned = path.to_path_buf();
+    let sha256 = tokio::task::spawn_blocking(move || hash_file(&owned)).await??;
+    let hash = ModelFileHash { path: key, size, modified_at, sha256 };
+    queries::upsert_model_file_hash(pool, &hash).await?;
+    Ok(hash)
+}
+
+/// GET /api/models/files
+/// Hashes each file the first time it is listed, which can take a while.
+#[utoipa::path(
+    get,
+    path = "/api/models/files",
+    tag = "models",
+    responses(
+        (status = 200, description = "`{files: [SharedModelFile]}`", body = serde_json::Value),
+        (status = 403, description = "`share_models` is off, or the caller is not an approved device", body = ErrorResponse),
+    )
+)]
+pub async fn list_model_files(
+    State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+) -> impl IntoResponse {
+    if let Some(refused) = refuse(&state, peer.map(|ConnectInfo(a)| a)).await {
+        return refused;
+    }
+    let aliases = match queries::list_model_aliases(&state.pool).await {
+        Ok(a) => a,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let mut files = Vec::new();
+    for alias in aliases {
+        let path = PathBuf::from(&alias.path);
+        if !shareable(&path) {
+            continue;
+        }
+        match file_hash(&state.pool, &path).await {
+            Ok(hash) => files.push(SharedModelFile {
+                alias: alias.alias,
+                file_name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                size: hash.size as u64,
+                sha256: hash.sha256,
+            }),
+            Err(e) => tracing::warn!("Not sharing {}: {}", alias.path, e),
+        }
+    }
+    Json(serde_json::json!({ "files": files })).into_response()
+}
+
+/// GET /api/models/files/:alias
+/// The model file itself. Honors `Range`, so an interrupted download resumes.
+#[utoipa::path(
+    get,
+    path = "/api/models/files/{alias}",
+    tag = "models",
+    params(("alias" = String, Path, description = "Model alias")),
+    responses(
+        (status = 200, description = "The GGUF file", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 206, description = "The requested byte range", content_type = "application/octet-stream", body = Vec<u8>),
+        (status = 403, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 416, description = "Range outside the file"),
+    )
+)]
+pub async fn serve_model_file(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    req: Request,
+) -> Response {
+    if let Some(refused) = refuse(&state, peer.map(|ConnectInfo(a)| a)).await {
+        return refused;
+    }
+    let path = match queries::get_model_alias(&state.pool, &alias).await {
+        Ok(Some(a)) => PathBuf::from(a.path),
+        Ok(None) => return error(StatusCode::NOT_FOUND, format!("No model alias '{}'", alias)),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    if !shareable(&path) {
+        return error(StatusCode::NOT_FOUND, format!("'{}' is not a single model file", alias));
+    }
+    match ServeFile::new(&path).oneshot(req).await {
+        Ok(resp) => resp.map(Body::new),
+        Err(never) => match never {},
+    }
+}
+
+/// Where fetched models go: beside the first aliased model, else
+/// `~/.sharedmem/models`.
+pub(super) async fn models_dir(pool: &SqlitePool) -> anyhow::Result<PathBuf> {
+    let beside = queries::list_model_aliases(pool)
+        .await?
+        .into_iter()
+        .find_map(|a| FsPath::new(&a.path).parent().map(FsPath::to_path_buf));
+    match beside {
+        Some(dir) => Ok(dir),
+        None => Ok(disk::sharedmem_dir()?.join("models")),
+    }
+}
+
+/// POST /api/models/fetch-from-peer
+/// Download a model from a peer host that shares it, resuming a partial
+/// download, check its SHA-256, and give it the same alias here.
+///
+/// Streams NDJSON progress lines:
+///   {"status": "Downloading... 42%", "pct": 42}
+///   {"status": "Done", "done": true, "alias": "...", "path": "..."}
+///   {"error": "reason", "done": true}   ← on failure
+#[utoipa::path(
+    post,
+    path = "/api/models/fetch-from-peer",
+    tag = "models",
+    request_body = FetchFromPeerRequest,
+    responses(
+        (status = 200, description = "Download progress", content_type = "application/x-ndjson", body = String),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "The alias exists here already", body = ErrorResponse),
+    )
+)]
+pub async fn fetch_from_peer(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<FetchFromPeerRequest>,
+) -> Response {
+    let alias = req.alias.trim().to_string();
+    if let Err(msg) = validate_alias(&alias) {
+        return error(StatusCode::BAD_REQUEST, msg);
+    }
+    if let Ok(Some(existing)) = queries::get_model_alias(&state.pool, &alias).await {
+        return error(StatusCode::CONFLICT, format!("Alias '{}' already exists", existing.alias));
+    }
+    let peer = match queries::get_device(&state.pool, &req.peer_device_id).await {
+        Ok(Some(d)) => d,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "Device not found"),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    if peer.status != DeviceStatus::Approved || peer.device_kind != DeviceKind::Peer {
+        return error(
+            StatusCode::BAD_REQUEST,
+            format!("{} is not an approved SharedLLM host", peer.name),
+        );
+    }
+
+    let (tx, rx) = mpsc::channel::<String>(32);
+    tokio::spawn(async move {
+        if let Err(e) = run_fetch(&state, &peer, &alias, &tx).await {
+            let msg = format!("{}\n", serde_json::json!({ "error": e.to_string(), "done": true }));
+            let _ = tx.send(msg).await;
+        }
+    });
+
+    let stream = ReceiverStream::new(rx).map(Ok::<_, std::convert::Infallible>);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::from_stream(stream))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+async fn run_fetch(state: &AppState, peer: &Device, alias: &str, tx: &mpsc::Sender<String>) -> anyhow::Result<()> {
+    macro_rules! send {
+        ($json:expr) => {
+            let _ = tx.send(format!("{}\n", $json)).await;
+        };
+    }
+
+    let base = federation::base_url(peer);
+    let client = reqwest::Client::new();
+    send!(serde_json::json!({ "status": format!("Asking {} for {} (it may need to hash it first)", peer.name, alias) }));
+    let resp = client.get(format!("{}/api/models/files", base)).send().await?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+        anyhow::bail!("{} refused to list its models (HTTP {}): {}", peer.name, status, body["error"].as_str().unwrap_or(""));
+    }
+    let listing: serde_json::Value = resp.json().await?;
+    let files: Vec<SharedModelFile> = serde_json::from_value(listing["files"].clone())?;
+    let Some(file) = files.into_iter().find(|f| f.alias.eq_ignore_ascii_case(alias)) else {
+        anyhow::bail!("{} does not share a model called '{}'", peer.name, alias);
+    };
+    // Only a bare file name may come from the peer
+    if FsPath::new(&file.file_name).file_name().and_then(|n| n.to_str()) != Some(file.file_name.as_str()) {
+        anyhow::bail!("{} sent an unusable file name '{}'", peer.name, file.file_name);
+    }
+
+    let dir = models_dir(&state.pool).await?;
+    tokio::fs::create_dir_all(&dir).await?;
+    let dest = dir.join(&file.file_name);
+    if dest.exists() {
+        anyhow::bail!("{} already exists; alias it instead", dest.display());
+    }
+    if let Some(conflict) = alias_conflict(&state.pool, alias, &dest.display().to_string()).await {
+        anyhow::bail!(conflict);
+    }
+
+    // Resume what an earlier attempt left in the .partial file
+    let partial = dir.join(format!("{}.{}", file.file_name, disk::PARTIAL_EXT));
+    let have = tokio::fs::metadata(&partial).await.map(|m| m.len()).unwrap_or(0).min(file.size);
+    disk::ensure_space(&[(dir.as_path(), file.size - have)])?;
+
+    let mut request = client.get(format!("{}/api/models/files/{}", base, file.alias));
+    if have > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", have));
+    }
+    let mut resp = request.send().await?;
+    let resumed = resp.status() == StatusCode::PARTIAL_CONTENT;
+    if !resp.status().is_success() {
+        anyhow::bail!("{} refused the download: HTTP {}", peer.name, resp.status());
+    }
+    let mut out = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&partial)
+        .await?;
+    let mut downloaded = if resumed { have } else { 0 };
+    send!(serde_json::json!({
+        "status": if resumed { format!("Resuming {} at {}%", file.file_name, downloaded * 100 / file.size.max(1)) }
+                  else { format!("Downloading {}...", file.file_name) }
+    }));
+    let mut last_pct = downloaded * 100 / file.size.max(1);
+    while let Some(chunk) = resp.chunk().await? {
+        out.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        let pct = downloaded * 100 / file.size.max(1);
+        // Report every 5%
+        if pct / 5 > last_pct / 5 {
+            last_pct = pct;
+            send!(serde_json::json!({ "status": format!("Downloading... {pct}%"), "pct": pct }));
+        }
+    }
+    out.flush().await?;
+    drop(out);
+
+    send!(serde_json::json!({ "status": "Verifying SHA-256..." }));
+    let check = partial.clone();
+    let sha256 = tokio::task::spawn_blocking(move || hash_file(&check)).await??;
+    if sha256 != file.sha256 {
+        // Corrupt, so it can't be resumed either
+        let _ = tokio::fs::remove_file(&partial).await;
+        anyhow::bail!("Checksum mismatch for {}: expected {}, got {}", file.file_name, file.sha256, sha256);
+    }
+    tokio::fs::rename(&partial, &dest).await?;
+
+    let path = dest.display().to_string();
+    let entry = ModelAlias {
+        alias: alias.to_string(),
+        path: path.clone(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+    queries::insert_model_alias(&state.pool, &entry).await?;
+    // Known already, so sharing it onward needs no hashing
+    if let Err(e) = file_hash(&state.pool, &dest).await {
+        tracing::warn!("Failed to cache the hash of {}: {}", path, e);
+    }
+    tracing::info!("Fetched {} from {} into {}", alias, peer.name, path);
+    let _ = state.event_tx.send(WsEvent::ModelAvailable { path: path.clone(), alias: Some(alias.to_string()) });
+    send!(serde_json::json!({ "status": "Done", "done": true, "alias": alias, "path": path }));
+    Ok(())
+}
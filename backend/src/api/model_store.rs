@@ -0,0 +1,160 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use super::openapi::ErrorResponse;
+use crate::{
+    db::{models::TrashedModel, queries},
+    llama_cpp::store::{self, DirUsage},
+    settings::Key,
+    ws::WsEvent,
+    AppState,
+};
+
+/// `GET /api/cluster/models/usage`
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ModelUsage {
+    pub dirs: Vec<DirUsage>,
+    /// Models in every directory, trash not included
+    pub total_bytes: u64,
+    /// Deleted files not purged yet, oldest first
+    pub trash: Vec<TrashedModel>,
+    pub trash_bytes: u64,
+    /// Days a deleted model stays in the trash (`model_trash_days`)
+    pub trash_days: u64,
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteModelParams {
+    /// Absolute path of a `.gguf` file in a model directory; for a split
+    /// model, any of its parts
+    pub path: String,
+}
+
+/// `DELETE /api/cluster/models`
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DeletedModel {
+    /// Files moved to the trash
+    pub files: Vec<String>,
+    /// Aliases that pointed at the model, now removed
+    pub aliases: Vec<String>,
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(serde_json::json!({ "error": message.into() }))).into_response()
+}
+
+/// Files the running session reads, if one is running.
+async fn in_use(state: &AppState) -> Vec<PathBuf> {
+    state
+        .llama_cpp
+        .get_current_session()
+        .await
+        .map(|s| store::session_files(&s))
+        .unwrap_or_default()
+}
+
+/// GET /api/cluster/models/usage — the `.gguf` files in each model directory
+/// (those of aliased models and `~/.sharedmem/models`), largest first, with
+/// their ages, when a session last used them and the aliases pointing at
+/// them, plus the deleted files waiting in the trash.
+#[utoipa::path(
+    get,
+    path = "/api/cluster/models/usage",
+    tag = "models",
+    responses(
+        (status = 200, body = ModelUsage),
+    )
+)]
+pub async fn model_usage(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let dirs = store::model_dirs(&state.pool).await;
+    let mut usage = tokio::task::spawn_blocking(move || store::scan(&dirs))
+        .await
+        .unwrap_or_default();
+    let last_used = queries::model_last_used(&state.pool).await.unwrap_or_default();
+    let aliases = queries::list_model_aliases(&state.pool).await.unwrap_or_default();
+    let in_use = in_use(&state).await;
+    let trash = queries::list_trashed_models(&state.pool).await.unwrap_or_default();
+    for file in usage.iter_mut().flat_map(|d| d.files.iter_mut()) {
+        file.last_used_at = last_used.get(&file.path).cloned();
+        file.aliases = aliases.iter().filter(|a| a.path == file.path).map(|a| a.alias.clone()).collect();
+        let resolved = PathBuf::from(&file.path).canonicalize().unwrap_or_else(|_| PathBuf::from(&file.path));
+        file.in_use = in_use.contains(&resolved);
+    }
+    Json(ModelUsage {
+        total_bytes: usage.iter().map(|d| d.total_bytes).sum(),
+        trash_bytes: trash.iter().map(|t| t.size.max(0) as u64).sum(),
+        trash,
+        dirs: usage,
+        trash_days: state.settings.get_u64(Key::ModelTrashDays),
+    })
+}
+
+/// DELETE /api/cluster/models?path= — move a model file, every part of a
+/// split one, to a `.trash` directory beside it; it is purged after
+/// `model_trash_days`. Aliases pointing at it are removed and a
+/// `model_removed` event is sent. The running session's files can't be
+/// deleted.
+#[utoipa::path(
+    delete,
+    path = "/api/cluster/models",
+    tag = "models",
+    params(DeleteModelParams),
+    responses(
+        (status = 200, body = DeletedModel),
+        (status = 400, description = "Not a model file in a model directory", body = ErrorResponse),
+        (status = 409, description = "The running session uses it", body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn delete_model_file(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DeleteModelParams>,
+) -> Response {
+    let dirs = store::model_dirs(&state.pool).await;
+    let path = match store::locate(&dirs, &params.path) {
+        Ok(p) => p,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let files = match crate::llama_cpp::split::model_files(&path) {
+        Ok(files) => files,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let in_use = in_use(&state).await;
+    if files.iter().any(|f| in_use.contains(f)) {
+        return error(
+            StatusCode::CONFLICT,
+            "The running inference session uses this model; stop it first",
+        );
+    }
+
+    let trashed = match store::trash(&state.pool, &path).await {
+        Ok(files) => files,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to delete the model: {}", e)),
+    };
+    let trashed: Vec<String> = trashed.iter().map(|f| f.display().to_string()).collect();
+
+    // Aliases may name the file as given or resolved
+    let mut removed = Vec::new();
+    for alias in queries::list_model_aliases(&state.pool).await.unwrap_or_default() {
+        if trashed.contains(&alias.path) || alias.path == params.path {
+            match queries::delete_model_alias(&state.pool, &alias.alias).await {
+                Ok(()) => removed.push(alias.alias),
+                Err(e) => tracing::warn!("Failed to remove alias {}: {}", alias.alias, e),
+            }
+        }
+    }
+    tracing::info!("Moved {} to the model trash", trashed.join(", "));
+    let _ = state.event_tx.send(WsEvent::ModelRemoved {
+        path: params.path.clone(),
+        aliases: removed.clone(),
+    });
+    Json(DeletedModel { files: trashed, aliases: removed }).into_response()
+}
@@ -0,0 +1,438 @@
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+use super::model_files::models_dir;
+use super::models::{alias_conflict, validate_alias};
+use super::openapi::ErrorResponse;
+use crate::{
+    db::{models::ModelAlias, queries},
+    disk,
+    llama_cpp::{split, validate_model_path},
+    settings::Key,
+    ws::WsEvent,
+    AppState,
+};
+
+/// Setting: largest model that may be uploaded, in MB.
+pub const MAX_UPLOAD_MB_KEY: &str = "model_upload_max_mb";
+/// Largest chunk one `PUT .../chunk` may carry.
+pub const MAX_CHUNK_BYTES: usize = 64 * 1024 * 1024;
+/// An upload that receives nothing for this long is dropped with its file.
+const UPLOAD_TTL: Duration = Duration::from_secs(60 * 60);
+const REAP_INTERVAL: Duration = Duration::from_secs(60);
+/// First bytes of every GGUF file.
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+struct Upload {
+    id: String,
+    file_name: String,
+    alias: Option<String>,
+    size: u64,
+    received: u64,
+    /// Where the finished model goes
+    dest: PathBuf,
+    /// Chunks are appended here, beside `dest`
+    partial: PathBuf,
+    created_at: String,
+    touched: Instant,
+}
+
+impl Upload {
+    fn status(&self) -> UploadStatus {
+        UploadStatus {
+            upload_id: self.id.clone(),
+            file_name: self.file_name.clone(),
+            alias: self.alias.clone(),
+            size: self.size,
+            received: self.received,
+            complete: self.received == self.size,
+            created_at: self.created_at.clone(),
+        }
+    }
+}
+
+/// Progress of an upload.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct UploadStatus {
+    pub upload_id: String,
+    pub file_name: String,
+    pub alias: Option<String>,
+    /// Total bytes announced at init
+    pub size: u64,
+    /// Bytes received so far; the offset of the next chunk
+    pub received: u64,
+    /// Every byte is in, so it can be finalized
+    pub complete: bool,
+    pub created_at: String,
+}
+
+/// An upload with its `dest`, readable while a chunk is being written.
+type Entry = (PathBuf, Arc<Mutex<Upload>>);
+
+/// Uploads in progress. They live in memory: after a restart a client
+/// starts over, and the reaper of the next run never sees the old files
+/// (`GET /api/system/disk` lists them as orphaned `.partial` files).
+#[derive(Default)]
+pub struct UploadRegistry {
+    uploads: std::sync::Mutex<HashMap<String, Entry>>,
+}
+
+impl UploadRegistry {
+    fn insert(&self, upload: Upload) {
+        let (id, dest) = (upload.id.clone(), upload.dest.clone());
+        self.uploads.lock().unwrap().insert(id, (dest, Arc::new(Mutex::new(upload))));
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<Mutex<Upload>>> {
+        self.uploads.lock().unwrap().get(id).map(|(_, u)| u.clone())
+    }
+
+    fn remove(&self, id: &str) {
+        self.uploads.lock().unwrap().remove(id);
+    }
+
+    /// Whether an upload in progress will produce `dest`.
+    fn targets(&self, dest: &FsPath) -> bool {
+        self.uploads.lock().unwrap().values().any(|(d, _)| d == dest)
+    }
+
+    /// Drop uploads idle for `ttl` and delete their files. One receiving a
+    /// chunk right now is busy, not idle. Returns how many were dropped.
+    async fn reap_idle(&self, ttl: Duration) -> usize {
+        let mut expired = Vec::new();
+        self.uploads.lock().unwrap().retain(|_, (_, upload)| match upload.try_lock() {
+            Ok(u) if u.touched.elapsed() >= ttl => {
+                expired.push((u.id.clone(), u.partial.clone()));
+                false
+            }
+            _ => true,
+        });
+        for (id, partial) in &expired {
+            tracing::info!("Dropping abandoned upload {}", id);
+            let _ = tokio::fs::remove_file(partial).await;
+        }
+        expired.len()
+    }
+
+    pub fn spawn_reaper(self: Arc<Self>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(REAP_INTERVAL).await;
+                self.reap_idle(UPLOAD_TTL).await;
+            }
+        });
+    }
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(serde_json::json!({ "error": message.into() }))).into_response()
+}
+
+fn not_found(id: &str) -> Response {
+    error(StatusCode::NOT_FOUND, format!("No upload '{}' (it may have expired)", id))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct InitUploadRequest {
+    /// Name of the `.gguf` file, without a directory
+    pub file_name: String,
+    /// Total size in bytes
+    pub size: u64,
+    /// Alias to register once the upload is finalized
+    pub alias: Option<String>,
+}
+
+/// POST /api/models/upload
+/// Start an upload. The file goes beside the first aliased model, or into
+/// `~/.sharedmem/models`.
+#[utoipa::path(
+    post,
+    path = "/api/models/upload",
+    tag = "models",
+    request_body = InitUploadRequest,
+    responses(
+        (status = 201, body = UploadStatus),
+        (status = 400, body = ErrorResponse),
+        (status = 409, description = "The file or alias exists already", body = ErrorResponse),
+        (status = 413, description = "Larger than `model_upload_max_mb`", body = ErrorResponse),
+        (status = 507, description = "Not enough disk space", body = ErrorResponse),
+    )
+)]
+pub async fn init_upload(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<InitUploadRequest>,
+) -> Response {
+    let file_name = req.file_name.trim().to_string();
+    if FsPath::new(&file_name).file_name().and_then(|n| n.to_str()) != Some(file_name.as_str()) {
+        return error(StatusCode::BAD_REQUEST, "file_name must be a bare file name");
+    }
+    if !file_name.to_ascii_lowercase().ends_with(".gguf") {
+        return error(StatusCode::BAD_REQUEST, "Only .gguf files can be uploaded");
+    }
+    if split::parse(&file_name).is_some() {
+        return error(StatusCode::BAD_REQUEST, "Split models can't be uploaded; merge them first");
+    }
+    let max_mb = state.settings.get_u64(Key::ModelUploadMaxMb);
+    if req.size == 0 {
+        return error(StatusCode::BAD_REQUEST, "size must be greater than 0");
+    }
+    if req.size > max_mb * 1024 * 1024 {
+        return error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Uploads are limited to {} MB ({})", max_mb, MAX_UPLOAD_MB_KEY),
+        );
+    }
+    let alias = req.alias.as_deref().map(str::trim).filter(|a| !a.is_empty()).map(str::to_string);
+    if let Some(alias) = &alias {
+        if let Err(msg) = validate_alias(alias) {
+            return error(StatusCode::BAD_REQUEST, msg);
+        }
+    }
+
+    let dir = match models_dir(&state.pool).await {
+        Ok(d) => d,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let dest = dir.join(&file_name);
+    if let Err(e) = validate_model_path(&dest.display().to_string()) {
+        return error(StatusCode::BAD_REQUEST, e.to_string());
+    }
+    if dest.exists() || state.uploads.targets(&dest) {
+        return error(StatusCode::CONFLICT, format!("{} already exists or is being uploaded", dest.display()));
+    }
+    if let Some(alias) = &alias {
+        if let Some(conflict) = alias_conflict(&state.pool, alias, &dest.display().to_string()).await {
+            return error(StatusCode::CONFLICT, conflict);
+        }
+    }
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    if let Err(e) = disk::ensure_space(&[(dir.as_path(), req.size)]) {
+        return error(StatusCode::INSUFFICIENT_STORAGE, e.to_string());
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let partial = dir.join(format!("{}.upload-{}.{}", file_name, id, disk::PARTIAL_EXT));
+    if let Err(e) = tokio::fs::File::create(&partial).await {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    let upload = Upload {
+        id: id.clone(),
+        file_name,
+        alias,
+        size: req.size,
+        received: 0,
+        dest,
+        partial,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        touched: Instant::now(),
+    };
+    let status = upload.status();
+    tracing::info!("Upload {} of {} ({} bytes) started", id, status.file_name, status.size);
+    state.uploads.insert(upload);
+    (StatusCode::CREATED, Json(status)).into_response()
+}
+
+/// GET /api/models/upload/:id
+#[utoipa::path(
+    get,
+    path = "/api/models/upload/{id}",
+    tag = "models",
+    params(("id" = String, Path, description = "Upload ID")),
+    responses(
+        (status = 200, body = UploadStatus),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn upload_status(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    match state.uploads.get(&id) {
+        Some(upload) => Json(upload.lock().await.status()).into_response(),
+        None => not_found(&id),
+    }
+}
+
+#[derive(Deserialize, utoipa::IntoParams)]
+pub struct ChunkParams {
+    /// Where the chunk starts; must equal `received`
+    pub offset: u64,
+}
+
+/// PUT /api/models/upload/:id/chunk?offset=N
+/// Append the raw request body (at most 64 MiB). A chunk at the wrong offset
+/// gets a 409 carrying the expected `offset`, so an interrupted client
+/// resumes from there.
+#[utoipa::path(
+    put,
+    path = "/api/models/upload/{id}/chunk",
+    tag = "models",
+    params(("id" = String, Path, description = "Upload ID"), ChunkParams),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, body = UploadStatus),
+        (status = 400, description = "The chunk runs past the announced size", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "`{error, offset}`: the offset the next chunk must start at", body = serde_json::Value),
+        (status = 413, description = "Chunk larger than 64 MiB"),
+    )
+)]
+pub async fn upload_chunk(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(params): Query<ChunkParams>,
+    body: Bytes,
+) -> Response {
+    let Some(upload) = state.uploads.get(&id) else {
+        return not_found(&id);
+    };
+    let mut upload = upload.lock().await;
+    if params.offset != upload.received {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "error": format!("Expected a chunk at offset {}", upload.received),
+                "offset": upload.received,
+            })),
+        )
+            .into_response();
+    }
+    if upload.received + body.len() as u64 > upload.size {
+        return error(
+            StatusCode::BAD_REQUEST,
+            format!("Chunk runs past the announced size of {} bytes", upload.size),
+        );
+    }
+
+    let written = async {
+        let mut file = tokio::fs::OpenOptions::new().append(true).open(&upload.partial).await?;
+        file.write_all(&body).await?;
+        file.flush().await
+    }
+    .await;
+    if let Err(e) = written {
+        // The file may hold part of the chunk; go by its real length
+        if let Ok(meta) = tokio::fs::metadata(&upload.partial).await {
+            upload.received = meta.len().min(upload.size);
+        }
+        return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    upload.received += body.len() as u64;
+    upload.touched = Instant::now();
+    Json(upload.status()).into_response()
+}
+
+/// POST /api/models/upload/:id/finalize
+/// Check the finished file and move it into place, registering its alias.
+#[utoipa::path(
+    post,
+    path = "/api/models/upload/{id}/finalize",
+    tag = "models",
+    params(("id" = String, Path, description = "Upload ID")),
+    responses(
+        (status = 200, description = "`{ok, path, alias}`", body = serde_json::Value),
+        (status = 400, description = "Not a GGUF file; the upload is dropped", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Bytes still missing, or the file or alias appeared meanwhile", body = ErrorResponse),
+    )
+)]
+pub async fn finalize_upload(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Response {
+    let Some(upload) = state.uploads.get(&id) else {
+        return not_found(&id);
+    };
+    let upload = upload.lock().await;
+    if upload.received != upload.size {
+        return error(
+            StatusCode::CONFLICT,
+            format!("Received {} of {} bytes", upload.received, upload.size),
+        );
+    }
+
+    let mut magic = [0u8; 4];
+    let read = async { tokio::fs::File::open(&upload.partial).await?.read_exact(&mut magic).await }.await;
+    if read.is_err() || &magic != GGUF_MAGIC {
+        state.uploads.remove(&id);
+        let _ = tokio::fs::remove_file(&upload.partial).await;
+        return error(StatusCode::BAD_REQUEST, format!("{} is not a GGUF file", upload.file_name));
+    }
+    let path = upload.dest.display().to_string();
+    if let Err(e) = validate_model_path(&path) {
+        state.uploads.remove(&id);
+        let _ = tokio::fs::remove_file(&upload.partial).await;
+        return error(StatusCode::BAD_REQUEST, e.to_string());
+    }
+    if upload.dest.exists() {
+        return error(StatusCode::CONFLICT, format!("{} appeared meanwhile", path));
+    }
+    if let Some(alias) = &upload.alias {
+        if let Some(conflict) = alias_conflict(&state.pool, alias, &path).await {
+            return error(StatusCode::CONFLICT, conflict);
+        }
+    }
+
+    if let Err(e) = tokio::fs::rename(&upload.partial, &upload.dest).await {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    state.uploads.remove(&id);
+    if let Some(alias) = &upload.alias {
+        let entry = ModelAlias {
+            alias: alias.clone(),
+            path: path.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        if let Err(e) = queries::insert_model_alias(&state.pool, &entry).await {
+            return error(StatusCode::INTERNAL_SERVER_ERROR, format!("Uploaded to {}, but: {}", path, e));
+        }
+    }
+    tracing::info!("Upload {} finished: {}", id, path);
+    let _ = state.event_tx.send(WsEvent::ModelAvailable { path: path.clone(), alias: upload.alias.clone() });
+    Json(serde_json::json!({ "ok": true, "path": path, "alias": upload.alias })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn idle_uploads_are_reaped_with_their_files() {
+        let registry = UploadRegistry::default();
+        let dir = std::env::temp_dir();
+        let partial = dir.join(format!("reaped-{}.{}", uuid::Uuid::new_v4(), disk::PARTIAL_EXT));
+        std::fs::write(&partial, b"GG").unwrap();
+        let upload = Upload {
+            id: "u1".into(),
+            file_name: "m.gguf".into(),
+            alias: None,
+            size: 4,
+            received: 2,
+            dest: dir.join("m.gguf"),
+            partial: partial.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            touched: Instant::now(),
+        };
+        registry.insert(upload);
+
+        assert_eq!(registry.reap_idle(UPLOAD_TTL).await, 0);
+        assert!(registry.targets(&dir.join("m.gguf")));
+        // Busy uploads are never idle
+        let held = registry.get("u1").unwrap();
+        let guard = held.lock().await;
+        assert_eq!(registry.reap_idle(Duration::ZERO).await, 0);
+        drop(guard);
+
+        assert_eq!(registry.reap_idle(Duration::ZERO).await, 1);
+        assert!(registry.get("u1").is_none());
+        assert!(!partial.exists());
+    }
+}
@@ -1,25 +1,139 @@
 use axum::{
     body::Body,
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::AppState;
+use super::openapi::{ErrorResponse, OkResponse};
+use crate::{
+    db::{
+        models::{ModelAlias, ModelPull},
+        queries,
+    },
+    llama_cpp::{lora, split, store, validate_model_path},
+    ollama::{
+        pulls::{PullErrorCode, PullFailure},
+        OllamaModelDetails, OllamaRunningModel, ShowError,
+    },
+    permissions::PermissionService,
+    settings::Key,
+    AppState,
+};
+
+/// Setting: pull a model missing from Ollama before a native chat/generate,
+/// for callers whose role may pull models.
+pub const AUTO_PULL_KEY: &str = "auto_pull_missing_models";
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct PullModelRequest {
     pub name: String,
+    /// Queue the pull for the `offpeak_start`..`offpeak_end` window instead
+    /// of pulling now
+    pub schedule: Option<PullSchedule>,
+    /// Queue the pull to start no earlier than this RFC 3339 time
+    pub not_before: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PullSchedule {
+    OffPeak,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct CreateAliasRequest {
+    pub alias: String,
+    pub path: String,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UpdateAliasRequest {
+    pub path: String,
 }
 
 /// GET /api/models
+/// Each model carries `details` from `/api/show` when Ollama has them.
+#[utoipa::path(
+    get,
+    path = "/api/models",
+    tag = "models",
+    responses(
+        (status = 200, description = "`{models: [OllamaModel]}`, each with `details: OllamaModelDetails | null`", body = serde_json::Value),
+        (status = 503, body = ErrorResponse),
+    )
+)]
 pub async fn list_models(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match state.ollama.list_models().await {
-        Ok(models) => Json(serde_json::json!({ "models": models })).into_response(),
-        Err(e) => (
+    let models = match state.ollama.list_models().await {
+        Ok(models) => models,
+        Err(e) => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    // Cached per digest, so only newly pulled models cost a request
+    let details = futures::future::join_all(
+        models.iter().map(|m| state.ollama.model_details(m)),
+    )
+    .await;
+    let models: Vec<serde_json::Value> = models
+        .into_iter()
+        .zip(details)
+        .map(|(model, details)| {
+            let mut entry = serde_json::to_value(model).unwrap_or_default();
+            entry["details"] = details
+                .ok()
+                .and_then(|d| serde_json::to_value(d).ok())
+                .unwrap_or_default();
+            entry
+        })
+        .collect();
+    Json(serde_json::json!({ "models": models })).into_response()
+}
+
+/// GET /api/models/:name/details
+/// 404 when Ollama has no such model, 503 when Ollama itself is unreachable.
+#[utoipa::path(
+    get,
+    path = "/api/models/{name}/details",
+    tag = "models",
+    params(("name" = String, Path, description = "Ollama model name; no tag means `:latest`")),
+    responses(
+        (status = 200, body = OllamaModelDetails),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 503, body = ErrorResponse),
+    )
+)]
+pub async fn model_details(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    if !valid_model_name(&name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "Invalid model name" })),
+        )
+            .into_response();
+    }
+    match state.ollama.show_model(&name).await {
+        Ok(details) => Json(details).into_response(),
+        Err(ShowError::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": format!("Model '{}' is not pulled", name) })),
+        )
+            .into_response(),
+        Err(e @ ShowError::Unavailable(_)) => (
             StatusCode::SERVICE_UNAVAILABLE,
             Json(serde_json::json!({ "error": e.to_string() })),
         )
@@ -29,37 +143,42 @@ pub async fn list_models(State(state): State<Arc<AppState>>) -> impl IntoRespons
 
 /// POST /api/models/pull
 /// Streams the Ollama pull response so the client gets progress lines in real time.
+/// A failed pull ends the stream with `{error, code, done: true}`, `code` being
+/// `not_found`, `disk_full`, `network` or `unknown`, and is recorded in the
+/// pull queue's history. With `schedule` or `not_before` the pull is queued instead.
+#[utoipa::path(
+    post,
+    path = "/api/models/pull",
+    tag = "models",
+    request_body = PullModelRequest,
+    responses(
+        (status = 200, description = "Ollama pull progress", content_type = "application/x-ndjson", body = String),
+        (status = 202, description = "`{ok, job}`: queued", body = serde_json::Value),
+        (status = 400, body = ErrorResponse),
+        (status = 404, description = "`{error, code}`: no such model in the registry", body = serde_json::Value),
+        (status = 422, description = "Not a valid model name", body = ErrorResponse),
+        (status = 502, description = "`{error, code}`: Ollama or the registry unreachable", body = serde_json::Value),
+        (status = 507, description = "`{error, code}`: Ollama's disk is full", body = serde_json::Value),
+    )
+)]
 pub async fn pull_model(
     State(state): State<Arc<AppState>>,
     Json(req): Json<PullModelRequest>,
 ) -> impl IntoResponse {
-    // Validate model name: only safe chars, max 200 chars (VULN-21)
-    let name_ok = !req.name.is_empty()
-        && req.name.len() <= 200
-        && req.name.chars().all(|c| c.is_ascii_alphanumeric() || ":-./_".contains(c));
-    if !name_ok {
-        return Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .header("Content-Type", "application/json")
-            .body(Body::from(
-                serde_json::json!({ "error": "Invalid model name" }).to_string(),
-            ))
-            .unwrap_or_else(|_| {
-                Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(Body::empty())
-                    .unwrap()
-            });
-    }
-
-    match state.ollama.pull_model_stream(&req.name).await {
-        Ok(response) => {
-            let status = response.status();
-            // Convert the reqwest byte stream into an axum Body so we stream
-            // progress NDJSON lines to the client without buffering the whole body.
-            let stream = response.bytes_stream();
-            Response::builder()
-                .status(status)
+    if let Some(e) = pull_name_error(&req.name) {
+        return (StatusCode::UNPROCESSABLE_ENTITY, Json(serde_json::json!({ "error": e }))).into_response();
+    }
+
+    if req.schedule.is_some() || req.not_before.is_some() {
+        return queue_pull(&state, req).await;
+    }
+
+    let refused = match state.ollama.pull_model_stream(&req.name).await {
+        Ok(response) if response.status().is_success() => {
+            // Relay the reqwest byte stream line by line, without buffering
+            // the whole body, so a failure can end it with its code.
+            let stream = state.pulls.clone().relay(&req.name, response);
+            return Response::builder()
                 .header("Content-Type", "application/x-ndjson")
                 .body(Body::from_stream(stream))
                 .unwrap_or_else(|_| {
@@ -67,24 +186,187 @@ pub async fn pull_model(
                         .status(StatusCode::INTERNAL_SERVER_ERROR)
                         .body(Body::empty())
                         .unwrap()
-                })
+                });
+        }
+        Ok(response) => PullFailure::refused(&req.name, response).await,
+        Err(e) => PullFailure::network(format!("Ollama unreachable: {}", e)),
+    };
+    let status = match refused.code {
+        PullErrorCode::NotFound => StatusCode::NOT_FOUND,
+        PullErrorCode::DiskFull => StatusCode::INSUFFICIENT_STORAGE,
+        PullErrorCode::Network | PullErrorCode::Unknown => StatusCode::BAD_GATEWAY,
+    };
+    let body = serde_json::json!({ "error": refused.message, "code": refused.code });
+    state.pulls.record_refused(&req.name, refused).await;
+    (status, Json(body)).into_response()
+}
+
+async fn queue_pull(state: &AppState, req: PullModelRequest) -> Response {
+    let not_before = match req.not_before.as_deref().map(chrono::DateTime::parse_from_rfc3339) {
+        None => None,
+        Some(Ok(t)) => Some(t.to_rfc3339()),
+        Some(Err(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": "not_before must be an RFC 3339 timestamp" })),
+            )
+                .into_response()
         }
-        Err(e) => Response::builder()
-            .status(StatusCode::BAD_GATEWAY)
-            .header("Content-Type", "application/json")
-            .body(Body::from(
-                serde_json::json!({ "error": e.to_string() }).to_string(),
-            ))
-            .unwrap_or_else(|_| {
-                Response::builder()
-                    .status(StatusCode::BAD_GATEWAY)
-                    .body(Body::empty())
-                    .unwrap()
-            }),
+    };
+    let job = ModelPull {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: req.name,
+        status: "pending".into(),
+        off_peak: req.schedule == Some(PullSchedule::OffPeak),
+        not_before,
+        completed_bytes: None,
+        total_bytes: None,
+        error: None,
+        error_code: None,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        started_at: None,
+        finished_at: None,
+    };
+    if let Err(e) = queries::insert_model_pull(&state.pool, &job).await {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response();
     }
+    state.pulls.wake();
+    (StatusCode::ACCEPTED, Json(serde_json::json!({ "ok": true, "job": job }))).into_response()
+}
+
+/// GET /api/models/pull/queue
+/// Pending and running pulls in queue order, then the latest finished ones.
+#[utoipa::path(
+    get,
+    path = "/api/models/pull/queue",
+    tag = "models",
+    responses(
+        (status = 200, description = "`{jobs: [ModelPull]}`", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_pull_queue(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match queries::list_model_pulls(&state.pool).await {
+        Ok(jobs) => Json(serde_json::json!({ "jobs": jobs })).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// DELETE /api/models/pull/queue/:id
+/// Cancel a queued pull, or stop it if it is running.
+#[utoipa::path(
+    delete,
+    path = "/api/models/pull/queue/{id}",
+    tag = "models",
+    params(("id" = String, Path, description = "Pull job ID")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "The pull has already finished", body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn cancel_queued_pull(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match queries::get_model_pull(&state.pool, &id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Pull job not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    }
+    match state.pulls.cancel(&id).await {
+        Ok(true) => Json(serde_json::json!({ "ok": true })).into_response(),
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({ "error": "Pull has already finished" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// Ollama model names: only safe chars, max 200 chars (VULN-21)
+/// Why `name` can't be pulled: Ollama takes `[host/][namespace/]model[:tag]`,
+/// each part starting with a letter or digit, then letters, digits, `_`,
+/// `.` or `-`; the host may carry a `:port`.
+fn pull_name_error(name: &str) -> Option<String> {
+    let word = |part: &str, extra: &str| {
+        part.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c) || extra.contains(c))
+    };
+    if name.is_empty() || name.len() > 200 {
+        return Some("Model names are 1 to 200 characters".into());
+    }
+    let (path, tag) = match name.rsplit_once(':') {
+        Some((path, tag)) if !tag.contains('/') => (path, Some(tag)),
+        _ => (name, None),
+    };
+    if let Some(tag) = tag {
+        if tag.len() > 128 || !(tag.starts_with('_') || word(tag, "")) {
+            return Some(format!(
+                "Invalid tag '{}': up to 128 letters, digits, '_', '.' or '-', not starting with '.' or '-'",
+                tag
+            ));
+        }
+    }
+    let parts: Vec<&str> = path.split('/').collect();
+    if parts.len() > 3 {
+        return Some(format!("'{}' has too many parts: use [host/][namespace/]model[:tag]", name));
+    }
+    for (i, part) in parts.iter().enumerate() {
+        let is_host = parts.len() == 3 && i == 0;
+        if !word(part, if is_host { ":" } else { "" }) || part.contains("..") {
+            return Some(format!(
+                "Invalid name part '{}': use letters, digits, '_', '.' or '-', starting with a letter or digit",
+                part
+            ));
+        }
+    }
+    None
+}
+
+fn valid_model_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 200
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || ":-./_".contains(c))
 }
 
 /// DELETE /api/models/:name
+#[utoipa::path(
+    delete,
+    path = "/api/models/{name}",
+    tag = "models",
+    params(("name" = String, Path, description = "Ollama model name")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 502, body = ErrorResponse),
+    )
+)]
 pub async fn delete_model(
     State(state): State<Arc<AppState>>,
     Path(name): Path<String>,
@@ -100,10 +382,453 @@ pub async fn delete_model(
 }
 
 /// GET /api/ollama/status
+#[utoipa::path(
+    get,
+    path = "/api/ollama/status",
+    tag = "models",
+    responses(
+        (status = 200, description = "`{running, host, mode}`; `mode` is an `OllamaMode`", body = serde_json::Value),
+    )
+)]
 pub async fn ollama_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     let running = state.ollama.is_healthy().await;
     Json(serde_json::json!({
         "running": running,
-        "host": state.ollama.host,
+        "host": state.ollama.host(),
+        "mode": state.ollama.mode(),
     }))
 }
+
+/// `GET /api/ollama/ps`
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RunningModels {
+    pub models: Vec<OllamaRunningModel>,
+}
+
+/// GET /api/ollama/ps — models Ollama keeps in memory after a request, with
+/// the part in GPU memory and when each is due to be unloaded. On this host
+/// their size also counts as allocated in `/api/gpu`.
+#[utoipa::path(
+    get,
+    path = "/api/ollama/ps",
+    tag = "models",
+    responses(
+        (status = 200, body = RunningModels),
+        (status = 503, description = "Ollama unreachable", body = ErrorResponse),
+    )
+)]
+pub async fn ollama_ps(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.ollama.list_running().await {
+        Ok(models) => Json(RunningModels { models }).into_response(),
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": format!("Ollama unreachable: {}", e) })),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UnloadRequest {
+    /// Ollama model name, as `/api/ollama/ps` lists it
+    pub model: String,
+}
+
+/// POST /api/ollama/unload — evict a model from memory now instead of
+/// waiting for its `keep_alive` to run out.
+#[utoipa::path(
+    post,
+    path = "/api/ollama/unload",
+    tag = "models",
+    request_body = UnloadRequest,
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 502, body = ErrorResponse),
+    )
+)]
+pub async fn ollama_unload(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UnloadRequest>,
+) -> impl IntoResponse {
+    if !valid_model_name(&req.model) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "Invalid model name" })),
+        )
+            .into_response();
+    }
+    match state.ollama.unload(&req.model).await {
+        Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+// ─── Ollama native API ───────────────────────────────────────────────────────
+
+/// POST /api/ollama/chat
+/// Ollama's native `/api/chat`, streamed through as NDJSON (or a single JSON
+/// object with `"stream": false`). Keeps options such as `keep_alive` and
+/// `format` that the OpenAI-compatible `/v1` endpoints don't carry.
+#[utoipa::path(
+    post,
+    path = "/api/ollama/chat",
+    tag = "models",
+    request_body(content = serde_json::Value, description = "Ollama chat request"),
+    responses(
+        (status = 200, description = "Ollama chat response", content_type = "application/x-ndjson", body = String),
+        (status = 400, body = ErrorResponse),
+        (status = 404, description = "The model isn't pulled", body = ErrorResponse),
+        (status = 502, body = ErrorResponse),
+        (status = 503, body = ErrorResponse),
+    )
+)]
+pub async fn ollama_chat(
+    State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    proxy_native(&state, peer, "/api/chat", body).await
+}
+
+/// POST /api/ollama/generate
+/// Ollama's native `/api/generate`; see `ollama_chat`.
+#[utoipa::path(
+    post,
+    path = "/api/ollama/generate",
+    tag = "models",
+    request_body(content = serde_json::Value, description = "Ollama generate request"),
+    responses(
+        (status = 200, description = "Ollama generate response", content_type = "application/x-ndjson", body = String),
+        (status = 400, body = ErrorResponse),
+        (status = 404, description = "The model isn't pulled", body = ErrorResponse),
+        (status = 502, body = ErrorResponse),
+        (status = 503, body = ErrorResponse),
+    )
+)]
+pub async fn ollama_generate(
+    State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Json(body): Json<serde_json::Value>,
+) -> Response {
+    proxy_native(&state, peer, "/api/generate", body).await
+}
+
+async fn proxy_native(
+    state: &AppState,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    path: &str,
+    body: serde_json::Value,
+) -> Response {
+    let error = |status: StatusCode, msg: String| {
+        (status, Json(serde_json::json!({ "error": msg }))).into_response()
+    };
+
+    let Some(model) = body.get("model").and_then(|m| m.as_str()).map(str::to_string) else {
+        return error(StatusCode::BAD_REQUEST, "model is required".into());
+    };
+    if !valid_model_name(&model) {
+        return error(StatusCode::BAD_REQUEST, "Invalid model name".into());
+    }
+
+    match state.ollama.has_model(&model).await {
+        Ok(true) => {}
+        Ok(false) => {
+            let auto_pull = state.settings.get_bool(Key::AutoPullMissingModels);
+            // Without connection info the caller can't be identified, so no pull
+            let may_pull = match peer {
+                Some(ConnectInfo(addr)) => {
+                    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+                    svc.can_pull_models(addr.ip()).await.unwrap_or(false)
+                }
+                None => false,
+            };
+            if !(auto_pull && may_pull) {
+                let hint = if auto_pull {
+                    "your role can't pull models"
+                } else {
+                    "pull it with POST /api/models/pull first"
+                };
+                return error(
+                    StatusCode::NOT_FOUND,
+                    format!("Model '{}' is not available in Ollama; {}", model, hint),
+                );
+            }
+            tracing::info!("Pulling missing Ollama model '{}'", model);
+            if let Err(e) = state.ollama.pull_model(&model).await {
+                return error(StatusCode::BAD_GATEWAY, e.to_string());
+            }
+        }
+        Err(e) => return error(StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+    }
+
+    match state.ollama.proxy_post_stream(path, &body).await {
+        Ok(response) => {
+            let status = response.status();
+            let content_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("application/x-ndjson")
+                .to_string();
+            Response::builder()
+                .status(status)
+                .header("Content-Type", content_type)
+                .body(Body::from_stream(response.bytes_stream()))
+                .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+        Err(e) => error(StatusCode::BAD_GATEWAY, e.to_string()),
+    }
+}
+
+// ─── Model aliases ───────────────────────────────────────────────────────────
+
+/// Aliases are what OpenAI-compatible clients see as the model id, so keep
+/// them short and free of path separators.
+pub(super) fn validate_alias(alias: &str) -> Result<(), String> {
+    if alias.is_empty() || alias.len() > 64 {
+        return Err("Alias must be 1-64 characters".into());
+    }
+    if !alias.chars().all(|c| c.is_ascii_alphanumeric() || "-._:".contains(c)) {
+        return Err("Alias may only contain letters, digits, '-', '.', '_' and ':'".into());
+    }
+    Ok(())
+}
+
+/// The alias must point at a valid, existing .gguf file; for a split model,
+/// its first part with all the others next to it.
+fn validate_alias_target(path: &str) -> Result<(), String> {
+    validate_model_path(path).map_err(|e| e.to_string())?;
+    if !std::path::Path::new(path).is_file() {
+        return Err(format!("Model file not found: {}", path));
+    }
+    split::model_files(std::path::Path::new(path)).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Name shown to API clients for a model path: its alias if one exists,
+/// otherwise the file name without extension, or without the part suffix
+/// for a split model, so its parts show as one model.
+pub fn public_model_name(path: &str, aliases_by_path: &HashMap<String, String>) -> String {
+    if let Some(alias) = aliases_by_path.get(path) {
+        return alias.clone();
+    }
+    let p = std::path::Path::new(path);
+    if let Some(s) = p.file_name().and_then(|n| n.to_str()).and_then(split::parse) {
+        return s.base.to_string();
+    }
+    if p.extension().is_some_and(|e| e.eq_ignore_ascii_case("gguf")) || path.contains('/') {
+        if let Some(stem) = p.file_stem().and_then(|s| s.to_str()) {
+            return stem.to_string();
+        }
+    }
+    path.to_string()
+}
+
+/// Turn an alias into its model path. Absolute paths pass through unchanged.
+pub async fn resolve_model_path(pool: &SqlitePool, name: &str) -> Result<String, String> {
+    if name.starts_with('/') {
+        return Ok(name.to_string());
+    }
+    match queries::get_model_alias(pool, name).await {
+        Ok(Some(a)) => Ok(a.path),
+        Ok(None) => Err(format!("Unknown model alias '{}'", name)),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// path → alias, for rewriting model lists.
+pub async fn aliases_by_path(pool: &SqlitePool) -> HashMap<String, String> {
+    queries::list_model_aliases(pool)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|a| (a.path, a.alias))
+        .collect()
+}
+
+/// GET /api/models/aliases
+#[utoipa::path(
+    get,
+    path = "/api/models/aliases",
+    tag = "models",
+    responses(
+        (status = 200, description = "`{aliases: [ModelAlias], adapters: [AdapterFile]}`. `adapters` are LoRA files found beside the aliased models or in the models directory", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_aliases(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match queries::list_model_aliases(&state.pool).await {
+        Ok(aliases) => {
+            let dirs = store::model_dirs(&state.pool).await;
+            let dirs: Vec<&std::path::Path> = dirs.iter().map(PathBuf::as_path).collect();
+            let adapters = lora::find(&dirs);
+            Json(serde_json::json!({ "aliases": aliases, "adapters": adapters })).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// POST /api/models/aliases
+#[utoipa::path(
+    post,
+    path = "/api/models/aliases",
+    tag = "models",
+    request_body = CreateAliasRequest,
+    responses(
+        (status = 201, body = ModelAlias),
+        (status = 400, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+    )
+)]
+pub async fn create_alias(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CreateAliasRequest>,
+) -> impl IntoResponse {
+    let alias = req.alias.trim().to_string();
+    if let Err(msg) = validate_alias(&alias).and_then(|_| validate_alias_target(&req.path)) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+            .into_response();
+    }
+
+    if let Some(conflict) = alias_conflict(&state.pool, &alias, &req.path).await {
+        return (StatusCode::CONFLICT, Json(serde_json::json!({ "error": conflict })))
+            .into_response();
+    }
+
+    let entry = ModelAlias {
+        alias,
+        path: req.path,
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+    match queries::insert_model_alias(&state.pool, &entry).await {
+        Ok(()) => (StatusCode::CREATED, Json(entry)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// PUT /api/models/aliases/:alias
+#[utoipa::path(
+    put,
+    path = "/api/models/aliases/{alias}",
+    tag = "models",
+    params(("alias" = String, Path, description = "Model alias")),
+    request_body = UpdateAliasRequest,
+    responses(
+        (status = 200, body = ModelAlias),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+    )
+)]
+pub async fn update_alias(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+    Json(req): Json<UpdateAliasRequest>,
+) -> impl IntoResponse {
+    let existing = match queries::get_model_alias(&state.pool, &alias).await {
+        Ok(Some(a)) => a,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Alias not found" })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+
+    if let Err(msg) = validate_alias_target(&req.path) {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+            .into_response();
+    }
+    if let Ok(Some(other)) = queries::get_model_alias_by_path(&state.pool, &req.path).await {
+        if other.alias != existing.alias {
+            return (
+                StatusCode::CONFLICT,
+                Json(serde_json::json!({
+                    "error": format!("{} already has alias '{}'", req.path, other.alias)
+                })),
+            )
+                .into_response();
+        }
+    }
+
+    match queries::update_model_alias_path(&state.pool, &existing.alias, &req.path).await {
+        Ok(()) => Json(ModelAlias { path: req.path, ..existing }).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// DELETE /api/models/aliases/:alias
+#[utoipa::path(
+    delete,
+    path = "/api/models/aliases/{alias}",
+    tag = "models",
+    params(("alias" = String, Path, description = "Model alias")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn delete_alias(
+    State(state): State<Arc<AppState>>,
+    Path(alias): Path<String>,
+) -> impl IntoResponse {
+    match queries::delete_model_alias(&state.pool, &alias).await {
+        Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// Describe why `alias` → `path` would collide with an existing alias, if it would.
+pub(super) async fn alias_conflict(pool: &SqlitePool, alias: &str, path: &str) -> Option<String> {
+    if let Ok(Some(existing)) = queries::get_model_alias(pool, alias).await {
+        return Some(format!("Alias '{}' already exists", existing.alias));
+    }
+    if let Ok(Some(existing)) = queries::get_model_alias_by_path(pool, path).await {
+        return Some(format!("{} already has alias '{}'", path, existing.alias));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pull_names_follow_ollama_references() {
+        for ok in ["llama3", "llama3:8b-instruct-q4_K_M", "library/qwen2.5:0.5b", "hf.co/bartowski/Llama-3.2-1B-GGUF:Q4_K_M", "localhost:5000/team/model"] {
+            assert_eq!(pull_name_error(ok), None, "{}", ok);
+        }
+        for bad in ["", "llama3:", "-llama", "a b", "../etc/passwd", "a/b/c/d", "llama3:8b!", "team:1/model", &"x".repeat(201)] {
+            assert!(pull_name_error(bad).is_some(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use serde::Serialize;
+use std::sync::{Arc, OnceLock};
+use utoipa::{OpenApi, ToSchema};
+use utoipa_swagger_ui::Config;
+
+use crate::{
+    api::{
+        agent, backends, cluster, devices, errors, gpu, install, keys, logs, model_files, model_store, model_uploads, models, permissions, presets,
+        resume::{self, LastSession, ResumeResponse, SkippedDevice},
+        settings, setup, system, webhooks, ws_handler,
+    },
+    api_keys::KeyScope,
+    db::{
+        backup::Backup,
+        models::{
+            Allocation, ApiKey, BackendProfile, Device, DeviceActivity, DeviceHealthEvent, DeviceImport, InferencePreset, ModelAlias, ModelPull,
+            PresetOptions, ProviderAllocation, Role, SavedDevice, TrashedModel,
+        },
+    },
+    disk::{DiskSpace, PartialFile},
+    doctor::{Check, CheckStatus, Report},
+    errors::{ErrorEntry, Severity},
+    inventory::{GpuDriver, HostInventory, SystemInfo},
+    listen::{BindSource, ListenReport},
+    logs::{
+        output::{LogConfig, LogFormat},
+        LogLine,
+    },
+    permissions::{
+        import::{ImportReport, ImportResult, ImportRow},
+        restart::{RestartVia, RpcRestarted},
+        DeviceKind, DeviceStatus, EndpointCheck, RpcStatus, Verification,
+    },
+    llama_cpp::{
+        admission::{LaneStats, Priority},
+        health::HealthReason,
+        rpc_probe::ProbeDepth,
+        binary::{BinariesInfo, BinaryInfo},
+        lora::{AdapterFile, LoraAdapter},
+        oom::OomFallback,
+        store,
+        DeviceMemory, DraftModel, FitStatus, FitSuggestion, GpuSelector, InferenceMetrics, InferenceSessionInfo, LaunchPlan, LlamaCppStatus,
+        ModelAnalysis, RpcBackend, RpcLaunch, SplitMode, SuggestionKind,
+    },
+    memory::{
+        hotplug::{ProviderInfo, ProvidersChange},
+        GpuKind, MemorySnapshot,
+    },
+    ollama::{pulls::PullErrorCode, OllamaMode, OllamaModel, OllamaModelDetails, OllamaRunningModel},
+    overview::{DeviceCounts, InferenceSummary, MemoryTotals, OllamaSummary, Overview},
+    settings::limits::{EffectiveLimit, LimitSource},
+    ws::{clients::WsClientInfo, ClientMessage, Envelope, LayerAssignment, WsEvent},
+};
+
+/// Where the spec is served; the Swagger UI loads it from here.
+const SPEC_PATH: &str = "/api/openapi.json";
+
+// ─── Response envelopes ──────────────────────────────────────────────────────
+//
+// Handlers build most responses with `json!`; these types only describe them.
+
+/// Error body returned by every `/api` endpoint.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// Body of endpoints that only acknowledge success.
+#[derive(Serialize, ToSchema)]
+pub struct OkResponse {
+    pub ok: bool,
+}
+
+/// `GET /api/devices`
+#[derive(Serialize, ToSchema)]
+pub struct DeviceList {
+    /// Device revision the list reflects
+    pub rev: i64,
+    pub devices: Vec<Device>,
+    /// Echoed when the request carried `since_rev`
+    pub since_rev: Option<i64>,
+    /// With `since_rev`: IDs deleted since, or no longer matching the filter
+    pub removed: Option<Vec<String>>,
+}
+
+/// `GET /api/gpu` and `POST /api/gpu/refresh`
+#[derive(Serialize, ToSchema)]
+pub struct GpuStats {
+    pub providers: Vec<MemorySnapshot>,
+    pub count: usize,
+    /// Age of the oldest snapshot, in seconds
+    pub stale_secs: u64,
+}
+
+/// API view of a webhook; the signing secret is never returned.
+#[derive(Serialize, ToSchema)]
+pub struct WebhookView {
+    pub id: String,
+    pub url: String,
+    /// Subscribed event types (empty = all notable events)
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub secret_set: bool,
+    pub created_at: String,
+}
+
+/// 409 from the start endpoints when a llama.cpp port is taken.
+#[derive(Serialize, ToSchema)]
+pub struct PortInUseError {
+    /// Always `port_in_use`
+    pub error: String,
+    pub port: u16,
+    /// `"name (pid N)"` when the holder could be identified
+    pub holder: Option<String>,
+    pub message: String,
+}
+
+// ─── Spec ────────────────────────────────────────────────────────────────────
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "SharedLLM API",
+        description = "REST API of the SharedLLM dashboard backend. Live updates are pushed over \
+                       `GET /ws` as `WsEvent` JSON messages."
+    ),
+    paths(
+        ws_handler::ws_handler,
+        ws_handler::list_ws_clients,
+        ws_handler::close_ws_client,
+        devices::list_devices,
+        devices::add_device,
+        devices::import_devices,
+        devices::list_device_imports,
+        devices::get_device,
+        devices::delete_device,
+        devices::approve_device,
+        devices::deny_device,
+        devices::suspend_device,
+        devices::restore_device,
+        devices::allocate_memory,
+        devices::set_memory_stats,
+        devices::update_device,
+        devices::set_device_tags,
+        devices::device_health,
+        devices::reset_device_health,
+        devices::restart_device_rpc,
+        gpu::get_gpu_stats,
+        gpu::refresh_gpu_stats,
+        gpu::redetect_providers,
+        models::list_models,
+        models::pull_model,
+        models::list_pull_queue,
+        models::cancel_queued_pull,
+        models::list_aliases,
+        models::create_alias,
+        models::update_alias,
+        models::delete_alias,
+        models::delete_model,
+        models::model_details,
+        model_files::list_model_files,
+        model_files::serve_model_file,
+        model_files::fetch_from_peer,
+        model_uploads::init_upload,
+        model_uploads::upload_status,
+        model_uploads::upload_chunk,
+        model_uploads::finalize_upload,
+        model_store::model_usage,
+        model_store::delete_model_file,
+        models::ollama_status,
+        models::ollama_ps,
+        models::ollama_unload,
+        models::ollama_chat,
+        models::ollama_generate,
+        permissions::list_roles,
+        permissions::create_role,
+        permissions::update_role,
+        permissions::delete_role,
+        settings::list_settings,
+        settings::update_setting,
+        setup::setup_status,
+        setup::setup_init,
+        webhooks::list_webhooks,
+        webhooks::create_webhook,
+        webhooks::update_webhook,
+        webhooks::delete_webhook,
+        webhooks::test_webhook,
+        keys::list_keys,
+        keys::create_key,
+        keys::update_key,
+        keys::revoke_key,
+        backends::get_backend_config,
+        backends::set_backend_config,
+        backends::list_backend_models,
+        backends::list_profiles,
+        backends::create_profile,
+        backends::update_profile,
+        backends::delete_profile,
+        backends::activate_profile,
+        cluster::cluster_status,
+        cluster::model_check,
+        cluster::start_inference,
+        cluster::stop_inference,
+        resume::resume_last_session,
+        presets::list_presets,
+        presets::create_preset,
+        presets::update_preset,
+        presets::delete_preset,
+        presets::start_preset,
+        cluster::inference_status,
+        cluster::inference_metrics,
+        cluster::start_rpc_server,
+        cluster::stop_rpc_server,
+        cluster::cluster_ports,
+        cluster::cluster_binaries,
+        install::install_binaries,
+        cluster::models_proxy,
+        cluster::chat_completions_proxy,
+        agent::heartbeat,
+        agent::install_script,
+        agent::uninstall_script,
+        agent::agent_info,
+        logs::recent_logs,
+        errors::recent_errors,
+        system::health,
+        system::overview,
+        system::system_info,
+        system::diagnostics,
+        system::disk_report,
+        system::disk_cleanup,
+        system::list_backups,
+        system::restore_backup,
+        system::support_bundle,
+        openapi_json,
+        swagger_ui_index,
+    ),
+    components(schemas(
+        ErrorResponse,
+        OkResponse,
+        DeviceList,
+        GpuStats,
+        WebhookView,
+        ApiKey,
+        keys::UpsertApiKeyRequest,
+        keys::CreatedApiKey,
+        KeyScope,
+        PortInUseError,
+        Device,
+        devices::DeviceWithDetail,
+        devices::DeviceDetail,
+        devices::RpcFreshness,
+        ProviderAllocation,
+        DeviceActivity,
+        DeviceHealthEvent,
+        DeviceImport,
+        RpcRestarted,
+        ImportRow,
+        ImportResult,
+        ImportReport,
+        RestartVia,
+        HealthReason,
+        Role,
+        Allocation,
+        ModelAlias,
+        ModelPull,
+        PullErrorCode,
+        models::PullSchedule,
+        BackendProfile,
+        OllamaModel,
+        OllamaModelDetails,
+        OllamaRunningModel,
+        models::RunningModels,
+        models::UnloadRequest,
+        model_files::SharedModelFile,
+        model_files::FetchFromPeerRequest,
+        model_uploads::InitUploadRequest,
+        model_uploads::UploadStatus,
+        model_store::ModelUsage,
+        model_store::DeletedModel,
+        store::DirUsage,
+        store::ModelFile,
+        TrashedModel,
+        settings::SettingsView,
+        EffectiveLimit,
+        LimitSource,
+        OllamaMode,
+        LogLine,
+        LogConfig,
+        LogFormat,
+        ListenReport,
+        BindSource,
+        ErrorEntry,
+        Severity,
+        DiskSpace,
+        PartialFile,
+        DeviceStatus,
+        DeviceKind,
+        RpcStatus,
+        ProbeDepth,
+        BinaryInfo,
+        BinariesInfo,
+        Verification,
+        EndpointCheck,
+        Report,
+        Check,
+        CheckStatus,
+        SystemInfo,
+        cluster::DeviceSort,
+        GpuDriver,
+        HostInventory,
+        system::DiskLocation,
+        system::DiskReport,
+        system::BackupList,
+        system::RestoreBackupRequest,
+        system::RestoreStarted,
+        Backup,
+        Overview,
+        MemoryTotals,
+        DeviceCounts,
+        InferenceSummary,
+        OllamaSummary,
+        GpuKind,
+        MemorySnapshot,
+        ProviderInfo,
+        ProvidersChange,
+        gpu::RedetectResponse,
+        FitStatus,
+        ModelAnalysis,
+        DeviceMemory,
+        FitSuggestion,
+        SuggestionKind,
+        InferenceSessionInfo,
+        InferenceMetrics,
+        LaneStats,
+        Priority,
+        GpuSelector,
+        SplitMode,
+        RpcBackend,
+        RpcLaunch,
+        DraftModel,
+        LoraAdapter,
+        AdapterFile,
+        OomFallback,
+        LaunchPlan,
+        LlamaCppStatus,
+        cluster::DeviceProbe,
+        LastSession,
+        SkippedDevice,
+        ResumeResponse,
+        InferencePreset,
+        PresetOptions,
+        SavedDevice,
+        presets::UpsertPresetRequest,
+        presets::StartPresetRequest,
+        presets::DeviceChange,
+        WsEvent,
+        LayerAssignment,
+        Envelope,
+        WsClientInfo,
+        ClientMessage,
+    )),
+    tags(
+        (name = "devices", description = "Discovered and manually added devices"),
+        (name = "gpu", description = "Local memory providers"),
+        (name = "models", description = "Ollama models and model aliases"),
+        (name = "permissions", description = "Roles"),
+        (name = "settings", description = "Runtime settings"),
+        (name = "setup", description = "First-run setup"),
+        (name = "webhooks", description = "Outgoing event notifications"),
+        (name = "api-keys", description = "Client keys for the OpenAI-compatible endpoints"),
+        (name = "backends", description = "Inference backend configuration and profiles"),
+        (name = "cluster", description = "Distributed inference with llama.cpp RPC"),
+        (name = "openai", description = "OpenAI-compatible proxy to the active backend"),
+        (name = "agent", description = "Agent install scripts and heartbeats"),
+        (name = "ws", description = "WebSocket event stream"),
+        (name = "logs", description = "Recent backend log lines"),
+        (name = "system", description = "Disk space for downloads"),
+        (name = "docs", description = "This specification"),
+    )
+)]
+pub struct ApiDoc;
+
+/// GET /api/openapi.json
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "docs",
+    responses((status = 200, description = "OpenAPI 3.1 document", body = serde_json::Value))
+)]
+pub async fn openapi_json() -> impl IntoResponse {
+    static SPEC: OnceLock<String> = OnceLock::new();
+    let spec = SPEC.get_or_init(|| ApiDoc::openapi().to_json().unwrap_or_default());
+    ([(header::CONTENT_TYPE, "application/json")], spec.as_str())
+}
+
+/// GET /api/docs — the UI's assets are relative, so it lives under `/api/docs/`.
+#[utoipa::path(
+    get,
+    path = "/api/docs",
+    tag = "docs",
+    responses((status = 308, description = "Redirect to the Swagger UI at `/api/docs/`"))
+)]
+pub async fn swagger_ui_index() -> Redirect {
+    Redirect::permanent("/api/docs/")
+}
+
+/// GET /api/docs/*file — Swagger UI assets, vendored into the binary.
+pub async fn swagger_ui(file: Option<Path<String>>) -> Response {
+    static CONFIG: OnceLock<Arc<Config<'static>>> = OnceLock::new();
+    let config = CONFIG.get_or_init(|| Arc::new(Config::from(SPEC_PATH)));
+    let file = file.map(|Path(f)| f).unwrap_or_default();
+
+    match utoipa_swagger_ui::serve(&file, config.clone()) {
+        Ok(Some(asset)) => (
+            [(header::CONTENT_TYPE, asset.content_type)],
+            asset.bytes.into_owned(),
+        )
+            .into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every `.route("…", …)` registered in main.rs, in OpenAPI path syntax.
+    fn registered_routes() -> Vec<(String, String)> {
+        let main = include_str!("../main.rs");
+        let mut routes = Vec::new();
+        for line in main.lines().map(str::trim) {
+            let Some(rest) = line.strip_prefix(".route(\"") else {
+                continue;
+            };
+            let (path, handler) = rest.split_once("\", ").unwrap();
+            let method = handler.split('(').next().unwrap().to_string();
+            let path = path
+                .split('/')
+                .map(|seg| match seg.strip_prefix(':') {
+                    Some(param) => format!("{{{}}}", param),
+                    None => seg.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            routes.push((path, method));
+        }
+        routes
+    }
+
+    #[test]
+    fn spec_parses_and_covers_every_route() {
+        let json = ApiDoc::openapi().to_json().unwrap();
+        let spec: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(spec["components"]["schemas"]["WsEvent"].is_object());
+
+        let routes = registered_routes();
+        assert!(routes.len() > 50, "route table not found in main.rs");
+        for (path, method) in routes {
+            // Swagger UI assets are not part of the API
+            if path.starts_with("/api/docs/") {
+                continue;
+            }
+            assert!(
+                spec["paths"][&path][&method].is_object(),
+                "{} {} is registered but missing from the OpenAPI spec",
+                method.to_uppercase(),
+                path
+            );
+        }
+    }
+}
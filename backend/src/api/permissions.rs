@@ -1,5 +1,5 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
     Json,
@@ -8,9 +8,14 @@ use serde::Deserialize;
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::{db::{models::Role, queries}, AppState};
+use super::openapi::ErrorResponse;
+use crate::{
+    db::{models::Role, queries},
+    permissions::{DeleteRoleError, PermissionService},
+    AppState,
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct UpsertRoleRequest {
     pub name: String,
     pub max_memory_mb: i64,
@@ -19,6 +24,15 @@ pub struct UpsertRoleRequest {
 }
 
 /// GET /api/permissions/roles
+#[utoipa::path(
+    get,
+    path = "/api/permissions/roles",
+    tag = "permissions",
+    responses(
+        (status = 200, description = "`{roles: [Role]}`", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn list_roles(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     match queries::list_roles(&state.pool).await {
         Ok(roles) => Json(serde_json::json!({ "roles": roles })).into_response(),
@@ -31,6 +45,16 @@ pub async fn list_roles(State(state): State<Arc<AppState>>) -> impl IntoResponse
 }
 
 /// POST /api/permissions/roles
+#[utoipa::path(
+    post,
+    path = "/api/permissions/roles",
+    tag = "permissions",
+    request_body = UpsertRoleRequest,
+    responses(
+        (status = 201, body = Role),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn create_role(
     State(state): State<Arc<AppState>>,
     Json(req): Json<UpsertRoleRequest>,
@@ -55,6 +79,17 @@ pub async fn create_role(
 }
 
 /// PUT /api/permissions/roles/:id
+#[utoipa::path(
+    put,
+    path = "/api/permissions/roles/{id}",
+    tag = "permissions",
+    params(("id" = String, Path, description = "Role ID")),
+    request_body = UpsertRoleRequest,
+    responses(
+        (status = 200, body = Role),
+        (status = 500, body = ErrorResponse),
+    )
+)]
 pub async fn update_role(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
@@ -90,23 +125,68 @@ pub async fn update_role(
     }
 }
 
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteRoleParams {
+    /// Move devices using the role to this role before deleting it
+    pub reassign_to: Option<String>,
+}
+
 /// DELETE /api/permissions/roles/:id
+///
+/// Refused with 409 while devices use the role, unless `?reassign_to=` names
+/// a role to move them to first.
+#[utoipa::path(
+    delete,
+    path = "/api/permissions/roles/{id}",
+    tag = "permissions",
+    params(("id" = String, Path, description = "Role ID"), DeleteRoleParams),
+    responses(
+        (status = 200, description = "`{ok, reassigned: [device_id]}`", body = serde_json::Value),
+        (status = 400, description = "`reassign_to` is not another existing role", body = ErrorResponse),
+        (status = 403, description = "Built-in roles cannot be deleted", body = ErrorResponse),
+        (status = 409, description = "`{error, count, devices: [{id, name}]}` — devices still use the role", body = serde_json::Value),
+    )
+)]
 pub async fn delete_role(
     State(state): State<Arc<AppState>>,
     Path(id): Path<String>,
+    Query(params): Query<DeleteRoleParams>,
 ) -> impl IntoResponse {
-    // Prevent deleting built-in roles
-    if ["role-admin", "role-user", "role-guest"].contains(&id.as_str()) {
-        return (
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let reassign_to = params.reassign_to.as_deref().filter(|r| !r.is_empty());
+    match svc.delete_role(&id, reassign_to).await {
+        Ok(reassigned) => {
+            Json(serde_json::json!({ "ok": true, "reassigned": reassigned })).into_response()
+        }
+        Err(DeleteRoleError::BuiltIn) => (
             StatusCode::FORBIDDEN,
             Json(serde_json::json!({ "error": "Cannot delete built-in roles" })),
         )
-            .into_response();
-    }
-
-    match queries::delete_role(&state.pool, &id).await {
-        Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
-        Err(e) => (
+            .into_response(),
+        Err(DeleteRoleError::InUse(users)) => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "error": format!(
+                    "{} device(s) still use this role; pass ?reassign_to=<role id> to move them",
+                    users.len()
+                ),
+                "count": users.len(),
+                "devices": users
+                    .iter()
+                    .map(|(id, name)| serde_json::json!({ "id": id, "name": name }))
+                    .collect::<Vec<_>>(),
+            })),
+        )
+            .into_response(),
+        Err(DeleteRoleError::BadTarget(target)) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("reassign_to must be another existing role: {}", target)
+            })),
+        )
+            .into_response(),
+        Err(DeleteRoleError::Db(e)) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": e.to_string() })),
         )
@@ -0,0 +1,567 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::cluster::{self, StartInferenceRequest};
+use super::devices::normalize_tags;
+use super::models::resolve_model_path;
+use super::openapi::{ErrorResponse, OkResponse};
+use super::resume::SkippedDevice;
+use crate::{
+    db::{
+        models::{Device, InferencePreset, PresetOptions, SavedDevice, SavedDevices, StringList},
+        queries,
+    },
+    llama_cpp::{
+        lora, split, validate_chat_template, validate_model_path, validate_system_prompt, GpuSelector, MAX_PARALLEL,
+    },
+    permissions::{DeviceStatus, PermissionService},
+    settings::Key,
+    AppState,
+};
+
+/// Body of `POST /api/cluster/presets` and `PUT /api/cluster/presets/:id`:
+/// the fields of `POST /api/cluster/inference/start` worth keeping.
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UpsertPresetRequest {
+    pub name: String,
+    /// Absolute .gguf path or a model alias
+    pub model_path: String,
+    #[serde(default)]
+    pub device_ids: Vec<String>,
+    #[serde(default)]
+    pub device_tags: Vec<String>,
+    /// -1 = all layers on GPU (default), 0 = CPU only
+    pub n_gpu_layers: Option<i32>,
+    pub ctx_size: Option<u32>,
+    #[serde(flatten)]
+    pub options: PresetOptions,
+}
+
+/// Body of `POST /api/cluster/presets/:id/start` (optional)
+#[derive(Deserialize, Default, utoipa::ToSchema)]
+pub struct StartPresetRequest {
+    /// Validate and return the llama-server command line without starting it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// A preset device that is no longer what it was when the preset was saved.
+#[derive(Debug, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct DeviceChange {
+    pub device_id: String,
+    /// Name when saved
+    pub name: String,
+    /// e.g. `"deleted"`, `"renamed to gpu-box-2"`, `"ip 10.0.0.7 → 10.0.0.9"`
+    pub changes: Vec<String>,
+}
+
+/// Check `req` and return it with its names, IDs and tags normalized.
+/// `max_devices` is the `max_cluster_devices` limit.
+fn validate(mut req: UpsertPresetRequest, max_devices: usize) -> Result<UpsertPresetRequest, String> {
+    req.name = req.name.trim().to_string();
+    if req.name.is_empty() || req.name.len() > 64 {
+        return Err("'name' must be 1-64 characters".into());
+    }
+    req.model_path = req.model_path.trim().to_string();
+    if req.model_path.is_empty() {
+        return Err("'model_path' must be a model alias or an absolute .gguf path".into());
+    }
+
+    let mut ids: Vec<String> = Vec::new();
+    for id in &req.device_ids {
+        let id = id.trim();
+        if id.is_empty() {
+            return Err("'device_ids' entries must be non-empty device IDs".into());
+        }
+        if !ids.iter().any(|i| i == id) {
+            ids.push(id.to_string());
+        }
+    }
+    // Same cap as a start (VULN-12)
+    if ids.len() > max_devices {
+        return Err(format!("Too many device IDs (max {})", max_devices));
+    }
+    req.device_ids = ids;
+    req.device_tags = normalize_tags(&req.device_tags)?;
+
+    if req.n_gpu_layers.is_some_and(|n| n < -1) {
+        return Err("'n_gpu_layers' must be -1 (all), 0 (CPU only) or a layer count".into());
+    }
+    if req.ctx_size == Some(0) {
+        return Err("'ctx_size' must be a positive number of tokens".into());
+    }
+    let options = &mut req.options;
+    if options.parallel.is_some_and(|p| !(1..=MAX_PARALLEL).contains(&p)) {
+        return Err(format!("parallel must be between 1 and {}", MAX_PARALLEL));
+    }
+    if let Some(selector) = options.gpu_selector.clone() {
+        GpuSelector::from_value(selector)?;
+    }
+    options.draft_model_path = options
+        .draft_model_path
+        .take()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty());
+    if let (Some(min), Some(max)) = (options.draft_min, options.draft_max) {
+        if min > max {
+            return Err("draft_min must not exceed draft_max".into());
+        }
+    }
+    options.mmproj_path = options
+        .mmproj_path
+        .take()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty());
+    options.chat_template = match options.chat_template.take().filter(|t| !t.trim().is_empty()) {
+        Some(t) => Some(validate_chat_template(&t).map_err(|e| e.to_string())?),
+        None => None,
+    };
+    options.system_prompt = match options.system_prompt.take() {
+        Some(p) => validate_system_prompt(&p).map_err(|e| e.to_string())?,
+        None => None,
+    };
+    // The files are checked when the preset is started
+    if options.lora_adapters.len() > lora::MAX_ADAPTERS {
+        return Err(format!("At most {} LoRA adapters may be loaded", lora::MAX_ADAPTERS));
+    }
+    Ok(req)
+}
+
+/// What changed about each of `saved` since the preset was saved, judged
+/// against `current` (the devices that still exist, by ID).
+fn device_changes(saved: &[SavedDevice], current: &HashMap<String, Device>) -> Vec<DeviceChange> {
+    saved
+        .iter()
+        .filter_map(|s| {
+            let changes = match current.get(&s.id) {
+                None => vec!["deleted".to_string()],
+                Some(d) => {
+                    let mut changes = Vec::new();
+                    if d.name != s.name {
+                        changes.push(format!("renamed to {}", d.name));
+                    }
+                    if d.ip != s.ip {
+                        changes.push(format!("ip {} → {}", s.ip, d.ip));
+                    }
+                    let ports = d.rpc_ports();
+                    if ports != s.rpc_ports {
+                        changes.push(format!("rpc ports {:?} → {:?}", s.rpc_ports, ports));
+                    }
+                    changes
+                }
+            };
+            (!changes.is_empty()).then(|| DeviceChange {
+                device_id: s.id.clone(),
+                name: s.name.clone(),
+                changes,
+            })
+        })
+        .collect()
+}
+
+/// The preset's devices as they are now, for `saved_devices`.
+async fn snapshot_devices(state: &AppState, ids: &[String]) -> anyhow::Result<SavedDevices> {
+    let mut saved = Vec::new();
+    for id in ids {
+        if let Some(d) = queries::get_device(&state.pool, id).await? {
+            saved.push(SavedDevice::from(&d));
+        }
+    }
+    Ok(SavedDevices(saved))
+}
+
+fn bad_request(msg: String) -> Response {
+    (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg }))).into_response()
+}
+
+fn internal_error(e: anyhow::Error) -> Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "error": e.to_string() })),
+    )
+        .into_response()
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "Preset not found" })),
+    )
+        .into_response()
+}
+
+fn name_taken(name: &str) -> Response {
+    (
+        StatusCode::CONFLICT,
+        Json(serde_json::json!({ "error": format!("A preset named '{}' already exists", name) })),
+    )
+        .into_response()
+}
+
+/// Validate `req` and write it as preset `id`, keeping `created_at`.
+async fn save(state: &AppState, id: String, created_at: Option<String>, req: UpsertPresetRequest) -> Response {
+    let req = match validate(req, state.limits.get().max_cluster_devices) {
+        Ok(r) => r,
+        Err(msg) => return bad_request(msg),
+    };
+    match queries::preset_name_taken(&state.pool, &req.name, &id).await {
+        Ok(false) => {}
+        Ok(true) => return name_taken(&req.name),
+        Err(e) => return internal_error(e),
+    }
+    let saved_devices = match snapshot_devices(state, &req.device_ids).await {
+        Ok(s) => s,
+        Err(e) => return internal_error(e),
+    };
+    let now = chrono::Utc::now().to_rfc3339();
+    let created = created_at.is_none();
+    let preset = InferencePreset {
+        id,
+        name: req.name,
+        model_path: req.model_path,
+        device_ids: StringList(req.device_ids),
+        device_tags: StringList(req.device_tags),
+        n_gpu_layers: req.n_gpu_layers.map(i64::from),
+        ctx_size: req.ctx_size.map(i64::from),
+        options: req.options,
+        saved_devices,
+        created_at: created_at.unwrap_or_else(|| now.clone()),
+        updated_at: now,
+    };
+    match queries::upsert_preset(&state.pool, &preset).await {
+        Ok(()) if created => (StatusCode::CREATED, Json(preset)).into_response(),
+        Ok(()) => Json(preset).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// GET /api/cluster/presets
+#[utoipa::path(
+    get,
+    path = "/api/cluster/presets",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "`{presets: [InferencePreset]}` by name", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_presets(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match queries::list_presets(&state.pool).await {
+        Ok(presets) => Json(serde_json::json!({ "presets": presets })).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// POST /api/cluster/presets
+#[utoipa::path(
+    post,
+    path = "/api/cluster/presets",
+    tag = "cluster",
+    request_body = UpsertPresetRequest,
+    responses(
+        (status = 201, body = InferencePreset),
+        (status = 400, body = ErrorResponse),
+        (status = 409, description = "Another preset has this name", body = ErrorResponse),
+    )
+)]
+pub async fn create_preset(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UpsertPresetRequest>,
+) -> impl IntoResponse {
+    save(&state, format!("preset-{}", Uuid::new_v4()), None, req).await
+}
+
+/// PUT /api/cluster/presets/:id
+/// Replaces the preset and takes a new snapshot of its devices.
+#[utoipa::path(
+    put,
+    path = "/api/cluster/presets/{id}",
+    tag = "cluster",
+    params(("id" = String, Path, description = "Preset ID")),
+    request_body = UpsertPresetRequest,
+    responses(
+        (status = 200, body = InferencePreset),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Another preset has this name", body = ErrorResponse),
+    )
+)]
+pub async fn update_preset(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<UpsertPresetRequest>,
+) -> impl IntoResponse {
+    let existing = match queries::get_preset(&state.pool, &id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return not_found(),
+        Err(e) => return internal_error(e),
+    };
+    save(&state, id, Some(existing.created_at), req).await
+}
+
+/// DELETE /api/cluster/presets/:id
+#[utoipa::path(
+    delete,
+    path = "/api/cluster/presets/{id}",
+    tag = "cluster",
+    params(("id" = String, Path, description = "Preset ID")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn delete_preset(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match queries::delete_preset(&state.pool, &id).await {
+        Ok(true) => Json(serde_json::json!({ "ok": true })).into_response(),
+        Ok(false) => not_found(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// POST /api/cluster/presets/:id/start
+/// Starts the preset through `POST /api/cluster/inference/start`, with the
+/// same validation, reachability probes and fit analysis. Devices that were
+/// deleted, are no longer approved or trusted, or don't answer are left out
+/// as long as the model still fits without them, and a draft model that is
+/// gone is dropped. The start response gains `preset_id`, `skipped`,
+/// `skipped_draft` and `changed`.
+#[utoipa::path(
+    post,
+    path = "/api/cluster/presets/{id}/start",
+    tag = "cluster",
+    params(("id" = String, Path, description = "Preset ID")),
+    request_body(content = StartPresetRequest, description = "Optional"),
+    responses(
+        (status = 200, description = "The start response plus `{preset_id, skipped: [SkippedDevice], skipped_draft, devices_changed, changed: [DeviceChange]}`", body = serde_json::Value),
+        (status = 400, description = "The model is missing, or doesn't fit without the skipped devices", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "A llama.cpp port is taken, or another start is in progress", body = ErrorResponse),
+    )
+)]
+pub async fn start_preset(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    body: Option<Json<StartPresetRequest>>,
+) -> Response {
+    let req = body.map(|Json(r)| r).unwrap_or_default();
+    let preset = match queries::get_preset(&state.pool, &id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => return not_found(),
+        Err(e) => return internal_error(e),
+    };
+
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let min_cluster_trust = svc.trust_threshold(Key::MinClusterTrust).await;
+    let role_trust = match svc.role_trust_levels().await {
+        Ok(r) => r,
+        Err(e) => return internal_error(e),
+    };
+
+    let mut current = HashMap::new();
+    let mut device_ids = Vec::new();
+    let mut skipped = Vec::new();
+    for id in &preset.device_ids.0 {
+        let skip = |name: Option<String>, reason: String| SkippedDevice {
+            device_id: id.clone(),
+            name,
+            reason,
+        };
+        let device = match queries::get_device(&state.pool, id).await {
+            Ok(Some(d)) => d,
+            Ok(None) => {
+                skipped.push(skip(None, "not found".into()));
+                continue;
+            }
+            Err(e) => return internal_error(e),
+        };
+        let name = Some(device.name.clone());
+        if device.status != DeviceStatus::Approved {
+            skipped.push(skip(name, format!("status is {}", device.status)));
+        } else if PermissionService::effective_trust_level(&device, &role_trust) < min_cluster_trust {
+            skipped.push(skip(name, "below min_cluster_trust".into()));
+        } else {
+            device_ids.push(device.id.clone());
+        }
+        current.insert(device.id.clone(), device);
+    }
+    let changed = device_changes(&preset.saved_devices.0, &current);
+
+    let options = preset.options.clone();
+    let skipped_draft = match &options.draft_model_path {
+        None => None,
+        Some(draft) => match resolve_model_path(&state.pool, draft).await {
+            Err(msg) => Some(msg),
+            Ok(path) => match validate_model_path(&path)
+                .and_then(|()| split::model_files(std::path::Path::new(&path)))
+            {
+                Err(e) => Some(e.to_string()),
+                Ok(files) if split::total_mb(&files) == 0 => {
+                    Some("Draft model file not found or is empty".to_string())
+                }
+                Ok(_) => None,
+            },
+        },
+    };
+    let left_out = skipped
+        .iter()
+        .map(|s| s.name.clone().unwrap_or_else(|| s.device_id.clone()))
+        .collect();
+
+    let start = StartInferenceRequest {
+        model_path: preset.model_path.clone(),
+        device_ids,
+        device_tags: preset.device_tags.0.clone(),
+        n_gpu_layers: preset.n_gpu_layers.map(|n| n as i32),
+        ctx_size: preset.ctx_size.map(|n| n as u32),
+        parallel: options.parallel,
+        cont_batching: options.cont_batching,
+        gpu_selector: options.gpu_selector,
+        draft_model_path: options.draft_model_path.filter(|_| skipped_draft.is_none()),
+        draft_max: options.draft_max,
+        draft_min: options.draft_min,
+        mmproj_path: options.mmproj_path,
+        chat_template: options.chat_template,
+        system_prompt: options.system_prompt,
+        lora_adapters: options.lora_adapters,
+        retry_on_oom: options.retry_on_oom,
+        wait: false,
+        skip_unreachable: true,
+        dry_run: req.dry_run,
+        idempotency_key: None,
+        left_out,
+    };
+    let started = cluster::start_inference(State(state.clone()), HeaderMap::new(), Json(start))
+        .await
+        .into_response();
+
+    let status = started.status();
+    let Ok(bytes) = axum::body::to_bytes(started.into_body(), usize::MAX).await else {
+        return internal_error(anyhow::anyhow!("Unreadable start response"));
+    };
+    let mut body: serde_json::Value =
+        serde_json::from_slice(&bytes).unwrap_or_else(|_| serde_json::json!({}));
+    if status.is_success() {
+        skipped.extend(unreachable_devices(&body["devices"]));
+    }
+    if let Some(fields) = body.as_object_mut() {
+        fields.insert("preset_id".into(), preset.id.into());
+        fields.insert("skipped".into(), serde_json::json!(skipped));
+        fields.insert("skipped_draft".into(), skipped_draft.into());
+        fields.insert("devices_changed".into(), (!changed.is_empty()).into());
+        fields.insert("changed".into(), serde_json::json!(changed));
+    }
+    let mut response = (status, Json(body)).into_response();
+    response.headers_mut().remove(header::CONTENT_LENGTH);
+    response
+}
+
+/// Devices of a start's `devices` probes none of whose endpoints answered.
+fn unreachable_devices(probes: &serde_json::Value) -> Vec<SkippedDevice> {
+    let mut seen: Vec<(String, String, bool)> = Vec::new();
+    for p in probes.as_array().into_iter().flatten() {
+        let (Some(id), Some(name)) = (p["device_id"].as_str(), p["name"].as_str()) else {
+            continue;
+        };
+        let reachable = p["reachable"].as_bool().unwrap_or(false);
+        match seen.iter_mut().find(|(i, _, _)| i == id) {
+            Some(entry) => entry.2 |= reachable,
+            None => seen.push((id.to_string(), name.to_string(), reachable)),
+        }
+    }
+    seen.into_iter()
+        .filter(|(_, _, reachable)| !reachable)
+        .map(|(device_id, name, _)| SkippedDevice {
+            device_id,
+            name: Some(name),
+            reason: "unreachable".into(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: serde_json::Value) -> UpsertPresetRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[test]
+    fn presets_are_validated_and_normalized() {
+        let req = validate(request(serde_json::json!({
+            "name": " big model ",
+            "model_path": "llama3-70b",
+            "device_ids": ["dev-1", " dev-1", "dev-2"],
+            "device_tags": ["GPU", "gpu"],
+            "parallel": 4,
+            "draft_model_path": "  ",
+        })), 20)
+        .unwrap();
+        assert_eq!(req.name, "big model");
+        assert_eq!(req.device_ids, vec!["dev-1", "dev-2"]);
+        assert_eq!(req.device_tags, vec!["gpu"]);
+        assert_eq!(req.options.parallel, Some(4));
+        assert_eq!(req.options.draft_model_path, None);
+
+        let invalid = [
+            serde_json::json!({ "name": "", "model_path": "m" }),
+            serde_json::json!({ "name": "p", "model_path": " " }),
+            serde_json::json!({ "name": "p", "model_path": "m", "device_ids": [""] }),
+            serde_json::json!({ "name": "p", "model_path": "m", "n_gpu_layers": -2 }),
+            serde_json::json!({ "name": "p", "model_path": "m", "ctx_size": 0 }),
+            serde_json::json!({ "name": "p", "model_path": "m", "parallel": 17 }),
+            serde_json::json!({ "name": "p", "model_path": "m", "draft_min": 8, "draft_max": 4 }),
+            serde_json::json!({ "name": "p", "model_path": "m", "gpu_selector": { "main_gpu": "x" } }),
+        ];
+        for body in invalid {
+            assert!(validate(request(body.clone()), 20).is_err(), "{}", body);
+        }
+        let three = serde_json::json!({ "name": "p", "model_path": "m", "device_ids": ["a", "b", "c"] });
+        assert!(validate(request(three), 2).is_err());
+    }
+
+    #[test]
+    fn changed_devices_are_reported() {
+        let mut a = Device::new("box-a".into(), "10.0.0.7".into(), None, "manual");
+        let b = Device::new("box-b".into(), "10.0.0.8".into(), None, "manual");
+        let saved = vec![
+            SavedDevice::from(&a),
+            SavedDevice::from(&b),
+            SavedDevice { id: "gone".into(), name: "old".into(), ip: "10.0.0.9".into(), rpc_ports: vec![8181] },
+        ];
+        a.name = "box-a2".into();
+        a.ip = "10.0.0.17".into();
+        let current = HashMap::from([(a.id.clone(), a.clone()), (b.id.clone(), b)]);
+
+        let changes = device_changes(&saved, &current);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].device_id, a.id);
+        assert_eq!(changes[0].changes, vec!["renamed to box-a2", "ip 10.0.0.7 → 10.0.0.17"]);
+        assert_eq!(changes[1].changes, vec!["deleted"]);
+        assert_eq!(PresetOptions::from("nope".to_string()), PresetOptions::default());
+    }
+
+    #[test]
+    fn devices_with_no_answering_endpoint_are_unreachable() {
+        let probes = serde_json::json!([
+            { "device_id": "a", "name": "A", "address": "10.0.0.1:8181", "reachable": false },
+            { "device_id": "a", "name": "A", "address": "10.0.0.1:8182", "reachable": true },
+            { "device_id": "b", "name": "B", "address": "10.0.0.2:8181", "reachable": false },
+        ]);
+        let skipped = unreachable_devices(&probes);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].device_id, "b");
+        assert_eq!(skipped[0].reason, "unreachable");
+    }
+}
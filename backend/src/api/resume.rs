@@ -0,0 +1,375 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::time::{sleep, Duration, Instant};
+
+use super::cluster::start_error;
+use super::models::resolve_model_path;
+use super::openapi::{ErrorResponse, PortInUseError};
+use crate::{
+    db::{models::Device, queries},
+    logs::current_request_id,
+    llama_cpp::{
+        lora::{self, LoraAdapter},
+        reservations::{self, Reserved},
+        default_parallel, DraftModel, FitStatus, GpuSelector, InferenceSessionInfo,
+        validate_mmproj, LaunchOptions, LlamaCppManager,
+    },
+    permissions::{DeviceStatus, PermissionService},
+    settings::{Key, SettingsCache},
+    ws::WsEvent,
+    AppState,
+};
+
+/// Setting: re-start the last session when the backend starts.
+pub const RESUME_ON_START_KEY: &str = "resume_inference_on_start";
+/// Setting holding the parameters of the last session started through the API.
+pub const LAST_SESSION_KEY: &str = "last_inference_session";
+
+/// How long startup waits for the last session's devices to become reachable.
+const DEVICE_WAIT_SECS: u64 = 60;
+const DEVICE_POLL_SECS: u64 = 5;
+
+/// Parameters of the last successful `POST /api/cluster/inference/start`.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct LastSession {
+    /// As requested: a model alias or an absolute .gguf path
+    pub model_path: String,
+    /// Device IDs after tag expansion
+    pub device_ids: Vec<String>,
+    pub n_gpu_layers: i32,
+    pub ctx_size: u32,
+    #[serde(default = "default_parallel")]
+    pub parallel: u32,
+    #[serde(default)]
+    pub cont_batching: bool,
+    #[serde(default)]
+    pub gpu_selector: GpuSelector,
+    /// Draft model as requested (alias or path)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft: Option<DraftModel>,
+    /// Vision projector as requested (alias or path)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmproj: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lora_adapters: Vec<LoraAdapter>,
+    #[serde(default)]
+    pub retry_on_oom: bool,
+    pub started_at: String,
+}
+
+/// A device of the last session left out of the resumed one.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct SkippedDevice {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub reason: String,
+}
+
+/// `POST /api/cluster/inference/resume-last`
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ResumeResponse {
+    pub ok: bool,
+    pub session: Option<InferenceSessionInfo>,
+    pub skipped: Vec<SkippedDevice>,
+}
+
+pub enum ResumeError {
+    NothingRecorded,
+    AlreadyRunning,
+    /// Another start holds the start path
+    StartInProgress,
+    /// The recorded session can't be started as it was (missing model, no fit)
+    Unavailable(String),
+    Start(anyhow::Error),
+}
+
+impl IntoResponse for ResumeError {
+    fn into_response(self) -> Response {
+        let (status, msg) = match self {
+            ResumeError::NothingRecorded => (StatusCode::NOT_FOUND, "No previous session recorded".to_string()),
+            ResumeError::AlreadyRunning => (StatusCode::CONFLICT, "Inference is already running".to_string()),
+            ResumeError::StartInProgress => (StatusCode::CONFLICT, "A start is already in progress".to_string()),
+            ResumeError::Unavailable(msg) => (StatusCode::BAD_REQUEST, msg),
+            ResumeError::Start(e) => return start_error(e),
+        };
+        (status, Json(serde_json::json!({ "error": msg }))).into_response()
+    }
+}
+
+/// Remember `session` for `resume-last` and `resume_inference_on_start`.
+pub async fn record(settings: &SettingsCache, session: &LastSession) {
+    let value = serde_json::to_string(session).unwrap_or_default();
+    if let Err(e) = settings.set(LAST_SESSION_KEY, &value).await {
+        tracing::warn!("Failed to record last inference session: {}", e);
+    }
+}
+
+pub fn last_session(settings: &SettingsCache) -> Option<LastSession> {
+    settings
+        .get(LAST_SESSION_KEY)
+        .and_then(|v| serde_json::from_str(&v).ok())
+}
+
+/// Start the last recorded session again. Devices that are gone, no longer
+/// approved or trusted, or unreachable are left out as long as the model
+/// still fits without them. The recorded parameters are kept as they were,
+/// so a device that comes back is used on the next resume.
+pub async fn resume_last(state: &AppState) -> Result<ResumeResponse, ResumeError> {
+    let last = last_session(&state.settings).ok_or(ResumeError::NothingRecorded)?;
+    if state.llama_cpp.is_inference_running().await {
+        return Err(ResumeError::AlreadyRunning);
+    }
+    let Some(_starting) = state.llama_cpp.begin_start() else {
+        return Err(ResumeError::StartInProgress);
+    };
+
+    let model_path = resolve_model_path(&state.pool, &last.model_path)
+        .await
+        .map_err(ResumeError::Unavailable)?;
+    if !std::path::Path::new(&model_path).is_file() {
+        return Err(ResumeError::Unavailable(format!("Model file not found: {}", model_path)));
+    }
+    let draft = match &last.draft {
+        None => None,
+        Some(d) => Some(DraftModel {
+            path: resolve_model_path(&state.pool, &d.path)
+                .await
+                .map_err(ResumeError::Unavailable)?,
+            ..d.clone()
+        }),
+    };
+
+    let mmproj = match &last.mmproj {
+        None => None,
+        Some(requested) => {
+            let path = resolve_model_path(&state.pool, requested)
+                .await
+                .map_err(ResumeError::Unavailable)?;
+            validate_mmproj(&model_path, &path)
+                .map_err(|e| ResumeError::Unavailable(format!("Projector: {}", e)))?;
+            Some(path)
+        }
+    };
+
+    // An adapter may have been moved or deleted since
+    lora::validate(&last.lora_adapters).map_err(|e| ResumeError::Unavailable(e.to_string()))?;
+
+    let svc = PermissionService::new(state.pool.clone(), state.event_tx.clone());
+    let min_cluster_trust = svc.trust_threshold(Key::MinClusterTrust).await;
+    let role_trust = svc
+        .role_trust_levels()
+        .await
+        .map_err(|e| ResumeError::Unavailable(e.to_string()))?;
+    let reserved = Reserved::load(&state.pool).await;
+
+    let mut rpc_addresses = Vec::new();
+    let mut device_free_mbs = Vec::new();
+    let mut skipped = Vec::new();
+    for id in &last.device_ids {
+        let skip = |name: Option<String>, reason: &str| SkippedDevice {
+            device_id: id.clone(),
+            name,
+            reason: reason.to_string(),
+        };
+        let device = match queries::get_device(&state.pool, id).await {
+            Ok(Some(d)) => d,
+            _ => {
+                skipped.push(skip(None, "not found"));
+                continue;
+            }
+        };
+        let name = Some(device.name.clone());
+        if device.status != DeviceStatus::Approved {
+            skipped.push(skip(name, &format!("status is {}", device.status)));
+        } else if PermissionService::effective_trust_level(&device, &role_trust) < min_cluster_trust {
+            skipped.push(skip(name, "below min_cluster_trust"));
+        } else {
+            let addresses = reachable_addresses(state, &device).await;
+            if addresses.is_empty() {
+                skipped.push(skip(name, "unreachable"));
+                continue;
+            }
+            rpc_addresses.extend(addresses);
+            device_free_mbs.push(
+                (device.memory_free_mb.max(0) as u64).saturating_sub(reserved.device(&device.id)),
+            );
+        }
+    }
+
+    let draft_path = draft.as_ref().map(|d| d.path.as_str());
+    if draft_path.is_some() || !skipped.is_empty() {
+        let host_reserved = state.settings.get_string(Key::HostReservedMb);
+        let local_free_mb = crate::memory::local_snapshots(&state.providers.load(), Some(&host_reserved))
+            .await
+            .iter()
+            .map(|s| s.usable_free_mb)
+            .sum::<u64>()
+            .saturating_sub(reserved.local_total());
+        if let Some(draft_path) = draft_path {
+            LlamaCppManager::check_draft_fit(&model_path, draft_path, local_free_mb, device_free_mbs.clone())
+                .map_err(|e| ResumeError::Unavailable(e.to_string()))?;
+        }
+        let analysis = LlamaCppManager::analyze_model(&model_path, draft_path, local_free_mb, device_free_mbs)
+            .map_err(|e| ResumeError::Unavailable(e.to_string()))?;
+        if !skipped.is_empty() && analysis.fit_status == FitStatus::TooLarge {
+            let names: Vec<String> = skipped
+                .iter()
+                .map(|s| s.name.clone().unwrap_or_else(|| s.device_id.clone()))
+                .collect();
+            return Err(ResumeError::Unavailable(format!(
+                "Model needs {} MB but only {} MB is available without {}",
+                analysis.model_size_mb,
+                analysis.total_available_mb,
+                names.join(", ")
+            )));
+        }
+    }
+
+    state
+        .llama_cpp
+        .start_inference(
+            &model_path,
+            rpc_addresses,
+            LaunchOptions {
+                n_gpu_layers: last.n_gpu_layers,
+                ctx_size: last.ctx_size,
+                parallel: last.parallel,
+                cont_batching: last.cont_batching,
+                gpu_selector: last.gpu_selector.clone(),
+                draft,
+                mmproj,
+                chat_template: last.chat_template.clone(),
+                system_prompt: last.system_prompt.clone(),
+                lora_adapters: last.lora_adapters.clone(),
+                retry_on_oom: last.retry_on_oom,
+                api_key: Some(state.settings.get_string(Key::InferenceSecret)),
+            },
+        )
+        .await
+        .map_err(ResumeError::Start)?;
+
+    let session = state.llama_cpp.get_current_session().await;
+    if let Some(s) = &session {
+        let (local, _) = state.memory.cached();
+        if let Err(e) = reservations::reserve(&state.pool, &local, s).await {
+            tracing::warn!("Failed to reserve memory for session {}: {}", s.id, e);
+        }
+    }
+    Ok(ResumeResponse { ok: true, session, skipped })
+}
+
+/// Log and broadcast why the last session could not be resumed.
+fn report_failure(state: &AppState, err: &ResumeError) {
+    let reason = match err {
+        ResumeError::Unavailable(msg) => msg.clone(),
+        ResumeError::Start(e) => e.to_string(),
+        ResumeError::NothingRecorded | ResumeError::AlreadyRunning | ResumeError::StartInProgress => return,
+    };
+    tracing::warn!("Could not resume last inference session: {}", reason);
+    let _ = state.event_tx.send(WsEvent::InferenceResumeFailed {
+        reason,
+        request_id: current_request_id(),
+    });
+}
+
+// ─── POST /api/cluster/inference/resume-last ─────────────────────────────────
+
+#[utoipa::path(
+    post,
+    path = "/api/cluster/inference/resume-last",
+    tag = "cluster",
+    responses(
+        (status = 200, description = "Started; `skipped` lists devices left out", body = ResumeResponse),
+        (status = 400, description = "The recorded session can't be started", body = ErrorResponse),
+        (status = 404, description = "No session has been started yet", body = ErrorResponse),
+        (status = 409, description = "Inference is already running, another start is in progress, or a llama.cpp port is taken", body = PortInUseError),
+    )
+)]
+pub async fn resume_last_session(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match resume_last(&state).await {
+        Ok(resumed) => Json(resumed).into_response(),
+        Err(e) => {
+            report_failure(&state, &e);
+            e.into_response()
+        }
+    }
+}
+
+// ─── Startup ─────────────────────────────────────────────────────────────────
+
+/// With `resume_inference_on_start`, resume the last session in the
+/// background once its devices answer (or after `DEVICE_WAIT_SECS`, without
+/// the ones that don't). Startup never waits on this.
+pub fn spawn_on_start(state: Arc<AppState>) {
+    if !state.settings.get_bool(Key::ResumeOnStart) {
+        return;
+    }
+    let Some(last) = last_session(&state.settings) else {
+        tracing::info!("{} is on, but no session has been recorded yet", RESUME_ON_START_KEY);
+        return;
+    };
+
+    tokio::spawn(async move {
+        wait_for_devices(&state, &last.device_ids).await;
+        match resume_last(&state).await {
+            Ok(resumed) => {
+                tracing::info!(
+                    "Resumed last inference session ({}, {} device(s) skipped)",
+                    last.model_path,
+                    resumed.skipped.len()
+                );
+            }
+            Err(e) => report_failure(&state, &e),
+        }
+    });
+}
+
+/// The device's RPC endpoints that answer right now.
+async fn reachable_addresses(state: &AppState, device: &Device) -> Vec<String> {
+    let probes = device
+        .rpc_ports()
+        .into_iter()
+        .map(|port| state.llama_cpp.probe_rpc_device(&device.ip, port));
+    let reachable = join_all(probes).await;
+    device
+        .rpc_addresses()
+        .into_iter()
+        .zip(reachable)
+        .filter(|(_, ok)| *ok)
+        .map(|(address, _)| address)
+        .collect()
+}
+
+/// Wait until every approved device in `ids` answers on all its RPC ports,
+/// giving agents time to start after a reboot.
+async fn wait_for_devices(state: &AppState, ids: &[String]) {
+    let deadline = Instant::now() + Duration::from_secs(DEVICE_WAIT_SECS);
+    loop {
+        let mut all_ready = true;
+        for id in ids {
+            if let Ok(Some(d)) = queries::get_device(&state.pool, id).await {
+                if d.status == DeviceStatus::Approved
+                    && reachable_addresses(state, &d).await.len() < d.rpc_ports().len()
+                {
+                    all_ready = false;
+                    break;
+                }
+            }
+        }
+        if all_ready || Instant::now() >= deadline {
+            return;
+        }
+        sleep(Duration::from_secs(DEVICE_POLL_SECS)).await;
+    }
+}
@@ -1,26 +1,88 @@
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
-    Json,
+    Extension, Json,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use crate::{db::queries, AppState};
+use super::openapi::{ErrorResponse, OkResponse};
+use crate::{
+    api::{agent::TLS_FINGERPRINT_KEY, backends::ACTIVE_PROFILE_KEY},
+    api_keys::{self, KeyScope},
+    cors,
+    db::queries,
+    listen,
+    llama_cpp::{oom, rpc_mem_cap},
+    logs::output,
+    ollama::pulls::parse_time,
+    permissions::ENROLLMENT_TOKEN_KEY,
+    settings::{
+        keys::{sensitivity, Sensitivity},
+        limits::{self, EffectiveLimit},
+        Key,
+    },
+    ws::WsEvent,
+    AppState,
+};
+
+/// Writable settings without a [`Key`], besides the limits.
+const EXTRA_KEYS: &[&str] = &[ENROLLMENT_TOKEN_KEY, TLS_FINGERPRINT_KEY];
 
-#[derive(Deserialize)]
+#[derive(Deserialize, utoipa::ToSchema)]
 pub struct UpdateSettingRequest {
     pub value: String,
 }
 
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct SettingsView {
+    /// Setting key → value, only public settings for callers who aren't
+    /// admins. Secrets are never returned: admins get `<key>_set`, whether
+    /// one has a value.
+    #[serde(flatten)]
+    pub values: HashMap<String, serde_json::Value>,
+    /// Each limit's value in use, where it comes from, and whether a saved
+    /// change waits for a restart; empty for callers who aren't admins
+    pub limits: Vec<EffectiveLimit>,
+}
+
 /// GET /api/settings
-pub async fn list_settings(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+/// Admins (this host, the admin token or an admin key) get every setting;
+/// anyone else only the public ones.
+#[utoipa::path(
+    get,
+    path = "/api/settings",
+    tag = "settings",
+    responses(
+        (status = 200, body = SettingsView),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_settings(
+    State(state): State<Arc<AppState>>,
+    scope: Option<Extension<KeyScope>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let checked = scope.map(|Extension(s)| s);
+    let peer = peer.map(|ConnectInfo(addr)| addr);
+    let admin = api_keys::caller_scope(&state, checked, &headers, peer).await == Some(KeyScope::Admin);
     match queries::list_settings(&state.pool).await {
         Ok(settings) => {
-            let map: std::collections::HashMap<String, String> =
-                settings.into_iter().map(|s| (s.key, s.value)).collect();
-            Json(map).into_response()
+            let values = settings
+                .into_iter()
+                .filter_map(|s| match sensitivity(&s.key) {
+                    Sensitivity::Public => Some((s.key, s.value.into())),
+                    Sensitivity::Internal if admin => Some((s.key, s.value.into())),
+                    Sensitivity::Secret if admin => Some((format!("{}_set", s.key), (!s.value.is_empty()).into())),
+                    _ => None,
+                })
+                .collect();
+            let limits = if admin { state.limits.effective(&state.settings) } else { Vec::new() };
+            Json(SettingsView { values, limits }).into_response()
         }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -31,23 +93,29 @@ pub async fn list_settings(State(state): State<Arc<AppState>>) -> impl IntoRespo
 }
 
 /// PUT /api/settings/:key
+#[utoipa::path(
+    put,
+    path = "/api/settings/{key}",
+    tag = "settings",
+    params(("key" = String, Path, description = "Setting key")),
+    request_body = UpdateSettingRequest,
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 400, body = ErrorResponse),
+    )
+)]
 pub async fn update_setting(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(key): axum::extract::Path<String>,
     Json(req): Json<UpdateSettingRequest>,
 ) -> impl IntoResponse {
     // Only allow known settings keys to be written (VULN-07)
-    const ALLOWED_KEYS: &[&str] = &[
-        "auto_start_ollama",
-        "ollama_host",
-        "mdns_enabled",
-        "trust_local_network",
-        "backend_type",
-        "backend_url",
-        "backend_model",
-        "backend_api_key",
-    ];
-    if !ALLOWED_KEYS.contains(&key.as_str()) {
+    let parsed = Key::parse(&key);
+    let writable = match parsed {
+        Some(k) => k.writable(),
+        None => EXTRA_KEYS.contains(&key.as_str()) || limits::SPECS.iter().any(|s| s.key == key),
+    };
+    if !writable {
         return (
             StatusCode::BAD_REQUEST,
             Json(serde_json::json!({ "error": "Unknown settings key" })),
@@ -55,9 +123,58 @@ pub async fn update_setting(
             .into_response();
     }
 
-    match queries::set_setting(&state.pool, &key, &req.value).await {
-        Ok(()) => Json(serde_json::json!({ "ok": true, "key": key }))
-            .into_response(),
+    if let Err(e) = validate_setting(&key, &req.value) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e })),
+        )
+            .into_response();
+    }
+    if parsed == Some(Key::DefaultRole) {
+        match queries::get_role(&state.pool, &req.value).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "error": "default_role must be the ID of an existing role" })),
+                )
+                    .into_response()
+            }
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({ "error": e.to_string() })),
+                )
+                    .into_response()
+            }
+        }
+    }
+
+    match state.settings.set(&key, &req.value).await {
+        Ok(()) => {
+            match parsed {
+                Some(Key::MemoryPollSecs | Key::MemoryPollRamSecs) => state.memory.wake(),
+                Some(Key::OllamaHost | Key::AutoStartOllama) => reconfigure_ollama(&state),
+                Some(Key::OffpeakStart | Key::OffpeakEnd) => state.pulls.wake(),
+                Some(Key::CorsAllowedOrigins) => cors::warn_if_wildcard(&req.value),
+                Some(Key::LogFormat) => state.log_output.apply_setting(Some(&req.value)),
+                // Editing the backend directly detaches it from its profile
+                Some(Key::BackendType | Key::BackendUrl | Key::BackendModel | Key::BackendSecret) => {
+                    let _ = state.settings.set(ACTIVE_PROFILE_KEY, "").await;
+                }
+                _ => {}
+            }
+            // Read once, when the server binds
+            if parsed == Some(Key::BindAddress) {
+                return Json(serde_json::json!({ "ok": true, "key": key, "restart_required": true })).into_response();
+            }
+            if let Some(spec) = limits::SPECS.iter().find(|s| s.key == key) {
+                state.limits.reload(&state.settings);
+                return Json(serde_json::json!({ "ok": true, "key": key, "restart_required": !spec.hot }))
+                    .into_response();
+            }
+            Json(serde_json::json!({ "ok": true, "key": key })).into_response()
+        }
         Err(_e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({ "error": "Failed to update setting" })),
@@ -65,3 +182,144 @@ pub async fn update_setting(
             .into_response(),
     }
 }
+
+/// Apply `ollama_host` / `auto_start_ollama` to the running manager. Starting
+/// a local Ollama can take seconds, so the result arrives as `ollama_status`.
+fn reconfigure_ollama(state: &Arc<AppState>) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let host = state.settings.get_string(Key::OllamaHost);
+        let auto_start = state.settings.get_bool(Key::AutoStartOllama);
+        if let Err(e) = state.ollama.reconfigure(host, auto_start).await {
+            tracing::warn!("Ollama reconfigure: {}", e);
+        }
+        let _ = state.event_tx.send(WsEvent::OllamaStatus {
+            running: state.ollama.is_healthy().await,
+            host: state.ollama.host(),
+        });
+    });
+}
+
+/// Per-key value validation for settings that have a constrained format.
+fn validate_setting(key: &str, value: &str) -> Result<(), String> {
+    if let Some(result) = limits::validate(key, value) {
+        return result;
+    }
+    let Some(parsed) = Key::parse(key) else {
+        return match key {
+            ENROLLMENT_TOKEN_KEY => {
+                let v = value.trim();
+                if v.len() >= 16 && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                    Ok(())
+                } else {
+                    Err(format!("{} must be at least 16 characters of [A-Za-z0-9_-]", key))
+                }
+            }
+            TLS_FINGERPRINT_KEY => {
+                let hex: String = value.chars().filter(|c| *c != ':' && !c.is_whitespace()).collect();
+                if hex.is_empty() || (hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())) {
+                    Ok(())
+                } else {
+                    Err(format!("{} must be a SHA-256 fingerprint (64 hex digits, colons optional) or empty", key))
+                }
+            }
+            _ => Ok(()),
+        };
+    };
+    match parsed {
+        Key::MinClusterTrust | Key::MinStatsTrust => match value.trim().parse::<i64>() {
+            Ok(n) if (0..=100).contains(&n) => Ok(()),
+            _ => Err(format!("{} must be an integer between 0 and 100", key)),
+        },
+        Key::MemoryPollSecs | Key::MemoryPollRamSecs => match value.trim().parse::<u64>() {
+            Ok(n) if (1..=3600).contains(&n) => Ok(()),
+            _ => Err(format!("{} must be an integer between 1 and 3600", key)),
+        },
+        Key::MemoryStatsDeltaMb => match value.trim().parse::<u64>() {
+            Ok(n) if n <= 1024 * 1024 => Ok(()),
+            _ => Err(format!("{} must be a number of MB", key)),
+        },
+        Key::HostReservedMb => match value.trim() {
+            "auto" => Ok(()),
+            v => match v.parse::<u64>() {
+                Ok(n) if n <= 1024 * 1024 => Ok(()),
+                _ => Err(format!("{} must be \"auto\" or a number of MB", key)),
+            },
+        },
+        Key::RpcMemMb => match value.trim() {
+            "0" => Ok(()),
+            v if v.parse().ok().and_then(rpc_mem_cap).is_some() => Ok(()),
+            _ => Err(format!("{} must be 0 (no cap) or a number of MB", key)),
+        },
+        Key::ProxyMaxBodyMb => match value.trim().parse::<u64>() {
+            Ok(n) if (1..=1024).contains(&n) => Ok(()),
+            _ => Err(format!("{} must be an integer between 1 and 1024", key)),
+        },
+        Key::ModelUploadMaxMb => match value.trim().parse::<u64>() {
+            Ok(n) if (1..=1024 * 1024).contains(&n) => Ok(()),
+            _ => Err(format!("{} must be an integer between 1 and 1048576", key)),
+        },
+        Key::ProxyMaxImageMb => match value.trim().parse::<u64>() {
+            Ok(n) if (1..=1024).contains(&n) => Ok(()),
+            _ => Err(format!("{} must be an integer between 1 and 1024", key)),
+        },
+        Key::MaxConcurrentCompletions => match value.trim().parse::<u64>() {
+            Ok(n) if n <= 64 => Ok(()),
+            _ => Err(format!("{} must be an integer between 0 and 64", key)),
+        },
+        Key::CompletionQueueSize => match value.trim().parse::<u64>() {
+            Ok(n) if n <= 1024 => Ok(()),
+            _ => Err(format!("{} must be an integer between 0 and 1024", key)),
+        },
+        Key::CompletionQueueTimeoutSecs => match value.trim().parse::<u64>() {
+            Ok(n) if (1..=600).contains(&n) => Ok(()),
+            _ => Err(format!("{} must be an integer between 1 and 600", key)),
+        },
+        Key::CompletionQueuePromoteSecs => match value.trim().parse::<u64>() {
+            Ok(n) if n <= 600 => Ok(()),
+            _ => Err(format!("{} must be an integer between 0 and 600", key)),
+        },
+        Key::DrainTimeoutSecs => match value.trim().parse::<u64>() {
+            Ok(n) if n <= 600 => Ok(()),
+            _ => Err(format!("{} must be an integer between 0 and 600", key)),
+        },
+        Key::DeviceOfflineAfterSecs => match value.trim().parse::<u64>() {
+            Ok(n) if (60..=7 * 24 * 3600).contains(&n) => Ok(()),
+            _ => Err(format!("{} must be an integer between 60 and 604800", key)),
+        },
+        Key::ModelTrashDays => match value.trim().parse::<u64>() {
+            Ok(n) if n <= 365 => Ok(()),
+            _ => Err(format!("{} must be a number of days between 0 and 365", key)),
+        },
+        Key::IdleTimeoutMinutes => match value.trim().parse::<u64>() {
+            Ok(n) if n <= 7 * 24 * 60 => Ok(()),
+            _ => Err(format!("{} must be 0 (never) or a number of minutes up to 10080", key)),
+        },
+        Key::InferenceSecret => {
+            let v = value.trim();
+            if v.is_empty() || (v.len() >= 16 && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')) {
+                Ok(())
+            } else {
+                Err(format!("{} must be empty (a new key per session) or at least 16 characters of [A-Za-z0-9_-]", key))
+            }
+        }
+        Key::OomRetryLayersPct => oom::parse_steps(value).map(|_| ()),
+        Key::CorsAllowedOrigins => cors::validate_origins(value),
+        Key::BindAddress => listen::parse_bind_addr(value).map(|_| ()).map_err(|e| format!("{}: {}", key, e)),
+        Key::LogFormat => output::validate_format(value),
+        Key::OffpeakStart | Key::OffpeakEnd => match parse_time(value) {
+            Some(_) => Ok(()),
+            None => Err(format!("{} must be a 24-hour time like 01:30", key)),
+        },
+        Key::OllamaHost => match reqwest::Url::parse(value) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.host_str().is_some() => Ok(()),
+            _ => Err(format!("{} must be an http(s) URL", key)),
+        },
+        // Every flag has "true" or "false" as its default
+        _ if matches!(parsed.default(), "true" | "false") => match value {
+            "true" | "false" => Ok(()),
+            _ => Err(format!("{} must be \"true\" or \"false\"", key)),
+        },
+        _ => Ok(()),
+    }
+}
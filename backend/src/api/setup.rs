@@ -0,0 +1,129 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use std::sync::Arc;
+
+use super::openapi::ErrorResponse;
+use crate::{
+    db::queries,
+    llama_cpp::LlamaCppManager,
+    permissions::{
+        ensure_builtin_roles, generate_secret, hash_secret, ADMIN_TOKEN_HASH_KEY, BUILTIN_ROLES, ENROLLMENT_TOKEN_KEY,
+    },
+    settings,
+    AppState,
+};
+
+fn internal_error(e: anyhow::Error) -> axum::response::Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "error": e.to_string() })),
+    )
+        .into_response()
+}
+
+/// Which built-in role IDs are present in the database.
+async fn builtin_roles_present(state: &AppState) -> anyhow::Result<serde_json::Map<String, serde_json::Value>> {
+    let roles = queries::list_roles(&state.pool).await?;
+    Ok(BUILTIN_ROLES
+        .iter()
+        .map(|(id, ..)| (id.to_string(), roles.iter().any(|r| r.id == *id).into()))
+        .collect())
+}
+
+/// GET /api/setup/status
+/// Reports what first-run setup has done and what this host can offer.
+#[utoipa::path(
+    get,
+    path = "/api/setup/status",
+    tag = "setup",
+    responses(
+        (status = 200, description = "First-run state and host capabilities", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn setup_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let roles = match builtin_roles_present(&state).await {
+        Ok(r) => r,
+        Err(e) => return internal_error(e),
+    };
+    let roles_ok = roles.values().all(|v| v.as_bool() == Some(true));
+    let admin_token_generated = state.settings.get(ADMIN_TOKEN_HASH_KEY).is_some();
+    let providers: Vec<String> = state.providers.load().iter().map(|p| p.id().to_string()).collect();
+    let rpc_server = LlamaCppManager::find_rpc_server_bin().is_some();
+    let inference_server = LlamaCppManager::find_inference_server_bin().is_some();
+
+    Json(serde_json::json!({
+        "initialized": roles_ok && admin_token_generated,
+        "builtin_roles": roles,
+        "builtin_roles_present": roles_ok,
+        "admin_token_generated": admin_token_generated,
+        "providers_detected": !providers.is_empty(),
+        "providers": providers,
+        "llama_cpp": {
+            "installed": rpc_server && inference_server,
+            "rpc_server": rpc_server,
+            "inference_server": inference_server,
+        },
+    }))
+    .into_response()
+}
+
+/// POST /api/setup/init
+/// Idempotently seeds the built-in roles and default settings, and generates
+/// the admin token. The token is returned by the call that creates it and
+/// never again — only its hash is stored.
+#[utoipa::path(
+    post,
+    path = "/api/setup/init",
+    tag = "setup",
+    responses(
+        (status = 201, description = "Initialized; `admin_token` is included only on this call", body = serde_json::Value),
+        (status = 200, description = "Already initialized", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn setup_init(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let created_roles = match ensure_builtin_roles(&state.pool).await {
+        Ok(created) => created,
+        Err(e) => return internal_error(e),
+    };
+
+    // The defaults are normally written at startup already
+    let mut created_settings = match settings::keys::seed(&state.pool).await {
+        Ok(created) => created,
+        Err(e) => return internal_error(e),
+    };
+    match queries::insert_setting_if_missing(&state.pool, ENROLLMENT_TOKEN_KEY, &generate_secret()).await {
+        Ok(true) => created_settings.push(ENROLLMENT_TOKEN_KEY),
+        Ok(false) => {}
+        Err(e) => return internal_error(e),
+    }
+
+    // Insert-if-missing decides the winner when two inits race: only the
+    // request whose insert landed returns the token
+    let admin_token = generate_secret();
+    let token_created =
+        match queries::insert_setting_if_missing(&state.pool, ADMIN_TOKEN_HASH_KEY, &hash_secret(&admin_token))
+            .await
+        {
+            Ok(created) => created,
+            Err(e) => return internal_error(e),
+        };
+
+    if let Err(e) = state.settings.refresh().await {
+        return internal_error(e);
+    }
+
+    let already_initialized = created_roles.is_empty() && !token_created;
+    let mut body = serde_json::json!({
+        "ok": true,
+        "already_initialized": already_initialized,
+        "created_roles": created_roles,
+        "created_settings": created_settings,
+    });
+    if token_created {
+        body["admin_token"] = admin_token.into();
+    }
+
+    let status = if already_initialized { StatusCode::OK } else { StatusCode::CREATED };
+    (status, Json(body)).into_response()
+}
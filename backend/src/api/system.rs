@@ -0,0 +1,578 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::openapi::ErrorResponse;
+use crate::{
+    db::{self, backup::{self, Backup}, queries},
+    disk::{self, DiskSpace, PartialFile},
+    doctor::{self, Report},
+    inventory::{self, SystemInfo},
+    listen,
+    llama_cpp::{reservations, LlamaCppManager},
+    memory::hotplug::ProviderInfo,
+    overview::{self, Overview},
+    process, support,
+    AppState,
+};
+
+/// A directory downloads land in, with the space on its filesystem.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DiskLocation {
+    /// `temp`, `sharedmem` or `models`
+    pub kind: String,
+    pub path: String,
+    /// `None` when the filesystem can't be identified
+    pub space: Option<DiskSpace>,
+}
+
+/// `GET /api/system/disk`
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct DiskReport {
+    pub locations: Vec<DiskLocation>,
+    /// Orphaned `.partial` files in those directories
+    pub partial_files: Vec<PartialFile>,
+    pub reclaimable_bytes: u64,
+}
+
+/// The temp dir, `~/.sharedmem`, and every directory holding an aliased model.
+async fn download_dirs(state: &AppState) -> Vec<(&'static str, PathBuf)> {
+    let mut dirs = vec![("temp", std::env::temp_dir())];
+    if let Ok(dir) = disk::sharedmem_dir() {
+        dirs.push(("sharedmem", dir));
+    }
+    let model_dirs: BTreeSet<PathBuf> = queries::list_model_aliases(&state.pool)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|a| PathBuf::from(a.path).parent().map(PathBuf::from))
+        .collect();
+    dirs.extend(model_dirs.into_iter().map(|d| ("models", d)));
+    dirs
+}
+
+/// Orphaned `.partial` files in the download dirs and `~/.sharedmem/bin`.
+async fn partials(state: &AppState) -> Vec<PartialFile> {
+    let mut dirs: Vec<PathBuf> = download_dirs(state).await.into_iter().map(|(_, d)| d).collect();
+    if let Ok(dir) = disk::sharedmem_dir() {
+        dirs.push(dir.join("bin"));
+    }
+    tokio::task::spawn_blocking(move || disk::orphaned_partials(&dirs))
+        .await
+        .unwrap_or_default()
+}
+
+/// The directory of the first aliased model, else Ollama's model store.
+async fn model_dir(state: &AppState) -> PathBuf {
+    download_dirs(state)
+        .await
+        .into_iter()
+        .find(|(kind, _)| *kind == "models")
+        .map(|(_, dir)| dir)
+        .or_else(inventory::ollama_models_dir)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+// ─── GET /api/health ─────────────────────────────────────────────────────────
+
+/// Liveness, and how other hosts tell a SharedLLM backend from an agent-only
+/// device or some other service on port 8080.
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "system",
+    responses(
+        (status = 200, description = "`{status: \"ok\", service: \"sharedllm\", version}`", body = serde_json::Value),
+    )
+)]
+pub async fn health() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "ok",
+        "service": crate::federation::HEALTH_SERVICE,
+        "version": env!("CARGO_PKG_VERSION"),
+    }))
+}
+
+// ─── GET /api/overview ───────────────────────────────────────────────────────
+
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OverviewParams {
+    /// Model path or alias of a llama.cpp session about to be started; a
+    /// warning is added if it won't fit in free memory
+    pub model: Option<String>,
+    /// Context size of that session (default 4096)
+    pub ctx_size: Option<u32>,
+}
+
+/// Memory, devices, inference, Ollama and errors in one response for the
+/// dashboard's landing page, from cached state only. The `overview` event
+/// carries the headline numbers when they change.
+#[utoipa::path(
+    get,
+    path = "/api/overview",
+    tag = "system",
+    params(OverviewParams),
+    responses(
+        (status = 200, body = Overview),
+        (status = 400, description = "Unknown model alias", body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn overview(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<OverviewParams>,
+) -> impl IntoResponse {
+    let session = match &params.model {
+        Some(model) => match super::models::resolve_model_path(&state.pool, model).await {
+            Ok(path) => Some((model, path)),
+            Err(e) => {
+                return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": e }))).into_response()
+            }
+        },
+        None => None,
+    };
+    match overview::compute(&state).await {
+        Ok(mut overview) => {
+            if let Some((model, path)) = session {
+                let need_mb = reservations::session_memory_mb(&path, params.ctx_size.unwrap_or(4096));
+                overview.check_session(model, need_mb);
+            }
+            Json(overview).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+// ─── GET /api/system/info ────────────────────────────────────────────────────
+
+/// CPU, OS, GPU drivers and free space on the model volume of this host.
+/// Collected at most every few minutes; remote devices report theirs with
+/// the agent heartbeat (see `/api/devices/:id`).
+#[utoipa::path(
+    get,
+    path = "/api/system/info",
+    tag = "system",
+    responses(
+        (status = 200, body = SystemInfo),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn system_info(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if let Some(info) = state.inventory.get() {
+        return Json(info).into_response();
+    }
+    let dir = model_dir(&state).await;
+    match tokio::task::spawn_blocking(move || inventory::collect(&dir)).await {
+        Ok(info) => {
+            state.inventory.put(info.clone());
+            Json(info).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": format!("Failed to collect system info: {}", e) })),
+        )
+            .into_response(),
+    }
+}
+
+// ─── GET /api/diagnostics ────────────────────────────────────────────────────
+
+/// The `--doctor` self-test, run against this server: its own ports count as
+/// healthy. Takes a few seconds (binaries are run, GitHub is contacted).
+#[utoipa::path(
+    get,
+    path = "/api/diagnostics",
+    tag = "system",
+    responses(
+        (status = 200, body = Report),
+    )
+)]
+pub async fn diagnostics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let status = state.llama_cpp.get_status().await;
+    let mut own_ports = vec![state.port];
+    if status.rpc_server_running {
+        own_ports.push(status.rpc_port);
+    }
+    if status.inference_running {
+        own_ports.push(status.inference_port);
+    }
+    let mut report = doctor::run(doctor::Context {
+        pool: Some(state.pool.clone()),
+        read_only: state.read_only,
+        own_ports,
+        dashboard_port: Some(state.port),
+        ollama_host: Some(state.ollama.host()),
+        provider_health: state.memory.health(),
+    })
+    .await;
+    report.logging = Some(state.log_output.config());
+    report.listen = Some(listen::ListenReport::new(state.bind, state.port));
+    Json(report)
+}
+
+// ─── GET /api/system/disk ────────────────────────────────────────────────────
+
+/// Free space where downloads go, plus space held by interrupted downloads.
+#[utoipa::path(
+    get,
+    path = "/api/system/disk",
+    tag = "system",
+    responses(
+        (status = 200, body = DiskReport),
+    )
+)]
+pub async fn disk_report(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let dirs = download_dirs(&state).await;
+    let locations = tokio::task::spawn_blocking(move || {
+        dirs.into_iter()
+            .map(|(kind, dir)| DiskLocation {
+                kind: kind.to_string(),
+                space: disk::space_for(&dir),
+                path: dir.display().to_string(),
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+
+    let partial_files = partials(&state).await;
+    let reclaimable_bytes = partial_files.iter().map(|p| p.bytes).sum();
+    Json(DiskReport { locations, partial_files, reclaimable_bytes })
+}
+
+// ─── POST /api/system/disk/cleanup ───────────────────────────────────────────
+
+/// Delete orphaned `.partial` files. Ones still being written are kept.
+#[utoipa::path(
+    post,
+    path = "/api/system/disk/cleanup",
+    tag = "system",
+    responses(
+        (status = 200, description = "`{ok, removed, freed_bytes}`", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn disk_cleanup(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut removed = Vec::new();
+    let mut freed_bytes = 0;
+    for file in partials(&state).await {
+        match tokio::fs::remove_file(&file.path).await {
+            Ok(()) => {
+                tracing::info!("Removed partial download {}", file.path);
+                freed_bytes += file.bytes;
+                removed.push(file.path);
+            }
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({
+                        "error": format!("Failed to remove {}: {}", file.path, e),
+                    })),
+                )
+                    .into_response();
+            }
+        }
+    }
+    Json(serde_json::json!({ "ok": true, "removed": removed, "freed_bytes": freed_bytes }))
+        .into_response()
+}
+
+// ─── GET /api/system/backups ─────────────────────────────────────────────────
+
+/// `GET /api/system/backups`
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct BackupList {
+    /// The database file; `None` when it is in memory and can't be backed up
+    pub database: Option<String>,
+    /// Newest first
+    pub backups: Vec<Backup>,
+    /// Backups kept before the oldest is deleted
+    pub keep: usize,
+}
+
+/// Copies of the database taken before migrations ran.
+#[utoipa::path(
+    get,
+    path = "/api/system/backups",
+    tag = "system",
+    responses(
+        (status = 200, body = BackupList),
+    )
+)]
+pub async fn list_backups(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let database = db::file_path(&state.pool).await;
+    let backups = match database.clone() {
+        Some(path) => tokio::task::spawn_blocking(move || backup::list(&path)).await.unwrap_or_default(),
+        None => Vec::new(),
+    };
+    Json(BackupList {
+        database: database.map(|p| p.display().to_string()),
+        backups,
+        keep: backup::KEEP,
+    })
+}
+
+// ─── POST /api/system/backups/restore ────────────────────────────────────────
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct RestoreBackupRequest {
+    /// `name` of a backup in `GET /api/system/backups`
+    pub name: String,
+}
+
+/// `POST /api/system/backups/restore`
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct RestoreStarted {
+    pub name: String,
+    /// The server is restarting to put the backup in place
+    pub restarting: bool,
+    /// What to do when it can't restart itself
+    pub instructions: Option<String>,
+}
+
+/// How long a restore waits for queries in flight to finish.
+const RESTORE_IDLE_WAIT: Duration = Duration::from_secs(5);
+
+/// Whether no connection of `pool` is running a query, waiting up to `wait`.
+async fn wait_idle(pool: &sqlx::SqlitePool, wait: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + wait;
+    loop {
+        if pool.size() as usize == pool.num_idle() {
+            return true;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Replace the database with a backup. The backup is staged and put in
+/// place at the next start, before the database is opened; the database it
+/// replaces is kept as a backup too. Where the server can restart itself it
+/// does so at once, after stopping llama.cpp and closing the database;
+/// elsewhere the answer says to restart it.
+#[utoipa::path(
+    post,
+    path = "/api/system/backups/restore",
+    tag = "system",
+    request_body = RestoreBackupRequest,
+    responses(
+        (status = 202, description = "Restarting to restore it", body = RestoreStarted),
+        (status = 200, description = "Staged; restart the server to restore it", body = RestoreStarted),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Read-only or in-memory database, or writes still in flight", body = ErrorResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn restore_backup(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<RestoreBackupRequest>,
+) -> impl IntoResponse {
+    let error = |status: StatusCode, message: String| (status, Json(serde_json::json!({ "error": message }))).into_response();
+    if state.read_only {
+        return error(StatusCode::CONFLICT, "The database is open read-only".to_string());
+    }
+    let Some(path) = db::file_path(&state.pool).await else {
+        return error(StatusCode::CONFLICT, "The database is in memory and has no backups".to_string());
+    };
+    if !backup::list(&path).iter().any(|b| b.name == req.name) {
+        return error(StatusCode::NOT_FOUND, format!("No backup named '{}'", req.name));
+    }
+    if !wait_idle(&state.pool, RESTORE_IDLE_WAIT).await {
+        return error(StatusCode::CONFLICT, "Database writes are still in flight; try again".to_string());
+    }
+    if let Err(e) = backup::stage(&path, &req.name) {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to stage the backup: {:#}", e));
+    }
+    tracing::warn!("Restoring the database from {} at the next start", req.name);
+
+    if !process::CAN_RESTART_SELF {
+        return Json(RestoreStarted {
+            name: req.name,
+            restarting: false,
+            instructions: Some(
+                "Restart SharedLLM to finish: the backup replaces the database before it is opened".to_string(),
+            ),
+        })
+        .into_response();
+    }
+
+    let restarting = state.clone();
+    tokio::spawn(async move {
+        // Let the answer go out first
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let _ = restarting.llama_cpp.stop_inference(true, Duration::ZERO).await;
+        let _ = restarting.llama_cpp.stop_rpc_server().await;
+        // Waits for queries still running
+        restarting.pool.close().await;
+        tracing::warn!("Restarting to restore the database");
+        let e = process::restart_self();
+        tracing::error!("Failed to restart ({}); start SharedLLM again to finish the restore", e);
+        std::process::exit(1);
+    });
+    (
+        StatusCode::ACCEPTED,
+        Json(RestoreStarted { name: req.name, restarting: true, instructions: None }),
+    )
+        .into_response()
+}
+
+// ─── GET /api/support-bundle ─────────────────────────────────────────────────
+
+/// Query params for GET /api/support-bundle
+#[derive(Deserialize, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SupportBundleParams {
+    /// Also replace every IP address other than loopback with `[redacted]`
+    #[serde(default)]
+    pub redact_ips: bool,
+}
+
+/// `{path, version}` of a llama.cpp binary, or why it has none.
+async fn binary_version(path: Option<PathBuf>) -> serde_json::Value {
+    let Some(path) = path else {
+        return serde_json::json!({ "path": null, "error": "not found" });
+    };
+    match doctor::version_of(&path).await {
+        Ok(version) => serde_json::json!({ "path": path, "version": version }),
+        Err(e) => serde_json::json!({ "path": path, "error": e }),
+    }
+}
+
+/// A zip for bug reports: settings, devices and roles, recent log lines,
+/// llama.cpp versions, memory providers, the current and last inference
+/// session, and host info. Secrets are always redacted.
+///
+/// An admin-only read: with `require_dashboard_auth` on, other hosts need an
+/// admin key, since the bundle names devices, addresses and models.
+#[utoipa::path(
+    get,
+    path = "/api/support-bundle",
+    tag = "system",
+    params(SupportBundleParams),
+    responses(
+        (status = 200, description = "A zip of JSON files and `logs.txt`", content_type = "application/zip", body = Vec<u8>),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn support_bundle(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<SupportBundleParams>,
+) -> impl IntoResponse {
+    let settings: serde_json::Map<String, serde_json::Value> = queries::list_settings(&state.pool)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|s| (s.key, s.value.into()))
+        .collect();
+    let devices = queries::list_devices(&state.pool).await.unwrap_or_default();
+    let roles = queries::list_roles(&state.pool).await.unwrap_or_default();
+    let system = match state.inventory.get() {
+        Some(info) => Some(info),
+        None => {
+            let dir = model_dir(&state).await;
+            tokio::task::spawn_blocking(move || inventory::collect(&dir)).await.ok()
+        }
+    };
+    let (snapshots, _) = state.memory.cached();
+    let providers = serde_json::json!({
+        "providers": state.providers.load().iter().map(|p| ProviderInfo::of(p.as_ref())).collect::<Vec<_>>(),
+        "health": state.memory.health().iter().map(|h| serde_json::json!({
+            "provider_id": h.provider_id,
+            "name": h.name,
+            "consecutive_failures": h.consecutive_failures,
+            "degraded": h.degraded,
+        })).collect::<Vec<_>>(),
+        "snapshots": snapshots,
+    });
+    let binaries = serde_json::json!({
+        "llama-server": binary_version(LlamaCppManager::find_inference_server_bin()).await,
+        "llama-rpc-server": binary_version(LlamaCppManager::find_rpc_server_bin()).await,
+    });
+    let sessions = serde_json::json!({
+        "current": state.llama_cpp.get_current_session().await,
+        "last": super::resume::last_session(&state.settings),
+    });
+    let manifest = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "created_at": chrono::Utc::now().to_rfc3339(),
+        "redact_ips": params.redact_ips,
+    });
+    let logs: String = state
+        .logs
+        .recent(None, crate::logs::BUFFER_LINES)
+        .iter()
+        .map(|l| {
+            format!(
+                "{} {:>5} {}: {}{}\n",
+                l.timestamp,
+                l.level,
+                l.target,
+                l.message,
+                l.request_id.as_deref().map(|id| format!(" request_id={}", id)).unwrap_or_default()
+            )
+        })
+        .collect();
+
+    let redact_ips = params.redact_ips;
+    let built = tokio::task::spawn_blocking(move || {
+        let documents = [
+            ("manifest.json", manifest),
+            ("settings.json", serde_json::Value::Object(settings)),
+            ("devices.json", serde_json::json!(devices)),
+            ("roles.json", serde_json::json!(roles)),
+            ("binaries.json", binaries),
+            ("providers.json", providers),
+            ("sessions.json", sessions),
+            ("system.json", serde_json::json!(system)),
+        ];
+        let mut files: Vec<(String, Vec<u8>)> = documents
+            .into_iter()
+            .map(|(name, mut doc)| {
+                support::redact_json(&mut doc, redact_ips);
+                (name.to_string(), serde_json::to_vec_pretty(&doc).unwrap_or_default())
+            })
+            .collect();
+        files.push(("logs.txt".to_string(), support::redact_text(&logs, redact_ips).into_bytes()));
+        support::build_zip(files)
+    })
+    .await;
+
+    match built {
+        Ok(Ok(zip)) => {
+            let name = format!(
+                "attachment; filename=\"sharedllm-support-{}.zip\"",
+                chrono::Utc::now().format("%Y%m%d-%H%M%S")
+            );
+            (
+                [(header::CONTENT_TYPE, "application/zip".to_string()), (header::CONTENT_DISPOSITION, name)],
+                zip,
+            )
+                .into_response()
+        }
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": format!("Failed to build the support bundle: {}", e) })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": format!("Failed to build the support bundle: {}", e) })),
+        )
+            .into_response(),
+    }
+}
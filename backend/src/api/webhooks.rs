@@ -0,0 +1,227 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::openapi::{ErrorResponse, OkResponse, WebhookView};
+use crate::{db::{models::Webhook, queries}, AppState};
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UpsertWebhookRequest {
+    pub url: String,
+    /// Omitted on update = keep the stored secret; "" = stop signing
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// API view of a webhook: the secret is replaced by whether one is set.
+fn to_json(w: &Webhook) -> serde_json::Value {
+    serde_json::json!({
+        "id": w.id,
+        "url": w.url,
+        "events": w.events(),
+        "enabled": w.enabled,
+        "secret_set": !w.secret.is_empty(),
+        "created_at": w.created_at,
+    })
+}
+
+/// Validate URL and event filter; returns the filter serialized as JSON.
+fn validate(req: &UpsertWebhookRequest) -> Result<String, String> {
+    let url = reqwest::Url::parse(req.url.trim()).map_err(|e| format!("Invalid URL: {}", e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("Webhook URL must use http or https".into());
+    }
+    for ev in &req.events {
+        if ev.is_empty() || !ev.chars().all(|c| c.is_ascii_lowercase() || c == '_') {
+            return Err(format!("Invalid event type '{}'", ev));
+        }
+    }
+    Ok(serde_json::to_string(&req.events).unwrap_or_else(|_| "[]".into()))
+}
+
+fn internal_error(e: anyhow::Error) -> axum::response::Response {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(serde_json::json!({ "error": e.to_string() })),
+    )
+        .into_response()
+}
+
+fn not_found() -> axum::response::Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "Webhook not found" })),
+    )
+        .into_response()
+}
+
+/// GET /api/webhooks
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "`{webhooks: [WebhookView]}`", body = serde_json::Value),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn list_webhooks(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match queries::list_webhooks(&state.pool).await {
+        Ok(hooks) => {
+            let hooks: Vec<_> = hooks.iter().map(to_json).collect();
+            Json(serde_json::json!({ "webhooks": hooks })).into_response()
+        }
+        Err(e) => internal_error(e),
+    }
+}
+
+/// POST /api/webhooks
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "webhooks",
+    request_body = UpsertWebhookRequest,
+    responses(
+        (status = 201, body = WebhookView),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+pub async fn create_webhook(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<UpsertWebhookRequest>,
+) -> impl IntoResponse {
+    let event_filter = match validate(&req) {
+        Ok(f) => f,
+        Err(msg) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+                .into_response()
+        }
+    };
+
+    let hook = Webhook {
+        id: format!("webhook-{}", Uuid::new_v4()),
+        url: req.url.trim().to_string(),
+        secret: req.secret.unwrap_or_default(),
+        event_filter,
+        enabled: req.enabled,
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+
+    match queries::upsert_webhook(&state.pool, &hook).await {
+        Ok(()) => (StatusCode::CREATED, Json(to_json(&hook))).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// PUT /api/webhooks/:id
+#[utoipa::path(
+    put,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook ID")),
+    request_body = UpsertWebhookRequest,
+    responses(
+        (status = 200, body = WebhookView),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn update_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(req): Json<UpsertWebhookRequest>,
+) -> impl IntoResponse {
+    let event_filter = match validate(&req) {
+        Ok(f) => f,
+        Err(msg) => {
+            return (StatusCode::BAD_REQUEST, Json(serde_json::json!({ "error": msg })))
+                .into_response()
+        }
+    };
+
+    let existing = match queries::get_webhook(&state.pool, &id).await {
+        Ok(Some(w)) => w,
+        Ok(None) => return not_found(),
+        Err(e) => return internal_error(e),
+    };
+
+    let hook = Webhook {
+        url: req.url.trim().to_string(),
+        secret: req.secret.unwrap_or(existing.secret.clone()),
+        event_filter,
+        enabled: req.enabled,
+        ..existing
+    };
+
+    match queries::upsert_webhook(&state.pool, &hook).await {
+        Ok(()) => Json(to_json(&hook)).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// DELETE /api/webhooks/:id
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook ID")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+pub async fn delete_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match queries::delete_webhook(&state.pool, &id).await {
+        Ok(()) => Json(serde_json::json!({ "ok": true })).into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// POST /api/webhooks/:id/test
+/// Sends a synthetic `webhook_test` event once (no retries) and reports the outcome.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks/{id}/test",
+    tag = "webhooks",
+    params(("id" = String, Path, description = "Webhook ID")),
+    responses(
+        (status = 200, description = "`{ok, status}` with the endpoint's HTTP status", body = serde_json::Value),
+        (status = 404, body = ErrorResponse),
+        (status = 502, body = ErrorResponse),
+    )
+)]
+pub async fn test_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let hook = match queries::get_webhook(&state.pool, &id).await {
+        Ok(Some(w)) => w,
+        Ok(None) => return not_found(),
+        Err(e) => return internal_error(e),
+    };
+
+    match state.webhooks.send_test(&hook).await {
+        Ok(status) => Json(serde_json::json!({ "ok": true, "status": status })).into_response(),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            Json(serde_json::json!({ "ok": false, "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
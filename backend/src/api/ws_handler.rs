@@ -1,46 +1,109 @@
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
     },
+    Extension,
+    http::StatusCode,
     response::IntoResponse,
+    Json,
 };
 use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch};
 
+use super::openapi::{ErrorResponse, OkResponse};
+use crate::api_keys::KeyScope;
+use crate::ws::{clients::WsClientInfo, ClientMessage, Envelope, WsEvent, PROTOCOL_VERSION};
 use crate::AppState;
 
+/// How long events are held back waiting for the client's `hello`.
+const HELLO_WAIT: Duration = Duration::from_secs(1);
+
 /// GET /ws  — upgrade to WebSocket
+/// A viewer key (`require_dashboard_auth`) only gets periodic stats.
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "ws",
+    responses(
+        (status = 101, description = "Switching to WebSocket. The client may first send `{\"type\": \"hello\", \"v\": N}`; the server then pushes an `Envelope` per event (v2), or bare `WsEvent`s to v1 clients", body = Envelope),
+    )
+)]
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    scope: Option<Extension<KeyScope>>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+    let remote_addr = peer.map(|ConnectInfo(addr)| addr);
+    let stats_only = scope.is_some_and(|Extension(s)| s == KeyScope::Viewer);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, remote_addr, stats_only))
 }
 
-async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    remote_addr: Option<SocketAddr>,
+    stats_only: bool,
+) {
+    // Counted while connected so memory sampling stays at full speed
+    let _sampling = state.memory.client_connected();
     let (mut sender, mut receiver) = socket.split();
     let mut event_rx = state.event_tx.subscribe();
+    // Listed in GET /api/ws/clients until this guard drops, even on a panic
+    let registration = state.ws_clients.register(remote_addr, &state.event_tx);
+    let client = registration.client();
+    let client_id = client.id();
 
     // Channel used by recv_task to forward Pong payloads to send_task
     let (pong_tx, mut pong_rx) = mpsc::channel::<Vec<u8>>(8);
+    // Protocol version the client announced in its hello
+    let (version_tx, mut version_rx) = watch::channel(PROTOCOL_VERSION);
 
     // Task: forward broadcast events → WebSocket client; also send Pongs
-    let send_task = tokio::spawn(async move {
+    let sending = client.clone();
+    let memory = state.memory.clone();
+    let mut send_task = tokio::spawn(async move {
+        let client = sending;
+        // Events queue up in event_rx meanwhile, so none are lost
+        let _ = tokio::time::timeout(HELLO_WAIT, version_rx.changed()).await;
+        // Stats are only sent when they move, so start the client off with all of them
+        if let Some(text) = memory.keyframe().and_then(|e| e.encode(*version_rx.borrow(), &chrono::Utc::now().to_rfc3339())) {
+            if sender.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+            client.event_sent();
+        }
         loop {
             tokio::select! {
                 event = event_rx.recv() => {
-                    match event {
-                        Ok(event) => {
-                            if let Ok(text) = serde_json::to_string(&event) {
-                                if sender.send(Message::Text(text)).await.is_err() {
-                                    break;
-                                }
-                            }
-                        }
+                    let event = match event {
+                        Ok(event) => event,
                         Err(broadcast::error::RecvError::Closed) => break,
-                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        // State changes were lost; have the client refetch them
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            tracing::warn!("WebSocket client lagged; missed {} events", missed);
+                            WsEvent::Resync { missed }
+                        }
+                    };
+                    // A client isn't told about its own connection
+                    if event.ws_client_id() == Some(client_id.as_str()) {
+                        continue;
+                    }
+                    if stats_only && !event.is_periodic() {
+                        continue;
+                    }
+                    let version = *version_rx.borrow();
+                    let ts = chrono::Utc::now().to_rfc3339();
+                    // None: the client's protocol can't represent this event
+                    if let Some(text) = event.encode(version, &ts) {
+                        if sender.send(Message::Text(text)).await.is_err() {
+                            break;
+                        }
+                        client.event_sent();
                     }
                 }
                 pong_data = pong_rx.recv() => {
@@ -53,30 +116,90 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
                         None => break,
                     }
                 }
+                _ = client.closed() => {
+                    let _ = sender
+                        .send(Message::Close(Some(CloseFrame {
+                            code: axum::extract::ws::close_code::NORMAL,
+                            reason: "Closed from the dashboard".into(),
+                        })))
+                        .await;
+                    break;
+                }
             }
         }
     });
 
     // Task: receive messages from client and handle Ping → Pong
-    let recv_task = tokio::spawn(async move {
+    let mut recv_task = tokio::spawn(async move {
         while let Some(msg) = receiver.next().await {
+            client.message_received();
             match msg {
                 Ok(Message::Close(_)) => break,
+                // A guard can't move `data` into the send
+                #[allow(clippy::collapsible_match)]
                 Ok(Message::Ping(data)) => {
                     if pong_tx.send(data).await.is_err() {
                         break;
                     }
                 }
+                Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                    Ok(ClientMessage::Hello { v }) => {
+                        let v = v.clamp(1, PROTOCOL_VERSION);
+                        tracing::debug!("WebSocket client speaks protocol v{}", v);
+                        client.set_protocol_version(v);
+                        let _ = version_tx.send(v);
+                    }
+                    Err(e) => tracing::debug!("Ignoring WebSocket message: {}", e),
+                },
                 _ => {}
             }
         }
     });
 
-    // Wait for either task to finish
+    // Wait for either task to finish (or panic), then stop the other
     tokio::select! {
-        _ = send_task => {}
-        _ = recv_task => {}
+        _ = &mut send_task => recv_task.abort(),
+        _ = &mut recv_task => send_task.abort(),
     }
 
+    drop(registration);
     tracing::debug!("WebSocket client disconnected");
 }
+
+/// GET /api/ws/clients
+#[utoipa::path(
+    get,
+    path = "/api/ws/clients",
+    tag = "ws",
+    responses(
+        (status = 200, description = "Open `/ws` connections, oldest first", body = Vec<WsClientInfo>),
+    )
+)]
+pub async fn list_ws_clients(State(state): State<Arc<AppState>>) -> Json<Vec<WsClientInfo>> {
+    Json(state.ws_clients.list())
+}
+
+/// DELETE /api/ws/clients/:id  — close one connection
+#[utoipa::path(
+    delete,
+    path = "/api/ws/clients/{id}",
+    tag = "ws",
+    params(("id" = String, Path, description = "Client ID from GET /api/ws/clients")),
+    responses(
+        (status = 200, body = OkResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+pub async fn close_ws_client(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    if !state.ws_clients.close(&id) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "No such WebSocket client" })),
+        )
+            .into_response();
+    }
+    Json(serde_json::json!({ "ok": true })).into_response()
+}
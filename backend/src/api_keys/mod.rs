@@ -0,0 +1,429 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::cluster::openai_error;
+use crate::db::{models::ApiKey, queries};
+use crate::permissions::{constant_time_eq, generate_secret, hash_secret, ADMIN_TOKEN_HASH_KEY};
+use crate::settings::Key;
+use crate::AppState;
+
+/// When "true", the OpenAI-compatible endpoints need a key from `api_keys`.
+pub const REQUIRE_API_KEYS_KEY: &str = "require_api_keys";
+/// When "true", `/api` and `/ws` need a viewer or admin key (or the admin
+/// token from setup). See [`trusted_local`] for requests from this host.
+pub const REQUIRE_DASHBOARD_AUTH_KEY: &str = "require_dashboard_auth";
+/// Every key starts with this, so a leaked one is easy to recognize.
+pub const KEY_PREFIX: &str = "sk-sllm-";
+/// Characters of a key kept in `key_prefix` to tell keys apart.
+const SHOWN_CHARS: usize = KEY_PREFIX.len() + 4;
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Reachable without a key even with `require_dashboard_auth`: health
+/// checks, agent heartbeats (which carry their own secret) and setup status.
+const OPEN_PATHS: &[&str] = &["/api/health", "/api/agent/heartbeat", "/api/setup/status"];
+/// Also open: shared model files, which only approved devices may fetch.
+const PEER_PREFIX: &str = "/api/models/files";
+/// Reads that still need `admin`, since they show secrets or other keys.
+const ADMIN_READS: &[&str] = &[
+    "/api/keys",
+    "/api/settings",
+    "/api/webhooks",
+    "/api/backends/",
+    "/api/logs/",
+    "/api/support-bundle",
+];
+
+/// What a key may do, stored in `api_keys.scope`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum KeyScope {
+    /// The OpenAI-compatible `/v1` endpoints
+    #[default]
+    Inference,
+    /// Dashboard reads, and stats only on `/ws`; for shared screens
+    Viewer,
+    /// Everything
+    Admin,
+}
+
+impl KeyScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyScope::Inference => "inference",
+            KeyScope::Viewer => "viewer",
+            KeyScope::Admin => "admin",
+        }
+    }
+
+    /// Whether a key with this scope may make a request that needs `needed`.
+    pub fn covers(self, needed: KeyScope) -> bool {
+        self == KeyScope::Admin || self == needed
+    }
+}
+
+/// The scope a request needs: `inference` for `/v1`, `viewer` for dashboard
+/// reads, `admin` for changes and for reads that show secrets.
+pub fn required_scope(method: &Method, path: &str) -> KeyScope {
+    if path.starts_with("/v1/") {
+        return KeyScope::Inference;
+    }
+    let reads = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+    if reads && !ADMIN_READS.iter().any(|p| path.starts_with(p)) {
+        KeyScope::Viewer
+    } else {
+        KeyScope::Admin
+    }
+}
+
+/// A new key: what the client sends, and what gets stored.
+pub struct NewKey {
+    pub key: String,
+    pub key_prefix: String,
+    pub salt: String,
+    pub hashed_key: String,
+}
+
+pub fn generate() -> NewKey {
+    let key = format!("{}{}", KEY_PREFIX, generate_secret());
+    let salt = uuid::Uuid::new_v4().simple().to_string();
+    NewKey {
+        key_prefix: key[..SHOWN_CHARS].to_string(),
+        hashed_key: hash_key(&salt, &key),
+        salt,
+        key,
+    }
+}
+
+fn hash_key(salt: &str, key: &str) -> String {
+    hash_secret(&format!("{}{}", salt, key))
+}
+
+/// The stored key `presented` matches, revoked or not.
+fn find<'a>(keys: &'a [ApiKey], presented: &str) -> Option<&'a ApiKey> {
+    // Hash against every row rather than stopping early, so timing doesn't
+    // tell which keys exist
+    keys.iter().fold(None, |found, k| {
+        let matches = constant_time_eq(
+            hash_key(&k.salt, presented).as_bytes(),
+            k.hashed_key.as_bytes(),
+        );
+        if matches { Some(k) } else { found }
+    })
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+}
+
+/// `?token=` from a `/ws` URL; browsers can't set headers on a WebSocket.
+fn query_token(query: Option<&str>) -> Option<&str> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .filter(|k| !k.is_empty())
+}
+
+/// Requests per key in the current one-minute window.
+#[derive(Default)]
+pub struct KeyRateLimiter {
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl KeyRateLimiter {
+    /// Count a request against a key's per-minute limit. `Err` holds the
+    /// seconds until its window resets.
+    pub fn check(&self, key_id: &str, per_minute: u32) -> Result<(), u64> {
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+        let (started, count) = windows.entry(key_id.to_string()).or_insert((now, 0));
+        if now.duration_since(*started) >= RATE_WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        if *count >= per_minute {
+            let left = RATE_WINDOW.saturating_sub(now.duration_since(*started));
+            return Err(left.as_secs().max(1));
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    pub fn forget(&self, key_id: &str) {
+        self.windows.lock().unwrap().remove(key_id);
+    }
+}
+
+fn unauthorized(message: &str) -> Response {
+    openai_error(StatusCode::UNAUTHORIZED, message, "invalid_request_error", Some("invalid_api_key"))
+}
+
+/// Middleware for the `/v1` routes. With `require_api_keys` on, the
+/// request needs `Authorization: Bearer <key>` naming an unrevoked key
+/// within its rate limit; the key is then added to the request's
+/// extensions for the handler's model checks. Otherwise requests pass as-is.
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if !state.settings.get_bool(Key::RequireApiKeys) {
+        return next.run(req).await;
+    }
+    let Some(presented) = bearer(req.headers()) else {
+        return unauthorized(
+            "You didn't provide an API key. Send it as 'Authorization: Bearer <key>'.",
+        );
+    };
+    let keys = match queries::list_api_keys(&state.pool).await {
+        Ok(k) => k,
+        Err(e) => {
+            tracing::error!("Reading API keys: {}", e);
+            return openai_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not check the API key",
+                "server_error",
+                None,
+            );
+        }
+    };
+    let Some(key) = find(&keys, presented).cloned() else {
+        return unauthorized("Incorrect API key provided.");
+    };
+    if key.revoked_at.is_some() {
+        return unauthorized("This API key has been revoked.");
+    }
+    if !key.scope.covers(KeyScope::Inference) {
+        return openai_error(
+            StatusCode::FORBIDDEN,
+            "This API key lacks the 'inference' scope.",
+            "invalid_request_error",
+            Some("insufficient_scope"),
+        );
+    }
+
+    if let Some(limit) = key.rate_limit_rpm.filter(|n| *n > 0) {
+        if let Err(retry_after) = state.key_limiter.check(&key.id, limit as u32) {
+            let mut resp = openai_error(
+                StatusCode::TOO_MANY_REQUESTS,
+                &format!("Rate limit of {} requests per minute reached for this API key", limit),
+                "rate_limit_error",
+                Some("rate_limit_exceeded"),
+            );
+            if let Ok(v) = HeaderValue::from_str(&retry_after.to_string()) {
+                resp.headers_mut().insert("Retry-After", v);
+            }
+            return resp;
+        }
+    }
+
+    if let Err(e) = queries::record_api_key_use(&state.pool, &key.id).await {
+        tracing::warn!("Recording use of API key {}: {}", key.id, e);
+    }
+    tracing::info!("Authorized with API key '{}' ({})", key.name, key.key_prefix);
+    req.extensions_mut().insert(key);
+    next.run(req).await
+}
+
+fn dashboard_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
+
+/// Scope of a dashboard credential: the admin token from setup, or an
+/// unrevoked key. `None` when it is neither.
+async fn presented_scope(state: &AppState, presented: &str) -> anyhow::Result<Option<KeyScope>> {
+    if let Some(hash) = state.settings.get(ADMIN_TOKEN_HASH_KEY) {
+        if constant_time_eq(hash_secret(presented).as_bytes(), hash.as_bytes()) {
+            return Ok(Some(KeyScope::Admin));
+        }
+    }
+    let keys = queries::list_api_keys(&state.pool).await?;
+    let Some(key) = find(&keys, presented).filter(|k| k.revoked_at.is_none()) else {
+        return Ok(None);
+    };
+    if let Err(e) = queries::record_api_key_use(&state.pool, &key.id).await {
+        tracing::warn!("Recording use of API key {}: {}", key.id, e);
+    }
+    Ok(Some(key.scope))
+}
+
+/// Whether a request without a key counts as admin: only with
+/// `trust_localhost` on, from a loopback address, and not relayed by a
+/// reverse proxy, since everything a proxy on this host passes on arrives
+/// from loopback.
+fn trusted_local(state: &AppState, headers: &HeaderMap, peer: Option<SocketAddr>) -> bool {
+    state.settings.get_bool(Key::TrustLocalhost)
+        && peer.is_some_and(|addr| addr.ip().is_loopback())
+        && ![header::FORWARDED.as_str(), "x-forwarded-for", "x-real-ip"]
+            .iter()
+            .any(|name| headers.contains_key(*name))
+}
+
+/// The caller's scope on a route anyone may call: what
+/// [`require_dashboard_key`] settled on, else that of a key or the admin token
+/// sent as a bearer, else admin from a [trusted](trusted_local) local caller.
+/// `None` for anyone else.
+pub async fn caller_scope(
+    state: &AppState,
+    checked: Option<KeyScope>,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+) -> Option<KeyScope> {
+    if checked.is_some() {
+        return checked;
+    }
+    if let Some(presented) = bearer(headers) {
+        match presented_scope(state, presented).await {
+            Ok(Some(scope)) => return Some(scope),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Reading API keys: {}", e),
+        }
+    }
+    trusted_local(state, headers, peer).then_some(KeyScope::Admin)
+}
+
+/// Middleware for the dashboard API and `/ws`. With `require_dashboard_auth`
+/// on, a request needs a key whose scope covers [`required_scope`], sent as
+/// `Authorization: Bearer <key>` (or `?token=` on `/ws`). Requests from this
+/// host without a key pass as admin only when [`trusted_local`]. The caller's scope is added to the
+/// request's extensions, so `/ws` can hold viewers to stats.
+pub async fn require_dashboard_key(
+    State(state): State<Arc<AppState>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let path = req.uri().path().to_string();
+    let guarded = (path.starts_with("/api/") || path == "/ws")
+        && !OPEN_PATHS.contains(&path.as_str())
+        && !path.starts_with(PEER_PREFIX);
+    if !guarded || !state.settings.get_bool(Key::RequireDashboardAuth) {
+        return next.run(req).await;
+    }
+
+    let presented = bearer(req.headers())
+        .or_else(|| if path == "/ws" { query_token(req.uri().query()) } else { None })
+        .map(str::to_string);
+    let scope = match presented {
+        Some(presented) => match presented_scope(&state, &presented).await {
+            Ok(Some(scope)) => scope,
+            Ok(None) => return dashboard_error(StatusCode::UNAUTHORIZED, "Unknown or revoked key"),
+            Err(e) => {
+                tracing::error!("Reading API keys: {}", e);
+                return dashboard_error(StatusCode::INTERNAL_SERVER_ERROR, "Could not check the key");
+            }
+        },
+        None => {
+            let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| *addr);
+            if !trusted_local(&state, req.headers(), peer) {
+                return dashboard_error(
+                    StatusCode::UNAUTHORIZED,
+                    "Send a viewer or admin key as 'Authorization: Bearer <key>'",
+                );
+            }
+            KeyScope::Admin
+        }
+    };
+
+    let needed = required_scope(req.method(), &path);
+    if !scope.covers(needed) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": format!("This key lacks the '{}' scope", needed.as_str()),
+                "missing_scope": needed,
+            })),
+        )
+            .into_response();
+    }
+    req.extensions_mut().insert(scope);
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::ModelIds;
+
+    fn stored(id: &str, new: &NewKey) -> ApiKey {
+        ApiKey {
+            id: id.into(),
+            name: id.into(),
+            key_prefix: new.key_prefix.clone(),
+            salt: new.salt.clone(),
+            hashed_key: new.hashed_key.clone(),
+            rate_limit_rpm: None,
+            allowed_models: ModelIds(vec!["llama3-8b".into()]),
+            scope: KeyScope::Inference,
+            requests: 0,
+            last_used_at: None,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            revoked_at: None,
+        }
+    }
+
+    #[test]
+    fn keys_are_found_by_salted_hash() {
+        let (a, b) = (generate(), generate());
+        assert!(a.key.starts_with(KEY_PREFIX));
+        assert!(a.key.starts_with(&a.key_prefix));
+        assert_ne!(a.hashed_key, hash_secret(&a.key), "the hash must be salted");
+
+        let keys = [stored("a", &a), stored("b", &b)];
+        assert_eq!(find(&keys, &b.key).map(|k| k.id.as_str()), Some("b"));
+        assert!(find(&keys, &format!("{}x", a.key)).is_none());
+        assert!(keys[0].allows("llama3-8b") && !keys[0].allows("mistral"));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer(&headers), None);
+        headers.insert(header::AUTHORIZATION, format!("Bearer {}", a.key).parse().unwrap());
+        assert_eq!(bearer(&headers), Some(a.key.as_str()));
+    }
+
+    #[test]
+    fn scopes_follow_method_and_prefix() {
+        assert_eq!(required_scope(&Method::POST, "/v1/chat/completions"), KeyScope::Inference);
+        assert_eq!(required_scope(&Method::GET, "/api/gpu"), KeyScope::Viewer);
+        assert_eq!(required_scope(&Method::GET, "/ws"), KeyScope::Viewer);
+        assert_eq!(required_scope(&Method::POST, "/api/devices/d1/approve"), KeyScope::Admin);
+        assert_eq!(required_scope(&Method::GET, "/api/keys"), KeyScope::Admin);
+
+        assert!(KeyScope::Admin.covers(KeyScope::Inference));
+        assert!(KeyScope::Viewer.covers(KeyScope::Viewer));
+        assert!(!KeyScope::Viewer.covers(KeyScope::Admin));
+        assert!(!KeyScope::Inference.covers(KeyScope::Viewer));
+
+        assert_eq!(query_token(Some("v=1&token=sk-sllm-ab")), Some("sk-sllm-ab"));
+        assert_eq!(query_token(Some("token=")), None);
+        assert_eq!(query_token(None), None);
+    }
+
+    #[test]
+    fn rate_limit_is_per_key_and_minute() {
+        let limiter = KeyRateLimiter::default();
+        assert!(limiter.check("a", 2).is_ok());
+        assert!(limiter.check("a", 2).is_ok());
+        assert!(matches!(limiter.check("a", 2), Err(secs) if (1..=60).contains(&secs)));
+        assert!(limiter.check("b", 2).is_ok());
+
+        limiter.windows.lock().unwrap().get_mut("a").unwrap().0 -= RATE_WINDOW;
+        assert!(limiter.check("a", 2).is_ok());
+        limiter.forget("a");
+        assert!(!limiter.windows.lock().unwrap().contains_key("a"));
+    }
+}
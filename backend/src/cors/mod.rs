@@ -0,0 +1,202 @@
+use axum::http::{request::Parts, HeaderValue};
+use reqwest::Url;
+use std::sync::Arc;
+use tower_http::cors::{AllowCredentials, AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
+
+use crate::settings::SettingsCache;
+
+/// Setting: comma-separated origins allowed to call the API from a browser.
+pub const CORS_ORIGINS_KEY: &str = "cors_allowed_origins";
+/// The dashboard on this machine, on any port.
+pub const DEFAULT_ORIGINS: &str =
+    "http://localhost:*,http://127.0.0.1:*,https://localhost:*,https://127.0.0.1:*";
+
+/// One entry of `cors_allowed_origins`.
+#[derive(Debug, PartialEq)]
+enum OriginPattern {
+    /// `*`: every origin, without credentials
+    Any,
+    /// `scheme://host[:port]`; `port` is `None` for `:*`
+    Origin {
+        scheme: String,
+        host: String,
+        port: Option<u16>,
+    },
+}
+
+/// Parse a `cors_allowed_origins` value. Entries are `*` or
+/// `http(s)://host[:port]`, where the port may be `*`.
+fn parse_origins(value: &str) -> Result<Vec<OriginPattern>, String> {
+    let mut patterns = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        if entry == "*" {
+            patterns.push(OriginPattern::Any);
+            continue;
+        }
+        let (base, any_port) = match entry.strip_suffix(":*") {
+            Some(base) => (base, true),
+            None => (entry, false),
+        };
+        let invalid = || format!("'{}' is not an origin like https://host:port", entry);
+        let url = Url::parse(base).map_err(|_| invalid())?;
+        let bare = url.path() == "/" && !base.ends_with('/');
+        if !matches!(url.scheme(), "http" | "https")
+            || !bare
+            || url.query().is_some()
+            || url.fragment().is_some()
+            || !url.username().is_empty()
+            || (any_port && url.port().is_some())
+        {
+            return Err(invalid());
+        }
+        let host = url.host_str().ok_or_else(invalid)?.to_string();
+        patterns.push(OriginPattern::Origin {
+            scheme: url.scheme().to_string(),
+            host,
+            port: if any_port { None } else { url.port_or_known_default() },
+        });
+    }
+    if patterns.is_empty() {
+        return Err(format!("{} must list at least one origin", CORS_ORIGINS_KEY));
+    }
+    Ok(patterns)
+}
+
+pub fn validate_origins(value: &str) -> Result<(), String> {
+    parse_origins(value).map(|_| ())
+}
+
+fn matches(patterns: &[OriginPattern], origin: &HeaderValue) -> bool {
+    let Some(url) = origin.to_str().ok().and_then(|o| Url::parse(o).ok()) else {
+        return false;
+    };
+    patterns.iter().any(|p| match p {
+        OriginPattern::Any => true,
+        OriginPattern::Origin { scheme, host, port } => {
+            url.scheme() == scheme
+                && url.host_str() == Some(host.as_str())
+                && port.is_none_or(|p| url.port_or_known_default() == Some(p))
+        }
+    })
+}
+
+/// Origins currently allowed, falling back to [`DEFAULT_ORIGINS`] when the
+/// setting is unset or (somehow) invalid.
+fn current(settings: &SettingsCache) -> Vec<OriginPattern> {
+    settings
+        .get(CORS_ORIGINS_KEY)
+        .and_then(|v| parse_origins(&v).ok())
+        .unwrap_or_else(|| parse_origins(DEFAULT_ORIGINS).unwrap_or_default())
+}
+
+/// Say loudly when any website can drive the API.
+pub fn warn_if_wildcard(value: &str) {
+    if parse_origins(value).is_ok_and(|p| p.contains(&OriginPattern::Any)) {
+        tracing::warn!(
+            "{} contains '*': any website open in a browser on this network can call this API",
+            CORS_ORIGINS_KEY
+        );
+    }
+}
+
+/// CORS layer that reads `cors_allowed_origins` on every request, so a
+/// change applies without a restart. Listed origins may send credentials;
+/// origins only let in by `*` may not.
+pub fn layer(settings: Arc<SettingsCache>) -> CorsLayer {
+    let origin_settings = settings.clone();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::predicate(move |origin, _: &Parts| {
+            matches(&current(&origin_settings), origin)
+        }))
+        .allow_credentials(AllowCredentials::predicate(move |origin, _: &Parts| {
+            let listed: Vec<OriginPattern> = current(&settings)
+                .into_iter()
+                .filter(|p| *p != OriginPattern::Any)
+                .collect();
+            matches(&listed, origin)
+        }))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+
+    #[test]
+    fn origins_are_validated() {
+        assert!(validate_origins(DEFAULT_ORIGINS).is_ok());
+        assert!(validate_origins("*").is_ok());
+        assert!(validate_origins("https://dash.example.com, http://10.0.0.5:5173").is_ok());
+        assert!(validate_origins("").is_err());
+        assert!(validate_origins("example.com").is_err());
+        assert!(validate_origins("ftp://example.com").is_err());
+        assert!(validate_origins("https://example.com/app").is_err());
+        assert!(validate_origins("https://example.com/").is_err());
+        assert!(validate_origins("https://example.com:8080:*").is_err());
+    }
+
+    /// A server with the CORS layer in front of one route.
+    async fn serve(settings: Arc<SettingsCache>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route("/api/devices", get(|| async { "[]" }))
+            .layer(layer(settings));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}/api/devices", addr)
+    }
+
+    async fn preflight(url: &str, origin: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .request(reqwest::Method::OPTIONS, url)
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type")
+            .send()
+            .await
+            .unwrap()
+    }
+
+    fn allowed_origin(response: &reqwest::Response) -> Option<&str> {
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .and_then(|v| v.to_str().ok())
+    }
+
+    #[tokio::test]
+    async fn preflight_follows_the_setting() {
+        let settings = Arc::new(
+            SettingsCache::load(crate::settings::tests::test_pool().await)
+                .await
+                .unwrap(),
+        );
+        let url = serve(settings.clone()).await;
+
+        // Unset: the local dashboard on any port, nothing else
+        let local = preflight(&url, "http://localhost:5173").await;
+        assert_eq!(allowed_origin(&local), Some("http://localhost:5173"));
+        let lookalike = preflight(&url, "http://localhost.evil.com").await;
+        assert_eq!(allowed_origin(&lookalike), None);
+
+        settings.set(CORS_ORIGINS_KEY, "https://dash.example.com").await.unwrap();
+        let listed = preflight(&url, "https://dash.example.com").await;
+        assert_eq!(allowed_origin(&listed), Some("https://dash.example.com"));
+        assert_eq!(
+            listed.headers().get("access-control-allow-credentials").unwrap(),
+            "true"
+        );
+        assert_eq!(listed.headers().get("access-control-allow-methods").unwrap(), "POST");
+        let other = preflight(&url, "https://evil.example.com").await;
+        assert_eq!(allowed_origin(&other), None);
+        let local = preflight(&url, "http://localhost:5173").await;
+        assert_eq!(allowed_origin(&local), None);
+
+        settings.set(CORS_ORIGINS_KEY, "*").await.unwrap();
+        let any = preflight(&url, "https://evil.example.com").await;
+        assert_eq!(allowed_origin(&any), Some("https://evil.example.com"));
+        assert!(any.headers().get("access-control-allow-credentials").is_none());
+    }
+}
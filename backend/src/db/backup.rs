@@ -0,0 +1,241 @@
+//! Copies of the database file taken before migrations run, so a bad one
+//! can be undone. A backup of `shared_memory.db` is
+//! `shared_memory.backup-<UTC time>.db` beside it, with the `-wal` and
+//! `-shm` files it had; a second backup within the same second gets `-2`,
+//! `-3`, ... after the time. Only the newest [`KEEP`] are kept.
+//!
+//! Restoring never swaps files under an open pool: the backup is staged as
+//! `<db>.restore` and moved into place by [`apply_staged`] at the next
+//! start, before the database is opened.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Backups kept; older ones are deleted when a new one is taken.
+pub const KEEP: usize = 3;
+/// Files SQLite keeps beside the database in WAL mode.
+const SIDECARS: [&str; 2] = ["-wal", "-shm"];
+const STAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const STAGED_EXT: &str = "restore";
+
+/// A backup beside the database.
+#[derive(Debug, Clone, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct Backup {
+    /// File name, as `POST /api/system/backups/restore` takes it
+    pub name: String,
+    /// The database file and its `-wal`
+    pub size_bytes: u64,
+    /// When it was taken (RFC 3339)
+    pub created_at: String,
+}
+
+/// `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// The `<stem>.backup-` prefix of `db`'s backups.
+fn prefix(db: &Path) -> String {
+    let stem = db.file_stem().and_then(|s| s.to_str()).unwrap_or("database");
+    format!("{}.backup-", stem)
+}
+
+/// When the backup called `name` was taken, and its place among those taken
+/// that second, if it is one of `db`'s.
+fn taken_at(db: &Path, name: &str) -> Option<(chrono::DateTime<chrono::Utc>, u32)> {
+    let stamp = name.strip_prefix(&prefix(db))?.strip_suffix(".db")?;
+    let (stamp, n) = match stamp.split_once('-') {
+        Some((stamp, n)) => (stamp, n.parse().ok().filter(|n| *n > 1)?),
+        None => (stamp, 1),
+    };
+    let at = chrono::NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT).ok()?.and_utc();
+    Some((at, n))
+}
+
+/// Copy `from` and its sidecar files to `to`, leaving no stale sidecars of
+/// `to` behind.
+fn copy_with_sidecars(from: &Path, to: &Path) -> Result<()> {
+    std::fs::copy(from, to).with_context(|| format!("copying {} to {}", from.display(), to.display()))?;
+    for suffix in SIDECARS {
+        let (src, dest) = (with_suffix(from, suffix), with_suffix(to, suffix));
+        if src.exists() {
+            std::fs::copy(&src, &dest).with_context(|| format!("copying {}", src.display()))?;
+        } else if dest.exists() {
+            std::fs::remove_file(&dest)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_with_sidecars(path: &Path) -> Result<()> {
+    std::fs::remove_file(path)?;
+    for suffix in SIDECARS {
+        let _ = std::fs::remove_file(with_suffix(path, suffix));
+    }
+    Ok(())
+}
+
+/// Copy `db` to a backup stamped `now`, never over an earlier backup.
+/// Returns its path.
+pub fn create(db: &Path, now: chrono::DateTime<chrono::Utc>) -> Result<PathBuf> {
+    let stamp = now.format(STAMP_FORMAT).to_string();
+    for n in 1u32.. {
+        let name = match n {
+            1 => format!("{}{}.db", prefix(db), stamp),
+            n => format!("{}{}-{}.db", prefix(db), stamp, n),
+        };
+        let dest = db.with_file_name(name);
+        // Claim the name first: the copy itself would overwrite
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&dest) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("creating {}", dest.display())),
+        }
+        copy_with_sidecars(db, &dest)?;
+        return Ok(dest);
+    }
+    unreachable!("backup names run out")
+}
+
+/// `db`'s backups, newest first.
+pub fn list(db: &Path) -> Vec<Backup> {
+    let dir = match db.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<Backup> = entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_str()?.to_string();
+            let (created, _) = taken_at(db, &name)?;
+            let size = |p: &Path| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0);
+            let path = e.path();
+            Some(Backup {
+                size_bytes: size(&path) + size(&with_suffix(&path, "-wal")),
+                created_at: created.to_rfc3339(),
+                name,
+            })
+        })
+        .collect();
+    backups.sort_by_key(|b| std::cmp::Reverse(taken_at(db, &b.name)));
+    backups
+}
+
+/// Delete all but the newest `keep` of `db`'s backups. Returns the names deleted.
+pub fn prune(db: &Path, keep: usize) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+    for backup in list(db).into_iter().skip(keep) {
+        remove_with_sidecars(&db.with_file_name(&backup.name))?;
+        removed.push(backup.name);
+    }
+    Ok(removed)
+}
+
+/// Stage the backup called `name` to replace `db` at the next start.
+pub fn stage(db: &Path, name: &str) -> Result<()> {
+    if !list(db).iter().any(|b| b.name == name) {
+        return Err(anyhow!("No backup named '{}'", name));
+    }
+    copy_with_sidecars(&db.with_file_name(name), &with_suffix(db, &format!(".{}", STAGED_EXT)))
+}
+
+/// Put a staged backup in place of `db`, first backing up what it replaces
+/// as of `now`. Returns whether there was one.
+pub fn apply_staged(db: &Path, now: chrono::DateTime<chrono::Utc>) -> Result<bool> {
+    let staged = with_suffix(db, &format!(".{}", STAGED_EXT));
+    if !staged.exists() {
+        return Ok(false);
+    }
+    if db.exists() {
+        let replaced = create(db, now)?;
+        tracing::info!("Database before the restore kept as {}", replaced.display());
+    }
+    copy_with_sidecars(&staged, db)?;
+    remove_with_sidecars(&staged)?;
+    prune(db, KEEP)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn only_the_newest_backups_are_kept() {
+        let dir = std::env::temp_dir().join(format!("sharedllm-backup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("shared_memory.db");
+        std::fs::write(&db, b"v1").unwrap();
+        std::fs::write(with_suffix(&db, "-wal"), b"wal").unwrap();
+        std::fs::write(dir.join("shared_memory.backup-notes.db"), b"not a backup").unwrap();
+
+        let at = |day| chrono::Utc.with_ymd_and_hms(2026, 1, day, 12, 0, 0).unwrap();
+        for day in 1..=5 {
+            create(&db, at(day)).unwrap();
+        }
+        let first = dir.join("shared_memory.backup-20260101T120000Z.db");
+        assert!(with_suffix(&first, "-wal").exists());
+        assert_eq!(list(&db).len(), 5);
+        assert_eq!(list(&db)[0].size_bytes, 5);
+
+        assert_eq!(prune(&db, KEEP).unwrap().len(), 2);
+        let names: Vec<String> = list(&db).into_iter().map(|b| b.name).collect();
+        assert_eq!(
+            names,
+            ["05", "04", "03"].map(|d| format!("shared_memory.backup-202601{}T120000Z.db", d))
+        );
+        assert!(!first.exists() && !with_suffix(&first, "-wal").exists());
+        assert!(dir.join("shared_memory.backup-notes.db").exists());
+
+        // A restore waits for the next start, and keeps what it replaces
+        assert!(stage(&db, "shared_memory.backup-20260101T120000Z.db").is_err());
+        std::fs::write(&db, b"v2").unwrap();
+        std::fs::remove_file(with_suffix(&db, "-wal")).unwrap();
+        stage(&db, &names[2]).unwrap();
+        assert_eq!(std::fs::read(&db).unwrap(), b"v2");
+        assert!(apply_staged(&db, at(6)).unwrap());
+        assert_eq!(std::fs::read(&db).unwrap(), b"v1");
+        assert_eq!(std::fs::read(with_suffix(&db, "-wal")).unwrap(), b"wal");
+        assert!(!apply_staged(&db, at(6)).unwrap());
+        assert_eq!(list(&db).len(), KEEP);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_restore_then_a_migration_backup_keeps_both() {
+        let dir = std::env::temp_dir().join(format!("sharedllm-backup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = dir.join("shared_memory.db");
+        let at = |secs| chrono::Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, secs).unwrap();
+        std::fs::write(&db, b"old").unwrap();
+        let old = create(&db, at(0)).unwrap();
+        std::fs::write(&db, b"current").unwrap();
+
+        // The restored file needs migrations again, so a backup follows in the same second
+        stage(&db, old.file_name().unwrap().to_str().unwrap()).unwrap();
+        assert!(apply_staged(&db, at(5)).unwrap());
+        let migration = create(&db, at(5)).unwrap();
+
+        let names: Vec<String> = list(&db).into_iter().map(|b| b.name).collect();
+        assert_eq!(
+            names,
+            [
+                "shared_memory.backup-20260101T120005Z-2.db",
+                "shared_memory.backup-20260101T120005Z.db",
+                "shared_memory.backup-20260101T120000Z.db",
+            ]
+        );
+        assert_eq!(migration, dir.join(&names[0]));
+        assert_eq!(std::fs::read(dir.join(&names[1])).unwrap(), b"current");
+        assert_eq!(std::fs::read(&migration).unwrap(), b"old");
+        assert!(taken_at(&db, "shared_memory.backup-20260101T120005Z-1.db").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
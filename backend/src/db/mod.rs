@@ -1,24 +1,90 @@
+pub mod backup;
 pub mod models;
 pub mod queries;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use sqlx::{
+    migrate::{Migration, Migrator},
     sqlite::{SqliteConnectOptions, SqlitePoolOptions},
     SqlitePool,
 };
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-pub async fn init_pool(database_url: &str) -> Result<SqlitePool> {
-    // Parse the URL into connect options and enable file creation
-    let connect_opts = SqliteConnectOptions::from_str(database_url)?
-        .create_if_missing(true);
+/// Migrations embedded at build time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Set to `1` to start read-only when the database can't be written.
+pub const READ_ONLY_ENV: &str = "READ_ONLY_OK";
+
+/// `DATABASE_URL`, or `./data/shared_memory.db` next to the working directory.
+pub fn database_url() -> String {
+    std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:./data/shared_memory.db".to_string())
+}
+
+/// Whether `READ_ONLY_OK` is set.
+pub fn read_only_allowed() -> bool {
+    std::env::var(READ_ONLY_ENV).is_ok_and(|v| matches!(v.trim(), "1" | "true"))
+}
+
+/// An open database. `read_only` when it couldn't be written and
+/// `READ_ONLY_OK` let the server start anyway.
+pub struct Database {
+    pub pool: SqlitePool,
+    pub read_only: bool,
+}
+
+/// Open and migrate the database. When that fails for lack of write access,
+/// `allow_read_only` falls back to a read-only pool, provided every
+/// migration was already applied; otherwise the error says how to fix it.
+pub async fn open(database_url: &str, allow_read_only: bool) -> Result<Database> {
+    let connect_opts = SqliteConnectOptions::from_str(database_url)?;
+    let path = connect_opts.clone().get_filename().to_path_buf();
+
+    if path.as_os_str() != ":memory:" && backup::apply_staged(&path, chrono::Utc::now()).context("restoring a backup")? {
+        tracing::warn!("Restored {} from the backup chosen before the restart", path.display());
+    }
+
+    let err = match open_writable(database_url, connect_opts.clone()).await {
+        Ok(pool) => return Ok(Database { pool, read_only: false }),
+        Err(e) if is_write_failure(&e) => e,
+        Err(e) => return Err(e),
+    };
+
+    if !allow_read_only {
+        let dir = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.display().to_string(),
+            _ => ".".to_string(),
+        };
+        return Err(err.context(format!(
+            "The database at {} is not writable. Make {} and the file writable by this user, \
+             point DATABASE_URL somewhere writable, or set {}=1 to start read-only",
+            path.display(),
+            dir,
+            READ_ONLY_ENV
+        )));
+    }
+
+    tracing::warn!("Cannot write {} ({:#}); opening it read-only", path.display(), err);
+    let pool = open_read_only(&path, connect_opts).await?;
+    tracing::warn!("Database opened read-only at {}: changes will be refused", database_url);
+    Ok(Database { pool, read_only: true })
+}
+
+async fn open_writable(database_url: &str, connect_opts: SqliteConnectOptions) -> Result<SqlitePool> {
+    let path = connect_opts.clone().get_filename().to_path_buf();
+    let in_memory = path.as_os_str() == ":memory:";
+    // A new database has nothing to lose to a migration
+    let existed = !in_memory && std::fs::metadata(&path).is_ok_and(|m| m.len() > 0);
 
     // Ensure the parent directory exists before sqlx tries to open the file
     if let Some(filename) = connect_opts.clone().get_filename().to_str() {
         if filename != ":memory:" {
-            if let Some(parent) = std::path::Path::new(filename).parent() {
+            if let Some(parent) = Path::new(filename).parent() {
                 if !parent.as_os_str().is_empty() {
-                    tokio::fs::create_dir_all(parent).await?;
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("creating {}", parent.display()))?;
                 }
             }
         }
@@ -26,12 +92,175 @@ pub async fn init_pool(database_url: &str) -> Result<SqlitePool> {
 
     let pool = SqlitePoolOptions::new()
         .max_connections(10)
-        .connect_with(connect_opts)
-        .await?;
+        .connect_with(connect_opts.create_if_missing(true))
+        .await
+        .context("opening the database")?;
+
+    if existed {
+        // A file whose migration history can't be read may need any of them
+        let pending = match pending_migrations(&pool).await {
+            Ok(pending) => pending,
+            Err(_) => MIGRATOR.iter().map(migration_name).collect(),
+        };
+        if !pending.is_empty() {
+            let copy = backup::create(&path, chrono::Utc::now()).context("backing up the database before migrating")?;
+            tracing::info!("Backed up the database to {} before migrating ({})", copy.display(), pending.join(", "));
+            for name in backup::prune(&path, backup::KEEP)? {
+                tracing::info!("Deleted old database backup {}", name);
+            }
+        }
+    }
 
     // Run embedded migrations
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    MIGRATOR.run(&pool).await.context("migrating the database")?;
+
+    // SQLite quietly opens a file it can't write read-only, and applied
+    // migrations write nothing, so try a write and roll it back
+    let mut tx = pool.begin().await?;
+    sqlx::query("CREATE TABLE _write_check (x)")
+        .execute(&mut *tx)
+        .await
+        .context("checking the database is writable")?;
+    tx.rollback().await?;
 
     tracing::info!("Database initialized at {}", database_url);
     Ok(pool)
 }
+
+async fn open_read_only(path: &Path, connect_opts: SqliteConnectOptions) -> Result<SqlitePool> {
+    let never_set_up = || {
+        anyhow!(
+            "{} has never been set up, and can't be while read-only; \
+             start once with write access first",
+            path.display()
+        )
+    };
+    if !path.exists() {
+        return Err(never_set_up());
+    }
+    let pool = SqlitePoolOptions::new()
+        .max_connections(10)
+        .connect_with(connect_opts.read_only(true))
+        .await
+        .with_context(|| format!("Cannot open {} read-only", path.display()))?;
+
+    // Nothing can be migrated, so the schema must already be current
+    let pending = pending_migrations(&pool).await.map_err(|_| never_set_up())?;
+    if !pending.is_empty() {
+        return Err(anyhow!(
+            "{} can't be opened read-only: it needs migrations that can't run without \
+             write access ({}). Start once with write access first",
+            path.display(),
+            pending.join(", ")
+        ));
+    }
+    Ok(pool)
+}
+
+/// Migrations not applied to the database yet, as `<version>_<name>`. An
+/// error when it has never been migrated.
+async fn pending_migrations(pool: &SqlitePool) -> Result<Vec<String>> {
+    let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+        .fetch_all(pool)
+        .await?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .map(migration_name)
+        .collect())
+}
+
+fn migration_name(m: &Migration) -> String {
+    format!("{}_{}", m.version, m.description.replace(' ', "_"))
+}
+
+/// The file `pool` reads, or `None` for an in-memory database.
+pub async fn file_path(pool: &SqlitePool) -> Option<PathBuf> {
+    let rows: Vec<(i64, String, String)> = sqlx::query_as("PRAGMA database_list").fetch_all(pool).await.ok()?;
+    rows.into_iter()
+        .find(|(_, name, _)| name == "main")
+        .map(|(_, _, file)| PathBuf::from(file))
+        .filter(|p| !p.as_os_str().is_empty())
+}
+
+/// Whether `e` comes from lacking write access: a read-only filesystem,
+/// a permission error, or SQLite refusing to write or create the file.
+fn is_write_failure(e: &anyhow::Error) -> bool {
+    fn io(e: &std::io::Error) -> bool {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem
+        )
+    }
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return io(e);
+        }
+        match cause.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::Io(e)) => io(e),
+            Some(sqlx::Error::Database(db)) => {
+                // Extended result codes; the primary code is the low byte.
+                // SQLITE_PERM, SQLITE_READONLY, SQLITE_CANTOPEN
+                let code = db.code().and_then(|c| c.parse::<i32>().ok()).unwrap_or(0);
+                matches!(code & 0xff, 3 | 8 | 14)
+            }
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn read_only_needs_an_up_to_date_database() {
+        let dir = std::env::temp_dir().join(format!("sharedllm-db-{}", uuid::Uuid::new_v4()));
+        let url = format!("sqlite:{}", dir.join("t.db").display());
+
+        // Not created yet: read-only can't help
+        let opts = SqliteConnectOptions::from_str(&url).unwrap();
+        let err = open_read_only(&dir.join("t.db"), opts.clone()).await.unwrap_err();
+        assert!(err.to_string().contains("never been set up"), "{}", err);
+
+        let db = open(&url, false).await.unwrap();
+        assert!(!db.read_only);
+        db.pool.close().await;
+
+        let pool = open_read_only(&dir.join("t.db"), opts.clone()).await.unwrap();
+        let err = sqlx::query("DELETE FROM settings").execute(&pool).await.unwrap_err();
+        assert!(is_write_failure(&err.into()));
+        pool.close().await;
+
+        let pool = open_writable(&url, opts.clone()).await.unwrap();
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+        let err = open_read_only(&dir.join("t.db"), opts).await.unwrap_err();
+        assert!(err.to_string().contains("needs migrations"), "{}", err);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreadable_migration_history_is_backed_up_first() {
+        let dir = std::env::temp_dir().join(format!("sharedllm-db-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("t.db");
+        let url = format!("sqlite:{}", path.display());
+
+        // An existing file that was never migrated
+        let opts = SqliteConnectOptions::from_str(&url).unwrap().create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(opts).await.unwrap();
+        sqlx::query("CREATE TABLE notes (x)").execute(&pool).await.unwrap();
+        pool.close().await;
+
+        let db = open(&url, false).await.unwrap();
+        db.pool.close().await;
+        assert_eq!(backup::list(&path).len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,16 @@
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+use crate::llama_cpp::{health::HealthReason, lora::LoraAdapter, rpc_probe::ProbeDepth, RpcBackend};
+use crate::api_keys::KeyScope;
+use crate::ollama::pulls::PullErrorCode;
+use crate::permissions::{DeviceKind, DeviceStatus, RpcStatus};
+
 // ─── Device ─────────────────────────────────────────────────────────────────
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
 pub struct Device {
     pub id: String,
     pub name: String,
@@ -13,7 +19,7 @@ pub struct Device {
     pub hostname: Option<String>,
     pub platform: Option<String>,
     pub role_id: Option<String>,
-    pub status: String, // pending | approved | denied | suspended | offline
+    pub status: DeviceStatus,
     pub discovery_method: String, // mdns | manual
     pub allocated_memory_mb: i64,
     pub last_seen: Option<String>,
@@ -21,9 +27,82 @@ pub struct Device {
     pub created_at: String,
     // RPC / distributed inference fields (added in migration 0003)
     pub rpc_port: i64,
-    pub rpc_status: String, // offline | connecting | ready | error
+    pub rpc_status: RpcStatus,
     pub memory_total_mb: i64,
     pub memory_free_mb: i64,
+    /// SHA-256 of the agent secret issued at approval (added in migration 0009)
+    #[serde(skip)]
+    pub agent_secret_hash: Option<String>,
+    /// Global revision of the last change to this device or its tags (migration 0012)
+    pub rev: i64,
+    /// When the RPC server was last seen becoming ready (migration 0013)
+    pub last_rpc_ready_at: Option<String>,
+    /// The agent runs llama-rpc-server with `--cache` (migration 0015)
+    pub rpc_cache_enabled: bool,
+    /// Size of that cache as of the last heartbeat
+    pub rpc_cache_mb: i64,
+    /// Further llama-rpc-server ports on the same host, e.g. one per GPU (migration 0017)
+    #[sqlx(try_from = "String")]
+    #[schema(value_type = Vec<u16>)]
+    pub extra_rpc_ports: RpcPorts,
+    // Hardware inventory from the agent heartbeat (migration 0018)
+    pub cpu_model: Option<String>,
+    /// Physical cores
+    pub cpu_cores: Option<i64>,
+    pub os_version: Option<String>,
+    /// GPU driver version (nvidia-smi, ROCm), or the macOS build on Apple Silicon
+    pub gpu_driver: Option<String>,
+    /// Free space on the agent's home volume
+    pub disk_free_mb: Option<i64>,
+    /// Runs its own SharedLLM backend that chat requests may be forwarded to (migration 0020)
+    pub is_peer: bool,
+    /// `--device` backend the agent's llama-rpc-server was installed with (migration 0022)
+    pub rpc_backend: Option<RpcBackend>,
+    /// Its `--mem` cap, in MB
+    pub rpc_mem_mb: Option<i64>,
+    /// Hash of the pairing code the agent printed at registration (migration 0023)
+    #[serde(skip)]
+    pub pairing_code_hash: Option<String>,
+    /// Approval needs the pairing code until then
+    pub pairing_expires_at: Option<String>,
+    /// Wrong pairing codes entered so far
+    pub pairing_attempts: i64,
+    /// Too many wrong codes: approval waits for the agent to check in with
+    /// the enrollment token
+    pub pairing_locked: bool,
+    /// Agent-only box or full backend (migration 0025)
+    pub device_kind: DeviceKind,
+    /// What the operator wants to remember about it (migration 0026)
+    pub notes: Option<String>,
+    /// Operator-defined key/value metadata, e.g. `{"location": "basement"}`
+    #[sqlx(try_from = "String")]
+    #[schema(value_type = HashMap<String, String>)]
+    pub labels: DeviceLabels,
+    /// Reliability as an RPC device, 0-100 (migration 0030); see `llama_cpp::health`
+    pub health_score: i64,
+    /// Status to return to when an `offline` device answers again (migration 0031)
+    pub prior_status: Option<DeviceStatus>,
+    /// `memory_total_mb` / `memory_free_mb` were set by hand and aren't
+    /// updated by probes or heartbeats (migration 0033)
+    pub memory_manual_override: bool,
+    /// How deep this host's last probe of its RPC server went (migration 0034)
+    pub rpc_probe_depth: Option<ProbeDepth>,
+    /// Whether that probe passed
+    pub rpc_probe_ok: Option<bool>,
+    /// What it found, e.g. `protocol 2.0.0: 6000 of 8192 MB free`
+    pub rpc_probe_detail: Option<String>,
+    pub rpc_probe_at: Option<String>,
+    /// When it was deleted; only soft-deleted devices, listed with
+    /// `?include_deleted=true`, have one (migration 0036)
+    pub deleted_at: Option<String>,
+    /// Grouping tags from the device_tags table (not a devices column)
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Seconds since `last_seen` (or `first_seen`), as of the read
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub last_seen_secs_ago: Option<i64>,
 }
 
 impl Device {
@@ -37,23 +116,120 @@ impl Device {
             hostname: None,
             platform: None,
             role_id: None,
-            status: "pending".into(),
+            status: DeviceStatus::Pending,
             discovery_method: discovery_method.into(),
             allocated_memory_mb: 0,
             last_seen: Some(now.clone()),
             first_seen: now.clone(),
             created_at: now,
             rpc_port: 8181,
-            rpc_status: "offline".into(),
+            rpc_status: RpcStatus::Offline,
             memory_total_mb: 0,
             memory_free_mb: 0,
+            agent_secret_hash: None,
+            rev: 0,
+            last_rpc_ready_at: None,
+            rpc_cache_enabled: false,
+            rpc_cache_mb: 0,
+            extra_rpc_ports: RpcPorts::default(),
+            cpu_model: None,
+            cpu_cores: None,
+            os_version: None,
+            gpu_driver: None,
+            disk_free_mb: None,
+            is_peer: false,
+            rpc_backend: None,
+            rpc_mem_mb: None,
+            pairing_code_hash: None,
+            pairing_expires_at: None,
+            pairing_attempts: 0,
+            pairing_locked: false,
+            device_kind: DeviceKind::Unknown,
+            notes: None,
+            labels: DeviceLabels::default(),
+            health_score: crate::llama_cpp::health::MAX_SCORE,
+            prior_status: None,
+            memory_manual_override: false,
+            rpc_probe_depth: None,
+            rpc_probe_ok: None,
+            rpc_probe_detail: None,
+            rpc_probe_at: None,
+            deleted_at: None,
+            tags: Vec::new(),
+            last_seen_secs_ago: Some(0),
+        }
+    }
+
+    /// When the device was last heard from: `last_seen`, else `first_seen`.
+    pub fn last_heard(&self) -> Option<chrono::DateTime<Utc>> {
+        let at = self.last_seen.as_deref().unwrap_or(&self.first_seen);
+        chrono::DateTime::parse_from_rfc3339(at).ok().map(|t| t.with_timezone(&Utc))
+    }
+
+    /// Work out `last_seen_secs_ago` from `last_seen` as of now.
+    pub fn update_freshness(&mut self) {
+        self.last_seen_secs_ago = self.last_heard().map(|t| (Utc::now() - t).num_seconds().max(0));
+    }
+
+    /// Every RPC endpoint port: `rpc_port` first, then `extra_rpc_ports`.
+    pub fn rpc_ports(&self) -> Vec<u16> {
+        let mut ports = vec![self.rpc_port as u16];
+        for &port in &self.extra_rpc_ports.0 {
+            if !ports.contains(&port) {
+                ports.push(port);
+            }
         }
+        ports
+    }
+
+    /// `ip:port` of every RPC endpoint, in [`Device::rpc_ports`] order.
+    pub fn rpc_addresses(&self) -> Vec<String> {
+        self.rpc_ports()
+            .into_iter()
+            .map(|port| format!("{}:{}", self.ip, port))
+            .collect()
+    }
+}
+
+/// `devices.extra_rpc_ports`, stored as a JSON array.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RpcPorts(pub Vec<u16>);
+
+impl From<String> for RpcPorts {
+    /// A malformed value reads as no extra ports rather than failing the row.
+    fn from(value: String) -> Self {
+        RpcPorts(serde_json::from_str(&value).unwrap_or_default())
+    }
+}
+
+impl RpcPorts {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).unwrap_or_else(|_| "[]".into())
+    }
+}
+
+/// `devices.labels`, stored as a JSON object.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DeviceLabels(pub BTreeMap<String, String>);
+
+impl From<String> for DeviceLabels {
+    /// A malformed value reads as no labels rather than failing the row.
+    fn from(value: String) -> Self {
+        DeviceLabels(serde_json::from_str(&value).unwrap_or_default())
+    }
+}
+
+impl DeviceLabels {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).unwrap_or_else(|_| "{}".into())
     }
 }
 
 // ─── Role ────────────────────────────────────────────────────────────────────
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
 pub struct Role {
     pub id: String,
     pub name: String,
@@ -65,16 +241,327 @@ pub struct Role {
 
 // ─── Allocation ──────────────────────────────────────────────────────────────
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
 pub struct Allocation {
     pub id: String,
-    pub device_id: String,
+    /// `None` for inference reservations on this host
+    pub device_id: Option<String>,
     pub memory_mb: i64,
     pub provider: String,
+    /// `device` (grant to a device) or `inference` (held by a running session)
+    pub purpose: String,
+    pub session_id: Option<String>,
     pub granted_at: String,
     pub revoked_at: Option<String>,
 }
 
+/// A device's allocations in force from one provider, summed.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct ProviderAllocation {
+    pub provider: String,
+    pub memory_mb: i64,
+    /// Allocations summed
+    pub allocations: i64,
+}
+
+/// One thing that happened to a device.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct DeviceActivity {
+    /// RFC 3339, to the second
+    pub at: String,
+    /// `allocation_granted`, `allocation_revoked` or `health_changed`
+    pub kind: String,
+    /// Size of the allocation
+    pub memory_mb: Option<i64>,
+    /// Provider and purpose of an allocation; reason, change and new score
+    /// of a health change
+    pub detail: Option<String>,
+}
+
+// ─── Webhook ─────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    /// HMAC-SHA256 signing key; never returned by the API
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub event_filter: String, // JSON array of event types, e.g. ["device_pending_approval"]
+    pub enabled: bool,
+    pub created_at: String,
+}
+
+impl Webhook {
+    /// Event types this webhook subscribes to (empty = all notable events).
+    pub fn events(&self) -> Vec<String> {
+        serde_json::from_str(&self.event_filter).unwrap_or_default()
+    }
+}
+
+// ─── Model alias ─────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct ModelAlias {
+    pub alias: String,
+    pub path: String,
+    pub created_at: String,
+}
+
+/// One change of a device's health score (migration 0030).
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct DeviceHealthEvent {
+    pub id: i64,
+    pub device_id: String,
+    /// Change applied, after clamping to 0-100
+    pub delta: i64,
+    /// Score after the change
+    pub score: i64,
+    pub reason: HealthReason,
+    pub created_at: String,
+}
+
+/// One bulk device import and how its rows turned out (migration 0037).
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct DeviceImport {
+    pub id: i64,
+    /// `json` or `csv`
+    pub format: String,
+    pub total: i64,
+    /// New devices
+    pub imported: i64,
+    /// Rows whose address was already registered
+    pub existing: i64,
+    pub failed: i64,
+    pub created_at: String,
+}
+
+/// Cached SHA-256 of a model file (migration 0029); stale once the file's
+/// size or mtime differ.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ModelFileHash {
+    pub path: String,
+    pub size: i64,
+    pub modified_at: String,
+    pub sha256: String,
+}
+
+/// A deleted model file waiting in the trash (migration 0032).
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct TrashedModel {
+    pub trashed_path: String,
+    pub original_path: String,
+    pub size: i64,
+    pub deleted_at: String,
+}
+
+// ─── Model pull ──────────────────────────────────────────────────────────────
+
+/// An Ollama pull: queued (`POST /api/models/pull` with a schedule), or
+/// streamed to its caller and recorded once it ended.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct ModelPull {
+    pub id: String,
+    pub name: String,
+    pub status: String, // pending | active | completed | failed | cancelled
+    /// Only start inside the `offpeak_start`..`offpeak_end` window
+    pub off_peak: bool,
+    /// RFC 3339; don't start before this
+    pub not_before: Option<String>,
+    pub completed_bytes: Option<i64>,
+    pub total_bytes: Option<i64>,
+    pub error: Option<String>,
+    /// What kind of failure `error` is (migration 0035)
+    pub error_code: Option<PullErrorCode>,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+}
+
+// ─── Backend profile ─────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct BackendProfile {
+    pub id: String,
+    pub name: String,
+    pub backend_type: String,
+    pub url: String,
+    pub model: String,
+    /// Never returned by the API
+    #[serde(skip_serializing)]
+    pub api_key: String,
+    pub created_at: String,
+}
+
+// ─── API key ─────────────────────────────────────────────────────────────────
+
+/// A client key for the OpenAI-compatible endpoints (migration 0021), or
+/// for the dashboard API (`scope`, migration 0027).
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// First characters of the key, e.g. `sk-sllm-3f9a`
+    pub key_prefix: String,
+    /// Never returned by the API
+    #[serde(skip_serializing)]
+    pub salt: String,
+    /// Never returned by the API
+    #[serde(skip_serializing)]
+    pub hashed_key: String,
+    /// Requests per minute; unlimited when absent
+    pub rate_limit_rpm: Option<i64>,
+    /// Model IDs the key may use (empty = every model)
+    #[sqlx(try_from = "String")]
+    #[schema(value_type = Vec<String>)]
+    pub allowed_models: ModelIds,
+    pub scope: KeyScope,
+    /// Requests authorized with the key
+    pub requests: i64,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+    pub revoked_at: Option<String>,
+}
+
+impl ApiKey {
+    pub fn allows(&self, model: &str) -> bool {
+        self.allowed_models.0.is_empty() || self.allowed_models.0.iter().any(|m| m == model)
+    }
+}
+
+/// `api_keys.allowed_models`, stored as a JSON array.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ModelIds(pub Vec<String>);
+
+impl From<String> for ModelIds {
+    /// A malformed value reads as no restriction rather than failing the row.
+    fn from(value: String) -> Self {
+        ModelIds(serde_json::from_str(&value).unwrap_or_default())
+    }
+}
+
+impl ModelIds {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).unwrap_or_else(|_| "[]".into())
+    }
+}
+
+// ─── Inference preset ────────────────────────────────────────────────────────
+
+/// Saved inference start parameters (migration 0024).
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct InferencePreset {
+    pub id: String,
+    pub name: String,
+    /// Absolute .gguf path or a model alias, as given
+    pub model_path: String,
+    #[sqlx(try_from = "String")]
+    #[schema(value_type = Vec<String>)]
+    pub device_ids: StringList,
+    /// Expanded to the approved, reachable devices carrying them at start
+    #[sqlx(try_from = "String")]
+    #[schema(value_type = Vec<String>)]
+    pub device_tags: StringList,
+    pub n_gpu_layers: Option<i64>,
+    pub ctx_size: Option<i64>,
+    #[sqlx(try_from = "String")]
+    pub options: PresetOptions,
+    /// `device_ids` as they were when the preset was saved
+    #[sqlx(try_from = "String")]
+    #[schema(value_type = Vec<SavedDevice>)]
+    pub saved_devices: SavedDevices,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// The rest of `POST /api/cluster/inference/start`, stored as JSON in
+/// `inference_presets.options`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(default)]
+pub struct PresetOptions {
+    pub parallel: Option<u32>,
+    pub cont_batching: bool,
+    pub gpu_selector: Option<serde_json::Value>,
+    /// Draft model (alias or path) for speculative decoding
+    pub draft_model_path: Option<String>,
+    pub draft_max: Option<u32>,
+    pub draft_min: Option<u32>,
+    /// Vision projector (alias or path)
+    pub mmproj_path: Option<String>,
+    /// Built-in chat template overriding the model's
+    pub chat_template: Option<String>,
+    /// Injected into chat requests without a system message
+    pub system_prompt: Option<String>,
+    /// LoRA adapters applied on top of the model
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lora_adapters: Vec<LoraAdapter>,
+    /// Retry with fewer GPU layers if llama-server runs out of memory
+    pub retry_on_oom: bool,
+}
+
+impl From<String> for PresetOptions {
+    /// A malformed value reads as the defaults rather than failing the row.
+    fn from(value: String) -> Self {
+        serde_json::from_str(&value).unwrap_or_default()
+    }
+}
+
+impl PresetOptions {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|_| "{}".into())
+    }
+}
+
+/// A JSON array of strings in a TEXT column.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct StringList(pub Vec<String>);
+
+impl From<String> for StringList {
+    fn from(value: String) -> Self {
+        StringList(serde_json::from_str(&value).unwrap_or_default())
+    }
+}
+
+impl StringList {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).unwrap_or_else(|_| "[]".into())
+    }
+}
+
+/// A preset device as it was when the preset was saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SavedDevice {
+    pub id: String,
+    pub name: String,
+    pub ip: String,
+    pub rpc_ports: Vec<u16>,
+}
+
+impl From<&Device> for SavedDevice {
+    fn from(d: &Device) -> Self {
+        SavedDevice { id: d.id.clone(), name: d.name.clone(), ip: d.ip.clone(), rpc_ports: d.rpc_ports() }
+    }
+}
+
+/// `inference_presets.saved_devices`, stored as a JSON array.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SavedDevices(pub Vec<SavedDevice>);
+
+impl From<String> for SavedDevices {
+    fn from(value: String) -> Self {
+        SavedDevices(serde_json::from_str(&value).unwrap_or_default())
+    }
+}
+
+impl SavedDevices {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.0).unwrap_or_else(|_| "[]".into())
+    }
+}
+
 // ─── Setting ─────────────────────────────────────────────────────────────────
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
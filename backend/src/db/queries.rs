@@ -1,37 +1,114 @@
 use anyhow::Result;
 use sqlx::SqlitePool;
+use std::collections::HashMap;
 
-use super::models::{Allocation, Device, Role, Setting};
+use super::models::{
+    Allocation, ApiKey, BackendProfile, Device, DeviceActivity, DeviceHealthEvent, DeviceImport, DeviceLabels, InferencePreset, ModelAlias,
+    ModelFileHash, ModelPull, ProviderAllocation, Role, RpcPorts, Setting, TrashedModel, Webhook,
+};
+use crate::inventory::HostInventory;
+use crate::llama_cpp::{health::HealthReason, rpc_probe::DeepProbe, RpcLaunch};
+use crate::ollama::pulls::PullErrorCode;
+use crate::permissions::{DeviceKind, DeviceStatus, RpcStatus};
 
 // ─── Device queries ──────────────────────────────────────────────────────────
 
+// Soft-deleted devices (`deleted_at` set) are left out of every device
+// query unless its name says otherwise.
+
 pub async fn list_devices(pool: &SqlitePool) -> Result<Vec<Device>> {
-    let devices = sqlx::query_as::<_, Device>("SELECT * FROM devices ORDER BY created_at DESC")
-        .fetch_all(pool)
-        .await?;
+    let mut devices = sqlx::query_as::<_, Device>(
+        "SELECT * FROM devices WHERE deleted_at IS NULL ORDER BY created_at DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut tags = list_all_device_tags(pool).await?;
+    for d in &mut devices {
+        d.tags = tags.remove(&d.id).unwrap_or_default();
+        d.update_freshness();
+    }
     Ok(devices)
 }
 
-pub async fn get_device(pool: &SqlitePool, id: &str) -> Result<Option<Device>> {
-    let device = sqlx::query_as::<_, Device>("SELECT * FROM devices WHERE id = ?")
-        .bind(id)
+/// Soft-deleted devices, most recently deleted first.
+pub async fn list_deleted_devices(pool: &SqlitePool) -> Result<Vec<Device>> {
+    let mut devices = sqlx::query_as::<_, Device>(
+        "SELECT * FROM devices WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut tags = list_all_device_tags(pool).await?;
+    for d in &mut devices {
+        d.tags = tags.remove(&d.id).unwrap_or_default();
+        d.update_freshness();
+    }
+    Ok(devices)
+}
+
+pub async fn list_devices_with_tag(pool: &SqlitePool, tag: &str) -> Result<Vec<Device>> {
+    let mut devices = sqlx::query_as::<_, Device>(
+        "SELECT d.* FROM devices d
+         JOIN device_tags t ON t.device_id = d.id
+         WHERE t.tag = ? AND d.deleted_at IS NULL
+         ORDER BY d.created_at DESC",
+    )
+    .bind(tag)
+    .fetch_all(pool)
+    .await?;
+    let mut tags = list_all_device_tags(pool).await?;
+    for d in &mut devices {
+        d.tags = tags.remove(&d.id).unwrap_or_default();
+        d.update_freshness();
+    }
+    Ok(devices)
+}
+
+/// Approved devices marked as peer hosts that run a backend, oldest first.
+pub async fn list_peer_devices(pool: &SqlitePool) -> Result<Vec<Device>> {
+    let devices = sqlx::query_as::<_, Device>(
+        "SELECT * FROM devices WHERE is_peer = 1 AND device_kind = 'peer' AND status = 'approved'
+           AND deleted_at IS NULL
+         ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(devices)
+}
+
+/// The device with `column` = `value`, live or soft-deleted as `deleted` says.
+async fn find_device(pool: &SqlitePool, column: &str, value: &str, deleted: bool) -> Result<Option<Device>> {
+    let sql = format!(
+        "SELECT * FROM devices WHERE {} = ? AND deleted_at IS {}",
+        column,
+        if deleted { "NOT NULL" } else { "NULL" }
+    );
+    let mut device = sqlx::query_as::<_, Device>(&sql)
+        .bind(value)
         .fetch_optional(pool)
         .await?;
+    if let Some(d) = device.as_mut() {
+        d.tags = get_device_tags(pool, &d.id).await?;
+        d.update_freshness();
+    }
     Ok(device)
 }
 
+pub async fn get_device(pool: &SqlitePool, id: &str) -> Result<Option<Device>> {
+    find_device(pool, "id", id, false).await
+}
+
 pub async fn get_device_by_ip(pool: &SqlitePool, ip: &str) -> Result<Option<Device>> {
-    let device = sqlx::query_as::<_, Device>("SELECT * FROM devices WHERE ip = ?")
-        .bind(ip)
-        .fetch_optional(pool)
-        .await?;
-    Ok(device)
+    find_device(pool, "ip", ip, false).await
+}
+
+pub async fn get_deleted_device_by_ip(pool: &SqlitePool, ip: &str) -> Result<Option<Device>> {
+    find_device(pool, "ip", ip, true).await
 }
 
 pub async fn insert_device(pool: &SqlitePool, d: &Device) -> Result<()> {
     sqlx::query(
-        "INSERT OR IGNORE INTO devices (id, name, ip, mac, hostname, platform, role_id, status, discovery_method, allocated_memory_mb, last_seen, first_seen, created_at, rpc_port, rpc_status, memory_total_mb, memory_free_mb)
-         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        "INSERT OR IGNORE INTO devices (id, name, ip, mac, hostname, platform, role_id, status, discovery_method, allocated_memory_mb, last_seen, first_seen, created_at, rpc_port, rpc_status, memory_total_mb, memory_free_mb, notes, labels)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
     )
     .bind(&d.id)
     .bind(&d.name)
@@ -40,23 +117,26 @@ pub async fn insert_device(pool: &SqlitePool, d: &Device) -> Result<()> {
     .bind(&d.hostname)
     .bind(&d.platform)
     .bind(&d.role_id)
-    .bind(&d.status)
+    .bind(d.status)
     .bind(&d.discovery_method)
     .bind(d.allocated_memory_mb)
     .bind(&d.last_seen)
     .bind(&d.first_seen)
     .bind(&d.created_at)
     .bind(d.rpc_port)
-    .bind(&d.rpc_status)
+    .bind(d.rpc_status)
     .bind(d.memory_total_mb)
     .bind(d.memory_free_mb)
+    .bind(&d.notes)
+    .bind(d.labels.to_json())
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn update_device_status(pool: &SqlitePool, id: &str, status: &str) -> Result<()> {
-    sqlx::query("UPDATE devices SET status = ? WHERE id = ?")
+/// Set a device's status by hand, which also drops any `prior_status`.
+pub async fn update_device_status(pool: &SqlitePool, id: &str, status: DeviceStatus) -> Result<()> {
+    sqlx::query("UPDATE devices SET status = ?, prior_status = NULL WHERE id = ?")
         .bind(status)
         .bind(id)
         .execute(pool)
@@ -73,15 +153,58 @@ pub async fn update_device_role(pool: &SqlitePool, id: &str, role_id: &str) -> R
     Ok(())
 }
 
-pub async fn update_device_memory(pool: &SqlitePool, id: &str, memory_mb: i64) -> Result<()> {
-    sqlx::query("UPDATE devices SET allocated_memory_mb = ? WHERE id = ?")
-        .bind(memory_mb)
+/// Apply `PATCH /api/devices/:id`: the fields an operator may edit.
+pub async fn update_device_settings(
+    pool: &SqlitePool,
+    id: &str,
+    name: &str,
+    rpc_port: i64,
+    extra_rpc_ports: &RpcPorts,
+    is_peer: bool,
+    device_kind: DeviceKind,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE devices SET name = ?, rpc_port = ?, extra_rpc_ports = ?, is_peer = ?, device_kind = ?
+         WHERE id = ?",
+    )
+    .bind(name)
+    .bind(rpc_port)
+    .bind(extra_rpc_ports.to_json())
+    .bind(is_peer)
+    .bind(device_kind)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Replace a device's notes and labels.
+pub async fn update_device_notes(
+    pool: &SqlitePool,
+    id: &str,
+    notes: Option<&str>,
+    labels: &DeviceLabels,
+) -> Result<()> {
+    sqlx::query("UPDATE devices SET notes = ?, labels = ? WHERE id = ?")
+        .bind(notes)
+        .bind(labels.to_json())
         .bind(id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
+/// Record what a probe found the device runs. Only a device still of
+/// `unknown` kind is changed, so an operator's correction stands.
+pub async fn detect_device_kind(pool: &SqlitePool, id: &str, kind: DeviceKind) -> Result<bool> {
+    let result = sqlx::query("UPDATE devices SET device_kind = ? WHERE id = ? AND device_kind = 'unknown'")
+        .bind(kind)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
 pub async fn update_device_last_seen(pool: &SqlitePool, id: &str) -> Result<()> {
     let now = chrono::Utc::now().to_rfc3339();
     sqlx::query("UPDATE devices SET last_seen = ? WHERE id = ?")
@@ -92,15 +215,72 @@ pub async fn update_device_last_seen(pool: &SqlitePool, id: &str) -> Result<()>
     Ok(())
 }
 
-pub async fn update_device_rpc_status(pool: &SqlitePool, id: &str, rpc_status: &str) -> Result<()> {
-    sqlx::query("UPDATE devices SET rpc_status = ? WHERE id = ?")
-        .bind(rpc_status)
-        .bind(id)
-        .execute(pool)
-        .await?;
+/// Mark a device `offline`, keeping its current status in `prior_status`.
+/// Only approved and suspended devices go offline; `false` for any other.
+pub async fn mark_device_offline(pool: &SqlitePool, id: &str) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE devices SET prior_status = status, status = 'offline'
+         WHERE id = ? AND status IN ('approved', 'suspended')",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Give an `offline` device back the status it had, approved when none was
+/// kept. `None` when the device wasn't offline.
+pub async fn restore_device_status(pool: &SqlitePool, id: &str) -> Result<Option<DeviceStatus>> {
+    let status = sqlx::query_scalar::<_, DeviceStatus>(
+        "UPDATE devices SET status = COALESCE(prior_status, 'approved'), prior_status = NULL
+         WHERE id = ? AND status = 'offline' RETURNING status",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(status)
+}
+
+/// `SET` clause stamping `last_rpc_ready_at` when `rpc_status` (bound as ?1)
+/// turns ready; SQLite evaluates it against the row before the update.
+const STAMP_RPC_READY: &str = "last_rpc_ready_at = CASE
+    WHEN ?1 = 'ready' AND (rpc_status != 'ready' OR last_rpc_ready_at IS NULL) THEN ?2
+    ELSE last_rpc_ready_at END";
+
+pub async fn update_device_rpc_status(
+    pool: &SqlitePool,
+    id: &str,
+    rpc_status: RpcStatus,
+) -> Result<()> {
+    sqlx::query(&format!(
+        "UPDATE devices SET rpc_status = ?1, {} WHERE id = ?3",
+        STAMP_RPC_READY
+    ))
+    .bind(rpc_status)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Store the result of probing a device's RPC server.
+pub async fn record_rpc_probe(pool: &SqlitePool, id: &str, probe: &DeepProbe) -> Result<()> {
+    sqlx::query(
+        "UPDATE devices SET rpc_probe_depth = ?, rpc_probe_ok = ?, rpc_probe_detail = ?, rpc_probe_at = ?
+         WHERE id = ?",
+    )
+    .bind(probe.depth)
+    .bind(probe.ok)
+    .bind(&probe.detail)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(id)
+    .execute(pool)
+    .await?;
     Ok(())
 }
 
+/// Store probed memory figures, unless the device's were set by hand.
 pub async fn update_device_memory_stats(
     pool: &SqlitePool,
     id: &str,
@@ -108,7 +288,8 @@ pub async fn update_device_memory_stats(
     memory_free_mb: i64,
 ) -> Result<()> {
     sqlx::query(
-        "UPDATE devices SET memory_total_mb = ?, memory_free_mb = ? WHERE id = ?",
+        "UPDATE devices SET memory_total_mb = ?, memory_free_mb = ?
+         WHERE id = ? AND NOT memory_manual_override",
     )
     .bind(memory_total_mb)
     .bind(memory_free_mb)
@@ -118,117 +299,1338 @@ pub async fn update_device_memory_stats(
     Ok(())
 }
 
-pub async fn delete_device(pool: &SqlitePool, id: &str) -> Result<()> {
-    sqlx::query("DELETE FROM devices WHERE id = ?")
+/// Set a device's memory figures by hand, `Some((total, free))`, so probes
+/// and heartbeats stop updating them, or with `None` let them resume.
+/// `false` when there is no such device.
+pub async fn set_device_memory_override(
+    pool: &SqlitePool,
+    id: &str,
+    memory: Option<(i64, i64)>,
+) -> Result<bool> {
+    let result = match memory {
+        Some((total, free)) => {
+            sqlx::query(
+                "UPDATE devices SET memory_total_mb = ?, memory_free_mb = ?, memory_manual_override = 1
+                 WHERE id = ?",
+            )
+            .bind(total)
+            .bind(free)
+            .bind(id)
+            .execute(pool)
+            .await?
+        }
+        None => {
+            sqlx::query("UPDATE devices SET memory_manual_override = 0 WHERE id = ?")
+                .bind(id)
+                .execute(pool)
+                .await?
+        }
+    };
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_device_agent_secret_hash(pool: &SqlitePool, id: &str, hash: &str) -> Result<()> {
+    sqlx::query("UPDATE devices SET agent_secret_hash = ? WHERE id = ?")
+        .bind(hash)
         .bind(id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-// ─── Role queries ─────────────────────────────────────────────────────────────
+/// Apply an agent heartbeat: memory figures (unless set by hand), RPC
+/// port/status and last_seen in one write.
+pub async fn record_device_heartbeat(
+    pool: &SqlitePool,
+    id: &str,
+    memory_total_mb: i64,
+    memory_free_mb: i64,
+    rpc_port: i64,
+    rpc_status: RpcStatus,
+    rpc_cache_mb: Option<i64>,
+) -> Result<()> {
+    sqlx::query(&format!(
+        "UPDATE devices
+         SET rpc_status = ?1, last_seen = ?2,
+             memory_total_mb = CASE WHEN memory_manual_override THEN memory_total_mb ELSE ?3 END,
+             memory_free_mb = CASE WHEN memory_manual_override THEN memory_free_mb ELSE ?4 END,
+             rpc_port = ?5, rpc_cache_enabled = ?7, rpc_cache_mb = ?8, {}
+         WHERE id = ?6",
+        STAMP_RPC_READY
+    ))
+    .bind(rpc_status)
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(memory_total_mb)
+    .bind(memory_free_mb)
+    .bind(rpc_port)
+    .bind(id)
+    .bind(rpc_cache_mb.is_some())
+    .bind(rpc_cache_mb.unwrap_or(0))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
 
-pub async fn list_roles(pool: &SqlitePool) -> Result<Vec<Role>> {
-    let roles = sqlx::query_as::<_, Role>("SELECT * FROM roles ORDER BY trust_level DESC")
-        .fetch_all(pool)
+/// Store the hardware inventory an agent reported with its heartbeat.
+pub async fn update_device_inventory(
+    pool: &SqlitePool,
+    id: &str,
+    inventory: &HostInventory,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE devices
+         SET cpu_model = ?, cpu_cores = ?, os_version = ?, gpu_driver = ?, disk_free_mb = ?
+         WHERE id = ?",
+    )
+    .bind(&inventory.cpu_model)
+    .bind(inventory.cpu_cores)
+    .bind(&inventory.os_version)
+    .bind(&inventory.gpu_driver)
+    .bind(inventory.disk_free_mb)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Store the llama-rpc-server launch options an agent registered with.
+pub async fn update_device_rpc_launch(pool: &SqlitePool, id: &str, launch: &RpcLaunch) -> Result<()> {
+    sqlx::query("UPDATE devices SET rpc_backend = ?, rpc_mem_mb = ? WHERE id = ?")
+        .bind(launch.backend)
+        .bind(launch.mem_mb.map(|mb| mb as i64))
+        .bind(id)
+        .execute(pool)
         .await?;
-    Ok(roles)
+    Ok(())
 }
 
-pub async fn get_role(pool: &SqlitePool, id: &str) -> Result<Option<Role>> {
-    let role = sqlx::query_as::<_, Role>("SELECT * FROM roles WHERE id = ?")
+/// Start a device's pairing: approval needs the code hashed as `hash` until `expires_at`.
+pub async fn set_device_pairing_code(
+    pool: &SqlitePool,
+    id: &str,
+    hash: &str,
+    expires_at: &str,
+) -> Result<()> {
+    sqlx::query("UPDATE devices SET pairing_code_hash = ?, pairing_expires_at = ? WHERE id = ?")
+        .bind(hash)
+        .bind(expires_at)
         .bind(id)
-        .fetch_optional(pool)
+        .execute(pool)
         .await?;
-    Ok(role)
+    Ok(())
 }
 
-pub async fn upsert_role(pool: &SqlitePool, r: &Role) -> Result<()> {
+/// Count a wrong pairing code; at `max_attempts` the code is voided and the
+/// device locked. Returns the attempts so far.
+pub async fn record_pairing_failure(pool: &SqlitePool, id: &str, max_attempts: i64) -> Result<i64> {
+    let attempts: i64 = sqlx::query_scalar(
+        "UPDATE devices SET pairing_attempts = pairing_attempts + 1 WHERE id = ? RETURNING pairing_attempts",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+    if attempts >= max_attempts {
+        sqlx::query(
+            "UPDATE devices SET pairing_locked = 1, pairing_code_hash = NULL, pairing_expires_at = NULL
+             WHERE id = ?",
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+    }
+    Ok(attempts)
+}
+
+/// End a device's pairing: no code needed any more, and any lock is lifted.
+pub async fn clear_device_pairing(pool: &SqlitePool, id: &str) -> Result<()> {
     sqlx::query(
-        "INSERT INTO roles (id, name, max_memory_mb, can_pull_models, trust_level, created_at)
-         VALUES (?, ?, ?, ?, ?, ?)
-         ON CONFLICT(id) DO UPDATE SET
-           name = excluded.name,
-           max_memory_mb = excluded.max_memory_mb,
-           can_pull_models = excluded.can_pull_models,
-           trust_level = excluded.trust_level",
+        "UPDATE devices SET pairing_code_hash = NULL, pairing_expires_at = NULL,
+             pairing_attempts = 0, pairing_locked = 0
+         WHERE id = ?",
     )
-    .bind(&r.id)
-    .bind(&r.name)
-    .bind(r.max_memory_mb)
-    .bind(r.can_pull_models)
-    .bind(r.trust_level)
-    .bind(&r.created_at)
+    .bind(id)
     .execute(pool)
     .await?;
     Ok(())
 }
 
-pub async fn delete_role(pool: &SqlitePool, id: &str) -> Result<()> {
-    sqlx::query("DELETE FROM roles WHERE id = ?")
+/// Remove a device, deleted or not, for good; its allocations and tags go
+/// with it. Returns whether there was one.
+pub async fn delete_device(pool: &SqlitePool, id: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM devices WHERE id = ?")
         .bind(id)
         .execute(pool)
         .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-// ─── Allocation queries ───────────────────────────────────────────────────────
+/// Mark a device deleted and revoke its memory grants at that same time, so
+/// [`restore_device`] can tell which to bring back. Tags and inference
+/// reservations are left alone. Returns the MB revoked, or `None` when there
+/// is no such live device.
+pub async fn soft_delete_device(pool: &SqlitePool, id: &str) -> Result<Option<i64>> {
+    let mut tx = pool.begin().await?;
+    let Some(deleted_at) = sqlx::query_scalar::<_, String>(
+        "UPDATE devices SET deleted_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+         WHERE id = ? AND deleted_at IS NULL
+         RETURNING deleted_at",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let revoked: i64 = sqlx::query_scalar(
+        "SELECT COALESCE(SUM(memory_mb), 0) FROM allocations
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at IS NULL",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE allocations SET revoked_at = ?
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at IS NULL",
+    )
+    .bind(&deleted_at)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE devices SET allocated_memory_mb = 0 WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(revoked))
+}
 
-pub async fn insert_allocation(pool: &SqlitePool, a: &Allocation) -> Result<()> {
+/// Undo [`soft_delete_device`]: clear `deleted_at` and reinstate the grants
+/// revoked by the delete. Returns the MB reinstated, or `None` when the
+/// device isn't deleted.
+pub async fn restore_device(pool: &SqlitePool, id: &str) -> Result<Option<i64>> {
+    let mut tx = pool.begin().await?;
+    let Some(deleted_at) = sqlx::query_scalar::<_, String>(
+        "SELECT deleted_at FROM devices WHERE id = ? AND deleted_at IS NOT NULL",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(None);
+    };
     sqlx::query(
-        "INSERT INTO allocations (id, device_id, memory_mb, provider, granted_at)
-         VALUES (?, ?, ?, ?, ?)",
+        "UPDATE allocations SET revoked_at = NULL
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at = ?",
     )
-    .bind(&a.id)
-    .bind(&a.device_id)
-    .bind(a.memory_mb)
-    .bind(&a.provider)
-    .bind(&a.granted_at)
-    .execute(pool)
+    .bind(id)
+    .bind(&deleted_at)
+    .execute(&mut *tx)
+    .await?;
+    let restored: i64 = sqlx::query_scalar(
+        "SELECT COALESCE(SUM(memory_mb), 0) FROM allocations
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at IS NULL",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE devices SET deleted_at = NULL, allocated_memory_mb = ? WHERE id = ?")
+        .bind(restored)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(restored))
+}
+
+/// Shrink a device's active grant to `max_mb` when it is larger, as after a
+/// restore under a lower role limit. Returns the device's allocation after.
+pub async fn cap_device_grant(pool: &SqlitePool, id: &str, max_mb: i64) -> Result<i64> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE allocations SET memory_mb = ?
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at IS NULL AND memory_mb > ?",
+    )
+    .bind(max_mb)
+    .bind(id)
+    .bind(max_mb)
+    .execute(&mut *tx)
+    .await?;
+    let capped: i64 = sqlx::query_scalar(
+        "UPDATE devices SET allocated_memory_mb = (
+           SELECT COALESCE(SUM(memory_mb), 0) FROM allocations
+           WHERE device_id = ?1 AND purpose = 'device' AND revoked_at IS NULL)
+         WHERE id = ?1 RETURNING allocated_memory_mb",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(capped)
+}
+
+pub async fn update_device_ip(pool: &SqlitePool, id: &str, ip: &str) -> Result<()> {
+    sqlx::query("UPDATE devices SET ip = ? WHERE id = ?")
+        .bind(ip)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Fold device `drop_id` into `keep_id`: its allocations and tags move over,
+/// the larger memory allocation wins, and the row is deleted.
+pub async fn merge_devices(pool: &SqlitePool, keep_id: &str, drop_id: &str) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE allocations SET device_id = ? WHERE device_id = ?")
+        .bind(keep_id)
+        .bind(drop_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT OR IGNORE INTO device_tags (device_id, tag) SELECT ?, tag FROM device_tags WHERE device_id = ?")
+        .bind(keep_id)
+        .bind(drop_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "UPDATE devices SET allocated_memory_mb = MAX(allocated_memory_mb,
+           (SELECT allocated_memory_mb FROM devices WHERE id = ?2))
+         WHERE id = ?1",
+    )
+    .bind(keep_id)
+    .bind(drop_id)
+    .execute(&mut *tx)
     .await?;
+    sqlx::query("DELETE FROM devices WHERE id = ?")
+        .bind(drop_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
     Ok(())
 }
 
-#[allow(dead_code)]
-pub async fn list_allocations_for_device(
+// ─── Device health queries ───────────────────────────────────────────────────
+
+/// Move a device's health score by `delta`, kept within `0..=max`, and log
+/// the change. Returns the new score, or `None` for an unknown device.
+pub async fn adjust_device_health(
     pool: &SqlitePool,
     device_id: &str,
-) -> Result<Vec<Allocation>> {
-    let allocs = sqlx::query_as::<_, Allocation>(
-        "SELECT * FROM allocations WHERE device_id = ? ORDER BY granted_at DESC",
+    delta: i64,
+    max: i64,
+    reason: HealthReason,
+) -> Result<Option<i64>> {
+    let mut tx = pool.begin().await?;
+    let Some(old) = sqlx::query_scalar::<_, i64>("SELECT health_score FROM devices WHERE id = ?")
+        .bind(device_id)
+        .fetch_optional(&mut *tx)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let score = (old + delta).clamp(0, max);
+    sqlx::query("UPDATE devices SET health_score = ? WHERE id = ?")
+        .bind(score)
+        .bind(device_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("INSERT INTO device_health_events (device_id, delta, score, reason) VALUES (?, ?, ?, ?)")
+        .bind(device_id)
+        .bind(score - old)
+        .bind(score)
+        .bind(reason)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Some(score))
+}
+
+/// A device's most recent health score changes, newest first.
+pub async fn list_device_health_events(
+    pool: &SqlitePool,
+    device_id: &str,
+    limit: i64,
+) -> Result<Vec<DeviceHealthEvent>> {
+    let events = sqlx::query_as::<_, DeviceHealthEvent>(
+        "SELECT * FROM device_health_events WHERE device_id = ? ORDER BY id DESC LIMIT ?",
     )
     .bind(device_id)
+    .bind(limit)
     .fetch_all(pool)
     .await?;
-    Ok(allocs)
+    Ok(events)
 }
 
-// ─── Settings queries ─────────────────────────────────────────────────────────
+// ─── Device import queries ───────────────────────────────────────────────────
 
-pub async fn get_setting(pool: &SqlitePool, key: &str) -> Result<Option<String>> {
-    let row = sqlx::query_as::<_, Setting>("SELECT * FROM settings WHERE key = ?")
-        .bind(key)
-        .fetch_optional(pool)
+/// Record one bulk import's counts.
+pub async fn insert_device_import(
+    pool: &SqlitePool,
+    format: &str,
+    total: usize,
+    imported: usize,
+    existing: usize,
+    failed: usize,
+) -> Result<i64> {
+    let id = sqlx::query("INSERT INTO device_imports (format, total, imported, existing, failed) VALUES (?, ?, ?, ?, ?)")
+        .bind(format)
+        .bind(total as i64)
+        .bind(imported as i64)
+        .bind(existing as i64)
+        .bind(failed as i64)
+        .execute(pool)
+        .await?
+        .last_insert_rowid();
+    Ok(id)
+}
+
+/// The most recent bulk imports, newest first.
+pub async fn list_device_imports(pool: &SqlitePool, limit: i64) -> Result<Vec<DeviceImport>> {
+    let imports = sqlx::query_as::<_, DeviceImport>("SELECT * FROM device_imports ORDER BY id DESC LIMIT ?")
+        .bind(limit)
+        .fetch_all(pool)
         .await?;
-    Ok(row.map(|s| s.value))
+    Ok(imports)
 }
 
-pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> Result<()> {
-    sqlx::query(
-        "INSERT INTO settings (key, value) VALUES (?, ?)
-         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+// ─── Device revision queries ─────────────────────────────────────────────────
+
+/// Current value of the global device revision counter.
+pub async fn device_revision(pool: &SqlitePool) -> Result<i64> {
+    let rev = sqlx::query_scalar::<_, i64>("SELECT value FROM revision WHERE id = 1")
+        .fetch_one(pool)
+        .await?;
+    Ok(rev)
+}
+
+/// `(id, status)` of every live device changed after `rev`.
+pub async fn list_devices_changed_since(
+    pool: &SqlitePool,
+    rev: i64,
+) -> Result<Vec<(String, DeviceStatus)>> {
+    let rows = sqlx::query_as::<_, (String, DeviceStatus)>(
+        "SELECT id, status FROM devices WHERE rev > ? AND deleted_at IS NULL",
     )
-    .bind(key)
-    .bind(value)
-    .execute(pool)
+    .bind(rev)
+    .fetch_all(pool)
     .await?;
-    Ok(())
+    Ok(rows)
 }
 
-pub async fn list_settings(pool: &SqlitePool) -> Result<Vec<Setting>> {
-    let settings = sqlx::query_as::<_, Setting>("SELECT * FROM settings")
-        .fetch_all(pool)
-        .await?;
-    Ok(settings)
+/// IDs of devices deleted after `rev`, soft-deleted or purged.
+pub async fn list_deleted_devices_since(pool: &SqlitePool, rev: i64) -> Result<Vec<String>> {
+    let ids = sqlx::query_scalar::<_, String>(
+        "SELECT id FROM device_tombstones WHERE rev > ?1
+         UNION SELECT id FROM devices WHERE rev > ?1 AND deleted_at IS NOT NULL",
+    )
+    .bind(rev)
+    .fetch_all(pool)
+    .await?;
+    Ok(ids)
 }
 
+// ─── Device tag queries ───────────────────────────────────────────────────────
+
+/// All tags keyed by device ID.
+pub async fn list_all_device_tags(pool: &SqlitePool) -> Result<HashMap<String, Vec<String>>> {
+    let rows = sqlx::query_as::<_, (String, String)>(
+        "SELECT device_id, tag FROM device_tags ORDER BY tag",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+    for (device_id, tag) in rows {
+        map.entry(device_id).or_default().push(tag);
+    }
+    Ok(map)
+}
+
+pub async fn get_device_tags(pool: &SqlitePool, device_id: &str) -> Result<Vec<String>> {
+    let tags = sqlx::query_scalar::<_, String>(
+        "SELECT tag FROM device_tags WHERE device_id = ? ORDER BY tag",
+    )
+    .bind(device_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(tags)
+}
+
+/// Replace a device's tags with `tags` in a single transaction.
+pub async fn set_device_tags(pool: &SqlitePool, device_id: &str, tags: &[String]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM device_tags WHERE device_id = ?")
+        .bind(device_id)
+        .execute(&mut *tx)
+        .await?;
+    for tag in tags {
+        sqlx::query("INSERT OR IGNORE INTO device_tags (device_id, tag) VALUES (?, ?)")
+            .bind(device_id)
+            .bind(tag)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// ─── Role queries ─────────────────────────────────────────────────────────────
+
+pub async fn list_roles(pool: &SqlitePool) -> Result<Vec<Role>> {
+    let roles = sqlx::query_as::<_, Role>("SELECT * FROM roles ORDER BY trust_level DESC")
+        .fetch_all(pool)
+        .await?;
+    Ok(roles)
+}
+
+pub async fn get_role(pool: &SqlitePool, id: &str) -> Result<Option<Role>> {
+    let role = sqlx::query_as::<_, Role>("SELECT * FROM roles WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(role)
+}
+
+pub async fn upsert_role(pool: &SqlitePool, r: &Role) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO roles (id, name, max_memory_mb, can_pull_models, trust_level, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+           name = excluded.name,
+           max_memory_mb = excluded.max_memory_mb,
+           can_pull_models = excluded.can_pull_models,
+           trust_level = excluded.trust_level",
+    )
+    .bind(&r.id)
+    .bind(&r.name)
+    .bind(r.max_memory_mb)
+    .bind(r.can_pull_models)
+    .bind(r.trust_level)
+    .bind(&r.created_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Insert a role unless one with the same ID or name exists; returns whether it was inserted.
+pub async fn insert_role_if_missing(pool: &SqlitePool, r: &Role) -> Result<bool> {
+    let result = sqlx::query(
+        "INSERT OR IGNORE INTO roles (id, name, max_memory_mb, can_pull_models, trust_level, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&r.id)
+    .bind(&r.name)
+    .bind(r.max_memory_mb)
+    .bind(r.can_pull_models)
+    .bind(r.trust_level)
+    .bind(&r.created_at)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete a role in one transaction. Devices using it are moved to
+/// `reassign_to` first; without it, a role still in use is left alone and its
+/// devices (id, name) are returned.
+pub async fn delete_role(
+    pool: &SqlitePool,
+    id: &str,
+    reassign_to: Option<&str>,
+) -> Result<Vec<(String, String)>> {
+    let mut tx = pool.begin().await?;
+    let users = sqlx::query_as::<_, (String, String)>(
+        "SELECT id, name FROM devices WHERE role_id = ? AND deleted_at IS NULL ORDER BY name",
+    )
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await?;
+    match reassign_to {
+        None if !users.is_empty() => return Ok(users),
+        // Deleted devices don't hold on to the role; a restore gives them the default
+        None => {
+            sqlx::query("UPDATE devices SET role_id = NULL WHERE role_id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Some(target) => {
+            sqlx::query("UPDATE devices SET role_id = ? WHERE role_id = ?")
+                .bind(target)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    sqlx::query("DELETE FROM roles WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(users)
+}
+
+/// Point devices whose role no longer exists at `fallback`; returns them (id, name).
+pub async fn repair_dangling_roles(pool: &SqlitePool, fallback: &str) -> Result<Vec<(String, String)>> {
+    let mut tx = pool.begin().await?;
+    let dangling = sqlx::query_as::<_, (String, String)>(
+        "SELECT id, name FROM devices
+         WHERE role_id IS NOT NULL AND role_id NOT IN (SELECT id FROM roles)",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if !dangling.is_empty() {
+        sqlx::query(
+            "UPDATE devices SET role_id = ?
+             WHERE role_id IS NOT NULL AND role_id NOT IN (SELECT id FROM roles)",
+        )
+        .bind(fallback)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(dangling)
+}
+
+// ─── Allocation queries ───────────────────────────────────────────────────────
+
+/// Record a grant to a device, replacing its earlier ones, and set its
+/// `allocated_memory_mb` to match.
+pub async fn grant_device_memory(pool: &SqlitePool, a: &Allocation) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "UPDATE allocations SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at IS NULL",
+    )
+    .bind(&a.device_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO allocations (id, device_id, memory_mb, provider, purpose, session_id, granted_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&a.id)
+    .bind(&a.device_id)
+    .bind(a.memory_mb)
+    .bind(&a.provider)
+    .bind(&a.purpose)
+    .bind(&a.session_id)
+    .bind(&a.granted_at)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE devices SET allocated_memory_mb = ? WHERE id = ?")
+        .bind(a.memory_mb)
+        .bind(&a.device_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Insert an inference reservation, or re-activate and resize it when a row
+/// with the same id exists (a restarted session reserves again).
+pub async fn upsert_reservation(pool: &SqlitePool, a: &Allocation) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO allocations (id, device_id, memory_mb, provider, purpose, session_id, granted_at)
+         VALUES (?, ?, ?, ?, 'inference', ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+            memory_mb = excluded.memory_mb,
+            granted_at = excluded.granted_at,
+            revoked_at = NULL",
+    )
+    .bind(&a.id)
+    .bind(&a.device_id)
+    .bind(a.memory_mb)
+    .bind(&a.provider)
+    .bind(&a.session_id)
+    .bind(&a.granted_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Inference reservations that have not been released.
+pub async fn list_active_reservations(pool: &SqlitePool) -> Result<Vec<Allocation>> {
+    let allocs = sqlx::query_as::<_, Allocation>(
+        "SELECT * FROM allocations WHERE purpose = 'inference' AND revoked_at IS NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(allocs)
+}
+
+/// Release the reservations of one session, or of every session when
+/// `session_id` is `None`. Returns the number of rows released.
+pub async fn release_reservations(pool: &SqlitePool, session_id: Option<&str>) -> Result<u64> {
+    let result = sqlx::query(
+        "UPDATE allocations SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+         WHERE purpose = 'inference' AND revoked_at IS NULL
+           AND (?1 IS NULL OR session_id = ?1)",
+    )
+    .bind(session_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Revoke a device's memory allocations and zero its `allocated_memory_mb`.
+/// Inference reservations are left to their session. Returns the MB revoked.
+pub async fn revoke_all_allocations_for_device(pool: &SqlitePool, device_id: &str) -> Result<i64> {
+    let mut tx = pool.begin().await?;
+    let revoked: i64 = sqlx::query_scalar(
+        "SELECT COALESCE(SUM(memory_mb), 0) FROM allocations
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at IS NULL",
+    )
+    .bind(device_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query(
+        "UPDATE allocations SET revoked_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+         WHERE device_id = ? AND purpose = 'device' AND revoked_at IS NULL",
+    )
+    .bind(device_id)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE devices SET allocated_memory_mb = 0 WHERE id = ?")
+        .bind(device_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(revoked)
+}
+
+/// A device's allocations still in force, grants and inference
+/// reservations alike, newest first.
+pub async fn list_active_allocations_for_device(pool: &SqlitePool, device_id: &str) -> Result<Vec<Allocation>> {
+    let allocs = sqlx::query_as::<_, Allocation>(
+        "SELECT * FROM allocations WHERE device_id = ? AND revoked_at IS NULL ORDER BY granted_at DESC",
+    )
+    .bind(device_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(allocs)
+}
+
+/// A device's allocations in force, summed per provider, largest first.
+pub async fn device_allocations_by_provider(pool: &SqlitePool, device_id: &str) -> Result<Vec<ProviderAllocation>> {
+    let totals = sqlx::query_as::<_, ProviderAllocation>(
+        "SELECT provider, SUM(memory_mb) AS memory_mb, COUNT(*) AS allocations
+         FROM allocations WHERE device_id = ? AND revoked_at IS NULL
+         GROUP BY provider ORDER BY memory_mb DESC, provider",
+    )
+    .bind(device_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(totals)
+}
+
+/// The role a device holds, if any.
+pub async fn get_device_role(pool: &SqlitePool, device_id: &str) -> Result<Option<Role>> {
+    let role = sqlx::query_as::<_, Role>(
+        "SELECT roles.* FROM roles JOIN devices ON devices.role_id = roles.id WHERE devices.id = ?",
+    )
+    .bind(device_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(role)
+}
+
+/// What last happened to a device, newest first: allocations granted and
+/// revoked, and health score changes.
+pub async fn list_device_activity(pool: &SqlitePool, device_id: &str, limit: i64) -> Result<Vec<DeviceActivity>> {
+    // Grants carry RFC 3339 times with fractions and an offset; strftime
+    // brings every source to one sortable form
+    let activity = sqlx::query_as::<_, DeviceActivity>(
+        "SELECT strftime('%Y-%m-%dT%H:%M:%SZ', granted_at) AS at, 'allocation_granted' AS kind,
+                memory_mb, provider || ', ' || purpose AS detail
+         FROM allocations WHERE device_id = ?1
+         UNION ALL
+         SELECT strftime('%Y-%m-%dT%H:%M:%SZ', revoked_at), 'allocation_revoked',
+                memory_mb, provider || ', ' || purpose
+         FROM allocations WHERE device_id = ?1 AND revoked_at IS NOT NULL
+         UNION ALL
+         SELECT strftime('%Y-%m-%dT%H:%M:%SZ', created_at), 'health_changed',
+                NULL, reason || ' ' || printf('%+d', delta) || ', now ' || score
+         FROM device_health_events WHERE device_id = ?1
+         ORDER BY at DESC LIMIT ?2",
+    )
+    .bind(device_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(activity)
+}
+
+#[allow(dead_code)]
+pub async fn list_allocations_for_device(
+    pool: &SqlitePool,
+    device_id: &str,
+) -> Result<Vec<Allocation>> {
+    let allocs = sqlx::query_as::<_, Allocation>(
+        "SELECT * FROM allocations WHERE device_id = ? ORDER BY granted_at DESC",
+    )
+    .bind(device_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(allocs)
+}
+
+// ─── Webhook queries ──────────────────────────────────────────────────────────
+
+pub async fn list_webhooks(pool: &SqlitePool) -> Result<Vec<Webhook>> {
+    let hooks = sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks ORDER BY created_at")
+        .fetch_all(pool)
+        .await?;
+    Ok(hooks)
+}
+
+pub async fn get_webhook(pool: &SqlitePool, id: &str) -> Result<Option<Webhook>> {
+    let hook = sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(hook)
+}
+
+pub async fn upsert_webhook(pool: &SqlitePool, w: &Webhook) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO webhooks (id, url, secret, event_filter, enabled, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+           url = excluded.url,
+           secret = excluded.secret,
+           event_filter = excluded.event_filter,
+           enabled = excluded.enabled",
+    )
+    .bind(&w.id)
+    .bind(&w.url)
+    .bind(&w.secret)
+    .bind(&w.event_filter)
+    .bind(w.enabled)
+    .bind(&w.created_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_webhook(pool: &SqlitePool, id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM webhooks WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Model alias queries ─────────────────────────────────────────────────────
+
+pub async fn list_model_aliases(pool: &SqlitePool) -> Result<Vec<ModelAlias>> {
+    let aliases = sqlx::query_as::<_, ModelAlias>("SELECT * FROM model_aliases ORDER BY alias")
+        .fetch_all(pool)
+        .await?;
+    Ok(aliases)
+}
+
+/// Look up an alias by name (case-insensitive).
+pub async fn get_model_alias(pool: &SqlitePool, alias: &str) -> Result<Option<ModelAlias>> {
+    let row = sqlx::query_as::<_, ModelAlias>(
+        "SELECT * FROM model_aliases WHERE alias = ? COLLATE NOCASE",
+    )
+    .bind(alias)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn get_model_alias_by_path(pool: &SqlitePool, path: &str) -> Result<Option<ModelAlias>> {
+    let row = sqlx::query_as::<_, ModelAlias>("SELECT * FROM model_aliases WHERE path = ?")
+        .bind(path)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row)
+}
+
+pub async fn insert_model_alias(pool: &SqlitePool, a: &ModelAlias) -> Result<()> {
+    sqlx::query("INSERT INTO model_aliases (alias, path, created_at) VALUES (?, ?, ?)")
+        .bind(&a.alias)
+        .bind(&a.path)
+        .bind(&a.created_at)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn update_model_alias_path(pool: &SqlitePool, alias: &str, path: &str) -> Result<()> {
+    sqlx::query("UPDATE model_aliases SET path = ? WHERE alias = ?")
+        .bind(path)
+        .bind(alias)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_model_file_hash(pool: &SqlitePool, path: &str) -> Result<Option<ModelFileHash>> {
+    let row = sqlx::query_as::<_, ModelFileHash>(
+        "SELECT path, size, modified_at, sha256 FROM model_file_hashes WHERE path = ?",
+    )
+    .bind(path)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn upsert_model_file_hash(pool: &SqlitePool, h: &ModelFileHash) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO model_file_hashes (path, size, modified_at, sha256) VALUES (?, ?, ?, ?)
+         ON CONFLICT(path) DO UPDATE SET
+           size = excluded.size,
+           modified_at = excluded.modified_at,
+           sha256 = excluded.sha256,
+           hashed_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')",
+    )
+    .bind(&h.path)
+    .bind(h.size)
+    .bind(&h.modified_at)
+    .bind(&h.sha256)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_model_alias(pool: &SqlitePool, alias: &str) -> Result<()> {
+    sqlx::query("DELETE FROM model_aliases WHERE alias = ?")
+        .bind(alias)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Inference session history queries ───────────────────────────────────────
+
+/// A session started, or started again after a restart.
+pub async fn record_session_start(pool: &SqlitePool, id: &str, model_path: &str, devices: &[String]) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO inference_sessions (id, model_path, status, devices) VALUES (?, ?, 'running', ?)
+         ON CONFLICT(id) DO UPDATE SET
+           model_path = excluded.model_path,
+           status = 'running',
+           devices = excluded.devices,
+           stopped_at = NULL",
+    )
+    .bind(id)
+    .bind(model_path)
+    .bind(serde_json::to_string(devices)?)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// A session ended, as `status` (`stopped` or `error`).
+pub async fn record_session_end(pool: &SqlitePool, id: &str, status: &str) -> Result<()> {
+    sqlx::query(
+        "UPDATE inference_sessions SET status = ?, stopped_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now')
+         WHERE id = ? AND stopped_at IS NULL",
+    )
+    .bind(status)
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// When each model path was last used: the latest end, or start of a
+/// session still without one, of its sessions.
+pub async fn model_last_used(pool: &SqlitePool) -> Result<HashMap<String, String>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT model_path, MAX(COALESCE(stopped_at, started_at)) FROM inference_sessions GROUP BY model_path",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().collect())
+}
+
+pub async fn insert_trashed_model(pool: &SqlitePool, original_path: &str, trashed_path: &str, size: u64) -> Result<()> {
+    sqlx::query("INSERT OR REPLACE INTO model_trash (trashed_path, original_path, size) VALUES (?, ?, ?)")
+        .bind(trashed_path)
+        .bind(original_path)
+        .bind(size as i64)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Oldest first.
+pub async fn list_trashed_models(pool: &SqlitePool) -> Result<Vec<TrashedModel>> {
+    let rows = sqlx::query_as::<_, TrashedModel>("SELECT * FROM model_trash ORDER BY deleted_at, trashed_path")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows)
+}
+
+pub async fn delete_trashed_model(pool: &SqlitePool, trashed_path: &str) -> Result<()> {
+    sqlx::query("DELETE FROM model_trash WHERE trashed_path = ?")
+        .bind(trashed_path)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Model pull queue queries ────────────────────────────────────────────────
+
+/// Unfinished pulls in queue order, then the 50 most recently finished.
+pub async fn list_model_pulls(pool: &SqlitePool) -> Result<Vec<ModelPull>> {
+    let mut pulls = sqlx::query_as::<_, ModelPull>(
+        "SELECT * FROM model_pulls WHERE status IN ('pending', 'active') ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await?;
+    let finished = sqlx::query_as::<_, ModelPull>(
+        "SELECT * FROM model_pulls WHERE status NOT IN ('pending', 'active')
+         ORDER BY finished_at DESC LIMIT 50",
+    )
+    .fetch_all(pool)
+    .await?;
+    pulls.extend(finished);
+    Ok(pulls)
+}
+
+pub async fn pending_model_pulls(pool: &SqlitePool) -> Result<Vec<ModelPull>> {
+    let pulls = sqlx::query_as::<_, ModelPull>(
+        "SELECT * FROM model_pulls WHERE status = 'pending' ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(pulls)
+}
+
+pub async fn get_model_pull(pool: &SqlitePool, id: &str) -> Result<Option<ModelPull>> {
+    let row = sqlx::query_as::<_, ModelPull>("SELECT * FROM model_pulls WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row)
+}
+
+pub async fn insert_model_pull(pool: &SqlitePool, p: &ModelPull) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO model_pulls (id, name, status, off_peak, not_before, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&p.id)
+    .bind(&p.name)
+    .bind(&p.status)
+    .bind(p.off_peak)
+    .bind(&p.not_before)
+    .bind(&p.created_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Store a pull that ran outside the queue, as it ended.
+pub async fn record_model_pull(pool: &SqlitePool, p: &ModelPull) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO model_pulls (id, name, status, off_peak, not_before, completed_bytes, total_bytes,
+             error, error_code, created_at, started_at, finished_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&p.id)
+    .bind(&p.name)
+    .bind(&p.status)
+    .bind(p.off_peak)
+    .bind(&p.not_before)
+    .bind(p.completed_bytes)
+    .bind(p.total_bytes)
+    .bind(&p.error)
+    .bind(p.error_code)
+    .bind(&p.created_at)
+    .bind(&p.started_at)
+    .bind(&p.finished_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn start_model_pull(pool: &SqlitePool, id: &str) -> Result<()> {
+    sqlx::query("UPDATE model_pulls SET status = 'active', started_at = ? WHERE id = ?")
+        .bind(chrono::Utc::now().to_rfc3339())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn update_model_pull_progress(
+    pool: &SqlitePool,
+    id: &str,
+    completed_bytes: Option<i64>,
+    total_bytes: Option<i64>,
+) -> Result<()> {
+    sqlx::query("UPDATE model_pulls SET completed_bytes = ?, total_bytes = ? WHERE id = ?")
+        .bind(completed_bytes)
+        .bind(total_bytes)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Move an unfinished pull to `status` (completed, failed or cancelled).
+/// Returns false if it had already finished.
+pub async fn finish_model_pull(
+    pool: &SqlitePool,
+    id: &str,
+    status: &str,
+    error: Option<(&str, PullErrorCode)>,
+) -> Result<bool> {
+    let result = sqlx::query(
+        "UPDATE model_pulls SET status = ?, error = ?, error_code = ?, finished_at = ?
+         WHERE id = ? AND status IN ('pending', 'active')",
+    )
+    .bind(status)
+    .bind(error.map(|(message, _)| message))
+    .bind(error.map(|(_, code)| code))
+    .bind(chrono::Utc::now().to_rfc3339())
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Pulls a backend restart interrupted go back to the queue; Ollama resumes
+/// partial downloads.
+pub async fn requeue_active_model_pulls(pool: &SqlitePool) -> Result<u64> {
+    let result = sqlx::query("UPDATE model_pulls SET status = 'pending' WHERE status = 'active'")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// ─── Backend profile queries ──────────────────────────────────────────────────
+
+pub async fn list_backend_profiles(pool: &SqlitePool) -> Result<Vec<BackendProfile>> {
+    let profiles = sqlx::query_as::<_, BackendProfile>("SELECT * FROM backend_profiles ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+    Ok(profiles)
+}
+
+pub async fn get_backend_profile(pool: &SqlitePool, id: &str) -> Result<Option<BackendProfile>> {
+    let row = sqlx::query_as::<_, BackendProfile>("SELECT * FROM backend_profiles WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row)
+}
+
+pub async fn get_backend_profile_by_name(
+    pool: &SqlitePool,
+    name: &str,
+) -> Result<Option<BackendProfile>> {
+    let row = sqlx::query_as::<_, BackendProfile>(
+        "SELECT * FROM backend_profiles WHERE name = ? COLLATE NOCASE",
+    )
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn upsert_backend_profile(pool: &SqlitePool, p: &BackendProfile) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO backend_profiles (id, name, backend_type, url, model, api_key, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+           name = excluded.name,
+           backend_type = excluded.backend_type,
+           url = excluded.url,
+           model = excluded.model,
+           api_key = excluded.api_key",
+    )
+    .bind(&p.id)
+    .bind(&p.name)
+    .bind(&p.backend_type)
+    .bind(&p.url)
+    .bind(&p.model)
+    .bind(&p.api_key)
+    .bind(&p.created_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_backend_profile(pool: &SqlitePool, id: &str) -> Result<()> {
+    sqlx::query("DELETE FROM backend_profiles WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── API key queries ──────────────────────────────────────────────────────────
+
+pub async fn list_api_keys(pool: &SqlitePool) -> Result<Vec<ApiKey>> {
+    let keys = sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys ORDER BY created_at")
+        .fetch_all(pool)
+        .await?;
+    Ok(keys)
+}
+
+pub async fn get_api_key(pool: &SqlitePool, id: &str) -> Result<Option<ApiKey>> {
+    let key = sqlx::query_as::<_, ApiKey>("SELECT * FROM api_keys WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(key)
+}
+
+pub async fn upsert_api_key(pool: &SqlitePool, k: &ApiKey) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO api_keys
+           (id, name, key_prefix, salt, hashed_key, rate_limit_rpm, allowed_models, scope, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+           name = excluded.name,
+           rate_limit_rpm = excluded.rate_limit_rpm,
+           allowed_models = excluded.allowed_models,
+           scope = excluded.scope",
+    )
+    .bind(&k.id)
+    .bind(&k.name)
+    .bind(&k.key_prefix)
+    .bind(&k.salt)
+    .bind(&k.hashed_key)
+    .bind(k.rate_limit_rpm)
+    .bind(k.allowed_models.to_json())
+    .bind(k.scope)
+    .bind(&k.created_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false if the key doesn't exist or was already revoked.
+pub async fn revoke_api_key(pool: &SqlitePool, id: &str) -> Result<bool> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let result = sqlx::query("UPDATE api_keys SET revoked_at = ? WHERE id = ? AND revoked_at IS NULL")
+        .bind(&now)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn record_api_key_use(pool: &SqlitePool, id: &str) -> Result<()> {
+    let now = chrono::Utc::now().to_rfc3339();
+    sqlx::query("UPDATE api_keys SET requests = requests + 1, last_used_at = ? WHERE id = ?")
+        .bind(&now)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// ─── Settings queries ─────────────────────────────────────────────────────────
+
+pub async fn get_setting(pool: &SqlitePool, key: &str) -> Result<Option<String>> {
+    let row = sqlx::query_as::<_, Setting>("SELECT * FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|s| s.value))
+}
+
+pub async fn set_setting(pool: &SqlitePool, key: &str, value: &str) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO settings (key, value) VALUES (?, ?)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+    )
+    .bind(key)
+    .bind(value)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Insert a setting unless the key exists; returns whether it was inserted.
+pub async fn insert_setting_if_missing(pool: &SqlitePool, key: &str, value: &str) -> Result<bool> {
+    let result = sqlx::query("INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO NOTHING")
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_settings(pool: &SqlitePool) -> Result<Vec<Setting>> {
+    let settings = sqlx::query_as::<_, Setting>("SELECT * FROM settings")
+        .fetch_all(pool)
+        .await?;
+    Ok(settings)
+}
+
+// ─── Inference preset queries ────────────────────────────────────────────────
+
+pub async fn list_presets(pool: &SqlitePool) -> Result<Vec<InferencePreset>> {
+    let presets = sqlx::query_as::<_, InferencePreset>("SELECT * FROM inference_presets ORDER BY name")
+        .fetch_all(pool)
+        .await?;
+    Ok(presets)
+}
+
+pub async fn get_preset(pool: &SqlitePool, id: &str) -> Result<Option<InferencePreset>> {
+    let preset = sqlx::query_as::<_, InferencePreset>("SELECT * FROM inference_presets WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(preset)
+}
+
+/// Whether another preset than `except_id` is called `name`.
+pub async fn preset_name_taken(pool: &SqlitePool, name: &str, except_id: &str) -> Result<bool> {
+    let count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM inference_presets WHERE name = ? AND id != ?")
+            .bind(name)
+            .bind(except_id)
+            .fetch_one(pool)
+            .await?;
+    Ok(count > 0)
+}
+
+pub async fn upsert_preset(pool: &SqlitePool, p: &InferencePreset) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO inference_presets
+           (id, name, model_path, device_ids, device_tags, n_gpu_layers, ctx_size, options,
+            saved_devices, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(id) DO UPDATE SET
+           name = excluded.name,
+           model_path = excluded.model_path,
+           device_ids = excluded.device_ids,
+           device_tags = excluded.device_tags,
+           n_gpu_layers = excluded.n_gpu_layers,
+           ctx_size = excluded.ctx_size,
+           options = excluded.options,
+           saved_devices = excluded.saved_devices,
+           updated_at = excluded.updated_at",
+    )
+    .bind(&p.id)
+    .bind(&p.name)
+    .bind(&p.model_path)
+    .bind(p.device_ids.to_json())
+    .bind(p.device_tags.to_json())
+    .bind(p.n_gpu_layers)
+    .bind(p.ctx_size)
+    .bind(p.options.to_json())
+    .bind(p.saved_devices.to_json())
+    .bind(&p.created_at)
+    .bind(&p.updated_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false if there was no such preset.
+pub async fn delete_preset(pool: &SqlitePool, id: &str) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM inference_presets WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -1,12 +1,10 @@
 use anyhow::Result;
 use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
-use tokio::sync::broadcast;
 
-use crate::ws::WsEvent;
+use crate::errors::{notify_error, Severity};
+use crate::ws::{EventBus, WsEvent};
 
 const SERVICE_TYPE: &str = "_sharedmem._tcp.local.";
-const API_PORT: u16 = 8080;
-
 /// Discovered device info from mDNS
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -17,8 +15,8 @@ pub struct DiscoveredDevice {
     pub hostname: String,
 }
 
-/// Start mDNS advertisement so other devices can find this host
-pub fn advertise() -> Result<ServiceDaemon> {
+/// Start mDNS advertisement so other devices can find this host at `ip`
+pub fn advertise(ip: std::net::IpAddr, port: u16) -> Result<ServiceDaemon> {
     let mdns = ServiceDaemon::new()?;
 
     // Get local hostname
@@ -26,10 +24,7 @@ pub fn advertise() -> Result<ServiceDaemon> {
         .map(|h| h.to_string_lossy().to_string())
         .unwrap_or_else(|_| "shared-memory-host".to_string());
 
-    // Get local IP
-    let ip = local_ip_address::local_ip()
-        .map(|ip| ip.to_string())
-        .unwrap_or_else(|_| "127.0.0.1".to_string());
+    let ip = ip.to_string();
 
     // Use the short hostname (before the first '.') as the mDNS instance name so
     // that every machine on the LAN advertises a unique name. Two devices with the
@@ -46,12 +41,12 @@ pub fn advertise() -> Result<ServiceDaemon> {
         &instance,
         &format!("{hostname}.local."),
         ip.as_str(),
-        API_PORT,
+        port,
         None,
     )?;
 
     mdns.register(service_info)?;
-    tracing::info!("mDNS: advertising {} at {}:{}", full_name, ip, API_PORT);
+    tracing::info!("mDNS: advertising {} at {}:{}", full_name, ip, port);
 
     Ok(mdns)
 }
@@ -60,7 +55,7 @@ pub fn advertise() -> Result<ServiceDaemon> {
 /// Sends discovered devices via the WsEvent broadcast channel.
 /// Self-exclusion: devices advertising from our own IP or with the canonical
 /// `SharedMemoryHost` instance name are ignored.
-pub async fn browse(event_tx: broadcast::Sender<WsEvent>) -> Result<()> {
+pub async fn browse(event_tx: EventBus) -> Result<()> {
     let mdns = ServiceDaemon::new()?;
     let receiver = mdns.browse(SERVICE_TYPE)?;
 
@@ -104,11 +99,17 @@ pub async fn browse(event_tx: broadcast::Sender<WsEvent>) -> Result<()> {
                     tracing::info!("mDNS: device removed: {}", fullname);
                     let _ = event_tx.send(WsEvent::DeviceOffline {
                         name: fullname.clone(),
+                        device_id: None,
                     });
                 }
                 Ok(_) => {}
                 Err(e) => {
-                    tracing::warn!("mDNS browse error: {}", e);
+                    notify_error(
+                        &event_tx,
+                        Severity::Error,
+                        "discovery",
+                        format!("mDNS browsing stopped: {}", e),
+                    );
                     break;
                 }
             }
@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use sysinfo::Disks;
+
+/// Extension of files still being downloaded; renamed away once complete.
+pub const PARTIAL_EXT: &str = "partial";
+/// Free space left over after a download, so the disk is never filled to the brim.
+pub const HEADROOM_BYTES: u64 = 512 * 1024 * 1024;
+/// A `.partial` untouched for this long belongs to no running download.
+const ORPHAN_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Space on the filesystem holding `path`.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DiskSpace {
+    pub mount_point: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// `~/.sharedmem`, where installed binaries live.
+pub fn sharedmem_dir() -> Result<PathBuf> {
+    let home = std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .map_err(|_| anyhow!("Cannot determine HOME directory"))?;
+    Ok(PathBuf::from(home).join(".sharedmem"))
+}
+
+/// The closest existing ancestor of `path`, resolved, so directories that
+/// are about to be created still map to a filesystem.
+fn existing_ancestor(path: &Path) -> Option<PathBuf> {
+    path.ancestors().find_map(|p| p.canonicalize().ok())
+}
+
+/// The disk whose mount point is the longest prefix of `path`.
+fn disk_for<'a>(disks: &'a Disks, path: &Path) -> Option<&'a sysinfo::Disk> {
+    let resolved = existing_ancestor(path)?;
+    disks
+        .list()
+        .iter()
+        .filter(|d| resolved.starts_with(d.mount_point()))
+        .max_by_key(|d| d.mount_point().as_os_str().len())
+}
+
+pub fn space_for(path: &Path) -> Option<DiskSpace> {
+    let disks = Disks::new_with_refreshed_list();
+    disk_for(&disks, path).map(|d| DiskSpace {
+        mount_point: d.mount_point().display().to_string(),
+        total_bytes: d.total_space(),
+        available_bytes: d.available_space(),
+    })
+}
+
+/// Fail unless every `(path, bytes)` fits with `HEADROOM_BYTES` to spare.
+/// Paths on the same filesystem add up. A path whose filesystem can't be
+/// identified is not checked.
+pub fn ensure_space(needs: &[(&Path, u64)]) -> Result<()> {
+    let disks = Disks::new_with_refreshed_list();
+    let located: Vec<(String, u64, u64)> = needs
+        .iter()
+        .filter_map(|(path, bytes)| {
+            let d = disk_for(&disks, path)?;
+            Some((d.mount_point().display().to_string(), d.available_space(), *bytes))
+        })
+        .collect();
+    check_totals(&located)
+}
+
+/// `(mount_point, available, needed)` per download target.
+fn check_totals(located: &[(String, u64, u64)]) -> Result<()> {
+    let mut per_mount: BTreeMap<&str, (u64, u64)> = BTreeMap::new();
+    for (mount, available, needed) in located {
+        let entry = per_mount.entry(mount).or_insert((*available, 0));
+        entry.1 += needed;
+    }
+    for (mount, (available, needed)) in per_mount {
+        if needed + HEADROOM_BYTES > available {
+            return Err(anyhow!(
+                "Not enough disk space on {}: {} MB needed (including {} MB headroom), {} MB free",
+                mount,
+                (needed + HEADROOM_BYTES) / (1024 * 1024),
+                HEADROOM_BYTES / (1024 * 1024),
+                available / (1024 * 1024),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// A `.partial` file left behind by an interrupted download.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct PartialFile {
+    pub path: String,
+    pub bytes: u64,
+}
+
+/// Orphaned `.partial` files directly inside `dirs`.
+pub fn orphaned_partials(dirs: &[PathBuf]) -> Vec<PartialFile> {
+    let now = SystemTime::now();
+    let mut found = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(PARTIAL_EXT) {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let idle = meta
+                .modified()
+                .ok()
+                .and_then(|m| now.duration_since(m).ok())
+                .unwrap_or_default();
+            if meta.is_file() && idle >= ORPHAN_AGE {
+                found.push(PartialFile {
+                    path: path.display().to_string(),
+                    bytes: meta.len(),
+                });
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_on_one_filesystem_add_up() {
+        let gb = 1024 * 1024 * 1024;
+        let one_disk = |needed: u64| ("/".to_string(), 2 * gb, needed);
+        assert!(check_totals(&[one_disk(gb)]).is_ok());
+        // Each fits alone, but not both on the same disk
+        let err = check_totals(&[one_disk(gb), one_disk(gb)]).unwrap_err();
+        assert!(err.to_string().starts_with("Not enough disk space on /"));
+        assert!(check_totals(&[one_disk(gb), ("/data".to_string(), 2 * gb, gb)]).is_ok());
+    }
+
+    #[test]
+    fn only_idle_partial_files_are_orphans() {
+        let dir = std::env::temp_dir().join(format!("sharedllm-disk-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("model.gguf.partial");
+        std::fs::write(&old, b"12345").unwrap();
+        let stale = SystemTime::now() - ORPHAN_AGE * 2;
+        std::fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(stale)
+            .unwrap();
+        std::fs::write(dir.join("active.gguf.partial"), b"1").unwrap();
+        std::fs::write(dir.join("done.gguf"), b"1").unwrap();
+
+        let found = orphaned_partials(std::slice::from_ref(&dir));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].bytes, 5);
+        assert!(found[0].path.ends_with("model.gguf.partial"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
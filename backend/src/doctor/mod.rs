@@ -0,0 +1,598 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::time::{timeout, Instant};
+
+use crate::db::{self, queries};
+use crate::listen::{self, ListenReport};
+use crate::llama_cpp::{ports, LlamaCppManager, INFERENCE_PORT, RPC_PORT};
+use crate::logs::output::LogConfig;
+use crate::memory::{amd, intel, nvidia, sampler::ProviderHealth, MemoryProvider};
+use crate::ollama::OLLAMA_HOST;
+
+/// Port Open WebUI is usually run on next to this server.
+pub const OPEN_WEBUI_PORT: u16 = 3001;
+const GITHUB_RELEASES_URL: &str = "https://api.github.com/repos/ggml-org/llama.cpp/releases/latest";
+/// Budget for opening the database, which waits while another process holds a lock.
+const DB_TIMEOUT: Duration = Duration::from_secs(10);
+/// Budget for `--version` runs and network requests.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Provider detection slower than this makes every memory sample slow too.
+const SLOW_DETECTION: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    fn label(self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        }
+    }
+}
+
+/// Outcome of one diagnostic.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure
+    pub hint: Option<String>,
+    pub duration_ms: u64,
+}
+
+/// `GET /api/diagnostics`
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Report {
+    pub checks: Vec<Check>,
+    pub passed: usize,
+    pub warned: usize,
+    pub failed: usize,
+    /// The server's logging; absent under `--doctor`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logging: Option<LogConfig>,
+    /// Where the server listens; absent under `--doctor`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<ListenReport>,
+}
+
+impl Report {
+    fn new(checks: Vec<Check>) -> Self {
+        let count = |status| checks.iter().filter(|c| c.status == status).count();
+        Report {
+            passed: count(CheckStatus::Pass),
+            warned: count(CheckStatus::Warn),
+            failed: count(CheckStatus::Fail),
+            checks,
+            logging: None,
+            listen: None,
+        }
+    }
+
+    /// Plain-text table for `--doctor`, hints indented under their check.
+    pub fn table(&self) -> String {
+        let width = self.checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        let mut out = String::new();
+        for check in &self.checks {
+            out.push_str(&format!(
+                "{:<4}  {:<width$}  {}\n",
+                check.status.label(),
+                check.name,
+                check.detail,
+                width = width
+            ));
+            if let Some(hint) = &check.hint {
+                out.push_str(&format!("      {:<width$}  -> {}\n", "", hint, width = width));
+            }
+        }
+        out.push_str(&format!(
+            "\n{} passed, {} warnings, {} failed\n",
+            self.passed, self.warned, self.failed
+        ));
+        out
+    }
+}
+
+/// One check under construction; `finish` stamps how long it took.
+struct Probe {
+    name: String,
+    started: Instant,
+}
+
+impl Probe {
+    fn start(name: impl Into<String>) -> Self {
+        Probe { name: name.into(), started: Instant::now() }
+    }
+
+    fn finish(self, status: CheckStatus, detail: impl Into<String>, hint: Option<&str>) -> Check {
+        Check {
+            name: self.name,
+            status,
+            detail: detail.into(),
+            hint: hint.map(str::to_string),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+/// What the running server already holds, so its own ports and database
+/// don't count against it.
+#[derive(Default)]
+pub struct Context {
+    /// The server's pool; `None` opens (and migrates) `DATABASE_URL`
+    pub pool: Option<SqlitePool>,
+    /// `pool` was opened read-only (`READ_ONLY_OK`)
+    pub read_only: bool,
+    /// Ports this process is serving on
+    pub own_ports: Vec<u16>,
+    /// The port the server bound; `None` checks the configured one
+    pub dashboard_port: Option<u16>,
+    pub ollama_host: Option<String>,
+    /// How the server's memory sampling is going; empty under `--doctor`
+    pub provider_health: Vec<ProviderHealth>,
+}
+
+/// The dashboard port from `PORT`, else 8080.
+pub fn dashboard_port() -> u16 {
+    listen::configured_port().unwrap_or(listen::DEFAULT_PORT)
+}
+
+/// Run every check. Independent of each other except that the database
+/// check supplies the Ollama host setting.
+pub async fn run(ctx: Context) -> Report {
+    let (db_check, pool) = check_database(ctx.pool, ctx.read_only).await;
+    let ollama_host = match (ctx.ollama_host, &pool) {
+        (Some(host), _) => host,
+        (None, Some(pool)) => queries::get_setting(pool, "ollama_host")
+            .await
+            .ok()
+            .flatten()
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| OLLAMA_HOST.to_string()),
+        (None, None) => OLLAMA_HOST.to_string(),
+    };
+
+    let mut checks = vec![db_check];
+    let ports = [
+        (ctx.dashboard_port.unwrap_or_else(dashboard_port), "dashboard", true),
+        (RPC_PORT, "llama-rpc-server", false),
+        (INFERENCE_PORT, "llama-server", false),
+    ];
+    for (port, owner, required) in ports {
+        checks.push(check_port(port, owner, required, &ctx.own_ports));
+    }
+    checks.push(check_open_webui_port());
+    checks.push(check_llama_binary("llama-rpc-server", LlamaCppManager::find_rpc_server_bin()).await);
+    checks.push(check_llama_binary("llama-server", LlamaCppManager::find_inference_server_bin()).await);
+    checks.push(check_ollama_binary());
+    checks.push(check_ollama_daemon(&ollama_host).await);
+    checks.push(check_python().await);
+    checks.push(check_open_webui().await);
+    checks.push(check_mdns());
+    checks.extend(check_providers().await);
+    checks.extend(ctx.provider_health.iter().map(check_sampling));
+    checks.push(check_github().await);
+    Report::new(checks)
+}
+
+// ─── Database ────────────────────────────────────────────────────────────────
+
+async fn check_database(pool: Option<SqlitePool>, read_only: bool) -> (Check, Option<SqlitePool>) {
+    let probe = Probe::start("database");
+    let url = db::database_url();
+    let (pool, read_only) = match pool {
+        Some(pool) => (pool, read_only),
+        None => match timeout(DB_TIMEOUT, db::open(&url, db::read_only_allowed())).await {
+            Ok(Ok(db)) => (db.pool, db.read_only),
+            Ok(Err(e)) => {
+                let check = probe.finish(
+                    CheckStatus::Fail,
+                    format!("Cannot open {}: {:#}", url, e),
+                    Some("Check DATABASE_URL, that its directory is writable, and that no other server holds the file"),
+                );
+                return (check, None);
+            }
+            Err(_) => {
+                let check = probe.finish(
+                    CheckStatus::Fail,
+                    format!("Timed out opening {}", url),
+                    Some("Another process is holding a lock on the database; stop other servers using it"),
+                );
+                return (check, None);
+            }
+        },
+    };
+    let applied: Result<i64, _> =
+        sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations WHERE success = 1")
+            .fetch_one(&pool)
+            .await;
+    let expected = db::MIGRATOR.iter().count() as i64;
+    let check = match applied {
+        Ok(n) if n >= expected && read_only => probe.finish(
+            CheckStatus::Warn,
+            format!("{}, {} migrations applied, read-only: changes are refused", url, n),
+            Some("Make the database writable and restart without READ_ONLY_OK"),
+        ),
+        Ok(n) if n >= expected => {
+            probe.finish(CheckStatus::Pass, format!("{}, {} migrations applied", url, n), None)
+        }
+        Ok(n) => probe.finish(
+            CheckStatus::Fail,
+            format!("{}: {} of {} migrations applied", url, n, expected),
+            Some("Restart the server so it can finish migrating"),
+        ),
+        Err(e) => probe.finish(
+            CheckStatus::Fail,
+            format!("{}: {}", url, e),
+            Some("The database may be corrupt; move it aside to start fresh"),
+        ),
+    };
+    (check, Some(pool))
+}
+
+// ─── Ports ───────────────────────────────────────────────────────────────────
+
+/// `required` ports keep the server from starting when taken; the others only
+/// keep one feature from working.
+fn check_port(port: u16, owner: &str, required: bool, own_ports: &[u16]) -> Check {
+    let probe = Probe::start(format!("port {}", port));
+    if own_ports.contains(&port) {
+        return probe.finish(CheckStatus::Pass, format!("In use by this server ({})", owner), None);
+    }
+    match ports::ensure_free(port) {
+        Ok(()) => probe.finish(CheckStatus::Pass, format!("Free for {}", owner), None),
+        Err(taken) => probe.finish(
+            if required { CheckStatus::Fail } else { CheckStatus::Warn },
+            format!("{}; {} can't start", taken, owner),
+            Some(if required {
+                "Stop the process holding it, set PORT to another port, or set PORT_FALLBACK=1"
+            } else {
+                "Stop the process holding it (often a llama.cpp server left running)"
+            }),
+        ),
+    }
+}
+
+fn check_open_webui_port() -> Check {
+    let probe = Probe::start(format!("port {}", OPEN_WEBUI_PORT));
+    match ports::ensure_free(OPEN_WEBUI_PORT) {
+        Ok(()) => probe.finish(CheckStatus::Pass, "Free for Open WebUI", None),
+        Err(taken)
+            if taken
+                .holder
+                .as_deref()
+                .is_some_and(|h| h.contains("python") || h.contains("webui")) =>
+        {
+            probe.finish(CheckStatus::Pass, format!("{} (likely Open WebUI)", taken), None)
+        }
+        Err(taken) => probe.finish(
+            CheckStatus::Warn,
+            taken.to_string(),
+            Some("Run Open WebUI on another port with `open-webui serve --port <port>`"),
+        ),
+    }
+}
+
+// ─── Binaries ────────────────────────────────────────────────────────────────
+
+/// First line of `program --version`, or why it couldn't be run.
+pub(crate) async fn version_of(program: &Path) -> Result<String, String> {
+    let output = timeout(PROBE_TIMEOUT, Command::new(program).arg("--version").kill_on_drop(true).output())
+        .await
+        .map_err(|_| format!("`--version` did not finish within {} s", PROBE_TIMEOUT.as_secs()))?
+        .map_err(|e| e.to_string())?;
+    // llama.cpp prints its version to stderr
+    let text = [&output.stdout[..], &output.stderr[..]].concat();
+    let text = String::from_utf8_lossy(&text);
+    if !output.status.success() {
+        let last = text.lines().last().unwrap_or("").trim().to_string();
+        return Err(format!("`--version` exited with {}: {}", output.status, last));
+    }
+    Ok(text.lines().find(|l| !l.trim().is_empty()).unwrap_or("").trim().to_string())
+}
+
+async fn check_llama_binary(name: &str, path: Option<std::path::PathBuf>) -> Check {
+    let probe = Probe::start(name);
+    let Some(path) = path else {
+        return probe.finish(
+            CheckStatus::Warn,
+            "Not found in PATH or ~/.sharedmem/bin",
+            Some("Install it with POST /api/cluster/install-binaries or the Inference page"),
+        );
+    };
+    match version_of(&path).await {
+        Ok(version) => probe.finish(
+            CheckStatus::Pass,
+            format!("{} ({})", path.display(), version),
+            None,
+        ),
+        Err(e) => probe.finish(
+            CheckStatus::Fail,
+            format!("{} is present but broken: {}", path.display(), e),
+            Some("Reinstall it; a missing shared library or wrong architecture build is the usual cause"),
+        ),
+    }
+}
+
+fn check_ollama_binary() -> Check {
+    let probe = Probe::start("ollama binary");
+    match which::which("ollama") {
+        Ok(path) => probe.finish(CheckStatus::Pass, path.display().to_string(), None),
+        Err(_) => probe.finish(
+            CheckStatus::Warn,
+            "Not found in PATH; Ollama can't be started from here",
+            Some("Install from https://ollama.com/download, or point ollama_host at a running one"),
+        ),
+    }
+}
+
+async fn check_ollama_daemon(host: &str) -> Check {
+    let probe = Probe::start("ollama daemon");
+    let url = format!("{}/api/version", host.trim_end_matches('/'));
+    let response = reqwest::Client::new().get(&url).timeout(PROBE_TIMEOUT).send().await;
+    match response {
+        Ok(r) if r.status().is_success() => {
+            let version = r
+                .json::<serde_json::Value>()
+                .await
+                .ok()
+                .and_then(|v| v["version"].as_str().map(str::to_string))
+                .unwrap_or_else(|| "unknown version".to_string());
+            probe.finish(CheckStatus::Pass, format!("{} answers ({})", host, version), None)
+        }
+        Ok(r) => probe.finish(
+            CheckStatus::Warn,
+            format!("{} answered HTTP {}", host, r.status()),
+            Some("Check that ollama_host points at Ollama and not another service"),
+        ),
+        Err(e) => probe.finish(
+            CheckStatus::Warn,
+            format!("{} is not reachable: {}", host, e),
+            Some("Start it with `ollama serve`, or enable auto_start_ollama"),
+        ),
+    }
+}
+
+// ─── Python / Open WebUI ─────────────────────────────────────────────────────
+
+async fn check_python() -> Check {
+    let probe = Probe::start("python");
+    let Some(python) = ["python3", "python"].into_iter().find_map(|p| which::which(p).ok()) else {
+        return probe.finish(
+            CheckStatus::Warn,
+            "No python3 in PATH",
+            Some("Install Python 3.11+ to run Open WebUI"),
+        );
+    };
+    match version_of(&python).await {
+        Ok(version) => probe.finish(CheckStatus::Pass, format!("{} ({})", python.display(), version), None),
+        Err(e) => probe.finish(
+            CheckStatus::Warn,
+            format!("{} is broken: {}", python.display(), e),
+            Some("Reinstall Python, or fix the virtualenv it belongs to"),
+        ),
+    }
+}
+
+async fn check_open_webui() -> Check {
+    let probe = Probe::start("open_webui");
+    if let Ok(path) = which::which("open-webui") {
+        return probe.finish(CheckStatus::Pass, path.display().to_string(), None);
+    }
+    let import = match which::which("python3") {
+        Ok(python) => timeout(
+            PROBE_TIMEOUT,
+            Command::new(python)
+                .args(["-c", "import open_webui"])
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .ok()
+        .and_then(Result::ok)
+        .is_some_and(|o| o.status.success()),
+        Err(_) => false,
+    };
+    if import {
+        probe.finish(CheckStatus::Pass, "open_webui module importable", None)
+    } else {
+        probe.finish(
+            CheckStatus::Warn,
+            "Not installed; any OpenAI-compatible client still works",
+            Some("pip install open-webui"),
+        )
+    }
+}
+
+// ─── mDNS ────────────────────────────────────────────────────────────────────
+
+fn check_mdns() -> Check {
+    let probe = Probe::start("mdns");
+    match mdns_sd::ServiceDaemon::new() {
+        Ok(daemon) => {
+            let _ = daemon.shutdown();
+            probe.finish(CheckStatus::Pass, "Daemon started", None)
+        }
+        Err(e) => probe.finish(
+            CheckStatus::Warn,
+            format!("Cannot start the mDNS daemon: {}", e),
+            Some("Devices won't be discovered automatically; allow UDP 5353 or add devices by IP"),
+        ),
+    }
+}
+
+// ─── Memory providers ────────────────────────────────────────────────────────
+
+/// Time `detect` on a blocking thread; it runs vendor tools.
+async fn timed<T: Send + 'static>(detect: fn() -> T) -> (T, Duration) {
+    tokio::task::spawn_blocking(move || {
+        let started = std::time::Instant::now();
+        let found = detect();
+        (found, started.elapsed())
+    })
+    .await
+    .expect("provider detection panicked")
+}
+
+fn provider_check(name: &str, found: Vec<String>, took: Duration) -> Check {
+    let probe = Probe::start(format!("provider {}", name));
+    let mut check = if found.is_empty() {
+        probe.finish(CheckStatus::Pass, format!("None found in {} ms", took.as_millis()), None)
+    } else if took > SLOW_DETECTION {
+        probe.finish(
+            CheckStatus::Warn,
+            format!("{} in {} ms", found.join(", "), took.as_millis()),
+            Some("Detection is slow, so every memory sample will be; check the vendor tool (e.g. nvidia-smi) responds quickly"),
+        )
+    } else {
+        probe.finish(
+            CheckStatus::Pass,
+            format!("{} in {} ms", found.join(", "), took.as_millis()),
+            None,
+        )
+    };
+    check.duration_ms = took.as_millis() as u64;
+    check
+}
+
+async fn check_providers() -> Vec<Check> {
+    let names = |p: Option<String>| p.into_iter().collect::<Vec<_>>();
+    let (nvidia, took) = timed(|| nvidia::NvidiaProvider::detect().map(|p| p.name().to_string())).await;
+    let mut checks = vec![provider_check("nvidia", names(nvidia), took)];
+    let (amd, took) = timed(|| amd::AmdProvider::detect().map(|p| p.name().to_string())).await;
+    checks.push(provider_check("amd", names(amd), took));
+    #[cfg(target_os = "macos")]
+    {
+        use crate::memory::apple;
+        let (apple, took) =
+            timed(|| apple::AppleProvider::detect().map(|p| p.name().to_string())).await;
+        checks.push(provider_check("apple", names(apple), took));
+    }
+    let (intel, took) = timed(|| {
+        intel::IntelProvider::detect_all()
+            .iter()
+            .map(|p| p.name().to_string())
+            .collect()
+    })
+    .await;
+    checks.push(provider_check("intel", intel, took));
+    checks
+}
+
+/// A provider the running server samples: degraded once it has missed
+/// several readings in a row.
+fn check_sampling(health: &ProviderHealth) -> Check {
+    let probe = Probe::start(format!("sampling {}", health.provider_id));
+    if health.degraded {
+        probe.finish(
+            CheckStatus::Warn,
+            format!(
+                "{}: degraded, no reading in the last {} samples",
+                health.name, health.consecutive_failures
+            ),
+            Some("Its memory figures are stale; check the vendor tool (e.g. nvidia-smi) isn't hanging"),
+        )
+    } else if health.consecutive_failures > 0 {
+        probe.finish(
+            CheckStatus::Pass,
+            format!("{}: missed the last {} sample(s)", health.name, health.consecutive_failures),
+            None,
+        )
+    } else {
+        probe.finish(CheckStatus::Pass, format!("{}: answering", health.name), None)
+    }
+}
+
+// ─── Network ─────────────────────────────────────────────────────────────────
+
+async fn check_github() -> Check {
+    let probe = Probe::start("github");
+    let client = reqwest::Client::builder()
+        .user_agent("sharedLLM/1.0")
+        .timeout(PROBE_TIMEOUT)
+        .build();
+    let response = match client {
+        Ok(client) => client.get(GITHUB_RELEASES_URL).send().await,
+        Err(e) => Err(e),
+    };
+    match response {
+        Ok(r) if r.status().is_success() => probe.finish(CheckStatus::Pass, "api.github.com reachable", None),
+        Ok(r) => probe.finish(
+            CheckStatus::Warn,
+            format!("api.github.com answered HTTP {}", r.status()),
+            Some("Rate limited or blocked; binary installs may fail until it clears"),
+        ),
+        Err(e) => probe.finish(
+            CheckStatus::Warn,
+            format!("api.github.com is not reachable: {}", e),
+            Some("llama.cpp binaries can't be downloaded; check the proxy or firewall, or install them by hand"),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, status: CheckStatus, hint: Option<&str>) -> Check {
+        Probe::start(name).finish(status, "detail", hint)
+    }
+
+    #[test]
+    fn report_counts_and_table() {
+        let report = Report::new(vec![
+            check("database", CheckStatus::Pass, None),
+            check("port 8181", CheckStatus::Warn, Some("stop it")),
+            check("llama-server", CheckStatus::Fail, Some("reinstall")),
+        ]);
+        assert_eq!((report.passed, report.warned, report.failed), (1, 1, 1));
+        let table = report.table();
+        assert!(table.contains("pass  database      detail\n"));
+        assert!(table.contains("fail  llama-server  detail\n"));
+        assert!(table.contains("-> stop it\n"));
+        assert!(table.ends_with("1 passed, 1 warnings, 1 failed\n"));
+    }
+
+    #[test]
+    fn own_ports_pass() {
+        let listener = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_eq!(check_port(port, "dashboard", true, &[port]).status, CheckStatus::Pass);
+        assert_eq!(check_port(port, "dashboard", true, &[]).status, CheckStatus::Fail);
+        assert_eq!(check_port(port, "llama-server", false, &[]).status, CheckStatus::Warn);
+    }
+
+    #[tokio::test]
+    async fn missing_binaries_warn_and_broken_ones_fail() {
+        assert_eq!(check_llama_binary("llama-server", None).await.status, CheckStatus::Warn);
+        let broken = check_llama_binary("llama-server", Some("/bin/false".into())).await;
+        assert_eq!(broken.status, CheckStatus::Fail);
+        assert!(broken.hint.is_some());
+    }
+
+    #[test]
+    fn degraded_providers_warn() {
+        let health = |failures, degraded| ProviderHealth {
+            provider_id: "nvidia-0".into(),
+            name: "RTX 4090".into(),
+            consecutive_failures: failures,
+            degraded,
+        };
+        assert_eq!(check_sampling(&health(0, false)).status, CheckStatus::Pass);
+        assert_eq!(check_sampling(&health(1, false)).status, CheckStatus::Pass);
+        let degraded = check_sampling(&health(3, true));
+        assert_eq!(degraded.status, CheckStatus::Warn);
+        assert_eq!(degraded.name, "sampling nvidia-0");
+        assert!(degraded.detail.contains("degraded"));
+    }
+}
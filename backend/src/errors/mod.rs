@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::ws::{EventBus, WsEvent};
+
+/// Problems kept for `GET /api/errors/recent`.
+const BUFFER_ENTRIES: usize = 50;
+
+/// How loudly a background problem should be shown. The dashboard lists
+/// warnings and raises a toast for `error` and `critical`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Degraded but recovering on its own (a restart is under way)
+    Warning,
+    /// Something failed and needs a look
+    Error,
+    /// A feature stopped working and will not retry
+    Critical,
+}
+
+/// Report a problem in a background task: log it and broadcast it as an
+/// `error` event. `source` names the subsystem, e.g. `ollama`.
+pub fn notify_error(event_tx: &EventBus, severity: Severity, source: &str, message: impl Into<String>) {
+    let message = message.into();
+    match severity {
+        Severity::Warning => tracing::warn!("[{}] {}", source, message),
+        Severity::Error | Severity::Critical => tracing::error!("[{}] {}", source, message),
+    }
+    let _ = event_tx.send(WsEvent::Error {
+        severity,
+        source: source.to_string(),
+        message,
+    });
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ErrorEntry {
+    pub timestamp: String,
+    pub severity: Severity,
+    pub source: String,
+    pub message: String,
+}
+
+/// The last [`BUFFER_ENTRIES`] `error` events, so a freshly opened dashboard
+/// can show what went wrong before it connected.
+#[derive(Default)]
+pub struct RecentErrors {
+    entries: Mutex<VecDeque<ErrorEntry>>,
+}
+
+impl RecentErrors {
+    fn push(&self, entry: ErrorEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == BUFFER_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Newest first.
+    pub fn recent(&self) -> Vec<ErrorEntry> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Record every `error` event from now on.
+    pub fn spawn(self: Arc<Self>, event_tx: &EventBus) {
+        let mut rx = event_tx.subscribe_critical();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(WsEvent::Error { severity, source, message }) => self.push(ErrorEntry {
+                        timestamp: chrono::Utc::now().to_rfc3339(),
+                        severity,
+                        source,
+                        message,
+                    }),
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn error_events_are_kept_newest_first() {
+        let bus = EventBus::default();
+        let recent = Arc::new(RecentErrors::default());
+        recent.clone().spawn(&bus);
+
+        for i in 0..BUFFER_ENTRIES + 5 {
+            notify_error(&bus, Severity::Warning, "memory", format!("problem {}", i));
+        }
+        notify_error(&bus, Severity::Critical, "llama_cpp", "gave up");
+        let _ = bus.send(WsEvent::OllamaStatus { running: true, host: "h".into() });
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+        while recent.recent().first().is_none_or(|e| e.source != "llama_cpp")
+            && tokio::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let entries = recent.recent();
+        assert_eq!(entries.len(), BUFFER_ENTRIES);
+        assert_eq!(entries[0].severity, Severity::Critical);
+        assert_eq!(entries[0].message, "gave up");
+        assert_eq!(entries[1].message, format!("problem {}", BUFFER_ENTRIES + 4));
+    }
+}
@@ -0,0 +1,214 @@
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tokio::time::{Duration, Instant};
+
+use crate::db::{models::Device, queries};
+use crate::llama_cpp::diagnosis::AGENT_API_PORT;
+use crate::permissions::DeviceKind;
+
+/// Header counting how many hosts a request has already been forwarded
+/// through. A request arriving with it is answered locally.
+pub const HOP_HEADER: &str = "x-sharedllm-hop";
+/// Most forwards a request may take, so two peers never bounce it between them.
+pub const MAX_HOPS: u32 = 1;
+/// Budget for reading a peer's `/v1/models`.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+/// A peer's model list is read again on its first heartbeat after this long.
+pub const REFRESH_AFTER: Duration = Duration::from_secs(60);
+/// A model list older than this is ignored; the peer stopped reporting in.
+pub const CACHE_TTL: Duration = Duration::from_secs(180);
+/// `service` in a SharedLLM backend's `GET /api/health`.
+pub const HEALTH_SERVICE: &str = "sharedllm";
+/// Budget for asking a device whether it runs a backend.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Hops a request has taken. An unreadable value counts as the limit, so a
+/// request of unknown origin is never forwarded again.
+pub fn hops(headers: &HeaderMap) -> u32 {
+    match headers.get(HOP_HEADER) {
+        None => 0,
+        Some(v) => v.to_str().ok().and_then(|v| v.trim().parse().ok()).unwrap_or(MAX_HOPS),
+    }
+}
+
+/// `http://ip:8080`, where a peer's backend listens.
+pub fn base_url(peer: &Device) -> String {
+    format!("http://{}:{}", peer.ip, AGENT_API_PORT)
+}
+
+/// Whether `ip` runs a SharedLLM backend: it answers `GET /api/health` on
+/// port 8080 as one. Anything else there doesn't count.
+pub async fn runs_backend(client: &reqwest::Client, ip: &str) -> bool {
+    let health: Option<serde_json::Value> = async {
+        client
+            .get(format!("http://{}:{}/api/health", ip, AGENT_API_PORT))
+            .timeout(PROBE_TIMEOUT)
+            .send()
+            .await
+            .ok()?
+            .json()
+            .await
+            .ok()
+    }
+    .await;
+    health.is_some_and(|h| h["service"] == HEALTH_SERVICE)
+}
+
+/// Find out in the background what a device of unknown kind runs, and store
+/// it. With `settle`, no answer means it is agent-only; without, the device
+/// stays unknown until a later probe (it may still be starting up).
+pub fn detect_kind(pool: sqlx::SqlitePool, client: reqwest::Client, device: Device, settle: bool) {
+    if device.device_kind != DeviceKind::Unknown {
+        return;
+    }
+    tokio::spawn(async move {
+        let kind = if runs_backend(&client, &device.ip).await {
+            DeviceKind::Peer
+        } else if settle {
+            DeviceKind::Agent
+        } else {
+            return;
+        };
+        match queries::detect_device_kind(&pool, &device.id, kind).await {
+            Ok(true) => tracing::info!("Device {} ({}) is {:?}", device.name, device.ip, kind),
+            Ok(false) => {}
+            Err(e) => tracing::warn!("Failed to record the kind of device {}: {}", device.id, e),
+        }
+    });
+}
+
+/// Model IDs of an OpenAI `/v1/models` response.
+fn model_ids(list: &serde_json::Value) -> Vec<String> {
+    list["data"]
+        .as_array()
+        .map(|data| {
+            data.iter()
+                .filter_map(|m| m["id"].as_str())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Ask a peer which models it serves itself. The hop header keeps it from
+/// listing its own peers' models.
+pub async fn fetch_models(client: &reqwest::Client, peer: &Device) -> Option<Vec<String>> {
+    let resp = client
+        .get(format!("{}/v1/models", base_url(peer)))
+        .header(HOP_HEADER, MAX_HOPS.to_string())
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await
+        .ok()?;
+    if !resp.status().is_success() {
+        return None;
+    }
+    Some(model_ids(&resp.json().await.ok()?))
+}
+
+struct PeerModels {
+    models: Vec<String>,
+    taken_at: Instant,
+}
+
+/// Models each peer host served when last asked, keyed by device ID.
+#[derive(Default)]
+pub struct PeerCache {
+    peers: RwLock<HashMap<String, PeerModels>>,
+}
+
+impl PeerCache {
+    /// The peer's models if they are younger than [`CACHE_TTL`].
+    pub fn models(&self, device_id: &str) -> Option<Vec<String>> {
+        let peers = self.peers.read().unwrap();
+        peers
+            .get(device_id)
+            .filter(|p| p.taken_at.elapsed() < CACHE_TTL)
+            .map(|p| p.models.clone())
+    }
+
+    pub fn needs_refresh(&self, device_id: &str) -> bool {
+        let peers = self.peers.read().unwrap();
+        peers.get(device_id).is_none_or(|p| p.taken_at.elapsed() >= REFRESH_AFTER)
+    }
+
+    pub fn put(&self, device_id: &str, models: Vec<String>) {
+        self.peers.write().unwrap().insert(
+            device_id.to_string(),
+            PeerModels { models, taken_at: Instant::now() },
+        );
+    }
+
+    pub fn forget(&self, device_id: &str) {
+        self.peers.write().unwrap().remove(device_id);
+    }
+
+    /// Peers among `peers` that serve `model`, in the order given.
+    pub fn serving<'a>(&self, peers: &'a [Device], model: &str) -> Vec<&'a Device> {
+        peers
+            .iter()
+            .filter(|p| self.models(&p.id).is_some_and(|m| m.iter().any(|id| id == model)))
+            .collect()
+    }
+
+    /// Re-read a peer's model list in the background. A peer that doesn't
+    /// answer is forgotten until it does.
+    pub fn refresh(self: &Arc<Self>, client: reqwest::Client, peer: Device) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            match fetch_models(&client, &peer).await {
+                Some(models) => cache.put(&peer.id, models),
+                None => {
+                    tracing::debug!("Peer {} ({}) did not list its models", peer.name, peer.ip);
+                    cache.forget(&peer.id);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(id: &str) -> Device {
+        let mut d = Device::new(id.into(), "10.0.0.2".into(), None, "manual");
+        d.id = id.into();
+        d
+    }
+
+    #[test]
+    fn hop_header_is_read() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(hops(&headers), 0);
+        headers.insert(HOP_HEADER, "1".parse().unwrap());
+        assert_eq!(hops(&headers), 1);
+        headers.insert(HOP_HEADER, "many".parse().unwrap());
+        assert_eq!(hops(&headers), MAX_HOPS);
+    }
+
+    #[test]
+    fn peers_are_found_by_model() {
+        let list = serde_json::json!({ "object": "list", "data": [
+            { "id": "llama3-8b", "object": "model" },
+            { "id": "qwen2-7b", "object": "model" },
+        ]});
+        let cache = PeerCache::default();
+        let peers = [peer("mac"), peer("desktop")];
+        assert!(cache.needs_refresh("mac"));
+        cache.put("mac", model_ids(&list));
+        cache.put("desktop", vec!["qwen2-7b".into()]);
+        assert!(!cache.needs_refresh("mac"));
+
+        let ids = |found: Vec<&Device>| found.iter().map(|d| d.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(cache.serving(&peers, "llama3-8b")), ["mac"]);
+        assert_eq!(ids(cache.serving(&peers, "qwen2-7b")), ["mac", "desktop"]);
+        assert!(cache.serving(&peers, "mistral").is_empty());
+
+        cache.peers.write().unwrap().get_mut("mac").unwrap().taken_at -= CACHE_TTL;
+        assert_eq!(ids(cache.serving(&peers, "llama3-8b")), Vec::<String>::new());
+        cache.forget("desktop");
+        assert!(cache.serving(&peers, "qwen2-7b").is_empty());
+    }
+}
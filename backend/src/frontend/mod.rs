@@ -0,0 +1,78 @@
+use axum::Router;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::AppState;
+
+/// Default location of the built frontend, relative to `backend/`.
+pub const DEFAULT_DIR: &str = "../frontend/dist";
+
+/// Frontend directory set with the `FRONTEND_DIR` env var, if any. It is
+/// not a setting: the directory is served without auth, so only whoever
+/// starts the server may choose it.
+pub fn configured_dir() -> Option<PathBuf> {
+    std::env::var("FRONTEND_DIR")
+        .ok()
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| PathBuf::from(d.trim()))
+}
+
+/// Attach the static frontend as the router's fallback so every `/api`, `/v1`,
+/// `/agent` and `/ws` route keeps precedence. Unknown paths are answered with
+/// `index.html` so client-side routes work on reload.
+///
+/// With the `embed-frontend` feature, assets baked into the binary are served
+/// unless a directory was configured explicitly.
+pub fn attach(router: Router<Arc<AppState>>, dir: Option<PathBuf>) -> Router<Arc<AppState>> {
+    #[cfg(feature = "embed-frontend")]
+    if dir.is_none() {
+        tracing::info!("Serving embedded frontend assets");
+        return router.fallback(embedded::serve);
+    }
+
+    let dir = dir.unwrap_or_else(|| PathBuf::from(DEFAULT_DIR));
+    if !dir.join("index.html").exists() {
+        tracing::warn!(
+            "Frontend not found at {} — set FRONTEND_DIR or build the frontend",
+            dir.display()
+        );
+    } else {
+        tracing::info!("Serving frontend from {}", dir.display());
+    }
+
+    router.fallback_service(
+        tower_http::services::ServeDir::new(&dir)
+            .not_found_service(tower_http::services::ServeFile::new(dir.join("index.html"))),
+    )
+}
+
+#[cfg(feature = "embed-frontend")]
+mod embedded {
+    use axum::{
+        http::{header, StatusCode, Uri},
+        response::{IntoResponse, Response},
+    };
+
+    #[derive(rust_embed::RustEmbed)]
+    #[folder = "../frontend/dist/"]
+    struct Assets;
+
+    /// Serve an embedded asset, falling back to index.html like ServeDir does.
+    pub async fn serve(uri: Uri) -> Response {
+        let path = uri.path().trim_start_matches('/');
+        let path = if path.is_empty() || path.ends_with('/') {
+            format!("{}index.html", path)
+        } else {
+            path.to_string()
+        };
+
+        match Assets::get(&path).or_else(|| Assets::get("index.html")) {
+            Some(file) => (
+                [(header::CONTENT_TYPE, file.metadata.mimetype().to_string())],
+                file.data,
+            )
+                .into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::RwLock;
+use sysinfo::System;
+use tokio::time::{Duration, Instant};
+
+use crate::disk::{self, DiskSpace};
+
+/// How long a collected [`SystemInfo`] is served before it is read again.
+pub const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Hardware summary an agent sends with its heartbeat. Every field is
+/// optional, since agents read them with whatever tools the host has.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct HostInventory {
+    pub cpu_model: Option<String>,
+    /// Physical cores
+    pub cpu_cores: Option<i64>,
+    pub os_version: Option<String>,
+    pub gpu_driver: Option<String>,
+    pub disk_free_mb: Option<i64>,
+}
+
+impl HostInventory {
+    /// Blank strings and impossible numbers read as unknown; shell agents
+    /// send `""` and `0` when a tool is missing.
+    pub fn normalized(self) -> Self {
+        let text = |s: Option<String>| s.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        HostInventory {
+            cpu_model: text(self.cpu_model),
+            cpu_cores: self.cpu_cores.filter(|&n| n > 0),
+            os_version: text(self.os_version),
+            gpu_driver: text(self.gpu_driver),
+            disk_free_mb: self.disk_free_mb.filter(|&mb| mb >= 0),
+        }
+    }
+}
+
+/// A GPU driver found on this host.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct GpuDriver {
+    /// `nvidia`, `amd` or `apple`
+    pub vendor: String,
+    /// GPU model, when the tool reports one
+    pub name: Option<String>,
+    pub version: String,
+}
+
+/// `GET /api/system/info`
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct SystemInfo {
+    pub hostname: Option<String>,
+    /// e.g. `Ubuntu 24.04 LTS` or `macOS 14.5 (23F79)`
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+    pub arch: Option<String>,
+    pub cpu_model: Option<String>,
+    pub cpu_cores: Option<usize>,
+    /// Logical CPUs, counting hyper-threads
+    pub cpu_threads: usize,
+    pub memory_total_mb: u64,
+    /// Directory models are stored in
+    pub model_dir: String,
+    /// `None` when its filesystem can't be identified
+    pub model_disk: Option<DiskSpace>,
+    pub gpu_drivers: Vec<GpuDriver>,
+    pub collected_at: String,
+}
+
+/// Where Ollama keeps its models: `$OLLAMA_MODELS`, else `~/.ollama/models`.
+pub fn ollama_models_dir() -> Option<PathBuf> {
+    if let Ok(dir) = std::env::var("OLLAMA_MODELS") {
+        return Some(PathBuf::from(dir));
+    }
+    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE")).ok()?;
+    Some(PathBuf::from(home).join(".ollama").join("models"))
+}
+
+/// Stdout of a command that succeeded, trimmed.
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!stdout.is_empty()).then_some(stdout)
+}
+
+/// `nvidia-smi --query-gpu=name,driver_version --format=csv,noheader`
+fn parse_nvidia_drivers(stdout: &str) -> Vec<GpuDriver> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let (name, version) = line.rsplit_once(',')?;
+            Some(GpuDriver {
+                vendor: "nvidia".into(),
+                name: Some(name.trim().to_string()),
+                version: version.trim().to_string(),
+            })
+        })
+        .filter(|d| !d.version.is_empty())
+        .collect()
+}
+
+/// `rocm-smi --showdriverversion --json`: `{"system": {"Driver version": "6.7.0"}}`
+fn parse_rocm_driver(stdout: &str) -> Option<String> {
+    let json: serde_json::Value = serde_json::from_str(stdout).ok()?;
+    let version = json["system"]["Driver version"].as_str()?.trim();
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+fn gpu_drivers() -> Vec<GpuDriver> {
+    let mut drivers = run(
+        "nvidia-smi",
+        &["--query-gpu=name,driver_version", "--format=csv,noheader"],
+    )
+    .map(|out| parse_nvidia_drivers(&out))
+    .unwrap_or_default();
+
+    let rocm = run("rocm-smi", &["--showdriverversion", "--json"])
+        .and_then(|out| parse_rocm_driver(&out))
+        .or_else(|| {
+            std::fs::read_to_string("/opt/rocm/.info/version")
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        });
+    if let Some(version) = rocm {
+        drivers.push(GpuDriver { vendor: "amd".into(), name: None, version });
+    }
+
+    // Apple's GPU driver ships with the OS, so its build identifies it
+    if cfg!(target_os = "macos") {
+        if let Some(build) = run("sw_vers", &["-buildVersion"]) {
+            drivers.push(GpuDriver { vendor: "apple".into(), name: None, version: build });
+        }
+    }
+    drivers
+}
+
+fn os_version() -> Option<String> {
+    if cfg!(target_os = "macos") {
+        if let Some(version) = run("sw_vers", &["-productVersion"]) {
+            return Some(match run("sw_vers", &["-buildVersion"]) {
+                Some(build) => format!("macOS {} ({})", version, build),
+                None => format!("macOS {}", version),
+            });
+        }
+    }
+    System::long_os_version()
+}
+
+/// Read the inventory of this host. Runs subprocesses, so call it from
+/// `spawn_blocking`.
+pub fn collect(model_dir: &Path) -> SystemInfo {
+    let mut sys = System::new();
+    sys.refresh_cpu();
+    sys.refresh_memory();
+    let cpu_model = sys
+        .cpus()
+        .first()
+        .map(|c| c.brand().trim().to_string())
+        .filter(|b| !b.is_empty());
+
+    SystemInfo {
+        hostname: System::host_name(),
+        os_version: os_version(),
+        kernel_version: System::kernel_version(),
+        arch: System::cpu_arch(),
+        cpu_model,
+        cpu_cores: sys.physical_core_count(),
+        cpu_threads: sys.cpus().len(),
+        memory_total_mb: sys.total_memory() / (1024 * 1024),
+        model_dir: model_dir.display().to_string(),
+        model_disk: disk::space_for(model_dir),
+        gpu_drivers: gpu_drivers(),
+        collected_at: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+/// The last [`collect`] result, reused for [`CACHE_TTL`].
+#[derive(Default)]
+pub struct InventoryCache {
+    latest: RwLock<Option<(Instant, SystemInfo)>>,
+}
+
+impl InventoryCache {
+    pub fn get(&self) -> Option<SystemInfo> {
+        let latest = self.latest.read().unwrap();
+        latest
+            .as_ref()
+            .filter(|(taken_at, _)| taken_at.elapsed() < CACHE_TTL)
+            .map(|(_, info)| info.clone())
+    }
+
+    pub fn put(&self, info: SystemInfo) {
+        *self.latest.write().unwrap() = Some((Instant::now(), info));
+    }
+}
+
+/// Order dotted versions like `550.54.14` numerically, part by part. Parts
+/// that aren't numbers compare as text.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let mut left = a.split(['.', '-', ' ']);
+    let mut right = b.split(['.', '-', ' ']);
+    loop {
+        match (left.next(), right.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => {
+                let order = match (x.parse::<u64>(), y.parse::<u64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y),
+                    _ => x.cmp(y),
+                };
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn driver_output_is_parsed() {
+        let nvidia = parse_nvidia_drivers(
+            "NVIDIA GeForce RTX 4090, 550.54.14\nNVIDIA RTX A6000, 550.54.14\n",
+        );
+        assert_eq!(nvidia.len(), 2);
+        assert_eq!(nvidia[0].name.as_deref(), Some("NVIDIA GeForce RTX 4090"));
+        assert_eq!(nvidia[1].version, "550.54.14");
+        assert!(parse_nvidia_drivers("No devices were found").is_empty());
+
+        assert_eq!(
+            parse_rocm_driver(r#"{"system": {"Driver version": "6.7.0"}}"#).as_deref(),
+            Some("6.7.0")
+        );
+        assert_eq!(parse_rocm_driver("{}"), None);
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert_eq!(compare_versions("550.54.14", "535.104.05"), Ordering::Greater);
+        assert_eq!(compare_versions("9.1", "10.0"), Ordering::Less);
+        assert_eq!(compare_versions("6.7", "6.7.0"), Ordering::Less);
+        assert_eq!(compare_versions("23F79", "23F79"), Ordering::Equal);
+    }
+
+    #[test]
+    fn blank_inventory_fields_are_unknown() {
+        let inv = HostInventory {
+            cpu_model: Some("  Intel(R) Xeon(R) ".into()),
+            cpu_cores: Some(0),
+            os_version: Some(String::new()),
+            gpu_driver: Some("550.54.14".into()),
+            disk_free_mb: Some(-1),
+        }
+        .normalized();
+        assert_eq!(inv.cpu_model.as_deref(), Some("Intel(R) Xeon(R)"));
+        assert_eq!(inv.cpu_cores, None);
+        assert_eq!(inv.os_version, None);
+        assert_eq!(inv.gpu_driver.as_deref(), Some("550.54.14"));
+        assert_eq!(inv.disk_free_mb, None);
+    }
+}
@@ -0,0 +1,259 @@
+use serde::Serialize;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr};
+use tokio::net::TcpListener;
+
+use crate::llama_cpp::ports::{self, PortInUse};
+
+/// The dashboard and API port unless `PORT` says otherwise.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Setting the port actually bound is written to, for the dashboard to show.
+pub const API_PORT_KEY: &str = "api_port";
+
+/// Setting for the address to listen on, read at startup; `BIND_ADDR` beats it.
+pub const BIND_ADDRESS_KEY: &str = "bind_address";
+
+/// All interfaces, unless `BIND_ADDR` or `bind_address` says otherwise.
+pub const DEFAULT_BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// How many ports above the configured one `PORT_FALLBACK=1` tries.
+pub const FALLBACK_RANGE: u16 = 20;
+
+/// Exit code when the port is taken and fallback is off (EADDRINUSE on Linux).
+pub const EXIT_PORT_IN_USE: i32 = 98;
+
+/// The port from `PORT`, else [`DEFAULT_PORT`].
+pub fn configured_port() -> Result<u16, String> {
+    match std::env::var("PORT") {
+        Ok(p) => p
+            .trim()
+            .parse()
+            .map_err(|_| format!("PORT must be a port number, got {:?}", p)),
+        Err(_) => Ok(DEFAULT_PORT),
+    }
+}
+
+/// Where the bind address came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BindSource {
+    /// `BIND_ADDR`
+    Env,
+    /// The `bind_address` setting
+    Setting,
+    Default,
+}
+
+/// The address to listen on and where it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindAddr {
+    pub ip: IpAddr,
+    pub source: BindSource,
+}
+
+impl BindAddr {
+    /// The address other machines are given for this server: the LAN IP when
+    /// listening on all interfaces, the address itself when on one, and
+    /// `None` on loopback, which nothing else can reach.
+    pub fn reachable_ip(&self) -> Option<IpAddr> {
+        if self.ip.is_unspecified() {
+            local_ip_address::local_ip().ok()
+        } else if self.ip.is_loopback() {
+            None
+        } else {
+            Some(self.ip)
+        }
+    }
+}
+
+/// The address from `BIND_ADDR`, else the `bind_address` setting (`setting`),
+/// else [`DEFAULT_BIND_ADDR`].
+pub fn configured_bind_addr(setting: &str) -> Result<BindAddr, String> {
+    if let Ok(value) = std::env::var("BIND_ADDR") {
+        if !value.trim().is_empty() {
+            let ip = parse_bind_addr(&value).map_err(|e| format!("BIND_ADDR: {}", e))?;
+            return Ok(BindAddr { ip, source: BindSource::Env });
+        }
+    }
+    if setting.trim().is_empty() {
+        return Ok(BindAddr { ip: DEFAULT_BIND_ADDR, source: BindSource::Default });
+    }
+    let ip = parse_bind_addr(setting).map_err(|e| format!("{}: {}", BIND_ADDRESS_KEY, e))?;
+    let source = if ip == DEFAULT_BIND_ADDR { BindSource::Default } else { BindSource::Setting };
+    Ok(BindAddr { ip, source })
+}
+
+/// An IP address of this host, `0.0.0.0` or `::` for all interfaces,
+/// `localhost`, or the name of a network interface, which means its first
+/// IPv4 address.
+pub fn parse_bind_addr(value: &str) -> Result<IpAddr, String> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("localhost") {
+        return Ok(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+    let interfaces = local_ip_address::list_afinet_netifas().ok();
+    match value.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) if ip.is_unspecified() || ip.is_loopback() => Ok(ip),
+        Ok(ip) => match &interfaces {
+            // Without the interface list, binding is what finds out
+            Some(ifas) if !ifas.iter().any(|(_, a)| *a == ip) => {
+                Err(format!("no interface of this host has the address {}", ip))
+            }
+            _ => Ok(ip),
+        },
+        Err(_) => interfaces
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(name, _)| name == value)
+            .map(|(_, ip)| ip)
+            .min_by_key(|ip| ip.is_ipv6())
+            .ok_or_else(|| format!("expected an IP address, localhost or an interface name, got {:?}", value)),
+    }
+}
+
+/// `GET /api/diagnostics`: where the server listens and who can get to it.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ListenReport {
+    pub bind_address: String,
+    pub source: BindSource,
+    pub port: u16,
+    /// The address mDNS and the agent scripts give out; `None` on loopback
+    pub advertised_ip: Option<String>,
+    /// Whether agents on other machines can connect directly. `false` on
+    /// loopback, where they need a reverse proxy on this host.
+    pub remote_reachable: bool,
+}
+
+impl ListenReport {
+    pub fn new(bind: BindAddr, port: u16) -> Self {
+        let advertised = bind.reachable_ip();
+        ListenReport {
+            bind_address: bind.ip.to_string(),
+            source: bind.source,
+            port,
+            remote_reachable: advertised.is_some_and(|ip| !ip.is_loopback()),
+            advertised_ip: advertised.map(|ip| ip.to_string()),
+        }
+    }
+}
+
+/// Whether `PORT_FALLBACK` allows moving to a free port above a busy one.
+pub fn fallback_enabled() -> bool {
+    std::env::var("PORT_FALLBACK")
+        .map(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+/// The server's listening socket.
+pub struct Bound {
+    pub listener: TcpListener,
+    /// The port bound, which differs from the configured one after a fallback
+    pub port: u16,
+    /// Who held the configured port when we moved off it
+    pub displaced: Option<PortInUse>,
+}
+
+#[derive(Debug)]
+pub enum BindError {
+    /// The port is taken; with `fallback`, so was every one tried above it
+    InUse { conflict: PortInUse, fallback: bool },
+    Io { addr: IpAddr, port: u16, source: std::io::Error },
+}
+
+impl std::fmt::Display for BindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindError::InUse { conflict, fallback: false } => write!(
+                f,
+                "{}. Stop it, set PORT to another port, or set PORT_FALLBACK=1 to use the next free one",
+                conflict
+            ),
+            BindError::InUse { conflict, fallback: true } => write!(
+                f,
+                "{}, and so are the {} ports above it. Stop it or set PORT to another port",
+                conflict, FALLBACK_RANGE
+            ),
+            BindError::Io { addr, port, source } => {
+                write!(f, "Failed to listen on {}: {}", std::net::SocketAddr::new(*addr, *port), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BindError {}
+
+/// Listen on `port` at `addr`. If it is taken and `fallback` is set, try the
+/// next [`FALLBACK_RANGE`] ports in turn.
+pub async fn bind(addr: IpAddr, port: u16, fallback: bool) -> Result<Bound, BindError> {
+    let attempts = if fallback { FALLBACK_RANGE } else { 0 };
+    let mut displaced = None;
+    for candidate in (0..=attempts).filter_map(|i| port.checked_add(i)) {
+        match TcpListener::bind((addr, candidate)).await {
+            Ok(listener) => {
+                // Port 0 asks the OS for one; report what it picked
+                let port = listener.local_addr().map(|a| a.port()).unwrap_or(candidate);
+                return Ok(Bound { listener, port, displaced });
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                if displaced.is_none() {
+                    displaced = Some(ports::in_use(candidate));
+                }
+            }
+            Err(source) => return Err(BindError::Io { addr, port: candidate, source }),
+        }
+    }
+    Err(BindError::InUse {
+        conflict: displaced.unwrap_or_else(|| ports::in_use(port)),
+        fallback,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn busy_port_falls_back_only_when_allowed() {
+        let held = std::net::TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let port = held.local_addr().unwrap().port();
+
+        match bind(DEFAULT_BIND_ADDR, port, false).await {
+            Err(BindError::InUse { conflict, fallback: false }) => {
+                assert_eq!(conflict.port, port);
+                #[cfg(any(target_os = "linux", windows))]
+                assert_eq!(conflict.pid, Some(std::process::id()));
+            }
+            other => panic!("expected InUse, got {:?}", other.map(|b| b.port)),
+        }
+
+        let bound = bind(DEFAULT_BIND_ADDR, port, true).await.unwrap();
+        assert!(bound.port > port && bound.port <= port + FALLBACK_RANGE);
+        assert_eq!(bound.displaced.unwrap().port, port);
+    }
+
+    #[tokio::test]
+    async fn loopback_binds_are_not_advertised() {
+        for value in ["localhost", "127.0.0.1", " [::1] ", "::"] {
+            assert!(parse_bind_addr(value).is_ok(), "{}", value);
+        }
+        for bad in ["", "example.com", "0.0.0.0:8080", "not an interface"] {
+            assert!(parse_bind_addr(bad).is_err(), "{}", bad);
+        }
+        // A documentation address no interface can have
+        assert!(parse_bind_addr("192.0.2.123").is_err());
+        if let Some((name, ip)) = local_ip_address::list_afinet_netifas().unwrap_or_default().into_iter().find(|(_, ip)| ip.is_ipv4()) {
+            assert_eq!(parse_bind_addr(&name), Ok(ip));
+        }
+
+        let loopback = BindAddr { ip: IpAddr::V4(Ipv4Addr::LOCALHOST), source: BindSource::Setting };
+        assert_eq!(loopback.reachable_ip(), None);
+        let report = ListenReport::new(loopback, 8080);
+        assert!(!report.remote_reachable && report.advertised_ip.is_none());
+        let lan = BindAddr { ip: "10.1.2.3".parse().unwrap(), source: BindSource::Env };
+        assert_eq!(ListenReport::new(lan, 8080).advertised_ip.as_deref(), Some("10.1.2.3"));
+
+        let bound = bind(loopback.ip, 0, false).await.unwrap();
+        assert!(bound.listener.local_addr().unwrap().ip().is_loopback());
+    }
+}
@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Admission control for chat completions proxied to llama-server: at most
+/// `limit` requests are forwarded at once, up to `max_queue` more wait, and
+/// everything beyond that is turned away.
+///
+/// Waiting requests sit in one FIFO lane per [`Priority`] and a free slot
+/// goes to the front of the highest non-empty lane, unless some request has
+/// waited `promote_after`: then the longest waiter goes first, whatever its
+/// lane, so guests aren't starved by a steady stream of admin requests.
+#[derive(Default)]
+pub struct Admission {
+    state: Mutex<AdmissionState>,
+}
+
+#[derive(Default)]
+struct AdmissionState {
+    in_flight: usize,
+    /// Concurrency limit of the most recent `acquire`
+    limit: usize,
+    /// Promotion threshold of the most recent `acquire`
+    promote_after: Duration,
+    next_ticket: u64,
+    /// By `Priority as usize`
+    lanes: [VecDeque<Waiter>; 4],
+}
+
+struct Waiter {
+    ticket: u64,
+    since: Instant,
+    tx: oneshot::Sender<()>,
+}
+
+/// Queue lane of a chat request, from the caller's role; higher lanes are
+/// served first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Anonymous,
+    Guest,
+    User,
+    Admin,
+}
+
+impl Priority {
+    /// Highest first
+    pub const ALL: [Priority; 4] = [Priority::Admin, Priority::User, Priority::Guest, Priority::Anonymous];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Anonymous => "anonymous",
+            Priority::Guest => "guest",
+            Priority::User => "user",
+            Priority::Admin => "admin",
+        }
+    }
+
+    /// Lane for a role's trust level: 3 and up is admin, 2 user, 1 guest.
+    pub fn from_trust(level: i64) -> Self {
+        match level {
+            i64::MIN..=0 => Priority::Anonymous,
+            1 => Priority::Guest,
+            2 => Priority::User,
+            _ => Priority::Admin,
+        }
+    }
+}
+
+/// Requests waiting in one lane.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct LaneStats {
+    pub priority: Priority,
+    pub queued: usize,
+    /// How long the front request has waited
+    pub oldest_wait_ms: Option<u64>,
+}
+
+/// Why a request was not admitted.
+#[derive(Debug, PartialEq)]
+pub enum Rejected {
+    QueueFull,
+    TimedOut,
+}
+
+/// A forwarding slot; the next queued request takes it over on drop.
+pub struct Permit(Arc<Admission>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        // Hand the slot straight to the next waiter that is still waiting
+        while state.in_flight <= state.limit {
+            let Some(waiter) = state.next_waiter() else {
+                break;
+            };
+            if waiter.tx.send(()).is_ok() {
+                return;
+            }
+        }
+        state.in_flight -= 1;
+    }
+}
+
+impl AdmissionState {
+    fn queued(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    /// The longest waiter once it has waited `promote_after`, else the
+    /// front of the highest non-empty lane.
+    fn next_waiter(&mut self) -> Option<Waiter> {
+        let oldest = self
+            .lanes
+            .iter()
+            .enumerate()
+            .filter_map(|(lane, q)| q.front().map(|w| (lane, w.since)))
+            .min_by_key(|(_, since)| *since);
+        if let Some((lane, since)) = oldest {
+            if since.elapsed() >= self.promote_after {
+                return self.lanes[lane].pop_front();
+            }
+        }
+        self.lanes.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+}
+
+impl Admission {
+    fn lock(&self) -> std::sync::MutexGuard<'_, AdmissionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// `(in_flight, queued)`
+    pub fn counts(&self) -> (usize, usize) {
+        let state = self.lock();
+        (state.in_flight, state.queued())
+    }
+
+    /// Every lane, highest first.
+    pub fn lanes(&self) -> Vec<LaneStats> {
+        let state = self.lock();
+        Priority::ALL
+            .iter()
+            .map(|&priority| {
+                let lane = &state.lanes[priority as usize];
+                LaneStats {
+                    priority,
+                    queued: lane.len(),
+                    oldest_wait_ms: lane.front().map(|w| w.since.elapsed().as_millis() as u64),
+                }
+            })
+            .collect()
+    }
+
+    /// Wait for a slot in `priority`'s lane. `limit` is clamped to at
+    /// least 1; a `promote_after` of zero serves every lane in arrival order.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        priority: Priority,
+        limit: usize,
+        max_queue: usize,
+        timeout: Duration,
+        promote_after: Duration,
+    ) -> Result<Permit, Rejected> {
+        let (ticket, rx) = {
+            let mut state = self.lock();
+            state.limit = limit.max(1);
+            state.promote_after = promote_after;
+            if state.in_flight < state.limit && state.queued() == 0 {
+                state.in_flight += 1;
+                return Ok(Permit(self.clone()));
+            }
+            if state.queued() >= max_queue {
+                return Err(Rejected::QueueFull);
+            }
+            let (tx, rx) = oneshot::channel();
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            state.lanes[priority as usize].push_back(Waiter { ticket, since: Instant::now(), tx });
+            (ticket, rx)
+        };
+
+        let mut rx = rx;
+        match tokio::time::timeout(timeout, &mut rx).await {
+            Ok(Ok(())) => Ok(Permit(self.clone())),
+            _ => {
+                let mut state = self.lock();
+                let lane = &mut state.lanes[priority as usize];
+                let before = lane.len();
+                lane.retain(|w| w.ticket != ticket);
+                if lane.len() < before {
+                    return Err(Rejected::TimedOut);
+                }
+                drop(state);
+                // Dequeued by a release just as the timeout fired: the slot is ours
+                match rx.try_recv() {
+                    Ok(()) => Ok(Permit(self.clone())),
+                    Err(_) => Err(Rejected::TimedOut),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEVER: Duration = Duration::from_secs(3600);
+
+    /// Queue a request in `priority`'s lane behind a held slot; its name
+    /// goes into `order` once admitted.
+    fn queue(
+        admission: &Arc<Admission>,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+        priority: Priority,
+        name: &'static str,
+        promote_after: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let (adm, order) = (admission.clone(), order.clone());
+        tokio::spawn(async move {
+            let _permit = adm.acquire(priority, 1, 8, Duration::from_secs(5), promote_after).await.unwrap();
+            order.lock().unwrap().push(name);
+        })
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_limit_queue_in_order() {
+        let admission = Arc::new(Admission::default());
+        let wait = Duration::from_secs(5);
+
+        let first = admission.acquire(Priority::User, 1, 2, wait, NEVER).await.unwrap();
+        let a = tokio::spawn({
+            let adm = admission.clone();
+            async move { adm.acquire(Priority::User, 1, 2, wait, NEVER).await.map(|_| "a") }
+        });
+        tokio::task::yield_now().await;
+        let b = tokio::spawn({
+            let adm = admission.clone();
+            async move { adm.acquire(Priority::User, 1, 2, wait, NEVER).await.map(|_| "b") }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(admission.counts(), (1, 2));
+        assert_eq!(
+            admission.acquire(Priority::User, 1, 2, wait, NEVER).await.err(),
+            Some(Rejected::QueueFull)
+        );
+
+        drop(first);
+        assert_eq!(a.await.unwrap(), Ok("a"));
+        assert_eq!(b.await.unwrap(), Ok("b"));
+        assert_eq!(admission.counts(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn queued_requests_time_out() {
+        let admission = Arc::new(Admission::default());
+        let _held = admission.acquire(Priority::User, 1, 4, Duration::from_secs(1), NEVER).await.unwrap();
+
+        let err = admission.acquire(Priority::User, 1, 4, Duration::from_millis(20), NEVER).await.err();
+        assert_eq!(err, Some(Rejected::TimedOut));
+        assert_eq!(admission.counts(), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn higher_lanes_go_first_until_a_request_starves() {
+        for (promote_after, expected) in [
+            (NEVER, ["admin", "guest", "anon"]),
+            // Promoted at once: arrival order, whatever the lane
+            (Duration::ZERO, ["anon", "guest", "admin"]),
+        ] {
+            let admission = Arc::new(Admission::default());
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let held = admission.acquire(Priority::Admin, 1, 8, NEVER, promote_after).await.unwrap();
+            let mut waiting = Vec::new();
+            for (priority, name) in [(Priority::Anonymous, "anon"), (Priority::Guest, "guest"), (Priority::Admin, "admin")] {
+                waiting.push(queue(&admission, &order, priority, name, promote_after));
+                tokio::task::yield_now().await;
+            }
+            let lanes = admission.lanes();
+            assert_eq!(lanes[0].priority, Priority::Admin);
+            assert_eq!((lanes[0].queued, lanes[1].queued, lanes[3].queued), (1, 0, 1));
+            assert!(lanes[1].oldest_wait_ms.is_none() && lanes[3].oldest_wait_ms.is_some());
+
+            drop(held);
+            for handle in waiting {
+                handle.await.unwrap();
+            }
+            assert_eq!(*order.lock().unwrap(), expected);
+            assert_eq!(admission.counts(), (0, 0));
+        }
+    }
+
+    #[test]
+    fn trust_levels_map_to_lanes() {
+        assert_eq!(Priority::from_trust(0), Priority::Anonymous);
+        assert_eq!(Priority::from_trust(1), Priority::Guest);
+        assert_eq!(Priority::from_trust(2), Priority::User);
+        assert_eq!(Priority::from_trust(50), Priority::Admin);
+        assert!(Priority::Admin > Priority::User && Priority::Guest > Priority::Anonymous);
+    }
+}
@@ -0,0 +1,227 @@
+//! What the installed llama.cpp binaries were built with. `llama-server
+//! --version` names the build, compiler and target, and the backends it
+//! loads (CUDA, Metal, Vulkan, ...) log themselves as they initialise. A
+//! build without `GGML_RPC` has no `--rpc` in `--help`, and can't use
+//! llama-rpc-server devices at all.
+
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// Budget for each `--version` and `--help` run.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One installed binary.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct BinaryInfo {
+    /// Where it was found; `None` when it isn't installed
+    pub path: Option<String>,
+    /// Build number and commit, e.g. `4589 (1a24c462)`
+    pub version: Option<String>,
+    pub build: Option<u32>,
+    /// e.g. `cc (Ubuntu 11.4.0-1ubuntu1~22.04) 11.4.0`
+    pub compiler: Option<String>,
+    /// e.g. `x86_64-linux-gnu` or `arm64-apple-darwin23.4.0`
+    pub target: Option<String>,
+    /// Backends seen as it started: `CUDA`, `ROCm`, `Metal`, `Vulkan`,
+    /// `SYCL`, `RPC`, `CPU`, ...
+    pub backends: Vec<String>,
+    /// Whether it takes `--rpc`; `None` when it couldn't be asked
+    pub rpc: Option<bool>,
+    /// Why it couldn't be run
+    pub error: Option<String>,
+}
+
+/// `GET /api/cluster/binaries`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct BinariesInfo {
+    pub llama_server: BinaryInfo,
+    /// Only `path`: llama-rpc-server has no `--version`
+    pub llama_rpc_server: BinaryInfo,
+}
+
+/// What a cached [`BinariesInfo`] was read from: each binary's path and
+/// modification time, so a reinstall is noticed.
+pub type Stamp = Option<(PathBuf, Option<SystemTime>)>;
+
+/// A [`BinariesInfo`] with the stamps of the llama-server and
+/// llama-rpc-server it was read from.
+pub type Cached = ((Stamp, Stamp), BinariesInfo);
+
+pub fn stamp(path: Option<&Path>) -> Stamp {
+    let path = path?;
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    Some((path.to_path_buf(), modified))
+}
+
+/// llama-server was asked to use RPC devices but can't.
+#[derive(Debug)]
+pub struct NoRpcSupport {
+    pub path: String,
+    pub version: Option<String>,
+}
+
+impl fmt::Display for NoRpcSupport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some(version) = &self.version {
+            write!(f, " (version {})", version)?;
+        }
+        f.write_str(
+            " was built without RPC support, so it can't use remote devices. \
+             Install a llama.cpp build with GGML_RPC=ON, or start without devices",
+        )
+    }
+}
+
+impl std::error::Error for NoRpcSupport {}
+
+/// The backend a line of startup output announces.
+fn backend_of(line: &str) -> Option<String> {
+    if let Some(rest) = line.strip_prefix("load_backend: loaded ") {
+        return rest.split_whitespace().next().map(str::to_string);
+    }
+    let backend = if line.starts_with("ggml_cuda_init") {
+        // HIP builds share the CUDA code and say which they found
+        if line.contains("ROCm") {
+            "ROCm"
+        } else {
+            "CUDA"
+        }
+    } else if line.starts_with("ggml_vulkan") {
+        "Vulkan"
+    } else if line.starts_with("ggml_metal") {
+        "Metal"
+    } else if line.starts_with("ggml_sycl") || line.starts_with("[SYCL]") {
+        "SYCL"
+    } else {
+        return None;
+    };
+    Some(backend.to_string())
+}
+
+/// Fill `info` from `--version` output.
+fn parse_version(text: &str, info: &mut BinaryInfo) {
+    for line in text.lines().map(str::trim) {
+        if let Some(version) = line.strip_prefix("version:") {
+            let version = version.trim();
+            info.build = version.split_whitespace().next().and_then(|b| b.parse().ok());
+            info.version = Some(version.to_string());
+        } else if let Some(built) = line.strip_prefix("built with ") {
+            match built.rsplit_once(" for ") {
+                Some((compiler, target)) => {
+                    info.compiler = Some(compiler.to_string());
+                    info.target = Some(target.to_string());
+                }
+                None => info.compiler = Some(built.to_string()),
+            }
+        } else if let Some(backend) = backend_of(line) {
+            if !info.backends.contains(&backend) {
+                info.backends.push(backend);
+            }
+        }
+    }
+}
+
+fn has_rpc_flag(help: &str) -> bool {
+    help.split(|c: char| c.is_whitespace() || c == ',').any(|word| word == "--rpc")
+}
+
+/// Whether `program arg` succeeded, and what it printed on stdout and stderr.
+async fn output_of(program: &Path, arg: &str) -> Result<(bool, String), String> {
+    let run = Command::new(program).arg(arg).stdin(Stdio::null()).kill_on_drop(true).output();
+    let output = timeout(PROBE_TIMEOUT, run)
+        .await
+        .map_err(|_| format!("`{}` did not finish within {} s", arg, PROBE_TIMEOUT.as_secs()))?
+        .map_err(|e| e.to_string())?;
+    let text = [&output.stdout[..], &output.stderr[..]].concat();
+    Ok((output.status.success(), String::from_utf8_lossy(&text).into_owned()))
+}
+
+/// Just where the binary is.
+pub fn located(path: Option<PathBuf>) -> BinaryInfo {
+    BinaryInfo { path: path.map(|p| p.display().to_string()), ..Default::default() }
+}
+
+/// Run llama-server at `path` with `--version` and `--help`.
+pub async fn inspect(path: Option<PathBuf>) -> BinaryInfo {
+    let Some(path) = path else {
+        return BinaryInfo::default();
+    };
+    let mut info = located(Some(path.clone()));
+    match output_of(&path, "--version").await {
+        Ok((ok, text)) => {
+            parse_version(&text, &mut info);
+            if !ok {
+                let last = text.lines().last().unwrap_or("").trim();
+                info.error = Some(format!("`--version` failed: {}", last));
+            }
+        }
+        Err(e) => {
+            info.error = Some(e);
+            return info;
+        }
+    }
+    info.rpc = match output_of(&path, "--help").await {
+        Ok((ok, text)) if ok || has_rpc_flag(&text) => {
+            Some(has_rpc_flag(&text) || info.backends.iter().any(|b| b == "RPC"))
+        }
+        _ => None,
+    };
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_output_names_build_and_backends() {
+        let cuda = "ggml_cuda_init: GGML_CUDA_FORCE_MMQ:    no\n\
+            ggml_cuda_init: found 1 CUDA devices:\n  Device 0: NVIDIA GeForce RTX 3090, compute capability 8.6, VMM: yes\n\
+            load_backend: loaded RPC backend from /opt/llama/libggml-rpc.so\n\
+            load_backend: loaded CUDA backend from /opt/llama/libggml-cuda.so\n\
+            version: 4589 (1a24c462)\nbuilt with cc (Ubuntu 11.4.0-1ubuntu1~22.04) 11.4.0 for x86_64-linux-gnu\n";
+        let mut info = BinaryInfo::default();
+        parse_version(cuda, &mut info);
+        assert_eq!(info.version.as_deref(), Some("4589 (1a24c462)"));
+        assert_eq!(info.build, Some(4589));
+        assert_eq!(info.compiler.as_deref(), Some("cc (Ubuntu 11.4.0-1ubuntu1~22.04) 11.4.0"));
+        assert_eq!(info.target.as_deref(), Some("x86_64-linux-gnu"));
+        assert_eq!(info.backends, ["CUDA", "RPC"]);
+
+        let mut hip = BinaryInfo::default();
+        parse_version("ggml_cuda_init: found 2 ROCm devices:\nggml_vulkan: Found 1 Vulkan devices:\n", &mut hip);
+        assert_eq!(hip.backends, ["ROCm", "Vulkan"]);
+
+        assert!(has_rpc_flag("--rpc SERVERS        comma separated list of RPC servers"));
+        assert!(!has_rpc_flag("--rpc-layers N\n-np, --parallel N"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn binaries_are_asked_for_their_build() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("sharedllm-binary-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server = dir.join("llama-server");
+        let script = "#!/bin/sh\nif [ \"$1\" = --version ]; then echo 'version: 3001 (abc1234)' >&2; \
+            echo 'built with clang for arm64-apple-darwin23.4.0' >&2; else echo '-m, --model FNAME'; fi\n";
+        std::fs::write(&server, script).unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let info = inspect(Some(server.clone())).await;
+        assert_eq!(info.build, Some(3001));
+        assert_eq!(info.target.as_deref(), Some("arm64-apple-darwin23.4.0"));
+        assert_eq!((info.rpc, info.error), (Some(false), None));
+
+        let missing = inspect(Some(dir.join("nope"))).await;
+        assert!(missing.error.is_some() && missing.rpc.is_none());
+        assert_eq!(inspect(None).await, BinaryInfo::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+/// Port the dashboard (and a device's own `/api/gpu`) listens on.
+pub const AGENT_API_PORT: u16 = 8080;
+/// A heartbeat older than this many intervals means the agent is gone.
+const MISSED_HEARTBEATS: u32 = 3;
+
+/// What cluster status learned about a device whose RPC probe failed.
+/// ICMP needs raw sockets, so reachability is judged from TCP connects alone.
+pub struct RpcProbe<'a> {
+    pub rpc_port: u16,
+    /// The RPC port accepted a TCP connection
+    pub rpc_open: bool,
+    /// `AGENT_API_PORT` accepted a TCP connection
+    pub api_open: bool,
+    /// Time since the agent last reported in, if it ever did
+    pub heartbeat_age: Option<Duration>,
+    pub heartbeat_interval: Duration,
+    /// `rpc_status` is `ready`, i.e. the last report said the server was up
+    pub reported_ready: bool,
+    /// `last_rpc_ready_at` is set
+    pub ever_ready: bool,
+    /// `platform` as the device registered it
+    pub platform: Option<&'a str>,
+}
+
+impl RpcProbe<'_> {
+    /// An actionable explanation for the UI, or `None` when RPC is reachable.
+    pub fn diagnosis(&self) -> Option<String> {
+        if self.rpc_open {
+            return None;
+        }
+        let reinstall = format!("reinstall via /agent/install?os={}", agent_os(self.platform));
+        let heartbeat_fresh = self
+            .heartbeat_age
+            .is_some_and(|age| age <= self.heartbeat_interval * MISSED_HEARTBEATS);
+
+        Some(if heartbeat_fresh {
+            // The agent is alive and talking to us, so the host is up
+            if self.reported_ready {
+                format!("firewall blocking {}", self.rpc_port)
+            } else if self.ever_ready {
+                "RPC server stopped — restart the agent".to_string()
+            } else {
+                format!("RPC server never started — {}", reinstall)
+            }
+        } else if !self.api_open {
+            "host unreachable".to_string()
+        } else if self.ever_ready {
+            "agent not running — restart it".to_string()
+        } else {
+            format!("agent not running — {}", reinstall)
+        })
+    }
+}
+
+/// The `/agent/install` `os` for a registered platform string.
+pub fn agent_os(platform: Option<&str>) -> &'static str {
+    let p = platform.unwrap_or_default().to_ascii_lowercase();
+    // "darwin" contains "win", so check it first
+    if p.contains("mac") || p.contains("darwin") {
+        "macos"
+    } else if p.contains("win") {
+        "windows"
+    } else {
+        "linux"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe() -> RpcProbe<'static> {
+        RpcProbe {
+            rpc_port: 8181,
+            rpc_open: false,
+            api_open: false,
+            heartbeat_age: None,
+            heartbeat_interval: Duration::from_secs(30),
+            reported_ready: false,
+            ever_ready: false,
+            platform: None,
+        }
+    }
+
+    #[test]
+    fn layered_checks_map_to_actions() {
+        assert_eq!(RpcProbe { rpc_open: true, ..probe() }.diagnosis(), None);
+
+        let fresh = Some(Duration::from_secs(10));
+        let firewalled = RpcProbe { heartbeat_age: fresh, reported_ready: true, ..probe() };
+        assert_eq!(firewalled.diagnosis().unwrap(), "firewall blocking 8181");
+
+        let crashed = RpcProbe { heartbeat_age: fresh, ever_ready: true, ..probe() };
+        assert!(crashed.diagnosis().unwrap().starts_with("RPC server stopped"));
+
+        let stale = Some(Duration::from_secs(600));
+        let gone = RpcProbe { heartbeat_age: stale, ever_ready: true, ..probe() };
+        assert_eq!(gone.diagnosis().unwrap(), "host unreachable");
+
+        let no_agent = RpcProbe {
+            api_open: true,
+            heartbeat_age: stale,
+            platform: Some("Darwin"),
+            ..probe()
+        };
+        assert_eq!(
+            no_agent.diagnosis().unwrap(),
+            "agent not running — reinstall via /agent/install?os=macos"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Reliability of RPC devices. A device loses points when a session it took
+//! part in crashes soon after starting, or when its RPC server keeps dropping
+//! out, and earns them back by lasting through long sessions. Devices below
+//! [`UNRELIABLE_BELOW`] are left out of tag-based selection.
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::InferenceSessionInfo;
+use crate::db::{models::Device, queries};
+
+/// Score of a new device, and the most any device can have.
+pub const MAX_SCORE: i64 = 100;
+/// Devices scoring below this are flagged `unreliable`.
+pub const UNRELIABLE_BELOW: i64 = 50;
+/// A crash this soon after the session started counts against its devices.
+const EARLY_CRASH: Duration = Duration::from_secs(2 * 60);
+const CRASH_PENALTY: i64 = 20;
+/// Running this long counts for the session's devices.
+pub const LONG_SESSION: Duration = Duration::from_secs(30 * 60);
+const LONG_SESSION_REWARD: i64 = 5;
+/// This many reachable-to-unreachable probes within `FLAP_WINDOW` is flapping.
+const FLAP_DROPS: usize = 3;
+const FLAP_WINDOW: Duration = Duration::from_secs(10 * 60);
+const FLAP_PENALTY: i64 = 10;
+
+/// Why a score changed, stored in `device_health_events.reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum HealthReason {
+    /// A session including the device crashed within two minutes
+    EarlyCrash,
+    /// Its RPC server kept dropping out
+    RpcFlapping,
+    /// A session including the device ran for half an hour
+    LongSession,
+    /// An operator reset the score
+    Reset,
+}
+
+pub fn is_unreliable(device: &Device) -> bool {
+    device.health_score < UNRELIABLE_BELOW
+}
+
+/// How long `session` has been running, from its `started_at`.
+pub fn uptime(session: &InferenceSessionInfo) -> Option<Duration> {
+    let started = chrono::DateTime::parse_from_rfc3339(&session.started_at).ok()?;
+    (chrono::Utc::now() - started.with_timezone(&chrono::Utc)).to_std().ok()
+}
+
+/// The registered devices behind a session's "ip:port" RPC addresses.
+async fn session_devices(pool: &SqlitePool, session: &InferenceSessionInfo) -> Vec<Device> {
+    let mut devices: Vec<Device> = Vec::new();
+    for addr in &session.rpc_devices {
+        let ip = addr.rsplit_once(':').map(|(ip, _)| ip).unwrap_or(addr);
+        let ip = ip.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(Some(device)) = queries::get_device_by_ip(pool, ip).await {
+            if !devices.iter().any(|d| d.id == device.id) {
+                devices.push(device);
+            }
+        }
+    }
+    devices
+}
+
+async fn adjust(pool: &SqlitePool, device: &Device, delta: i64, reason: HealthReason) {
+    match queries::adjust_device_health(pool, &device.id, delta, MAX_SCORE, reason).await {
+        Ok(Some(score)) if score < UNRELIABLE_BELOW && device.health_score >= UNRELIABLE_BELOW => {
+            tracing::warn!("Device {} is now unreliable (health {})", device.name, score);
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to update the health of device {}: {}", device.name, e),
+    }
+}
+
+/// Count a crash of `session` against its devices if it came early.
+pub async fn session_crashed(pool: &SqlitePool, session: &InferenceSessionInfo) {
+    if uptime(session).is_none_or(|u| u >= EARLY_CRASH) {
+        return;
+    }
+    for device in session_devices(pool, session).await {
+        adjust(pool, &device, -CRASH_PENALTY, HealthReason::EarlyCrash).await;
+    }
+}
+
+/// Credit the devices of a session that has run for [`LONG_SESSION`].
+pub async fn session_lasted(pool: &SqlitePool, session: &InferenceSessionInfo) {
+    for device in session_devices(pool, session).await {
+        adjust(pool, &device, LONG_SESSION_REWARD, HealthReason::LongSession).await;
+    }
+}
+
+/// Set a device's score back to [`MAX_SCORE`]. `None` for an unknown device.
+pub async fn reset(pool: &SqlitePool, device_id: &str) -> anyhow::Result<Option<i64>> {
+    queries::adjust_device_health(pool, device_id, MAX_SCORE, MAX_SCORE, HealthReason::Reset).await
+}
+
+/// Recent RPC probe results per device, to spot servers that keep dropping out.
+#[derive(Default)]
+pub struct FlapTracker {
+    devices: Mutex<HashMap<String, Probes>>,
+}
+
+#[derive(Default)]
+struct Probes {
+    last_reachable: bool,
+    drops: Vec<Instant>,
+}
+
+impl FlapTracker {
+    /// Note a probe of `device`, and penalize it once it has dropped out
+    /// [`FLAP_DROPS`] times within [`FLAP_WINDOW`].
+    pub async fn observe(&self, pool: &SqlitePool, device: &Device, reachable: bool) {
+        if self.observe_at(&device.id, reachable, Instant::now()) {
+            adjust(pool, device, -FLAP_PENALTY, HealthReason::RpcFlapping).await;
+        }
+    }
+
+    /// Whether this probe makes the device count as flapping.
+    fn observe_at(&self, device_id: &str, reachable: bool, now: Instant) -> bool {
+        let mut devices = self.devices.lock().unwrap();
+        let probes = devices.entry(device_id.to_string()).or_default();
+        let dropped = probes.last_reachable && !reachable;
+        probes.last_reachable = reachable;
+        if !dropped {
+            return false;
+        }
+        probes.drops.retain(|t| now.duration_since(*t) < FLAP_WINDOW);
+        probes.drops.push(now);
+        if probes.drops.len() < FLAP_DROPS {
+            return false;
+        }
+        // Start over, so one bad stretch costs one penalty
+        probes.drops.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_drops_are_flapping() {
+        let flaps = FlapTracker::default();
+        let start = Instant::now();
+        let mut flapping = Vec::new();
+        for i in 0..8u64 {
+            let at = start + Duration::from_secs(i * 30);
+            flapping.push(flaps.observe_at("d1", i % 2 == 0, at));
+        }
+        // Drops at probes 1, 3 and 5; the third one flaps
+        assert_eq!(flapping, [false, false, false, false, false, true, false, false]);
+
+        // Drops further apart than the window never add up
+        let slow = FlapTracker::default();
+        for i in 0..8u64 {
+            let at = start + Duration::from_secs(i * 4 * 60);
+            assert!(!slow.observe_at("d1", i % 2 == 0, at));
+        }
+    }
+
+    #[tokio::test]
+    async fn scores_stay_in_range_and_are_logged() {
+        let pool = crate::settings::tests::test_pool().await;
+        let device = Device::new("wifi-box".into(), "10.0.0.8".into(), None, "manual");
+        queries::insert_device(&pool, &device).await.unwrap();
+
+        for _ in 0..6 {
+            adjust(&pool, &device, -CRASH_PENALTY, HealthReason::EarlyCrash).await;
+        }
+        let stored = queries::get_device(&pool, &device.id).await.unwrap().unwrap();
+        assert_eq!(stored.health_score, 0);
+        assert!(is_unreliable(&stored));
+
+        assert_eq!(reset(&pool, &device.id).await.unwrap(), Some(MAX_SCORE));
+        let events = queries::list_device_health_events(&pool, &device.id, 10).await.unwrap();
+        assert_eq!(events.len(), 7);
+        assert_eq!((events[0].reason, events[0].delta, events[0].score), (HealthReason::Reset, 100, 100));
+        // The sixth crash found nothing left to take
+        assert_eq!(events[1].delta, 0);
+        assert_eq!(reset(&pool, "missing").await.unwrap(), None);
+    }
+}
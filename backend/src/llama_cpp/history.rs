@@ -0,0 +1,37 @@
+//! Sessions recorded in `inference_sessions` as they start and end, so the
+//! model usage report can tell when a model file was last used.
+
+use sqlx::SqlitePool;
+use tokio::sync::broadcast;
+
+use crate::db::queries;
+use crate::ws::{EventBus, WsEvent};
+
+/// Record every session started or ended from now on.
+pub fn spawn(pool: SqlitePool, event_tx: &EventBus) {
+    let mut rx = event_tx.subscribe_critical();
+    tokio::spawn(async move {
+        loop {
+            let result = match rx.recv().await {
+                Ok(WsEvent::InferenceStarted { session_id, model, devices, .. }) => {
+                    queries::record_session_start(&pool, &session_id, &model, &devices).await
+                }
+                Ok(WsEvent::InferenceStopped { session_id, .. }) => {
+                    queries::record_session_end(&pool, &session_id, "stopped").await
+                }
+                Ok(WsEvent::InferenceFailed { session_id, .. }) => {
+                    queries::record_session_end(&pool, &session_id, "error").await
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Session history lagged; skipped {} events", n);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if let Err(e) = result {
+                tracing::warn!("Failed to record session history: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,161 @@
+//! LoRA adapters loaded on top of the base model (`--lora`,
+//! `--lora-scaled`). llama.cpp takes them as GGUF, or as the older `.bin`
+//! ggml format.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::{split, DISALLOWED_DIRS};
+
+/// Most adapters one session may load.
+pub const MAX_ADAPTERS: usize = 4;
+/// Largest scale magnitude accepted; llama.cpp itself doesn't bound it.
+const MAX_SCALE: f32 = 10.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+pub struct LoraAdapter {
+    /// Absolute path of a `.gguf` or `.bin` adapter
+    pub path: String,
+    /// How strongly the adapter applies; 1.0 (the default) is as trained
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// An adapter file found next to the models, for GET /api/models/aliases.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct AdapterFile {
+    pub path: String,
+    pub size_mb: u64,
+}
+
+fn has_adapter_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("gguf") || e.eq_ignore_ascii_case("bin"))
+}
+
+/// Check adapters the way model paths are checked: absolute, no `..`, a
+/// `.gguf` or `.bin` file outside the system directories, present and not
+/// empty. At most [`MAX_ADAPTERS`] of them, each with a finite scale.
+pub fn validate(adapters: &[LoraAdapter]) -> Result<()> {
+    if adapters.len() > MAX_ADAPTERS {
+        return Err(anyhow!("At most {} LoRA adapters may be loaded", MAX_ADAPTERS));
+    }
+    for adapter in adapters {
+        let path = adapter.path.as_str();
+        let p = Path::new(path);
+        if !p.is_absolute() || path.contains("..") {
+            return Err(anyhow!("LoRA adapter path must be absolute, without '..'"));
+        }
+        if !has_adapter_extension(p) {
+            return Err(anyhow!("LoRA adapter must be a .gguf or .bin file"));
+        }
+        if DISALLOWED_DIRS.iter().any(|prefix| path.starts_with(prefix)) {
+            return Err(anyhow!("LoRA adapter is not in an allowed location"));
+        }
+        if !adapter.scale.is_finite() || adapter.scale.abs() > MAX_SCALE {
+            return Err(anyhow!("LoRA adapter scale must be between -{} and {}", MAX_SCALE, MAX_SCALE));
+        }
+        if !std::fs::metadata(p).is_ok_and(|m| m.is_file() && m.len() > 0) {
+            // Only the file name; the directory isn't echoed back
+            let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            return Err(anyhow!("LoRA adapter {} not found or is empty", name));
+        }
+    }
+    Ok(())
+}
+
+/// llama-server flags for `adapters`: `--lora` at the default scale,
+/// `--lora-scaled` otherwise.
+pub fn args(adapters: &[LoraAdapter]) -> Vec<String> {
+    let mut args = Vec::new();
+    for adapter in adapters {
+        if adapter.scale == 1.0 {
+            args.extend(["--lora".to_string(), adapter.path.clone()]);
+        } else {
+            args.extend(["--lora-scaled".to_string(), adapter.path.clone(), adapter.scale.to_string()]);
+        }
+    }
+    args
+}
+
+/// Combined size of the adapters' files.
+pub fn total_mb(adapters: &[LoraAdapter]) -> u64 {
+    let files: Vec<_> = adapters.iter().map(|a| PathBuf::from(&a.path)).collect();
+    split::total_mb(&files)
+}
+
+/// Whether a file name looks like an adapter rather than a model: a `.gguf`
+/// or `.bin` file with `lora` or `adapter` in its name, as llama.cpp's
+/// `convert_lora_to_gguf.py` and PEFT name theirs.
+fn named_like_adapter(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_ascii_lowercase();
+    has_adapter_extension(path) && (name.contains("lora") || name.contains("adapter"))
+}
+
+/// Adapter files in `dirs`, by path.
+pub fn find(dirs: &[&Path]) -> Vec<AdapterFile> {
+    let mut found: Vec<AdapterFile> = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(meta) = entry.metadata() else { continue };
+            if !meta.is_file() || !named_like_adapter(&path) {
+                continue;
+            }
+            let path = path.display().to_string();
+            if !found.iter().any(|f| f.path == path) {
+                found.push(AdapterFile { path, size_mb: meta.len().div_ceil(1024 * 1024) });
+            }
+        }
+    }
+    found.sort_by(|a, b| a.path.cmp(&b.path));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adapters_are_checked_and_become_flags() {
+        let dir = std::env::temp_dir().join(format!("lora-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("domain-lora-f16.gguf");
+        std::fs::write(&file, b"GGUF adapter").unwrap();
+        std::fs::write(dir.join("model.Q4_K_M.gguf"), b"GGUF model").unwrap();
+        let path = file.display().to_string();
+
+        let adapter = |path: &str, scale: f32| LoraAdapter { path: path.into(), scale };
+        assert!(validate(&[adapter(&path, 1.0), adapter(&path, 0.5)]).is_ok());
+        let too_many = vec![adapter(&path, 1.0); MAX_ADAPTERS + 1];
+        for bad in [
+            too_many,
+            vec![adapter("relative/lora.gguf", 1.0)],
+            vec![adapter("/etc/lora.gguf", 1.0)],
+            vec![adapter("/models/lora.safetensors", 1.0)],
+            vec![adapter(&path, f32::NAN)],
+            vec![adapter(&dir.join("missing-lora.bin").display().to_string(), 1.0)],
+        ] {
+            assert!(validate(&bad).is_err(), "{:?}", bad);
+        }
+
+        assert_eq!(
+            args(&[adapter("/m/a.gguf", 1.0), adapter("/m/b.bin", 0.5)]),
+            ["--lora", "/m/a.gguf", "--lora-scaled", "/m/b.bin", "0.5"]
+        );
+
+        let found = find(&[dir.as_path(), dir.as_path()]);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, path);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
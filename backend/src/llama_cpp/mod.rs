@@ -1,19 +1,83 @@
 use anyhow::{anyhow, Result};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
-use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::process::{Child, Command};
-use tokio::sync::{broadcast, Mutex};
-use which::which;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::errors::{notify_error, Severity};
+use crate::logs::{self, current_request_id};
+use crate::memory::{hotplug::ProviderSet, MemoryProvider};
+use crate::process::{self, ManagedChild, OsSpawner, Spawner};
+use crate::settings::limits::{Limits, LimitsCache};
+use crate::settings::{Key, SettingsCache};
+use admission::{Admission, LaneStats};
+use binary::{BinariesInfo, NoRpcSupport};
+use crate::ws::{EventBus, WsEvent};
+
+pub mod admission;
+pub mod binary;
+pub mod diagnosis;
+pub mod health;
+pub mod history;
+pub mod lora;
+pub mod oom;
+pub mod ports;
+pub mod reservations;
+pub mod rpc_probe;
+pub mod split;
+pub mod store;
+
+/// Binary name of the RPC server.
+pub const RPC_SERVER_BIN: &str = "llama-rpc-server";
+/// Binary name of the inference server.
+pub const INFERENCE_SERVER_BIN: &str = "llama-server";
+/// Port the local llama-rpc-server listens on.
+pub const RPC_PORT: u16 = 8181;
+/// Port llama-server (the inference API) listens on.
+pub const INFERENCE_PORT: u16 = 8282;
+/// How many times the watchdog re-launches a crashed session before giving up
+/// (only when the `auto_restart_inference` setting is on).
+const MAX_RESTART_ATTEMPTS: u32 = 3;
+/// Delay before the first restart attempt; doubles on every further attempt.
+const RESTART_BASE_DELAY_SECS: u64 = 2;
+/// A restarted session that stays up this long has its restart attempts,
+/// and the backoff, start over at its next crash.
+const STABLE_RUN: chrono::TimeDelta = chrono::TimeDelta::minutes(10);
+/// Largest `--parallel` (server slots) accepted for a session.
+pub const MAX_PARALLEL: u32 = 16;
+/// f16 KV cache bytes per token and layer, assuming grouped-query attention
+/// with 8 KV heads of 128 dimensions (Llama 3, Qwen 2, Mistral and kin).
+const KV_BYTES_PER_TOKEN_LAYER: u64 = 2 * 8 * 128 * 2;
+/// Context per slot below which longer chats get cut short.
+const MIN_SLOT_CTX: u32 = 1024;
+/// Percent of free memory the fit checks leave unused (`memory_headroom_pct`).
+/// Global because the checks are plain functions called from many handlers;
+/// [`LimitsCache`](crate::settings::limits::LimitsCache) keeps it current.
+static MEMORY_HEADROOM_PCT: AtomicU64 = AtomicU64::new(10);
+
+pub fn set_memory_headroom(pct: u64) {
+    MEMORY_HEADROOM_PCT.store(pct.min(99), Ordering::Relaxed);
+}
+
+/// Share of free memory the fit checks count as usable.
+fn usable_fraction() -> f64 {
+    1.0 - MEMORY_HEADROOM_PCT.load(Ordering::Relaxed) as f64 / 100.0
+}
 
-use crate::ws::WsEvent;
+/// The part of `free_mb` a model may fill, after the headroom.
+fn usable_mb(free_mb: u64) -> u64 {
+    (free_mb as f64 * usable_fraction()) as u64
+}
 
 // ─── Types ───────────────────────────────────────────────────────────────────
 
 /// How well a model fits into the available cluster memory.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum FitStatus {
     FitsLocally,
@@ -23,11 +87,25 @@ pub enum FitStatus {
 }
 
 /// Analysis of how a model will run across local + cluster memory.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct ModelAnalysis {
+    /// Size of the model file, or of all its parts for a split model
     pub model_size_mb: u64,
+    /// Files the model is made of; more than 1 for a split model
+    #[serde(default)]
+    pub shard_count: u32,
+    /// Size of the speculative-decoding draft model; it is held in local memory
+    #[serde(default)]
+    pub draft_size_mb: u64,
+    /// Combined size of the LoRA adapters, loaded with the model
+    #[serde(default)]
+    pub lora_size_mb: u64,
     pub estimated_layers: u32,
     pub local_free_mb: u64,
+    /// Local memory kept free for the host OS (`host_reserved_mb`), already
+    /// left out of `local_free_mb`
+    #[serde(default)]
+    pub host_reserved_mb: u64,
     pub cluster_free_mb: u64,
     pub total_available_mb: u64,
     pub fit_status: FitStatus,
@@ -36,18 +114,537 @@ pub struct ModelAnalysis {
     pub recommended_n_gpu_layers: i32,
     pub recommended_ctx_size: u32,
     pub warnings: Vec<String>,
+    /// Free memory of each selected device, as it went into `cluster_free_mb`
+    #[serde(default)]
+    pub device_breakdown: Vec<DeviceMemory>,
+    /// Estimated KV cache for the checked context size, shared by all slots
+    #[serde(default)]
+    pub kv_cache_mb: u64,
+    /// Ways to make a model that is too large fit
+    #[serde(default)]
+    pub suggestions: Vec<FitSuggestion>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SuggestionKind {
+    /// Use a smaller quantization of the same model
+    Quantize,
+    /// Free or add memory on cluster devices
+    AddDevices,
+}
+
+/// One entry of `ModelAnalysis::suggestions`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+pub struct FitSuggestion {
+    pub kind: SuggestionKind,
+    pub message: String,
+    /// Quantization to use instead (`quantize`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quant: Option<String>,
+    /// Estimated model size after the change, in MB
+    pub model_size_mb: u64,
+    /// Cluster memory to add, in MB (`add_devices`)
+    #[serde(default)]
+    pub additional_mb: u64,
+    /// How the model would fit after the change
+    pub fit_status: FitStatus,
+}
+
+/// Quantization suggested for a model that is too large.
+const SUGGESTED_QUANT: &str = "Q4_K_M";
+
+/// Approximate bits per weight of common GGUF quantizations, as llama.cpp
+/// reports them for 7B models. Longer names first, so `Q4_K_M` isn't taken
+/// for `Q4_K`.
+const QUANT_BITS: &[(&str, f64)] = &[
+    ("F32", 32.0),
+    ("BF16", 16.0),
+    ("F16", 16.0),
+    ("Q8_0", 8.5),
+    ("Q6_K", 6.56),
+    ("Q5_K_M", 5.69),
+    ("Q5_K_S", 5.54),
+    ("Q5_K", 5.69),
+    ("Q5_1", 6.0),
+    ("Q5_0", 5.5),
+    ("Q4_K_M", 4.85),
+    ("Q4_K_S", 4.58),
+    ("Q4_K", 4.85),
+    ("IQ4_XS", 4.25),
+    ("IQ4_NL", 4.5),
+    ("Q4_1", 5.0),
+    ("Q4_0", 4.55),
+    ("Q3_K_L", 4.27),
+    ("Q3_K_M", 3.91),
+    ("Q3_K_S", 3.5),
+    ("Q2_K", 2.96),
+];
+
+/// The quantization named in a GGUF file name, e.g. `Q8_0` in
+/// `llama-3-70b.Q8_0.gguf`, with its bits per weight.
+pub fn quant_from_name(path: &str) -> Option<(&'static str, f64)> {
+    let name = std::path::Path::new(path).file_name()?.to_str()?.to_ascii_uppercase();
+    name.split(['-', '.'])
+        .find_map(|token| QUANT_BITS.iter().find(|(q, _)| *q == token))
+        .copied()
+}
+
+/// One device's share of `ModelAnalysis::cluster_free_mb`
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DeviceMemory {
+    pub device_id: String,
+    pub name: String,
+    /// Free memory the device reported, before inference reservations
+    pub free_mb: u64,
+    /// Read from the device for this request rather than reused from the
+    /// last few seconds
+    pub live: bool,
+    /// The device couldn't be read; this is the last stored value
+    pub stale: bool,
+    /// Its llama-rpc-server keeps received tensors on disk (`--cache`)
+    #[serde(default)]
+    pub rpc_cache_enabled: bool,
+    #[serde(default)]
+    pub rpc_cache_mb: i64,
+    /// The cache is at least as large as this device's share of the model,
+    /// so the weights probably won't have to be sent again
+    #[serde(default)]
+    pub cache_likely: bool,
+    /// `free_mb` was set by hand (`PATCH /api/devices/:id/memory-stats`)
+    #[serde(default)]
+    pub manual_override: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Link speed assumed for streaming weights to RPC devices. Nothing measures
+/// it yet; this is a typical Wi-Fi figure.
+pub const ASSUMED_LINK_MBITS: u64 = 100;
+/// Weights to send below which the distribution time isn't worth a warning.
+const TRANSFER_WARN_MB: u64 = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct InferenceSessionInfo {
     pub id: String,
     pub model_path: String,
-    pub status: String, // starting | running | stopped | error
+    pub status: String, // starting | running | restarting | draining | stopped | error
     pub rpc_devices: Vec<String>, // "ip:port" strings
     pub started_at: String,
+    /// Launch arguments, kept so the watchdog can re-launch the same session.
+    pub n_gpu_layers: i32,
+    pub ctx_size: u32,
+    /// Server slots (`--parallel`): requests llama-server decodes at once
+    #[serde(default = "default_parallel")]
+    pub parallel: u32,
+    /// `--cont-batching`: slots join a running batch instead of waiting for it
+    #[serde(default)]
+    pub cont_batching: bool,
+    /// Number of automatic restarts performed after unexpected exits.
+    pub restart_attempts: u32,
+    /// When the watchdog last re-launched it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restarted_at: Option<String>,
+    /// GPU selection the session was started with (re-applied on restart)
+    #[serde(default)]
+    pub gpu_selector: GpuSelector,
+    /// Environment variables set on llama-server, derived from `gpu_selector`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Draft model for speculative decoding
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft: Option<DraftModel>,
+    /// Multimodal projector (`--mmproj`) of a vision model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mmproj: Option<String>,
+    /// Started with a projector, so chat requests may carry images
+    #[serde(default)]
+    pub supports_vision: bool,
+    /// Built-in chat template (`--chat-template`) overriding the model's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<String>,
+    /// Added by the proxy as the first message of requests without a system message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// LoRA adapters applied on top of the model (`--lora`, `--lora-scaled`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lora_adapters: Vec<lora::LoraAdapter>,
+    /// Retry with fewer GPU layers if llama-server runs out of memory loading
+    #[serde(default)]
+    pub retry_on_oom: bool,
+    /// Set once it has; `n_gpu_layers` holds the layers actually used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oom_fallback: Option<oom::OomFallback>,
+    /// `--api-key` llama-server requires of every request, so only this
+    /// backend can reach it. Shown masked.
+    #[serde(default, skip_deserializing, serialize_with = "serialize_masked")]
+    pub api_key: String,
+}
+
+/// `key` with all but its last four characters hidden; short keys entirely.
+pub fn mask_secret(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() < 12 {
+        return "****".to_string();
+    }
+    format!("****{}", chars[chars.len() - 4..].iter().collect::<String>())
+}
+
+fn serialize_masked<S: serde::Serializer>(key: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&mask_secret(key))
+}
+
+pub fn default_parallel() -> u32 {
+    1
+}
+
+/// Small model that proposes tokens for the main one to verify
+/// (`--model-draft`). It always runs on the local GPU.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+pub struct DraftModel {
+    pub path: String,
+    /// `--draft-max`: tokens drafted per step
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+    /// `--draft-min`: fewest drafted tokens worth verifying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+}
+
+/// Everything about a llama-server launch besides the model and RPC devices.
+#[derive(Debug, Clone)]
+pub struct LaunchOptions {
+    /// -1 = all layers on GPU, 0 = CPU only, N = N layers on GPU
+    pub n_gpu_layers: i32,
+    /// Context window in tokens
+    pub ctx_size: u32,
+    /// Server slots (`--parallel`)
+    pub parallel: u32,
+    /// Pass `--cont-batching`
+    pub cont_batching: bool,
+    /// Which local GPUs llama-server may use
+    pub gpu_selector: GpuSelector,
+    pub draft: Option<DraftModel>,
+    /// Multimodal projector, validated with [`validate_mmproj`]
+    pub mmproj: Option<String>,
+    /// One of [`CHAT_TEMPLATES`]
+    pub chat_template: Option<String>,
+    pub system_prompt: Option<String>,
+    /// Validated with [`lora::validate`]
+    pub lora_adapters: Vec<lora::LoraAdapter>,
+    /// See [`oom`]
+    pub retry_on_oom: bool,
+    /// `--api-key` for llama-server (the `inference_api_key` setting); a
+    /// random one is made for the session when unset
+    pub api_key: Option<String>,
+}
+
+impl InferenceSessionInfo {
+    /// Restart attempts that still count at a crash at `now`: none once the
+    /// last automatic restart stayed up for [`STABLE_RUN`].
+    fn restarts_counted(&self, now: chrono::DateTime<chrono::Utc>) -> u32 {
+        let stable = self
+            .restarted_at
+            .as_deref()
+            .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+            .is_some_and(|at| now - at.with_timezone(&chrono::Utc) >= STABLE_RUN);
+        if stable {
+            0
+        } else {
+            self.restart_attempts
+        }
+    }
+
+    fn new(model_path: &str, rpc_devices: Vec<String>, opts: LaunchOptions) -> Self {
+        InferenceSessionInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            model_path: model_path.to_string(),
+            status: "starting".to_string(),
+            rpc_devices,
+            started_at: chrono::Utc::now().to_rfc3339(),
+            n_gpu_layers: opts.n_gpu_layers,
+            ctx_size: opts.ctx_size,
+            parallel: opts.parallel,
+            cont_batching: opts.cont_batching,
+            restart_attempts: 0,
+            restarted_at: None,
+            env: opts.gpu_selector.env(),
+            gpu_selector: opts.gpu_selector,
+            draft: opts.draft,
+            supports_vision: opts.mmproj.is_some(),
+            mmproj: opts.mmproj,
+            chat_template: opts.chat_template,
+            system_prompt: opts.system_prompt,
+            lora_adapters: opts.lora_adapters,
+            retry_on_oom: opts.retry_on_oom,
+            oom_fallback: None,
+            api_key: opts
+                .api_key
+                .filter(|k| !k.trim().is_empty())
+                .unwrap_or_else(crate::permissions::generate_secret),
+        }
+    }
+}
+
+/// How llama.cpp splits a model across several local GPUs (`--split-mode`).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitMode {
+    None,
+    Layer,
+    Row,
+}
+
+/// Which local GPUs llama-server may use. Every field maps to a fixed
+/// environment variable or flag — there is no free-form env passthrough.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GpuSelector {
+    /// `CUDA_VISIBLE_DEVICES`: comma-separated indices or `GPU-…` / `MIG-…` UUIDs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cuda_visible_devices: Option<String>,
+    /// `HIP_VISIBLE_DEVICES` (ROCm): comma-separated indices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hip_visible_devices: Option<String>,
+    /// `--main-gpu`: index (among the visible GPUs) for the model / scratch buffers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_gpu: Option<u32>,
+    /// `--split-mode`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_mode: Option<SplitMode>,
+    /// `GGML_METAL_NDEBUG=1`: silence Metal debug output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metal_ndebug: Option<bool>,
+}
+
+/// Largest `--main-gpu` index accepted.
+const MAX_GPU_INDEX: u32 = 63;
+
+impl GpuSelector {
+    /// Parse a request's `gpu_selector`; unknown keys and malformed values
+    /// are rejected with a message suitable for a 400.
+    pub fn from_value(value: serde_json::Value) -> std::result::Result<Self, String> {
+        let selector: GpuSelector =
+            serde_json::from_value(value).map_err(|e| format!("Invalid gpu_selector: {}", e))?;
+        if let Some(list) = &selector.cuda_visible_devices {
+            validate_device_list("cuda_visible_devices", list, true)?;
+        }
+        if let Some(list) = &selector.hip_visible_devices {
+            validate_device_list("hip_visible_devices", list, false)?;
+        }
+        if selector.main_gpu.is_some_and(|g| g > MAX_GPU_INDEX) {
+            return Err(format!("gpu_selector.main_gpu must be between 0 and {}", MAX_GPU_INDEX));
+        }
+        Ok(selector)
+    }
+
+    /// Environment variables for llama-server.
+    pub fn env(&self) -> BTreeMap<String, String> {
+        let mut env = BTreeMap::new();
+        if let Some(v) = &self.cuda_visible_devices {
+            env.insert("CUDA_VISIBLE_DEVICES".to_string(), v.clone());
+        }
+        if let Some(v) = &self.hip_visible_devices {
+            env.insert("HIP_VISIBLE_DEVICES".to_string(), v.clone());
+        }
+        if self.metal_ndebug == Some(true) {
+            env.insert("GGML_METAL_NDEBUG".to_string(), "1".to_string());
+        }
+        env
+    }
+
+    /// Extra llama-server flags.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(gpu) = self.main_gpu {
+            args.push("--main-gpu".to_string());
+            args.push(gpu.to_string());
+        }
+        if let Some(mode) = self.split_mode {
+            let mode = match mode {
+                SplitMode::None => "none",
+                SplitMode::Layer => "layer",
+                SplitMode::Row => "row",
+            };
+            args.push("--split-mode".to_string());
+            args.push(mode.to_string());
+        }
+        args
+    }
+}
+
+/// A `*_VISIBLE_DEVICES` value: 1–16 comma-separated GPU indices, or (CUDA
+/// only) `GPU-…` / `MIG-…` UUIDs.
+fn validate_device_list(field: &str, list: &str, allow_uuids: bool) -> std::result::Result<(), String> {
+    let items: Vec<&str> = list.split(',').map(str::trim).collect();
+    let valid_item = |item: &str| {
+        (!item.is_empty() && item.len() <= 4 && item.chars().all(|c| c.is_ascii_digit()))
+            || (allow_uuids
+                && (item.starts_with("GPU-") || item.starts_with("MIG-"))
+                && item.len() <= 64
+                && item.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '/'))
+    };
+    if items.len() > 16 || !items.iter().all(|i| valid_item(i)) {
+        return Err(format!(
+            "gpu_selector.{} must be a comma-separated list of GPU indices{}",
+            field,
+            if allow_uuids { " or GPU-/MIG- UUIDs" } else { "" }
+        ));
+    }
+    Ok(())
+}
+
+/// Setting capping the local llama-rpc-server's memory, in MB; `0` = no cap.
+pub const RPC_MEM_KEY: &str = "rpc_mem_mb";
+/// Setting: minutes without a chat completion after which the watchdog
+/// stops the inference session; `0` = never.
+pub const IDLE_TIMEOUT_KEY: &str = "idle_timeout_minutes";
+
+/// `reason` of the `InferenceStopped` sent when the idle timeout stops a session.
+pub const STOP_REASON_IDLE: &str = "idle_timeout";
+
+/// The idle timeout of `minutes` in seconds, or `None` when it is off.
+pub fn idle_timeout_secs(minutes: u64) -> Option<u64> {
+    (minutes > 0).then(|| minutes * 60)
+}
+
+/// Largest `--mem` cap accepted, in MB.
+const MAX_RPC_MEM_MB: u64 = 1024 * 1024;
+
+/// The ggml backend a llama-rpc-server serves, for builds with several.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, sqlx::Type, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum RpcBackend {
+    Cuda,
+    Metal,
+    Cpu,
+}
+
+impl RpcBackend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cuda" => Some(RpcBackend::Cuda),
+            "metal" => Some(RpcBackend::Metal),
+            "cpu" => Some(RpcBackend::Cpu),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcBackend::Cuda => "cuda",
+            RpcBackend::Metal => "metal",
+            RpcBackend::Cpu => "cpu",
+        }
+    }
+
+    /// `--device` name of the backend's first device, as llama.cpp lists it.
+    pub fn device(&self) -> &'static str {
+        match self {
+            RpcBackend::Cuda => "CUDA0",
+            RpcBackend::Metal => "MTL0",
+            RpcBackend::Cpu => "CPU",
+        }
+    }
+}
+
+/// How an agent's llama-rpc-server is launched, beyond host and port.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+pub struct RpcLaunch {
+    /// `--device`; `None` runs whatever backend the build defaults to
+    #[serde(default)]
+    pub backend: Option<RpcBackend>,
+    /// `--mem`: memory the server may hand out, in MB
+    #[serde(default)]
+    pub mem_mb: Option<u64>,
+}
+
+impl RpcLaunch {
+    /// Read `?backend=cuda|metal|cpu&mem_mb=…` from an install request.
+    pub fn from_query(
+        params: &std::collections::HashMap<String, String>,
+    ) -> std::result::Result<Self, String> {
+        let backend = match params.get("backend").map(|b| b.trim()).filter(|b| !b.is_empty()) {
+            None => None,
+            Some(b) => Some(
+                RpcBackend::parse(b)
+                    .ok_or_else(|| format!("Invalid backend '{}': expected cuda, metal or cpu", b))?,
+            ),
+        };
+        let mem_mb = match params.get("mem_mb").map(|m| m.trim()).filter(|m| !m.is_empty()) {
+            None => None,
+            Some(m) => Some(m.parse::<u64>().map_err(|_| {
+                format!("Invalid mem_mb '{}': expected a number of MB", m)
+            })?),
+        };
+        let launch = RpcLaunch { backend, mem_mb };
+        launch.validate()?;
+        Ok(launch)
+    }
+
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if self.mem_mb.is_some_and(|mb| mb == 0 || mb > MAX_RPC_MEM_MB) {
+            return Err(format!("mem_mb must be between 1 and {}", MAX_RPC_MEM_MB));
+        }
+        Ok(())
+    }
+
+    /// Extra llama-rpc-server flags.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(backend) = self.backend {
+            args.push("--device".to_string());
+            args.push(backend.device().to_string());
+        }
+        if let Some(mb) = self.mem_mb {
+            args.push("--mem".to_string());
+            args.push(mb.to_string());
+        }
+        args
+    }
+}
+
+/// `rpc_mem_mb` as a cap; `0` or more than 1 TB means none.
+pub fn rpc_mem_cap(mb: u64) -> Option<u64> {
+    (1..=MAX_RPC_MEM_MB).contains(&mb).then_some(mb)
+}
+
+/// What `start_inference` would run: binary, flags and environment.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LaunchPlan {
+    /// `None` when llama-server is not installed
+    pub binary: Option<String>,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Live generation metrics scraped from llama-server's /metrics and /slots.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct InferenceMetrics {
+    pub slots_total: u32,
+    pub slots_busy: u32,
+    pub slots_idle: u32,
+    /// Requests waiting for a free slot (`None` when /metrics is unavailable)
+    pub queue_depth: Option<u64>,
+    pub prompt_tokens_total: Option<u64>,
+    pub generated_tokens_total: Option<u64>,
+    /// Average prompt processing throughput reported by llama-server
+    pub prompt_tokens_per_sec: Option<f64>,
+    /// Average generation throughput reported by llama-server
+    pub generated_tokens_per_sec: Option<f64>,
+    /// Chat requests the proxy is forwarding right now
+    #[serde(default)]
+    pub proxy_in_flight: usize,
+    /// Chat requests waiting in the proxy's admission queue
+    #[serde(default)]
+    pub proxy_queued: usize,
+    /// The same by priority lane, highest first
+    #[serde(default)]
+    pub proxy_lanes: Vec<LaneStats>,
+    /// "metrics" when /metrics was scraped, "slots" for the /slots-only fallback
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct LlamaCppStatus {
     pub rpc_server_running: bool,
     pub inference_running: bool,
@@ -56,14 +653,27 @@ pub struct LlamaCppStatus {
     pub rpc_port: u16,
     pub inference_port: u16,
     pub current_session: Option<InferenceSessionInfo>,
+    /// A start is checking devices or launching llama-server
+    pub starting: bool,
 }
 
 // ─── Internal state ──────────────────────────────────────────────────────────
 
 struct LlamaCppState {
-    rpc_process: Option<Child>,
-    inference_process: Option<Child>,
+    rpc_process: Option<ManagedChild>,
+    inference_process: Option<ManagedChild>,
     current_session: Option<InferenceSessionInfo>,
+    /// Session whose llama-server exited unexpectedly, waiting for the watchdog
+    /// to decide whether to restart it. Set by whichever path reaps the child.
+    crashed_session: Option<InferenceSessionInfo>,
+    /// Session whose llama-server ran out of memory while loading, waiting
+    /// for the watchdog to retry it with fewer GPU layers (`retry_on_oom`).
+    oom_session: Option<InferenceSessionInfo>,
+    /// ID of the session the watchdog is currently trying to restart.
+    /// Cleared by a manual start/stop so a pending restart is abandoned.
+    pending_restart: Option<String>,
+    /// Where the current llama-server's output starts in its log file
+    log_mark: u64,
 }
 
 // ─── Manager ─────────────────────────────────────────────────────────────────
@@ -73,16 +683,67 @@ pub struct LlamaCppManager {
     pub inference_port: u16,
     pub client: Client,
     state: Arc<Mutex<LlamaCppState>>,
-    event_tx: broadcast::Sender<WsEvent>,
+    event_tx: EventBus,
+    /// Chat requests currently proxied to llama-server (see `track_request`).
+    in_flight: Arc<AtomicUsize>,
+    /// Unix time of the last chat completion to start or finish, or of the
+    /// session launch if later; the idle timeout counts from here.
+    last_completion: Arc<AtomicI64>,
+    /// Concurrency limit and queue in front of llama-server
+    pub admission: Arc<Admission>,
+    /// Finds and starts llama-server and llama-rpc-server
+    spawner: Arc<dyn Spawner>,
+    /// Probe and proxy timeouts
+    limits: Arc<LimitsCache>,
+    /// Held by the start under way (see `begin_start`)
+    starting: Arc<AtomicBool>,
+    /// Last `binary_info`, with the binaries it was read from
+    binary_info: Arc<std::sync::Mutex<Option<binary::Cached>>>,
+}
+
+/// Marks a start as under way until dropped; see
+/// [`LlamaCppManager::begin_start`].
+pub struct StartGuard {
+    starting: Arc<AtomicBool>,
+}
+
+impl Drop for StartGuard {
+    fn drop(&mut self) {
+        self.starting.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Marks one proxied request as in flight; the count drops when the guard
+/// does, so keep it alive until the response body has finished streaming.
+pub struct InFlightGuard {
+    in_flight: Arc<AtomicUsize>,
+    last_completion: Arc<AtomicI64>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        // Stamp before the count drops, so the session never looks idle since
+        // before a completion that just finished
+        self.last_completion.store(chrono::Utc::now().timestamp(), Ordering::SeqCst);
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 // ─── Model path validation ────────────────────────────────────────────────────
 
+/// Protected system directories no model or adapter may be loaded from.
+const DISALLOWED_DIRS: &[&str] = &[
+    "/etc/", "/proc/", "/sys/", "/dev/",
+    "/boot/", "/var/run/", "/run/", "/bin/", "/sbin/",
+    "/usr/bin/", "/usr/sbin/",
+];
+
 /// Validate that a model path is safe to load:
 /// - Must be absolute (no relative paths)
 /// - Must end in `.gguf`
 /// - Must not contain path traversal (`..`)
 /// - Must not point at protected system directories
+/// - Must be the first part of a split model, which llama.cpp loads the rest from
 pub fn validate_model_path(path: &str) -> Result<()> {
     if path.is_empty() {
         return Err(anyhow!("Model path cannot be empty"));
@@ -110,38 +771,143 @@ pub fn validate_model_path(path: &str) -> Result<()> {
     }
 
     // Reject protected system directories
-    let disallowed = [
-        "/etc/", "/proc/", "/sys/", "/dev/",
-        "/boot/", "/var/run/", "/run/", "/bin/", "/sbin/",
-        "/usr/bin/", "/usr/sbin/",
-    ];
-    for prefix in &disallowed {
+    for prefix in DISALLOWED_DIRS {
         if path.starts_with(prefix) {
             return Err(anyhow!("Model path is not in an allowed location"));
         }
     }
 
+    if let Some(s) = p.file_name().and_then(|n| n.to_str()).and_then(split::parse) {
+        if s.part != 1 {
+            return Err(anyhow!(
+                "Split model: pass its first part, {}",
+                split::part_file_name(s.base, 1, s.count)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate the multimodal projector of `model_path`: a safe `.gguf` path
+/// like a model's, named like a projector (`mmproj` in the file name), and
+/// in the model's directory, which is where llama.cpp vision models ship
+/// theirs.
+pub fn validate_mmproj(model_path: &str, mmproj_path: &str) -> Result<()> {
+    validate_model_path(mmproj_path)?;
+    let mmproj = std::path::Path::new(mmproj_path);
+    let named_like_one = mmproj
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.to_ascii_lowercase().contains("mmproj"));
+    if !named_like_one {
+        return Err(anyhow!("Projector file name must contain 'mmproj'"));
+    }
+    if mmproj.parent() != std::path::Path::new(model_path).parent() {
+        return Err(anyhow!("Projector must be in the same directory as the model"));
+    }
+    if !std::fs::metadata(mmproj).is_ok_and(|m| m.is_file() && m.len() > 0) {
+        return Err(anyhow!("Projector file not found or is empty"));
+    }
     Ok(())
 }
 
+/// Chat templates built into llama-server, for `--chat-template`.
+pub const CHAT_TEMPLATES: &[&str] = &[
+    "chatglm3", "chatglm4", "chatml", "command-r", "deepseek", "deepseek2", "deepseek3",
+    "exaone3", "falcon3", "gemma", "gigachat", "glmedge", "granite", "llama2", "llama2-sys",
+    "llama2-sys-bos", "llama2-sys-strip", "llama3", "megrez", "minicpm", "mistral-v1",
+    "mistral-v3", "mistral-v3-tekken", "mistral-v7", "monarch", "openchat", "orion", "phi3",
+    "phi4", "rwkv-world", "vicuna", "vicuna-orca", "zephyr",
+];
+
+/// Longest system prompt a session may inject, in characters.
+pub const MAX_SYSTEM_PROMPT_LEN: usize = 16_000;
+
+/// Check a chat template name against [`CHAT_TEMPLATES`]. Only built-in
+/// names are taken: a template file would be a path to validate and trust.
+pub fn validate_chat_template(name: &str) -> Result<String> {
+    let name = name.trim().to_ascii_lowercase();
+    if CHAT_TEMPLATES.contains(&name.as_str()) {
+        Ok(name)
+    } else {
+        Err(anyhow!(
+            "Unknown chat template '{}'; use one of: {}",
+            name,
+            CHAT_TEMPLATES.join(", ")
+        ))
+    }
+}
+
+/// Trim a system prompt and check its length. An empty one is none.
+pub fn validate_system_prompt(prompt: &str) -> Result<Option<String>> {
+    let prompt = prompt.trim();
+    if prompt.chars().count() > MAX_SYSTEM_PROMPT_LEN {
+        return Err(anyhow!("system_prompt must be at most {} characters", MAX_SYSTEM_PROMPT_LEN));
+    }
+    Ok((!prompt.is_empty()).then(|| prompt.to_string()))
+}
+
+/// Size of a validated model file in MB.
+/// Size of a model in MB, every part of a split model included, and its
+/// number of files.
+fn model_file_mb(path: &str) -> Result<(u64, u32)> {
+    validate_model_path(path)?;
+    let files = split::model_files(std::path::Path::new(path))?;
+    let size_mb = split::total_mb(&files);
+    if size_mb == 0 {
+        // Don't echo the path back in the error — avoid path disclosure
+        return Err(anyhow!("Model file not found or is empty"));
+    }
+    Ok((size_mb, files.len() as u32))
+}
+
+/// Client for requests proxied to llama-server.
+fn proxy_client(limits: Limits) -> Client {
+    Client::builder()
+        .timeout(limits.proxy_timeout())
+        .build()
+        .unwrap_or_default()
+}
+
 impl LlamaCppManager {
-    pub fn new(event_tx: broadcast::Sender<WsEvent>) -> Self {
+    pub fn new(event_tx: EventBus) -> Self {
+        Self::with_spawner(event_tx, Arc::new(OsSpawner))
+    }
+
+    /// A manager that starts its servers through `spawner`.
+    pub fn with_spawner(event_tx: EventBus, spawner: Arc<dyn Spawner>) -> Self {
         LlamaCppManager {
-            rpc_port: 8181,
-            inference_port: 8282,
-            client: Client::builder()
-                .timeout(std::time::Duration::from_secs(120))
-                .build()
-                .unwrap_or_default(),
+            rpc_port: RPC_PORT,
+            inference_port: INFERENCE_PORT,
+            client: proxy_client(Limits::default()),
             state: Arc::new(Mutex::new(LlamaCppState {
                 rpc_process: None,
                 inference_process: None,
                 current_session: None,
+                crashed_session: None,
+                oom_session: None,
+                pending_restart: None,
+                log_mark: 0,
             })),
             event_tx,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            last_completion: Arc::new(AtomicI64::new(chrono::Utc::now().timestamp())),
+            admission: Arc::new(Admission::default()),
+            spawner,
+            limits: Arc::default(),
+            starting: Arc::new(AtomicBool::new(false)),
+            binary_info: Arc::default(),
         }
     }
 
+    /// Take probe and proxy timeouts from `limits` rather than the defaults.
+    pub fn with_limits(mut self, limits: Arc<LimitsCache>) -> Self {
+        self.client = proxy_client(limits.get());
+        self.limits = limits;
+        self
+    }
+
     /// Estimate llama.cpp layer count from model file size (MB).
     /// These are approximate heuristics based on common GGUF model families.
     fn estimate_layers(model_size_mb: u64) -> u32 {
@@ -158,56 +924,57 @@ impl LlamaCppManager {
     /// Analyse how well a model fits into local + cluster memory.
     ///
     /// - `model_path`       – absolute path to the .gguf file (used for size).
+    /// - `draft_path`       – optional speculative-decoding draft model.
     /// - `local_free_mb`    – free memory on this machine (GPU/unified).
     /// - `device_free_mbs`  – free memory per approved cluster device.
     pub fn analyze_model(
         model_path: &str,
+        draft_path: Option<&str>,
         local_free_mb: u64,
         device_free_mbs: Vec<u64>,
     ) -> anyhow::Result<ModelAnalysis> {
-        // Validate path before any filesystem access
-        validate_model_path(model_path)?;
+        let (model_size_mb, shard_count) = model_file_mb(model_path)?;
+        let draft_size_mb = draft_path.map(model_file_mb).transpose()?.map_or(0, |(mb, _)| mb);
 
-        let model_size_mb = std::fs::metadata(model_path)
-            .map(|m| m.len() / (1024 * 1024))
-            .unwrap_or(0);
+        let mut warnings: Vec<String> = Vec::new();
 
-        if model_size_mb == 0 {
-            // Don't echo the path back in the error — avoid path disclosure
-            return Err(anyhow!("Model file not found or is empty"));
+        // The draft model sits on the local GPU; the main model gets what's left
+        let draft_fits = draft_size_mb <= usable_mb(local_free_mb);
+        if !draft_fits {
+            warnings.push(format!(
+                "Draft model needs {} MB but only {} MB is free locally",
+                draft_size_mb, local_free_mb
+            ));
         }
+        let local_free_mb = local_free_mb.saturating_sub(draft_size_mb);
 
         let estimated_layers = Self::estimate_layers(model_size_mb);
         let cluster_free_mb: u64 = device_free_mbs.iter().sum();
         let total_available_mb = local_free_mb + cluster_free_mb;
 
-        let mut warnings: Vec<String> = Vec::new();
-
-        // Leave 10% headroom when computing "usable" memory.
-        let usable_local  = (local_free_mb  as f64 * 0.90) as u64;
-        let usable_total  = (total_available_mb as f64 * 0.90) as u64;
-
-        let fit_status = if model_size_mb <= usable_local {
-            FitStatus::FitsLocally
-        } else if model_size_mb <= usable_total && cluster_free_mb > 0 {
-            FitStatus::FitsDistributed
-        } else if model_size_mb <= total_available_mb {
-            if cluster_free_mb == 0 {
-                warnings.push(
-                    "Add cluster devices to offload layers and fit this model".to_string(),
-                );
-            } else {
-                warnings.push("Model may not fit — very tight on memory".to_string());
-            }
-            FitStatus::PartialGpu
-        } else {
-            warnings.push(format!(
-                "Model needs ~{} GB but only {} GB available across cluster",
-                (model_size_mb + 511) / 1024,
-                (total_available_mb + 511) / 1024,
-            ));
+        let mut suggestions = Vec::new();
+        let fit_status = if !draft_fits {
             FitStatus::TooLarge
+        } else {
+            Self::classify_fit(model_size_mb, local_free_mb, cluster_free_mb)
         };
+        match fit_status {
+            FitStatus::PartialGpu if cluster_free_mb == 0 => warnings.push(
+                "Add cluster devices to offload layers and fit this model".to_string(),
+            ),
+            FitStatus::PartialGpu => {
+                warnings.push("Model may not fit — very tight on memory".to_string())
+            }
+            FitStatus::TooLarge if draft_fits => {
+                warnings.push(format!(
+                    "Model needs ~{} GB but only {} GB available across cluster",
+                    (model_size_mb + 511) / 1024,
+                    (total_available_mb + 511) / 1024,
+                ));
+                suggestions = Self::fit_suggestions(model_path, model_size_mb, local_free_mb, cluster_free_mb);
+            }
+            _ => {}
+        }
 
         // Recommended n_gpu_layers (-1 = all layers on GPU)
         let recommended_n_gpu_layers: i32 = match &fit_status {
@@ -244,41 +1011,262 @@ impl LlamaCppManager {
 
         Ok(ModelAnalysis {
             model_size_mb,
+            shard_count,
+            draft_size_mb,
             estimated_layers,
             local_free_mb,
+            host_reserved_mb: 0,
             cluster_free_mb,
             total_available_mb,
             fit_status,
             recommended_n_gpu_layers,
             recommended_ctx_size,
             warnings,
+            device_breakdown: Vec::new(),
+            kv_cache_mb: 0,
+            suggestions,
+            lora_size_mb: 0,
         })
     }
 
-    // ─── Binary discovery ─────────────────────────────────────────────────
+    /// How a model of `model_size_mb` fits, leaving the memory headroom out of
+    /// "usable" memory.
+    fn classify_fit(model_size_mb: u64, local_free_mb: u64, cluster_free_mb: u64) -> FitStatus {
+        let total_available_mb = local_free_mb + cluster_free_mb;
+        let usable_local = usable_mb(local_free_mb);
+        let usable_total = usable_mb(total_available_mb);
+        if model_size_mb <= usable_local {
+            FitStatus::FitsLocally
+        } else if model_size_mb <= usable_total && cluster_free_mb > 0 {
+            FitStatus::FitsDistributed
+        } else if model_size_mb <= total_available_mb {
+            FitStatus::PartialGpu
+        } else {
+            FitStatus::TooLarge
+        }
+    }
+
+    /// Suggestions for a model that is too large: a [`SUGGESTED_QUANT`]
+    /// build of it, when its file name shows a larger quantization, and the
+    /// cluster memory that would let it run distributed as it is.
+    pub fn fit_suggestions(
+        model_path: &str,
+        model_size_mb: u64,
+        local_free_mb: u64,
+        cluster_free_mb: u64,
+    ) -> Vec<FitSuggestion> {
+        let gb = |mb: u64| (mb + 511) / 1024;
+        let mut suggestions = Vec::new();
+
+        let target_bits = QUANT_BITS.iter().find(|(q, _)| *q == SUGGESTED_QUANT).map_or(4.85, |(_, b)| *b);
+        if let Some((quant, bits)) = quant_from_name(model_path).filter(|(_, bits)| *bits > target_bits) {
+            // Same parameter count, fewer bits for each
+            let size_mb = (model_size_mb as f64 * target_bits / bits).round() as u64;
+            let fit_status = Self::classify_fit(size_mb, local_free_mb, cluster_free_mb);
+            let outcome = match fit_status {
+                FitStatus::FitsLocally => "fit locally",
+                FitStatus::FitsDistributed => "fit across the cluster",
+                FitStatus::PartialGpu => "fit with part of it on the CPU",
+                FitStatus::TooLarge => "still be too large",
+            };
+            suggestions.push(FitSuggestion {
+                kind: SuggestionKind::Quantize,
+                message: format!(
+                    "A {} build of this {} model would be ~{} GB and would {}",
+                    SUGGESTED_QUANT, quant, gb(size_mb), outcome
+                ),
+                quant: Some(SUGGESTED_QUANT.to_string()),
+                model_size_mb: size_mb,
+                additional_mb: 0,
+                fit_status,
+            });
+        }
+
+        // Smallest total for which the model passes the headroom check
+        let needed_mb = (model_size_mb as f64 / usable_fraction()).ceil() as u64;
+        let additional_mb = needed_mb.saturating_sub(local_free_mb + cluster_free_mb);
+        if additional_mb > 0 {
+            suggestions.push(FitSuggestion {
+                kind: SuggestionKind::AddDevices,
+                message: format!(
+                    "Add ~{} GB of free memory via cluster devices to run it distributed",
+                    additional_mb.div_ceil(1024)
+                ),
+                quant: None,
+                model_size_mb,
+                additional_mb,
+                fit_status: FitStatus::FitsDistributed,
+            });
+        }
+        suggestions
+    }
+
+    /// Record the memory kept for the host OS in `analysis`, and warn when
+    /// that reservation is why the model no longer fits locally.
+    pub fn check_host_reserve(analysis: &mut ModelAnalysis, host_reserved_mb: u64) {
+        analysis.host_reserved_mb = host_reserved_mb;
+        let usable_without = usable_mb(analysis.local_free_mb + host_reserved_mb);
+        if analysis.fit_status != FitStatus::FitsLocally
+            && host_reserved_mb > 0
+            && analysis.model_size_mb <= usable_without
+        {
+            analysis.warnings.push(format!(
+                "The model would fit locally, but not with {} MB kept free for the OS (host_reserved_mb)",
+                host_reserved_mb
+            ));
+        }
+    }
+
+    /// Record the LoRA adapters in `analysis`. They load with the model, so
+    /// the fit is checked again for both together.
+    pub fn check_lora_fit(analysis: &mut ModelAnalysis, adapters: &[lora::LoraAdapter]) {
+        analysis.lora_size_mb = lora::total_mb(adapters);
+        if analysis.lora_size_mb == 0 || analysis.fit_status == FitStatus::TooLarge {
+            return;
+        }
+        let needed_mb = analysis.model_size_mb + analysis.lora_size_mb;
+        let fit_status = Self::classify_fit(needed_mb, analysis.local_free_mb, analysis.cluster_free_mb);
+        if fit_status != analysis.fit_status {
+            analysis.warnings.push(format!(
+                "With {} MB of LoRA adapters the model no longer fits as before",
+                analysis.lora_size_mb
+            ));
+            analysis.fit_status = fit_status;
+        }
+    }
+
+    /// KV cache llama-server allocates for `ctx_size` tokens of a model with
+    /// `layers` layers. `--ctx-size` is split between the `--parallel` slots,
+    /// so the cache doesn't grow with them.
+    pub fn estimate_kv_cache_mb(layers: u32, ctx_size: u32) -> u64 {
+        (layers as u64 * ctx_size as u64 * KV_BYTES_PER_TOKEN_LAYER).div_ceil(1024 * 1024)
+    }
+
+    /// Record the KV cache for `ctx_size` in `analysis`, and warn when it
+    /// won't fit next to the model or when `parallel` slots leave each one
+    /// little context.
+    pub fn check_kv_cache(analysis: &mut ModelAnalysis, ctx_size: u32, parallel: u32) {
+        analysis.kv_cache_mb = Self::estimate_kv_cache_mb(analysis.estimated_layers, ctx_size);
+        let left_mb = usable_mb(analysis.total_available_mb).saturating_sub(analysis.model_size_mb);
+        if analysis.fit_status != FitStatus::TooLarge && analysis.kv_cache_mb > left_mb {
+            analysis.warnings.push(format!(
+                "KV cache for {} tokens across {} slot(s) needs ~{} MB but only {} MB is left after the model",
+                ctx_size, parallel, analysis.kv_cache_mb, left_mb
+            ));
+        }
+        let per_slot = ctx_size / parallel.max(1);
+        if parallel > 1 && per_slot < MIN_SLOT_CTX {
+            analysis.warnings.push(format!(
+                "Each of the {} slots gets only {} tokens of context; raise ctx_size to at least {}",
+                parallel,
+                per_slot,
+                MIN_SLOT_CTX * parallel
+            ));
+        }
+    }
+
+    /// Fail with a memory error when `draft_path` can't be loaded next to
+    /// `model_path`, so llama-server isn't left to run out of memory. A main
+    /// model that is too large on its own is not this check's concern.
+    pub fn check_draft_fit(
+        model_path: &str,
+        draft_path: &str,
+        local_free_mb: u64,
+        device_free_mbs: Vec<u64>,
+    ) -> anyhow::Result<()> {
+        let with = Self::analyze_model(model_path, Some(draft_path), local_free_mb, device_free_mbs.clone())?;
+        if with.fit_status != FitStatus::TooLarge {
+            return Ok(());
+        }
+        if with.draft_size_mb > usable_mb(local_free_mb) {
+            return Err(anyhow!(
+                "Draft model needs {} MB but only {} MB is free locally",
+                with.draft_size_mb, local_free_mb
+            ));
+        }
+        let without = Self::analyze_model(model_path, None, local_free_mb, device_free_mbs)?;
+        if without.fit_status != FitStatus::TooLarge {
+            return Err(anyhow!(
+                "Draft model ({} MB) doesn't fit alongside the main model ({} MB): \
+                 only {} MB is available across the cluster",
+                with.draft_size_mb, with.model_size_mb, without.total_available_mb
+            ));
+        }
+        Ok(())
+    }
 
-    /// Find a binary in PATH or ~/.sharedmem/bin/
-    fn find_binary(name: &str) -> Option<PathBuf> {
-        // First try PATH
-        if let Ok(path) = which(name) {
-            return Some(path);
+    /// Work out which devices in `device_breakdown` likely hold their share
+    /// of the model in their RPC cache, and warn how long sending the weights
+    /// to the others will take. Shares follow free memory, as llama.cpp
+    /// splits tensors.
+    pub fn estimate_distribution(analysis: &mut ModelAnalysis) {
+        let pool_mb = analysis.local_free_mb
+            + analysis.device_breakdown.iter().map(|d| d.free_mb).sum::<u64>();
+        if pool_mb == 0 {
+            return;
         }
-        // Then try ~/.sharedmem/bin/
-        if let Ok(home) = std::env::var("HOME") {
-            let path = PathBuf::from(home).join(".sharedmem").join("bin").join(name);
-            if path.exists() {
-                return Some(path);
+        let mut to_send_mb = 0;
+        let mut uncached = Vec::new();
+        for device in &mut analysis.device_breakdown {
+            let share_mb = analysis.model_size_mb * device.free_mb / pool_mb;
+            device.cache_likely =
+                device.rpc_cache_enabled && device.rpc_cache_mb.max(0) as u64 >= share_mb;
+            if !device.cache_likely {
+                to_send_mb += share_mb;
+                uncached.push(device.name.clone());
             }
         }
-        None
+        if to_send_mb < TRANSFER_WARN_MB {
+            return;
+        }
+        let secs = to_send_mb * 8 / ASSUMED_LINK_MBITS;
+        let duration = if secs < 120 {
+            format!("{} s", secs)
+        } else {
+            format!("{} min", secs / 60)
+        };
+        let mut warning = format!(
+            "Sending ~{} MB of weights to {} takes about {} at {} Mbit/s",
+            to_send_mb,
+            uncached.join(", "),
+            duration,
+            ASSUMED_LINK_MBITS
+        );
+        if analysis.device_breakdown.iter().any(|d| !d.rpc_cache_enabled) {
+            warning.push_str(
+                "; reinstall the agent on devices without an RPC cache so weights are kept between starts",
+            );
+        }
+        analysis.warnings.push(warning);
     }
 
+    // ─── Binary discovery ─────────────────────────────────────────────────
+
     pub fn find_rpc_server_bin() -> Option<PathBuf> {
-        Self::find_binary("llama-rpc-server")
+        process::find_binary(RPC_SERVER_BIN)
     }
 
     pub fn find_inference_server_bin() -> Option<PathBuf> {
-        Self::find_binary("llama-server")
+        process::find_binary(INFERENCE_SERVER_BIN)
+    }
+
+    /// What the installed llama-server and llama-rpc-server were built with
+    /// (see [`binary::inspect`]). Kept until either binary is replaced.
+    pub async fn binary_info(&self) -> BinariesInfo {
+        let server = self.spawner.find(INFERENCE_SERVER_BIN);
+        let rpc = self.spawner.find(RPC_SERVER_BIN);
+        let stamps = (binary::stamp(server.as_deref()), binary::stamp(rpc.as_deref()));
+        if let Some((read_from, info)) = &*self.binary_info.lock().unwrap() {
+            if *read_from == stamps {
+                return info.clone();
+            }
+        }
+        let info = BinariesInfo {
+            llama_server: binary::inspect(server).await,
+            llama_rpc_server: binary::located(rpc),
+        };
+        *self.binary_info.lock().unwrap() = Some((stamps, info.clone()));
+        info
     }
 
     pub fn get_status_sync(
@@ -296,6 +1284,7 @@ impl LlamaCppManager {
             rpc_port,
             inference_port: inf_port,
             current_session: session,
+            starting: false,
         }
     }
 
@@ -313,42 +1302,98 @@ impl LlamaCppManager {
                 let _ = self.event_tx.send(WsEvent::RpcServerOffline);
             }
         }
-        if let Some(child) = state.inference_process.as_mut() {
-            if let Ok(Some(exit_status)) = child.try_wait() {
-                tracing::warn!(
-                    "llama-server exited unexpectedly (code: {:?})",
-                    exit_status.code()
-                );
-                state.inference_process = None;
-                // Clear session so the UI reflects the stopped state
-                if let Some(session) = state.current_session.take() {
-                    let _ = self.event_tx.send(WsEvent::InferenceStopped {
-                        session_id: session.id,
-                    });
-                }
-            }
-        }
+        self.reap_inference(&mut state);
 
         LlamaCppStatus {
             rpc_server_running: state.rpc_process.is_some(),
             inference_running: state.inference_process.is_some(),
-            rpc_server_bin: Self::find_rpc_server_bin().is_some(),
-            inference_server_bin: Self::find_inference_server_bin().is_some(),
+            rpc_server_bin: self.spawner.find(RPC_SERVER_BIN).is_some(),
+            inference_server_bin: self.spawner.find(INFERENCE_SERVER_BIN).is_some(),
             rpc_port: self.rpc_port,
             inference_port: self.inference_port,
             current_session: state.current_session.clone(),
+            starting: self.starting.load(Ordering::SeqCst),
         }
     }
 
+    /// Claim the start path for a start that will replace the session.
+    /// `None` while another holds it: `start_inference` kills the running
+    /// llama-server, so two overlapping starts would kill each other's.
+    pub fn begin_start(&self) -> Option<StartGuard> {
+        self.starting
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| StartGuard { starting: self.starting.clone() })
+    }
+
     // ─── Watchdog ─────────────────────────────────────────────────────────
 
+    /// If llama-server has exited on its own, clear it from `state`, move the
+    /// session to `crashed_session` for the watchdog, and notify the UI.
+    /// Returns `true` if the process was found dead.
+    fn reap_inference(&self, state: &mut LlamaCppState) -> bool {
+        let Some(child) = state.inference_process.as_mut() else {
+            return false;
+        };
+        let Ok(Some(exit_status)) = child.try_wait() else {
+            return false;
+        };
+        notify_error(
+            &self.event_tx,
+            Severity::Error,
+            "llama_cpp",
+            format!("llama-server exited unexpectedly (code: {:?})", exit_status.code()),
+        );
+        state.inference_process = None;
+        // Clear session so the UI reflects the stopped state
+        if let Some(session) = state.current_session.take() {
+            let _ = self.event_tx.send(WsEvent::InferenceStopped {
+                session_id: session.id.clone(),
+                request_id: None,
+                reason: None,
+            });
+            if Self::ran_out_of_memory(&session, state.log_mark) {
+                state.oom_session = Some(session);
+            } else {
+                state.crashed_session = Some(session);
+            }
+        }
+        true
+    }
+
+    /// Whether `session` asked for `retry_on_oom` and its llama-server, just
+    /// exited, failed an allocation while starting on the GPU.
+    fn ran_out_of_memory(session: &InferenceSessionInfo, log_mark: u64) -> bool {
+        session.retry_on_oom
+            && session.n_gpu_layers != 0
+            && health::uptime(session).is_none_or(|u| u < oom::STARTUP_WINDOW)
+            && oom::is_oom(&oom::output_since(log_mark))
+    }
+
     /// Spawn a background task that monitors the RPC and inference processes
-    /// every 5 seconds. If either dies unexpectedly, the state is cleaned up
-    /// and the appropriate WebSocket event is broadcast so the UI updates.
-    pub fn spawn_watchdog(mgr: Arc<LlamaCppManager>) {
+    /// every 5 seconds.
+    ///
+    /// - Liveness: if either process dies unexpectedly, the state is cleaned up
+    ///   and the appropriate WebSocket event is broadcast so the UI updates.
+    ///   A crashed inference session is re-launched when the
+    ///   `auto_restart_inference` setting is on.
+    /// - Readiness: a "starting" session is promoted to "running" once
+    ///   llama-server answers its /health endpoint.
+    /// - Idle timeout: a running session with no chat completion for
+    ///   `idle_timeout_minutes` is stopped.
+    /// - Device health: an early crash counts against the session's RPC
+    ///   devices, and lasting `health::LONG_SESSION` counts for them.
+    pub fn spawn_watchdog(
+        mgr: Arc<LlamaCppManager>,
+        pool: SqlitePool,
+        providers: Arc<ProviderSet>,
+        settings: Arc<SettingsCache>,
+    ) {
         tokio::spawn(async move {
             let mut interval =
                 tokio::time::interval(tokio::time::Duration::from_secs(5));
+            // Session whose devices were credited for lasting
+            let mut credited: Option<String> = None;
             loop {
                 interval.tick().await;
 
@@ -357,69 +1402,375 @@ impl LlamaCppManager {
                 // ── RPC server watchdog ────────────────────────────────────
                 if let Some(child) = state.rpc_process.as_mut() {
                     if let Ok(Some(exit_status)) = child.try_wait() {
-                        tracing::warn!(
-                            "Watchdog: llama-rpc-server exited (code: {:?}). \
-                             Port {} may have been in use.",
-                            exit_status.code(),
-                            mgr.rpc_port,
+                        notify_error(
+                            &mgr.event_tx,
+                            Severity::Error,
+                            "llama_cpp",
+                            format!(
+                                "llama-rpc-server exited (code: {:?}). Port {} may have been in use.",
+                                exit_status.code(),
+                                mgr.rpc_port,
+                            ),
                         );
                         state.rpc_process = None;
                         let _ = mgr.event_tx.send(WsEvent::RpcServerOffline);
                     }
                 }
 
-                // ── Inference server watchdog ──────────────────────────────
-                if let Some(child) = state.inference_process.as_mut() {
-                    if let Ok(Some(exit_status)) = child.try_wait() {
-                        tracing::warn!(
-                            "Watchdog: llama-server exited (code: {:?})",
-                            exit_status.code(),
-                        );
-                        state.inference_process = None;
-                        if let Some(session) = state.current_session.take() {
-                            let _ = mgr.event_tx.send(WsEvent::InferenceStopped {
-                                session_id: session.id,
-                            });
-                        }
-                    }
+                // ── Inference server liveness ──────────────────────────────
+                mgr.reap_inference(&mut state);
+                let crashed = state.crashed_session.take();
+                // Pending from here, so a manual start or stop abandons it
+                let out_of_memory = state.oom_session.take();
+                if let Some(session) = &out_of_memory {
+                    state.pending_restart = Some(session.id.clone());
                 }
-            }
-        });
-    }
 
-    // ─── Local RPC server ─────────────────────────────────────────────────
+                // ── Inference server readiness ─────────────────────────────
+                let starting_id = state
+                    .current_session
+                    .as_ref()
+                    .filter(|s| s.status == "starting")
+                    .map(|s| s.id.clone());
+                let lasting = state
+                    .current_session
+                    .as_ref()
+                    .filter(|s| s.status == "running" && credited.as_deref() != Some(s.id.as_str()))
+                    .filter(|s| health::uptime(s).is_some_and(|u| u >= health::LONG_SESSION))
+                    .cloned();
+                drop(state);
+
+                if let Some(session) = lasting {
+                    credited = Some(session.id.clone());
+                    health::session_lasted(&pool, &session).await;
+                }
 
-    /// Start the local llama-rpc-server so this host's GPU can be used by other
-    /// machines in the cluster.
-    pub async fn start_rpc_server(&self) -> Result<()> {
-        let binary = Self::find_rpc_server_bin()
+                if let Some(id) = starting_id {
+                    if mgr.inference_is_healthy().await {
+                        mgr.mark_running(&id).await;
+                    }
+                }
+
+                // ── Idle timeout ───────────────────────────────────────────
+                if let Some(limit) = idle_timeout_secs(settings.get_u64(Key::IdleTimeoutMinutes)) {
+                    mgr.stop_if_idle(limit).await;
+                }
+
+                if let Some(session) = out_of_memory {
+                    mgr.retry_after_oom(session, &oom::steps(&settings)).await;
+                }
+
+                if let Some(session) = crashed {
+                    health::session_crashed(&pool, &session).await;
+                    let mgr = mgr.clone();
+                    let pool = pool.clone();
+                    let providers = providers.load();
+                    tokio::spawn(async move {
+                        mgr.restart_crashed_session(session, &pool, &providers).await;
+                    });
+                }
+            }
+        });
+    }
+
+    /// Promote session `id` from "starting" to "running" once llama-server
+    /// answers. Returns the session if it is the current one.
+    async fn mark_running(&self, id: &str) -> Option<InferenceSessionInfo> {
+        let mut state = self.state.lock().await;
+        let session = state.current_session.as_mut().filter(|s| s.id == id)?;
+        if session.status == "starting" {
+            session.status = "running".to_string();
+            tracing::info!("llama-server is ready (session {})", id);
+        }
+        Some(session.clone())
+    }
+
+    /// Wait up to `timeout` for session `id` to come up, following its
+    /// retries after running out of memory. `Ok` with the session once
+    /// llama-server answers, `Err` with the reason once it has stopped for
+    /// good or the time is up.
+    pub async fn wait_until_ready(&self, id: &str, timeout: std::time::Duration) -> Result<InferenceSessionInfo, String> {
+        let mut events = self.event_tx.subscribe_critical();
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let (current, pending) = {
+                let mut state = self.state.lock().await;
+                self.reap_inference(&mut state);
+                let pending = state.oom_session.as_ref().is_some_and(|s| s.id == id)
+                    || state.pending_restart.as_deref() == Some(id);
+                (state.current_session.clone().filter(|s| s.id == id), pending)
+            };
+            match current {
+                Some(session) if session.status == "running" => return Ok(session),
+                Some(_) if self.inference_is_healthy().await => {
+                    if let Some(session) = self.mark_running(id).await {
+                        return Ok(session);
+                    }
+                }
+                Some(_) => {}
+                None if !pending => {
+                    while let Ok(event) = events.try_recv() {
+                        if let WsEvent::InferenceFailed { session_id, reason } = event {
+                            if session_id == id {
+                                return Err(reason);
+                            }
+                        }
+                    }
+                    return Err("llama-server exited while starting".to_string());
+                }
+                None => {}
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(format!("llama-server was not ready after {}s", timeout.as_secs()));
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Start `session` again with fewer GPU layers after llama-server ran
+    /// out of memory loading it, or give up once `steps` run out. The
+    /// watchdog has set `pending_restart` to the session.
+    async fn retry_after_oom(&self, mut session: InferenceSessionInfo, steps: &[u32]) {
+        let fallback = session.oom_fallback.clone().unwrap_or(oom::OomFallback {
+            requested_n_gpu_layers: session.n_gpu_layers,
+            attempts: 0,
+        });
+        let attempt = fallback.attempts + 1;
+        let model_layers = model_file_mb(&session.model_path).map_or(0, |(mb, _)| Self::estimate_layers(mb));
+        let Some(n_gpu_layers) = oom::retry_layers(fallback.requested_n_gpu_layers, model_layers, steps, attempt) else {
+            // Failed before the restart is dropped, so a waiting start sees why
+            self.fail_restart(
+                &session.id,
+                format!("llama-server ran out of memory loading the model, with {} GPU layers last", session.n_gpu_layers),
+            );
+            let mut state = self.state.lock().await;
+            if state.pending_restart.as_deref() == Some(session.id.as_str()) {
+                state.pending_restart = None;
+            }
+            return;
+        };
+
+        let _ = self.event_tx.send(WsEvent::InferenceRetrying {
+            session_id: session.id.clone(),
+            attempt,
+            n_gpu_layers,
+        });
+        notify_error(
+            &self.event_tx,
+            Severity::Warning,
+            "llama_cpp",
+            format!(
+                "llama-server ran out of memory; retrying session {} with {} GPU layers (attempt {}/{})",
+                session.id, n_gpu_layers, attempt, steps.len(),
+            ),
+        );
+
+        let mut state = self.state.lock().await;
+        // A manual start/stop since the exit wins over the retry
+        if state.pending_restart.as_deref() != Some(session.id.as_str())
+            || state.inference_process.is_some()
+        {
+            tracing::info!("Retry of session {} abandoned (superseded)", session.id);
+            return;
+        }
+        state.pending_restart = None;
+
+        session.n_gpu_layers = n_gpu_layers;
+        session.oom_fallback = Some(oom::OomFallback { attempts: attempt, ..fallback });
+        session.status = "starting".to_string();
+        // The startup window counts from this launch
+        session.started_at = chrono::Utc::now().to_rfc3339();
+        if let Err(e) = self.launch_inference(&mut state, session.clone()) {
+            drop(state);
+            self.fail_restart(&session.id, e.to_string());
+        }
+    }
+
+    /// Stop a running session that has had no chat completion for
+    /// `limit_secs`. Checked under the state lock: a completion registered by
+    /// `track_request` before it is taken counts as activity and keeps the
+    /// session alive.
+    async fn stop_if_idle(&self, limit_secs: u64) {
+        let mut state = self.state.lock().await;
+        if state.current_session.as_ref().is_none_or(|s| s.status != "running") {
+            return;
+        }
+        let idle = self.idle_for_secs();
+        if idle < limit_secs {
+            return;
+        }
+        if let Some(mut child) = state.inference_process.take() {
+            let _ = child.kill().await;
+        }
+        state.pending_restart = None;
+        state.crashed_session = None;
+        state.oom_session = None;
+        if let Some(session) = state.current_session.take() {
+            tracing::info!("Stopping llama-server session {}: idle for {}s", session.id, idle);
+            let _ = self.event_tx.send(WsEvent::InferenceStopped {
+                session_id: session.id,
+                request_id: None,
+                reason: Some(STOP_REASON_IDLE.to_string()),
+            });
+        }
+    }
+
+    /// Re-launch a session whose llama-server exited unexpectedly, with
+    /// exponential backoff. Remote RPC devices that are unreachable at restart
+    /// time are dropped only if the model still fits without them.
+    async fn restart_crashed_session(
+        &self,
+        mut session: InferenceSessionInfo,
+        pool: &SqlitePool,
+        providers: &[Arc<dyn MemoryProvider>],
+    ) {
+        if !crate::settings::get_bool(pool, Key::AutoRestartInference).await {
+            return;
+        }
+
+        let counted = session.restarts_counted(chrono::Utc::now());
+        if counted < session.restart_attempts {
+            tracing::info!(
+                "Session {} ran for {} minutes since its last restart; restart attempts start over",
+                session.id,
+                STABLE_RUN.num_minutes()
+            );
+            session.restart_attempts = counted;
+        }
+        if session.restart_attempts >= MAX_RESTART_ATTEMPTS {
+            self.fail_restart(
+                &session.id,
+                format!("llama-server kept crashing; gave up after {} restart attempts", MAX_RESTART_ATTEMPTS),
+            );
+            return;
+        }
+
+        session.restart_attempts += 1;
+        let attempt = session.restart_attempts;
+        self.state.lock().await.pending_restart = Some(session.id.clone());
+        let _ = self.event_tx.send(WsEvent::InferenceRestarting {
+            session_id: session.id.clone(),
+            attempt,
+        });
+
+        let delay = RESTART_BASE_DELAY_SECS << (attempt - 1);
+        notify_error(
+            &self.event_tx,
+            Severity::Warning,
+            "llama_cpp",
+            format!(
+                "Restarting llama-server session {} in {}s (attempt {}/{})",
+                session.id, delay, attempt, MAX_RESTART_ATTEMPTS,
+            ),
+        );
+        tokio::time::sleep(tokio::time::Duration::from_secs(delay)).await;
+
+        // Drop remote devices that are no longer reachable
+        let mut reachable = Vec::new();
+        let mut lost = Vec::new();
+        for addr in &session.rpc_devices {
+            let (ip, port) = match addr.rsplit_once(':') {
+                Some((ip, port)) => (ip, port.parse::<u16>().unwrap_or(self.rpc_port)),
+                None => (addr.as_str(), self.rpc_port),
+            };
+            if self.probe_rpc_device(ip, port).await {
+                reachable.push(addr.clone());
+            } else {
+                lost.push(addr.clone());
+            }
+        }
+
+        if !lost.is_empty() {
+            // Memory held by other sessions isn't free, whatever the providers say
+            let reserved = reservations::Reserved::load(pool).await;
+            let host_reserved = crate::settings::get_string(pool, Key::HostReservedMb).await;
+            let snapshots = crate::memory::local_snapshots(providers, Some(&host_reserved)).await;
+            let local_free_mb: u64 = snapshots
+                .iter()
+                .map(|s| s.usable_free_mb)
+                .sum::<u64>()
+                .saturating_sub(reserved.local_total());
+            let mut device_free_mbs = Vec::new();
+            for addr in &reachable {
+                let ip = addr.rsplit_once(':').map(|(ip, _)| ip).unwrap_or(addr);
+                if let Ok(Some(device)) = crate::db::queries::get_device_by_ip(pool, ip).await {
+                    let free = (device.memory_free_mb.max(0) as u64)
+                        .saturating_sub(reserved.device(&device.id));
+                    if free > 0 {
+                        device_free_mbs.push(free);
+                    }
+                }
+            }
+
+            let draft_path = session.draft.as_ref().map(|d| d.path.as_str());
+            match Self::analyze_model(&session.model_path, draft_path, local_free_mb, device_free_mbs) {
+                Ok(analysis) if analysis.fit_status != FitStatus::TooLarge => {
+                    tracing::warn!(
+                        "Restarting without unreachable RPC device(s): {}",
+                        lost.join(", ")
+                    );
+                    session.rpc_devices = reachable;
+                }
+                Ok(_) => {
+                    self.fail_restart(
+                        &session.id,
+                        format!(
+                            "RPC device(s) {} unreachable and the model does not fit without them",
+                            lost.join(", ")
+                        ),
+                    );
+                    return;
+                }
+                Err(e) => {
+                    self.fail_restart(&session.id, e.to_string());
+                    return;
+                }
+            }
+        }
+
+        let mut state = self.state.lock().await;
+        // A manual start/stop during the backoff wins over the restart
+        if state.pending_restart.as_deref() != Some(session.id.as_str())
+            || state.inference_process.is_some()
+        {
+            tracing::info!("Restart of session {} abandoned (superseded)", session.id);
+            return;
+        }
+        state.pending_restart = None;
+
+        session.status = "starting".to_string();
+        session.restarted_at = Some(chrono::Utc::now().to_rfc3339());
+        if let Err(e) = self.launch_inference(&mut state, session.clone()) {
+            drop(state);
+            self.fail_restart(&session.id, e.to_string());
+        }
+    }
+
+    /// Broadcast a terminal restart failure.
+    fn fail_restart(&self, session_id: &str, reason: String) {
+        notify_error(
+            &self.event_tx,
+            Severity::Critical,
+            "llama_cpp",
+            format!("Inference session {} will not be restarted: {}", session_id, reason),
+        );
+        let _ = self.event_tx.send(WsEvent::InferenceFailed {
+            session_id: session_id.to_string(),
+            reason,
+        });
+    }
+
+    // ─── Local RPC server ─────────────────────────────────────────────────
+
+    /// Start the local llama-rpc-server so this host's GPU can be used by other
+    /// machines in the cluster, handing out at most `mem_mb` when given.
+    pub async fn start_rpc_server(&self, mem_mb: Option<u64>) -> Result<()> {
+        let binary = self.spawner.find(RPC_SERVER_BIN)
             .ok_or_else(|| anyhow!(
                 "llama-rpc-server not found. Install llama.cpp and add it to your PATH, \
                  or place it in ~/.sharedmem/bin/"
             ))?;
 
-        // ── Free the port before binding ──────────────────────────────────
-        // If a previous llama-rpc-server from a crashed session is still
-        // holding the port, the new process would exit immediately. Kill it first.
-        #[cfg(unix)]
-        {
-            let port_str = self.rpc_port.to_string();
-            let _ = tokio::process::Command::new("sh")
-                .args([
-                    "-c",
-                    &format!(
-                        "lsof -ti :{port} 2>/dev/null | xargs kill -9 2>/dev/null; true",
-                        port = port_str
-                    ),
-                ])
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .output()
-                .await;
-            // Brief pause to let the OS release the port
-            tokio::time::sleep(tokio::time::Duration::from_millis(400)).await;
-        }
-
         let mut state = self.state.lock().await;
 
         if state.rpc_process.is_some() {
@@ -427,17 +1778,42 @@ impl LlamaCppManager {
             return Ok(());
         }
 
-        tracing::info!("Starting llama-rpc-server on port {}", self.rpc_port);
-        let child = Command::new(&binary)
-            .args(["--host", "0.0.0.0", "--port", &self.rpc_port.to_string()])
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?;
+        // ── Pre-flight: is the port free? ─────────────────────────────────
+        // A llama-rpc-server orphaned by a crashed backend is ours to replace;
+        // anything else holding the port is reported, never killed.
+        if let Err(in_use) = ports::ensure_free(self.rpc_port) {
+            // /proc comm is cut at 15 bytes: "llama-rpc-serve"
+            let orphan = in_use.holder.as_deref().is_some_and(|h| h.starts_with("llama-rpc-serv"));
+            let Some(pid) = in_use.pid.filter(|_| orphan) else {
+                return Err(in_use.into());
+            };
+            tracing::warn!("Killing orphaned llama-rpc-server (pid {}) holding port {}", pid, self.rpc_port);
+            let _ = process::kill_pid(pid).await;
+            // Brief pause to let the OS release the port
+            tokio::time::sleep(tokio::time::Duration::from_millis(400)).await;
+            ports::ensure_free(self.rpc_port)?;
+        }
+
+        let launch = RpcLaunch { backend: None, mem_mb };
+        tracing::info!(
+            "Starting llama-rpc-server on port {}{}",
+            self.rpc_port,
+            mem_mb.map(|mb| format!(", capped at {} MB", mb)).unwrap_or_default()
+        );
+        let (stdout, stderr) = logs::output::child_log("rpc-server");
+        let child = self.spawner.spawn(
+            &binary,
+            Command::new(&binary)
+                .args(["--host", "0.0.0.0", "--port", &self.rpc_port.to_string()])
+                .args(launch.args())
+                .stdout(stdout)
+                .stderr(stderr),
+        )?;
 
         state.rpc_process = Some(child);
 
         // ── Verify the process is still alive 700ms after spawning ────────
-        // An immediate exit usually means the port was still in use.
+        // An immediate exit usually means a bad binary or a port race.
         drop(state);
         tokio::time::sleep(tokio::time::Duration::from_millis(700)).await;
         let mut state = self.state.lock().await;
@@ -493,26 +1869,42 @@ impl LlamaCppManager {
     ///
     /// `rpc_addresses` is a list of "ip:port" strings for remote devices
     /// (e.g. ["192.168.1.10:8181"]). Pass an empty list to run locally only.
-    ///
-    /// `n_gpu_layers`: -1 = all layers on GPU, 0 = CPU only, N = N layers on GPU.
-    /// `ctx_size`: context window in tokens.
     pub async fn start_inference(
         &self,
         model_path: &str,
         rpc_addresses: Vec<String>,
-        n_gpu_layers: i32,
-        ctx_size: u32,
+        opts: LaunchOptions,
     ) -> Result<()> {
-        // Validate model path before anything else
+        // Validate model paths before anything else
         validate_model_path(model_path)?;
+        split::model_files(std::path::Path::new(model_path))?;
+        if let Some(draft) = &opts.draft {
+            validate_model_path(&draft.path)?;
+            split::model_files(std::path::Path::new(&draft.path))?;
+        }
 
-        let binary = Self::find_inference_server_bin()
+        // Fail early with a clear message; launch_inference looks it up again
+        self.spawner.find(INFERENCE_SERVER_BIN)
             .ok_or_else(|| anyhow!(
                 "llama-server not found. Install llama.cpp and add it to your PATH, \
                  or place it in ~/.sharedmem/bin/"
             ))?;
+        // A build without RPC would exit on `--rpc` with nothing but its usage
+        if !rpc_addresses.is_empty() {
+            let server = self.binary_info().await.llama_server;
+            if server.rpc == Some(false) {
+                return Err(NoRpcSupport {
+                    path: server.path.unwrap_or_else(|| INFERENCE_SERVER_BIN.to_string()),
+                    version: server.version,
+                }
+                .into());
+            }
+        }
 
         let mut state = self.state.lock().await;
+        state.pending_restart = None;
+        state.crashed_session = None;
+        state.oom_session = None;
 
         // Kill existing inference if running
         if let Some(mut child) = state.inference_process.take() {
@@ -521,26 +1913,63 @@ impl LlamaCppManager {
         if let Some(session) = state.current_session.take() {
             let _ = self.event_tx.send(WsEvent::InferenceStopped {
                 session_id: session.id,
+                request_id: current_request_id(),
+                reason: None,
             });
         }
 
-        let session_id = uuid::Uuid::new_v4().to_string();
-        let started_at = chrono::Utc::now().to_rfc3339();
+        let session = InferenceSessionInfo::new(model_path, rpc_addresses, opts);
+        self.launch_inference(&mut state, session)
+    }
 
+    /// The command `start_inference` would run for these arguments, without
+    /// starting anything.
+    pub fn plan_inference(
+        &self,
+        model_path: &str,
+        rpc_addresses: Vec<String>,
+        opts: LaunchOptions,
+    ) -> LaunchPlan {
+        let session = InferenceSessionInfo::new(model_path, rpc_addresses, opts);
+        let mut args = self.inference_args(&session);
+        // The plan is shown; the key isn't
+        if let Some(i) = args.iter().position(|a| a == "--api-key") {
+            args[i + 1] = mask_secret(&session.api_key);
+        }
+        LaunchPlan {
+            binary: self.spawner.find(INFERENCE_SERVER_BIN).map(|p| p.display().to_string()),
+            args,
+            env: session.env,
+        }
+    }
+
+    /// llama-server flags for `session`.
+    fn inference_args(&self, session: &InferenceSessionInfo) -> Vec<String> {
         let mut args = vec![
             "-m".to_string(),
-            model_path.to_string(),
+            session.model_path.clone(),
             "--port".to_string(),
             self.inference_port.to_string(),
             "--host".to_string(),
             "0.0.0.0".to_string(),
             "--ctx-size".to_string(),
-            ctx_size.to_string(),
+            session.ctx_size.to_string(),
+            "--parallel".to_string(),
+            session.parallel.to_string(),
+            // Expose Prometheus-style /metrics for the dashboard
+            "--metrics".to_string(),
         ];
+        if !session.api_key.is_empty() {
+            args.push("--api-key".to_string());
+            args.push(session.api_key.clone());
+        }
+        if session.cont_batching {
+            args.push("--cont-batching".to_string());
+        }
 
         // Map our -1 sentinel ("all layers") to a large number llama-server understands.
         // 0 means CPU-only (omit the flag to let llama-server default).
-        match n_gpu_layers {
+        match session.n_gpu_layers {
             -1 => {
                 args.push("--n-gpu-layers".to_string());
                 args.push("999".to_string()); // "all" for any model
@@ -554,47 +1983,113 @@ impl LlamaCppManager {
             }
         }
 
-        if !rpc_addresses.is_empty() {
+        if !session.rpc_devices.is_empty() {
             args.push("--rpc".to_string());
-            args.push(rpc_addresses.join(","));
+            args.push(session.rpc_devices.join(","));
+        }
+
+        if let Some(draft) = &session.draft {
+            args.push("--model-draft".to_string());
+            args.push(draft.path.clone());
+            // The draft is small and latency-bound: keep it whole on the local GPU
+            if session.n_gpu_layers != 0 {
+                args.push("--n-gpu-layers-draft".to_string());
+                args.push("999".to_string());
+            }
+            if let Some(max) = draft.max {
+                args.push("--draft-max".to_string());
+                args.push(max.to_string());
+            }
+            if let Some(min) = draft.min {
+                args.push("--draft-min".to_string());
+                args.push(min.to_string());
+            }
+        }
+
+        args.extend(lora::args(&session.lora_adapters));
+
+        if let Some(mmproj) = &session.mmproj {
+            args.push("--mmproj".to_string());
+            args.push(mmproj.clone());
+        }
+
+        if let Some(template) = &session.chat_template {
+            args.push("--chat-template".to_string());
+            args.push(template.clone());
         }
 
+        args.extend(session.gpu_selector.args());
+        args
+    }
+
+    /// Spawn llama-server for `session` and make it the current session.
+    /// The caller must hold the state lock and have cleared any previous process.
+    fn launch_inference(
+        &self,
+        state: &mut LlamaCppState,
+        session: InferenceSessionInfo,
+    ) -> Result<()> {
+        let binary = self.spawner.find(INFERENCE_SERVER_BIN)
+            .ok_or_else(|| anyhow!(
+                "llama-server not found. Install llama.cpp and add it to your PATH, \
+                 or place it in ~/.sharedmem/bin/"
+            ))?;
+
+        // Pre-flight: fail with the holder's name now instead of an exit later
+        ports::ensure_free(self.inference_port)?;
+
+        let args = self.inference_args(&session);
+
         tracing::info!(
-            "Starting llama-server: rpc=[{}] port={} n_gpu_layers={} ctx={}",
-            rpc_addresses.join(","),
+            "Starting llama-server: rpc=[{}] port={} n_gpu_layers={} ctx={} env={:?}",
+            session.rpc_devices.join(","),
             self.inference_port,
-            n_gpu_layers,
-            ctx_size,
+            session.n_gpu_layers,
+            session.ctx_size,
+            session.env,
         );
 
-        let child = Command::new(&binary)
-            .args(&args)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .spawn()?;
+        let log_mark = oom::log_mark();
+        let (stdout, stderr) = logs::output::child_log("llama-server");
+        let child = self.spawner.spawn(
+            &binary,
+            Command::new(&binary)
+                .args(&args)
+                .envs(&session.env)
+                .stdout(stdout)
+                .stderr(stderr),
+        )?;
 
-        let session = InferenceSessionInfo {
-            id: session_id.clone(),
-            model_path: model_path.to_string(),
-            status: "starting".to_string(),
-            rpc_devices: rpc_addresses.clone(),
-            started_at,
-        };
+        let _ = self.event_tx.send(WsEvent::InferenceStarted {
+            session_id: session.id.clone(),
+            model: session.model_path.clone(),
+            devices: session.rpc_devices.clone(),
+            request_id: current_request_id(),
+        });
 
         state.inference_process = Some(child);
         state.current_session = Some(session);
-
-        let _ = self.event_tx.send(WsEvent::InferenceStarted {
-            session_id,
-            model: model_path.to_string(),
-            devices: rpc_addresses,
-        });
+        state.log_mark = log_mark;
+        // A new session starts its idle time from zero
+        self.last_completion.store(chrono::Utc::now().timestamp(), Ordering::SeqCst);
 
         Ok(())
     }
 
-    pub async fn stop_inference(&self) -> Result<()> {
+    /// Stop llama-server.
+    ///
+    /// Unless `force` is set, the session is first marked "draining" — new chat
+    /// requests are refused — and in-flight proxied requests get up to
+    /// `drain_timeout` to finish before the process is killed.
+    pub async fn stop_inference(&self, force: bool, drain_timeout: std::time::Duration) -> Result<()> {
+        if !force {
+            self.drain(drain_timeout).await;
+        }
+
         let mut state = self.state.lock().await;
+        state.pending_restart = None;
+        state.crashed_session = None;
+        state.oom_session = None;
         if let Some(mut child) = state.inference_process.take() {
             let _ = child.kill().await;
             tracing::info!("llama-server stopped");
@@ -602,34 +2097,83 @@ impl LlamaCppManager {
         if let Some(session) = state.current_session.take() {
             let _ = self.event_tx.send(WsEvent::InferenceStopped {
                 session_id: session.id,
+                request_id: current_request_id(),
+                reason: None,
             });
         }
         Ok(())
     }
 
-    pub async fn is_inference_running(&self) -> bool {
-        let mut state = self.state.lock().await;
-        if let Some(child) = state.inference_process.as_mut() {
-            if let Ok(Some(exit_status)) = child.try_wait() {
+    async fn drain(&self, timeout: std::time::Duration) {
+        let session_id = {
+            let mut state = self.state.lock().await;
+            // A crash mid-drain must not trigger an auto-restart
+            state.pending_restart = None;
+            if state.inference_process.is_none() {
+                return;
+            }
+            let Some(session) = state.current_session.as_mut() else {
+                return;
+            };
+            session.status = "draining".to_string();
+            session.id.clone()
+        };
+
+        let in_flight = self.in_flight.load(Ordering::SeqCst);
+        tracing::info!("Draining llama-server ({} request(s) in flight)", in_flight);
+        let _ = self.event_tx.send(WsEvent::InferenceDraining {
+            session_id,
+            in_flight,
+        });
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            if tokio::time::Instant::now() >= deadline {
                 tracing::warn!(
-                    "llama-server exited (code: {:?})",
-                    exit_status.code()
+                    "Drain timed out with {} request(s) still in flight",
+                    self.in_flight.load(Ordering::SeqCst)
                 );
-                state.inference_process = None;
-                // Clear session and notify the UI
-                if let Some(session) = state.current_session.take() {
-                    let _ = self.event_tx.send(WsEvent::InferenceStopped {
-                        session_id: session.id,
-                    });
-                }
-                return false;
+                break;
             }
-            true
-        } else {
-            false
+            tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+        }
+    }
+
+    /// Register a proxied chat request. Returns `None` when the session is
+    /// draining and must not accept new work.
+    ///
+    /// The count is bumped before the draining check so a request racing with
+    /// `stop_inference` is either refused or waited for — never cut off.
+    pub async fn track_request(&self) -> Option<InFlightGuard> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.last_completion.store(chrono::Utc::now().timestamp(), Ordering::SeqCst);
+        let guard = InFlightGuard {
+            in_flight: self.in_flight.clone(),
+            last_completion: self.last_completion.clone(),
+        };
+        let state = self.state.lock().await;
+        match &state.current_session {
+            Some(s) if s.status == "draining" => None,
+            _ => Some(guard),
         }
     }
 
+    /// Seconds since the last chat completion started or finished; 0 while
+    /// one is in flight.
+    pub fn idle_for_secs(&self) -> u64 {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            return 0;
+        }
+        let last = self.last_completion.load(Ordering::SeqCst);
+        (chrono::Utc::now().timestamp() - last).max(0) as u64
+    }
+
+    pub async fn is_inference_running(&self) -> bool {
+        let mut state = self.state.lock().await;
+        self.reap_inference(&mut state);
+        state.inference_process.is_some()
+    }
+
     pub async fn get_current_session(&self) -> Option<InferenceSessionInfo> {
         let state = self.state.lock().await;
         state.current_session.clone()
@@ -640,10 +2184,132 @@ impl LlamaCppManager {
         format!("http://127.0.0.1:{}", self.inference_port)
     }
 
+    /// The API key llama-server was started with, while a session runs.
+    pub async fn inference_api_key(&self) -> Option<String> {
+        let state = self.state.lock().await;
+        state.current_session.as_ref().map(|s| s.api_key.clone()).filter(|k| !k.is_empty())
+    }
+
+    /// A GET of `path` on llama-server, with the session's API key.
+    async fn inference_get(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.get(format!("{}{}", self.inference_base_url(), path));
+        match self.inference_api_key().await {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    /// Scrape slot and queue metrics from the running llama-server.
+    ///
+    /// Returns `Ok(None)` when no inference session is running. Older
+    /// llama-server builds (or ones started without `--metrics`) lack the
+    /// /metrics endpoint; in that case only slot occupancy from /slots is
+    /// reported and the token counters are `None`.
+    pub async fn get_inference_metrics(&self) -> Result<Option<InferenceMetrics>> {
+        if !self.is_inference_running().await {
+            return Ok(None);
+        }
+        let (proxy_in_flight, proxy_queued) = self.admission.counts();
+
+        let (slots_total, slots_busy) = match self
+            .inference_get("/slots")
+            .await
+            .timeout(std::time::Duration::from_secs(3))
+            .send()
+            .await
+        {
+            Ok(resp) if resp.status().is_success() => {
+                let slots: serde_json::Value = resp.json().await.unwrap_or_default();
+                count_busy_slots(&slots)
+            }
+            _ => (0, 0),
+        };
+
+        let metrics_text = match self
+            .inference_get("/metrics")
+            .await
+            .timeout(std::time::Duration::from_secs(3))
+            .send()
+            .await
+        {
+            Ok(resp) if resp.status().is_success() => resp.text().await.ok(),
+            _ => None,
+        };
+
+        let Some(text) = metrics_text else {
+            if slots_total == 0 {
+                return Err(anyhow!("llama-server exposes neither /metrics nor /slots"));
+            }
+            return Ok(Some(InferenceMetrics {
+                slots_total,
+                slots_busy,
+                slots_idle: slots_total - slots_busy,
+                queue_depth: None,
+                prompt_tokens_total: None,
+                generated_tokens_total: None,
+                prompt_tokens_per_sec: None,
+                generated_tokens_per_sec: None,
+                proxy_in_flight,
+                proxy_queued,
+                proxy_lanes: self.admission.lanes(),
+                source: "slots".to_string(),
+            }));
+        };
+
+        let metric = |name: &str| prometheus_value(&text, &format!("llamacpp:{}", name));
+
+        // Prefer /slots for occupancy; fall back to the processing gauge
+        let (slots_total, slots_busy) = if slots_total > 0 {
+            (slots_total, slots_busy)
+        } else {
+            let busy = metric("requests_processing").unwrap_or(0.0) as u32;
+            (busy, busy)
+        };
+
+        Ok(Some(InferenceMetrics {
+            slots_total,
+            slots_busy,
+            slots_idle: slots_total.saturating_sub(slots_busy),
+            queue_depth: metric("requests_deferred").map(|v| v as u64),
+            prompt_tokens_total: metric("prompt_tokens_total").map(|v| v as u64),
+            generated_tokens_total: metric("tokens_predicted_total").map(|v| v as u64),
+            prompt_tokens_per_sec: metric("prompt_tokens_seconds"),
+            generated_tokens_per_sec: metric("predicted_tokens_seconds"),
+            proxy_in_flight,
+            proxy_queued,
+            proxy_lanes: self.admission.lanes(),
+            source: "metrics".to_string(),
+        }))
+    }
+
+    /// Spawn a background task that broadcasts condensed `InferenceMetrics`
+    /// events every 5 seconds while an inference session is active.
+    pub fn spawn_metrics_broadcaster(mgr: Arc<LlamaCppManager>) {
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_secs(5));
+            loop {
+                interval.tick().await;
+                let Some(session_id) = mgr.get_current_session().await.map(|s| s.id) else {
+                    continue;
+                };
+                if let Ok(Some(m)) = mgr.get_inference_metrics().await {
+                    let _ = mgr.event_tx.send(WsEvent::InferenceMetrics {
+                        session_id,
+                        slots_busy: m.slots_busy,
+                        slots_total: m.slots_total,
+                        queue_depth: m.queue_depth,
+                        generated_tokens_per_sec: m.generated_tokens_per_sec,
+                    });
+                }
+            }
+        });
+    }
+
     /// Health check — poll /health on the inference server
     pub async fn inference_is_healthy(&self) -> bool {
-        self.client
-            .get(format!("{}/health", self.inference_base_url()))
+        self.inference_get("/health")
+            .await
             .timeout(std::time::Duration::from_secs(3))
             .send()
             .await
@@ -652,14 +2318,513 @@ impl LlamaCppManager {
     }
 
     /// Check if a remote device's RPC server is reachable.
-    /// Uses a 2-second TCP connect timeout so offline devices don't block the UI.
+    /// Uses a short TCP connect timeout (`probe_timeout_ms`) so offline
+    /// devices don't block the UI.
     pub async fn probe_rpc_device(&self, ip: &str, port: u16) -> bool {
         tokio::time::timeout(
-            std::time::Duration::from_secs(2),
+            self.limits.get().probe_timeout(),
             tokio::net::TcpStream::connect(format!("{}:{}", ip, port)),
         )
         .await
         .map(|r| r.is_ok())
         .unwrap_or(false)
     }
+
+    /// Probe a remote RPC server with the RPC protocol (see [`rpc_probe`]).
+    /// An endpoint the running session is connected to only gets the TCP
+    /// probe: the server serves one client at a time.
+    pub async fn probe_rpc_deep(&self, ip: &str, port: u16) -> rpc_probe::DeepProbe {
+        let addr = format!("{}:{}", ip, port);
+        let in_session = self
+            .get_current_session()
+            .await
+            .is_some_and(|s| s.rpc_devices.contains(&addr));
+        if in_session {
+            let reachable = self.probe_rpc_device(ip, port).await;
+            return rpc_probe::DeepProbe::tcp(reachable, "in use by the running session; only its port was checked");
+        }
+        rpc_probe::probe(&addr, self.limits.get().probe_timeout()).await
+    }
+
+    /// [`probe_rpc_deep`](Self::probe_rpc_deep) when `deep`, else the TCP
+    /// probe.
+    pub async fn probe_rpc(&self, ip: &str, port: u16, deep: bool) -> rpc_probe::DeepProbe {
+        if deep {
+            return self.probe_rpc_deep(ip, port).await;
+        }
+        let reachable = self.probe_rpc_device(ip, port).await;
+        rpc_probe::DeepProbe::tcp(reachable, if reachable { "port open" } else { "no connection" })
+    }
+}
+
+// ─── Metrics parsing ──────────────────────────────────────────────────────────
+
+/// Count (total, busy) slots in a /slots response. Newer llama-server builds
+/// report `is_processing`; older ones use `state` (0 = idle, non-zero = busy).
+fn count_busy_slots(slots: &serde_json::Value) -> (u32, u32) {
+    let Some(slots) = slots.as_array() else {
+        return (0, 0);
+    };
+    let busy = slots
+        .iter()
+        .filter(|slot| {
+            slot["is_processing"]
+                .as_bool()
+                .unwrap_or_else(|| slot["state"].as_u64().unwrap_or(0) != 0)
+        })
+        .count();
+    (slots.len() as u32, busy as u32)
+}
+
+/// Read a single un-labelled sample from Prometheus text exposition format.
+fn prometheus_value(text: &str, name: &str) -> Option<f64> {
+    text.lines()
+        .filter(|l| !l.starts_with('#'))
+        .find_map(|l| {
+            let mut parts = l.split_whitespace();
+            if parts.next()? == name {
+                parts.next()?.parse().ok()
+            } else {
+                None
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_selector_maps_to_whitelisted_env_and_flags() {
+        let selector = GpuSelector::from_value(serde_json::json!({
+            "cuda_visible_devices": "1,GPU-8f2a",
+            "main_gpu": 0,
+            "split_mode": "none",
+            "metal_ndebug": true,
+        }))
+        .unwrap();
+        assert_eq!(
+            selector.env().into_iter().collect::<Vec<_>>(),
+            vec![
+                ("CUDA_VISIBLE_DEVICES".to_string(), "1,GPU-8f2a".to_string()),
+                ("GGML_METAL_NDEBUG".to_string(), "1".to_string()),
+            ]
+        );
+        assert_eq!(selector.args(), ["--main-gpu", "0", "--split-mode", "none"]);
+        assert!(GpuSelector::default().env().is_empty());
+    }
+
+    #[test]
+    fn gpu_selector_rejects_unknown_keys_and_bad_values() {
+        let parse = |v: serde_json::Value| GpuSelector::from_value(v);
+        assert!(parse(serde_json::json!({ "LD_PRELOAD": "/tmp/x.so" })).is_err());
+        assert!(parse(serde_json::json!({ "cuda_visible_devices": "1; rm -rf /" })).is_err());
+        assert!(parse(serde_json::json!({ "hip_visible_devices": "GPU-8f2a" })).is_err());
+        assert!(parse(serde_json::json!({ "main_gpu": 64 })).is_err());
+        assert!(parse(serde_json::json!({ "split_mode": "diagonal" })).is_err());
+        assert!(parse(serde_json::json!({ "hip_visible_devices": "0,1" })).is_ok());
+    }
+
+    #[test]
+    fn draft_model_is_passed_to_llama_server() {
+        let manager = LlamaCppManager::new(EventBus::default());
+        let opts = LaunchOptions {
+            n_gpu_layers: -1,
+            ctx_size: 4096,
+            parallel: 1,
+            cont_batching: false,
+            gpu_selector: GpuSelector::default(),
+            draft: Some(DraftModel { path: "/models/draft.gguf".into(), max: Some(16), min: None }),
+            mmproj: None,
+            chat_template: None,
+            system_prompt: None,
+            lora_adapters: Vec::new(),
+            retry_on_oom: false,
+            api_key: None,
+        };
+        let args = manager.plan_inference("/models/main.gguf", vec![], opts).args;
+        let draft_args = args.iter().skip_while(|a| *a != "--model-draft").collect::<Vec<_>>();
+        assert_eq!(
+            draft_args,
+            ["--model-draft", "/models/draft.gguf", "--n-gpu-layers-draft", "999", "--draft-max", "16"]
+        );
+    }
+
+    #[test]
+    fn projector_must_sit_next_to_its_model() {
+        let dir = std::env::temp_dir().join(format!("sharedllm-vision-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).display().to_string();
+        let model = path("llava-v1.6-7b.Q4_K_M.gguf");
+        let mmproj = path("mmproj-model-f16.gguf");
+        std::fs::write(&mmproj, b"GGUF").unwrap();
+        std::fs::write(path("llava-v1.6-7b.Q8_0.gguf"), b"GGUF").unwrap();
+
+        assert!(validate_mmproj(&model, &mmproj).is_ok());
+        assert!(validate_mmproj("/models/other/llava.gguf", &mmproj).is_err());
+        assert!(validate_mmproj(&model, &path("llava-v1.6-7b.Q8_0.gguf")).is_err());
+        assert!(validate_mmproj(&model, &path("mmproj-missing.gguf")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let opts = LaunchOptions {
+            n_gpu_layers: -1,
+            ctx_size: 4096,
+            parallel: 1,
+            cont_batching: false,
+            gpu_selector: GpuSelector::default(),
+            draft: None,
+            mmproj: Some(mmproj.clone()),
+            chat_template: None,
+            system_prompt: None,
+            lora_adapters: Vec::new(),
+            retry_on_oom: false,
+            api_key: None,
+        };
+        let session = InferenceSessionInfo::new(&model, vec![], opts.clone());
+        assert!(session.supports_vision);
+        let args = LlamaCppManager::new(EventBus::default()).plan_inference(&model, vec![], opts).args;
+        assert!(args.windows(2).any(|w| w == ["--mmproj", mmproj.as_str()]));
+    }
+
+    #[test]
+    fn chat_template_must_be_built_in() {
+        assert_eq!(validate_chat_template(" ChatML ").unwrap(), "chatml");
+        assert!(validate_chat_template("/tmp/template.jinja").is_err());
+        assert!(validate_chat_template("").is_err());
+        assert_eq!(validate_system_prompt("  \n ").unwrap(), None);
+        assert_eq!(validate_system_prompt(" Be brief. ").unwrap().as_deref(), Some("Be brief."));
+        assert!(validate_system_prompt(&"x".repeat(MAX_SYSTEM_PROMPT_LEN + 1)).is_err());
+
+        let opts = LaunchOptions {
+            n_gpu_layers: 0,
+            ctx_size: 4096,
+            parallel: 1,
+            cont_batching: false,
+            gpu_selector: GpuSelector::default(),
+            draft: None,
+            mmproj: None,
+            chat_template: Some("llama3".into()),
+            system_prompt: Some("Be brief.".into()),
+            lora_adapters: Vec::new(),
+            retry_on_oom: false,
+            api_key: None,
+        };
+        let args = LlamaCppManager::new(EventBus::default()).plan_inference("/models/m.gguf", vec![], opts).args;
+        assert!(args.windows(2).any(|w| w == ["--chat-template", "llama3"]));
+    }
+
+    #[test]
+    fn uncached_devices_get_a_distribution_estimate() {
+        let device = |name: &str, cache: Option<i64>| DeviceMemory {
+            device_id: name.into(),
+            name: name.into(),
+            free_mb: 8000,
+            live: true,
+            stale: false,
+            rpc_cache_enabled: cache.is_some(),
+            rpc_cache_mb: cache.unwrap_or(0),
+            cache_likely: false,
+            manual_override: false,
+        };
+        let mut analysis = ModelAnalysis {
+            model_size_mb: 12000,
+            shard_count: 1,
+            draft_size_mb: 0,
+            estimated_layers: 40,
+            local_free_mb: 8000,
+            host_reserved_mb: 0,
+            cluster_free_mb: 16000,
+            total_available_mb: 24000,
+            fit_status: FitStatus::FitsDistributed,
+            recommended_n_gpu_layers: -1,
+            recommended_ctx_size: 4096,
+            warnings: Vec::new(),
+            device_breakdown: vec![device("cached", Some(5000)), device("fresh", None)],
+            kv_cache_mb: 0,
+            suggestions: Vec::new(),
+            lora_size_mb: 0,
+        };
+        LlamaCppManager::estimate_distribution(&mut analysis);
+
+        // Each device takes a third: 4000 MB
+        assert!(analysis.device_breakdown[0].cache_likely);
+        assert!(!analysis.device_breakdown[1].cache_likely);
+        assert_eq!(analysis.warnings.len(), 1);
+        assert!(analysis.warnings[0].starts_with("Sending ~4000 MB of weights to fresh takes about 5 min"));
+        assert!(analysis.warnings[0].contains("reinstall the agent"));
+    }
+
+    #[test]
+    fn host_reserve_is_named_when_it_blocks_a_local_fit() {
+        let analysis = |model_size_mb, fit_status| ModelAnalysis {
+            model_size_mb,
+            shard_count: 1,
+            draft_size_mb: 0,
+            estimated_layers: 32,
+            local_free_mb: 6000,
+            host_reserved_mb: 0,
+            cluster_free_mb: 0,
+            total_available_mb: 6000,
+            fit_status,
+            recommended_n_gpu_layers: -1,
+            recommended_ctx_size: 4096,
+            warnings: Vec::new(),
+            device_breakdown: Vec::new(),
+            kv_cache_mb: 0,
+            suggestions: Vec::new(),
+            lora_size_mb: 0,
+        };
+
+        // 6000 + 2048 MB less 10% headroom holds 7000 MB, 6000 doesn't
+        let mut blocked = analysis(7000, FitStatus::TooLarge);
+        LlamaCppManager::check_host_reserve(&mut blocked, 2048);
+        assert_eq!(blocked.host_reserved_mb, 2048);
+        assert_eq!(blocked.warnings.len(), 1);
+        assert!(blocked.warnings[0].contains("2048 MB kept free for the OS"));
+
+        let mut fits = analysis(4000, FitStatus::FitsLocally);
+        LlamaCppManager::check_host_reserve(&mut fits, 2048);
+        let mut too_large = analysis(9000, FitStatus::TooLarge);
+        LlamaCppManager::check_host_reserve(&mut too_large, 2048);
+        assert!(fits.warnings.is_empty() && too_large.warnings.is_empty());
+    }
+
+    #[test]
+    fn kv_cache_is_checked_against_what_the_model_leaves() {
+        // 32 layers x 4096 tokens x 128 KiB per token-layer group = 512 MB
+        assert_eq!(LlamaCppManager::estimate_kv_cache_mb(32, 4096), 512);
+
+        let analysis = |total_available_mb| ModelAnalysis {
+            model_size_mb: 4500,
+            shard_count: 1,
+            draft_size_mb: 0,
+            estimated_layers: 32,
+            local_free_mb: total_available_mb,
+            host_reserved_mb: 0,
+            cluster_free_mb: 0,
+            total_available_mb,
+            fit_status: FitStatus::FitsLocally,
+            recommended_n_gpu_layers: -1,
+            recommended_ctx_size: 4096,
+            warnings: Vec::new(),
+            device_breakdown: Vec::new(),
+            kv_cache_mb: 0,
+            suggestions: Vec::new(),
+            lora_size_mb: 0,
+        };
+        let mut roomy = analysis(16000);
+        LlamaCppManager::check_kv_cache(&mut roomy, 16384, 4);
+        assert_eq!(roomy.kv_cache_mb, 2048);
+        assert!(roomy.warnings.is_empty());
+
+        let mut tight = analysis(6000);
+        LlamaCppManager::check_kv_cache(&mut tight, 16384, 4);
+        assert_eq!(tight.warnings.len(), 1);
+        assert!(tight.warnings[0].starts_with("KV cache for 16384 tokens across 4 slot(s) needs ~2048 MB"));
+
+        let mut starved = analysis(16000);
+        LlamaCppManager::check_kv_cache(&mut starved, 4096, 8);
+        assert_eq!(starved.warnings, ["Each of the 8 slots gets only 512 tokens of context; raise ctx_size to at least 8192"]);
+    }
+
+    #[test]
+    fn cont_batching_is_passed_when_asked_for() {
+        let manager = LlamaCppManager::new(EventBus::default());
+        let opts = |cont_batching| LaunchOptions {
+            n_gpu_layers: 0,
+            ctx_size: 8192,
+            parallel: 4,
+            cont_batching,
+            gpu_selector: GpuSelector::default(),
+            draft: None,
+            mmproj: None,
+            chat_template: None,
+            system_prompt: None,
+            lora_adapters: Vec::new(),
+            retry_on_oom: false,
+            api_key: None,
+        };
+        let args = manager.plan_inference("/models/m.gguf", vec![], opts(true)).args;
+        assert!(args.windows(2).any(|w| w == ["--parallel", "4"]));
+        assert!(args.contains(&"--cont-batching".to_string()));
+        let args = manager.plan_inference("/models/m.gguf", vec![], opts(false)).args;
+        assert!(!args.contains(&"--cont-batching".to_string()));
+    }
+
+    #[tokio::test]
+    async fn restart_attempts_start_over_after_a_stable_run() {
+        let pool = crate::settings::tests::test_pool().await;
+        crate::db::queries::set_setting(&pool, "auto_restart_inference", "true").await.unwrap();
+        let events = EventBus::default();
+        let mut rx = events.subscribe_critical();
+        let manager = LlamaCppManager::new(events);
+        let opts = LaunchOptions {
+            n_gpu_layers: 0,
+            ctx_size: 8192,
+            parallel: 1,
+            cont_batching: false,
+            gpu_selector: GpuSelector::default(),
+            draft: None,
+            mmproj: None,
+            chat_template: None,
+            system_prompt: None,
+            lora_adapters: Vec::new(),
+            retry_on_oom: false,
+            api_key: None,
+        };
+        let mut session = InferenceSessionInfo::new("/models/m.gguf", vec![], opts);
+        let now = chrono::Utc::now();
+        assert_eq!(session.restarts_counted(now), 0);
+        session.restart_attempts = MAX_RESTART_ATTEMPTS;
+        let next = |rx: &mut tokio::sync::broadcast::Receiver<WsEvent>| loop {
+            match rx.try_recv() {
+                Ok(e @ (WsEvent::InferenceFailed { .. } | WsEvent::InferenceRestarting { .. })) => break e,
+                Ok(_) => {}
+                Err(e) => panic!("no restart event: {:?}", e),
+            }
+        };
+
+        // Crashing again soon after the last restart: out of attempts
+        session.restarted_at = Some((now - chrono::TimeDelta::minutes(2)).to_rfc3339());
+        assert_eq!(session.restarts_counted(now), MAX_RESTART_ATTEMPTS);
+        manager.restart_crashed_session(session.clone(), &pool, &[]).await;
+        assert!(matches!(next(&mut rx), WsEvent::InferenceFailed { reason, .. } if reason.contains("gave up")));
+
+        // After a long healthy run the crash is a first one, with the shortest backoff
+        session.restarted_at = Some((now - STABLE_RUN).to_rfc3339());
+        assert_eq!(session.restarts_counted(now), 0);
+        manager.restart_crashed_session(session, &pool, &[]).await;
+        assert!(matches!(next(&mut rx), WsEvent::InferenceRestarting { attempt: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn idle_session_is_stopped_unless_a_completion_resets_the_timer() {
+        let events = EventBus::default();
+        let mut rx = events.subscribe_critical();
+        let manager = LlamaCppManager::new(events);
+        let opts = LaunchOptions {
+            n_gpu_layers: 0,
+            ctx_size: 8192,
+            parallel: 1,
+            cont_batching: false,
+            gpu_selector: GpuSelector::default(),
+            draft: None,
+            mmproj: None,
+            chat_template: None,
+            system_prompt: None,
+            lora_adapters: Vec::new(),
+            retry_on_oom: false,
+            api_key: None,
+        };
+        let mut session = InferenceSessionInfo::new("/models/m.gguf", vec![], opts);
+        session.status = "running".to_string();
+        manager.state.lock().await.current_session = Some(session);
+        let an_hour_ago = chrono::Utc::now().timestamp() - 3600;
+        manager.last_completion.store(an_hour_ago, Ordering::SeqCst);
+        assert!(manager.idle_for_secs() >= 3600);
+
+        // A completion in flight is never idle, and finishing one restarts the count
+        let guard = manager.track_request().await.unwrap();
+        manager.stop_if_idle(60).await;
+        assert_eq!(manager.idle_for_secs(), 0);
+        drop(guard);
+        manager.stop_if_idle(60).await;
+        assert!(manager.get_current_session().await.is_some());
+
+        manager.last_completion.store(an_hour_ago, Ordering::SeqCst);
+        manager.stop_if_idle(60).await;
+        assert!(manager.get_current_session().await.is_none());
+        match rx.try_recv() {
+            Ok(WsEvent::InferenceStopped { reason, .. }) => {
+                assert_eq!(reason.as_deref(), Some(STOP_REASON_IDLE))
+            }
+            other => panic!("expected InferenceStopped, got {:?}", other),
+        }
+        assert_eq!(idle_timeout_secs(0), None);
+        assert_eq!(idle_timeout_secs(30), Some(1800));
+    }
+
+    #[tokio::test]
+    async fn out_of_memory_starts_are_retried_with_fewer_layers() {
+        let events = EventBus::default();
+        let mut rx = events.subscribe_critical();
+        let spawner = Arc::new(crate::testing::StubSpawner::default());
+        let mut manager = LlamaCppManager::with_spawner(events, spawner.clone());
+        manager.inference_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let model = std::env::temp_dir().join(format!("oom-{}.gguf", uuid::Uuid::new_v4()));
+        std::fs::write(&model, vec![0u8; 1024 * 1024]).unwrap();
+        let model = model.display().to_string();
+        let opts = LaunchOptions {
+            n_gpu_layers: 40,
+            ctx_size: 4096,
+            parallel: 1,
+            cont_batching: false,
+            gpu_selector: GpuSelector::default(),
+            draft: None,
+            mmproj: None,
+            chat_template: None,
+            system_prompt: None,
+            lora_adapters: Vec::new(),
+            retry_on_oom: true,
+            api_key: None,
+        };
+        let session = InferenceSessionInfo::new(&model, vec![], opts);
+        let steps = [75, 50, 0];
+
+        manager.state.lock().await.pending_restart = Some(session.id.clone());
+        manager.retry_after_oom(session.clone(), &steps).await;
+        assert!(matches!(rx.try_recv(), Ok(WsEvent::InferenceRetrying { attempt: 1, n_gpu_layers: 30, .. })));
+        let retried = manager.get_current_session().await.unwrap();
+        assert_eq!((retried.id.as_str(), retried.n_gpu_layers), (session.id.as_str(), 30));
+        assert_eq!(retried.oom_fallback, Some(oom::OomFallback { requested_n_gpu_layers: 40, attempts: 1 }));
+        let line = spawner.started().pop().unwrap();
+        assert!(line.windows(2).any(|w| w == ["--n-gpu-layers", "30"]));
+
+        // Once the steps run out the session fails
+        let mut last = retried;
+        last.oom_fallback = Some(oom::OomFallback { requested_n_gpu_layers: 40, attempts: 3 });
+        manager.state.lock().await.inference_process = None;
+        manager.state.lock().await.pending_restart = Some(last.id.clone());
+        while rx.try_recv().is_ok() {}
+        manager.retry_after_oom(last, &steps).await;
+        assert!(matches!(rx.try_recv(), Ok(WsEvent::Error { .. })));
+        assert!(matches!(rx.try_recv(), Ok(WsEvent::InferenceFailed { .. })));
+        assert!(manager.state.lock().await.pending_restart.is_none());
+        std::fs::remove_file(&model).unwrap();
+    }
+
+    #[test]
+    fn too_large_models_get_quantization_and_cluster_suggestions() {
+        use FitStatus::*;
+        // (file, size MB, local free, cluster free, Q4_K_M size and fit, cluster MB to add)
+        type Scenario = (&'static str, u64, u64, u64, Option<(u64, FitStatus)>, u64);
+        let scenarios: &[Scenario] = &[
+            ("/m/llama-3-70b-instruct.Q8_0.gguf", 71000, 24000, 24000, Some((40512, FitsDistributed)), 30889),
+            ("/m/mistral-7b-f16.gguf", 13500, 8000, 0, Some((4092, FitsLocally)), 7000),
+            ("/m/llama-3-70b.Q8_0.gguf", 70000, 8000, 0, Some((39941, TooLarge)), 69778),
+            // Already at or below Q4_K_M, or no quantization in the name
+            ("/m/qwen2-72b-q4_k_m-00001-of-00003.gguf", 40000, 8000, 8000, None, 28445),
+            ("/m/llama-3-70b.Q3_K_S.gguf", 30000, 8000, 4000, None, 21334),
+            ("/m/model.gguf", 20000, 8000, 4000, None, 10223),
+        ];
+        for (file, size, local, cluster, quant, add) in scenarios {
+            let suggestions = LlamaCppManager::fit_suggestions(file, *size, *local, *cluster);
+            let quantize = suggestions.iter().find(|s| s.kind == SuggestionKind::Quantize);
+            assert_eq!(
+                quantize.map(|s| (s.model_size_mb, s.fit_status.clone())),
+                *quant,
+                "{}",
+                file
+            );
+            let more = suggestions.iter().find(|s| s.kind == SuggestionKind::AddDevices).unwrap();
+            assert_eq!(more.additional_mb, *add, "{}", file);
+            // With that much more on the cluster, the model fits distributed
+            assert_eq!(LlamaCppManager::classify_fit(*size, *local, cluster + add), FitsDistributed, "{}", file);
+        }
+        assert_eq!(quant_from_name("/m/llama-2-13b-chat.Q4_K_S.gguf"), Some(("Q4_K_S", 4.58)));
+        // Nothing to add when the memory is already there
+        assert!(!LlamaCppManager::fit_suggestions("/m/m.Q8_0.gguf", 4000, 8000, 0)
+            .iter()
+            .any(|s| s.kind == SuggestionKind::AddDevices));
+    }
 }
@@ -0,0 +1,137 @@
+//! Falling back to fewer GPU layers when llama-server runs out of memory
+//! while loading. A session started with `retry_on_oom` whose llama-server
+//! exits within [`STARTUP_WINDOW`] with an allocation failure in its output
+//! is started again with a share of the layers first asked for, one step of
+//! `oom_retry_layers_pct` per retry; the last step is usually CPU only.
+
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::logs::output;
+use crate::settings::SettingsCache;
+
+pub const RETRY_STEPS_KEY: &str = "oom_retry_layers_pct";
+pub const DEFAULT_RETRY_STEPS: &str = "75,50,0";
+/// Most retries of one start.
+pub const MAX_RETRIES: usize = 3;
+/// An exit this soon after launch counts as a failure to load.
+pub const STARTUP_WINDOW: Duration = Duration::from_secs(2 * 60);
+/// What llama.cpp and the GPU runtimes print when an allocation fails,
+/// lowercased.
+const OOM_PATTERNS: &[&str] = &[
+    "out of memory",
+    "cudamalloc failed",
+    "hipmalloc failed",
+    "erroroutofdevicememory",
+    "failed to allocate",
+    "unable to allocate",
+    "insufficient memory",
+];
+/// Output searched for them, from the end.
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// What a session fell back from, kept in its info.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+pub struct OomFallback {
+    /// `n_gpu_layers` of the start as asked for (-1 = all)
+    pub requested_n_gpu_layers: i32,
+    /// Retries made with fewer layers
+    pub attempts: u32,
+}
+
+/// Percentages of the GPU layers to retry with: up to [`MAX_RETRIES`]
+/// comma-separated values below 100, each smaller than the one before.
+pub fn parse_steps(value: &str) -> Result<Vec<u32>, String> {
+    let err = || {
+        format!(
+            "{} must be up to {} decreasing percentages below 100, e.g. {}",
+            RETRY_STEPS_KEY, MAX_RETRIES, DEFAULT_RETRY_STEPS
+        )
+    };
+    let steps: Vec<u32> = value
+        .split(',')
+        .map(|s| s.trim().parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| err())?;
+    if steps.len() > MAX_RETRIES || steps.iter().any(|&s| s >= 100) || steps.windows(2).any(|w| w[1] >= w[0]) {
+        return Err(err());
+    }
+    Ok(steps)
+}
+
+/// The configured steps, or the defaults.
+pub fn steps(settings: &SettingsCache) -> Vec<u32> {
+    settings
+        .get(RETRY_STEPS_KEY)
+        .and_then(|v| parse_steps(&v).ok())
+        .unwrap_or_else(|| parse_steps(DEFAULT_RETRY_STEPS).unwrap_or_default())
+}
+
+/// `n_gpu_layers` for retry `attempt` (from 1) of a start that asked for
+/// `requested` layers of a model of about `model_layers`. `None` once the
+/// steps run out, or when the start was CPU only to begin with.
+pub fn retry_layers(requested: i32, model_layers: u32, steps: &[u32], attempt: u32) -> Option<i32> {
+    let step = *steps.get(attempt.checked_sub(1)? as usize)?;
+    let full = match requested {
+        0 => return None,
+        n if n < 0 => model_layers,
+        n => n as u32,
+    };
+    Some((full * step / 100) as i32)
+}
+
+/// Whether `output` shows an allocation failure.
+pub fn is_oom(output: &str) -> bool {
+    let output = output.to_ascii_lowercase();
+    OOM_PATTERNS.iter().any(|p| output.contains(p))
+}
+
+fn log_path() -> Option<PathBuf> {
+    output::child_log_dir().map(|d| d.join(format!("{}.log", super::INFERENCE_SERVER_BIN)))
+}
+
+/// Length of llama-server's output file, where a launch's output starts.
+pub fn log_mark() -> u64 {
+    log_path().and_then(|p| std::fs::metadata(p).ok()).map_or(0, |m| m.len())
+}
+
+/// llama-server's output since `mark`, at most the last [`TAIL_BYTES`].
+/// From the start of the file if it was truncated since.
+pub fn output_since(mark: u64) -> String {
+    let read = || -> std::io::Result<String> {
+        let mut file = std::fs::File::open(log_path().ok_or_else(|| std::io::Error::other("no log"))?)?;
+        let len = file.metadata()?.len();
+        let start = if mark > len { 0 } else { mark }.max(len.saturating_sub(TAIL_BYTES));
+        file.seek(SeekFrom::Start(start))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    };
+    read().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocation_failures_step_the_layers_down() {
+        assert!(is_oom("ggml_backend_cuda_buffer_type_alloc_buffer: allocating 9000 MiB on device 0: cudaMalloc failed: out of memory"));
+        assert!(is_oom("llama_model_load: error loading model: ErrorOutOfDeviceMemory"));
+        assert!(!is_oom("llama_model_load: error loading model: invalid magic"));
+
+        let steps = parse_steps(DEFAULT_RETRY_STEPS).unwrap();
+        assert_eq!(steps, [75, 50, 0]);
+        for bad in ["", "75,80", "100", "75,50,25,0", "half"] {
+            assert!(parse_steps(bad).is_err(), "{}", bad);
+        }
+
+        // All layers of a ~40-layer model: 30, 20, then CPU only
+        let tried: Vec<_> = (1..=4).map(|n| retry_layers(-1, 40, &steps, n)).collect();
+        assert_eq!(tried, [Some(30), Some(20), Some(0), None]);
+        assert_eq!(retry_layers(16, 40, &steps, 1), Some(12));
+        assert_eq!(retry_layers(0, 40, &steps, 1), None);
+    }
+}
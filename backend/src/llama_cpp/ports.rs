@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::net::{Ipv4Addr, TcpListener};
+
+/// A server port is taken by some other process.
+#[derive(Debug, Clone, Serialize)]
+pub struct PortInUse {
+    pub port: u16,
+    /// `"name (pid N)"` when the holder could be identified
+    pub holder: Option<String>,
+    #[serde(skip)]
+    pub pid: Option<u32>,
+}
+
+impl std::fmt::Display for PortInUse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.holder {
+            Some(holder) => write!(f, "Port {} is already in use by {}", self.port, holder),
+            None => write!(f, "Port {} is already in use", self.port),
+        }
+    }
+}
+
+impl std::error::Error for PortInUse {}
+
+/// Whether `port` can be bound on all interfaces, the way llama.cpp binds it.
+/// The probe socket is closed again before returning.
+pub fn is_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok()
+}
+
+/// Pre-flight check before spawning a server on `port`.
+pub fn ensure_free(port: u16) -> Result<(), PortInUse> {
+    if is_free(port) {
+        return Ok(());
+    }
+    Err(in_use(port))
+}
+
+/// Describe `port` as taken, naming its holder where that can be found.
+pub fn in_use(port: u16) -> PortInUse {
+    let holder = find_holder(port);
+    PortInUse {
+        port,
+        holder: holder.as_ref().map(|(pid, name)| format!("{} (pid {})", name, pid)),
+        pid: holder.map(|(pid, _)| pid),
+    }
+}
+
+/// `(pid, command name)` of the process listening on `port`.
+///
+/// Linux only: the listening socket's inode comes from /proc/net/tcp{,6} and
+/// is matched against every readable /proc/<pid>/fd. Processes of other users
+/// are not readable without privileges, in which case the holder is unknown.
+#[cfg(target_os = "linux")]
+pub fn find_holder(port: u16) -> Option<(u32, String)> {
+    let inodes: Vec<String> = ["/proc/net/tcp", "/proc/net/tcp6"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|table| listening_inodes(&table, port))
+        .collect();
+    if inodes.is_empty() {
+        return None;
+    }
+    let targets: Vec<String> = inodes.iter().map(|i| format!("socket:[{}]", i)).collect();
+
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        let holds = fds.flatten().any(|fd| {
+            std::fs::read_link(fd.path())
+                .map(|link| targets.iter().any(|t| link.as_os_str() == t.as_str()))
+                .unwrap_or(false)
+        });
+        if holds {
+            let name = std::fs::read_to_string(entry.path().join("comm"))
+                .map(|c| c.trim().to_string())
+                .unwrap_or_else(|_| "unknown".to_string());
+            return Some((pid, name));
+        }
+    }
+    None
+}
+
+/// `(pid, process name)` of the process listening on `port`, from the TCP
+/// table (`GetExtendedTcpTable`).
+#[cfg(windows)]
+pub fn find_holder(port: u16) -> Option<(u32, String)> {
+    let pid = crate::process::listening_pid(port)?;
+    let mut sys = sysinfo::System::new();
+    let pid_key = sysinfo::Pid::from_u32(pid);
+    sys.refresh_process(pid_key);
+    let name = sys
+        .process(pid_key)
+        .map(|p| p.name().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    Some((pid, name))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn find_holder(_port: u16) -> Option<(u32, String)> {
+    None
+}
+
+/// Inodes of LISTEN sockets on `port` in a /proc/net/tcp-format table.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn listening_inodes(table: &str, port: u16) -> Vec<String> {
+    const TCP_LISTEN: &str = "0A";
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            let local_port = cols.get(1)?.rsplit_once(':')?.1;
+            let listening = u16::from_str_radix(local_port, 16).ok()? == port
+                && *cols.get(3)? == TCP_LISTEN;
+            listening.then(|| cols.get(9).map(|s| s.to_string()))?
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listening_sockets_are_found_by_port() {
+        let table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:205A 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 41234 1 0000000000000000 100 0 0 10 0
+   1: 0100007F:205A 0100007F:A1B2 01 00000000:00000000 00:00000000 00000000  1000        0 41299 1 0000000000000000 20 4 30 10 -1
+   2: 00000000:1FF5 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 41777 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(listening_inodes(table, 8282), vec!["41234"]);
+        assert_eq!(listening_inodes(table, 8181), vec!["41777"]);
+        assert!(listening_inodes(table, 8080).is_empty());
+    }
+
+    #[test]
+    fn bound_port_is_reported_with_its_holder() {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let err = ensure_free(port).unwrap_err();
+        assert_eq!(err.port, port);
+        #[cfg(any(target_os = "linux", windows))]
+        assert_eq!(err.pid, Some(std::process::id()));
+
+        drop(listener);
+        assert!(ensure_free(port).is_ok());
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+use super::{InferenceSessionInfo, LlamaCppManager};
+use crate::db::{models::Allocation, queries};
+use crate::memory::{sampler::MemorySampler, GpuKind, MemorySnapshot};
+use crate::ws::{EventBus, WsEvent};
+
+/// `provider` recorded for the share of a session held by a remote RPC device.
+const RPC_PROVIDER: &str = "rpc";
+
+/// Approximate f16 KV cache per token per layer, in KB.
+const KV_KB_PER_TOKEN_LAYER: u64 = 16;
+
+/// Memory currently held by inference sessions (`purpose = 'inference'`
+/// allocations that have not been released).
+#[derive(Debug, Default)]
+pub struct Reserved {
+    /// Local provider id → MB
+    pub local: HashMap<String, u64>,
+    /// Device id → MB
+    pub devices: HashMap<String, u64>,
+}
+
+impl Reserved {
+    pub async fn load(pool: &SqlitePool) -> Self {
+        let mut reserved = Reserved::default();
+        for a in queries::list_active_reservations(pool).await.unwrap_or_default() {
+            let mb = a.memory_mb.max(0) as u64;
+            match a.device_id {
+                Some(id) => *reserved.devices.entry(id).or_default() += mb,
+                None => *reserved.local.entry(a.provider).or_default() += mb,
+            }
+        }
+        reserved
+    }
+
+    pub fn local_total(&self) -> u64 {
+        self.local.values().sum()
+    }
+
+    pub fn device(&self, device_id: &str) -> u64 {
+        self.devices.get(device_id).copied().unwrap_or(0)
+    }
+}
+
+/// Estimated memory a session needs: the model's files plus its KV cache.
+pub fn session_memory_mb(model_path: &str, ctx_size: u32) -> u64 {
+    let model_size_mb = super::split::model_files(std::path::Path::new(model_path))
+        .map(|files| super::split::total_mb(&files))
+        .unwrap_or(0);
+    let layers = LlamaCppManager::estimate_layers(model_size_mb) as u64;
+    let kv_mb = ctx_size as u64 * layers * KV_KB_PER_TOKEN_LAYER / 1024;
+    model_size_mb + kv_mb
+}
+
+/// Split `total_mb` across local providers and remote devices in proportion to
+/// their free memory, the way llama.cpp spreads layers. Locally, GPU providers
+/// take the share when there are any; system RAM only backs CPU-only hosts.
+/// Returns `(device_id, provider, mb)`; `device_id` is `None` for local shares.
+fn split(
+    total_mb: u64,
+    local: &[MemorySnapshot],
+    devices: &[(String, u64)],
+) -> Vec<(Option<String>, String, u64)> {
+    let has_gpu = local
+        .iter()
+        .any(|s| s.kind != GpuKind::SystemRam && s.free_mb > 0);
+    let mut targets: Vec<(Option<String>, String, u64)> = local
+        .iter()
+        .filter(|s| s.free_mb > 0 && (s.kind != GpuKind::SystemRam) == has_gpu)
+        .map(|s| (None, s.provider_id.clone(), s.free_mb))
+        .chain(
+            devices
+                .iter()
+                .filter(|(_, free)| *free > 0)
+                .map(|(id, free)| (Some(id.clone()), RPC_PROVIDER.to_string(), *free)),
+        )
+        .collect();
+
+    let free_total: u64 = targets.iter().map(|t| t.2).sum();
+    if free_total == 0 || total_mb == 0 {
+        return Vec::new();
+    }
+
+    let mut remaining = total_mb;
+    let last = targets.len() - 1;
+    for (i, target) in targets.iter_mut().enumerate() {
+        // The last target takes the rounding remainder
+        let share = if i == last {
+            remaining
+        } else {
+            total_mb * target.2 / free_total
+        };
+        target.2 = share;
+        remaining -= share;
+    }
+    targets.retain(|t| t.2 > 0);
+    targets
+}
+
+/// Reserve memory for `session` against the local providers in `local` and the
+/// RPC devices it uses. Idempotent: reserving the same session again replaces
+/// its previous figures. Returns the total reserved, in MB.
+pub async fn reserve(
+    pool: &SqlitePool,
+    local: &[MemorySnapshot],
+    session: &InferenceSessionInfo,
+) -> Result<u64> {
+    let mut devices = Vec::new();
+    for addr in &session.rpc_devices {
+        let ip = addr.rsplit_once(':').map(|(ip, _)| ip).unwrap_or(addr);
+        if let Some(device) = queries::get_device_by_ip(pool, ip).await? {
+            devices.push((device.id, device.memory_free_mb.max(0) as u64));
+        }
+    }
+
+    let total_mb = session_memory_mb(&session.model_path, session.ctx_size);
+    let now = chrono::Utc::now().to_rfc3339();
+    // A re-reservation may land on fewer targets; drop the old split first
+    queries::release_reservations(pool, Some(&session.id)).await?;
+    for (device_id, provider, mb) in split(total_mb, local, &devices) {
+        let target = device_id.as_deref().unwrap_or(&provider);
+        queries::upsert_reservation(
+            pool,
+            &Allocation {
+                id: format!("inference-{}-{}", session.id, target),
+                device_id,
+                memory_mb: mb as i64,
+                provider,
+                purpose: "inference".into(),
+                session_id: Some(session.id.clone()),
+                granted_at: now.clone(),
+                revoked_at: None,
+            },
+        )
+        .await?;
+    }
+    tracing::info!("Reserved {} MB for inference session {}", total_mb, session.id);
+    Ok(total_mb)
+}
+
+/// Release everything held by `session_id`.
+pub async fn release(pool: &SqlitePool, session_id: &str) {
+    match queries::release_reservations(pool, Some(session_id)).await {
+        Ok(0) => {}
+        Ok(_) => tracing::info!("Released reservations of inference session {}", session_id),
+        Err(e) => tracing::warn!("Failed to release reservations of {}: {}", session_id, e),
+    }
+}
+
+/// No session survives a backend restart; release whatever a crash left behind.
+pub async fn release_stale(pool: &SqlitePool) -> Result<u64> {
+    queries::release_reservations(pool, None).await
+}
+
+/// Keep reservations in step with the session lifecycle: reserve when
+/// llama-server launches (including watchdog restarts), release when it stops,
+/// crashes or fails for good.
+pub fn spawn(
+    pool: SqlitePool,
+    memory: Arc<MemorySampler>,
+    llama_cpp: Arc<LlamaCppManager>,
+    event_tx: &EventBus,
+) {
+    let mut rx = event_tx.subscribe_critical();
+    tokio::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(WsEvent::InferenceStarted { session_id, .. }) => {
+                    let Some(session) = llama_cpp.get_current_session().await else {
+                        continue;
+                    };
+                    if session.id != session_id {
+                        continue;
+                    }
+                    let (local, _) = memory.cached();
+                    if let Err(e) = reserve(&pool, &local, &session).await {
+                        tracing::warn!("Failed to reserve memory for {}: {}", session_id, e);
+                    }
+                }
+                Ok(WsEvent::InferenceStopped { session_id, .. })
+                | Ok(WsEvent::InferenceFailed { session_id, .. }) => {
+                    release(&pool, &session_id).await;
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Reservation tracker lagged; skipped {} events", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(id: &str, kind: GpuKind, free_mb: u64) -> MemorySnapshot {
+        MemorySnapshot {
+            provider_id: id.to_string(),
+            name: id.to_string(),
+            kind,
+            total_mb: free_mb * 2,
+            used_mb: free_mb,
+            free_mb,
+            allocated_mb: 0,
+            ollama_mb: 0,
+            usable_free_mb: free_mb,
+            stale: false,
+        }
+    }
+
+    #[test]
+    fn split_follows_free_memory_and_prefers_gpus() {
+        let local = [
+            snapshot("nvidia-0", GpuKind::Nvidia, 24_000),
+            snapshot("system_ram", GpuKind::SystemRam, 64_000),
+        ];
+        let devices = [("dev-a".to_string(), 8_000), ("dev-b".to_string(), 0)];
+
+        let shares = split(16_000, &local, &devices);
+        assert_eq!(
+            shares,
+            vec![
+                (None, "nvidia-0".to_string(), 12_000),
+                (Some("dev-a".to_string()), RPC_PROVIDER.to_string(), 4_000),
+            ]
+        );
+
+        // CPU-only host: system RAM holds the local share
+        let shares = split(1_001, &local[1..], &[]);
+        assert_eq!(shares, vec![(None, "system_ram".to_string(), 1_001)]);
+        assert!(split(1_000, &[], &[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn reservations_release_per_session_and_on_startup() {
+        let pool = crate::settings::tests::test_pool().await;
+        let session = |id: &str| InferenceSessionInfo {
+            id: id.to_string(),
+            model_path: "/nonexistent.gguf".to_string(),
+            status: "running".to_string(),
+            rpc_devices: vec![],
+            started_at: String::new(),
+            n_gpu_layers: -1,
+            ctx_size: 4096,
+            parallel: 1,
+            cont_batching: false,
+            restart_attempts: 0,
+            restarted_at: None,
+            gpu_selector: Default::default(),
+            env: Default::default(),
+            draft: None,
+            mmproj: None,
+            supports_vision: false,
+            chat_template: None,
+            system_prompt: None,
+            lora_adapters: Vec::new(),
+            retry_on_oom: false,
+            oom_fallback: None,
+            api_key: String::new(),
+        };
+        let local = [snapshot("nvidia-0", GpuKind::Nvidia, 24_000)];
+
+        // Missing model file: only the KV estimate is reserved
+        let kv_mb = reserve(&pool, &local, &session("a")).await.unwrap();
+        assert!(kv_mb > 0);
+        reserve(&pool, &local, &session("a")).await.unwrap();
+        reserve(&pool, &local, &session("b")).await.unwrap();
+        assert_eq!(Reserved::load(&pool).await.local_total(), 2 * kv_mb);
+
+        release(&pool, "a").await;
+        assert_eq!(Reserved::load(&pool).await.local_total(), kv_mb);
+
+        assert_eq!(release_stale(&pool).await.unwrap(), 1);
+        assert_eq!(Reserved::load(&pool).await.local_total(), 0);
+    }
+}
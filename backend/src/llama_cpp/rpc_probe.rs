@@ -0,0 +1,313 @@
+//! Deep probe of a llama-rpc-server. A TCP connect only shows that the port
+//! is open: a wedged server still accepts connections but never answers, and
+//! the session then breaks when llama-server loads the model. This probe
+//! speaks enough of the RPC protocol to see the server work: `HELLO` for its
+//! protocol version, then `GET_DEVICE_MEMORY`.
+//!
+//! A message is a command byte, the payload size as a little-endian u64 and
+//! the payload; a reply is its size and its data. Servers from before `HELLO`
+//! close the connection on it and get the TCP verdict. So does a server whose
+//! major version is newer than [`KNOWN_MAJOR`], once it has answered `HELLO`.
+//!
+//! llama-rpc-server serves one client at a time: an endpoint a running
+//! session is connected to only gets the TCP probe, or the probe would wait
+//! behind llama-server and time out.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Setting: probe with the RPC protocol in cluster status too, not only
+/// before a session starts.
+pub const DEEP_PROBE_KEY: &str = "rpc_deep_probe";
+
+const RPC_CMD_GET_DEVICE_MEMORY: u8 = 11;
+const RPC_CMD_HELLO: u8 = 14;
+/// Newest protocol major version whose `GET_DEVICE_MEMORY` is understood.
+/// From 3 on it takes the device index as a u32.
+const KNOWN_MAJOR: u8 = 3;
+/// Largest reply read; both replies asked for are a few bytes
+const MAX_REPLY: u64 = 64;
+const MB: u64 = 1024 * 1024;
+
+/// How far a probe got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ProbeDepth {
+    /// The port accepted a connection
+    Tcp,
+    /// The server answered `HELLO`
+    Hello,
+    /// It also reported its device memory
+    Memory,
+}
+
+/// Result of probing one RPC endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct DeepProbe {
+    pub ok: bool,
+    /// The level the verdict came from
+    pub depth: ProbeDepth,
+    /// What the server said, or why it failed
+    pub detail: String,
+}
+
+impl DeepProbe {
+    fn passed(depth: ProbeDepth, detail: impl Into<String>) -> Self {
+        DeepProbe { ok: true, depth, detail: detail.into() }
+    }
+
+    fn failed(depth: ProbeDepth, detail: impl Into<String>) -> Self {
+        DeepProbe { ok: false, depth, detail: detail.into() }
+    }
+
+    /// Verdict of a TCP-only probe.
+    pub fn tcp(reachable: bool, detail: impl Into<String>) -> Self {
+        DeepProbe { ok: reachable, depth: ProbeDepth::Tcp, detail: detail.into() }
+    }
+}
+
+enum CallError {
+    /// The server hung up
+    Closed,
+    TimedOut(Duration),
+    TooLarge(u64),
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for CallError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe => CallError::Closed,
+            _ => CallError::Io(e),
+        }
+    }
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Closed => f.write_str("connection closed"),
+            CallError::TimedOut(t) => write!(f, "no answer within {} ms", t.as_millis()),
+            CallError::TooLarge(size) => write!(f, "{}-byte reply", size),
+            CallError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Send one command and read its reply, within `timeout`.
+async fn call(stream: &mut TcpStream, cmd: u8, payload: &[u8], timeout: Duration) -> Result<Vec<u8>, CallError> {
+    let exchange = async {
+        let mut msg = Vec::with_capacity(9 + payload.len());
+        msg.push(cmd);
+        msg.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        msg.extend_from_slice(payload);
+        stream.write_all(&msg).await?;
+        let mut size = [0u8; 8];
+        stream.read_exact(&mut size).await?;
+        let size = u64::from_le_bytes(size);
+        if size > MAX_REPLY {
+            return Err(CallError::TooLarge(size));
+        }
+        let mut reply = vec![0u8; size as usize];
+        stream.read_exact(&mut reply).await?;
+        Ok(reply)
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .unwrap_or(Err(CallError::TimedOut(timeout)))
+}
+
+/// Probe the RPC server at `addr` (`ip:port`), giving each step `timeout`.
+pub async fn probe(addr: &str, timeout: Duration) -> DeepProbe {
+    let mut stream = match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return DeepProbe::failed(ProbeDepth::Tcp, e.to_string()),
+        Err(_) => {
+            return DeepProbe::failed(
+                ProbeDepth::Tcp,
+                format!("no connection within {} ms", timeout.as_millis()),
+            )
+        }
+    };
+
+    let hello = match call(&mut stream, RPC_CMD_HELLO, &[], timeout).await {
+        Ok(reply) => reply,
+        Err(CallError::Closed) => {
+            return DeepProbe::passed(
+                ProbeDepth::Tcp,
+                "closed the connection on HELLO: a server from before the handshake, only its port was checked",
+            )
+        }
+        Err(e) => return DeepProbe::failed(ProbeDepth::Hello, format!("HELLO: {}", e)),
+    };
+    let &[major, minor, patch] = hello.as_slice() else {
+        return DeepProbe::failed(
+            ProbeDepth::Hello,
+            format!("HELLO: {}-byte reply, expected 3", hello.len()),
+        );
+    };
+    let version = format!("{}.{}.{}", major, minor, patch);
+    if major == 0 || major > KNOWN_MAJOR {
+        return DeepProbe::passed(
+            ProbeDepth::Hello,
+            format!("protocol {} is unknown here; only HELLO was checked", version),
+        );
+    }
+
+    let device = 0u32.to_le_bytes();
+    let payload: &[u8] = if major >= 3 { &device } else { &[] };
+    let memory = match call(&mut stream, RPC_CMD_GET_DEVICE_MEMORY, payload, timeout).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            return DeepProbe::failed(
+                ProbeDepth::Memory,
+                format!("protocol {}: GET_DEVICE_MEMORY: {}", version, e),
+            )
+        }
+    };
+    let Ok(memory) = <[u8; 16]>::try_from(memory.as_slice()) else {
+        return DeepProbe::failed(
+            ProbeDepth::Memory,
+            format!("protocol {}: GET_DEVICE_MEMORY: {}-byte reply, expected 16", version, memory.len()),
+        );
+    };
+    let free = u64::from_le_bytes(memory[..8].try_into().unwrap());
+    let total = u64::from_le_bytes(memory[8..].try_into().unwrap());
+    if total == 0 || free > total {
+        return DeepProbe::failed(
+            ProbeDepth::Memory,
+            format!("protocol {}: reports {} of {} bytes free", version, free, total),
+        );
+    }
+    DeepProbe::passed(
+        ProbeDepth::Memory,
+        format!("protocol {}: {} of {} MB free", version, free / MB, total / MB),
+    )
+}
+
+/// A healthy protocol 2 server on a loopback port, for tests: 6000 of 8192
+/// MB free.
+#[cfg(test)]
+pub async fn stub_server() -> std::net::SocketAddr {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut header = [0u8; 9];
+                while stream.read_exact(&mut header).await.is_ok() {
+                    let size = u64::from_le_bytes(header[1..].try_into().unwrap());
+                    let mut payload = vec![0u8; size as usize];
+                    if stream.read_exact(&mut payload).await.is_err() {
+                        return;
+                    }
+                    let reply = match header[0] {
+                        RPC_CMD_HELLO => vec![2, 0, 0],
+                        RPC_CMD_GET_DEVICE_MEMORY => [(6000 * MB).to_le_bytes(), (8192 * MB).to_le_bytes()].concat(),
+                        _ => return,
+                    };
+                    let _ = stream.write_all(&(reply.len() as u64).to_le_bytes()).await;
+                    let _ = stream.write_all(&reply).await;
+                }
+            });
+        }
+    });
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_millis(300);
+
+    /// An RPC server answering each command with `reply(cmd, payload)`;
+    /// `None` hangs up. Serves one connection.
+    async fn server(reply: fn(u8, &[u8]) -> Option<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            loop {
+                let mut header = [0u8; 9];
+                if stream.read_exact(&mut header).await.is_err() {
+                    return;
+                }
+                let size = u64::from_le_bytes(header[1..].try_into().unwrap());
+                let mut payload = vec![0u8; size as usize];
+                stream.read_exact(&mut payload).await.unwrap();
+                let Some(data) = reply(header[0], &payload) else {
+                    return;
+                };
+                stream.write_all(&(data.len() as u64).to_le_bytes()).await.unwrap();
+                stream.write_all(&data).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn memory(free_mb: u64, total_mb: u64) -> Vec<u8> {
+        [(free_mb * MB).to_le_bytes(), (total_mb * MB).to_le_bytes()].concat()
+    }
+
+    #[tokio::test]
+    async fn working_servers_report_their_memory() {
+        let v2 = stub_server().await.to_string();
+        let probe = probe(&v2, TIMEOUT).await;
+        assert!(probe.ok);
+        assert_eq!(probe.depth, ProbeDepth::Memory);
+        assert_eq!(probe.detail, "protocol 2.0.0: 6000 of 8192 MB free");
+
+        // Version 3 names the device
+        let v3 = server(|cmd, payload| match cmd {
+            RPC_CMD_HELLO => Some(vec![3, 0, 0]),
+            RPC_CMD_GET_DEVICE_MEMORY if payload == [0, 0, 0, 0] => Some(memory(100, 200)),
+            _ => None,
+        })
+        .await;
+        assert!(super::probe(&v3, TIMEOUT).await.ok);
+    }
+
+    #[tokio::test]
+    async fn a_server_that_accepts_but_never_answers_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // Accepted by the backlog, never read
+        let probe = probe(&addr, TIMEOUT).await;
+        assert!(!probe.ok);
+        assert_eq!(probe.depth, ProbeDepth::Hello);
+        assert!(probe.detail.contains("no answer"), "{}", probe.detail);
+        drop(listener);
+
+        let insane = server(|cmd, _| match cmd {
+            RPC_CMD_HELLO => Some(vec![1, 0, 0]),
+            _ => Some(memory(10, 5)),
+        })
+        .await;
+        let probe = super::probe(&insane, TIMEOUT).await;
+        assert_eq!((probe.ok, probe.depth), (false, ProbeDepth::Memory));
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let probe = super::probe(&closed.to_string(), TIMEOUT).await;
+        assert_eq!((probe.ok, probe.depth), (false, ProbeDepth::Tcp));
+    }
+
+    #[tokio::test]
+    async fn unknown_protocols_fall_back_to_what_was_checked() {
+        // Before HELLO existed the server hung up on unknown commands
+        let old = server(|_, _| None).await;
+        let probe = probe(&old, TIMEOUT).await;
+        assert_eq!((probe.ok, probe.depth), (true, ProbeDepth::Tcp));
+
+        let newer = server(|cmd, _| (cmd == RPC_CMD_HELLO).then(|| vec![9, 1, 0])).await;
+        let probe = super::probe(&newer, TIMEOUT).await;
+        assert_eq!((probe.ok, probe.depth), (true, ProbeDepth::Hello));
+        assert!(probe.detail.contains("9.1.0"));
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+
+/// A part of a model split by `llama-gguf-split`: `<base>-00001-of-00003.gguf`.
+#[derive(Debug, PartialEq)]
+pub struct SplitName<'a> {
+    pub base: &'a str,
+    pub part: u32,
+    pub count: u32,
+}
+
+/// Read a split part's file name. `None` for an ordinary model file.
+pub fn parse(file_name: &str) -> Option<SplitName<'_>> {
+    let (stem, ext) = file_name.rsplit_once('.')?;
+    if !ext.eq_ignore_ascii_case("gguf") {
+        return None;
+    }
+    let (rest, count) = stem.rsplit_once("-of-")?;
+    let (base, part) = rest.rsplit_once('-')?;
+    // llama.cpp always writes five digits
+    let number = |s: &str| {
+        if s.len() == 5 && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse::<u32>().ok()
+        } else {
+            None
+        }
+    };
+    let (part, count) = (number(part)?, number(count)?);
+    if base.is_empty() || part == 0 || part > count {
+        return None;
+    }
+    Some(SplitName { base, part, count })
+}
+
+/// File name of part `part` of a split model.
+pub fn part_file_name(base: &str, part: u32, count: u32) -> String {
+    format!("{}-{:05}-of-{:05}.gguf", base, part, count)
+}
+
+/// The files making up the model at `path`, in load order: every part of a
+/// split model, or `path` alone. A missing part is an error naming it.
+pub fn model_files(path: &Path) -> Result<Vec<PathBuf>> {
+    let Some(split) = path.file_name().and_then(|n| n.to_str()).and_then(parse) else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut files = Vec::with_capacity(split.count as usize);
+    for part in 1..=split.count {
+        let name = part_file_name(split.base, part, split.count);
+        let file = dir.join(&name);
+        if !file.is_file() {
+            // Only the file name; the directory isn't echoed back
+            return Err(anyhow!(
+                "Split model is incomplete: part {} of {} ({}) is missing",
+                part,
+                split.count,
+                name
+            ));
+        }
+        files.push(file);
+    }
+    Ok(files)
+}
+
+/// Combined size of `files`, in MB. Unreadable files count as empty.
+pub fn total_mb(files: &[PathBuf]) -> u64 {
+    let bytes: u64 = files
+        .iter()
+        .map(|f| std::fs::metadata(f).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    bytes / (1024 * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_names_are_parsed() {
+        assert_eq!(
+            parse("qwen2-72b-q4_k_m-00002-of-00003.gguf"),
+            Some(SplitName { base: "qwen2-72b-q4_k_m", part: 2, count: 3 })
+        );
+        assert_eq!(parse("Model-00001-of-00001.GGUF").map(|s| s.count), Some(1));
+        assert_eq!(parse("llama3-8b.gguf"), None);
+        assert_eq!(parse("model-1-of-3.gguf"), None);
+        assert_eq!(parse("model-00004-of-00003.gguf"), None);
+        assert_eq!(parse("-00001-of-00003.gguf"), None);
+        assert_eq!(part_file_name("m", 1, 12), "m-00001-of-00012.gguf");
+    }
+
+    #[test]
+    fn every_part_must_exist() {
+        let dir = std::env::temp_dir().join(format!("sharedllm-split-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("m-00001-of-00003.gguf"), vec![0u8; 1024 * 1024]).unwrap();
+        std::fs::write(dir.join("m-00003-of-00003.gguf"), vec![0u8; 2 * 1024 * 1024]).unwrap();
+
+        let first = dir.join("m-00001-of-00003.gguf");
+        let err = model_files(&first).unwrap_err().to_string();
+        assert_eq!(err, "Split model is incomplete: part 2 of 3 (m-00002-of-00003.gguf) is missing");
+        assert!(!err.contains(dir.to_str().unwrap()));
+
+        std::fs::write(dir.join("m-00002-of-00003.gguf"), vec![0u8; 1024 * 1024]).unwrap();
+        let files = model_files(&first).unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(total_mb(&files), 4);
+
+        let single = dir.join("other.gguf");
+        assert_eq!(model_files(&single).unwrap(), vec![single]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
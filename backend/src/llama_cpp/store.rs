@@ -0,0 +1,249 @@
+//! GGUF files kept in the model directories: the directories of aliased
+//! models and `~/.sharedmem/models`. Deleting a model moves its files into
+//! a [`TRASH_DIR`] beside them and lists them in `model_trash`, from which
+//! they are purged after `model_trash_days`.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{split, InferenceSessionInfo};
+use crate::db::queries;
+use crate::settings::{Key, SettingsCache};
+
+/// Where deleted models wait in each model directory.
+pub const TRASH_DIR: &str = ".trash";
+/// Setting: days a deleted model stays in the trash.
+pub const TRASH_DAYS_KEY: &str = "model_trash_days";
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A `.gguf` file in a model directory.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ModelFile {
+    pub path: String,
+    pub file_name: String,
+    pub size_bytes: u64,
+    /// Last modified (RFC 3339), usually when it was downloaded
+    pub modified_at: Option<String>,
+    pub age_days: Option<u64>,
+    /// When a session with this model last ran, if one has since session
+    /// history was kept
+    pub last_used_at: Option<String>,
+    /// Part of the running session
+    pub in_use: bool,
+    /// Aliases pointing at it
+    pub aliases: Vec<String>,
+}
+
+/// Disk usage of one model directory.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DirUsage {
+    pub path: String,
+    /// Combined size of `files`
+    pub total_bytes: u64,
+    /// Largest first
+    pub files: Vec<ModelFile>,
+}
+
+/// The directories of aliased models, then `~/.sharedmem/models`, each once.
+/// Not all of them need exist.
+pub async fn model_dirs(pool: &SqlitePool) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let aliased = queries::list_model_aliases(pool).await.unwrap_or_default();
+    for dir in aliased.iter().filter_map(|a| Path::new(&a.path).parent()) {
+        if !dirs.iter().any(|d| d == dir) {
+            dirs.push(dir.to_path_buf());
+        }
+    }
+    if let Ok(dir) = crate::disk::sharedmem_dir() {
+        let dir = dir.join("models");
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Every file `session` reads: the model (all its parts), the draft model,
+/// the projector and the LoRA adapters, resolved.
+pub fn session_files(session: &InferenceSessionInfo) -> Vec<PathBuf> {
+    let model = Path::new(&session.model_path);
+    let mut files = split::model_files(model).unwrap_or_else(|_| vec![model.to_path_buf()]);
+    files.extend(session.draft.iter().map(|d| PathBuf::from(&d.path)));
+    files.extend(session.mmproj.iter().map(PathBuf::from));
+    files.extend(session.lora_adapters.iter().map(|a| PathBuf::from(&a.path)));
+    files.iter().map(|f| f.canonicalize().unwrap_or_else(|_| f.clone())).collect()
+}
+
+fn is_gguf(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("gguf"))
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339()
+}
+
+/// Files directly in `dir`, with their sizes.
+fn files_in(dir: &Path) -> Vec<(PathBuf, std::fs::Metadata)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|e| Some((e.path(), e.metadata().ok()?)))
+        .filter(|(_, meta)| meta.is_file())
+        .collect()
+}
+
+/// The `.gguf` files directly in each of `dirs`. `last_used_at`, `in_use` and `aliases` are left for the caller.
+pub fn scan(dirs: &[PathBuf]) -> Vec<DirUsage> {
+    let now = SystemTime::now();
+    dirs.iter()
+        .map(|dir| {
+            let mut files: Vec<ModelFile> = files_in(dir)
+                .into_iter()
+                .filter(|(path, _)| is_gguf(path))
+                .map(|(path, meta)| {
+                    let modified = meta.modified().ok();
+                    ModelFile {
+                        file_name: path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string(),
+                        path: path.display().to_string(),
+                        size_bytes: meta.len(),
+                        modified_at: modified.map(rfc3339),
+                        age_days: modified
+                            .and_then(|m| now.duration_since(m).ok())
+                            .map(|d| d.as_secs() / 86_400),
+                        last_used_at: None,
+                        in_use: false,
+                        aliases: Vec::new(),
+                    }
+                })
+                .collect();
+            files.sort_by(|a, b| b.size_bytes.cmp(&a.size_bytes).then_with(|| a.path.cmp(&b.path)));
+            DirUsage {
+                path: dir.display().to_string(),
+                total_bytes: files.iter().map(|f| f.size_bytes).sum(),
+                files,
+            }
+        })
+        .collect()
+}
+
+/// `path` resolved, if it is a `.gguf` file directly in one of `dirs`.
+pub fn locate(dirs: &[PathBuf], path: &str) -> Result<PathBuf> {
+    let p = Path::new(path);
+    if !p.is_absolute() || path.contains("..") || !is_gguf(p) {
+        return Err(anyhow!("path must be an absolute .gguf path, without '..'"));
+    }
+    let file = p.canonicalize().map_err(|_| anyhow!("Model file not found"))?;
+    let parent = file.parent().ok_or_else(|| anyhow!("Model file not found"))?;
+    let inside = dirs.iter().filter_map(|d| d.canonicalize().ok()).any(|d| d == parent);
+    if !inside || !file.is_file() {
+        return Err(anyhow!("path is not a model file in a model directory"));
+    }
+    Ok(file)
+}
+
+/// Move the model at `path`, every part of a split one, into the trash
+/// beside it and list each file in `model_trash`. Returns the files moved,
+/// by their old paths.
+pub async fn trash(pool: &SqlitePool, path: &Path) -> Result<Vec<PathBuf>> {
+    let files = split::model_files(path)?;
+    let dir = path.parent().ok_or_else(|| anyhow!("Model file has no directory"))?;
+    let trash = dir.join(TRASH_DIR);
+    tokio::fs::create_dir_all(&trash).await?;
+    // Stamped so a model deleted twice doesn't overwrite the first copy
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    for file in &files {
+        let name = file.file_name().and_then(|n| n.to_str()).unwrap_or("model.gguf");
+        let dest = trash.join(format!("{}-{}", stamp, name));
+        let size = tokio::fs::metadata(file).await.map(|m| m.len()).unwrap_or(0);
+        tokio::fs::rename(file, &dest).await?;
+        queries::insert_trashed_model(pool, &file.display().to_string(), &dest.display().to_string(), size).await?;
+    }
+    Ok(files)
+}
+
+/// Delete files trashed more than `keep` ago. Returns how many went.
+pub async fn purge(pool: &SqlitePool, keep: Duration) -> Result<usize> {
+    let cutoff = chrono::Utc::now() - chrono::Duration::from_std(keep)?;
+    let mut purged = 0;
+    for entry in queries::list_trashed_models(pool).await? {
+        let deleted_at = chrono::DateTime::parse_from_rfc3339(&entry.deleted_at).ok();
+        if deleted_at.is_some_and(|t| t > cutoff) {
+            continue;
+        }
+        match tokio::fs::remove_file(&entry.trashed_path).await {
+            Ok(()) => purged += 1,
+            // Removed by hand; the entry goes all the same
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                tracing::warn!("Failed to purge {}: {}", entry.trashed_path, e);
+                continue;
+            }
+        }
+        queries::delete_trashed_model(pool, &entry.trashed_path).await?;
+    }
+    Ok(purged)
+}
+
+/// Purge the trash every [`PURGE_INTERVAL`] for the life of the process.
+pub fn spawn_purger(pool: SqlitePool, settings: Arc<SettingsCache>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let days = settings.get_u64(Key::ModelTrashDays);
+            match purge(&pool, Duration::from_secs(days * 86_400)).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Purged {} deleted model file(s) older than {} days", n, days),
+                Err(e) => tracing::warn!("Model trash purge failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn deleted_models_wait_in_the_trash() {
+        let pool = crate::settings::tests::test_pool().await;
+        let dir = std::env::temp_dir().join(format!("store-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dirs = vec![dir.clone()];
+        std::fs::write(dir.join("small.gguf"), b"GGUF").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not a model").unwrap();
+        for part in 1..=2 {
+            std::fs::write(dir.join(split::part_file_name("big", part, 2)), b"GGUF part").unwrap();
+        }
+
+        let usage = scan(&dirs);
+        assert_eq!(usage[0].files.len(), 3);
+        assert_eq!(usage[0].total_bytes, 4 + 9 + 9);
+        assert_eq!(usage[0].files[2].file_name, "small.gguf");
+
+        let first = dir.join(split::part_file_name("big", 1, 2));
+        assert!(locate(&dirs, "relative.gguf").is_err());
+        assert!(locate(&dirs, &dir.join("notes.txt").display().to_string()).is_err());
+        assert!(locate(&[std::env::temp_dir().join("elsewhere")], &first.display().to_string()).is_err());
+        let located = locate(&dirs, &first.display().to_string()).unwrap();
+
+        assert_eq!(trash(&pool, &located).await.unwrap().len(), 2);
+        assert_eq!(scan(&dirs)[0].files.len(), 1);
+        let trashed = queries::list_trashed_models(&pool).await.unwrap();
+        assert_eq!(trashed.len(), 2);
+        assert!(std::path::Path::new(&trashed[0].trashed_path).is_file());
+
+        // Too recent to go, then gone once nothing is kept
+        assert_eq!(purge(&pool, Duration::from_secs(86_400)).await.unwrap(), 0);
+        assert_eq!(purge(&pool, Duration::ZERO).await.unwrap(), 2);
+        assert!(queries::list_trashed_models(&pool).await.unwrap().is_empty());
+        assert!(!std::path::Path::new(&trashed[0].trashed_path).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
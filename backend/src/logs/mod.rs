@@ -0,0 +1,247 @@
+use axum::{
+    body::{Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{span, Event, Instrument, Subscriber};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+pub mod output;
+
+/// Response header carrying the ID assigned to the request.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Log lines kept for `GET /api/logs/recent`.
+pub const BUFFER_LINES: usize = 2000;
+/// Larger error bodies are passed through without a `request_id`.
+const MAX_ERROR_BODY: u64 = 64 * 1024;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// ID of the request the current task is handling, if any. Work spawned
+/// off the request (and background tasks) has none.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+// ─── Request IDs ─────────────────────────────────────────────────────────────
+
+/// Give every request a UUID: it is set on a `request` span (so every log
+/// line of the handler carries it), returned as `x-request-id`, and added as
+/// `request_id` to JSON error bodies.
+pub async fn assign_request_id(req: Request, next: Next) -> Response {
+    let id = uuid::Uuid::new_v4().to_string();
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        path = %req.uri().path(),
+    );
+    let response = REQUEST_ID
+        .scope(id.clone(), next.run(req).instrument(span))
+        .await;
+
+    let mut response = tag_error_body(response, &id).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Add `request_id` to a 4xx/5xx JSON object body. Streamed or large bodies
+/// are left alone.
+async fn tag_error_body(response: Response, id: &str) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    let small = response
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|n| n <= MAX_ERROR_BODY);
+    if !(response.status().is_client_error() || response.status().is_server_error())
+        || !is_json
+        || !small
+    {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, MAX_ERROR_BODY as usize).await {
+        Ok(b) => b,
+        Err(_) => return Response::from_parts(parts, Body::empty()),
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut map)) => {
+            map.insert("request_id".into(), id.into());
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(serde_json::Value::Object(map).to_string())
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
+
+// ─── Recent log lines ────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LogLine {
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    /// The message followed by any other fields as `key=value`
+    pub message: String,
+    pub request_id: Option<String>,
+}
+
+/// The last `BUFFER_LINES` log lines, oldest first.
+#[derive(Default)]
+pub struct LogBuffer {
+    lines: Mutex<VecDeque<LogLine>>,
+}
+
+impl LogBuffer {
+    fn push(&self, line: LogLine) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() == BUFFER_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Up to `limit` of the newest lines, optionally only those of one request.
+    pub fn recent(&self, request_id: Option<&str>, limit: usize) -> Vec<LogLine> {
+        let lines = self.lines.lock().unwrap();
+        let mut out: Vec<LogLine> = lines
+            .iter()
+            .rev()
+            .filter(|l| request_id.is_none() || l.request_id.as_deref() == request_id)
+            .take(limit)
+            .cloned()
+            .collect();
+        out.reverse();
+        out
+    }
+}
+
+/// `tracing` layer that copies every event into a `LogBuffer`.
+pub struct LogLayer(Arc<LogBuffer>);
+
+impl LogLayer {
+    pub fn new(buffer: Arc<LogBuffer>) -> Self {
+        LogLayer(buffer)
+    }
+}
+
+/// `request_id` of a span, kept in its extensions.
+struct SpanRequestId(String);
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: String,
+    request_id: Option<String>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{:?}", value);
+            }
+            "request_id" => self.request_id = Some(format!("{:?}", value)),
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message.push_str(value),
+            "request_id" => self.request_id = Some(value.to_string()),
+            name => {
+                let _ = write!(self.fields, " {}={}", name, value);
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for LogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(request_id), Some(span)) = (visitor.request_id, ctx.span(id)) {
+            span.extensions_mut().insert(SpanRequestId(request_id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let request_id = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<SpanRequestId>().map(|r| r.0.clone()))
+        });
+        let meta = event.metadata();
+        self.0.push(LogLine {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            level: meta.level().to_string(),
+            target: meta.target().to_string(),
+            message: visitor.message + &visitor.fields,
+            request_id,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn lines_carry_the_request_id_of_their_span() {
+        let buffer = Arc::new(LogBuffer::default());
+        let subscriber = tracing_subscriber::registry().with(LogLayer::new(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("before");
+            let span = tracing::info_span!("request", request_id = "req-1");
+            span.in_scope(|| tracing::warn!(port = 8282, "port taken"));
+        });
+
+        let all = buffer.recent(None, 10);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].request_id, None);
+        let trail = buffer.recent(Some("req-1"), 10);
+        assert_eq!(trail.len(), 1);
+        assert_eq!(trail[0].message, "port taken port=8282");
+        assert_eq!(trail[0].level, "WARN");
+    }
+
+    #[tokio::test]
+    async fn error_bodies_get_the_request_id() {
+        let error = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"error":"bad"}"#))
+            .unwrap();
+        let tagged = tag_error_body(error, "req-2").await;
+        let bytes = axum::body::to_bytes(tagged.into_body(), 1024).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body, serde_json::json!({ "error": "bad", "request_id": "req-2" }));
+    }
+}
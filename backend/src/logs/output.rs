@@ -0,0 +1,453 @@
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    fmt::{self, format, FmtContext, FormatEvent, FormatFields},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    Layer, Registry,
+};
+
+use super::{LogBuffer, LogLayer, SpanRequestId};
+
+/// Setting: `pretty` or `json` log lines. The `LOG_FORMAT` env var wins.
+pub const LOG_FORMAT_KEY: &str = "log_format";
+/// Rotate `LOG_FILE` past this size unless `LOG_FILE_MAX_MB` says otherwise.
+const DEFAULT_FILE_MAX_MB: u64 = 50;
+/// Rotated `LOG_FILE`s kept unless `LOG_FILE_KEEP` says otherwise.
+const DEFAULT_FILE_KEEP: u32 = 5;
+/// Size at which a server's output file is truncated.
+pub const CHILD_LOG_MAX_MB: u64 = 50;
+const CHILD_LOG_CHECK_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines, as printed so far
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "pretty" | "text" => Some(LogFormat::Pretty),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Check a `log_format` value for the settings API.
+pub fn validate_format(value: &str) -> Result<(), String> {
+    LogFormat::parse(value)
+        .map(|_| ())
+        .ok_or_else(|| "log_format must be 'pretty' or 'json'".to_string())
+}
+
+/// Where log lines go, for `GET /api/diagnostics`.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// `env` (`LOG_FORMAT`), `setting` (`log_format`) or `default`
+    pub format_source: String,
+    /// `LOG_FILE`, written next to stdout
+    pub file: Option<String>,
+    pub file_max_mb: u64,
+    /// Rotated files kept: `<file>.1` (newest) to `<file>.<keep>`
+    pub file_keep: u32,
+    /// Where llama-rpc-server and llama-server write their output
+    pub child_log_dir: Option<String>,
+    pub child_log_max_mb: u64,
+}
+
+type Output = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// The installed log output. Its format can be switched while running.
+pub struct LogOutput {
+    handle: reload::Handle<Output, Registry>,
+    file: Option<Arc<RotatingFile>>,
+    config: RwLock<LogConfig>,
+}
+
+impl LogOutput {
+    /// Install the global subscriber: `RUST_LOG` filtering, stdout (and
+    /// `LOG_FILE`) in the `LOG_FORMAT` format, and `buffer` for
+    /// `GET /api/logs/recent`.
+    pub fn init(buffer: Arc<LogBuffer>) -> Arc<Self> {
+        let env_format = std::env::var("LOG_FORMAT").ok();
+        let format = env_format.as_deref().and_then(LogFormat::parse);
+        let file_max_mb = env_number("LOG_FILE_MAX_MB").filter(|n| *n > 0).unwrap_or(DEFAULT_FILE_MAX_MB);
+        let file_keep = env_number("LOG_FILE_KEEP").map_or(DEFAULT_FILE_KEEP, |n| n as u32);
+        let file_path = std::env::var("LOG_FILE").ok().filter(|p| !p.trim().is_empty());
+        let mut file_error = None;
+        let file = file_path.as_ref().and_then(|path| {
+            match RotatingFile::open(path, file_max_mb * 1024 * 1024, file_keep) {
+                Ok(f) => Some(Arc::new(f)),
+                Err(e) => {
+                    file_error = Some(format!("Cannot write LOG_FILE {}: {}", path, e));
+                    None
+                }
+            }
+        });
+
+        let (layer, handle) = reload::Layer::new(output(format.unwrap_or(LogFormat::Pretty), file.clone()));
+        tracing_subscriber::registry()
+            .with(layer)
+            .with(
+                tracing_subscriber::EnvFilter::try_from_default_env()
+                    .unwrap_or_else(|_| "server=debug,tower_http=info".into()),
+            )
+            .with(LogLayer::new(buffer))
+            .init();
+
+        if let Some(e) = file_error {
+            tracing::warn!("{}", e);
+        }
+        if let (Some(value), None) = (&env_format, format) {
+            tracing::warn!("Ignoring LOG_FORMAT={}: use 'pretty' or 'json'", value);
+        }
+        Arc::new(LogOutput {
+            handle,
+            config: RwLock::new(LogConfig {
+                format: format.unwrap_or(LogFormat::Pretty),
+                format_source: if format.is_some() { "env" } else { "default" }.to_string(),
+                file: file.as_ref().and(file_path),
+                file_max_mb,
+                file_keep,
+                child_log_dir: child_log_dir().map(|d| d.display().to_string()),
+                child_log_max_mb: CHILD_LOG_MAX_MB,
+            }),
+            file,
+        })
+    }
+
+    /// Logging that isn't installed anywhere, for tests: the global
+    /// subscriber can only be set once per process.
+    #[cfg(test)]
+    pub fn detached() -> Arc<Self> {
+        let (_layer, handle) = reload::Layer::new(output(LogFormat::Pretty, None));
+        Arc::new(LogOutput {
+            handle,
+            config: RwLock::new(LogConfig {
+                format: LogFormat::Pretty,
+                format_source: "default".to_string(),
+                file: None,
+                file_max_mb: DEFAULT_FILE_MAX_MB,
+                file_keep: DEFAULT_FILE_KEEP,
+                child_log_dir: None,
+                child_log_max_mb: CHILD_LOG_MAX_MB,
+            }),
+            file: None,
+        })
+    }
+
+    pub fn config(&self) -> LogConfig {
+        self.config.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Switch to the `log_format` setting's format, unless `LOG_FORMAT` is
+    /// set. An unset or unknown value means `pretty`.
+    pub fn apply_setting(&self, value: Option<&str>) {
+        let mut config = self.config.write().unwrap_or_else(|e| e.into_inner());
+        if config.format_source == "env" {
+            return;
+        }
+        let format = value.and_then(LogFormat::parse);
+        let source = if format.is_some() { "setting" } else { "default" };
+        let format = format.unwrap_or(LogFormat::Pretty);
+        config.format_source = source.to_string();
+        if config.format == format {
+            return;
+        }
+        if let Err(e) = self.handle.reload(output(format, self.file.clone())) {
+            tracing::warn!("Failed to switch the log format: {}", e);
+            return;
+        }
+        config.format = format;
+    }
+}
+
+fn env_number(name: &str) -> Option<u64> {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok())
+}
+
+/// stdout, and `file` if any, in `format`.
+fn output(format: LogFormat, file: Option<Arc<RotatingFile>>) -> Output {
+    let stdout = match format {
+        LogFormat::Pretty => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().event_format(JsonFormat).boxed(),
+    };
+    let Some(file) = file else {
+        return stdout;
+    };
+    let file = match format {
+        LogFormat::Pretty => fmt::layer().with_ansi(false).with_writer(file).boxed(),
+        LogFormat::Json => fmt::layer().event_format(JsonFormat).with_writer(file).boxed(),
+    };
+    stdout.and_then(file).boxed()
+}
+
+// ─── JSON lines ──────────────────────────────────────────────────────────────
+
+/// One JSON object per event: `timestamp`, `level`, `target`, `message`,
+/// `request_id` (inside a request) and the event's other fields.
+pub struct JsonFormat;
+
+#[derive(Default)]
+struct JsonVisitor(serde_json::Map<String, serde_json::Value>);
+
+impl JsonVisitor {
+    fn put(&mut self, field: &Field, value: serde_json::Value) {
+        self.0.insert(field.name().to_string(), value);
+    }
+}
+
+impl Visit for JsonVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.put(field, format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.put(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.put(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.put(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.put(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.put(field, value.into());
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut fields = JsonVisitor::default();
+        event.record(&mut fields);
+        let meta = event.metadata();
+        let mut line = serde_json::Map::new();
+        line.insert("timestamp".into(), chrono::Utc::now().to_rfc3339().into());
+        line.insert("level".into(), meta.level().as_str().into());
+        line.insert("target".into(), meta.target().into());
+        let request_id = ctx.event_scope().and_then(|scope| {
+            scope
+                .from_root()
+                .find_map(|span| span.extensions().get::<SpanRequestId>().map(|r| r.0.clone()))
+        });
+        if let Some(id) = request_id {
+            line.insert("request_id".into(), id.into());
+        }
+        line.extend(fields.0);
+        writeln!(writer, "{}", serde_json::Value::Object(line))
+    }
+}
+
+// ─── LOG_FILE ────────────────────────────────────────────────────────────────
+
+/// A log file rotated by size: past `max_bytes` it becomes `<path>.1`, the
+/// older ones move up, and those past `<path>.<keep>` are deleted.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+    /// The open file and its size
+    state: Mutex<(File, u64)>,
+}
+
+impl RotatingFile {
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64, keep: u32) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, max_bytes, keep, state: Mutex::new((file, size)) })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> io::Result<File> {
+        if self.keep == 0 {
+            return File::create(&self.path);
+        }
+        let _ = fs::remove_file(self.rotated(self.keep));
+        for n in (1..self.keep).rev() {
+            let _ = fs::rename(self.rotated(n), self.rotated(n + 1));
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        OpenOptions::new().create(true).append(true).open(&self.path)
+    }
+}
+
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.1 > 0 && state.1 + buf.len() as u64 > self.max_bytes {
+            // Keep writing to the current file if rotation fails
+            if let Ok(file) = self.rotate() {
+                *state = (file, 0);
+            }
+        }
+        state.0.write_all(buf)?;
+        state.1 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).0.flush()
+    }
+}
+
+// ─── Server output ───────────────────────────────────────────────────────────
+
+/// `~/.sharedmem/logs`, where the servers this backend starts write.
+pub fn child_log_dir() -> Option<PathBuf> {
+    crate::disk::sharedmem_dir().ok().map(|d| d.join("logs"))
+}
+
+/// stdout and stderr for a server, appended to `<child_log_dir>/<name>.log`.
+/// The output is discarded if the file can't be opened.
+pub fn child_log(name: &str) -> (Stdio, Stdio) {
+    let open = || -> io::Result<(Stdio, Stdio)> {
+        let dir = child_log_dir().ok_or_else(|| io::Error::other("no home directory"))?;
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{}.log", name)))?;
+        Ok((Stdio::from(file.try_clone()?), Stdio::from(file)))
+    };
+    open().unwrap_or_else(|e| {
+        tracing::debug!("Discarding {} output: {}", name, e);
+        (Stdio::null(), Stdio::null())
+    })
+}
+
+/// Empty every `.log` file in `dir` larger than `max_bytes` and return them.
+/// The servers append, so they carry on at the start of the file.
+fn cap_logs(dir: &Path, max_bytes: u64) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "log"))
+        .filter(|p| fs::metadata(p).is_ok_and(|m| m.len() > max_bytes))
+        .filter(|p| OpenOptions::new().write(true).open(p).and_then(|f| f.set_len(0)).is_ok())
+        .collect()
+}
+
+/// Keep the servers' output files under [`CHILD_LOG_MAX_MB`].
+pub fn spawn_child_log_cap() {
+    let Some(dir) = child_log_dir() else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(std::time::Duration::from_secs(CHILD_LOG_CHECK_SECS));
+        loop {
+            tick.tick().await;
+            let dir = dir.clone();
+            let capped = tokio::task::spawn_blocking(move || cap_logs(&dir, CHILD_LOG_MAX_MB * 1024 * 1024))
+                .await
+                .unwrap_or_default();
+            for path in capped {
+                tracing::info!("Truncated {} (over {} MB)", path.display(), CHILD_LOG_MAX_MB);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sharedllm-logs-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn json_lines_rotate_by_size() {
+        let dir = temp_dir();
+        let file = Arc::new(RotatingFile::open(dir.join("server.log"), 300, 2).unwrap());
+        let subscriber = tracing_subscriber::registry()
+            .with(LogLayer::new(Arc::new(LogBuffer::default())))
+            .with(fmt::layer().event_format(JsonFormat).with_writer(file));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "req-1");
+            span.in_scope(|| tracing::warn!(port = 8282, taken = true, "port taken"));
+            for n in 0..3 {
+                tracing::info!(n, "line");
+            }
+        });
+
+        let mut lines = Vec::new();
+        for name in ["server.log.2", "server.log.1", "server.log"] {
+            let path = dir.join(name);
+            if let Ok(text) = fs::read_to_string(&path) {
+                assert!(text.len() <= 300, "{} holds {} bytes", name, text.len());
+                lines.extend(text.lines().map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()));
+            }
+        }
+        assert!(dir.join("server.log.1").exists());
+        assert!(!dir.join("server.log.3").exists());
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["message"], "port taken");
+        assert_eq!(lines[0]["level"], "WARN");
+        assert_eq!(lines[0]["request_id"], "req-1");
+        assert_eq!(lines[0]["port"], 8282);
+        assert_eq!(lines[0]["taken"], true);
+        assert_eq!(lines[3]["n"], 2);
+        assert!(lines[3].get("request_id").is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_server_logs_are_emptied() {
+        let dir = temp_dir();
+        fs::write(dir.join("rpc-server.log"), vec![b'x'; 2048]).unwrap();
+        fs::write(dir.join("llama-server.log"), vec![b'x'; 100]).unwrap();
+        fs::write(dir.join("notes.txt"), vec![b'x'; 2048]).unwrap();
+
+        assert_eq!(cap_logs(&dir, 1024), vec![dir.join("rpc-server.log")]);
+        assert_eq!(fs::metadata(dir.join("rpc-server.log")).unwrap().len(), 0);
+        assert_eq!(fs::metadata(dir.join("llama-server.log")).unwrap().len(), 100);
+        assert_eq!(fs::metadata(dir.join("notes.txt")).unwrap().len(), 2048);
+        assert_eq!(LogFormat::parse(" JSON"), Some(LogFormat::Json));
+        assert!(validate_format("xml").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,82 +1,164 @@
 mod api;
+mod api_keys;
+mod cors;
 mod db;
 mod discovery;
+mod disk;
+mod doctor;
+mod errors;
+mod federation;
+mod frontend;
+mod inventory;
+mod listen;
 mod llama_cpp;
+mod logs;
 mod memory;
 mod ollama;
+mod overview;
 mod permissions;
+mod process;
+mod settings;
+mod support;
+#[cfg(test)]
+mod testing;
+mod webhooks;
 mod ws;
 
 use anyhow::Result;
 use axum::{
-    extract::Request,
+    extract::{DefaultBodyLimit, Request, State},
+    http::{Method, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
     routing::{delete, get, patch, post, put},
-    Router,
+    Json, Router,
 };
-use llama_cpp::LlamaCppManager;
-use memory::MemoryProvider;
-use ollama::OllamaManager;
+use llama_cpp::{health::FlapTracker, reservations, LlamaCppManager};
+use memory::{hotplug::ProviderSet, remote::RemoteMemoryCache, sampler::MemorySampler};
+use ollama::{pulls::PullQueue, OllamaManager, OllamaMode};
+use settings::{limits::LimitsCache, Key, SettingsCache};
 use sqlx::SqlitePool;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tower_http::{
-    cors::{AllowOrigin, CorsLayer},
-    trace::TraceLayer,
-};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tower_http::trace::TraceLayer;
+use webhooks::WebhookDispatcher;
 
-use crate::ws::WsEvent;
+use crate::errors::{notify_error, RecentErrors, Severity};
+use crate::ws::{clients::WsClientRegistry, EventBus, WsEvent};
 
 // ─── App State ───────────────────────────────────────────────────────────────
 
 #[derive(Clone)]
 pub struct AppState {
     pub pool: SqlitePool,
-    pub event_tx: broadcast::Sender<WsEvent>,
-    pub providers: Vec<Arc<dyn MemoryProvider>>,
+    pub event_tx: EventBus,
+    /// Detected memory providers; replaced when detection runs again
+    pub providers: Arc<ProviderSet>,
     pub ollama: Arc<OllamaManager>,
+    /// Scheduled model pulls
+    pub pulls: Arc<PullQueue>,
     pub llama_cpp: Arc<LlamaCppManager>,
+    pub webhooks: Arc<WebhookDispatcher>,
+    pub settings: Arc<SettingsCache>,
+    pub memory: Arc<MemorySampler>,
+    /// Recent readings of remote devices' /api/gpu
+    pub remote_memory: Arc<RemoteMemoryCache>,
+    /// Agents able to restart their RPC server, and restarts waiting for them
+    pub rpc_restarts: Arc<permissions::restart::RpcRestarts>,
+    /// Responses of recent inference starts by idempotency key
+    pub start_keys: Arc<api::idempotency::IdempotencyKeys>,
+    /// Models each peer host serves, read as its heartbeats come in
+    pub peers: Arc<federation::PeerCache>,
+    /// This host's hardware inventory for GET /api/system/info
+    pub inventory: Arc<inventory::InventoryCache>,
+    pub logs: Arc<logs::LogBuffer>,
+    /// Log format and files, switched by the `log_format` setting
+    pub log_output: Arc<logs::output::LogOutput>,
+    /// Recent `error` events for GET /api/errors/recent
+    pub errors: Arc<RecentErrors>,
+    /// Open `/ws` connections for GET /api/ws/clients
+    pub ws_clients: Arc<WsClientRegistry>,
+    /// Recent RPC probes per device, for health scoring
+    pub device_health: Arc<FlapTracker>,
+    /// Device cap, timeouts and buffers from `settings::limits`
+    pub limits: Arc<LimitsCache>,
+    /// Model uploads in progress
+    pub uploads: Arc<api::model_uploads::UploadRegistry>,
+    /// Per-minute request counts of rate-limited API keys
+    pub key_limiter: Arc<api_keys::KeyRateLimiter>,
+    /// The database couldn't be written and `READ_ONLY_OK` let us start anyway
+    pub read_only: bool,
+    /// The port the server is listening on, after any `PORT_FALLBACK` move
+    pub port: u16,
+    /// The address it is listening on
+    pub bind: listen::BindAddr,
 }
 
 // ─── Main ─────────────────────────────────────────────────────────────────────
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Logging
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "shared_memory_backend=debug,tower_http=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    // `server --doctor`: run the self-test, print a table, and exit non-zero on failures
+    if std::env::args().skip(1).any(|a| a == "--doctor") {
+        let report = doctor::run(doctor::Context::default()).await;
+        print!("{}", report.table());
+        std::process::exit(if report.failed > 0 { 1 } else { 0 });
+    }
+
+    // Logging: to stdout (and LOG_FILE) as text or JSON, and into a buffer
+    // for GET /api/logs/recent. Targets are named after the binary, `server`.
+    let log_buffer = Arc::new(logs::LogBuffer::default());
+    let log_output = logs::output::LogOutput::init(log_buffer.clone());
 
     tracing::info!("=== Shared Memory Network starting ===");
 
     // Database
-    let db_url = std::env::var("DATABASE_URL")
-        .unwrap_or_else(|_| "sqlite:./data/shared_memory.db".to_string());
-    let pool = db::init_pool(&db_url).await?;
-    tracing::info!("Database ready");
+    let db_url = db::database_url();
+    let database = db::open(&db_url, db::read_only_allowed()).await?;
+    let pool = database.pool;
+    tracing::info!("Database ready{}", if database.read_only { " (read-only)" } else { "" });
+
+    // Settings: defaults filled in, then read once here and written through
+    // on every update
+    if !database.read_only {
+        if let Err(e) = settings::keys::seed(&pool).await {
+            tracing::warn!("Failed to write default settings: {}", e);
+        }
+    }
+    let settings = Arc::new(SettingsCache::load(pool.clone()).await?);
+    log_output.apply_setting(settings.get(logs::output::LOG_FORMAT_KEY).as_deref());
+    logs::output::spawn_child_log_cap();
+    cors::warn_if_wildcard(&settings.get_string(Key::CorsAllowedOrigins));
 
     // Memory providers
-    let providers = memory::detect_providers();
-    tracing::info!("Detected {} memory provider(s)", providers.len());
+    let providers = Arc::new(ProviderSet::new(memory::detect_providers()));
+    tracing::info!("Detected {} memory provider(s)", providers.load().len());
+
+    // Limits from settings and the environment; some only apply at startup
+    let limits = Arc::new(LimitsCache::load(&settings));
+
+    // Event bus: state changes and periodic stats on separate channels
+    let event_tx = EventBus::with_buffer(limits.get().ws_buffer);
+    // Subscribed before any background task can report a problem
+    let errors = Arc::new(RecentErrors::default());
+    errors.clone().spawn(&event_tx);
 
-    // WebSocket broadcast channel
-    let (event_tx, _) = broadcast::channel::<WsEvent>(256);
+    // The fallbacks below lean on the built-in roles, so they come first
+    if let Err(e) = permissions::ensure_builtin_roles(&pool).await {
+        tracing::error!("Failed to check the built-in roles: {}", e);
+    }
+    // Devices left pointing at a deleted role would escape role limits
+    let svc = permissions::PermissionService::new(pool.clone(), event_tx.clone());
+    if let Err(e) = svc.repair_dangling_roles().await {
+        tracing::warn!("Failed to check device roles: {}", e);
+    }
 
     // Ollama manager
-    let ollama_host = db::queries::get_setting(&pool, "ollama_host")
-        .await
-        .ok()
-        .flatten();
-    let ollama = Arc::new(OllamaManager::new(ollama_host));
+    let ollama_host = settings.get_string(Key::OllamaHost);
+    let auto_start = settings.get_bool(Key::AutoStartOllama);
+    let ollama = Arc::new(OllamaManager::new(Some(ollama_host), auto_start));
 
     // llama.cpp manager (for distributed inference)
-    let llama_cpp = Arc::new(LlamaCppManager::new(event_tx.clone()));
+    let llama_cpp = Arc::new(LlamaCppManager::new(event_tx.clone()).with_limits(limits.clone()));
     tracing::info!(
         "llama-rpc-server: {}",
         if LlamaCppManager::find_rpc_server_bin().is_some() { "found" } else { "not found" }
@@ -86,100 +168,187 @@ async fn main() -> Result<()> {
         if LlamaCppManager::find_inference_server_bin().is_some() { "found" } else { "not found" }
     );
     // Spawn background watchdog to detect crashed child processes
-    LlamaCppManager::spawn_watchdog(llama_cpp.clone());
+    LlamaCppManager::spawn_watchdog(llama_cpp.clone(), pool.clone(), providers.clone(), settings.clone());
+    // Broadcast slot/queue metrics while a session is active
+    LlamaCppManager::spawn_metrics_broadcaster(llama_cpp.clone());
+    // Mark devices offline once they stop answering, and back when they do
+    permissions::presence::spawn(pool.clone(), settings.clone(), llama_cpp.clone(), event_tx.clone());
 
-    // Auto-start Ollama
-    let auto_start = db::queries::get_setting(&pool, "auto_start_ollama")
-        .await
-        .unwrap_or(None)
-        .map(|v| v == "true")
-        .unwrap_or(true);
-
-    if auto_start {
+    // Auto-start Ollama (loopback hosts only; a remote one is left alone)
+    if ollama.mode() == OllamaMode::Managed {
         match ollama.ensure_running().await {
             Ok(()) => {
                 let _ = event_tx.send(WsEvent::OllamaStatus {
                     running: true,
-                    host: ollama.host.clone(),
+                    host: ollama.host(),
                 });
             }
             Err(e) => {
                 tracing::warn!("Ollama auto-start failed: {}. Continuing without it.", e);
                 let _ = event_tx.send(WsEvent::OllamaStatus {
                     running: false,
-                    host: ollama.host.clone(),
+                    host: ollama.host(),
                 });
             }
         }
-        // Start watchdog
-        ollama.clone().spawn_watchdog();
     }
+    // The watchdog idles unless the mode is Managed, which settings can change
+    ollama.clone().spawn_watchdog(event_tx.clone());
 
-    // mDNS: advertise this host
-    let _mdns_daemon = discovery::advertise().ok();
+    // Model pulls queued for off-peak hours or a later time
+    let pulls = Arc::new(PullQueue::new(
+        pool.clone(),
+        ollama.clone(),
+        settings.clone(),
+        event_tx.clone(),
+    ));
+    pulls.clone().spawn();
+
+    // Listen before advertising, so mDNS and the agent scripts get the port
+    // actually bound
+    let configured_port = listen::configured_port().map_err(anyhow::Error::msg)?;
+    let bind = listen::configured_bind_addr(&settings.get_string(Key::BindAddress)).map_err(anyhow::Error::msg)?;
+    let bound = match listen::bind(bind.ip, configured_port, listen::fallback_enabled()).await {
+        Ok(bound) => bound,
+        Err(e @ listen::BindError::InUse { .. }) => {
+            tracing::error!("{}", e);
+            std::process::exit(listen::EXIT_PORT_IN_USE);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let port = bound.port;
+    if let Some(conflict) = &bound.displaced {
+        tracing::warn!("{}; listening on port {} instead (PORT_FALLBACK)", conflict, port);
+    }
+    if !database.read_only {
+        if settings.get_u16_port(Key::ApiPort) != port {
+            if let Err(e) = settings.set(Key::ApiPort.name(), &port.to_string()).await {
+                tracing::warn!("Failed to record the API port: {}", e);
+            }
+        }
+        match permissions::address::normalize_stored(&pool).await {
+            Ok(0) => {}
+            Ok(n) => tracing::warn!("Merged {} devices registered twice under different spellings of one IP", n),
+            Err(e) => tracing::warn!("Failed to normalize stored device IPs: {}", e),
+        }
+    }
+
+    // mDNS: advertise this host, unless only this host can connect
+    let _mdns_daemon = match bind.reachable_ip() {
+        Some(ip) => discovery::advertise(ip, port).ok(),
+        None => {
+            tracing::warn!(
+                "Listening on {} only: not advertising over mDNS, and agents on other machines need a reverse proxy to reach this server",
+                bind.ip
+            );
+            None
+        }
+    };
 
     // mDNS: browse for other devices
-    let mdns_enabled = db::queries::get_setting(&pool, "mdns_enabled")
-        .await
-        .unwrap_or(None)
-        .map(|v| v == "true")
-        .unwrap_or(true);
+    let mdns_enabled = settings.get_bool(Key::MdnsEnabled);
 
     if mdns_enabled {
         discovery::browse(event_tx.clone()).await.ok();
     }
 
+    // Webhooks: deliver notable events to configured endpoints
+    let webhooks = Arc::new(WebhookDispatcher::new(pool.clone()));
+    webhooks.clone().spawn(&event_tx);
+
+    // Memory stats: sampled per provider kind, cached, broadcast as memory_stats
+    let memory = Arc::new(MemorySampler::new(providers.clone(), settings.clone()));
+    memory.clone().spawn(llama_cpp.clone(), event_tx.clone());
+    // A GPU plugged in (or a driver back) after startup is picked up by re-detection
+    memory::hotplug::spawn(providers.clone(), memory.clone(), event_tx.clone());
+
+    // Inference reservations: nothing is running yet, so anything left is stale
+    match reservations::release_stale(&pool).await {
+        Ok(0) => {}
+        Ok(n) => tracing::info!("Released {} stale inference reservation(s)", n),
+        Err(e) => tracing::warn!("Failed to release stale inference reservations: {}", e),
+    }
+    reservations::spawn(pool.clone(), memory.clone(), llama_cpp.clone(), &event_tx);
+    // Sessions go into inference_sessions, for when each model was last used
+    llama_cpp::history::spawn(pool.clone(), &event_tx);
+    // Deleted models wait in each model directory's .trash for model_trash_days
+    llama_cpp::store::spawn_purger(pool.clone(), settings.clone());
+    // Drops model uploads abandoned for an hour
+    let uploads = Arc::new(api::model_uploads::UploadRegistry::default());
+    uploads.clone().spawn_reaper();
+
     // App state
     let state = Arc::new(AppState {
         pool: pool.clone(),
         event_tx: event_tx.clone(),
         providers,
         ollama: ollama.clone(),
+        pulls,
         llama_cpp: llama_cpp.clone(),
+        webhooks,
+        settings,
+        memory,
+        remote_memory: Arc::new(RemoteMemoryCache::default()),
+        rpc_restarts: Arc::new(permissions::restart::RpcRestarts::default()),
+        start_keys: Arc::new(api::idempotency::IdempotencyKeys::default()),
+        device_health: Arc::new(FlapTracker::default()),
+        limits,
+        uploads,
+        peers: Arc::new(federation::PeerCache::default()),
+        inventory: Arc::new(inventory::InventoryCache::default()),
+        logs: log_buffer,
+        log_output,
+        errors,
+        ws_clients: Arc::new(WsClientRegistry::default()),
+        key_limiter: Arc::new(api_keys::KeyRateLimiter::default()),
+        read_only: database.read_only,
+        port,
+        bind,
     });
 
-    // Spawn GPU stats broadcaster (every 3 seconds)
-    {
-        let state_clone = state.clone();
-        tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(tokio::time::Duration::from_secs(3));
-            loop {
-                ticker.tick().await;
-                let snapshots = memory::aggregate_snapshot_async(&state_clone.providers).await;
-                let _ = state_clone.event_tx.send(WsEvent::MemoryStats { snapshots });
-            }
-        });
-    }
+    // Broadcast the dashboard overview when it changes materially
+    overview::spawn(state.clone());
 
     // mDNS device-auto-register task: listen for DeviceDiscovered events and register them
     {
         let pool_clone = pool.clone();
         let tx_clone = event_tx.clone();
-        let mut rx = event_tx.subscribe();
+        let mut rx = event_tx.subscribe_critical();
         tokio::spawn(async move {
             while let Ok(event) = rx.recv().await {
                 if let WsEvent::DeviceDiscovered { ip, name, hostname: _, method } = event {
                     let svc = permissions::PermissionService::new(pool_clone.clone(), tx_clone.clone());
-                    if let Err(e) = svc.register_device(name, ip, None, &method).await {
-                        tracing::warn!("Failed to register discovered device: {}", e);
+                    if let Err(e) = svc.register_device(name.clone(), ip.clone(), None, &method).await {
+                        // Deleted by hand and not to be restored: stay quiet about it
+                        if e.downcast_ref::<permissions::DeviceDeleted>().is_some() {
+                            tracing::debug!("Skipping discovered device {} ({}): it was deleted", name, ip);
+                            continue;
+                        }
+                        notify_error(
+                            &tx_clone,
+                            Severity::Warning,
+                            "discovery",
+                            format!("Failed to register discovered device {} ({}): {}", name, ip, e),
+                        );
                     }
                 }
             }
         });
     }
 
+    // Resume the last inference session once its devices are back (opt-in)
+    api::resume::spawn_on_start(state.clone());
+
     // Build router
-    let app = build_router(state);
+    let frontend_dir = frontend::configured_dir();
+    let app = build_router(state, frontend_dir);
 
     // Start server
-    let port = std::env::var("PORT").unwrap_or_else(|_| "8080".to_string());
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-
-    tracing::info!("Server listening on http://{}", addr);
+    tracing::info!("Server listening on http://{}", std::net::SocketAddr::new(bind.ip, port));
     tracing::info!("Dashboard: http://localhost:{}", port);
 
-    axum::serve(listener, app).await?;
+    // Peer addresses let handlers map a caller to its device
+    axum::serve(bound.listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await?;
     Ok(())
 }
 
@@ -194,37 +363,95 @@ async fn add_security_headers(req: Request, next: Next) -> Response {
     response
 }
 
-fn build_router(state: Arc<AppState>) -> Router {
-    // Only allow requests from localhost / 127.0.0.1 origins (VULN-06)
-    let cors = CorsLayer::new()
-        .allow_origin(AllowOrigin::predicate(|origin, _req_head| {
-            let s = origin.as_bytes();
-            s.starts_with(b"http://localhost")
-                || s.starts_with(b"http://127.0.0.1")
-                || s.starts_with(b"https://localhost")
-                || s.starts_with(b"https://127.0.0.1")
-        }))
-        .allow_methods(tower_http::cors::Any)
-        .allow_headers(tower_http::cors::Any);
-
-    Router::new()
+// ─── Read-only database middleware ────────────────────────────────────────────
+
+/// Requests that change nothing stored, despite their method.
+const WRITES_NOTHING: &[&str] = &[
+    "/v1/",
+    "/api/ollama/chat",
+    "/api/ollama/generate",
+    "/api/ollama/unload",
+    "/api/gpu/refresh",
+    "/api/ws/clients/",
+];
+
+/// With a read-only database, refuse requests that would write to it with a
+/// 503 up front, rather than failing halfway through.
+async fn refuse_writes_when_read_only(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let mutating = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+    let path = req.uri().path();
+    if state.read_only && mutating && !WRITES_NOTHING.iter().any(|p| path.starts_with(p)) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "database is read-only" })),
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+fn build_router(state: Arc<AppState>, frontend_dir: Option<std::path::PathBuf>) -> Router {
+    // Only origins in cors_allowed_origins, localhost by default (VULN-06)
+    let cors = cors::layer(state.settings.clone());
+
+    let router = Router::new()
         // WebSocket
         .route("/ws", get(api::ws_handler::ws_handler))
+        .route("/api/ws/clients", get(api::ws_handler::list_ws_clients))
+        .route("/api/ws/clients/:id", delete(api::ws_handler::close_ws_client))
         // Devices
         .route("/api/devices", get(api::devices::list_devices))
         .route("/api/devices", post(api::devices::add_device))
+        .route("/api/devices/import", post(api::devices::import_devices))
+        .route("/api/devices/imports", get(api::devices::list_device_imports))
         .route("/api/devices/:id", get(api::devices::get_device))
         .route("/api/devices/:id", delete(api::devices::delete_device))
+        .route("/api/devices/:id", patch(api::devices::update_device))
         .route("/api/devices/:id/approve", post(api::devices::approve_device))
         .route("/api/devices/:id/deny", post(api::devices::deny_device))
+        .route("/api/devices/:id/suspend", post(api::devices::suspend_device))
+        .route("/api/devices/:id/restore", post(api::devices::restore_device))
         .route("/api/devices/:id/memory", patch(api::devices::allocate_memory))
+        .route("/api/devices/:id/memory-stats", patch(api::devices::set_memory_stats))
+        .route("/api/devices/:id/tags", patch(api::devices::set_device_tags))
+        .route("/api/devices/:id/health", get(api::devices::device_health))
+        .route("/api/devices/:id/reset-health", post(api::devices::reset_device_health))
+        .route("/api/devices/:id/rpc/restart", post(api::devices::restart_device_rpc))
         // GPU / Memory stats
         .route("/api/gpu", get(api::gpu::get_gpu_stats))
+        .route("/api/gpu/refresh", post(api::gpu::refresh_gpu_stats))
+        .route("/api/gpu/redetect", post(api::gpu::redetect_providers))
         // Models / Ollama
         .route("/api/models", get(api::models::list_models))
         .route("/api/models/pull", post(api::models::pull_model))
+        .route("/api/models/pull/queue", get(api::models::list_pull_queue))
+        .route("/api/models/pull/queue/:id", delete(api::models::cancel_queued_pull))
+        .route("/api/models/aliases", get(api::models::list_aliases))
+        .route("/api/models/aliases", post(api::models::create_alias))
+        .route("/api/models/aliases/:alias", put(api::models::update_alias))
+        .route("/api/models/aliases/:alias", delete(api::models::delete_alias))
+        .route("/api/models/files", get(api::model_files::list_model_files))
+        .route("/api/models/files/:alias", get(api::model_files::serve_model_file))
+        .route("/api/models/fetch-from-peer", post(api::model_files::fetch_from_peer))
+        .route("/api/models/upload", post(api::model_uploads::init_upload))
+        .route("/api/models/upload/:id", get(api::model_uploads::upload_status))
+        .route(
+            "/api/models/upload/:id/chunk",
+            put(api::model_uploads::upload_chunk)
+                .layer(DefaultBodyLimit::max(api::model_uploads::MAX_CHUNK_BYTES)),
+        )
+        .route("/api/models/upload/:id/finalize", post(api::model_uploads::finalize_upload))
         .route("/api/models/:name", delete(api::models::delete_model))
+        .route("/api/models/:name/details", get(api::models::model_details))
         .route("/api/ollama/status", get(api::models::ollama_status))
+        .route("/api/ollama/ps", get(api::models::ollama_ps))
+        .route("/api/ollama/unload", post(api::models::ollama_unload))
+        .route("/api/ollama/chat", post(api::models::ollama_chat))
+        .route("/api/ollama/generate", post(api::models::ollama_generate))
         // Permissions / Roles
         .route("/api/permissions/roles", get(api::permissions::list_roles))
         .route("/api/permissions/roles", post(api::permissions::create_role))
@@ -233,34 +460,94 @@ fn build_router(state: Arc<AppState>) -> Router {
         // Settings
         .route("/api/settings", get(api::settings::list_settings))
         .route("/api/settings/:key", put(api::settings::update_setting))
+        // Setup
+        .route("/api/setup/status", get(api::setup::setup_status))
+        .route("/api/setup/init", post(api::setup::setup_init))
+        // Webhooks
+        .route("/api/webhooks", get(api::webhooks::list_webhooks))
+        .route("/api/webhooks", post(api::webhooks::create_webhook))
+        .route("/api/webhooks/:id", put(api::webhooks::update_webhook))
+        .route("/api/webhooks/:id", delete(api::webhooks::delete_webhook))
+        .route("/api/webhooks/:id/test", post(api::webhooks::test_webhook))
+        // OpenAI endpoint API keys
+        .route("/api/keys", get(api::keys::list_keys))
+        .route("/api/keys", post(api::keys::create_key))
+        .route("/api/keys/:id", put(api::keys::update_key))
+        .route("/api/keys/:id", delete(api::keys::revoke_key))
         // Inference backend config
         .route("/api/backends/config", get(api::backends::get_backend_config))
         .route("/api/backends/config", post(api::backends::set_backend_config))
         .route("/api/backends/models", get(api::backends::list_backend_models))
+        .route("/api/backends/profiles", get(api::backends::list_profiles))
+        .route("/api/backends/profiles", post(api::backends::create_profile))
+        .route("/api/backends/profiles/:id", put(api::backends::update_profile))
+        .route("/api/backends/profiles/:id", delete(api::backends::delete_profile))
+        .route("/api/backends/profiles/:id/activate", post(api::backends::activate_profile))
         // Cluster / Distributed inference
         .route("/api/cluster/status", get(api::cluster::cluster_status))
         .route("/api/cluster/model-check", get(api::cluster::model_check))
+        .route("/api/cluster/models", delete(api::model_store::delete_model_file))
+        .route("/api/cluster/models/usage", get(api::model_store::model_usage))
         .route("/api/cluster/inference/start", post(api::cluster::start_inference))
         .route("/api/cluster/inference/stop", post(api::cluster::stop_inference))
+        .route("/api/cluster/inference/resume-last", post(api::resume::resume_last_session))
+        .route("/api/cluster/presets", get(api::presets::list_presets))
+        .route("/api/cluster/presets", post(api::presets::create_preset))
+        .route("/api/cluster/presets/:id", put(api::presets::update_preset))
+        .route("/api/cluster/presets/:id", delete(api::presets::delete_preset))
+        .route("/api/cluster/presets/:id/start", post(api::presets::start_preset))
         .route("/api/cluster/inference/status", get(api::cluster::inference_status))
+        .route("/api/cluster/inference/metrics", get(api::cluster::inference_metrics))
         .route("/api/cluster/rpc/start", post(api::cluster::start_rpc_server))
         .route("/api/cluster/rpc/stop", post(api::cluster::stop_rpc_server))
+        .route("/api/cluster/ports", get(api::cluster::cluster_ports))
+        .route("/api/cluster/binaries", get(api::cluster::cluster_binaries))
         // Binary installer (streams NDJSON progress)
         .route("/api/cluster/install-binaries", post(api::install::install_binaries))
-        // OpenAI-compatible API proxy → llama-server
-        .route("/v1/models", get(api::cluster::models_proxy))
-        .route("/v1/chat/completions", post(api::cluster::chat_completions_proxy))
-        // Agent install scripts
+        // OpenAI-compatible API proxy → llama-server, behind `require_api_keys`
+        .merge(
+            Router::new()
+                .route("/v1/models", get(api::cluster::models_proxy))
+                .route("/v1/chat/completions", post(api::cluster::chat_completions_proxy))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    api_keys::require_api_key,
+                )),
+        )
+        // Agent heartbeat + install scripts
+        .route("/api/agent/heartbeat", post(api::agent::heartbeat))
         .route("/agent/install", get(api::agent::install_script))
+        .route("/agent/uninstall", get(api::agent::uninstall_script))
         .route("/agent/info", get(api::agent::agent_info))
-        // Serve static frontend (production)
-        .nest_service(
-            "/",
-            tower_http::services::ServeDir::new("../frontend/dist")
-                .not_found_service(tower_http::services::ServeFile::new("../frontend/dist/index.html")),
-        )
+        // Logs
+        .route("/api/logs/recent", get(api::logs::recent_logs))
+        .route("/api/errors/recent", get(api::errors::recent_errors))
+        // System
+        .route("/api/health", get(api::system::health))
+        .route("/api/overview", get(api::system::overview))
+        .route("/api/system/info", get(api::system::system_info))
+        .route("/api/diagnostics", get(api::system::diagnostics))
+        .route("/api/system/backups", get(api::system::list_backups))
+        .route("/api/system/backups/restore", post(api::system::restore_backup))
+        .route("/api/support-bundle", get(api::system::support_bundle))
+        // Disk space
+        .route("/api/system/disk", get(api::system::disk_report))
+        .route("/api/system/disk/cleanup", post(api::system::disk_cleanup))
+        // OpenAPI spec + Swagger UI
+        .route("/api/openapi.json", get(api::openapi::openapi_json))
+        .route("/api/docs", get(api::openapi::swagger_ui_index))
+        .route("/api/docs/", get(api::openapi::swagger_ui))
+        .route("/api/docs/*file", get(api::openapi::swagger_ui));
+
+    // Serve static frontend (production) as the fallback for unmatched paths
+    frontend::attach(router, frontend_dir)
+        .layer(axum::middleware::from_fn_with_state(state.clone(), refuse_writes_when_read_only))
+        // Before the read-only check, so an unauthorized caller learns nothing
+        .layer(axum::middleware::from_fn_with_state(state.clone(), api_keys::require_dashboard_key))
         .layer(cors)
         .layer(TraceLayer::new_for_http())
         .layer(axum::middleware::from_fn(add_security_headers))
+        // Outermost, so the trace span and error bodies see the request ID
+        .layer(axum::middleware::from_fn(logs::assign_request_id))
         .with_state(state)
 }
@@ -2,6 +2,7 @@ use super::{GpuKind, MemoryProvider};
 
 /// Apple Silicon unified memory via sysctl.
 /// Only activates on Macs with Apple Silicon (ARM) CPUs.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
 pub struct AppleProvider {
     name: String,
     total_mb: u64,
@@ -80,6 +81,7 @@ impl AppleProvider {
         })
     }
 
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
     fn query_used_mb(&self) -> u64 {
         // Use vm_stat to calculate used memory.
         // Page size on Apple Silicon is 16 KiB.
@@ -120,6 +122,7 @@ impl AppleProvider {
     }
 }
 
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
 fn extract_pages(line: &str) -> u64 {
     line.split(':')
         .nth(1)
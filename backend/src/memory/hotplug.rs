@@ -0,0 +1,180 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use tokio::time::Duration;
+
+use super::{detect_providers, sampler::MemorySampler, sampler::ProviderHealth, GpuKind, MemoryProvider};
+use crate::ws::{EventBus, WsEvent};
+
+/// Seconds between checks for whether detection should run again.
+const CHECK_SECS: u64 = 300;
+
+/// A detected provider, as listed in `providers_changed`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ProviderInfo {
+    pub provider_id: String,
+    pub name: String,
+    pub kind: GpuKind,
+}
+
+impl ProviderInfo {
+    pub fn of(p: &dyn MemoryProvider) -> Self {
+        ProviderInfo { provider_id: p.id().to_string(), name: p.name().to_string(), kind: p.kind() }
+    }
+}
+
+/// Providers found or lost by a new detection.
+#[derive(Debug, Clone, Default, Serialize, utoipa::ToSchema)]
+pub struct ProvidersChange {
+    pub added: Vec<ProviderInfo>,
+    pub removed: Vec<ProviderInfo>,
+}
+
+impl ProvidersChange {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+type ProviderList = Arc<Vec<Arc<dyn MemoryProvider>>>;
+
+/// The memory providers in use. Detection can run again while the server
+/// is up, so the list is replaced whole: readers take the current list and
+/// never hold the lock across a sample.
+pub struct ProviderSet {
+    current: RwLock<ProviderList>,
+    /// Held while detection runs, so two never race to store their result
+    detecting: tokio::sync::Mutex<()>,
+}
+
+impl ProviderSet {
+    pub fn new(providers: Vec<Arc<dyn MemoryProvider>>) -> Self {
+        ProviderSet {
+            current: RwLock::new(Arc::new(providers)),
+            detecting: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// The current providers.
+    pub fn load(&self) -> ProviderList {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the providers and return what changed, by provider ID.
+    pub fn store(&self, providers: Vec<Arc<dyn MemoryProvider>>) -> ProvidersChange {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        let has = |list: &[Arc<dyn MemoryProvider>], id: &str| list.iter().any(|p| p.id() == id);
+        let change = ProvidersChange {
+            added: providers
+                .iter()
+                .filter(|p| !has(&current, p.id()))
+                .map(|p| ProviderInfo::of(p.as_ref()))
+                .collect(),
+            removed: current
+                .iter()
+                .filter(|p| !has(&providers, p.id()))
+                .map(|p| ProviderInfo::of(p.as_ref()))
+                .collect(),
+        };
+        *current = Arc::new(providers);
+        change
+    }
+
+    /// Run detection again (it shells out, so off the runtime) and swap in
+    /// the result.
+    pub async fn redetect(&self) -> Result<ProvidersChange> {
+        let _detecting = self.detecting.lock().await;
+        let providers = tokio::task::spawn_blocking(detect_providers)
+            .await
+            .map_err(|e| anyhow!("Provider detection failed: {}", e))?;
+        Ok(self.store(providers))
+    }
+}
+
+/// Re-detect providers, then have `sampler` drop the lost ones and sample
+/// the new ones right away. A change is broadcast as `providers_changed`.
+pub async fn redetect(
+    providers: &ProviderSet,
+    sampler: &MemorySampler,
+    event_tx: &EventBus,
+) -> Result<ProvidersChange> {
+    let change = providers.redetect().await?;
+    if !change.is_empty() {
+        tracing::info!(
+            "Memory providers changed: +[{}] -[{}]",
+            names(&change.added),
+            names(&change.removed)
+        );
+        sampler.forget(change.removed.iter().map(|p| p.provider_id.as_str()));
+        sampler.wake();
+        let _ = event_tx.send(WsEvent::ProvidersChanged {
+            added: change.added.clone(),
+            removed: change.removed.clone(),
+        });
+    }
+    Ok(change)
+}
+
+fn names(list: &[ProviderInfo]) -> String {
+    list.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+/// Whether the periodic check should run detection: nothing but system RAM
+/// was found (a GPU may have appeared since), or a provider has stopped
+/// answering (its driver may have come back under another ID).
+fn should_redetect(providers: &[Arc<dyn MemoryProvider>], health: &[ProviderHealth]) -> bool {
+    providers.iter().all(|p| p.kind() == GpuKind::SystemRam) || health.iter().any(|h| h.degraded)
+}
+
+/// Check every [`CHECK_SECS`] whether detection should run again.
+pub fn spawn(providers: Arc<ProviderSet>, sampler: Arc<MemorySampler>, event_tx: EventBus) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(CHECK_SECS)).await;
+            if !should_redetect(&providers.load(), &sampler.health()) {
+                continue;
+            }
+            if let Err(e) = redetect(&providers, &sampler, &event_tx).await {
+                tracing::warn!("{}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::system_ram::SystemRamProvider;
+    use crate::memory::tests::SlowProvider;
+
+    #[test]
+    fn swapping_reports_added_and_removed_providers() {
+        let ram: Arc<dyn MemoryProvider> = Arc::new(SystemRamProvider::new());
+        let set = ProviderSet::new(vec![ram.clone()]);
+        assert!(should_redetect(&set.load(), &[]));
+
+        let before = set.load();
+        let gpu: Arc<dyn MemoryProvider> = SlowProvider::new("hotplug-egpu", 0);
+        let change = set.store(vec![gpu.clone(), ram.clone()]);
+        assert_eq!(change.added.len(), 1);
+        assert_eq!(change.added[0].provider_id, "hotplug-egpu");
+        assert!(change.removed.is_empty());
+        // A list taken before the swap is left as it was
+        assert_eq!(before.len(), 1);
+        assert_eq!(set.load().len(), 2);
+        assert!(!should_redetect(&set.load(), &[]));
+
+        let degraded = ProviderHealth {
+            provider_id: "hotplug-egpu".into(),
+            name: "Slow GPU".into(),
+            consecutive_failures: 3,
+            degraded: true,
+        };
+        assert!(should_redetect(&set.load(), &[degraded]));
+
+        let change = set.store(vec![ram]);
+        assert!(change.added.is_empty());
+        assert_eq!(change.removed[0].name, "Slow GPU");
+        assert!(set.store(set.load().to_vec()).is_empty());
+    }
+}
@@ -1,61 +1,145 @@
 use super::{GpuKind, MemoryProvider};
 
-/// Intel integrated GPU via sysfs (Linux) or system_profiler (macOS).
-/// On Linux, the iGPU shares system RAM and doesn't expose precise usage
-/// via standard sysfs — we use GuC/lmem sysfs when available, otherwise
+/// Intel GPUs via sysfs (Linux) or system_profiler (macOS).
+///
+/// Discrete cards (Arc / Data Center GPU) have dedicated local memory ("lmem")
+/// that the i915 and xe drivers expose through sysfs; those providers report the
+/// real VRAM total and read usage from the same nodes. Integrated GPUs share
+/// system RAM and don't expose precise usage via standard sysfs — for those we
 /// report used as a conservative fraction of the shared pool.
 pub struct IntelProvider {
+    id: String,
     name: String,
     total_mb: u64,
-    /// Optional sysfs path for used memory (Linux only)
-    #[allow(dead_code)]
-    lmem_used_path: Option<std::path::PathBuf>,
+    /// Where to read used VRAM from. `None` for integrated GPUs.
+    lmem: Option<LmemSource>,
 }
 
+/// Source of used-memory readings for a discrete card's local memory.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+enum LmemSource {
+    /// sysfs node holding the number of used bytes
+    UsedBytes(std::path::PathBuf),
+    /// sysfs node holding the number of available bytes (used = total - avail)
+    AvailBytes(std::path::PathBuf),
+    /// No sysfs usage node — sample `intel_gpu_top -J` for this DRM card (e.g. "card1"),
+    /// keeping the last used MB and when it was read
+    GpuTop {
+        card: String,
+        last: std::sync::Mutex<Option<(std::time::Instant, u64)>>,
+    },
+}
+
+/// Shortest gap between `intel_gpu_top` runs for one card; each can take up
+/// to 2 s, far too slow for every memory poll.
+#[cfg(target_os = "linux")]
+const GPU_TOP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// sysfs nodes (relative to /sys/class/drm/cardN) reporting local memory size in bytes.
+/// i915 exposes `lmem_total_bytes`; xe exposes the VRAM size per tile.
+#[cfg(target_os = "linux")]
+const LMEM_TOTAL_NODES: &[&str] = &[
+    "lmem_total_bytes",
+    "device/lmem_total_bytes",
+    "device/local_memory/total_bytes",
+    "device/tile0/physical_vram_size_bytes",
+];
+
+/// sysfs nodes reporting used local memory in bytes.
+#[cfg(target_os = "linux")]
+const LMEM_USED_NODES: &[&str] = &[
+    "lmem_used_bytes",
+    "device/lmem_used_bytes",
+    "device/local_memory/used_bytes",
+];
+
+/// sysfs nodes reporting available local memory in bytes.
+#[cfg(target_os = "linux")]
+const LMEM_AVAIL_NODES: &[&str] = &[
+    "lmem_avail_bytes",
+    "device/lmem_avail_bytes",
+    "device/local_memory/avail_bytes",
+];
+
 impl IntelProvider {
-    pub fn detect() -> Option<Self> {
-        // Linux: look for i915 / xe (Xe2) driver in /sys/class/drm
+    /// Detect every Intel GPU on this machine. Each card gets a distinct id
+    /// (`intel-0`, `intel-1`, …) in DRM card order.
+    pub fn detect_all() -> Vec<Self> {
+        #[allow(unused_mut)]
+        let mut providers: Vec<IntelProvider> = Vec::new();
+
+        // Linux: look for cards bound to the i915 / xe (Xe2) driver in /sys/class/drm
         #[cfg(target_os = "linux")]
         {
-            let drm_path = std::path::Path::new("/sys/class/drm");
-            if drm_path.exists() {
-                for entry in std::fs::read_dir(drm_path).ok()?.flatten() {
-                    let driver_link = entry.path().join("device/driver");
-                    if let Ok(link) = std::fs::read_link(&driver_link) {
-                        let link_str = link.to_string_lossy();
-                        if link_str.contains("i915") || link_str.contains("xe") {
-                            // Intel iGPU shares system RAM; report a portion as "VRAM"
-                            let mut sys = sysinfo::System::new();
-                            sys.refresh_memory();
-                            let total_mb = sys.total_memory() / (1024 * 1024);
-                            let igpu_mb = total_mb / 2;
-
-                            // Check for lmem (discrete-style local memory) usage path
-                            let lmem_path = entry.path().join("device/drm/card0/lmem0/used");
-                            let lmem_used_path = if lmem_path.exists() {
-                                Some(lmem_path)
-                            } else {
-                                None
-                            };
-
-                            return Some(IntelProvider {
-                                name: "Intel Integrated GPU".into(),
-                                total_mb: igpu_mb,
-                                lmem_used_path,
-                            });
-                        }
-                    }
+            for (card, card_path) in drm_cards() {
+                let driver = std::fs::read_link(card_path.join("device/driver"))
+                    .ok()
+                    .and_then(|l| l.file_name().map(|n| n.to_string_lossy().to_string()))
+                    .unwrap_or_default();
+                if driver != "i915" && driver != "xe" {
+                    continue;
+                }
+
+                let id = format!("intel-{}", providers.len());
+
+                // Discrete card with dedicated VRAM reported via sysfs
+                if let Some(total_bytes) = first_bytes(&card_path, LMEM_TOTAL_NODES) {
+                    let lmem = existing_node(&card_path, LMEM_USED_NODES)
+                        .map(LmemSource::UsedBytes)
+                        .or_else(|| {
+                            existing_node(&card_path, LMEM_AVAIL_NODES).map(LmemSource::AvailBytes)
+                        })
+                        .unwrap_or_else(|| LmemSource::GpuTop {
+                            card: card.clone(),
+                            last: Default::default(),
+                        });
+                    providers.push(IntelProvider {
+                        id,
+                        name: format!("Intel Discrete GPU ({card})"),
+                        total_mb: total_bytes / (1024 * 1024),
+                        lmem: Some(lmem),
+                    });
+                    continue;
                 }
+
+                // Older kernels don't expose lmem nodes — ask intel_gpu_top instead
+                if let Some((total_bytes, free_bytes)) = gpu_top_local_memory(&card) {
+                    let used_mb = total_bytes.saturating_sub(free_bytes) / (1024 * 1024);
+                    providers.push(IntelProvider {
+                        id,
+                        name: format!("Intel Discrete GPU ({card})"),
+                        total_mb: total_bytes / (1024 * 1024),
+                        lmem: Some(LmemSource::GpuTop {
+                            card: card.clone(),
+                            last: std::sync::Mutex::new(Some((std::time::Instant::now(), used_mb))),
+                        }),
+                    });
+                    continue;
+                }
+
+                // True iGPU: shares system RAM; report a portion as "VRAM"
+                let mut sys = sysinfo::System::new();
+                sys.refresh_memory();
+                let total_mb = sys.total_memory() / (1024 * 1024);
+                providers.push(IntelProvider {
+                    id,
+                    name: "Intel Integrated GPU".into(),
+                    total_mb: total_mb / 2,
+                    lmem: None,
+                });
             }
         }
 
         // macOS with Intel iGPU (not Apple Silicon)
         #[cfg(target_os = "macos")]
         {
-            let out = std::process::Command::new("system_profiler")
+            let out = match std::process::Command::new("system_profiler")
                 .args(["SPDisplaysDataType", "-json"])
                 .output()
-                .ok()?;
+            {
+                Ok(o) => o,
+                Err(_) => return providers,
+            };
             let s = String::from_utf8_lossy(&out.stdout);
             if let Ok(json) = serde_json::from_str::<serde_json::Value>(&s) {
                 if let Some(displays) = json["SPDisplaysDataType"].as_array() {
@@ -71,10 +155,11 @@ impl IntelProvider {
                                 .next()
                                 .and_then(|s| s.parse().ok())
                                 .unwrap_or(1536);
-                            return Some(IntelProvider {
+                            providers.push(IntelProvider {
+                                id: format!("intel-{}", providers.len()),
                                 name: format!("Intel iGPU ({})", name),
                                 total_mb: vram_mb,
-                                lmem_used_path: None,
+                                lmem: None,
                             });
                         }
                     }
@@ -82,21 +167,47 @@ impl IntelProvider {
             }
         }
 
-        None
+        providers
+    }
+
+    /// Whether this card has dedicated VRAM (as opposed to sharing system RAM).
+    pub fn is_discrete(&self) -> bool {
+        self.lmem.is_some()
     }
 
     fn query_used_mb(&self) -> u64 {
-        // Linux: try lmem sysfs first (available on some Gen12+ configs)
-        #[cfg(target_os = "linux")]
-        if let Some(path) = &self.lmem_used_path {
-            if let Ok(s) = std::fs::read_to_string(path) {
-                if let Ok(bytes) = s.trim().parse::<u64>() {
-                    return bytes / (1024 * 1024);
+        match &self.lmem {
+            Some(LmemSource::UsedBytes(path)) => read_bytes(path)
+                .map(|b| b / (1024 * 1024))
+                .unwrap_or(0),
+            Some(LmemSource::AvailBytes(path)) => read_bytes(path)
+                .map(|avail| self.total_mb.saturating_sub(avail / (1024 * 1024)))
+                .unwrap_or(0),
+            #[cfg(target_os = "linux")]
+            Some(LmemSource::GpuTop { card, last }) => {
+                let mut last = last.lock().unwrap_or_else(|e| e.into_inner());
+                match *last {
+                    Some((at, used_mb)) if at.elapsed() < GPU_TOP_INTERVAL => used_mb,
+                    prev => {
+                        // A failed run keeps the previous reading until the next try
+                        let used_mb = gpu_top_local_memory(card)
+                            .map(|(total, free)| total.saturating_sub(free) / (1024 * 1024))
+                            .or(prev.map(|(_, used_mb)| used_mb))
+                            .unwrap_or(0);
+                        *last = Some((std::time::Instant::now(), used_mb));
+                        used_mb
+                    }
                 }
             }
+            #[cfg(not(target_os = "linux"))]
+            Some(LmemSource::GpuTop { .. }) => 0,
+            None => self.shared_used_mb(),
         }
+    }
 
-        // Fallback: read /proc/meminfo to estimate GPU-shared memory usage.
+    /// iGPU usage estimate: a proportional share of overall system RAM use.
+    fn shared_used_mb(&self) -> u64 {
+        // Read /proc/meminfo to estimate GPU-shared memory usage.
         // MemAvailable gives free+reclaimable. Used ≈ MemTotal - MemAvailable.
         // Since the iGPU shares RAM, this is an approximation.
         #[cfg(target_os = "linux")]
@@ -125,6 +236,96 @@ impl IntelProvider {
     }
 }
 
+fn read_bytes(path: &std::path::Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// All `/sys/class/drm/cardN` entries (connectors and render nodes skipped),
+/// sorted by card number.
+#[cfg(target_os = "linux")]
+fn drm_cards() -> Vec<(String, std::path::PathBuf)> {
+    let mut cards: Vec<(u32, String, std::path::PathBuf)> = std::fs::read_dir("/sys/class/drm")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let num: u32 = name.strip_prefix("card")?.parse().ok()?;
+                    Some((num, name, entry.path()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    cards.sort_by_key(|(num, _, _)| *num);
+    cards.into_iter().map(|(_, name, path)| (name, path)).collect()
+}
+
+#[cfg(target_os = "linux")]
+fn existing_node(card_path: &std::path::Path, nodes: &[&str]) -> Option<std::path::PathBuf> {
+    nodes
+        .iter()
+        .map(|n| card_path.join(n))
+        .find(|p| read_bytes(p).is_some())
+}
+
+#[cfg(target_os = "linux")]
+fn first_bytes(card_path: &std::path::Path, nodes: &[&str]) -> Option<u64> {
+    existing_node(card_path, nodes)
+        .and_then(|p| read_bytes(&p))
+        .filter(|b| *b > 0)
+}
+
+/// Sample `intel_gpu_top -J` once for `card` and return its local memory region
+/// as (total_bytes, free_bytes). intel_gpu_top streams records forever, so we
+/// read the first complete JSON object and kill it; a 2-second deadline guards
+/// against builds that never print anything (e.g. missing perf permissions).
+#[cfg(target_os = "linux")]
+fn gpu_top_local_memory(card: &str) -> Option<(u64, u64)> {
+    use std::io::BufRead;
+
+    let mut child = std::process::Command::new("intel_gpu_top")
+        .args(["-J", "-s", "100", "-d", &format!("drm:/dev/dri/{card}")])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .ok()?;
+    let stdout = child.stdout.take()?;
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut record = String::new();
+        let mut depth: i32 = 0;
+        for line in std::io::BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            let trimmed = line.trim();
+            // The output is one long JSON array; skip its opening bracket
+            if depth == 0 && (trimmed.is_empty() || trimmed == "[") {
+                continue;
+            }
+            depth += trimmed.matches('{').count() as i32;
+            depth -= trimmed.matches('}').count() as i32;
+            record.push_str(&line);
+            record.push('\n');
+            if depth <= 0 {
+                break;
+            }
+        }
+        let _ = tx.send(record);
+    });
+
+    let record = rx.recv_timeout(std::time::Duration::from_secs(2)).ok();
+    let _ = child.kill();
+    let _ = child.wait();
+
+    let record = record?;
+    let json: serde_json::Value =
+        serde_json::from_str(record.trim().trim_end_matches(',')).ok()?;
+    let local = &json["memory"]["local"];
+    let total = local["total"].as_u64().filter(|t| *t > 0)?;
+    let free = local["free"].as_u64().unwrap_or(total);
+    Some((total, free))
+}
+
 #[cfg(target_os = "linux")]
 fn parse_kb(line: &str) -> u64 {
     line.split_whitespace()
@@ -135,7 +336,7 @@ fn parse_kb(line: &str) -> u64 {
 
 impl MemoryProvider for IntelProvider {
     fn id(&self) -> &str {
-        "intel"
+        &self.id
     }
     fn name(&self) -> &str {
         &self.name
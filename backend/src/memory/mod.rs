@@ -1,14 +1,19 @@
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 pub mod apple;
 pub mod amd;
+pub mod hotplug;
 pub mod intel;
 pub mod nvidia;
+pub mod remote;
+pub mod sampler;
 pub mod system_ram;
 
 /// What kind of memory this provider represents
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum GpuKind {
     Nvidia,
@@ -19,7 +24,7 @@ pub enum GpuKind {
 }
 
 /// Snapshot of a single memory provider's current state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct MemorySnapshot {
     pub provider_id: String,
     pub name: String,
@@ -27,7 +32,62 @@ pub struct MemorySnapshot {
     pub total_mb: u64,
     pub used_mb: u64,
     pub free_mb: u64,
-    pub allocated_mb: u64, // sum of all device allocations from this provider
+    pub allocated_mb: u64, // device allocations + inference reservations on this provider
+    /// Part of `allocated_mb` held by models Ollama keeps loaded
+    #[serde(default)]
+    pub ollama_mb: u64,
+    /// Free memory models may use: `free_mb` less the `host_reserved_mb`
+    /// floor on system RAM or unified memory
+    #[serde(default)]
+    pub usable_free_mb: u64,
+    /// The provider didn't answer the latest sample in time; this is its
+    /// last good reading
+    #[serde(default)]
+    pub stale: bool,
+}
+
+/// Setting: MB of local memory kept free for the host OS in fit
+/// calculations. "auto" (the default) picks [`default_host_reserved_mb`].
+pub const HOST_RESERVED_KEY: &str = "host_reserved_mb";
+
+/// Reservation for a host whose system or unified memory is `total_mb`.
+/// macOS pages hard once its unified memory fills, so it keeps the larger
+/// of 2 GB and 10%; Windows gets 2 GB and Linux 1 GB.
+pub fn default_host_reserved_mb(total_mb: u64) -> u64 {
+    if cfg!(target_os = "macos") {
+        (total_mb / 10).max(2048)
+    } else if cfg!(windows) {
+        2048
+    } else {
+        1024
+    }
+}
+
+/// The `host_reserved_mb` setting for a provider of `total_mb`.
+pub fn host_reserved_mb(setting: Option<&str>, total_mb: u64) -> u64 {
+    setting
+        .map(str::trim)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| default_host_reserved_mb(total_mb))
+}
+
+/// Set `usable_free_mb` on every snapshot. The OS runs in system RAM or
+/// unified memory, so only those providers give up the reservation; a
+/// discrete GPU's free memory is all usable.
+pub fn apply_host_reserve(snapshots: &mut [MemorySnapshot], setting: Option<&str>) {
+    for s in snapshots {
+        s.usable_free_mb = match s.kind {
+            GpuKind::SystemRam | GpuKind::AppleSilicon => {
+                s.free_mb.saturating_sub(host_reserved_mb(setting, s.total_mb))
+            }
+            _ => s.free_mb,
+        };
+    }
+}
+
+/// Memory the host reservation takes out of `snapshots`, in MB.
+pub fn host_reserved_total(snapshots: &[MemorySnapshot]) -> u64 {
+    snapshots.iter().map(|s| s.free_mb - s.usable_free_mb.min(s.free_mb)).sum()
 }
 
 /// Trait every memory provider must implement.
@@ -41,9 +101,11 @@ pub trait MemoryProvider: Send + Sync {
     fn snapshot(&self) -> Option<(u64, u64, u64)>;
 }
 
-/// Detect all available providers on this machine (runs at startup, blocking is fine)
+/// Detect all available providers on this machine. Blocking: it runs at
+/// startup, and in `spawn_blocking` when detection runs again.
 pub fn detect_providers() -> Vec<Arc<dyn MemoryProvider>> {
     let mut providers: Vec<Arc<dyn MemoryProvider>> = Vec::new();
+    #[cfg_attr(not(target_os = "macos"), allow(unused_mut))]
     let mut has_apple_silicon = false;
 
     // NVIDIA
@@ -66,9 +128,13 @@ pub fn detect_providers() -> Vec<Arc<dyn MemoryProvider>> {
         providers.push(Arc::new(p));
     }
 
-    // Intel integrated
-    if let Some(p) = intel::IntelProvider::detect() {
-        tracing::info!("Detected Intel iGPU: {}", p.name());
+    // Intel (integrated and discrete Arc cards)
+    for p in intel::IntelProvider::detect_all() {
+        if p.is_discrete() {
+            tracing::info!("Detected Intel discrete GPU: {}", p.name());
+        } else {
+            tracing::info!("Detected Intel iGPU: {}", p.name());
+        }
         providers.push(Arc::new(p));
     }
 
@@ -84,29 +150,95 @@ pub fn detect_providers() -> Vec<Arc<dyn MemoryProvider>> {
     providers
 }
 
-/// Aggregate snapshot across all providers.
-/// Runs provider `snapshot()` calls inside `spawn_blocking` to avoid
-/// blocking the async runtime with subprocess calls (nvidia-smi, rocm-smi, vm_stat).
-pub async fn aggregate_snapshot_async(providers: &[Arc<dyn MemoryProvider>]) -> Vec<MemorySnapshot> {
-    let providers_clone: Vec<Arc<dyn MemoryProvider>> = providers.to_vec();
-    tokio::task::spawn_blocking(move || {
-        providers_clone
-            .iter()
-            .filter_map(|p| {
-                p.snapshot().map(|(total, used, free)| MemorySnapshot {
-                    provider_id: p.id().to_string(),
-                    name: p.name().to_string(),
-                    kind: p.kind(),
-                    total_mb: total,
-                    used_mb: used,
-                    free_mb: free,
-                    allocated_mb: 0, // filled in by API layer from DB
-                })
-            })
-            .collect()
-    })
+/// How long one provider's `snapshot()` may take before its reading is
+/// given up on for this sample.
+pub const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Providers whose `snapshot()` is still running. A timed-out call can't be
+/// cancelled, so a provider stuck in e.g. nvidia-smi isn't called again
+/// until it returns, rather than tying up another blocking thread per sample.
+static READING: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// The outcome of asking one provider for a snapshot.
+#[derive(Debug)]
+pub enum Reading {
+    Fresh(MemorySnapshot),
+    /// The provider answered without a reading
+    Unavailable,
+    /// No answer within the timeout (or the previous call is still running)
+    TimedOut,
+}
+
+fn to_snapshot(p: &dyn MemoryProvider, (total, used, free): (u64, u64, u64)) -> MemorySnapshot {
+    MemorySnapshot {
+        provider_id: p.id().to_string(),
+        name: p.name().to_string(),
+        kind: p.kind(),
+        total_mb: total,
+        used_mb: used,
+        free_mb: free,
+        allocated_mb: 0, // filled in by API layer from DB
+        ollama_mb: 0,
+        usable_free_mb: free, // see apply_host_reserve
+        stale: false,
+    }
+}
+
+/// Read every provider at once, each in its own `spawn_blocking` task, so
+/// one slow provider (a hung nvidia-smi, rocm-smi or vm_stat) costs at most
+/// `limit` and doesn't hold up the others. Readings are in `providers` order.
+pub async fn read_providers(providers: &[Arc<dyn MemoryProvider>], limit: Duration) -> Vec<Reading> {
+    let tasks: Vec<_> = providers
+        .iter()
+        .map(|p| {
+            let id = p.id().to_string();
+            if !READING.lock().unwrap_or_else(|e| e.into_inner()).insert(id.clone()) {
+                return None;
+            }
+            let p = p.clone();
+            Some(tokio::task::spawn_blocking(move || {
+                let reading = p.snapshot().map(|r| to_snapshot(p.as_ref(), r));
+                READING.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+                reading
+            }))
+        })
+        .collect();
+
+    let deadline = tokio::time::Instant::now() + limit;
+    futures::future::join_all(tasks.into_iter().map(|task| async move {
+        let Some(task) = task else {
+            return Reading::TimedOut;
+        };
+        match tokio::time::timeout_at(deadline, task).await {
+            Ok(Ok(Some(snapshot))) => Reading::Fresh(snapshot),
+            Ok(Ok(None)) | Ok(Err(_)) => Reading::Unavailable,
+            Err(_) => Reading::TimedOut,
+        }
+    }))
     .await
-    .unwrap_or_default()
+}
+
+/// Aggregate snapshot across all providers: those that answered within
+/// [`SNAPSHOT_TIMEOUT`].
+pub async fn aggregate_snapshot_async(providers: &[Arc<dyn MemoryProvider>]) -> Vec<MemorySnapshot> {
+    read_providers(providers, SNAPSHOT_TIMEOUT)
+        .await
+        .into_iter()
+        .filter_map(|r| match r {
+            Reading::Fresh(snapshot) => Some(snapshot),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Fresh snapshots of every provider, with the host reservation applied.
+pub async fn local_snapshots(
+    providers: &[Arc<dyn MemoryProvider>],
+    host_reserved_setting: Option<&str>,
+) -> Vec<MemorySnapshot> {
+    let mut snapshots = aggregate_snapshot_async(providers).await;
+    apply_host_reserve(&mut snapshots, host_reserved_setting);
+    snapshots
 }
 
 /// Synchronous aggregate snapshot — only safe to call from within spawn_blocking.
@@ -115,16 +247,100 @@ pub async fn aggregate_snapshot_async(providers: &[Arc<dyn MemoryProvider>]) ->
 pub fn aggregate_snapshot(providers: &[Arc<dyn MemoryProvider>]) -> Vec<MemorySnapshot> {
     providers
         .iter()
-        .filter_map(|p| {
-            p.snapshot().map(|(total, used, free)| MemorySnapshot {
-                provider_id: p.id().to_string(),
-                name: p.name().to_string(),
-                kind: p.kind(),
-                total_mb: total,
-                used_mb: used,
-                free_mb: free,
-                allocated_mb: 0,
-            })
-        })
+        .filter_map(|p| p.snapshot().map(|r| to_snapshot(p.as_ref(), r)))
         .collect()
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// A provider that takes `delay_ms` to answer.
+    pub struct SlowProvider {
+        pub id: String,
+        pub delay_ms: AtomicU64,
+    }
+
+    impl SlowProvider {
+        pub fn new(id: &str, delay_ms: u64) -> Arc<Self> {
+            Arc::new(SlowProvider { id: id.into(), delay_ms: AtomicU64::new(delay_ms) })
+        }
+    }
+
+    impl MemoryProvider for SlowProvider {
+        fn id(&self) -> &str {
+            &self.id
+        }
+        fn name(&self) -> &str {
+            "Slow GPU"
+        }
+        fn kind(&self) -> GpuKind {
+            GpuKind::Nvidia
+        }
+        fn snapshot(&self) -> Option<(u64, u64, u64)> {
+            std::thread::sleep(Duration::from_millis(self.delay_ms.load(Ordering::Relaxed)));
+            Some((8192, 1024, 7168))
+        }
+    }
+
+    fn snapshot(kind: GpuKind, total_mb: u64, free_mb: u64) -> MemorySnapshot {
+        MemorySnapshot {
+            provider_id: format!("{:?}", kind),
+            name: "test".into(),
+            kind,
+            total_mb,
+            used_mb: total_mb - free_mb,
+            free_mb,
+            allocated_mb: 0,
+            ollama_mb: 0,
+            usable_free_mb: free_mb,
+            stale: false,
+        }
+    }
+
+    #[test]
+    fn host_reserve_comes_out_of_os_memory_only() {
+        let mut snapshots = [
+            snapshot(GpuKind::Nvidia, 8192, 8000),
+            snapshot(GpuKind::SystemRam, 16384, 6000),
+        ];
+        apply_host_reserve(&mut snapshots, Some("3000"));
+        assert_eq!(snapshots[0].usable_free_mb, 8000);
+        assert_eq!(snapshots[1].usable_free_mb, 3000);
+        assert_eq!(host_reserved_total(&snapshots), 3000);
+
+        // A reservation larger than what is free leaves nothing
+        apply_host_reserve(&mut snapshots, Some("9000"));
+        assert_eq!(snapshots[1].usable_free_mb, 0);
+        assert_eq!(host_reserved_total(&snapshots), 6000);
+
+        apply_host_reserve(&mut snapshots, Some("auto"));
+        assert_eq!(snapshots[1].usable_free_mb, 6000 - default_host_reserved_mb(16384));
+        assert_eq!(host_reserved_mb(None, 65536), default_host_reserved_mb(65536));
+    }
+
+    #[tokio::test]
+    async fn a_hung_provider_times_out_alone() {
+        let fast = SlowProvider::new("read-fast", 0);
+        let hung = SlowProvider::new("read-hung", 600);
+        let providers: Vec<Arc<dyn MemoryProvider>> = vec![hung.clone(), fast];
+
+        let started = std::time::Instant::now();
+        let readings = read_providers(&providers, Duration::from_millis(100)).await;
+        assert!(started.elapsed() < Duration::from_millis(400));
+        assert!(matches!(readings[0], Reading::TimedOut));
+        assert!(matches!(&readings[1], Reading::Fresh(s) if s.provider_id == "read-fast" && !s.stale));
+
+        // Still stuck in the first call: not called again
+        hung.delay_ms.store(0, std::sync::atomic::Ordering::Relaxed);
+        let started = std::time::Instant::now();
+        let readings = read_providers(&providers[..1], Duration::from_secs(2)).await;
+        assert!(matches!(readings[0], Reading::TimedOut));
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        let readings = read_providers(&providers[..1], Duration::from_millis(100)).await;
+        assert!(matches!(readings[0], Reading::Fresh(_)));
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::time::{Duration, Instant};
+
+/// Per-device budget for reading `/api/gpu`. Devices are asked in parallel,
+/// so a whole batch stays under this plus connection overhead.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a reading is reused before the device is asked again.
+pub const CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Fetch total and free memory from a remote device's /api/gpu endpoint.
+/// Returns `None` if the request fails or the device reports no memory.
+pub async fn fetch_remote_memory(client: &reqwest::Client, ip: &str) -> Option<(i64, i64)> {
+    let url = format!("http://{}:8080/api/gpu", ip);
+    let data: serde_json::Value = client
+        .get(&url)
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await
+        .ok()?
+        .json()
+        .await
+        .ok()?;
+    totals(&data)
+}
+
+/// Sum `total_mb` and `free_mb` over the providers of an /api/gpu response.
+fn totals(data: &serde_json::Value) -> Option<(i64, i64)> {
+    let providers = data["providers"].as_array()?;
+    if providers.is_empty() {
+        return None;
+    }
+    let total: i64 = providers
+        .iter()
+        .filter_map(|p| p["total_mb"].as_i64())
+        .sum();
+    let free: i64 = providers
+        .iter()
+        .filter_map(|p| p["free_mb"].as_i64())
+        .sum();
+    if total == 0 {
+        return None;
+    }
+    Some((total, free))
+}
+
+struct Reading {
+    total_mb: i64,
+    free_mb: i64,
+    taken_at: Instant,
+}
+
+/// Latest `(total, free)` reading of each remote device, keyed by device ID.
+#[derive(Default)]
+pub struct RemoteMemoryCache {
+    readings: RwLock<HashMap<String, Reading>>,
+}
+
+impl RemoteMemoryCache {
+    /// The device's reading if it is younger than [`CACHE_TTL`].
+    pub fn get(&self, device_id: &str) -> Option<(i64, i64)> {
+        let readings = self.readings.read().unwrap();
+        readings
+            .get(device_id)
+            .filter(|r| r.taken_at.elapsed() < CACHE_TTL)
+            .map(|r| (r.total_mb, r.free_mb))
+    }
+
+    /// How long ago the device was last read, however long that is.
+    pub fn age(&self, device_id: &str) -> Option<std::time::Duration> {
+        self.readings.read().unwrap().get(device_id).map(|r| r.taken_at.elapsed())
+    }
+
+    pub fn put(&self, device_id: &str, total_mb: i64, free_mb: i64) {
+        self.readings.write().unwrap().insert(
+            device_id.to_string(),
+            Reading { total_mb, free_mb, taken_at: Instant::now() },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn providers_are_summed() {
+        let data = serde_json::json!({ "providers": [
+            { "total_mb": 8192, "free_mb": 2048 },
+            { "total_mb": 16384, "free_mb": 12000 },
+        ]});
+        assert_eq!(totals(&data), Some((24576, 14048)));
+        assert_eq!(totals(&serde_json::json!({ "providers": [] })), None);
+        assert_eq!(totals(&serde_json::json!({ "error": "nope" })), None);
+    }
+
+    #[test]
+    fn readings_expire() {
+        let cache = RemoteMemoryCache::default();
+        cache.put("dev", 8192, 4096);
+        assert_eq!(cache.get("dev"), Some((8192, 4096)));
+        assert_eq!(cache.get("other"), None);
+        cache.readings.write().unwrap().get_mut("dev").unwrap().taken_at -= CACHE_TTL;
+        assert_eq!(cache.get("dev"), None);
+    }
+}
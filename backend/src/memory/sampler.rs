@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{broadcast, Notify};
+use tokio::time::{Duration, Instant};
+
+use super::{
+    apply_host_reserve, hotplug::ProviderSet, read_providers, GpuKind, MemoryProvider,
+    MemorySnapshot, Reading, SNAPSHOT_TIMEOUT,
+};
+use crate::errors::{notify_error, Severity};
+use crate::llama_cpp::LlamaCppManager;
+use crate::settings::{Key, SettingsCache};
+use crate::ws::{EventBus, WsEvent};
+
+/// Setting: seconds between samples of GPU / unified-memory providers.
+pub const POLL_SECS_KEY: &str = "memory_poll_secs";
+/// Setting: seconds between samples of the system RAM provider.
+pub const RAM_POLL_SECS_KEY: &str = "memory_poll_ram_secs";
+/// Setting: MB a provider's total, used or free memory must move before
+/// `memory_stats` is sent again. 0 sends any change.
+pub const STATS_DELTA_KEY: &str = "memory_stats_delta_mb";
+
+/// Interval for every provider while nobody is watching (no WebSocket
+/// clients, no inference session).
+const IDLE_POLL_SECS: u64 = 30;
+/// Readings in a row a provider may miss before it is reported and counted
+/// as degraded.
+const MISSES_BEFORE_REPORT: u32 = 3;
+/// `memory_stats` goes out at least this often, whether anything moved or not.
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(30);
+
+struct Sample {
+    snapshot: MemorySnapshot,
+    taken_at: Instant,
+}
+
+/// What the last `memory_stats` said, so samples that barely moved aren't sent.
+#[derive(Default)]
+struct SentStats {
+    /// Per provider: total, used and free MB, and `stale`, as last sent
+    sent: HashMap<String, (u64, u64, u64, bool)>,
+    keyframe_at: Option<Instant>,
+}
+
+impl SentStats {
+    /// The providers that moved by `delta_mb` or more since they were last
+    /// sent, and whether a keyframe is due. `None` when neither, and no
+    /// provider went away: the sample isn't worth sending.
+    fn diff(&mut self, snapshots: &[MemorySnapshot], delta_mb: u64, now: Instant) -> Option<(Vec<String>, bool)> {
+        let delta_mb = delta_mb.max(1);
+        let reading = |s: &MemorySnapshot| (s.total_mb, s.used_mb, s.free_mb, s.stale);
+        let changed: Vec<String> = snapshots
+            .iter()
+            .filter(|s| match self.sent.get(&s.provider_id) {
+                Some(&(total, used, free, stale)) => {
+                    stale != s.stale
+                        || total.abs_diff(s.total_mb) >= delta_mb
+                        || used.abs_diff(s.used_mb) >= delta_mb
+                        || free.abs_diff(s.free_mb) >= delta_mb
+                }
+                None => true,
+            })
+            .map(|s| s.provider_id.clone())
+            .collect();
+        let removed = self.sent.keys().any(|id| !snapshots.iter().any(|s| s.provider_id == *id));
+        let keyframe = self.keyframe_at.is_none_or(|at| now.duration_since(at) >= KEYFRAME_INTERVAL);
+        if changed.is_empty() && !removed && !keyframe {
+            return None;
+        }
+
+        if keyframe {
+            self.keyframe_at = Some(now);
+            self.sent = snapshots.iter().map(|s| (s.provider_id.clone(), reading(s))).collect();
+        } else {
+            // Providers that didn't move keep their baseline, so slow drift adds up
+            self.sent.retain(|id, _| snapshots.iter().any(|s| s.provider_id == *id));
+            for s in snapshots.iter().filter(|s| changed.contains(&s.provider_id)) {
+                self.sent.insert(s.provider_id.clone(), reading(s));
+            }
+        }
+        Some((changed, keyframe))
+    }
+}
+
+/// Samples memory providers on a per-kind schedule and keeps the latest
+/// snapshot of each, so `/api/gpu` never shells out to nvidia-smi & co.
+///
+/// The loop slows to [`IDLE_POLL_SECS`] while nobody is watching and is woken
+/// immediately when a WebSocket client connects or inference starts.
+pub struct MemorySampler {
+    providers: Arc<ProviderSet>,
+    settings: Arc<SettingsCache>,
+    latest: RwLock<HashMap<String, Sample>>,
+    /// Consecutive missed or timed-out readings per provider
+    misses: RwLock<HashMap<String, u32>>,
+    ws_clients: AtomicUsize,
+    wake: Notify,
+    sent: Mutex<SentStats>,
+}
+
+/// How one provider's sampling is going, for the diagnostics report.
+#[derive(Debug, Clone)]
+pub struct ProviderHealth {
+    pub provider_id: String,
+    pub name: String,
+    /// Samples in a row without a reading (none, or not in time)
+    pub consecutive_failures: u32,
+    /// `consecutive_failures` reached the reporting threshold
+    pub degraded: bool,
+}
+
+/// Keeps a WebSocket client counted for as long as it is alive.
+pub struct ClientGuard(Arc<MemorySampler>);
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.ws_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl MemorySampler {
+    pub fn new(providers: Arc<ProviderSet>, settings: Arc<SettingsCache>) -> Self {
+        MemorySampler {
+            providers,
+            settings,
+            latest: RwLock::new(HashMap::new()),
+            misses: RwLock::new(HashMap::new()),
+            ws_clients: AtomicUsize::new(0),
+            wake: Notify::new(),
+            sent: Mutex::new(SentStats::default()),
+        }
+    }
+
+    /// Register a connected WebSocket client and bring the loop back to its
+    /// normal pace.
+    pub fn client_connected(self: &Arc<Self>) -> ClientGuard {
+        self.ws_clients.fetch_add(1, Ordering::Relaxed);
+        self.wake.notify_one();
+        ClientGuard(self.clone())
+    }
+
+    /// Re-evaluate the schedule now (e.g. after the poll settings changed).
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Latest snapshot of every provider, and the age of the oldest one.
+    /// `usable_free_mb` follows the current `host_reserved_mb`.
+    pub fn cached(&self) -> (Vec<MemorySnapshot>, Duration) {
+        let latest = self.latest.read().unwrap_or_else(|e| e.into_inner());
+        let mut oldest = Duration::ZERO;
+        let mut snapshots: Vec<MemorySnapshot> = self
+            .providers
+            .load()
+            .iter()
+            .filter_map(|p| latest.get(p.id()))
+            .map(|s| {
+                oldest = oldest.max(s.taken_at.elapsed());
+                s.snapshot.clone()
+            })
+            .collect();
+        apply_host_reserve(&mut snapshots, Some(&self.settings.get_string(Key::HostReservedMb)));
+        (snapshots, oldest)
+    }
+
+    /// Sample every provider immediately and return the fresh snapshots.
+    pub async fn refresh_all(&self) -> Vec<MemorySnapshot> {
+        self.sample(self.providers.load().to_vec(), SNAPSHOT_TIMEOUT).await;
+        self.cached().0
+    }
+
+    /// A full `memory_stats` of the cached snapshots, every provider listed
+    /// as changed: for a client that just connected, or after a refresh.
+    /// `None` before anything was sampled.
+    pub fn keyframe(&self) -> Option<WsEvent> {
+        let (snapshots, _) = self.cached();
+        if snapshots.is_empty() {
+            return None;
+        }
+        Some(WsEvent::MemoryStats {
+            changed: snapshots.iter().map(|s| s.provider_id.clone()).collect(),
+            keyframe: true,
+            snapshots,
+        })
+    }
+
+    /// `memory_stats` for the latest sample, or `None` when no provider moved
+    /// by `memory_stats_delta_mb` and no keyframe is due.
+    fn stats_event(&self, now: Instant) -> Option<WsEvent> {
+        let delta_mb = self.settings.get_u64(Key::MemoryStatsDeltaMb);
+        let (snapshots, _) = self.cached();
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        let (changed, keyframe) = sent.diff(&snapshots, delta_mb, now)?;
+        Some(WsEvent::MemoryStats { snapshots, changed, keyframe })
+    }
+
+    /// Sampling state of every provider.
+    pub fn health(&self) -> Vec<ProviderHealth> {
+        let misses = self.misses.read().unwrap_or_else(|e| e.into_inner());
+        self.providers
+            .load()
+            .iter()
+            .map(|p| {
+                let failures = misses.get(p.id()).copied().unwrap_or(0);
+                ProviderHealth {
+                    provider_id: p.id().to_string(),
+                    name: p.name().to_string(),
+                    consecutive_failures: failures,
+                    degraded: failures >= MISSES_BEFORE_REPORT,
+                }
+            })
+            .collect()
+    }
+
+    /// Sample `providers` and return those that gave no reading. A provider
+    /// that timed out keeps its last snapshot, marked stale.
+    async fn sample(
+        &self,
+        providers: Vec<Arc<dyn MemoryProvider>>,
+        limit: Duration,
+    ) -> Vec<Arc<dyn MemoryProvider>> {
+        let readings = read_providers(&providers, limit).await;
+        let now = Instant::now();
+        let mut missed = Vec::new();
+        let mut latest = self.latest.write().unwrap_or_else(|e| e.into_inner());
+        let mut misses = self.misses.write().unwrap_or_else(|e| e.into_inner());
+        for (p, reading) in providers.into_iter().zip(readings) {
+            match reading {
+                Reading::Fresh(snapshot) => {
+                    misses.remove(p.id());
+                    latest.insert(p.id().to_string(), Sample { snapshot, taken_at: now });
+                    continue;
+                }
+                Reading::TimedOut => {
+                    tracing::debug!("{} gave no memory reading within {:?}", p.name(), limit);
+                    if let Some(sample) = latest.get_mut(p.id()) {
+                        sample.snapshot.stale = true;
+                    }
+                }
+                Reading::Unavailable => {}
+            }
+            *misses.entry(p.id().to_string()).or_default() += 1;
+            missed.push(p);
+        }
+        missed
+    }
+
+    /// Drop what is kept about providers that are no longer detected.
+    pub fn forget<'a>(&self, ids: impl Iterator<Item = &'a str>) {
+        let mut latest = self.latest.write().unwrap_or_else(|e| e.into_inner());
+        let mut misses = self.misses.write().unwrap_or_else(|e| e.into_inner());
+        for id in ids {
+            latest.remove(id);
+            misses.remove(id);
+        }
+    }
+
+    fn misses_of(&self, id: &str) -> u32 {
+        let misses = self.misses.read().unwrap_or_else(|e| e.into_inner());
+        misses.get(id).copied().unwrap_or(0)
+    }
+
+    /// How long a snapshot of a `kind` provider stays fresh under the current load.
+    fn interval_for(kind: &GpuKind, idle: bool, settings: &SettingsCache) -> Duration {
+        let secs = if idle {
+            IDLE_POLL_SECS
+        } else {
+            let key = match kind {
+                GpuKind::SystemRam => Key::MemoryPollRamSecs,
+                _ => Key::MemoryPollSecs,
+            };
+            settings.get_u64(key).max(1)
+        };
+        Duration::from_secs(secs)
+    }
+
+    /// Background loop: re-sample providers whose snapshot is due and
+    /// broadcast `memory_stats` whenever a provider moved enough.
+    pub fn spawn(
+        self: Arc<Self>,
+        llama_cpp: Arc<LlamaCppManager>,
+        event_tx: EventBus,
+    ) {
+        let mut event_rx = event_tx.subscribe_critical();
+        tokio::spawn(async move {
+            loop {
+                let idle = self.ws_clients.load(Ordering::Relaxed) == 0
+                    && !llama_cpp.is_inference_running().await;
+
+                // Providers never sampled (or that failed) are always due
+                let mut due = Vec::new();
+                let mut next_wake = Duration::from_secs(IDLE_POLL_SECS);
+                {
+                    let latest = self.latest.read().unwrap_or_else(|e| e.into_inner());
+                    for p in self.providers.load().iter() {
+                        let interval = Self::interval_for(&p.kind(), idle, &self.settings);
+                        let age = latest.get(p.id()).map(|s| s.taken_at.elapsed());
+                        match age {
+                            Some(age) if age < interval => {
+                                next_wake = next_wake.min(interval - age);
+                            }
+                            _ => {
+                                due.push(p.clone());
+                                next_wake = next_wake.min(interval);
+                            }
+                        }
+                    }
+                }
+
+                if !due.is_empty() {
+                    let missed = self.sample(due, SNAPSHOT_TIMEOUT).await;
+                    for p in &missed {
+                        let count = self.misses_of(p.id());
+                        // Once per outage, not on every poll
+                        if count == MISSES_BEFORE_REPORT {
+                            notify_error(
+                                &event_tx,
+                                Severity::Warning,
+                                "memory",
+                                format!(
+                                    "{} returned no memory reading {} times in a row",
+                                    p.name(),
+                                    MISSES_BEFORE_REPORT
+                                ),
+                            );
+                        }
+                    }
+                    if let Some(event) = self.stats_event(Instant::now()) {
+                        let _ = event_tx.send(event);
+                    }
+                }
+
+                let sleep = tokio::time::sleep(next_wake.max(Duration::from_millis(250)));
+                tokio::pin!(sleep);
+                loop {
+                    tokio::select! {
+                        _ = &mut sleep => break,
+                        _ = self.wake.notified() => break,
+                        event = event_rx.recv() => match event {
+                            Ok(WsEvent::InferenceStarted { .. }) => break,
+                            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => return,
+                        },
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::tests::SlowProvider;
+    use crate::settings::tests::test_pool;
+
+    #[tokio::test]
+    async fn intervals_follow_kind_settings_and_idle() {
+        let settings = SettingsCache::load(test_pool().await).await.unwrap();
+        let secs = |kind: GpuKind, idle: bool| {
+            MemorySampler::interval_for(&kind, idle, &settings).as_secs()
+        };
+
+        assert_eq!(secs(GpuKind::Nvidia, false), 3);
+        assert_eq!(secs(GpuKind::SystemRam, false), 15);
+
+        settings.set(POLL_SECS_KEY, "5").await.unwrap();
+        settings.set(RAM_POLL_SECS_KEY, "60").await.unwrap();
+        assert_eq!(secs(GpuKind::AppleSilicon, false), 5);
+        assert_eq!(secs(GpuKind::SystemRam, false), 60);
+
+        assert_eq!(secs(GpuKind::Amd, true), IDLE_POLL_SECS);
+        assert_eq!(secs(GpuKind::SystemRam, true), IDLE_POLL_SECS);
+    }
+
+    fn snapshot(id: &str, used_mb: u64) -> MemorySnapshot {
+        MemorySnapshot {
+            provider_id: id.into(),
+            name: id.into(),
+            kind: GpuKind::Nvidia,
+            total_mb: 24576,
+            used_mb,
+            free_mb: 24576 - used_mb,
+            allocated_mb: 0,
+            ollama_mb: 0,
+            usable_free_mb: 24576 - used_mb,
+            stale: false,
+        }
+    }
+
+    #[test]
+    fn stats_are_sent_when_a_provider_moves_or_a_keyframe_is_due() {
+        let mut sent = SentStats::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let ids = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let both = [snapshot("nvidia-0", 1000), snapshot("nvidia-1", 1000)];
+        assert_eq!(sent.diff(&both, 64, at(0)), Some((ids(&["nvidia-0", "nvidia-1"]), true)));
+        // A few MB either way is noise
+        let both = [snapshot("nvidia-0", 1040), snapshot("nvidia-1", 990)];
+        assert_eq!(sent.diff(&both, 64, at(3)), None);
+        let both = [snapshot("nvidia-0", 1070), snapshot("nvidia-1", 990)];
+        assert_eq!(sent.diff(&both, 64, at(6)), Some((ids(&["nvidia-0"]), false)));
+        // Drift adds up against what was last sent
+        let both = [snapshot("nvidia-0", 1070), snapshot("nvidia-1", 936)];
+        assert_eq!(sent.diff(&both, 64, at(9)), Some((ids(&["nvidia-1"]), false)));
+
+        let mut stale = both.clone();
+        stale[1].stale = true;
+        assert_eq!(sent.diff(&stale, 64, at(12)), Some((ids(&["nvidia-1"]), false)));
+        // A provider going away is news
+        assert_eq!(sent.diff(&stale[..1], 64, at(15)), Some((vec![], false)));
+        assert_eq!(sent.diff(&stale[..1], 64, at(18)), None);
+        assert_eq!(sent.diff(&stale[..1], 64, at(30)), Some((vec![], true)));
+        assert_eq!(sent.diff(&stale[..1], 0, at(33)), None);
+    }
+
+    #[tokio::test]
+    async fn timed_out_providers_keep_a_stale_snapshot_and_degrade() {
+        let settings = Arc::new(SettingsCache::load(test_pool().await).await.unwrap());
+        let slow = SlowProvider::new("sampler-slow", 0);
+        let sampler = MemorySampler::new(Arc::new(ProviderSet::new(vec![slow.clone()])), settings);
+        let limit = Duration::from_millis(100);
+
+        assert!(sampler.sample(sampler.providers.load().to_vec(), limit).await.is_empty());
+        let (snapshots, _) = sampler.cached();
+        assert!(!snapshots[0].stale);
+
+        slow.delay_ms.store(400, Ordering::Relaxed);
+        for n in 1..=MISSES_BEFORE_REPORT {
+            assert_eq!(sampler.sample(sampler.providers.load().to_vec(), limit).await.len(), 1);
+            let health = &sampler.health()[0];
+            assert_eq!(health.consecutive_failures, n);
+            assert_eq!(health.degraded, n == MISSES_BEFORE_REPORT);
+        }
+        let (snapshots, _) = sampler.cached();
+        assert_eq!(snapshots.len(), 1);
+        assert!(snapshots[0].stale);
+        assert_eq!(snapshots[0].free_mb, 7168);
+
+        slow.delay_ms.store(0, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(sampler.sample(sampler.providers.load().to_vec(), limit).await.is_empty());
+        assert!(!sampler.cached().0[0].stale);
+        assert!(!sampler.health()[0].degraded);
+    }
+}
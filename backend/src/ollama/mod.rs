@@ -1,16 +1,25 @@
 use anyhow::Result;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tokio::process::{Child, Command};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::process::Command;
 use tokio::sync::Mutex;
 use tokio::time::{interval, sleep, Duration};
 use which::which;
 
-const OLLAMA_HOST: &str = "http://127.0.0.1:11434";
+use crate::errors::{notify_error, Severity};
+use crate::process::ManagedChild;
+use crate::ws::EventBus;
+
+pub mod native;
+pub mod pulls;
+
+pub const OLLAMA_HOST: &str = "http://127.0.0.1:11434";
 const HEALTH_INTERVAL_SECS: u64 = 10;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct OllamaModel {
     pub name: String,
     pub size: u64,
@@ -23,29 +32,204 @@ struct OllamaListResponse {
     models: Vec<OllamaModel>,
 }
 
+/// A model Ollama holds in memory, from `/api/ps`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct OllamaRunningModel {
+    pub model: String,
+    /// Bytes held in all, GPU and system memory together
+    pub size: u64,
+    /// Bytes of `size` in GPU memory
+    #[serde(default)]
+    pub size_vram: u64,
+    /// When Ollama unloads it unless it is used again
+    #[serde(default)]
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaPsResponse {
+    #[serde(default)]
+    models: Vec<OllamaRunningModel>,
+}
+
+/// What `/api/show` says about a model, trimmed to what the dashboard shows.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct OllamaModelDetails {
+    pub family: Option<String>,
+    /// e.g. `8.0B`
+    pub parameter_size: Option<String>,
+    /// e.g. `Q4_K_M`
+    pub quantization_level: Option<String>,
+    /// Trained context window, from `<architecture>.context_length`
+    pub context_length: Option<u64>,
+    /// Modelfile `PARAMETER` lines, one per line
+    pub parameters: Option<String>,
+    /// Prompt template in Go template syntax
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OllamaShowResponse {
+    #[serde(default)]
+    parameters: Option<String>,
+    #[serde(default)]
+    template: Option<String>,
+    #[serde(default)]
+    details: OllamaShowDetails,
+    #[serde(default)]
+    model_info: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OllamaShowDetails {
+    family: Option<String>,
+    parameter_size: Option<String>,
+    quantization_level: Option<String>,
+}
+
+impl From<OllamaShowResponse> for OllamaModelDetails {
+    fn from(show: OllamaShowResponse) -> Self {
+        let arch = show
+            .model_info
+            .get("general.architecture")
+            .and_then(|a| a.as_str());
+        let context_length = arch
+            .and_then(|a| show.model_info.get(&format!("{}.context_length", a)))
+            .and_then(|n| n.as_u64());
+        let non_empty = |s: Option<String>| s.filter(|s| !s.trim().is_empty());
+        OllamaModelDetails {
+            family: non_empty(show.details.family),
+            parameter_size: non_empty(show.details.parameter_size),
+            quantization_level: non_empty(show.details.quantization_level),
+            context_length,
+            parameters: non_empty(show.parameters),
+            template: non_empty(show.template),
+        }
+    }
+}
+
+/// Why [`OllamaManager::show_model`] came back empty-handed.
+#[derive(Debug)]
+pub enum ShowError {
+    /// Ollama answered but has no such model
+    NotFound,
+    /// Ollama could not be reached or gave an unusable answer
+    Unavailable(anyhow::Error),
+}
+
+impl std::fmt::Display for ShowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShowError::NotFound => write!(f, "Model not found"),
+            ShowError::Unavailable(e) => write!(f, "Ollama unavailable: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ShowError {}
+
+/// How the backend relates to the Ollama instance at `ollama_host`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OllamaMode {
+    /// Loopback host with `auto_start_ollama`: started and restarted by us
+    Managed,
+    /// Loopback host the user runs themselves
+    Local,
+    /// Another machine; never spawned or restarted
+    Remote,
+}
+
+/// Whether `host` (a URL) points at this machine.
+pub fn is_loopback_host(host: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(host) else {
+        return false;
+    };
+    let Some(h) = url.host_str() else {
+        return false;
+    };
+    // IPv6 hosts come bracketed
+    h.eq_ignore_ascii_case("localhost")
+        || h.trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
 #[derive(Debug)]
 pub struct OllamaManager {
-    pub host: String,
+    /// Changed at runtime by `reconfigure`
+    host: RwLock<String>,
+    auto_start: AtomicBool,
     client: Client,
     is_running: Arc<Mutex<bool>>,
     /// Handle to the child process we spawned (None if Ollama was already running externally)
-    child: Arc<Mutex<Option<Child>>>,
+    child: Arc<Mutex<Option<ManagedChild>>>,
+    /// `/api/show` results by digest; a digest's contents never change
+    details: RwLock<HashMap<String, OllamaModelDetails>>,
+    /// What `/api/ps` listed last
+    resident: RwLock<Vec<OllamaRunningModel>>,
 }
 
 impl OllamaManager {
-    pub fn new(host: Option<String>) -> Self {
+    pub fn new(host: Option<String>, auto_start: bool) -> Self {
         OllamaManager {
-            host: host.unwrap_or_else(|| OLLAMA_HOST.to_string()),
+            host: RwLock::new(host.unwrap_or_else(|| OLLAMA_HOST.to_string())),
+            auto_start: AtomicBool::new(auto_start),
             client: Client::new(),
             is_running: Arc::new(Mutex::new(false)),
             child: Arc::new(Mutex::new(None)),
+            details: RwLock::new(HashMap::new()),
+            resident: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn host(&self) -> String {
+        self.host.read().unwrap().clone()
+    }
+
+    pub fn mode(&self) -> OllamaMode {
+        if !is_loopback_host(&self.host()) {
+            OllamaMode::Remote
+        } else if self.auto_start.load(Ordering::Relaxed) {
+            OllamaMode::Managed
+        } else {
+            OllamaMode::Local
+        }
+    }
+
+    /// Point the manager at `host` without a restart. A process we spawned
+    /// for the old host is stopped; a loopback host with `auto_start` is
+    /// started (and watched) again, a remote one never is.
+    pub async fn reconfigure(&self, host: String, auto_start: bool) -> Result<()> {
+        let changed = {
+            let mut current = self.host.write().unwrap();
+            let changed = *current != host;
+            *current = host;
+            changed
+        };
+        self.auto_start.store(auto_start, Ordering::Relaxed);
+        if changed {
+            self.stop().await;
         }
+        match self.mode() {
+            OllamaMode::Managed => self.ensure_running().await,
+            _ => {
+                *self.is_running.lock().await = self.is_healthy().await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether Ollama answered when last checked, without asking it again.
+    pub async fn running(&self) -> bool {
+        *self.is_running.lock().await
     }
 
     /// Check if Ollama HTTP server is reachable
     pub async fn is_healthy(&self) -> bool {
         self.client
-            .get(format!("{}/api/tags", self.host))
+            .get(format!("{}/api/tags", self.host()))
             .timeout(Duration::from_secs(3))
             .send()
             .await
@@ -53,13 +237,18 @@ impl OllamaManager {
             .unwrap_or(false)
     }
 
-    /// Start Ollama as a background process if not already running
+    /// Start Ollama as a background process if not already running.
+    /// A remote host is only checked, never spawned.
     pub async fn ensure_running(&self) -> Result<()> {
+        let host = self.host();
         if self.is_healthy().await {
-            tracing::info!("Ollama is already running at {}", self.host);
+            tracing::info!("Ollama is already running at {}", host);
             *self.is_running.lock().await = true;
             return Ok(());
         }
+        if self.mode() == OllamaMode::Remote {
+            anyhow::bail!("Remote Ollama at {} is not reachable", host);
+        }
 
         // Find ollama binary
         let ollama_path = which("ollama").map_err(|_| {
@@ -70,9 +259,8 @@ impl OllamaManager {
 
         tracing::info!("Starting Ollama: {}", ollama_path.display());
 
-        let child = Command::new(&ollama_path)
-            .arg("serve")
-            .spawn()?;
+        // `ollama serve` starts a runner process per loaded model
+        let child = ManagedChild::spawn(Command::new(&ollama_path).arg("serve"))?;
 
         // Store the child handle so it stays alive and can be managed
         *self.child.lock().await = Some(child);
@@ -105,12 +293,22 @@ impl OllamaManager {
         *self.is_running.lock().await = false;
     }
 
-    /// Spawn a watchdog task that restarts Ollama if it crashes
-    pub fn spawn_watchdog(self: Arc<Self>) {
+    /// Spawn a watchdog task that restarts Ollama if it crashes.
+    /// It only acts while the mode is `Managed`, so it can run for the
+    /// lifetime of the process and follow `reconfigure`. Every tick it also
+    /// refreshes [`resident`](Self::resident).
+    pub fn spawn_watchdog(self: Arc<Self>, event_tx: EventBus) {
         tokio::spawn(async move {
             let mut ticker = interval(Duration::from_secs(HEALTH_INTERVAL_SECS));
             loop {
                 ticker.tick().await;
+                if self.mode() != OllamaMode::Remote && self.list_running().await.is_err() {
+                    // Down: whatever it held is gone
+                    self.resident.write().unwrap().clear();
+                }
+                if self.mode() != OllamaMode::Managed {
+                    continue;
+                }
                 let healthy = self.is_healthy().await;
 
                 // Check and release the lock before potentially calling ensure_running
@@ -125,9 +323,19 @@ impl OllamaManager {
                 };
 
                 if was_running {
-                    tracing::warn!("Ollama went down — attempting restart...");
+                    notify_error(
+                        &event_tx,
+                        Severity::Warning,
+                        "ollama",
+                        "Ollama went down — attempting restart...",
+                    );
                     if let Err(e) = self.ensure_running().await {
-                        tracing::error!("Failed to restart Ollama: {}", e);
+                        notify_error(
+                            &event_tx,
+                            Severity::Error,
+                            "ollama",
+                            format!("Failed to restart Ollama: {}", e),
+                        );
                     }
                 }
             }
@@ -138,7 +346,7 @@ impl OllamaManager {
     pub async fn list_models(&self) -> Result<Vec<OllamaModel>> {
         let resp = self
             .client
-            .get(format!("{}/api/tags", self.host))
+            .get(format!("{}/api/tags", self.host()))
             .send()
             .await?
             .json::<OllamaListResponse>()
@@ -146,6 +354,103 @@ impl OllamaManager {
         Ok(resp.models)
     }
 
+    /// Models Ollama holds in memory now, from `/api/ps`
+    pub async fn list_running(&self) -> Result<Vec<OllamaRunningModel>> {
+        let resp = self
+            .client
+            .get(format!("{}/api/ps", self.host()))
+            .timeout(Duration::from_secs(3))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Ollama ps failed: HTTP {}", resp.status());
+        }
+        let models = resp.json::<OllamaPsResponse>().await?.models;
+        *self.resident.write().unwrap() = models.clone();
+        Ok(models)
+    }
+
+    /// Models held in this host's memory as of the last
+    /// [`list_running`](Self::list_running), without asking again. Empty
+    /// when Ollama runs on another host.
+    pub fn resident(&self) -> Vec<OllamaRunningModel> {
+        if self.mode() == OllamaMode::Remote {
+            return Vec::new();
+        }
+        self.resident.read().unwrap().clone()
+    }
+
+    /// Evict `model` from memory now: a generate call without a prompt and
+    /// with `keep_alive: 0`
+    pub async fn unload(&self, model: &str) -> Result<()> {
+        let resp = self
+            .client
+            .post(format!("{}/api/generate", self.host()))
+            .json(&serde_json::json!({ "model": model, "keep_alive": 0, "stream": false }))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Ollama unload failed for '{}': HTTP {}", model, resp.status());
+        }
+        let wanted = with_default_tag(model);
+        self.resident
+            .write()
+            .unwrap()
+            .retain(|m| with_default_tag(&m.model) != wanted);
+        Ok(())
+    }
+
+    /// Details for the pulled model `name` (a missing tag means `:latest`),
+    /// from the cache when its digest has been seen before.
+    pub async fn show_model(&self, name: &str) -> Result<OllamaModelDetails, ShowError> {
+        let wanted = with_default_tag(name);
+        let model = self
+            .list_models()
+            .await
+            .map_err(ShowError::Unavailable)?
+            .into_iter()
+            .find(|m| with_default_tag(&m.name) == wanted)
+            .ok_or(ShowError::NotFound)?;
+        self.model_details(&model).await
+    }
+
+    /// Details for a model from [`list_models`](Self::list_models).
+    pub async fn model_details(&self, model: &OllamaModel) -> Result<OllamaModelDetails, ShowError> {
+        if let Some(cached) = self.details.read().unwrap().get(&model.digest) {
+            return Ok(cached.clone());
+        }
+        let resp = self
+            .client
+            .post(format!("{}/api/show", self.host()))
+            .json(&serde_json::json!({ "name": model.name }))
+            .send()
+            .await
+            .map_err(|e| ShowError::Unavailable(e.into()))?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(ShowError::NotFound);
+        }
+        if !resp.status().is_success() {
+            return Err(ShowError::Unavailable(anyhow::anyhow!(
+                "Ollama show failed for '{}': HTTP {}",
+                model.name,
+                resp.status()
+            )));
+        }
+        let details: OllamaModelDetails = resp
+            .json::<OllamaShowResponse>()
+            .await
+            .map_err(|e| ShowError::Unavailable(e.into()))?
+            .into();
+        // Without a digest there is nothing immutable to key on
+        if !model.digest.is_empty() {
+            self.details
+                .write()
+                .unwrap()
+                .insert(model.digest.clone(), details.clone());
+        }
+        Ok(details)
+    }
+
     /// Stream a model pull response as raw bytes
     pub async fn pull_model_stream(
         &self,
@@ -153,18 +458,42 @@ impl OllamaManager {
     ) -> Result<reqwest::Response> {
         let resp = self
             .client
-            .post(format!("{}/api/pull", self.host))
+            .post(format!("{}/api/pull", self.host()))
             .json(&serde_json::json!({ "name": model, "stream": true }))
             .send()
             .await?;
         Ok(resp)
     }
 
+    /// Pull a model and wait for it to finish
+    pub async fn pull_model(&self, model: &str) -> Result<()> {
+        let resp = self
+            .client
+            .post(format!("{}/api/pull", self.host()))
+            .json(&serde_json::json!({ "name": model, "stream": false }))
+            .send()
+            .await?;
+        if !resp.status().is_success() {
+            anyhow::bail!("Ollama pull failed for '{}': HTTP {}", model, resp.status());
+        }
+        Ok(())
+    }
+
+    /// Whether `model` has been pulled. A name without a tag means `:latest`.
+    pub async fn has_model(&self, model: &str) -> Result<bool> {
+        let wanted = with_default_tag(model);
+        Ok(self
+            .list_models()
+            .await?
+            .iter()
+            .any(|m| with_default_tag(&m.name) == wanted))
+    }
+
     /// Delete a model
     pub async fn delete_model(&self, model: &str) -> Result<()> {
         let resp = self
             .client
-            .delete(format!("{}/api/delete", self.host))
+            .delete(format!("{}/api/delete", self.host()))
             .json(&serde_json::json!({ "name": model }))
             .send()
             .await?;
@@ -178,11 +507,27 @@ impl OllamaManager {
         Ok(())
     }
 
+    /// Proxy a request to Ollama and hand back the response unread, so
+    /// streamed NDJSON can be passed through as it arrives
+    pub async fn proxy_post_stream(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> Result<reqwest::Response> {
+        let resp = self
+            .client
+            .post(format!("{}{}", self.host(), path))
+            .json(body)
+            .send()
+            .await?;
+        Ok(resp)
+    }
+
     /// Proxy a raw request to Ollama (generate, chat, embeddings, etc.)
     pub async fn proxy_post(&self, path: &str, body: serde_json::Value) -> Result<serde_json::Value> {
         let resp = self
             .client
-            .post(format!("{}{}", self.host, path))
+            .post(format!("{}{}", self.host(), path))
             .json(&body)
             .send()
             .await?
@@ -191,3 +536,154 @@ impl OllamaManager {
         Ok(resp)
     }
 }
+
+fn with_default_tag(model: &str) -> String {
+    if model.contains(':') {
+        model.to_string()
+    } else {
+        format!("{}:latest", model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Json, Router};
+
+    /// An `/api/tags` stub listing one model called `name`.
+    async fn stub(name: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/api/tags",
+            get(move || async move {
+                Json(serde_json::json!({
+                    "models": [{ "name": name, "size": 1, "digest": "", "modified_at": "" }]
+                }))
+            }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    /// Tags and `/api/show` for one model, `llama3:latest`, counting shows.
+    async fn show_stub(shows: Arc<std::sync::atomic::AtomicUsize>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/api/tags",
+                get(|| async {
+                    Json(serde_json::json!({
+                        "models": [{ "name": "llama3:latest", "size": 1, "digest": "sha256:abc", "modified_at": "" }]
+                    }))
+                }),
+            )
+            .route(
+                "/api/show",
+                axum::routing::post(move || async move {
+                    shows.fetch_add(1, Ordering::SeqCst);
+                    Json(serde_json::json!({
+                        "parameters": "stop \"<|eot_id|>\"",
+                        "template": "{{ .Prompt }}",
+                        "details": { "family": "llama", "parameter_size": "8.0B", "quantization_level": "Q4_K_M" },
+                        "model_info": { "general.architecture": "llama", "llama.context_length": 8192 }
+                    }))
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn details_are_cached_by_digest() {
+        let shows = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let ollama = OllamaManager::new(Some(show_stub(shows.clone()).await), false);
+
+        let details = ollama.show_model("llama3").await.unwrap();
+        assert_eq!(details.family.as_deref(), Some("llama"));
+        assert_eq!(details.quantization_level.as_deref(), Some("Q4_K_M"));
+        assert_eq!(details.context_length, Some(8192));
+        assert_eq!(details.template.as_deref(), Some("{{ .Prompt }}"));
+
+        assert_eq!(ollama.show_model("llama3:latest").await.unwrap(), details);
+        assert_eq!(shows.load(Ordering::SeqCst), 1);
+
+        assert!(matches!(ollama.show_model("mistral").await, Err(ShowError::NotFound)));
+        ollama.reconfigure("http://127.0.0.1:9".into(), false).await.unwrap();
+        assert!(matches!(ollama.show_model("llama3").await, Err(ShowError::Unavailable(_))));
+    }
+
+    #[tokio::test]
+    async fn unloading_drops_a_resident_model() {
+        let unloads = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = unloads.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/api/ps",
+                get(|| async {
+                    Json(serde_json::json!({ "models": [
+                        { "name": "llama3:latest", "model": "llama3:latest", "size": 6_000_000_000u64,
+                          "size_vram": 4_000_000_000u64, "expires_at": "2026-10-18T12:05:00Z" },
+                        { "name": "mistral:latest", "model": "mistral:latest", "size": 5_000_000_000u64,
+                          "size_vram": 0, "expires_at": "2026-10-18T12:04:00Z" }
+                    ]}))
+                }),
+            )
+            .route(
+                "/api/generate",
+                axum::routing::post(move |Json(body): Json<serde_json::Value>| async move {
+                    seen.lock().unwrap().push(body);
+                    Json(serde_json::json!({ "done": true, "done_reason": "unload" }))
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let ollama = OllamaManager::new(Some(format!("http://{}", addr)), false);
+
+        assert!(ollama.resident().is_empty());
+        let running = ollama.list_running().await.unwrap();
+        assert_eq!(running.len(), 2);
+        assert_eq!(running[0].size_vram, 4_000_000_000);
+        assert_eq!(ollama.resident(), running);
+
+        ollama.unload("llama3").await.unwrap();
+        let body = unloads.lock().unwrap()[0].clone();
+        assert_eq!(body["model"], "llama3");
+        assert_eq!(body["keep_alive"], 0);
+        assert_eq!(ollama.resident().len(), 1);
+        assert_eq!(ollama.resident()[0].model, "mistral:latest");
+    }
+
+    #[test]
+    fn loopback_hosts_are_local() {
+        assert!(is_loopback_host("http://127.0.0.1:11434"));
+        assert!(is_loopback_host("http://localhost:11434"));
+        assert!(is_loopback_host("http://[::1]:11434"));
+        assert!(!is_loopback_host("http://192.168.1.20:11434"));
+        assert!(!is_loopback_host("not a url"));
+    }
+
+    #[tokio::test]
+    async fn host_swaps_without_restart() {
+        let (a, b) = (stub("a").await, stub("b").await);
+        let ollama = OllamaManager::new(Some(a.clone()), false);
+        assert_eq!(ollama.list_models().await.unwrap()[0].name, "a");
+
+        ollama.reconfigure(b.clone(), false).await.unwrap();
+        assert_eq!(ollama.host(), b);
+        assert_eq!(ollama.mode(), OllamaMode::Local);
+        assert_eq!(ollama.list_models().await.unwrap()[0].name, "b");
+
+        // A remote host is never spawned, even with auto-start on
+        ollama.reconfigure("http://ollama.invalid:11434".into(), true).await.unwrap();
+        assert_eq!(ollama.mode(), OllamaMode::Remote);
+        assert!(ollama.ensure_running().await.unwrap_err().to_string().contains("not reachable"));
+
+        // Back on loopback, auto-start applies again
+        ollama.reconfigure(a, true).await.unwrap();
+        assert_eq!(ollama.mode(), OllamaMode::Managed);
+        assert!(*ollama.is_running.lock().await);
+    }
+}
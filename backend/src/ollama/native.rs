@@ -0,0 +1,505 @@
+//! OpenAI chat requests in Ollama's native `/api/chat` form, for Ollama
+//! builds without `/v1` and for options only the native API takes
+//! (`num_ctx`, `keep_alive`, `mirostat`, ...). Used by the `/v1` proxy when
+//! `ollama_native_mode` is on.
+//!
+//! Requests are converted up front; anything that can't be expressed
+//! natively (tool calls, images, logprobs, several choices) is refused with
+//! a message rather than dropped. Replies are converted back: a whole
+//! `chat.completion`, or the NDJSON stream as `chat.completion.chunk` SSE
+//! events ending in `data: [DONE]`.
+
+use serde_json::{json, Map, Value};
+
+/// Setting: send `/v1/chat/completions` to Ollama's `/api/chat`.
+pub const NATIVE_MODE_KEY: &str = "ollama_native_mode";
+
+/// OpenAI sampling fields and the native option each becomes.
+const OPTION_FIELDS: &[(&str, &str)] = &[
+    ("temperature", "temperature"),
+    ("top_p", "top_p"),
+    ("max_tokens", "num_predict"),
+    ("max_completion_tokens", "num_predict"),
+    ("seed", "seed"),
+    ("presence_penalty", "presence_penalty"),
+    ("frequency_penalty", "frequency_penalty"),
+];
+
+/// Native options a request may also give at the top level.
+const NATIVE_OPTIONS: &[&str] = &[
+    "num_ctx",
+    "num_predict",
+    "num_keep",
+    "top_k",
+    "min_p",
+    "typical_p",
+    "repeat_penalty",
+    "repeat_last_n",
+    "mirostat",
+    "mirostat_eta",
+    "mirostat_tau",
+];
+
+/// OpenAI fields that change the answer in ways `/api/chat` can't.
+const UNSUPPORTED_FIELDS: &[(&str, &str)] = &[
+    ("tools", "tool calls"),
+    ("tool_choice", "tool calls"),
+    ("functions", "function calls"),
+    ("function_call", "function calls"),
+    ("logprobs", "logprobs"),
+    ("top_logprobs", "logprobs"),
+    ("logit_bias", "logit_bias"),
+];
+
+/// Convert an OpenAI chat request to an `/api/chat` body.
+pub fn to_native_request(req: &Value) -> Result<Value, String> {
+    let obj = req.as_object().ok_or("Request body must be a JSON object")?;
+    for (field, what) in UNSUPPORTED_FIELDS {
+        if obj.get(*field).is_some_and(|v| !v.is_null() && *v != json!(false)) {
+            return Err(unsupported(what));
+        }
+    }
+    if obj.get("n").and_then(Value::as_u64).is_some_and(|n| n > 1) {
+        return Err(unsupported("n > 1"));
+    }
+
+    let messages = obj
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or("messages must be an array")?
+        .iter()
+        .map(native_message)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut options = Map::new();
+    for (field, option) in OPTION_FIELDS {
+        if let Some(v) = obj.get(*field).filter(|v| !v.is_null()) {
+            options.insert(option.to_string(), v.clone());
+        }
+    }
+    match obj.get("stop") {
+        Some(Value::String(s)) => {
+            options.insert("stop".into(), json!([s]));
+        }
+        Some(Value::Array(stops)) => {
+            options.insert("stop".into(), Value::Array(stops.clone()));
+        }
+        _ => {}
+    }
+    for option in NATIVE_OPTIONS {
+        if let Some(v) = obj.get(*option) {
+            options.insert(option.to_string(), v.clone());
+        }
+    }
+    // Given as Ollama's own `options` object, they win
+    if let Some(Value::Object(given)) = obj.get("options") {
+        options.extend(given.clone());
+    }
+
+    let mut native = json!({
+        "model": obj.get("model").cloned().unwrap_or(Value::Null),
+        "messages": messages,
+        // Ollama streams unless told not to; OpenAI doesn't
+        "stream": obj.get("stream").and_then(Value::as_bool).unwrap_or(false),
+    });
+    if !options.is_empty() {
+        native["options"] = Value::Object(options);
+    }
+    if let Some(keep_alive) = obj.get("keep_alive") {
+        native["keep_alive"] = keep_alive.clone();
+    }
+    if let Some(format) = native_format(obj.get("response_format"))? {
+        native["format"] = format;
+    }
+    Ok(native)
+}
+
+fn unsupported(what: &str) -> String {
+    format!("{} can't be sent to Ollama in native mode; turn off ollama_native_mode to use them", what)
+}
+
+/// One message: text only, with a role `/api/chat` knows.
+fn native_message(message: &Value) -> Result<Value, String> {
+    let role = message["role"].as_str().ok_or("Every message needs a role")?;
+    if !matches!(role, "system" | "user" | "assistant") {
+        return Err(unsupported(&format!("Messages with role '{}'", role)));
+    }
+    if message.get("tool_calls").is_some_and(|v| !v.is_null()) || message.get("function_call").is_some_and(|v| !v.is_null()) {
+        return Err(unsupported("tool calls"));
+    }
+    let content = match &message["content"] {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => {
+            let mut text = Vec::new();
+            for part in parts {
+                match part["type"].as_str() {
+                    Some("text") => text.push(part["text"].as_str().unwrap_or_default()),
+                    Some("image_url") => return Err(unsupported("images")),
+                    other => return Err(unsupported(&format!("Content parts of type {:?}", other.unwrap_or("none")))),
+                }
+            }
+            text.join("\n")
+        }
+        _ => return Err("Message content must be a string or an array of parts".to_string()),
+    };
+    Ok(json!({ "role": role, "content": content }))
+}
+
+/// `response_format` as the native `format`: `"json"` or a JSON schema.
+fn native_format(response_format: Option<&Value>) -> Result<Option<Value>, String> {
+    let Some(format) = response_format.filter(|v| !v.is_null()) else {
+        return Ok(None);
+    };
+    match format["type"].as_str() {
+        Some("text") => Ok(None),
+        Some("json_object") => Ok(Some(json!("json"))),
+        Some("json_schema") => match format["json_schema"].get("schema") {
+            Some(schema) => Ok(Some(schema.clone())),
+            None => Err("response_format json_schema needs a schema".to_string()),
+        },
+        other => Err(unsupported(&format!("response_format {:?}", other.unwrap_or("none")))),
+    }
+}
+
+/// OpenAI's `finish_reason` for Ollama's `done_reason`.
+pub fn finish_reason(done_reason: Option<&str>) -> &'static str {
+    match done_reason {
+        Some("length") => "length",
+        // "stop", and "load" / "unload" for requests that only (un)loaded the model
+        _ => "stop",
+    }
+}
+
+fn usage(native: &Value) -> Value {
+    let prompt = native["prompt_eval_count"].as_u64().unwrap_or(0);
+    let completion = native["eval_count"].as_u64().unwrap_or(0);
+    json!({
+        "prompt_tokens": prompt,
+        "completion_tokens": completion,
+        "total_tokens": prompt + completion,
+    })
+}
+
+/// ID and creation time shared by a reply's chunks.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub id: String,
+    pub created: i64,
+}
+
+impl Completion {
+    /// A new reply, stamped now.
+    pub fn start() -> Self {
+        Completion {
+            id: format!("chatcmpl-{}", uuid::Uuid::new_v4().simple()),
+            created: chrono::Utc::now().timestamp(),
+        }
+    }
+
+    /// A whole (non-streamed) `/api/chat` reply as a `chat.completion`.
+    pub fn to_openai_response(&self, native: &Value) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion",
+            "created": self.created,
+            "model": native["model"],
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": native["message"]["content"].as_str().unwrap_or_default(),
+                },
+                "finish_reason": finish_reason(native["done_reason"].as_str()),
+            }],
+            "usage": usage(native),
+        })
+    }
+
+    fn chunk(&self, model: &Value, delta: Value, finish_reason: Option<&str>) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+    }
+}
+
+/// Turns a streamed `/api/chat` reply (NDJSON, split anywhere) into SSE.
+pub struct StreamTranslator {
+    completion: Completion,
+    include_usage: bool,
+    buffer: Vec<u8>,
+    sent_role: bool,
+    done: bool,
+}
+
+impl StreamTranslator {
+    /// `include_usage` is the request's `stream_options.include_usage`.
+    pub fn new(completion: Completion, include_usage: bool) -> Self {
+        StreamTranslator { completion, include_usage, buffer: Vec::new(), sent_role: false, done: false }
+    }
+
+    /// SSE text for the complete lines in `bytes`; a partial line waits
+    /// for the next call.
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.buffer.extend_from_slice(bytes);
+        let mut out = String::new();
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            self.line(&line, &mut out);
+        }
+        out
+    }
+
+    /// SSE text for what is left once the reply ends: a last line without
+    /// a newline, and `[DONE]`.
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        let rest = std::mem::take(&mut self.buffer);
+        self.line(&rest, &mut out);
+        if !self.done {
+            // Cut off before Ollama said it was done
+            self.done = true;
+            out.push_str("data: [DONE]\n\n");
+        }
+        out
+    }
+
+    fn line(&mut self, line: &[u8], out: &mut String) {
+        if self.done {
+            return;
+        }
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let Ok(native) = serde_json::from_str::<Value>(text) else {
+            tracing::warn!("Ollama sent a line that isn't JSON: {}", text);
+            return;
+        };
+        if let Some(error) = native["error"].as_str() {
+            event(out, &json!({ "error": { "message": error, "type": "server_error", "param": null, "code": "ollama_error" } }));
+            out.push_str("data: [DONE]\n\n");
+            self.done = true;
+            return;
+        }
+        let model = &native["model"];
+        let content = native["message"]["content"].as_str().unwrap_or_default();
+        if !self.sent_role || !content.is_empty() {
+            let mut delta = json!({ "content": content });
+            if !self.sent_role {
+                delta["role"] = json!("assistant");
+                self.sent_role = true;
+            }
+            event(out, &self.completion.chunk(model, delta, None));
+        }
+        if native["done"].as_bool() == Some(true) {
+            let reason = finish_reason(native["done_reason"].as_str());
+            event(out, &self.completion.chunk(model, json!({}), Some(reason)));
+            if self.include_usage {
+                let mut chunk = self.completion.chunk(model, json!({}), None);
+                chunk["choices"] = json!([]);
+                chunk["usage"] = usage(&native);
+                event(out, &chunk);
+            }
+            out.push_str("data: [DONE]\n\n");
+            self.done = true;
+        }
+    }
+}
+
+fn event(out: &mut String, data: &Value) {
+    out.push_str("data: ");
+    out.push_str(&data.to_string());
+    out.push_str("\n\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(sse: &str) -> Vec<Value> {
+        sse.split("\n\n")
+            .filter_map(|e| e.strip_prefix("data: "))
+            .filter(|d| *d != "[DONE]")
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn requests_map_to_native_fields_and_options() {
+        let req = json!({
+            "model": "llama3:8b",
+            "messages": [
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": [{ "type": "text", "text": "Hi" }, { "type": "text", "text": "there" }] },
+                { "role": "assistant", "content": null },
+            ],
+            "temperature": 0.2,
+            "top_p": 0.9,
+            "max_tokens": 64,
+            "stop": "###",
+            "seed": 7,
+            "num_ctx": 8192,
+            "mirostat": 2,
+            "keep_alive": "10m",
+            "options": { "temperature": 0.5 },
+            "response_format": { "type": "json_object" },
+            "user": "alice",
+        });
+        let native = to_native_request(&req).unwrap();
+        assert_eq!(native["model"], "llama3:8b");
+        assert_eq!(native["stream"], false);
+        assert_eq!(native["keep_alive"], "10m");
+        assert_eq!(native["format"], "json");
+        assert_eq!(
+            native["messages"],
+            json!([
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "Hi\nthere" },
+                { "role": "assistant", "content": "" },
+            ])
+        );
+        assert_eq!(
+            native["options"],
+            json!({
+                "temperature": 0.5,
+                "top_p": 0.9,
+                "num_predict": 64,
+                "stop": ["###"],
+                "seed": 7,
+                "num_ctx": 8192,
+                "mirostat": 2,
+            })
+        );
+
+        let minimal = to_native_request(&json!({ "model": "m", "messages": [], "stream": true })).unwrap();
+        assert_eq!(minimal, json!({ "model": "m", "messages": [], "stream": true }));
+        let schema = json!({ "type": "object", "properties": { "a": { "type": "string" } } });
+        let req = json!({ "model": "m", "messages": [], "stop": ["a", "b"], "max_completion_tokens": 5,
+            "response_format": { "type": "json_schema", "json_schema": { "name": "x", "schema": schema } } });
+        let native = to_native_request(&req).unwrap();
+        assert_eq!(native["format"], schema);
+        assert_eq!(native["options"], json!({ "stop": ["a", "b"], "num_predict": 5 }));
+    }
+
+    #[test]
+    fn what_native_chat_cannot_carry_is_refused() {
+        let with = |extra: Value| {
+            let mut req = json!({ "model": "m", "messages": [{ "role": "user", "content": "hi" }] });
+            req.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            to_native_request(&req)
+        };
+        let refused = |extra: Value, what: &str| {
+            let e = with(extra).unwrap_err();
+            assert!(e.contains(what), "{}", e);
+        };
+        refused(json!({ "tools": [{ "type": "function", "function": { "name": "f" } }] }), "tool calls");
+        refused(json!({ "tool_choice": "auto" }), "tool calls");
+        refused(json!({ "logprobs": true }), "logprobs");
+        refused(json!({ "n": 2 }), "n > 1");
+        refused(json!({ "response_format": { "type": "grammar" } }), "response_format");
+        let image = json!({ "type": "image_url", "image_url": { "url": "data:image/png;base64,AAAA" } });
+        refused(json!({ "messages": [{ "role": "user", "content": [image] }] }), "images");
+        refused(json!({ "messages": [{ "role": "tool", "content": "42", "tool_call_id": "c1" }] }), "role 'tool'");
+        let call = json!([{ "id": "c1", "type": "function", "function": { "name": "f", "arguments": "{}" } }]);
+        refused(json!({ "messages": [{ "role": "assistant", "content": null, "tool_calls": call }] }), "tool calls");
+        assert!(with(json!({ "messages": "hi" })).is_err());
+
+        // Explicit "off" values are fine
+        assert!(with(json!({ "logprobs": false, "tools": null, "n": 1 })).is_ok());
+    }
+
+    #[test]
+    fn done_reasons_become_finish_reasons() {
+        assert_eq!(finish_reason(Some("stop")), "stop");
+        assert_eq!(finish_reason(Some("length")), "length");
+        assert_eq!(finish_reason(Some("load")), "stop");
+        assert_eq!(finish_reason(None), "stop");
+    }
+
+    #[test]
+    fn whole_replies_round_trip() {
+        let req = json!({ "model": "llama3", "messages": [{ "role": "user", "content": "Count to 3" }], "max_tokens": 3 });
+        let native_req = to_native_request(&req).unwrap();
+        // What Ollama answers to that request
+        let native = json!({
+            "model": native_req["model"],
+            "created_at": "2026-10-18T09:30:00Z",
+            "message": { "role": "assistant", "content": "1, 2," },
+            "done": true,
+            "done_reason": "length",
+            "prompt_eval_count": 12,
+            "eval_count": 3,
+        });
+        let completion = Completion { id: "chatcmpl-1".into(), created: 1_700_000_000 };
+        assert_eq!(
+            completion.to_openai_response(&native),
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1_700_000_000,
+                "model": "llama3",
+                "choices": [{ "index": 0, "message": { "role": "assistant", "content": "1, 2," }, "finish_reason": "length" }],
+                "usage": { "prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15 },
+            })
+        );
+    }
+
+    #[test]
+    fn streams_become_sse_chunks_whatever_the_splits() {
+        let ndjson = [
+            json!({ "model": "llama3", "message": { "role": "assistant", "content": "Hel" }, "done": false }),
+            json!({ "model": "llama3", "message": { "role": "assistant", "content": "lo" }, "done": false }),
+            json!({ "model": "llama3", "message": { "role": "assistant", "content": "" }, "done": true,
+                "done_reason": "stop", "prompt_eval_count": 4, "eval_count": 2 }),
+        ]
+        .iter()
+        .map(|l| format!("{}\n", l))
+        .collect::<String>();
+
+        for split in [1, 7, ndjson.len()] {
+            let mut translator = StreamTranslator::new(Completion { id: "chatcmpl-1".into(), created: 1 }, true);
+            let mut sse: String = ndjson.as_bytes().chunks(split).map(|c| translator.push(c)).collect();
+            sse.push_str(&translator.finish());
+            assert!(sse.ends_with("data: [DONE]\n\n"));
+            assert_eq!(sse.matches("[DONE]").count(), 1);
+
+            let chunks = events(&sse);
+            assert_eq!(chunks.len(), 4, "split {}: {}", split, sse);
+            assert!(chunks.iter().all(|c| c["id"] == "chatcmpl-1" && c["model"] == "llama3"));
+            assert_eq!(chunks[0]["choices"][0]["delta"], json!({ "role": "assistant", "content": "Hel" }));
+            assert_eq!(chunks[1]["choices"][0]["delta"], json!({ "content": "lo" }));
+            assert!(chunks[1]["choices"][0]["finish_reason"].is_null());
+            assert_eq!(chunks[2]["choices"][0]["finish_reason"], "stop");
+            assert_eq!(chunks[2]["choices"][0]["delta"], json!({}));
+            assert_eq!(chunks[3]["usage"]["total_tokens"], 6);
+            assert_eq!(chunks[3]["choices"], json!([]));
+            let text: String = chunks.iter().filter_map(|c| c["choices"][0]["delta"]["content"].as_str()).collect();
+            assert_eq!(text, "Hello");
+        }
+    }
+
+    #[test]
+    fn stream_errors_and_cut_offs_still_end_the_stream() {
+        let mut translator = StreamTranslator::new(Completion::start(), false);
+        let mut sse = translator.push(b"{\"model\":\"m\",\"message\":{\"content\":\"a\"},\"done\":false}\n");
+        sse.push_str(&translator.push(b"{\"error\":\"model runner crashed\"}"));
+        sse.push_str(&translator.finish());
+        let chunks = events(&sse);
+        assert_eq!(chunks[1]["error"]["message"], "model runner crashed");
+        assert_eq!(chunks[1]["error"]["code"], "ollama_error");
+        assert_eq!(sse.matches("[DONE]").count(), 1);
+
+        // Ollama went away mid-reply: no finish_reason, but the client isn't left hanging
+        let mut translator = StreamTranslator::new(Completion::start(), true);
+        let mut sse = translator.push(b"{\"model\":\"m\",\"message\":{\"content\":\"a\"},\"done\":false}\n{\"mod");
+        sse.push_str(&translator.finish());
+        assert_eq!(events(&sse).len(), 1);
+        assert!(sse.ends_with("data: [DONE]\n\n"));
+    }
+}
@@ -0,0 +1,637 @@
+use anyhow::Result;
+use axum::body::Bytes;
+use chrono::{DateTime, NaiveTime, Utc};
+use futures::{stream::BoxStream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+use tokio::time::{Duration, Instant};
+
+use super::OllamaManager;
+use crate::db::{models::ModelPull, queries};
+use crate::settings::SettingsCache;
+use crate::ws::{EventBus, WsEvent};
+
+/// Setting: local time (`HH:MM`) the off-peak window opens.
+pub const OFFPEAK_START_KEY: &str = "offpeak_start";
+/// Setting: local time (`HH:MM`) the off-peak window closes.
+pub const OFFPEAK_END_KEY: &str = "offpeak_end";
+pub const DEFAULT_OFFPEAK_START: &str = "01:00";
+pub const DEFAULT_OFFPEAK_END: &str = "07:00";
+
+/// How often the worker looks for due pulls when nothing wakes it.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Least time between progress events (and DB writes) of one pull.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What kind of failure ended a pull, read from Ollama's message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PullErrorCode {
+    /// No such model or tag in the registry
+    NotFound,
+    /// Ollama's models directory ran out of space
+    DiskFull,
+    /// The registry or Ollama couldn't be reached, or the download broke off
+    Network,
+    /// Anything else; the message says what
+    Unknown,
+}
+
+/// Pieces of Ollama's (and Go's) error messages, by what they mean.
+const DISK_FULL: &[&str] = &["no space left", "not enough space", "disk full", "disk quota exceeded"];
+const NOT_FOUND: &[&str] = &["file does not exist", "not found", "manifest unknown"];
+const NETWORK: &[&str] = &[
+    "timeout",
+    "timed out",
+    "connection refused",
+    "connection reset",
+    "no such host",
+    "dial tcp",
+    "unexpected eof",
+    "network is unreachable",
+    "max retries exceeded",
+];
+
+impl PullErrorCode {
+    pub fn classify(message: &str) -> Self {
+        let message = message.to_ascii_lowercase();
+        let any = |pieces: &[&str]| pieces.iter().any(|p| message.contains(p));
+        if any(DISK_FULL) {
+            PullErrorCode::DiskFull
+        } else if any(NOT_FOUND) {
+            PullErrorCode::NotFound
+        } else if any(NETWORK) {
+            PullErrorCode::Network
+        } else {
+            PullErrorCode::Unknown
+        }
+    }
+}
+
+/// Why a pull failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullFailure {
+    pub code: PullErrorCode,
+    pub message: String,
+}
+
+impl PullFailure {
+    pub fn new(message: impl Into<String>) -> Self {
+        let message = message.into();
+        PullFailure { code: PullErrorCode::classify(&message), message }
+    }
+
+    pub fn network(message: impl Into<String>) -> Self {
+        PullFailure { code: PullErrorCode::Network, message: message.into() }
+    }
+
+    /// Ollama answered the pull with an error status instead of a stream.
+    pub async fn refused(name: &str, resp: reqwest::Response) -> Self {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        let error = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v["error"].as_str().map(str::to_string))
+            .unwrap_or_else(|| body.trim().to_string());
+        let code = match PullErrorCode::classify(&error) {
+            PullErrorCode::Unknown if status == reqwest::StatusCode::NOT_FOUND => PullErrorCode::NotFound,
+            code => code,
+        };
+        let message = format!("Ollama pull failed for '{}': HTTP {}: {}", name, status.as_u16(), error);
+        PullFailure { code, message }
+    }
+
+    /// The NDJSON line a relayed pull ends with.
+    pub fn final_line(&self) -> Bytes {
+        let mut line = serde_json::to_vec(&serde_json::json!({
+            "error": self.message,
+            "code": self.code,
+            "done": true,
+        }))
+        .unwrap_or_default();
+        line.push(b'\n');
+        line.into()
+    }
+}
+
+impl fmt::Display for PullFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// One line of Ollama's pull progress.
+#[derive(Debug)]
+enum PullLine {
+    /// Passed on as it came
+    Progress { line: Vec<u8>, status: Option<String> },
+    Failed(PullFailure),
+}
+
+/// Reads Ollama's NDJSON pull progress as it arrives.
+#[derive(Default)]
+struct PullReader {
+    buf: Vec<u8>,
+    completed: Option<i64>,
+    total: Option<i64>,
+    /// The last status was `success`
+    succeeded: bool,
+}
+
+impl PullReader {
+    /// The lines completed by `chunk`. Lines that aren't JSON are dropped.
+    fn push(&mut self, chunk: &[u8]) -> Vec<PullLine> {
+        self.buf.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            lines.extend(self.read(line));
+        }
+        lines
+    }
+
+    /// The stream has ended: its last line, if it had no newline.
+    fn finish(&mut self) -> Vec<PullLine> {
+        if self.buf.iter().all(u8::is_ascii_whitespace) {
+            return Vec::new();
+        }
+        self.push(b"\n")
+    }
+
+    /// How the pull went, once the stream has ended without an error line.
+    fn outcome(&self) -> Result<(), PullFailure> {
+        if self.succeeded {
+            Ok(())
+        } else {
+            Err(PullFailure::network("Ollama's progress stream ended before the pull finished"))
+        }
+    }
+
+    fn read(&mut self, mut line: Vec<u8>) -> Option<PullLine> {
+        let value = serde_json::from_slice::<serde_json::Value>(&line).ok()?;
+        if let Some(error) = value["error"].as_str() {
+            return Some(PullLine::Failed(PullFailure::new(error)));
+        }
+        if let (Some(completed), Some(total)) = (value["completed"].as_i64(), value["total"].as_i64()) {
+            self.completed = Some(completed);
+            self.total = Some(total);
+        }
+        let status = value["status"].as_str().map(str::to_string);
+        self.succeeded = status.as_deref() == Some("success");
+        if line.last() != Some(&b'\n') {
+            line.push(b'\n');
+        }
+        Some(PullLine::Progress { line, status })
+    }
+}
+
+/// A pull relayed to its caller: see [`PullQueue::relay`].
+struct Relay {
+    queue: Arc<PullQueue>,
+    record: ModelPull,
+    stream: BoxStream<'static, reqwest::Result<Bytes>>,
+    reader: PullReader,
+    lines: VecDeque<PullLine>,
+    ended: bool,
+    done: bool,
+}
+
+impl Relay {
+    /// End the relay with `failure`: record it and return the final line.
+    async fn fail(&mut self, failure: PullFailure) -> Bytes {
+        self.done = true;
+        let line = failure.final_line();
+        self.queue.record(&mut self.record, Some(failure), &self.reader).await;
+        line
+    }
+
+    async fn next(mut self) -> Option<(Result<Bytes, std::io::Error>, Self)> {
+        loop {
+            if self.done {
+                return None;
+            }
+            match self.lines.pop_front() {
+                Some(PullLine::Progress { line, .. }) => return Some((Ok(line.into()), self)),
+                Some(PullLine::Failed(failure)) => return Some((Ok(self.fail(failure).await), self)),
+                None => {}
+            }
+            if self.ended {
+                return match self.reader.outcome() {
+                    Ok(()) => {
+                        self.queue.record(&mut self.record, None, &self.reader).await;
+                        None
+                    }
+                    Err(failure) => Some((Ok(self.fail(failure).await), self)),
+                };
+            }
+            match self.stream.next().await {
+                Some(Ok(chunk)) => {
+                    let lines = self.reader.push(&chunk);
+                    self.lines.extend(lines);
+                }
+                Some(Err(e)) => {
+                    let failure = PullFailure::network(format!("Ollama's progress stream broke off: {}", e));
+                    return Some((Ok(self.fail(failure).await), self));
+                }
+                None => {
+                    let lines = self.reader.finish();
+                    self.lines.extend(lines);
+                    self.ended = true;
+                }
+            }
+        }
+    }
+}
+
+pub fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value.trim(), "%H:%M").ok()
+}
+
+/// Daily `[start, end)` window in local time; wraps past midnight when
+/// `end` is earlier than `start`.
+#[derive(Debug, Clone, Copy)]
+pub struct OffPeakWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl OffPeakWindow {
+    fn from_settings(settings: &SettingsCache) -> Self {
+        let time = |key, default| {
+            settings
+                .get(key)
+                .and_then(|v| parse_time(&v))
+                .or_else(|| parse_time(default))
+                .unwrap_or_default()
+        };
+        OffPeakWindow {
+            start: time(OFFPEAK_START_KEY, DEFAULT_OFFPEAK_START),
+            end: time(OFFPEAK_END_KEY, DEFAULT_OFFPEAK_END),
+        }
+    }
+
+    pub fn contains(&self, t: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= t && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+/// Whether a pending pull may start at `now` (`local` is the same instant
+/// as wall-clock time).
+fn is_due(pull: &ModelPull, now: DateTime<Utc>, local: NaiveTime, window: OffPeakWindow) -> bool {
+    let after = pull
+        .not_before
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .is_none_or(|t| now >= t);
+    after && (!pull.off_peak || window.contains(local))
+}
+
+/// Runs queued pulls one at a time, each once it is due.
+pub struct PullQueue {
+    pool: SqlitePool,
+    ollama: Arc<OllamaManager>,
+    settings: Arc<SettingsCache>,
+    event_tx: EventBus,
+    wake: Notify,
+    /// The pull being run, so it can be cancelled
+    active: Mutex<Option<(String, AbortHandle)>>,
+}
+
+impl PullQueue {
+    pub fn new(
+        pool: SqlitePool,
+        ollama: Arc<OllamaManager>,
+        settings: Arc<SettingsCache>,
+        event_tx: EventBus,
+    ) -> Self {
+        PullQueue {
+            pool,
+            ollama,
+            settings,
+            event_tx,
+            wake: Notify::new(),
+            active: Mutex::new(None),
+        }
+    }
+
+    /// Look for due pulls now, e.g. after one was queued or the window moved.
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Cancel a pending or running pull. Returns false if it had already
+    /// finished.
+    pub async fn cancel(&self, id: &str) -> Result<bool> {
+        if !queries::finish_model_pull(&self.pool, id, "cancelled", None).await? {
+            return Ok(false);
+        }
+        {
+            let mut active = self.active.lock().unwrap();
+            if active.as_ref().is_some_and(|(active_id, _)| active_id == id) {
+                if let Some((_, handle)) = active.take() {
+                    handle.abort();
+                }
+            }
+        }
+        if let Some(pull) = queries::get_model_pull(&self.pool, id).await? {
+            self.broadcast(&pull, "cancelled");
+        }
+        Ok(true)
+    }
+
+    fn broadcast(&self, pull: &ModelPull, status: &str) {
+        let _ = self.event_tx.send(WsEvent::ModelPullProgress {
+            job_id: pull.id.clone(),
+            name: pull.name.clone(),
+            status: status.to_string(),
+            completed: pull.completed_bytes,
+            total: pull.total_bytes,
+            error: pull.error.clone(),
+        });
+    }
+
+    pub fn spawn(self: Arc<Self>) {
+        tokio::spawn(async move {
+            match queries::requeue_active_model_pulls(&self.pool).await {
+                Ok(0) => {}
+                Ok(n) => tracing::info!("Re-queued {} interrupted model pull(s)", n),
+                Err(e) => tracing::warn!("Failed to re-queue interrupted model pulls: {}", e),
+            }
+            loop {
+                match self.next_due().await {
+                    Some(pull) => self.clone().run(pull).await,
+                    None => {
+                        tokio::select! {
+                            _ = self.wake.notified() => {}
+                            _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    async fn next_due(&self) -> Option<ModelPull> {
+        let pending = match queries::pending_model_pulls(&self.pool).await {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::warn!("Failed to read the model pull queue: {}", e);
+                return None;
+            }
+        };
+        let window = OffPeakWindow::from_settings(&self.settings);
+        let now = Utc::now();
+        let local = chrono::Local::now().time();
+        pending.into_iter().find(|p| is_due(p, now, local, window))
+    }
+
+    async fn run(self: Arc<Self>, mut pull: ModelPull) {
+        if let Err(e) = queries::start_model_pull(&self.pool, &pull.id).await {
+            tracing::warn!("Failed to start model pull {}: {}", pull.id, e);
+            return;
+        }
+        tracing::info!("Pulling queued model {} ({})", pull.name, pull.id);
+        self.broadcast(&pull, "active");
+
+        let worker = self.clone();
+        let job = pull.clone();
+        let task = tokio::spawn(async move { worker.pull(&job).await });
+        *self.active.lock().unwrap() = Some((pull.id.clone(), task.abort_handle()));
+        // A cancel that landed before the handle was stored could not abort it
+        if let Ok(Some(current)) = queries::get_model_pull(&self.pool, &pull.id).await {
+            if current.status == "cancelled" {
+                task.abort();
+            }
+        }
+        let result = task.await;
+        self.active.lock().unwrap().take();
+
+        let (status, error) = match result {
+            // Cancelled: `cancel` has recorded and announced it
+            Err(e) if e.is_cancelled() => return,
+            Err(e) => ("failed", Some(PullFailure { code: PullErrorCode::Unknown, message: e.to_string() })),
+            Ok(Ok(())) => ("completed", None),
+            Ok(Err(failure)) => ("failed", Some(failure)),
+        };
+        let recorded = error.as_ref().map(|f| (f.message.as_str(), f.code));
+        match queries::finish_model_pull(&self.pool, &pull.id, status, recorded).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => tracing::warn!("Failed to record model pull {}: {}", pull.id, e),
+        }
+        if let Some(e) = &error {
+            tracing::warn!("Queued pull of {} failed: {}", pull.name, e);
+        }
+        if let Ok(Some(finished)) = queries::get_model_pull(&self.pool, &pull.id).await {
+            pull = finished;
+        }
+        self.broadcast(&pull, status);
+    }
+
+    /// Stream Ollama's pull progress into the row and `model_pull_progress` events.
+    async fn pull(&self, pull: &ModelPull) -> Result<(), PullFailure> {
+        let resp = self
+            .ollama
+            .pull_model_stream(&pull.name)
+            .await
+            .map_err(|e| PullFailure::network(format!("Ollama unreachable: {}", e)))?;
+        if !resp.status().is_success() {
+            return Err(PullFailure::refused(&pull.name, resp).await);
+        }
+        let mut progress = pull.clone();
+        let mut last_sent: Option<Instant> = None;
+        let mut reader = PullReader::default();
+        let mut stream = resp.bytes_stream();
+        loop {
+            let chunk = stream.next().await;
+            let ended = chunk.is_none();
+            let lines = match chunk {
+                Some(chunk) => reader.push(&chunk.map_err(|e| {
+                    PullFailure::network(format!("Ollama's progress stream broke off: {}", e))
+                })?),
+                None => reader.finish(),
+            };
+            for line in lines {
+                let status = match line {
+                    PullLine::Failed(failure) => return Err(failure),
+                    PullLine::Progress { status, .. } => status,
+                };
+                progress.completed_bytes = reader.completed.or(progress.completed_bytes);
+                progress.total_bytes = reader.total.or(progress.total_bytes);
+                if last_sent.is_none_or(|t| t.elapsed() >= PROGRESS_INTERVAL) {
+                    last_sent = Some(Instant::now());
+                    let _ = queries::update_model_pull_progress(
+                        &self.pool,
+                        &pull.id,
+                        progress.completed_bytes,
+                        progress.total_bytes,
+                    )
+                    .await;
+                    self.broadcast(&progress, status.as_deref().unwrap_or("active"));
+                }
+            }
+            if ended {
+                return reader.outcome();
+            }
+        }
+    }
+
+    /// Relay a pull started by `POST /api/models/pull` to its caller:
+    /// Ollama's progress lines as they come, until the first error, which
+    /// ends the stream with [`PullFailure::final_line`]. A stream that stops
+    /// without `success` ends the same way, as a network failure. The pull
+    /// is recorded in `model_pulls` once it ends.
+    pub fn relay(
+        self: Arc<Self>,
+        name: &str,
+        resp: reqwest::Response,
+    ) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static {
+        let relay = Relay {
+            record: direct_pull(name),
+            queue: self,
+            stream: resp.bytes_stream().boxed(),
+            reader: PullReader::default(),
+            lines: VecDeque::new(),
+            ended: false,
+            done: false,
+        };
+        futures::stream::unfold(relay, Relay::next)
+    }
+
+    /// Record a pull that Ollama refused before streaming anything.
+    pub async fn record_refused(&self, name: &str, failure: PullFailure) {
+        self.record(&mut direct_pull(name), Some(failure), &PullReader::default()).await;
+    }
+
+    /// Store a pull run outside the queue, as it ended.
+    async fn record(&self, pull: &mut ModelPull, failure: Option<PullFailure>, reader: &PullReader) {
+        pull.status = if failure.is_some() { "failed" } else { "completed" }.into();
+        pull.completed_bytes = reader.completed;
+        pull.total_bytes = reader.total;
+        pull.finished_at = Some(Utc::now().to_rfc3339());
+        if let Some(failure) = failure {
+            tracing::warn!("Pull of {} failed ({:?}): {}", pull.name, failure.code, failure.message);
+            pull.error = Some(failure.message);
+            pull.error_code = Some(failure.code);
+        }
+        if let Err(e) = queries::record_model_pull(&self.pool, pull).await {
+            tracing::warn!("Failed to record the pull of {}: {}", pull.name, e);
+        }
+    }
+}
+
+/// The row of a pull started now, outside the queue.
+fn direct_pull(name: &str) -> ModelPull {
+    let now = Utc::now().to_rfc3339();
+    ModelPull {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        status: "active".into(),
+        off_peak: false,
+        not_before: None,
+        completed_bytes: None,
+        total_bytes: None,
+        error: None,
+        error_code: None,
+        created_at: now.clone(),
+        started_at: Some(now),
+        finished_at: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pull(off_peak: bool, not_before: Option<&str>) -> ModelPull {
+        ModelPull {
+            id: "p1".into(),
+            name: "llama3:70b".into(),
+            status: "pending".into(),
+            off_peak,
+            not_before: not_before.map(Into::into),
+            completed_bytes: None,
+            total_bytes: None,
+            error: None,
+            error_code: None,
+            created_at: "2026-01-01T00:00:00Z".into(),
+            started_at: None,
+            finished_at: None,
+        }
+    }
+
+    #[test]
+    fn window_wraps_past_midnight() {
+        let t = |s| parse_time(s).unwrap();
+        let night = OffPeakWindow { start: t("23:00"), end: t("06:00") };
+        assert!(night.contains(t("23:30")));
+        assert!(night.contains(t("02:00")));
+        assert!(!night.contains(t("06:00")));
+        assert!(!night.contains(t("12:00")));
+        let day = OffPeakWindow { start: t("01:00"), end: t("07:00") };
+        assert!(day.contains(t("01:00")));
+        assert!(!day.contains(t("23:30")));
+        assert!(parse_time("25:00").is_none());
+    }
+
+    #[test]
+    fn pulls_wait_for_window_and_time() {
+        let t = |s| parse_time(s).unwrap();
+        let window = OffPeakWindow { start: t("01:00"), end: t("07:00") };
+        let now: DateTime<Utc> = "2026-03-01T12:00:00Z".parse().unwrap();
+
+        assert!(is_due(&pull(false, None), now, t("12:00"), window));
+        assert!(!is_due(&pull(true, None), now, t("12:00"), window));
+        assert!(is_due(&pull(true, None), now, t("02:00"), window));
+        assert!(!is_due(&pull(false, Some("2026-03-01T13:00:00Z")), now, t("12:00"), window));
+        assert!(is_due(&pull(false, Some("2026-03-01T11:00:00+00:00")), now, t("12:00"), window));
+        // Both: the later of the two
+        assert!(!is_due(&pull(true, Some("2026-03-01T11:00:00Z")), now, t("12:00"), window));
+    }
+
+    #[test]
+    fn failures_are_classified_from_ollama_messages() {
+        let code = PullErrorCode::classify;
+        assert_eq!(code("pull model manifest: file does not exist"), PullErrorCode::NotFound);
+        assert_eq!(
+            code("write /root/.ollama/models/blobs/sha256-ab-partial: no space left on device"),
+            PullErrorCode::DiskFull
+        );
+        assert_eq!(
+            code("Get \"https://registry.ollama.ai/v2/library/llama3/manifests/latest\": net/http: TLS handshake timeout"),
+            PullErrorCode::Network
+        );
+        assert_eq!(code("dial tcp: lookup registry.ollama.ai: no such host"), PullErrorCode::Network);
+        assert_eq!(code("invalid model name"), PullErrorCode::Unknown);
+    }
+
+    #[test]
+    fn progress_is_read_across_chunks() {
+        let mut reader = PullReader::default();
+        assert_eq!(reader.push(b"{\"status\":\"pulling manifest\"}\n{\"status\":\"pulling ab\",").len(), 1);
+        let lines = reader.push(b"\"completed\":5,\"total\":10}\nnot json\n");
+        assert_eq!(lines.len(), 1);
+        assert_eq!((reader.completed, reader.total), (Some(5), Some(10)));
+        assert_eq!(reader.outcome().unwrap_err().code, PullErrorCode::Network);
+
+        // The last line may come without its newline
+        reader.push(b"{\"status\":\"success\"}");
+        assert!(matches!(&reader.finish()[..], [PullLine::Progress { line, .. }] if line.ends_with(b"\n")));
+        assert!(reader.outcome().is_ok());
+
+        let lines = reader.push(b"{\"error\":\"max retries exceeded: unexpected EOF\"}\n");
+        assert!(matches!(&lines[..], [PullLine::Failed(f)] if f.code == PullErrorCode::Network));
+        let line: serde_json::Value = serde_json::from_slice(&PullFailure::new("file does not exist").final_line()).unwrap();
+        assert_eq!(line, serde_json::json!({ "error": "file does not exist", "code": "not_found", "done": true }));
+    }
+}
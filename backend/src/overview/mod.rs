@@ -0,0 +1,327 @@
+//! One aggregate of the cluster for the dashboard's landing page, built
+//! from what is already cached: provider snapshots, remote devices' last
+//! readings, device rows as heartbeats leave them, the llama.cpp session,
+//! Ollama's last known state and the recent errors. Nothing is probed, so
+//! `GET /api/overview` answers in a few milliseconds.
+//!
+//! [`spawn`] broadcasts a smaller `overview` event whenever one of these
+//! changes materially.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    db::queries,
+    ollama::OllamaMode,
+    permissions::{DeviceStatus, RpcStatus},
+    ws::WsEvent,
+    AppState,
+};
+
+/// Errors within this long count as recent.
+const RECENT_ERRORS_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// How often the broadcaster looks again after events arrived.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Looked at this often even without events, for changes that have none
+/// (remote readings, heartbeats).
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// Free memory moving by less than this share of the total (percent) isn't
+/// worth an event.
+const MEMORY_STEP_PCT: u64 = 5;
+
+/// Memory of the local providers plus the cluster devices whose RPC server
+/// is up.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct MemoryTotals {
+    pub total_mb: u64,
+    pub used_mb: u64,
+    pub free_mb: u64,
+    /// Local memory providers counted
+    pub providers: usize,
+    /// Reachable cluster devices counted
+    pub cluster_devices: usize,
+}
+
+/// Devices by status.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct DeviceCounts {
+    pub total: usize,
+    pub pending: usize,
+    pub approved: usize,
+    pub denied: usize,
+    pub suspended: usize,
+    pub offline: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct InferenceSummary {
+    pub running: bool,
+    /// Session status: starting, running, restarting, draining, ...
+    pub status: Option<String>,
+    /// Model path of the session
+    pub model: Option<String>,
+    pub uptime_secs: Option<u64>,
+    /// RPC devices (`ip:port`) the session uses
+    pub devices: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct OllamaSummary {
+    /// Whether Ollama answered when last checked
+    pub running: bool,
+    pub host: String,
+    pub mode: OllamaMode,
+    /// Held by the models Ollama keeps loaded on this host, as `/api/ps`
+    /// last listed them
+    pub resident_mb: u64,
+}
+
+/// `GET /api/overview`
+#[derive(Debug, Clone, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct Overview {
+    pub memory: MemoryTotals,
+    pub devices: DeviceCounts,
+    pub inference: InferenceSummary,
+    pub ollama: OllamaSummary,
+    /// Devices waiting for approval
+    pub pending_approvals: usize,
+    /// `error` events in the last hour
+    pub recent_errors: usize,
+    /// Problems with the session asked about in `?model=`
+    pub warnings: Vec<String>,
+}
+
+/// The overview as of now.
+pub async fn compute(state: &AppState) -> Result<Overview> {
+    let devices = queries::list_devices(&state.pool).await?;
+
+    let mut memory = MemoryTotals::default();
+    for snapshot in state.memory.cached().0 {
+        memory.total_mb += snapshot.total_mb;
+        memory.used_mb += snapshot.used_mb;
+        memory.free_mb += snapshot.free_mb;
+        memory.providers += 1;
+    }
+    for device in devices
+        .iter()
+        .filter(|d| d.status == DeviceStatus::Approved && d.rpc_status == RpcStatus::Ready)
+    {
+        let (total, free) = state
+            .remote_memory
+            .get(&device.id)
+            .unwrap_or((device.memory_total_mb, device.memory_free_mb));
+        let (total, free) = (total.max(0) as u64, free.max(0) as u64);
+        memory.total_mb += total;
+        memory.free_mb += free.min(total);
+        memory.used_mb += total.saturating_sub(free);
+        memory.cluster_devices += 1;
+    }
+
+    let mut counts = DeviceCounts { total: devices.len(), ..Default::default() };
+    for device in &devices {
+        match device.status {
+            DeviceStatus::Pending => counts.pending += 1,
+            DeviceStatus::Approved => counts.approved += 1,
+            DeviceStatus::Denied => counts.denied += 1,
+            DeviceStatus::Suspended => counts.suspended += 1,
+            DeviceStatus::Offline => counts.offline += 1,
+        }
+    }
+
+    let inference = match state.llama_cpp.get_current_session().await {
+        Some(session) => InferenceSummary {
+            running: true,
+            uptime_secs: chrono::DateTime::parse_from_rfc3339(&session.started_at)
+                .ok()
+                .map(|t| (chrono::Utc::now() - t.with_timezone(&chrono::Utc)).num_seconds().max(0) as u64),
+            status: Some(session.status),
+            model: Some(session.model_path),
+            devices: session.rpc_devices,
+        },
+        None => InferenceSummary::default(),
+    };
+
+    let cutoff = chrono::Utc::now() - chrono::Duration::from_std(RECENT_ERRORS_WINDOW)?;
+    let recent_errors = state
+        .errors
+        .recent()
+        .iter()
+        .filter(|e| chrono::DateTime::parse_from_rfc3339(&e.timestamp).is_ok_and(|t| t >= cutoff))
+        .count();
+
+    Ok(Overview {
+        memory,
+        pending_approvals: counts.pending,
+        devices: counts,
+        inference,
+        ollama: OllamaSummary {
+            running: state.ollama.running().await,
+            host: state.ollama.host(),
+            mode: state.ollama.mode(),
+            resident_mb: state.ollama.resident().iter().map(|m| m.size).sum::<u64>() / (1024 * 1024),
+        },
+        recent_errors,
+        warnings: Vec::new(),
+    })
+}
+
+impl Overview {
+    /// Warn if a session of `model` needing `need_mb` won't fit in free
+    /// memory, naming what Ollama's loaded models hold when they are part
+    /// of the reason.
+    pub fn check_session(&mut self, model: &str, need_mb: u64) {
+        let free = self.memory.free_mb;
+        if need_mb <= free {
+            return;
+        }
+        let resident = self.ollama.resident_mb;
+        self.warnings.push(if resident > 0 {
+            format!(
+                "{} needs about {} MB but only {} MB is free; Ollama keeps {} MB of models loaded, which POST /api/ollama/unload frees{}",
+                model,
+                need_mb,
+                free,
+                resident,
+                if need_mb > free + resident { ", though not enough" } else { "" },
+            )
+        } else {
+            format!("{} needs about {} MB but only {} MB is free", model, need_mb, free)
+        });
+    }
+
+    /// The `overview` event: the headline numbers only.
+    pub fn event(&self) -> WsEvent {
+        WsEvent::Overview {
+            memory_total_mb: self.memory.total_mb,
+            memory_free_mb: self.memory.free_mb,
+            devices_approved: self.devices.approved,
+            devices_offline: self.devices.offline,
+            pending_approvals: self.pending_approvals,
+            inference_running: self.inference.running,
+            ollama_running: self.ollama.running,
+            recent_errors: self.recent_errors,
+        }
+    }
+
+    /// Whether `self` is different enough from `before` to broadcast: any
+    /// count or state changed, or free memory moved by [`MEMORY_STEP_PCT`]
+    /// of the total or more.
+    pub fn differs_materially(&self, before: &Overview) -> bool {
+        let step = self.memory.total_mb * MEMORY_STEP_PCT / 100;
+        self.memory.total_mb != before.memory.total_mb
+            || self.memory.free_mb.abs_diff(before.memory.free_mb) >= step.max(1)
+            || self.memory.providers != before.memory.providers
+            || self.memory.cluster_devices != before.memory.cluster_devices
+            || self.devices != before.devices
+            || self.inference.running != before.inference.running
+            || self.inference.status != before.inference.status
+            || self.inference.model != before.inference.model
+            || self.inference.devices != before.inference.devices
+            || self.ollama != before.ollama
+            || self.recent_errors != before.recent_errors
+    }
+}
+
+/// Broadcast an `overview` event whenever the overview changes materially.
+/// It is looked at again a moment after other events, and every
+/// [`REFRESH_INTERVAL`] regardless.
+pub fn spawn(state: Arc<AppState>) {
+    let mut rx = state.event_tx.subscribe();
+    tokio::spawn(async move {
+        let mut last = compute(&state).await.ok();
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        let mut dirty = false;
+        let mut checked_at = tokio::time::Instant::now();
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    // Its own events, and ones that never move it
+                    Ok(WsEvent::Overview { .. })
+                    | Ok(WsEvent::InferenceMetrics { .. })
+                    | Ok(WsEvent::ModelPullProgress { .. })
+                    | Ok(WsEvent::WsClientConnected { .. })
+                    | Ok(WsEvent::WsClientDisconnected { .. }) => {}
+                    Ok(_) | Err(RecvError::Lagged(_)) => dirty = true,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => {
+                    if !dirty && checked_at.elapsed() < REFRESH_INTERVAL {
+                        continue;
+                    }
+                    dirty = false;
+                    checked_at = tokio::time::Instant::now();
+                    let overview = match compute(&state).await {
+                        Ok(o) => o,
+                        Err(e) => {
+                            tracing::warn!("Failed to compute the overview: {}", e);
+                            continue;
+                        }
+                    };
+                    if last.as_ref().is_none_or(|l| overview.differs_materially(l)) {
+                        let _ = state.event_tx.send(overview.event());
+                        last = Some(overview);
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overview(total_mb: u64, free_mb: u64) -> Overview {
+        Overview {
+            memory: MemoryTotals { total_mb, used_mb: total_mb - free_mb, free_mb, providers: 1, cluster_devices: 0 },
+            devices: DeviceCounts::default(),
+            inference: InferenceSummary::default(),
+            ollama: OllamaSummary {
+                running: false,
+                host: "http://127.0.0.1:11434".into(),
+                mode: OllamaMode::Local,
+                resident_mb: 0,
+            },
+            pending_approvals: 0,
+            recent_errors: 0,
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn small_memory_moves_are_not_material() {
+        let before = overview(20_000, 10_000);
+        assert!(!overview(20_000, 10_900).differs_materially(&before));
+        assert!(overview(20_000, 11_000).differs_materially(&before));
+        assert!(overview(24_000, 10_000).differs_materially(&before));
+
+        let mut pending = overview(20_000, 10_000);
+        pending.devices.pending = 1;
+        pending.pending_approvals = 1;
+        assert!(pending.differs_materially(&before));
+        let mut running = overview(20_000, 10_000);
+        running.inference.running = true;
+        assert!(running.differs_materially(&before));
+    }
+
+    #[test]
+    fn sessions_that_do_not_fit_name_ollama() {
+        let mut fits = overview(20_000, 10_000);
+        fits.check_session("llama3", 8_000);
+        assert!(fits.warnings.is_empty());
+
+        let mut crowded = overview(20_000, 10_000);
+        crowded.ollama.resident_mb = 6_000;
+        crowded.check_session("llama3", 12_000);
+        assert_eq!(crowded.warnings.len(), 1);
+        assert!(crowded.warnings[0].contains("Ollama keeps 6000 MB"), "{}", crowded.warnings[0]);
+        assert!(!crowded.warnings[0].contains("not enough"));
+
+        let mut full = overview(20_000, 10_000);
+        full.check_session("llama3", 12_000);
+        assert!(!full.warnings[0].contains("Ollama"));
+    }
+}
@@ -0,0 +1,217 @@
+//! Device addresses as given at registration: an IP in any common spelling,
+//! or a hostname to resolve. Stored IPs are always canonical, so one machine
+//! can't be registered twice under different spellings.
+
+use sqlx::SqlitePool;
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::db::{models::Device, queries};
+
+/// A registration address in canonical form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceAddress {
+    /// Dotted decimal without leading zeros, or compressed lowercase IPv6
+    pub ip: String,
+    /// The name given, when it was a hostname rather than an IP
+    pub hostname: Option<String>,
+}
+
+/// Why an address can't identify a device.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddressError {
+    Empty,
+    /// Neither an IP nor a valid hostname
+    Invalid(String),
+    /// Unspecified, multicast, broadcast or reserved: never one machine
+    NotUnicast(IpAddr),
+    /// A hostname that doesn't resolve
+    Unresolved(String),
+}
+
+impl std::fmt::Display for AddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressError::Empty => write!(f, "'ip' is required"),
+            AddressError::Invalid(raw) => write!(f, "'{}' is not an IP address or hostname", raw),
+            AddressError::NotUnicast(ip) => write!(f, "{} can't be a device address", ip),
+            AddressError::Unresolved(host) => write!(f, "Could not resolve '{}'", host),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// Parse `raw` as an IP, allowing surrounding whitespace, leading zeros in
+/// IPv4 octets (read as decimal), brackets around IPv6 and IPv4-mapped IPv6.
+/// `None` when it isn't an IP.
+pub fn parse_ip(raw: &str) -> Option<IpAddr> {
+    let raw = raw.trim();
+    let unbracketed = raw.strip_prefix('[').and_then(|r| r.strip_suffix(']')).unwrap_or(raw);
+    if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    parse_padded_v4(raw).map(IpAddr::V4)
+}
+
+/// `192.168.001.005`, which the standard parser refuses.
+fn parse_padded_v4(raw: &str) -> Option<Ipv4Addr> {
+    let octets: Vec<u8> = raw
+        .split('.')
+        .map(|o| {
+            if o.is_empty() || o.len() > 3 || !o.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            o.parse().ok()
+        })
+        .collect::<Option<_>>()?;
+    let octets: [u8; 4] = octets.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+/// `ip`, unless it can't stand for a single machine.
+fn check_unicast(ip: IpAddr) -> Result<IpAddr, AddressError> {
+    let bad = match ip {
+        IpAddr::V4(v4) => {
+            v4.is_unspecified() || v4.is_multicast() || v4.is_broadcast() || v4.octets()[0] >= 240
+        }
+        IpAddr::V6(v6) => v6.is_unspecified() || v6.is_multicast(),
+    };
+    if bad {
+        Err(AddressError::NotUnicast(ip))
+    } else {
+        Ok(ip)
+    }
+}
+
+/// RFC 1123 hostname; an all-numeric last label is a mistyped IP instead.
+fn valid_hostname(name: &str) -> bool {
+    let labels: Vec<&str> = name.split('.').collect();
+    name.len() <= 253
+        && labels.iter().all(|l| {
+            !l.is_empty()
+                && l.len() <= 63
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+        && labels.last().is_some_and(|l| !l.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// The canonical address for `raw`: the IP itself, or what a hostname
+/// resolves to (IPv4 preferred).
+pub async fn normalize(raw: &str) -> Result<DeviceAddress, AddressError> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Err(AddressError::Empty);
+    }
+    if let Some(ip) = parse_ip(raw) {
+        return Ok(DeviceAddress { ip: check_unicast(ip)?.to_string(), hostname: None });
+    }
+
+    let host = raw.trim_end_matches('.').to_ascii_lowercase();
+    if !valid_hostname(&host) {
+        return Err(AddressError::Invalid(raw.to_string()));
+    }
+    let resolved: Vec<IpAddr> = tokio::net::lookup_host((host.as_str(), 0))
+        .await
+        .map_err(|_| AddressError::Unresolved(host.clone()))?
+        .map(|a| a.ip().to_canonical())
+        .collect();
+    let ip = resolved
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or(resolved.first())
+        .copied()
+        .ok_or_else(|| AddressError::Unresolved(host.clone()))?;
+    Ok(DeviceAddress { ip: check_unicast(ip)?.to_string(), hostname: Some(host) })
+}
+
+/// Rewrite stored device IPs written before registration normalized them.
+/// Devices that turn out to share an IP are merged into the most recently
+/// seen one. Returns how many devices were merged away.
+pub async fn normalize_stored(pool: &SqlitePool) -> anyhow::Result<usize> {
+    let mut merged = 0;
+    for device in queries::list_devices(pool).await? {
+        let Some(ip) = parse_ip(&device.ip).map(|ip| ip.to_string()) else {
+            continue;
+        };
+        if ip == device.ip {
+            continue;
+        }
+        match queries::get_device_by_ip(pool, &ip).await? {
+            None => queries::update_device_ip(pool, &device.id, &ip).await?,
+            Some(other) => {
+                let seen = |d: &Device| d.last_seen.clone().unwrap_or_else(|| d.first_seen.clone());
+                let (keep, drop) = if seen(&device) > seen(&other) {
+                    (&device, &other)
+                } else {
+                    (&other, &device)
+                };
+                queries::merge_devices(pool, &keep.id, &drop.id).await?;
+                queries::update_device_ip(pool, &keep.id, &ip).await?;
+                tracing::info!("Merged device {} into {} ({})", drop.name, keep.name, ip);
+                merged += 1;
+            }
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(raw: &str) -> Option<String> {
+        parse_ip(raw).map(|ip| ip.to_string())
+    }
+
+    #[test]
+    fn ips_are_canonical() {
+        assert_eq!(ip("192.168.1.5").as_deref(), Some("192.168.1.5"));
+        assert_eq!(ip("  192.168.1.5 ").as_deref(), Some("192.168.1.5"));
+        assert_eq!(ip("192.168.001.005").as_deref(), Some("192.168.1.5"));
+        assert_eq!(ip("FE80:0000:0000:0000:0000:0000:0000:0001").as_deref(), Some("fe80::1"));
+        assert_eq!(ip("[2001:db8::1]").as_deref(), Some("2001:db8::1"));
+        assert_eq!(ip("::ffff:10.0.0.7").as_deref(), Some("10.0.0.7"));
+        assert_eq!(ip("192.168.1.256"), None);
+        assert_eq!(ip("192.168.1"), None);
+    }
+
+    #[tokio::test]
+    async fn addresses_are_checked_and_resolved() {
+        let ok = normalize(" 10.0.0.20\n").await.unwrap();
+        assert_eq!(ok, DeviceAddress { ip: "10.0.0.20".into(), hostname: None });
+
+        for bad in ["0.0.0.0", "224.0.0.251", "255.255.255.255", "240.0.0.1", "::", "ff02::1"] {
+            assert!(matches!(normalize(bad).await, Err(AddressError::NotUnicast(_))), "{}", bad);
+        }
+        assert_eq!(normalize("   ").await, Err(AddressError::Empty));
+        assert!(matches!(normalize("not a host!").await, Err(AddressError::Invalid(_))));
+        assert!(matches!(normalize("192.168.1.999").await, Err(AddressError::Invalid(_))));
+
+        let host = normalize("LOCALHOST.").await.unwrap();
+        assert_eq!(host.hostname.as_deref(), Some("localhost"));
+        assert!(parse_ip(&host.ip).is_some_and(|ip| ip.is_loopback()));
+    }
+
+    #[tokio::test]
+    async fn stored_duplicates_are_merged() {
+        let pool = crate::settings::tests::test_pool().await;
+        let mut old = Device::new("old".into(), "10.0.0.5".into(), None, "manual");
+        old.last_seen = Some("2026-01-01T00:00:00Z".into());
+        let mut new = Device::new("new".into(), "10.0.0.005".into(), None, "manual");
+        new.last_seen = Some("2026-06-01T00:00:00Z".into());
+        let lone = Device::new("lone".into(), "010.0.0.9".into(), None, "manual");
+        for d in [&old, &new, &lone] {
+            queries::insert_device(&pool, d).await.unwrap();
+        }
+        queries::set_device_tags(&pool, &old.id, &["gpu-lab".into()]).await.unwrap();
+
+        assert_eq!(normalize_stored(&pool).await.unwrap(), 1);
+        let kept = queries::get_device_by_ip(&pool, "10.0.0.5").await.unwrap().unwrap();
+        assert_eq!(kept.name, "new");
+        assert_eq!(kept.tags, vec!["gpu-lab".to_string()]);
+        assert!(queries::get_device(&pool, &old.id).await.unwrap().is_none());
+        assert_eq!(queries::get_device(&pool, &lone.id).await.unwrap().unwrap().ip, "10.0.0.9");
+    }
+}
@@ -0,0 +1,379 @@
+//! Registering devices in bulk before their agents first come online, e.g.
+//! a lab whose machines' names and addresses are known. Rows come as a JSON
+//! array or as CSV with the [`CSV_HEADER`] columns. Each is checked first; two
+//! rows for one address reject the whole import, while a bad address, MAC,
+//! role, notes or labels fail only their row. Devices already registered are
+//! left as they are. Every import that gets to its rows is recorded in
+//! `device_imports` with its counts.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{address, DeviceStatus, PermissionService, Preset};
+use crate::api::devices::{normalize_labels, normalize_notes};
+use crate::db::queries;
+
+/// Columns of a CSV import. `name` and `ip` are required, the others may be
+/// left out or empty. `labels` is written `key=value;key=value`.
+pub const CSV_HEADER: &str = "name,ip,mac,role_id,auto_approve,notes,labels";
+/// Most rows one import takes.
+pub const MAX_ROWS: usize = 1000;
+const DISCOVERY_METHOD: &str = "import";
+
+/// One device to register.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, utoipa::ToSchema)]
+pub struct ImportRow {
+    pub name: String,
+    /// An IP in any spelling, or a hostname
+    pub ip: String,
+    #[serde(default)]
+    pub mac: Option<String>,
+    /// Role for the device; the default role when approved without one
+    #[serde(default)]
+    pub role_id: Option<String>,
+    /// Approve now instead of leaving the device pending
+    #[serde(default)]
+    pub auto_approve: bool,
+    /// As for `PATCH /api/devices/:id`
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// As for `PATCH /api/devices/:id`
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+/// What became of one row.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ImportResult {
+    /// 1-based, not counting the CSV header
+    pub row: usize,
+    pub name: String,
+    pub ip: String,
+    pub ok: bool,
+    pub device_id: Option<String>,
+    pub status: Option<DeviceStatus>,
+    /// False when a device at the address already existed; it was not changed
+    pub created: bool,
+    pub error: Option<String>,
+}
+
+/// `POST /api/devices/import`
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ImportReport {
+    /// The import's entry in `GET /api/devices/imports`
+    pub id: i64,
+    pub total: usize,
+    /// New devices
+    pub imported: usize,
+    /// Rows whose address was already registered
+    pub existing: usize,
+    pub failed: usize,
+    pub results: Vec<ImportResult>,
+}
+
+/// Why nothing was imported.
+#[derive(Debug)]
+pub enum ImportError {
+    Empty,
+    TooMany(usize),
+    /// Canonical addresses given by more than one row, with their rows
+    Duplicates(Vec<(String, Vec<usize>)>),
+    Db(anyhow::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Empty => f.write_str("No devices to import"),
+            ImportError::TooMany(n) => write!(f, "{} rows; an import takes at most {}", n, MAX_ROWS),
+            ImportError::Duplicates(dups) => {
+                let list: Vec<String> = dups
+                    .iter()
+                    .map(|(ip, rows)| {
+                        let rows: Vec<String> = rows.iter().map(usize::to_string).collect();
+                        format!("{} (rows {})", ip, rows.join(", "))
+                    })
+                    .collect();
+                write!(f, "Addresses given more than once: {}", list.join("; "))
+            }
+            ImportError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ImportError {
+    fn from(e: anyhow::Error) -> Self {
+        ImportError::Db(e)
+    }
+}
+
+/// Fields of one CSV line. A field may be quoted, with `""` for a quote.
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".into());
+    }
+    fields.push(field);
+    Ok(fields.into_iter().map(|f| f.trim().to_string()).collect())
+}
+
+/// Labels of a CSV field, `key=value` pairs separated by `;`.
+fn parse_labels(value: &str) -> Result<HashMap<String, String>, String> {
+    let mut labels = HashMap::new();
+    for pair in value.split(';').filter(|p| !p.trim().is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            return Err(format!("labels are key=value pairs separated by ';', not '{}'", pair.trim()));
+        };
+        if labels.insert(key.trim().to_string(), value.trim().to_string()).is_some() {
+            return Err(format!("Label '{}' is given more than once", key.trim()));
+        }
+    }
+    Ok(labels)
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "" | "false" | "no" | "0" => Some(false),
+        "true" | "yes" | "1" => Some(true),
+        _ => None,
+    }
+}
+
+/// Rows of a CSV import: a header naming some of [`CSV_HEADER`]'s columns,
+/// in any order, then one device per line. Blank lines and lines starting
+/// with `#` are skipped.
+pub fn parse_csv(text: &str) -> Result<Vec<ImportRow>, String> {
+    let mut lines = text
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty() && !l.trim_start().starts_with('#'));
+    let Some((_, header)) = lines.next() else {
+        return Err(format!("Empty CSV; expected the header {}", CSV_HEADER));
+    };
+    let columns: Vec<String> = split_csv_line(header)?.iter().map(|c| c.to_ascii_lowercase()).collect();
+    for (i, column) in columns.iter().enumerate() {
+        if !CSV_HEADER.split(',').any(|c| c == column) {
+            return Err(format!("Unknown column '{}'; the header is {}", column, CSV_HEADER));
+        }
+        if columns[..i].contains(column) {
+            return Err(format!("Column '{}' appears twice", column));
+        }
+    }
+    if !["name", "ip"].iter().all(|c| columns.iter().any(|col| col == c)) {
+        return Err(format!("The header needs name and ip columns: {}", CSV_HEADER));
+    }
+
+    lines
+        .map(|(n, line)| {
+            let at = |e: String| format!("Line {}: {}", n + 1, e);
+            let fields = split_csv_line(line).map_err(at)?;
+            if fields.len() != columns.len() {
+                return Err(at(format!("expected {} fields, found {}", columns.len(), fields.len())));
+            }
+            let mut row = ImportRow::default();
+            for (column, value) in columns.iter().zip(fields) {
+                let optional = (!value.is_empty()).then(|| value.clone());
+                match column.as_str() {
+                    "name" => row.name = value,
+                    "ip" => row.ip = value,
+                    "mac" => row.mac = optional,
+                    "role_id" => row.role_id = optional,
+                    "notes" => row.notes = optional,
+                    "labels" => row.labels = parse_labels(&value).map_err(at)?,
+                    _ => {
+                        row.auto_approve = parse_flag(&value)
+                            .ok_or_else(|| at(format!("auto_approve must be true or false, not '{}'", value)))?
+                    }
+                }
+            }
+            Ok(row)
+        })
+        .collect()
+}
+
+/// `aa:bb:cc:dd:ee:ff` for a MAC written with `:` or `-`.
+fn normalize_mac(raw: &str) -> Option<String> {
+    let octets: Vec<&str> = raw.trim().split([':', '-']).collect();
+    let valid = octets.len() == 6 && octets.iter().all(|o| o.len() == 2 && o.bytes().all(|b| b.is_ascii_hexdigit()));
+    valid.then(|| octets.join(":").to_ascii_lowercase())
+}
+
+/// A row that passed its checks.
+struct Checked {
+    ip: String,
+    mac: Option<String>,
+    preset: Preset,
+}
+
+async fn check(svc: &PermissionService, row: &ImportRow) -> anyhow::Result<Result<Checked, String>> {
+    if row.name.trim().is_empty() {
+        return Ok(Err("name is required".into()));
+    }
+    let ip = match address::normalize(&row.ip).await {
+        Ok(address) => address.ip,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+    let mac = match row.mac.as_deref().map(str::trim).filter(|m| !m.is_empty()) {
+        None => None,
+        Some(raw) => match normalize_mac(raw) {
+            Some(mac) => Some(mac),
+            None => return Ok(Err(format!("'{}' is not a MAC address", raw))),
+        },
+    };
+    let role_id = row.role_id.as_deref().map(str::trim).filter(|r| !r.is_empty());
+    if let Some(role) = role_id {
+        if queries::get_role(&svc.pool, role).await?.is_none() {
+            return Ok(Err(format!("No role '{}'", role)));
+        }
+    }
+    let notes = match row.notes.as_deref().map(normalize_notes).transpose() {
+        Ok(notes) => notes.flatten(),
+        Err(e) => return Ok(Err(e)),
+    };
+    let labels = match normalize_labels(&row.labels) {
+        Ok(labels) => labels,
+        Err(e) => return Ok(Err(e)),
+    };
+    let status = if row.auto_approve { DeviceStatus::Approved } else { DeviceStatus::Pending };
+    let role_id = role_id.map(str::to_string);
+    Ok(Ok(Checked { ip, mac, preset: Preset { status, role_id, notes, labels } }))
+}
+
+/// Register `rows` through [`PermissionService::register_device_as`] and
+/// record the import; `format` is `json` or `csv`.
+pub async fn import(svc: &PermissionService, rows: Vec<ImportRow>, format: &str) -> Result<ImportReport, ImportError> {
+    if rows.is_empty() {
+        return Err(ImportError::Empty);
+    }
+    if rows.len() > MAX_ROWS {
+        return Err(ImportError::TooMany(rows.len()));
+    }
+
+    let mut checked = Vec::with_capacity(rows.len());
+    for row in &rows {
+        checked.push(check(svc, row).await?);
+    }
+    let mut by_ip: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, c) in checked.iter().enumerate() {
+        if let Ok(c) = c {
+            by_ip.entry(c.ip.as_str()).or_default().push(i + 1);
+        }
+    }
+    let mut duplicates: Vec<(String, Vec<usize>)> = by_ip
+        .into_iter()
+        .filter(|(_, rows)| rows.len() > 1)
+        .map(|(ip, rows)| (ip.to_string(), rows))
+        .collect();
+    if !duplicates.is_empty() {
+        duplicates.sort_by_key(|(_, rows)| rows[0]);
+        return Err(ImportError::Duplicates(duplicates));
+    }
+
+    let mut results = Vec::with_capacity(rows.len());
+    for (i, (row, checked)) in rows.into_iter().zip(checked).enumerate() {
+        let mut result = ImportResult {
+            row: i + 1,
+            name: row.name.trim().to_string(),
+            ip: row.ip.trim().to_string(),
+            ok: false,
+            device_id: None,
+            status: None,
+            created: false,
+            error: None,
+        };
+        let registered = match checked {
+            Err(e) => Err(e),
+            Ok(c) => {
+                let existing = queries::get_device_by_ip(&svc.pool, &c.ip).await?.is_some();
+                svc.register_device_as(result.name.clone(), result.ip.clone(), c.mac, DISCOVERY_METHOD, Some(c.preset))
+                    .await
+                    .map(|device| (device, !existing))
+                    .map_err(|e| e.to_string())
+            }
+        };
+        match registered {
+            Ok((device, created)) => {
+                result.ok = true;
+                result.device_id = Some(device.id);
+                result.status = Some(device.status);
+                result.created = created;
+            }
+            Err(e) => result.error = Some(e),
+        }
+        results.push(result);
+    }
+
+    let imported = results.iter().filter(|r| r.created).count();
+    let existing = results.iter().filter(|r| r.ok && !r.created).count();
+    let failed = results.iter().filter(|r| !r.ok).count();
+    let id = queries::insert_device_import(&svc.pool, format, results.len(), imported, existing, failed).await?;
+    let report = ImportReport { id, total: results.len(), imported, existing, failed, results };
+    tracing::info!(
+        "Device import: {} row(s), {} imported, {} already registered, {} failed",
+        report.total,
+        report.imported,
+        report.existing,
+        report.failed
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_follow_the_header() {
+        let text = "\u{feff}ip,name,auto_approve,role_id,notes,labels\n# lab bench A\n10.0.0.1,\"bench, left\",yes,role-user,\"fan, loud\",rack=a; shelf = 2\n\n10.0.0.2,\"say \"\"hi\"\"\",,,,\n";
+        let rows = parse_csv(text).unwrap();
+        assert_eq!(
+            rows,
+            [
+                ImportRow {
+                    name: "bench, left".into(),
+                    ip: "10.0.0.1".into(),
+                    mac: None,
+                    role_id: Some("role-user".into()),
+                    auto_approve: true,
+                    notes: Some("fan, loud".into()),
+                    labels: HashMap::from([("rack".into(), "a".into()), ("shelf".into(), "2".into())]),
+                },
+                ImportRow { name: "say \"hi\"".into(), ip: "10.0.0.2".into(), ..Default::default() },
+            ]
+        );
+        assert!(parse_csv("name,ip,owner\n").unwrap_err().contains("owner"));
+        assert!(parse_csv("name,mac\n").is_err());
+        assert!(parse_csv("name,ip\na,10.0.0.1,extra\n").unwrap_err().starts_with("Line 2"));
+        assert!(parse_csv("name,ip,auto_approve\na,10.0.0.1,maybe\n").is_err());
+        assert!(parse_csv("name,ip,labels\na,10.0.0.1,rack\n").unwrap_err().contains("key=value"));
+        assert!(parse_csv("name,ip,labels\na,10.0.0.1,rack=a;rack=b\n").is_err());
+        assert!(parse_csv("").is_err());
+    }
+
+    #[test]
+    fn macs_are_written_one_way() {
+        assert_eq!(normalize_mac("AA-BB-cc-00-11-22").as_deref(), Some("aa:bb:cc:00:11:22"));
+        assert_eq!(normalize_mac("aa:bb:cc:00:11"), None);
+        assert_eq!(normalize_mac("aa:bb:cc:00:11:zz"), None);
+    }
+}
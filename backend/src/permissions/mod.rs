@@ -1,15 +1,29 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::SqlitePool;
-use tokio::sync::broadcast;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
-use crate::db::{models::Device, queries};
-use crate::ws::WsEvent;
+use crate::db::{
+    models::{Device, DeviceLabels, Role},
+    queries,
+};
+use crate::llama_cpp::LlamaCppManager;
+use crate::memory::remote::fetch_remote_memory;
+use crate::settings::{self, Key};
+use crate::ws::{EventBus, WsEvent};
 
-/// Possible device states — all variants used in DB and future API endpoints
-#[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub mod address;
+pub mod import;
+pub mod presence;
+pub mod restart;
+
+/// Approval state of a device, stored in `devices.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema)]
 #[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
 pub enum DeviceStatus {
     Pending,
     Approved,
@@ -19,8 +33,7 @@ pub enum DeviceStatus {
 }
 
 impl DeviceStatus {
-    #[allow(dead_code)]
-    pub fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             DeviceStatus::Pending => "pending",
             DeviceStatus::Approved => "approved",
@@ -31,18 +44,374 @@ impl DeviceStatus {
     }
 }
 
+impl fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DeviceStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DeviceStatus::Pending),
+            "approved" => Ok(DeviceStatus::Approved),
+            "denied" => Ok(DeviceStatus::Denied),
+            "suspended" => Ok(DeviceStatus::Suspended),
+            "offline" => Ok(DeviceStatus::Offline),
+            other => Err(format!("Unknown device status '{}'", other)),
+        }
+    }
+}
+
+/// State of a device's llama-rpc-server, stored in `devices.rpc_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum RpcStatus {
+    Offline,
+    Connecting,
+    Ready,
+    Error,
+}
+
+impl RpcStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcStatus::Offline => "offline",
+            RpcStatus::Connecting => "connecting",
+            RpcStatus::Ready => "ready",
+            RpcStatus::Error => "error",
+        }
+    }
+}
+
+impl fmt::Display for RpcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RpcStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "offline" => Ok(RpcStatus::Offline),
+            "connecting" => Ok(RpcStatus::Connecting),
+            "ready" => Ok(RpcStatus::Ready),
+            "error" => Ok(RpcStatus::Error),
+            other => Err(format!("Unknown RPC status '{}'", other)),
+        }
+    }
+}
+
+/// What a device runs, stored in `devices.device_kind`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum DeviceKind {
+    /// Only the llama-rpc-server agent; it has no `/api` of its own
+    Agent,
+    /// A whole SharedLLM backend with its own dashboard
+    Peer,
+    /// Not found out yet; the next heartbeat probes it
+    #[default]
+    Unknown,
+}
+
+/// Setting: minimum role trust_level a device needs to be used as an RPC device.
+pub const MIN_CLUSTER_TRUST_KEY: &str = "min_cluster_trust";
+/// Setting: minimum role trust_level a device needs for its /api/gpu to be polled.
+pub const MIN_STATS_TRUST_KEY: &str = "min_stats_trust";
+/// Setting: shared token agents present until they have a per-device secret.
+pub const ENROLLMENT_TOKEN_KEY: &str = "agent_enrollment_token";
+/// Setting: SHA-256 of the admin token issued by first-run setup.
+pub const ADMIN_TOKEN_HASH_KEY: &str = "admin_token_hash";
+
+/// How long a pairing code stays valid after registration.
+pub const PAIRING_CODE_TTL_MINUTES: i64 = 15;
+/// Wrong pairing codes a device may get before it is locked.
+pub const MAX_PAIRING_ATTEMPTS: i64 = 5;
+
+/// Built-in roles: (id, name, max_memory_mb, can_pull_models, trust_level).
+pub const BUILTIN_ROLES: &[(&str, &str, i64, bool, i64)] = &[
+    ("role-admin", "admin", 16384, true, 3),
+    ("role-user", "user", 4096, true, 2),
+    ("role-guest", "guest", 1024, false, 1),
+];
+
+/// Role that devices fall back to when theirs is deleted or missing.
+pub const FALLBACK_ROLE: &str = "role-guest";
+
+/// Recreate any built-in role missing from the database with its defaults,
+/// e.g. after a delete from the sqlite3 shell. A custom role that took the
+/// name meanwhile keeps it; the built-in one gets a `-builtin` suffix.
+/// Returns the IDs recreated.
+pub async fn ensure_builtin_roles(pool: &SqlitePool) -> anyhow::Result<Vec<String>> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut created = Vec::new();
+    for (id, name, max_memory_mb, can_pull_models, trust_level) in BUILTIN_ROLES {
+        if queries::get_role(pool, id).await?.is_some() {
+            continue;
+        }
+        let mut role = Role {
+            id: id.to_string(),
+            name: name.to_string(),
+            max_memory_mb: *max_memory_mb,
+            can_pull_models: *can_pull_models,
+            trust_level: *trust_level,
+            created_at: now.clone(),
+        };
+        if !queries::insert_role_if_missing(pool, &role).await? {
+            role.name = format!("{}-builtin", name);
+            if !queries::insert_role_if_missing(pool, &role).await? {
+                anyhow::bail!("Could not recreate built-in role {}", id);
+            }
+        }
+        tracing::error!(
+            "Built-in role {} was missing from the database; recreated it as '{}' with its defaults",
+            id,
+            role.name
+        );
+        created.push(role.id);
+    }
+    Ok(created)
+}
+
+/// Why a role could not be deleted.
+#[derive(Debug)]
+pub enum DeleteRoleError {
+    BuiltIn,
+    /// Devices (id, name) still use the role and no reassignment was given
+    InUse(Vec<(String, String)>),
+    /// `reassign_to` names no role, or the role being deleted
+    BadTarget(String),
+    Db(anyhow::Error),
+}
+
+/// Status, role, notes and labels for a device registered ahead of its
+/// agent: see [`PermissionService::register_device_as`].
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub status: DeviceStatus,
+    /// For approved devices, the default role when `None`. A pending device
+    /// keeps it for its approval.
+    pub role_id: Option<String>,
+    pub notes: Option<String>,
+    pub labels: DeviceLabels,
+}
+
+/// A device at the address being registered was soft-deleted, and the
+/// `restore_deleted_devices` setting is off.
+#[derive(Debug)]
+pub struct DeviceDeleted {
+    pub device_id: String,
+    pub ip: String,
+}
+
+impl fmt::Display for DeviceDeleted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The device at {} was deleted. Restore it with POST /api/devices/{}/restore, \
+             or delete it with ?purge=true to register the address again",
+            self.ip, self.device_id
+        )
+    }
+}
+
+impl std::error::Error for DeviceDeleted {}
+
+/// Result of [`PermissionService::approve_device`].
+pub struct Approval {
+    pub device: Device,
+    /// Returned only once; the agent authenticates heartbeats with it
+    pub agent_secret: String,
+    /// What contacting the agent found, when it was asked to
+    pub verification: Option<Verification>,
+}
+
+/// What contacting a newly approved device's agent found.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Verification {
+    /// At least one RPC endpoint answered
+    pub ok: bool,
+    pub rpc_endpoints: Vec<EndpointCheck>,
+    /// Read from the device's `/api/gpu`; `None` when it has none (agents
+    /// report memory with their heartbeat instead)
+    pub memory_total_mb: Option<i64>,
+    pub memory_free_mb: Option<i64>,
+    /// What failed, when `ok` is false
+    pub error: Option<String>,
+}
+
+/// A TCP probe of one RPC endpoint.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct EndpointCheck {
+    /// `ip:port`
+    pub address: String,
+    pub reachable: bool,
+}
+
+/// Why a device could not be approved.
+#[derive(Debug)]
+pub enum ApproveError {
+    NotFound,
+    /// The device has an outstanding pairing code and none was given
+    PairingCodeRequired,
+    PairingCodeExpired,
+    WrongPairingCode { tries_left: i64 },
+    /// Too many wrong codes; waiting for an enrollment-token check-in
+    PairingLocked,
+    Db(anyhow::Error),
+}
+
+impl fmt::Display for ApproveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApproveError::NotFound => f.write_str("Device not found"),
+            ApproveError::PairingCodeRequired => f.write_str(
+                "'pairing_code' is required: enter the code the agent printed when it registered",
+            ),
+            ApproveError::PairingCodeExpired => f.write_str(
+                "The pairing code has expired; re-run the agent installer to get a new one",
+            ),
+            ApproveError::WrongPairingCode { tries_left } => {
+                write!(f, "Wrong pairing code; {} tries left", tries_left)
+            }
+            ApproveError::PairingLocked => f.write_str(
+                "Too many wrong pairing codes. The device can be approved once its agent checks in \
+                 with the enrollment token",
+            ),
+            ApproveError::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApproveError {
+    fn from(e: anyhow::Error) -> Self {
+        ApproveError::Db(e)
+    }
+}
+
 /// Permission service: handles approval, denial, role assignment
 pub struct PermissionService {
     pool: SqlitePool,
-    event_tx: broadcast::Sender<WsEvent>,
+    event_tx: EventBus,
 }
 
 impl PermissionService {
-    pub fn new(pool: SqlitePool, event_tx: broadcast::Sender<WsEvent>) -> Self {
+    pub fn new(pool: SqlitePool, event_tx: EventBus) -> Self {
         PermissionService { pool, event_tx }
     }
 
-    /// Register a newly-discovered device (goes to pending unless trust_local_network is on)
+    /// Current value of a trust threshold setting (`min_cluster_trust` / `min_stats_trust`).
+    pub async fn trust_threshold(&self, key: Key) -> i64 {
+        settings::get_u64(&self.pool, key).await as i64
+    }
+
+    /// trust_level of every role, keyed by role ID.
+    pub async fn role_trust_levels(&self) -> anyhow::Result<HashMap<String, i64>> {
+        Ok(queries::list_roles(&self.pool)
+            .await?
+            .into_iter()
+            .map(|r| (r.id, r.trust_level))
+            .collect())
+    }
+
+    /// Trust level a device is evaluated at: its role's trust_level, or 0 when
+    /// it has no role (e.g. still pending) or the role no longer exists.
+    pub fn effective_trust_level(device: &Device, role_trust: &HashMap<String, i64>) -> i64 {
+        device
+            .role_id
+            .as_ref()
+            .and_then(|r| role_trust.get(r))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Whether the caller at `ip` may download this host's model files:
+    /// this host itself, or an approved device.
+    pub async fn may_fetch_model_files(&self, ip: std::net::IpAddr) -> anyhow::Result<bool> {
+        if ip.is_loopback() {
+            return Ok(true);
+        }
+        Ok(queries::get_device_by_ip(&self.pool, &ip.to_canonical().to_string())
+            .await?
+            .is_some_and(|d| d.status == DeviceStatus::Approved))
+    }
+
+    /// Whether the caller at `ip` may pull models: this host itself, or an
+    /// approved device whose role has `can_pull_models`.
+    pub async fn can_pull_models(&self, ip: std::net::IpAddr) -> anyhow::Result<bool> {
+        if ip.is_loopback() {
+            return Ok(true);
+        }
+        let Some(device) = queries::get_device_by_ip(&self.pool, &ip.to_string()).await? else {
+            return Ok(false);
+        };
+        if device.status != DeviceStatus::Approved {
+            return Ok(false);
+        }
+        let Some(role_id) = device.role_id.as_deref() else {
+            return Ok(false);
+        };
+        Ok(queries::get_role(&self.pool, role_id)
+            .await?
+            .is_some_and(|r| r.can_pull_models))
+    }
+
+    /// Trust level of the role of the approved device at `ip`; `None` when
+    /// no approved device with a role has that address.
+    pub async fn device_trust(&self, ip: std::net::IpAddr) -> anyhow::Result<Option<i64>> {
+        let Some(device) = queries::get_device_by_ip(&self.pool, &ip.to_string()).await? else {
+            return Ok(None);
+        };
+        if device.status != DeviceStatus::Approved {
+            return Ok(None);
+        }
+        let Some(role_id) = device.role_id.as_deref() else {
+            return Ok(None);
+        };
+        Ok(queries::get_role(&self.pool, role_id).await?.map(|r| r.trust_level))
+    }
+
+    /// The enrollment token embedded in generated install scripts, if one is set.
+    pub async fn enrollment_token(&self) -> Option<String> {
+        queries::get_setting(&self.pool, ENROLLMENT_TOKEN_KEY)
+            .await
+            .unwrap_or(None)
+            .filter(|t| !t.is_empty())
+    }
+
+    /// Whether `token` is the enrollment token.
+    pub async fn is_enrollment_token(&self, token: &str) -> bool {
+        match self.enrollment_token().await {
+            Some(expected) => !token.is_empty() && constant_time_eq(expected.as_bytes(), token.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// Whether `token` authenticates an agent speaking for `device`: either the
+    /// enrollment token or the secret issued when the device was approved.
+    pub async fn authenticate_agent(&self, device: Option<&Device>, token: &str) -> bool {
+        if token.is_empty() {
+            return false;
+        }
+        if self.is_enrollment_token(token).await {
+            return true;
+        }
+        device
+            .and_then(|d| d.agent_secret_hash.as_deref())
+            .is_some_and(|h| constant_time_eq(h.as_bytes(), hash_secret(token).as_bytes()))
+    }
+
+    /// Register a newly-discovered device (goes to pending unless trust_local_network is on).
+    /// `ip` may be any spelling of an IP, or a hostname; it is stored in
+    /// canonical form, with the hostname kept. An unusable one fails with an
+    /// [`address::AddressError`].
     pub async fn register_device(
         &self,
         name: String,
@@ -50,32 +419,63 @@ impl PermissionService {
         mac: Option<String>,
         discovery_method: &str,
     ) -> anyhow::Result<Device> {
+        self.register_device_as(name, ip, mac, discovery_method, None).await
+    }
+
+    /// [`register_device`](Self::register_device), with a new device's status
+    /// and role set by `preset` instead of `trust_local_network`. A device
+    /// already at the address is returned as it is.
+    pub async fn register_device_as(
+        &self,
+        name: String,
+        ip: String,
+        mac: Option<String>,
+        discovery_method: &str,
+        preset: Option<Preset>,
+    ) -> anyhow::Result<Device> {
+        let address = address::normalize(&ip).await?;
+        let ip = address.ip;
+
+        // A deleted device still holds its address: bring it back or leave it be
+        if let Some(deleted) = queries::get_deleted_device_by_ip(&self.pool, &ip).await? {
+            if !settings::get_bool(&self.pool, Key::RestoreDeletedDevices).await {
+                return Err(DeviceDeleted { device_id: deleted.id, ip }.into());
+            }
+            tracing::info!("Device {} registered again; restoring it (restore_deleted_devices=true)", ip);
+            self.restore_device(&deleted.id).await?;
+        }
+
         // Check if device with this IP already exists
-        if let Some(existing) = queries::get_device_by_ip(&self.pool, &ip).await? {
-            // Update last_seen and return existing
-            queries::update_device_last_seen(&self.pool, &existing.id).await?;
+        if let Some(mut existing) = queries::get_device_by_ip(&self.pool, &ip).await? {
+            // Update last_seen, bring it back if it was offline, and return existing
+            existing.status = presence::seen(&self.pool, &self.event_tx, &existing).await?;
             return Ok(existing);
         }
 
-        // Check trust_local_network setting
-        let trust_all = queries::get_setting(&self.pool, "trust_local_network")
-            .await?
-            .map(|v| v == "true")
-            .unwrap_or(false);
-
-        let default_role = queries::get_setting(&self.pool, "default_role")
-            .await?
-            .unwrap_or_else(|| "role-guest".to_string());
-
         let mut device = Device::new(name.clone(), ip.clone(), mac, discovery_method);
+        device.hostname = address.hostname;
 
-        if trust_all {
-            device.status = "approved".into();
-            device.role_id = Some(default_role);
-            tracing::info!("Auto-approved device {} (trust_local_network=true)", ip);
-        } else {
-            device.status = "pending".into();
-            tracing::info!("Device {} is pending approval", ip);
+        match preset {
+            Some(preset) => {
+                device.status = preset.status;
+                device.role_id = match preset.role_id {
+                    None if preset.status == DeviceStatus::Approved => Some(self.default_role().await?),
+                    role => role,
+                };
+                device.notes = preset.notes;
+                device.labels = preset.labels;
+                tracing::info!("Device {} registered as {}", ip, preset.status);
+            }
+            // Check trust_local_network setting
+            None if settings::get_bool(&self.pool, Key::TrustLocalNetwork).await => {
+                device.status = DeviceStatus::Approved;
+                device.role_id = Some(self.default_role().await?);
+                tracing::info!("Auto-approved device {} (trust_local_network=true)", ip);
+            }
+            None => {
+                device.status = DeviceStatus::Pending;
+                tracing::info!("Device {} is pending approval", ip);
+            }
         }
 
         queries::insert_device(&self.pool, &device).await?;
@@ -86,57 +486,278 @@ impl PermissionService {
             .await?
             .unwrap_or(device);
 
-        // Broadcast event
-        let event = if trust_all {
-            WsEvent::DeviceApproved {
+        // Broadcast what the stored row says, which a concurrent insert may have decided
+        let event = match device.status {
+            DeviceStatus::Approved => Some(WsEvent::DeviceApproved {
                 device_id: device.id.clone(),
                 name: device.name.clone(),
                 ip: device.ip.clone(),
-            }
-        } else {
-            WsEvent::DevicePendingApproval {
+            }),
+            DeviceStatus::Pending => Some(WsEvent::DevicePendingApproval {
                 device_id: device.id.clone(),
                 name: device.name.clone(),
                 ip: device.ip.clone(),
                 discovery_method: discovery_method.to_string(),
-            }
+            }),
+            DeviceStatus::Denied | DeviceStatus::Suspended | DeviceStatus::Offline => None,
         };
 
-        let _ = self.event_tx.send(event);
+        if let Some(event) = event {
+            let _ = self.event_tx.send(event);
+        }
         Ok(device)
     }
 
-    /// Approve a pending device and assign a role
+    /// Give a pending, self-registered device a pairing code, unless it
+    /// holds one that is still valid or it is locked. Returns the new code;
+    /// only its hash is stored, so this is the one chance to show it.
+    pub async fn issue_pairing_code(&self, device: &Device) -> anyhow::Result<Option<String>> {
+        if device.status != DeviceStatus::Pending || device.pairing_locked {
+            return Ok(None);
+        }
+        if device.pairing_code_hash.is_some() && pairing_code_live(device) {
+            return Ok(None);
+        }
+        let code = generate_pairing_code();
+        let expires_at =
+            chrono::Utc::now() + chrono::Duration::minutes(PAIRING_CODE_TTL_MINUTES);
+        queries::set_device_pairing_code(
+            &self.pool,
+            &device.id,
+            &pairing_code_hash(&device.id, &code),
+            &expires_at.to_rfc3339(),
+        )
+        .await?;
+        tracing::info!("Device {} must be approved with its pairing code", device.ip);
+        Ok(Some(code))
+    }
+
+    /// Check `code` against the device's pairing state. A device without a
+    /// code (discovered over mDNS, added before pairing codes) passes.
+    async fn check_pairing_code(&self, device: &Device, code: Option<&str>) -> Result<(), ApproveError> {
+        if device.pairing_locked {
+            return Err(ApproveError::PairingLocked);
+        }
+        let Some(hash) = device.pairing_code_hash.as_deref() else {
+            return Ok(());
+        };
+        let Some(code) = code.map(str::trim).filter(|c| !c.is_empty()) else {
+            return Err(ApproveError::PairingCodeRequired);
+        };
+        if !pairing_code_live(device) {
+            return Err(ApproveError::PairingCodeExpired);
+        }
+        let given = pairing_code_hash(&device.id, code);
+        if constant_time_eq(given.as_bytes(), hash.as_bytes()) {
+            return Ok(());
+        }
+        let attempts = queries::record_pairing_failure(&self.pool, &device.id, MAX_PAIRING_ATTEMPTS).await?;
+        if attempts >= MAX_PAIRING_ATTEMPTS {
+            tracing::warn!(
+                "Device {} locked after {} wrong pairing codes",
+                device.ip,
+                attempts
+            );
+            return Err(ApproveError::PairingLocked);
+        }
+        Err(ApproveError::WrongPairingCode { tries_left: MAX_PAIRING_ATTEMPTS - attempts })
+    }
+
+    /// Lift a device's pairing lock because its agent checked in with the
+    /// enrollment token.
+    pub async fn unlock_pairing(&self, device: &Device) -> anyhow::Result<()> {
+        if !device.pairing_locked {
+            return Ok(());
+        }
+        queries::clear_device_pairing(&self.pool, &device.id).await?;
+        tracing::info!(
+            "Device {} checked in with the enrollment token; it can be approved without a code",
+            device.ip
+        );
+        Ok(())
+    }
+
+    /// Approve a pending device and assign a role. A device that registered
+    /// itself needs the pairing code its agent printed.
+    ///
+    /// Also issues a fresh agent secret (replacing any previous one) and returns
+    /// it alongside the device — only its hash is stored, so this is the one
+    /// chance to hand it to the agent.
+    ///
+    /// With `verify_with`, the agent is contacted right away: see
+    /// [`PermissionService::verify_device`]. The device is approved either way.
     pub async fn approve_device(
         &self,
         device_id: &str,
         role_id: Option<&str>,
-    ) -> anyhow::Result<Device> {
-        // Treat missing or empty role_id as "role-guest"
-        let role = match role_id {
-            Some(r) if !r.is_empty() => r,
-            _ => "role-guest",
+        pairing_code: Option<&str>,
+        verify_with: Option<&LlamaCppManager>,
+    ) -> Result<Approval, ApproveError> {
+        let device = queries::get_device(&self.pool, device_id)
+            .await?
+            .ok_or(ApproveError::NotFound)?;
+        self.check_pairing_code(&device, pairing_code).await?;
+        queries::clear_device_pairing(&self.pool, device_id).await?;
+
+        // A role set when the device was imported stands until one is given
+        let role = match (role_id, &device.role_id) {
+            (Some(r), _) if !r.is_empty() => r.to_string(),
+            (_, Some(preset)) => preset.clone(),
+            _ => self.default_role().await?,
         };
-        queries::update_device_status(&self.pool, device_id, "approved").await?;
-        queries::update_device_role(&self.pool, device_id, role).await?;
+        queries::update_device_status(&self.pool, device_id, DeviceStatus::Approved).await?;
+        queries::update_device_role(&self.pool, device_id, &role).await?;
+
+        let secret = generate_secret();
+        queries::set_device_agent_secret_hash(&self.pool, device_id, &hash_secret(&secret))
+            .await?;
 
         let device = queries::get_device(&self.pool, device_id)
             .await?
-            .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_id))?;
+            .ok_or(ApproveError::NotFound)?;
 
         let _ = self.event_tx.send(WsEvent::DeviceApproved {
             device_id: device.id.clone(),
             name: device.name.clone(),
             ip: device.ip.clone(),
         });
-
         tracing::info!("Device {} approved with role {}", device.ip, role);
-        Ok(device)
+
+        let (device, verification) = match verify_with {
+            Some(llama_cpp) => {
+                let (device, verification) = self.verify_device(device, llama_cpp).await?;
+                (device, Some(verification))
+            }
+            None => (device, None),
+        };
+        Ok(Approval { device, agent_secret: secret, verification })
+    }
+
+    /// Contact a device's agent: probe every RPC endpoint and read memory
+    /// from its `/api/gpu`. `rpc_status` is set from the probes and the
+    /// memory columns from the reading, if there was one. A device whose RPC
+    /// server answers is announced with `rpc_device_ready`.
+    pub async fn verify_device(
+        &self,
+        device: Device,
+        llama_cpp: &LlamaCppManager,
+    ) -> anyhow::Result<(Device, Verification)> {
+        let ip = device.ip.as_str();
+        let probes = device.rpc_ports().into_iter().map(|port| async move {
+            let reachable = llama_cpp.probe_rpc_device(ip, port).await;
+            EndpointCheck { address: format!("{}:{}", ip, port), reachable }
+        });
+        let (rpc_endpoints, memory) = tokio::join!(
+            futures::future::join_all(probes),
+            fetch_remote_memory(&llama_cpp.client, ip)
+        );
+        let ok = rpc_endpoints.iter().any(|e| e.reachable);
+
+        let status = if ok { RpcStatus::Ready } else { RpcStatus::Offline };
+        queries::update_device_rpc_status(&self.pool, &device.id, status).await?;
+        if let Some((total, free)) = memory {
+            queries::update_device_memory_stats(&self.pool, &device.id, total, free).await?;
+        }
+        let device = queries::get_device(&self.pool, &device.id)
+            .await?
+            .unwrap_or(device);
+
+        let error = if ok {
+            let _ = self.event_tx.send(WsEvent::RpcDeviceReady {
+                device_id: device.id.clone(),
+                memory_total_mb: device.memory_total_mb,
+                memory_free_mb: device.memory_free_mb,
+            });
+            None
+        } else {
+            let addresses: Vec<&str> = rpc_endpoints.iter().map(|e| e.address.as_str()).collect();
+            tracing::warn!("Approved device {} but its RPC server didn't answer", device.ip);
+            Some(format!(
+                "llama-rpc-server didn't answer at {}. Check that the agent is running and the port is open.",
+                addresses.join(", ")
+            ))
+        };
+        let verification = Verification {
+            ok,
+            rpc_endpoints,
+            memory_total_mb: memory.map(|(total, _)| total),
+            memory_free_mb: memory.map(|(_, free)| free),
+            error,
+        };
+        Ok((device, verification))
+    }
+
+    /// Delete a custom role. Devices using it block the delete unless
+    /// `reassign_to` names another role to move them to. Returns the IDs of
+    /// reassigned devices.
+    pub async fn delete_role(
+        &self,
+        id: &str,
+        reassign_to: Option<&str>,
+    ) -> Result<Vec<String>, DeleteRoleError> {
+        if BUILTIN_ROLES.iter().any(|(builtin, ..)| *builtin == id) {
+            return Err(DeleteRoleError::BuiltIn);
+        }
+        if let Some(target) = reassign_to {
+            let exists = queries::get_role(&self.pool, target)
+                .await
+                .map_err(DeleteRoleError::Db)?
+                .is_some();
+            if target == id || !exists {
+                return Err(DeleteRoleError::BadTarget(target.to_string()));
+            }
+        }
+
+        let users = queries::delete_role(&self.pool, id, reassign_to)
+            .await
+            .map_err(DeleteRoleError::Db)?;
+        match reassign_to {
+            None if !users.is_empty() => Err(DeleteRoleError::InUse(users)),
+            _ => {
+                if let Some(target) = reassign_to.filter(|_| !users.is_empty()) {
+                    tracing::info!("Moved {} device(s) from role {} to {}", users.len(), id, target);
+                }
+                Ok(users.into_iter().map(|(id, _)| id).collect())
+            }
+        }
+    }
+
+    /// The role devices get when none is given: the `default_role` setting
+    /// while it names a role, else the one with the least trust.
+    pub async fn default_role(&self) -> anyhow::Result<String> {
+        let configured = settings::get_string(&self.pool, Key::DefaultRole).await;
+        let roles = queries::list_roles(&self.pool).await?;
+        if roles.iter().any(|r| r.id == configured) {
+            return Ok(configured);
+        }
+        let fallback = roles
+            .iter()
+            .min_by_key(|r| (r.trust_level, r.max_memory_mb))
+            .map_or(FALLBACK_ROLE, |r| r.id.as_str())
+            .to_string();
+        tracing::warn!("default_role '{}' names no role; using {}", configured, fallback);
+        Ok(fallback)
+    }
+
+    /// Move devices whose role was deleted behind our back (e.g. from the
+    /// sqlite3 shell, which doesn't enforce foreign keys) to `FALLBACK_ROLE`,
+    /// so role limits apply to them again.
+    pub async fn repair_dangling_roles(&self) -> anyhow::Result<()> {
+        for (id, name) in queries::repair_dangling_roles(&self.pool, FALLBACK_ROLE).await? {
+            tracing::warn!(
+                "Device '{}' ({}) referenced a missing role; reassigned to {}",
+                name,
+                id,
+                FALLBACK_ROLE
+            );
+        }
+        Ok(())
     }
 
     /// Deny a pending device
     pub async fn deny_device(&self, device_id: &str) -> anyhow::Result<()> {
-        queries::update_device_status(&self.pool, device_id, "denied").await?;
+        queries::update_device_status(&self.pool, device_id, DeviceStatus::Denied).await?;
+        self.revoke_allocations(device_id).await?;
 
         let _ = self.event_tx.send(WsEvent::DeviceDenied {
             device_id: device_id.to_string(),
@@ -146,6 +767,119 @@ impl PermissionService {
         Ok(())
     }
 
+    /// Take an approved device out of service without forgetting it; approve
+    /// it again to bring it back.
+    pub async fn suspend_device(&self, device_id: &str) -> anyhow::Result<()> {
+        queries::get_device(&self.pool, device_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Device not found"))?;
+        queries::update_device_status(&self.pool, device_id, DeviceStatus::Suspended).await?;
+        self.revoke_allocations(device_id).await?;
+
+        let _ = self.event_tx.send(WsEvent::DeviceSuspended {
+            device_id: device_id.to_string(),
+        });
+
+        tracing::info!("Device {} suspended", device_id);
+        Ok(())
+    }
+
+    /// Soft-delete a device: it drops out of every list and lookup and its
+    /// memory grants are revoked, but its status, role and tags are kept for
+    /// [`restore_device`](Self::restore_device). Returns false when there is
+    /// no such live device.
+    pub async fn delete_device(&self, device_id: &str) -> anyhow::Result<bool> {
+        let Some(revoked) = queries::soft_delete_device(&self.pool, device_id).await? else {
+            return Ok(false);
+        };
+        if revoked != 0 {
+            let _ = self.event_tx.send(WsEvent::MemoryAllocated {
+                device_id: device_id.to_string(),
+                memory_mb: 0,
+            });
+        }
+        tracing::info!("Device {} deleted (revoked {} MB); it can be restored", device_id, revoked);
+        Ok(true)
+    }
+
+    /// Delete a device, soft-deleted or not, for good; its allocations and
+    /// tags go with it. Returns false when there is no such device.
+    pub async fn purge_device(&self, device_id: &str) -> anyhow::Result<bool> {
+        let allocated = queries::get_device(&self.pool, device_id)
+            .await?
+            .map_or(0, |d| d.allocated_memory_mb);
+        if !queries::delete_device(&self.pool, device_id).await? {
+            return Ok(false);
+        }
+        if allocated != 0 {
+            let _ = self.event_tx.send(WsEvent::MemoryAllocated {
+                device_id: device_id.to_string(),
+                memory_mb: 0,
+            });
+        }
+        tracing::info!("Device {} purged", device_id);
+        Ok(true)
+    }
+
+    /// Bring back a soft-deleted device with the status, tags and memory
+    /// grants it had. An approved device whose role was deleted meanwhile
+    /// gets the default role, and a grant over its role's current limit is
+    /// cut down to it. Returns `None` when it isn't deleted.
+    pub async fn restore_device(&self, device_id: &str) -> anyhow::Result<Option<Device>> {
+        let Some(mut restored) = queries::restore_device(&self.pool, device_id).await? else {
+            return Ok(None);
+        };
+        let Some(device) = queries::get_device(&self.pool, device_id).await? else {
+            return Ok(None);
+        };
+        let device = if device.status == DeviceStatus::Approved && device.role_id.is_none() {
+            queries::update_device_role(&self.pool, device_id, &self.default_role().await?).await?;
+            queries::get_device(&self.pool, device_id).await?.unwrap_or(device)
+        } else {
+            device
+        };
+        let role = match &device.role_id {
+            Some(role_id) => queries::get_role(&self.pool, role_id).await?,
+            None => None,
+        };
+        let device = match role {
+            Some(role) if restored > role.max_memory_mb => {
+                tracing::warn!(
+                    "Device {} had {} MB, over role '{}' limit of {} MB; restoring it with {} MB",
+                    device_id,
+                    restored,
+                    role.name,
+                    role.max_memory_mb,
+                    role.max_memory_mb
+                );
+                restored = queries::cap_device_grant(&self.pool, device_id, role.max_memory_mb).await?;
+                Device { allocated_memory_mb: restored, ..device }
+            }
+            _ => device,
+        };
+        if restored != 0 {
+            let _ = self.event_tx.send(WsEvent::MemoryAllocated {
+                device_id: device_id.to_string(),
+                memory_mb: restored,
+            });
+        }
+        tracing::info!("Device {} restored as {} with {} MB", device_id, device.status, restored);
+        Ok(Some(device))
+    }
+
+    /// Revoke a device's allocations and tell clients its share is gone.
+    async fn revoke_allocations(&self, device_id: &str) -> anyhow::Result<()> {
+        let revoked = queries::revoke_all_allocations_for_device(&self.pool, device_id).await?;
+        if revoked != 0 {
+            let _ = self.event_tx.send(WsEvent::MemoryAllocated {
+                device_id: device_id.to_string(),
+                memory_mb: 0,
+            });
+            tracing::info!("Revoked {} MB allocated to device {}", revoked, device_id);
+        }
+        Ok(())
+    }
+
     /// Allocate memory to a device (enforces role limits)
     pub async fn allocate_memory(
         &self,
@@ -156,7 +890,7 @@ impl PermissionService {
             .await?
             .ok_or_else(|| anyhow::anyhow!("Device not found"))?;
 
-        if device.status != "approved" {
+        if device.status != DeviceStatus::Approved {
             anyhow::bail!("Device must be approved before allocating memory");
         }
 
@@ -174,18 +908,18 @@ impl PermissionService {
             }
         }
 
-        queries::update_device_memory(&self.pool, device_id, memory_mb).await?;
-
-        // Record allocation
+        // Record allocation, replacing the device's earlier grant
         let alloc = crate::db::models::Allocation {
             id: Uuid::new_v4().to_string(),
-            device_id: device_id.to_string(),
+            device_id: Some(device_id.to_string()),
             memory_mb,
             provider: "system_ram".into(), // TODO: pick provider dynamically
+            purpose: "device".into(),
+            session_id: None,
             granted_at: chrono::Utc::now().to_rfc3339(),
             revoked_at: None,
         };
-        queries::insert_allocation(&self.pool, &alloc).await?;
+        queries::grant_device_memory(&self.pool, &alloc).await?;
 
         let _ = self.event_tx.send(WsEvent::MemoryAllocated {
             device_id: device_id.to_string(),
@@ -196,3 +930,348 @@ impl PermissionService {
         Ok(())
     }
 }
+
+/// Fresh random secret (256 bits, hex).
+pub fn generate_secret() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Six random digits, short enough to read off a terminal.
+fn generate_pairing_code() -> String {
+    format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000)
+}
+
+/// Whether a device's pairing code is before its expiry.
+fn pairing_code_live(device: &Device) -> bool {
+    device
+        .pairing_expires_at
+        .as_deref()
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .is_some_and(|t| t > chrono::Utc::now())
+}
+
+/// Stored form of a device's pairing code, salted with the device ID.
+fn pairing_code_hash(device_id: &str, code: &str) -> String {
+    hash_secret(&format!("{}:{}", device_id, code))
+}
+
+/// SHA-256 hex digest of an agent secret, as stored in `devices.agent_secret_hash`.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn setup() -> (PermissionService, SqlitePool) {
+        let pool = crate::settings::tests::test_pool().await;
+        let role = Role {
+            id: "role-lab".into(),
+            name: "lab".into(),
+            max_memory_mb: 2048,
+            can_pull_models: false,
+            trust_level: 2,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        queries::upsert_role(&pool, &role).await.unwrap();
+        let mut device = Device::new("rig".into(), "10.0.0.9".into(), None, "manual");
+        device.role_id = Some("role-lab".into());
+        queries::insert_device(&pool, &device).await.unwrap();
+        (PermissionService::new(pool.clone(), EventBus::default()), pool)
+    }
+
+    #[tokio::test]
+    async fn roles_in_use_are_not_deleted() {
+        let (svc, pool) = setup().await;
+        match svc.delete_role("role-lab", None).await {
+            Err(DeleteRoleError::InUse(users)) => assert_eq!(users[0].1, "rig"),
+            other => panic!("expected InUse, got {:?}", other),
+        }
+        assert!(queries::get_role(&pool, "role-lab").await.unwrap().is_some());
+        assert!(matches!(
+            svc.delete_role("role-guest", None).await,
+            Err(DeleteRoleError::BuiltIn)
+        ));
+    }
+
+    #[tokio::test]
+    async fn reassigned_devices_move_before_the_delete() {
+        let (svc, pool) = setup().await;
+        assert!(matches!(
+            svc.delete_role("role-lab", Some("role-nope")).await,
+            Err(DeleteRoleError::BadTarget(_))
+        ));
+
+        let moved = svc.delete_role("role-lab", Some("role-user")).await.unwrap();
+        assert_eq!(moved.len(), 1);
+        assert!(queries::get_role(&pool, "role-lab").await.unwrap().is_none());
+        let device = queries::get_device(&pool, &moved[0]).await.unwrap().unwrap();
+        assert_eq!(device.role_id.as_deref(), Some("role-user"));
+    }
+
+    #[tokio::test]
+    async fn denying_a_device_revokes_its_allocations() {
+        let (svc, pool) = setup().await;
+        let id = queries::list_devices(&pool).await.unwrap()[0].id.clone();
+        queries::update_device_status(&pool, &id, DeviceStatus::Approved).await.unwrap();
+        svc.allocate_memory(&id, 512).await.unwrap();
+        let mut events = svc.event_tx.subscribe_critical();
+
+        svc.deny_device(&id).await.unwrap();
+        let device = queries::get_device(&pool, &id).await.unwrap().unwrap();
+        assert_eq!(device.allocated_memory_mb, 0);
+        let active = queries::list_allocations_for_device(&pool, &id).await.unwrap();
+        assert!(active.iter().all(|a| a.revoked_at.is_some()));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            WsEvent::MemoryAllocated { memory_mb: 0, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn pulls_follow_the_callers_role() {
+        let (svc, pool) = setup().await;
+        let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+        assert!(svc.can_pull_models(ip("127.0.0.1")).await.unwrap());
+        assert!(!svc.can_pull_models(ip("10.0.0.77")).await.unwrap());
+
+        let id = queries::list_devices(&pool).await.unwrap()[0].id.clone();
+        // Pending devices can't pull, whatever their role
+        queries::update_device_role(&pool, &id, "role-user").await.unwrap();
+        assert!(!svc.can_pull_models(ip("10.0.0.9")).await.unwrap());
+        queries::update_device_status(&pool, &id, DeviceStatus::Approved).await.unwrap();
+        assert!(svc.can_pull_models(ip("10.0.0.9")).await.unwrap());
+        queries::update_device_role(&pool, &id, "role-lab").await.unwrap();
+        assert!(!svc.can_pull_models(ip("10.0.0.9")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn dangling_roles_fall_back_to_guest() {
+        let (svc, pool) = setup().await;
+        // As the sqlite3 shell would, with foreign keys off
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM roles WHERE id = 'role-lab'")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        svc.repair_dangling_roles().await.unwrap();
+        let devices = queries::list_devices(&pool).await.unwrap();
+        assert_eq!(devices[0].role_id.as_deref(), Some(FALLBACK_ROLE));
+    }
+
+    #[tokio::test]
+    async fn deleted_builtin_roles_come_back() {
+        let (svc, pool) = setup().await;
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM roles WHERE id IN ('role-admin', 'role-guest')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        // A custom role took the guest name meanwhile
+        let squatter = Role {
+            id: "role-visitor".into(),
+            name: "guest".into(),
+            max_memory_mb: 512,
+            can_pull_models: false,
+            trust_level: 0,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        queries::upsert_role(&pool, &squatter).await.unwrap();
+
+        // Until repaired, the default falls back to the least trusted role left
+        assert_eq!(svc.default_role().await.unwrap(), "role-visitor");
+
+        let mut created = ensure_builtin_roles(&pool).await.unwrap();
+        created.sort();
+        assert_eq!(created, ["role-admin", "role-guest"]);
+        let guest = queries::get_role(&pool, "role-guest").await.unwrap().unwrap();
+        assert_eq!((guest.name.as_str(), guest.max_memory_mb), ("guest-builtin", 1024));
+        assert!(ensure_builtin_roles(&pool).await.unwrap().is_empty());
+        assert_eq!(svc.default_role().await.unwrap(), "role-guest");
+    }
+
+    #[tokio::test]
+    async fn approvals_use_the_default_role_of_the_moment() {
+        let (svc, pool) = setup().await;
+        queries::set_setting(&pool, "default_role", "role-lab").await.unwrap();
+        let device = svc.register_device("new".into(), "10.0.0.10".into(), None, "manual").await.unwrap();
+        let approved = svc.approve_device(&device.id, None, None, None).await.unwrap().device;
+        assert_eq!(approved.role_id.as_deref(), Some("role-lab"));
+
+        // Pointing at a role that has gone, it falls back rather than dangle
+        queries::set_setting(&pool, "default_role", "role-gone").await.unwrap();
+        let device = svc.register_device("newer".into(), "10.0.0.11".into(), None, "manual").await.unwrap();
+        let approved = svc.approve_device(&device.id, Some(""), None, None).await.unwrap().device;
+        assert_eq!(approved.role_id.as_deref(), Some(FALLBACK_ROLE));
+    }
+
+    #[tokio::test]
+    async fn statuses_round_trip_and_bad_values_are_refused() {
+        let (_, pool) = setup().await;
+        for status in ["pending", "approved", "denied", "suspended", "offline"] {
+            assert_eq!(status.parse::<DeviceStatus>().unwrap().to_string(), status);
+        }
+        assert!("Approved".parse::<DeviceStatus>().is_err());
+        assert_eq!("ready".parse::<RpcStatus>(), Ok(RpcStatus::Ready));
+
+        let id = queries::list_devices(&pool).await.unwrap()[0].id.clone();
+        queries::update_device_status(&pool, &id, DeviceStatus::Suspended).await.unwrap();
+        queries::update_device_rpc_status(&pool, &id, RpcStatus::Ready).await.unwrap();
+        let device = queries::get_device(&pool, &id).await.unwrap().unwrap();
+        assert_eq!((device.status, device.rpc_status), (DeviceStatus::Suspended, RpcStatus::Ready));
+
+        // Raw SQL can't store a value the enums can't read back
+        let typo = sqlx::query("UPDATE devices SET status = 'Approved' WHERE id = ?")
+            .bind(&id)
+            .execute(&pool)
+            .await;
+        assert!(typo.is_err());
+    }
+
+    #[tokio::test]
+    async fn pairing_codes_gate_approval() {
+        let (svc, pool) = setup().await;
+        let device = svc
+            .register_device("agent".into(), "10.0.0.20".into(), None, "manual")
+            .await
+            .unwrap();
+        let code = svc.issue_pairing_code(&device).await.unwrap().unwrap();
+        assert_eq!(code.len(), 6);
+        let device = queries::get_device(&pool, &device.id).await.unwrap().unwrap();
+        // Still valid: not replaced by a second registration
+        assert!(svc.issue_pairing_code(&device).await.unwrap().is_none());
+
+        assert!(matches!(
+            svc.approve_device(&device.id, None, None, None).await,
+            Err(ApproveError::PairingCodeRequired)
+        ));
+        let wrong = format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000);
+        for left in (1..MAX_PAIRING_ATTEMPTS).rev() {
+            assert!(matches!(
+                svc.approve_device(&device.id, None, Some(&wrong), None).await,
+                Err(ApproveError::WrongPairingCode { tries_left }) if tries_left == left
+            ));
+        }
+        assert!(matches!(
+            svc.approve_device(&device.id, None, Some(&wrong), None).await,
+            Err(ApproveError::PairingLocked)
+        ));
+        // Locked: even the right code is refused, and no new code is issued
+        assert!(matches!(
+            svc.approve_device(&device.id, None, Some(&code), None).await,
+            Err(ApproveError::PairingLocked)
+        ));
+        let device = queries::get_device(&pool, &device.id).await.unwrap().unwrap();
+        assert!(device.pairing_locked && device.pairing_code_hash.is_none());
+        assert!(svc.issue_pairing_code(&device).await.unwrap().is_none());
+
+        svc.unlock_pairing(&device).await.unwrap();
+        let approved = svc.approve_device(&device.id, None, None, None).await.unwrap().device;
+        assert_eq!(approved.status, DeviceStatus::Approved);
+        assert_eq!((approved.pairing_attempts, approved.pairing_locked), (0, false));
+    }
+
+    #[tokio::test]
+    async fn expired_pairing_codes_are_refused_and_replaced() {
+        let (svc, pool) = setup().await;
+        let device = svc
+            .register_device("agent".into(), "10.0.0.21".into(), None, "manual")
+            .await
+            .unwrap();
+        let code = svc.issue_pairing_code(&device).await.unwrap().unwrap();
+        let past = (chrono::Utc::now() - chrono::Duration::minutes(1)).to_rfc3339();
+        queries::set_device_pairing_code(&pool, &device.id, &pairing_code_hash(&device.id, &code), &past)
+            .await
+            .unwrap();
+        assert!(matches!(
+            svc.approve_device(&device.id, None, Some(&code), None).await,
+            Err(ApproveError::PairingCodeExpired)
+        ));
+
+        let device = queries::get_device(&pool, &device.id).await.unwrap().unwrap();
+        let fresh = svc.issue_pairing_code(&device).await.unwrap().unwrap();
+        assert!(svc.approve_device(&device.id, None, Some(&fresh), None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn deleted_devices_come_back_as_they_were() {
+        let (svc, pool) = setup().await;
+        let id = queries::list_devices(&pool).await.unwrap()[0].id.clone();
+        queries::update_device_status(&pool, &id, DeviceStatus::Approved).await.unwrap();
+        queries::set_device_tags(&pool, &id, &["gpu-lab".to_string()]).await.unwrap();
+        svc.allocate_memory(&id, 512).await.unwrap();
+
+        assert!(svc.delete_device(&id).await.unwrap());
+        assert!(!svc.delete_device(&id).await.unwrap());
+        assert!(queries::get_device(&pool, &id).await.unwrap().is_none());
+        assert!(queries::list_devices_with_tag(&pool, "gpu-lab").await.unwrap().is_empty());
+        let deleted = queries::list_deleted_devices(&pool).await.unwrap();
+        assert_eq!((deleted[0].allocated_memory_mb, deleted[0].tags.len()), (0, 1));
+
+        // Registering the address again doesn't make a new pending device
+        let again = svc.register_device("rig".into(), "10.0.0.9".into(), None, "mdns").await;
+        assert!(again.unwrap_err().downcast_ref::<DeviceDeleted>().is_some());
+        assert!(queries::list_devices(&pool).await.unwrap().is_empty());
+
+        let device = svc.restore_device(&id).await.unwrap().unwrap();
+        assert_eq!((device.status, device.allocated_memory_mb), (DeviceStatus::Approved, 512));
+        assert_eq!((device.role_id.as_deref(), device.tags.as_slice()), (Some("role-lab"), &["gpu-lab".to_string()][..]));
+        assert!(svc.restore_device(&id).await.unwrap().is_none());
+
+        // A role limit lowered while it was deleted caps the restored grant
+        svc.delete_device(&id).await.unwrap();
+        sqlx::query("UPDATE roles SET max_memory_mb = 256 WHERE id = 'role-lab'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let device = svc.restore_device(&id).await.unwrap().unwrap();
+        assert_eq!(device.allocated_memory_mb, 256);
+        let grants = queries::list_allocations_for_device(&pool, &id).await.unwrap();
+        let active: Vec<_> = grants.iter().filter(|a| a.revoked_at.is_none()).collect();
+        assert_eq!((active.len(), active[0].memory_mb), (1, 256));
+
+        // With restore_deleted_devices on, registering brings it back instead
+        svc.delete_device(&id).await.unwrap();
+        queries::set_setting(&pool, "restore_deleted_devices", "true").await.unwrap();
+        let again = svc.register_device("rig".into(), "10.0.0.9".into(), None, "mdns").await.unwrap();
+        assert_eq!((again.id.as_str(), again.status), (id.as_str(), DeviceStatus::Approved));
+
+        assert!(svc.purge_device(&id).await.unwrap());
+        assert!(queries::list_deleted_devices(&pool).await.unwrap().is_empty());
+        assert!(queries::list_allocations_for_device(&pool, &id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn detected_kind_only_replaces_unknown() {
+        let (_svc, pool) = setup().await;
+        let device = queries::list_devices(&pool).await.unwrap().remove(0);
+        assert_eq!(device.device_kind, DeviceKind::Unknown);
+        queries::update_device_status(&pool, &device.id, DeviceStatus::Approved).await.unwrap();
+        queries::update_device_settings(
+            &pool,
+            &device.id,
+            "rig",
+            device.rpc_port,
+            &device.extra_rpc_ports,
+            true,
+            DeviceKind::Unknown,
+        )
+        .await
+        .unwrap();
+        // Opted in, but not yet known to run a backend
+        assert!(queries::list_peer_devices(&pool).await.unwrap().is_empty());
+
+        assert!(queries::detect_device_kind(&pool, &device.id, DeviceKind::Peer).await.unwrap());
+        assert_eq!(queries::list_peer_devices(&pool).await.unwrap().len(), 1);
+        // A later probe doesn't undo what was detected or set
+        assert!(!queries::detect_device_kind(&pool, &device.id, DeviceKind::Agent).await.unwrap());
+        let device = queries::get_device(&pool, &device.id).await.unwrap().unwrap();
+        assert_eq!(device.device_kind, DeviceKind::Peer);
+    }
+}
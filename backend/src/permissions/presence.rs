@@ -0,0 +1,157 @@
+//! Whether devices are still around. Every answer from a device — a
+//! heartbeat, a re-registration, or an RPC or agent API port accepting a
+//! probe — bumps its `last_seen`. Approved and suspended devices not heard
+//! from within `device_offline_after_secs` are marked `offline`, and get
+//! their old status back from `prior_status` when they answer again.
+
+use anyhow::Result;
+use futures::future::join_all;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::DeviceStatus;
+use crate::db::{models::Device, queries};
+use crate::llama_cpp::{diagnosis::AGENT_API_PORT, LlamaCppManager};
+use crate::settings::{Key, SettingsCache};
+use crate::ws::{EventBus, WsEvent};
+
+/// Seconds without an answer before a device counts as offline.
+pub const OFFLINE_AFTER_KEY: &str = "device_offline_after_secs";
+/// How often devices are probed.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Note that `device` answered just now: bump `last_seen`, and bring it back
+/// from `offline`. Returns its status afterwards.
+pub async fn seen(pool: &SqlitePool, event_tx: &EventBus, device: &Device) -> Result<DeviceStatus> {
+    queries::update_device_last_seen(pool, &device.id).await?;
+    if device.status != DeviceStatus::Offline {
+        return Ok(device.status);
+    }
+    let Some(status) = queries::restore_device_status(pool, &device.id).await? else {
+        return Ok(device.status);
+    };
+    tracing::info!("Device {} is back; status {}", device.name, status);
+    if status == DeviceStatus::Approved {
+        let _ = event_tx.send(WsEvent::DeviceApproved {
+            device_id: device.id.clone(),
+            name: device.name.clone(),
+            ip: device.ip.clone(),
+        });
+    }
+    Ok(status)
+}
+
+/// Mark approved and suspended devices not heard from within `window`
+/// offline. Returns the IDs marked.
+pub async fn mark_stale(pool: &SqlitePool, event_tx: &EventBus, window: Duration) -> Result<Vec<String>> {
+    let cutoff = chrono::Utc::now() - chrono::Duration::from_std(window)?;
+    let mut marked = Vec::new();
+    for device in queries::list_devices(pool).await? {
+        if !matches!(device.status, DeviceStatus::Approved | DeviceStatus::Suspended)
+            || device.last_heard().is_none_or(|t| t >= cutoff)
+        {
+            continue;
+        }
+        if queries::mark_device_offline(pool, &device.id).await? {
+            tracing::info!("Device {} not heard from since {:?}; marked offline", device.name, device.last_seen);
+            let _ = event_tx.send(WsEvent::DeviceOffline {
+                name: device.name.clone(),
+                device_id: Some(device.id.clone()),
+            });
+            marked.push(device.id);
+        }
+    }
+    Ok(marked)
+}
+
+/// Whether any RPC port of `device` or its agent API accepts a connection.
+async fn answers(llama_cpp: &LlamaCppManager, device: &Device) -> bool {
+    let mut ports = device.rpc_ports();
+    ports.push(AGENT_API_PORT);
+    join_all(ports.into_iter().map(|port| llama_cpp.probe_rpc_device(&device.ip, port)))
+        .await
+        .contains(&true)
+}
+
+/// Probe the devices that may go or be offline, then mark the stale ones.
+async fn check(pool: &SqlitePool, settings: &SettingsCache, llama_cpp: &LlamaCppManager, event_tx: &EventBus) -> Result<()> {
+    let devices: Vec<Device> = queries::list_devices(pool)
+        .await?
+        .into_iter()
+        .filter(|d| matches!(d.status, DeviceStatus::Approved | DeviceStatus::Suspended | DeviceStatus::Offline))
+        .collect();
+    let probes = join_all(devices.iter().map(|d| answers(llama_cpp, d))).await;
+    for (device, answered) in devices.iter().zip(probes) {
+        if answered {
+            seen(pool, event_tx, device).await?;
+        }
+    }
+    let window = settings.get_u64(Key::DeviceOfflineAfterSecs);
+    mark_stale(pool, event_tx, Duration::from_secs(window)).await?;
+    Ok(())
+}
+
+/// Probe devices every [`CHECK_INTERVAL`] for the life of the process.
+pub fn spawn(pool: SqlitePool, settings: Arc<SettingsCache>, llama_cpp: Arc<LlamaCppManager>, event_tx: EventBus) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = check(&pool, &settings, &llama_cpp, &event_tx).await {
+                tracing::warn!("Device presence check failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn device(pool: &SqlitePool, ip: &str, status: DeviceStatus, last_seen: &str) -> Device {
+        let mut device = Device::new(format!("box-{}", ip), ip.into(), None, "manual");
+        device.status = status;
+        device.last_seen = Some(last_seen.into());
+        queries::insert_device(pool, &device).await.unwrap();
+        device
+    }
+
+    #[tokio::test]
+    async fn stale_devices_go_offline_and_come_back_as_they_were() {
+        let pool = crate::settings::tests::test_pool().await;
+        let events = EventBus::default();
+        let mut rx = events.subscribe_critical();
+        let long_ago = "2026-01-01T00:00:00Z";
+        let approved = device(&pool, "10.0.0.1", DeviceStatus::Approved, long_ago).await;
+        let suspended = device(&pool, "10.0.0.2", DeviceStatus::Suspended, long_ago).await;
+        let pending = device(&pool, "10.0.0.3", DeviceStatus::Pending, long_ago).await;
+        let now = chrono::Utc::now().to_rfc3339();
+        let fresh = device(&pool, "10.0.0.4", DeviceStatus::Approved, &now).await;
+
+        let mut marked = mark_stale(&pool, &events, Duration::from_secs(300)).await.unwrap();
+        marked.sort();
+        let mut expected = vec![approved.id.clone(), suspended.id.clone()];
+        expected.sort();
+        assert_eq!(marked, expected);
+        assert!(matches!(rx.try_recv(), Ok(WsEvent::DeviceOffline { device_id: Some(_), .. })));
+        let get = |id: String| {
+            let pool = pool.clone();
+            async move { queries::get_device(&pool, &id).await.unwrap().unwrap() }
+        };
+        let stored = get(approved.id.clone()).await;
+        assert_eq!((stored.status, stored.prior_status), (DeviceStatus::Offline, Some(DeviceStatus::Approved)));
+        assert_eq!(get(pending.id.clone()).await.status, DeviceStatus::Pending);
+        assert_eq!(get(fresh.id.clone()).await.status, DeviceStatus::Approved);
+        assert!(stored.last_seen_secs_ago.unwrap() > 300);
+
+        // Each comes back as it was; a suspended device stays suspended
+        assert_eq!(seen(&pool, &events, &stored).await.unwrap(), DeviceStatus::Approved);
+        let stored = get(suspended.id.clone()).await;
+        assert_eq!(seen(&pool, &events, &stored).await.unwrap(), DeviceStatus::Suspended);
+        let stored = get(suspended.id).await;
+        assert_eq!((stored.status, stored.prior_status), (DeviceStatus::Suspended, None));
+        assert!(stored.last_seen_secs_ago.unwrap() < 60);
+        assert!(mark_stale(&pool, &events, Duration::from_secs(300)).await.unwrap().is_empty());
+    }
+}
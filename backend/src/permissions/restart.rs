@@ -0,0 +1,263 @@
+//! Restarting a device's llama-rpc-server from the dashboard. A peer host is
+//! asked through its own `/api/cluster/rpc/stop` and `start`. An agent has no
+//! API to call: one installed as a service says so in its heartbeats
+//! (`can_restart_rpc`), and a restart waits for its next heartbeat, whose
+//! answer carries `restart_rpc: true`; the agent then restarts the service
+//! itself. Either way the RPC port is watched until it answers again.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::{DeviceKind, DeviceStatus, RpcStatus};
+use crate::api::agent::HEARTBEAT_INTERVAL_SECS;
+use crate::db::{models::Device, queries};
+use crate::federation;
+use crate::ws::WsEvent;
+use crate::AppState;
+
+/// Budget for a restart: an agent's next heartbeat, then the server coming up.
+pub const RESTART_TIMEOUT: Duration = Duration::from_secs(HEARTBEAT_INTERVAL_SECS + 30);
+/// Time an agent is given to stop the old server before the port is probed.
+const SETTLE: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Agents able to restart their RPC server, and restarts waiting for them.
+#[derive(Default)]
+pub struct RpcRestarts {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Sent `can_restart_rpc` in their last heartbeat
+    restartable: HashSet<String>,
+    /// Asked to restart; taken by the next heartbeat
+    pending: HashSet<String>,
+}
+
+impl RpcRestarts {
+    /// Note a heartbeat from `device_id`. Returns whether it should restart
+    /// its RPC server now.
+    pub fn heartbeat(&self, device_id: &str, can_restart: bool) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if can_restart {
+            inner.restartable.insert(device_id.to_string());
+        } else {
+            inner.restartable.remove(device_id);
+        }
+        inner.pending.remove(device_id) && can_restart
+    }
+
+    pub fn can_restart(&self, device_id: &str) -> bool {
+        self.inner.lock().unwrap().restartable.contains(device_id)
+    }
+
+    fn request(&self, device_id: &str) {
+        self.inner.lock().unwrap().pending.insert(device_id.to_string());
+    }
+
+    fn is_pending(&self, device_id: &str) -> bool {
+        self.inner.lock().unwrap().pending.contains(device_id)
+    }
+
+    /// Withdraw a restart no heartbeat took.
+    fn cancel(&self, device_id: &str) {
+        self.inner.lock().unwrap().pending.remove(device_id);
+    }
+}
+
+/// How the restart reached the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartVia {
+    /// The peer's own `/api/cluster/rpc/stop` and `start`
+    Peer,
+    /// The agent's heartbeat loop
+    Agent,
+}
+
+/// `POST /api/devices/:id/rpc/restart`
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct RpcRestarted {
+    pub device_id: String,
+    pub via: RestartVia,
+    pub rpc_port: i64,
+    /// From asking to the RPC port answering
+    pub took_ms: u64,
+}
+
+/// Why a restart didn't happen.
+#[derive(Debug)]
+pub enum RestartError {
+    NotApproved(DeviceStatus),
+    /// Neither a peer nor an agent installed as a service
+    Unsupported,
+    /// The peer refused or couldn't be reached
+    Failed(String),
+    /// The agent never took the restart, or the port never answered
+    TimedOut(String),
+}
+
+impl fmt::Display for RestartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartError::NotApproved(status) => write!(f, "Device is {}; only approved devices can be restarted", status),
+            RestartError::Unsupported => f.write_str(
+                "The device's agent can't restart llama-rpc-server: reinstall it with the persistent-service option",
+            ),
+            RestartError::Failed(e) => write!(f, "Restart failed: {}", e),
+            RestartError::TimedOut(e) => f.write_str(e),
+        }
+    }
+}
+
+/// Ask the peer to stop, then start, its RPC server.
+async fn restart_peer(state: &AppState, device: &Device) -> Result<(), RestartError> {
+    let base = federation::base_url(device);
+    for step in ["stop", "start"] {
+        let resp = state
+            .llama_cpp
+            .client
+            .post(format!("{}/api/cluster/rpc/{}", base, step))
+            .timeout(Duration::from_secs(15))
+            .send()
+            .await
+            .map_err(|e| RestartError::Failed(format!("{} unreachable: {}", device.name, e)))?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body: serde_json::Value = resp.json().await.unwrap_or_default();
+            return Err(RestartError::Failed(format!(
+                "{} refused to {} its RPC server (HTTP {}): {}",
+                device.name,
+                step,
+                status,
+                body["error"].as_str().unwrap_or("")
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Queue a restart for the agent and wait until a heartbeat takes it.
+async fn restart_agent(state: &AppState, device: &Device, deadline: Instant) -> Result<(), RestartError> {
+    state.rpc_restarts.request(&device.id);
+    while state.rpc_restarts.is_pending(&device.id) {
+        if Instant::now() >= deadline {
+            state.rpc_restarts.cancel(&device.id);
+            return Err(RestartError::TimedOut(format!(
+                "{}'s agent didn't check in within {} s",
+                device.name,
+                RESTART_TIMEOUT.as_secs()
+            )));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    tokio::time::sleep(SETTLE).await;
+    Ok(())
+}
+
+/// Restart `device`'s llama-rpc-server and wait, until `timeout`, for its
+/// port to answer. `rpc_status` is `connecting` meanwhile, then `ready` or
+/// `offline`, with an `rpc_device_ready` or `rpc_device_offline` event.
+pub async fn restart(state: &AppState, device: &Device, timeout: Duration) -> Result<RpcRestarted, RestartError> {
+    if device.status != DeviceStatus::Approved {
+        return Err(RestartError::NotApproved(device.status));
+    }
+    let via = if device.device_kind == DeviceKind::Peer {
+        RestartVia::Peer
+    } else if state.rpc_restarts.can_restart(&device.id) {
+        RestartVia::Agent
+    } else {
+        return Err(RestartError::Unsupported);
+    };
+
+    let started = Instant::now();
+    let deadline = started + timeout;
+    if let Err(e) = queries::update_device_rpc_status(&state.pool, &device.id, RpcStatus::Connecting).await {
+        tracing::warn!("Failed to update rpc_status of {}: {}", device.id, e);
+    }
+    tracing::info!("Restarting the RPC server of {} ({:?})", device.name, via);
+    let asked = match via {
+        RestartVia::Peer => restart_peer(state, device).await,
+        RestartVia::Agent => restart_agent(state, device, deadline).await,
+    };
+
+    let port = u16::try_from(device.rpc_port).unwrap_or(0);
+    let result = match asked {
+        Ok(()) => loop {
+            if state.llama_cpp.probe_rpc_device(&device.ip, port).await {
+                break Ok(());
+            }
+            if Instant::now() >= deadline {
+                break Err(RestartError::TimedOut(format!(
+                    "llama-rpc-server on {} didn't answer on port {} within {} s",
+                    device.name,
+                    device.rpc_port,
+                    timeout.as_secs()
+                )));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        },
+        Err(e) => Err(e),
+    };
+
+    let status = if result.is_ok() { RpcStatus::Ready } else { RpcStatus::Offline };
+    if let Err(e) = queries::update_device_rpc_status(&state.pool, &device.id, status).await {
+        tracing::warn!("Failed to update rpc_status of {}: {}", device.id, e);
+    }
+    match result {
+        Ok(()) => {
+            let _ = state.event_tx.send(WsEvent::RpcDeviceReady {
+                device_id: device.id.clone(),
+                memory_total_mb: device.memory_total_mb,
+                memory_free_mb: device.memory_free_mb,
+            });
+            Ok(RpcRestarted {
+                device_id: device.id.clone(),
+                via,
+                rpc_port: device.rpc_port,
+                took_ms: started.elapsed().as_millis() as u64,
+            })
+        }
+        Err(e) => {
+            tracing::warn!("Restarting the RPC server of {} failed: {}", device.name, e);
+            let _ = state.event_tx.send(WsEvent::RpcDeviceOffline {
+                device_id: device.id.clone(),
+            });
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_wait_for_a_heartbeat_that_can_take_them() {
+        let restarts = RpcRestarts::default();
+        assert!(!restarts.can_restart("a"));
+        assert!(!restarts.heartbeat("a", true));
+        assert!(restarts.can_restart("a"));
+
+        restarts.request("a");
+        assert!(restarts.is_pending("a"));
+        assert!(restarts.heartbeat("a", true));
+        assert!(!restarts.is_pending("a"));
+        assert!(!restarts.heartbeat("a", true));
+
+        // Reinstalled without the service: the restart is dropped, not run
+        restarts.request("a");
+        assert!(!restarts.heartbeat("a", false));
+        assert!(!restarts.can_restart("a"));
+        assert!(!restarts.is_pending("a"));
+
+        restarts.request("b");
+        restarts.cancel("b");
+        assert!(!restarts.heartbeat("b", true));
+    }
+}
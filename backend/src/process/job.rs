@@ -0,0 +1,116 @@
+use std::ffi::c_void;
+use std::io;
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, ERROR_INSUFFICIENT_BUFFER, FALSE, HANDLE, NO_ERROR};
+use windows::Win32::NetworkManagement::IpHelper::{
+    GetExtendedTcpTable, MIB_TCP6TABLE_OWNER_PID, MIB_TCPTABLE_OWNER_PID,
+    TCP_TABLE_OWNER_PID_LISTENER,
+};
+use windows::Win32::Networking::WinSock::{ADDRESS_FAMILY, AF_INET, AF_INET6};
+use windows::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+    SetInformationJobObject, TerminateJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+};
+use windows::Win32::System::Threading::{OpenProcess, TerminateProcess, PROCESS_TERMINATE};
+
+/// A Job Object that kills its processes when terminated or closed.
+#[derive(Debug)]
+pub struct Job(HANDLE);
+
+impl Job {
+    pub fn new() -> io::Result<Self> {
+        let job = Job(unsafe { CreateJobObjectW(None, PCWSTR::null()) }?);
+        let mut limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        limits.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        unsafe {
+            SetInformationJobObject(
+                job.0,
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const c_void,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )
+        }?;
+        Ok(job)
+    }
+
+    /// Put `child` in the job; processes it starts from now on join too.
+    pub fn assign(&self, child: &tokio::process::Child) -> io::Result<()> {
+        let process = child
+            .raw_handle()
+            .ok_or_else(|| io::Error::other("the process has already exited"))?;
+        unsafe { AssignProcessToJobObject(self.0, HANDLE(process as isize)) }?;
+        Ok(())
+    }
+
+    pub fn terminate(&self) -> io::Result<()> {
+        unsafe { TerminateJobObject(self.0, 1) }?;
+        Ok(())
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // With KILL_ON_JOB_CLOSE this also ends anything still running in it
+        let _ = unsafe { CloseHandle(self.0) };
+    }
+}
+
+pub fn terminate_process(pid: u32) -> io::Result<()> {
+    let process = unsafe { OpenProcess(PROCESS_TERMINATE, FALSE, pid) }?;
+    let result = unsafe { TerminateProcess(process, 1) };
+    let _ = unsafe { CloseHandle(process) };
+    result?;
+    Ok(())
+}
+
+/// The listening sockets of one address family, as returned by
+/// `GetExtendedTcpTable`. `u32`s keep the table's rows aligned.
+fn listener_table(family: ADDRESS_FAMILY) -> Option<Vec<u32>> {
+    let mut size = 0u32;
+    let mut table: Vec<u32> = Vec::new();
+    // The table may grow between the size query and the read
+    for _ in 0..3 {
+        let buffer = (!table.is_empty()).then(|| table.as_mut_ptr() as *mut c_void);
+        let status = unsafe {
+            GetExtendedTcpTable(
+                buffer,
+                &mut size,
+                FALSE,
+                family.0 as u32,
+                TCP_TABLE_OWNER_PID_LISTENER,
+                0,
+            )
+        };
+        if status == NO_ERROR.0 && !table.is_empty() {
+            return Some(table);
+        }
+        if status != ERROR_INSUFFICIENT_BUFFER.0 && status != NO_ERROR.0 {
+            return None;
+        }
+        table = vec![0; (size as usize).div_ceil(4)];
+    }
+    None
+}
+
+/// PID of the process listening on TCP `port`, over IPv4 or IPv6.
+pub fn listening_pid(port: u16) -> Option<u32> {
+    // Ports are stored in network byte order in the low 16 bits
+    let matches = |local_port: u32| u16::from_be(local_port as u16) == port;
+
+    if let Some(table) = listener_table(AF_INET) {
+        let table = table.as_ptr() as *const MIB_TCPTABLE_OWNER_PID;
+        let rows = unsafe {
+            std::slice::from_raw_parts((*table).table.as_ptr(), (*table).dwNumEntries as usize)
+        };
+        if let Some(row) = rows.iter().find(|r| matches(r.dwLocalPort)) {
+            return Some(row.dwOwningPid);
+        }
+    }
+    let table = listener_table(AF_INET6)?;
+    let table = table.as_ptr() as *const MIB_TCP6TABLE_OWNER_PID;
+    let rows = unsafe {
+        std::slice::from_raw_parts((*table).table.as_ptr(), (*table).dwNumEntries as usize)
+    };
+    rows.iter().find(|r| matches(r.dwLocalPort)).map(|r| r.dwOwningPid)
+}
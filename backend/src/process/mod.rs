@@ -0,0 +1,212 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use tokio::process::{Child, Command};
+use which::which;
+
+#[cfg(windows)]
+mod job;
+#[cfg(windows)]
+pub use job::listening_pid;
+
+/// Find a binary in PATH or ~/.sharedmem/bin/
+pub fn find_binary(name: &str) -> Option<PathBuf> {
+    // First try PATH
+    if let Ok(path) = which(name) {
+        return Some(path);
+    }
+    // Then try ~/.sharedmem/bin/
+    if let Ok(home) = std::env::var("HOME") {
+        let path = PathBuf::from(home).join(".sharedmem").join("bin").join(name);
+        if path.exists() {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// How the llama.cpp manager finds and starts its servers. Tests swap in
+/// one that starts nothing, so they run without llama.cpp installed.
+pub trait Spawner: Send + Sync {
+    /// Path of the binary called `name`, if it's installed.
+    fn find(&self, name: &str) -> Option<PathBuf>;
+    /// Start `program` as configured by `command` (arguments, environment,
+    /// output); `command` was created for `program`.
+    fn spawn(&self, program: &Path, command: &mut Command) -> io::Result<ManagedChild>;
+}
+
+/// Runs real processes.
+pub struct OsSpawner;
+
+impl Spawner for OsSpawner {
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        find_binary(name)
+    }
+
+    fn spawn(&self, _program: &Path, command: &mut Command) -> io::Result<ManagedChild> {
+        ManagedChild::spawn(command)
+    }
+}
+
+/// A server process we spawned (llama-server, llama-rpc-server, Ollama).
+///
+/// On Windows the process runs in a Job Object: killing it ends every
+/// process it started too, such as the GPU workers that otherwise keep the
+/// port busy, and the job goes down with the backend if that exits first.
+/// Elsewhere this is a plain [`Child`].
+#[derive(Debug)]
+pub struct ManagedChild {
+    /// None for a stand-in from a test spawner, which runs until killed
+    child: Option<Child>,
+    #[cfg(windows)]
+    job: Option<job::Job>,
+}
+
+impl ManagedChild {
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        let child = command.spawn()?;
+        #[cfg(windows)]
+        {
+            let mut child = child;
+            // Grandchildren started before the assignment escape the job;
+            // the servers we run only start theirs once they are up
+            let job = match job::Job::new().and_then(|job| job.assign(&child).map(|()| job)) {
+                Ok(job) => job,
+                Err(e) => {
+                    let _ = child.start_kill();
+                    return Err(e);
+                }
+            };
+            Ok(ManagedChild { child: Some(child), job: Some(job) })
+        }
+        #[cfg(not(windows))]
+        Ok(ManagedChild { child: Some(child) })
+    }
+
+    /// A process that isn't there: it never exits on its own and killing it
+    /// does nothing.
+    #[cfg(test)]
+    pub fn stub() -> Self {
+        ManagedChild {
+            child: None,
+            #[cfg(windows)]
+            job: None,
+        }
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match self.child.as_mut() {
+            Some(child) => child.try_wait(),
+            None => Ok(None),
+        }
+    }
+
+    /// Kill the process, and on Windows everything it started, then reap it.
+    pub async fn kill(&mut self) -> io::Result<()> {
+        let Some(child) = self.child.as_mut() else {
+            return Ok(());
+        };
+        #[cfg(windows)]
+        {
+            if let Some(job) = &self.job {
+                job.terminate()?;
+            }
+            child.wait().await.map(|_| ())
+        }
+        #[cfg(not(windows))]
+        child.kill().await
+    }
+}
+
+/// Forcefully end process `pid`, e.g. a server orphaned by a crashed backend.
+#[cfg(windows)]
+pub async fn kill_pid(pid: u32) -> io::Result<()> {
+    job::terminate_process(pid)
+}
+
+/// Forcefully end process `pid`, e.g. a server orphaned by a crashed backend.
+#[cfg(not(windows))]
+pub async fn kill_pid(pid: u32) -> io::Result<()> {
+    let status = Command::new("kill")
+        .args(["-9", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(io::Error::other(format!("kill -9 {} failed", pid)));
+    }
+    Ok(())
+}
+
+/// Whether [`restart_self`] can replace this process.
+pub const CAN_RESTART_SELF: bool = cfg!(unix);
+
+/// Replace this process with a fresh run of the same program, with the
+/// same arguments and environment. Returns only on failure.
+#[cfg(unix)]
+pub fn restart_self() -> io::Error {
+    use std::os::unix::process::CommandExt;
+    match std::env::current_exe() {
+        Ok(exe) => std::process::Command::new(exe).args(std::env::args_os().skip(1)).exec(),
+        Err(e) => e,
+    }
+}
+
+/// Replace this process with a fresh run of the same program, with the
+/// same arguments and environment. Returns only on failure.
+#[cfg(not(unix))]
+pub fn restart_self() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "a process can't replace itself on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{sleep, Duration};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kill_reaps_the_child() {
+        let mut child = ManagedChild::spawn(Command::new("sleep").arg("30")).unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().await.unwrap();
+        assert!(child.try_wait().unwrap().is_some());
+
+        let mut orphan = Command::new("sleep").arg("30").spawn().unwrap();
+        kill_pid(orphan.id().unwrap()).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert!(orphan.try_wait().unwrap().is_some());
+    }
+
+    /// A `cmd.exe` that starts a second `ping` loop, like llama-server
+    /// starting its workers: killing the parent must end both.
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn kill_ends_the_whole_tree() {
+        use sysinfo::{Pid, System};
+
+        let mut child = ManagedChild::spawn(Command::new("cmd").args([
+            "/C",
+            "start /B ping -n 60 127.0.0.1 >NUL & ping -n 60 127.0.0.1 >NUL",
+        ]))
+        .unwrap();
+        let parent = Pid::from_u32(child.child.as_ref().unwrap().id().unwrap());
+        sleep(Duration::from_secs(1)).await;
+
+        let mut sys = System::new();
+        sys.refresh_processes();
+        let tree: Vec<Pid> = sys
+            .processes()
+            .values()
+            .filter(|p| p.parent() == Some(parent))
+            .map(|p| p.pid())
+            .collect();
+        assert!(tree.len() >= 2, "expected two ping processes, found {:?}", tree);
+
+        child.kill().await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+        sys.refresh_processes();
+        assert!(tree.iter().all(|pid| sys.process(*pid).is_none()));
+    }
+}
@@ -0,0 +1,415 @@
+//! Settings with a default, stated once here. Missing ones are written
+//! with their defaults at startup and by first-run setup ([`seed`]), so
+//! `GET /api/settings` lists them all. The typed getters fall back to the
+//! same defaults when a value is missing or doesn't parse.
+//!
+//! Limits (`settings::limits`) are not seeded: their environment variables
+//! apply until one is saved. Neither are secrets, which setup generates.
+
+use sqlx::SqlitePool;
+
+use super::SettingsCache;
+use crate::{
+    api::{
+        cluster::{
+            MAX_BODY_MB_KEY, MAX_CONCURRENT_KEY, MAX_IMAGE_MB_KEY, QUEUE_PROMOTE_KEY, QUEUE_SIZE_KEY,
+            QUEUE_TIMEOUT_KEY,
+        },
+        model_files::SHARE_MODELS_KEY,
+        model_uploads::MAX_UPLOAD_MB_KEY,
+        models::AUTO_PULL_KEY,
+        resume::RESUME_ON_START_KEY,
+    },
+    api_keys::{REQUIRE_API_KEYS_KEY, REQUIRE_DASHBOARD_AUTH_KEY},
+    cors::{CORS_ORIGINS_KEY, DEFAULT_ORIGINS},
+    db::queries,
+    listen::{API_PORT_KEY, BIND_ADDRESS_KEY},
+    llama_cpp::{oom, rpc_probe::DEEP_PROBE_KEY, store, IDLE_TIMEOUT_KEY, RPC_MEM_KEY},
+    logs::output::LOG_FORMAT_KEY,
+    memory::{
+        sampler::{POLL_SECS_KEY, RAM_POLL_SECS_KEY, STATS_DELTA_KEY},
+        HOST_RESERVED_KEY,
+    },
+    ollama::{
+        native::NATIVE_MODE_KEY,
+        pulls::{DEFAULT_OFFPEAK_END, DEFAULT_OFFPEAK_START, OFFPEAK_END_KEY, OFFPEAK_START_KEY},
+        OLLAMA_HOST,
+    },
+    permissions::{
+        presence::OFFLINE_AFTER_KEY, ADMIN_TOKEN_HASH_KEY, ENROLLMENT_TOKEN_KEY, MIN_CLUSTER_TRUST_KEY,
+        MIN_STATS_TRUST_KEY,
+    },
+};
+
+/// Who may read a setting through `GET /api/settings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sensitivity {
+    /// Anyone who can reach the API
+    Public,
+    /// Admins only: addresses, hosts and models of this setup
+    Internal,
+    /// Nobody; admins see only whether it is set, as `<key>_set`
+    Secret,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    TrustLocalNetwork,
+    RestoreDeletedDevices,
+    AutoStartOllama,
+    ApiPort,
+    BindAddress,
+    MdnsEnabled,
+    DefaultRole,
+    OllamaHost,
+    OllamaNativeMode,
+    AutoPullMissingModels,
+    AutoRestartInference,
+    ResumeOnStart,
+    RpcDeepProbe,
+    MinClusterTrust,
+    MinStatsTrust,
+    DrainTimeoutSecs,
+    IdleTimeoutMinutes,
+    MemoryPollSecs,
+    MemoryPollRamSecs,
+    MemoryStatsDeltaMb,
+    HostReservedMb,
+    RpcMemMb,
+    ProxyMaxBodyMb,
+    ProxyMaxImageMb,
+    MaxConcurrentCompletions,
+    CompletionQueueSize,
+    CompletionQueueTimeoutSecs,
+    CompletionQueuePromoteSecs,
+    CorsAllowedOrigins,
+    OffpeakStart,
+    OffpeakEnd,
+    RequireApiKeys,
+    RequireDashboardAuth,
+    TrustLocalhost,
+    ShareModels,
+    ModelUploadMaxMb,
+    LogFormat,
+    DeviceOfflineAfterSecs,
+    OomRetryLayersPct,
+    ModelTrashDays,
+    BackendType,
+    BackendUrl,
+    BackendModel,
+    BackendSecret,
+    InferenceSecret,
+}
+
+impl Key {
+    pub const ALL: &'static [Key] = &[
+        Key::TrustLocalNetwork,
+        Key::RestoreDeletedDevices,
+        Key::AutoStartOllama,
+        Key::ApiPort,
+        Key::BindAddress,
+        Key::MdnsEnabled,
+        Key::DefaultRole,
+        Key::OllamaHost,
+        Key::OllamaNativeMode,
+        Key::AutoPullMissingModels,
+        Key::AutoRestartInference,
+        Key::ResumeOnStart,
+        Key::RpcDeepProbe,
+        Key::MinClusterTrust,
+        Key::MinStatsTrust,
+        Key::DrainTimeoutSecs,
+        Key::IdleTimeoutMinutes,
+        Key::MemoryPollSecs,
+        Key::MemoryPollRamSecs,
+        Key::MemoryStatsDeltaMb,
+        Key::HostReservedMb,
+        Key::RpcMemMb,
+        Key::ProxyMaxBodyMb,
+        Key::ProxyMaxImageMb,
+        Key::MaxConcurrentCompletions,
+        Key::CompletionQueueSize,
+        Key::CompletionQueueTimeoutSecs,
+        Key::CompletionQueuePromoteSecs,
+        Key::CorsAllowedOrigins,
+        Key::OffpeakStart,
+        Key::OffpeakEnd,
+        Key::RequireApiKeys,
+        Key::RequireDashboardAuth,
+        Key::TrustLocalhost,
+        Key::ShareModels,
+        Key::ModelUploadMaxMb,
+        Key::LogFormat,
+        Key::DeviceOfflineAfterSecs,
+        Key::OomRetryLayersPct,
+        Key::ModelTrashDays,
+        Key::BackendType,
+        Key::BackendUrl,
+        Key::BackendModel,
+        Key::BackendSecret,
+        Key::InferenceSecret,
+    ];
+
+    /// Its row in the `settings` table.
+    pub fn name(self) -> &'static str {
+        match self {
+            Key::TrustLocalNetwork => "trust_local_network",
+            Key::RestoreDeletedDevices => "restore_deleted_devices",
+            Key::AutoStartOllama => "auto_start_ollama",
+            Key::ApiPort => API_PORT_KEY,
+            Key::BindAddress => BIND_ADDRESS_KEY,
+            Key::MdnsEnabled => "mdns_enabled",
+            Key::DefaultRole => "default_role",
+            Key::OllamaHost => "ollama_host",
+            Key::OllamaNativeMode => NATIVE_MODE_KEY,
+            Key::AutoPullMissingModels => AUTO_PULL_KEY,
+            Key::AutoRestartInference => "auto_restart_inference",
+            Key::ResumeOnStart => RESUME_ON_START_KEY,
+            Key::RpcDeepProbe => DEEP_PROBE_KEY,
+            Key::MinClusterTrust => MIN_CLUSTER_TRUST_KEY,
+            Key::MinStatsTrust => MIN_STATS_TRUST_KEY,
+            Key::DrainTimeoutSecs => "inference_drain_timeout_secs",
+            Key::IdleTimeoutMinutes => IDLE_TIMEOUT_KEY,
+            Key::MemoryPollSecs => POLL_SECS_KEY,
+            Key::MemoryPollRamSecs => RAM_POLL_SECS_KEY,
+            Key::MemoryStatsDeltaMb => STATS_DELTA_KEY,
+            Key::HostReservedMb => HOST_RESERVED_KEY,
+            Key::RpcMemMb => RPC_MEM_KEY,
+            Key::ProxyMaxBodyMb => MAX_BODY_MB_KEY,
+            Key::ProxyMaxImageMb => MAX_IMAGE_MB_KEY,
+            Key::MaxConcurrentCompletions => MAX_CONCURRENT_KEY,
+            Key::CompletionQueueSize => QUEUE_SIZE_KEY,
+            Key::CompletionQueueTimeoutSecs => QUEUE_TIMEOUT_KEY,
+            Key::CompletionQueuePromoteSecs => QUEUE_PROMOTE_KEY,
+            Key::CorsAllowedOrigins => CORS_ORIGINS_KEY,
+            Key::OffpeakStart => OFFPEAK_START_KEY,
+            Key::OffpeakEnd => OFFPEAK_END_KEY,
+            Key::RequireApiKeys => REQUIRE_API_KEYS_KEY,
+            Key::RequireDashboardAuth => REQUIRE_DASHBOARD_AUTH_KEY,
+            Key::TrustLocalhost => "trust_localhost",
+            Key::ShareModels => SHARE_MODELS_KEY,
+            Key::ModelUploadMaxMb => MAX_UPLOAD_MB_KEY,
+            Key::LogFormat => LOG_FORMAT_KEY,
+            Key::DeviceOfflineAfterSecs => OFFLINE_AFTER_KEY,
+            Key::OomRetryLayersPct => oom::RETRY_STEPS_KEY,
+            Key::ModelTrashDays => store::TRASH_DAYS_KEY,
+            Key::BackendType => "backend_type",
+            Key::BackendUrl => "backend_url",
+            Key::BackendModel => "backend_model",
+            Key::BackendSecret => "backend_api_key",
+            Key::InferenceSecret => "inference_api_key",
+        }
+    }
+
+    /// The key stored as `name`, if it is one.
+    pub fn parse(name: &str) -> Option<Key> {
+        Key::ALL.iter().copied().find(|k| k.name() == name)
+    }
+
+    /// Whether `PUT /api/settings/:key` may change it. `api_port` only
+    /// reports the port bound at startup.
+    pub fn writable(self) -> bool {
+        self != Key::ApiPort
+    }
+
+    /// Who may read it.
+    pub fn sensitivity(self) -> Sensitivity {
+        match self {
+            Key::BackendSecret | Key::InferenceSecret => Sensitivity::Secret,
+            Key::OllamaHost
+            | Key::BindAddress
+            | Key::DefaultRole
+            | Key::CorsAllowedOrigins
+            | Key::BackendUrl
+            | Key::BackendModel => Sensitivity::Internal,
+            _ => Sensitivity::Public,
+        }
+    }
+
+    /// The value it has until one is saved.
+    pub fn default(self) -> &'static str {
+        match self {
+            Key::AutoStartOllama | Key::MdnsEnabled => "true",
+            Key::TrustLocalNetwork
+            | Key::RestoreDeletedDevices
+            | Key::OllamaNativeMode
+            | Key::AutoPullMissingModels
+            | Key::AutoRestartInference
+            | Key::ResumeOnStart
+            | Key::RpcDeepProbe
+            | Key::RequireApiKeys
+            | Key::RequireDashboardAuth
+            | Key::TrustLocalhost
+            | Key::ShareModels => "false",
+            Key::ApiPort => "8080",
+            Key::BindAddress => "0.0.0.0",
+            Key::DefaultRole => "role-guest",
+            Key::OllamaHost => OLLAMA_HOST,
+            Key::MinClusterTrust | Key::MinStatsTrust => "1",
+            Key::DrainTimeoutSecs => "30",
+            Key::IdleTimeoutMinutes | Key::RpcMemMb | Key::MaxConcurrentCompletions => "0",
+            Key::MemoryPollSecs => "3",
+            Key::MemoryPollRamSecs => "15",
+            Key::MemoryStatsDeltaMb => "64",
+            Key::HostReservedMb => "auto",
+            Key::ProxyMaxBodyMb => "10",
+            Key::ProxyMaxImageMb => "8",
+            Key::CompletionQueueSize => "16",
+            Key::CompletionQueueTimeoutSecs => "60",
+            Key::CompletionQueuePromoteSecs => "15",
+            Key::CorsAllowedOrigins => DEFAULT_ORIGINS,
+            Key::OffpeakStart => DEFAULT_OFFPEAK_START,
+            Key::OffpeakEnd => DEFAULT_OFFPEAK_END,
+            Key::ModelUploadMaxMb => "65536",
+            Key::LogFormat => "pretty",
+            Key::DeviceOfflineAfterSecs => "300",
+            Key::OomRetryLayersPct => oom::DEFAULT_RETRY_STEPS,
+            Key::ModelTrashDays => "7",
+            Key::BackendType => "llamacpp",
+            Key::BackendUrl | Key::BackendModel | Key::BackendSecret | Key::InferenceSecret => "",
+        }
+    }
+}
+
+/// Who may read the setting stored as `name`. Of those without a [`Key`],
+/// the tokens are secret and the rest internal.
+pub fn sensitivity(name: &str) -> Sensitivity {
+    if let Some(key) = Key::parse(name) {
+        return key.sensitivity();
+    }
+    match name {
+        ADMIN_TOKEN_HASH_KEY | ENROLLMENT_TOKEN_KEY => Sensitivity::Secret,
+        _ => Sensitivity::Internal,
+    }
+}
+
+/// `value` of `key` as a boolean: `"true"` or `"false"`, else the default.
+fn parse_bool(key: Key, value: Option<&str>) -> bool {
+    match value.map(str::trim) {
+        Some("true") => true,
+        Some("false") => false,
+        other => {
+            if let Some(v) = other {
+                tracing::warn!("Setting {} is {:?}, not true or false; using {}", key.name(), v, key.default());
+            }
+            key.default() == "true"
+        }
+    }
+}
+
+/// `value` of `key` as a port number, else the default.
+fn parse_port(key: Key, value: Option<&str>) -> u16 {
+    let default = key.default().parse().unwrap_or_default();
+    match value.map(|v| v.trim().parse::<u16>()) {
+        Some(Ok(port)) if port > 0 => port,
+        None => default,
+        Some(_) => {
+            tracing::warn!("Setting {} is not a port number; using {}", key.name(), default);
+            default
+        }
+    }
+}
+
+/// `value` of `key` as a whole number, else the default.
+fn parse_u64(key: Key, value: Option<&str>) -> u64 {
+    let default = key.default().parse().unwrap_or_default();
+    match value.map(|v| v.trim().parse::<u64>()) {
+        Some(Ok(n)) => n,
+        None => default,
+        Some(Err(_)) => {
+            tracing::warn!("Setting {} is not a whole number; using {}", key.name(), default);
+            default
+        }
+    }
+}
+
+impl SettingsCache {
+    pub fn get_bool(&self, key: Key) -> bool {
+        parse_bool(key, self.get(key.name()).as_deref())
+    }
+
+    pub fn get_u16_port(&self, key: Key) -> u16 {
+        parse_port(key, self.get(key.name()).as_deref())
+    }
+
+    pub fn get_u64(&self, key: Key) -> u64 {
+        parse_u64(key, self.get(key.name()).as_deref())
+    }
+
+    /// The stored value, empty or not, else the default.
+    pub fn get_string(&self, key: Key) -> String {
+        self.get(key.name()).unwrap_or_else(|| key.default().to_string())
+    }
+}
+
+async fn read(pool: &SqlitePool, key: Key) -> Option<String> {
+    queries::get_setting(pool, key.name()).await.unwrap_or_else(|e| {
+        tracing::warn!("Failed to read setting {}: {}", key.name(), e);
+        None
+    })
+}
+
+/// [`SettingsCache::get_bool`] for code that holds only the pool.
+pub async fn get_bool(pool: &SqlitePool, key: Key) -> bool {
+    parse_bool(key, read(pool, key).await.as_deref())
+}
+
+/// [`SettingsCache::get_u64`] for code that holds only the pool.
+pub async fn get_u64(pool: &SqlitePool, key: Key) -> u64 {
+    parse_u64(key, read(pool, key).await.as_deref())
+}
+
+/// [`SettingsCache::get_string`] for code that holds only the pool.
+pub async fn get_string(pool: &SqlitePool, key: Key) -> String {
+    read(pool, key).await.unwrap_or_else(|| key.default().to_string())
+}
+
+/// Write the default of every key that has no value. Returns the keys
+/// written.
+pub async fn seed(pool: &SqlitePool) -> anyhow::Result<Vec<&'static str>> {
+    let mut created = Vec::new();
+    for &key in Key::ALL {
+        if queries::insert_setting_if_missing(pool, key.name(), key.default()).await? {
+            created.push(key.name());
+        }
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn defaults_fill_gaps_and_bad_values() {
+        let pool = super::super::tests::test_pool().await;
+        let names: std::collections::HashSet<_> = Key::ALL.iter().map(|k| k.name()).collect();
+        assert_eq!(names.len(), Key::ALL.len());
+
+        // Missing: the default, without writing it
+        assert!(!get_bool(&pool, Key::AutoRestartInference).await);
+        assert_eq!(get_string(&pool, Key::BackendType).await, "llamacpp");
+        assert_eq!(get_u64(&pool, Key::DrainTimeoutSecs).await, 30);
+        assert!(queries::get_setting(&pool, "backend_type").await.unwrap().is_none());
+
+        // Values that don't parse fall back too
+        queries::set_setting(&pool, "auto_start_ollama", "yes").await.unwrap();
+        queries::set_setting(&pool, "require_api_keys", " true ").await.unwrap();
+        queries::set_setting(&pool, "api_port", "99999").await.unwrap();
+        queries::set_setting(&pool, "completion_queue_size", " 4 ").await.unwrap();
+        queries::set_setting(&pool, "memory_poll_secs", "-1").await.unwrap();
+        let cache = SettingsCache::load(pool.clone()).await.unwrap();
+        assert!(cache.get_bool(Key::AutoStartOllama));
+        assert!(cache.get_bool(Key::RequireApiKeys));
+        assert_eq!(cache.get_u16_port(Key::ApiPort), 8080);
+        assert_eq!((cache.get_u64(Key::CompletionQueueSize), cache.get_u64(Key::MemoryPollSecs)), (4, 3));
+
+        // Seeding fills in the rest and keeps what is there
+        let created = seed(&pool).await.unwrap();
+        assert!(created.contains(&"backend_type") && !created.contains(&"auto_start_ollama"));
+        assert!(seed(&pool).await.unwrap().is_empty());
+        let stored = queries::list_settings(&pool).await.unwrap();
+        assert!(Key::ALL.iter().all(|k| stored.iter().any(|s| s.key == k.name())));
+        assert_eq!(get_string(&pool, Key::AutoStartOllama).await, "yes");
+    }
+}
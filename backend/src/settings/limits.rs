@@ -0,0 +1,219 @@
+//! Numeric limits that tune the server: how many devices a session may use,
+//! how long RPC probes wait, how much memory the fit checks hold back, and
+//! the sizes fixed when the server starts.
+//!
+//! Each limit is read from its setting, else from its environment variable,
+//! else its default. None of them is seeded by first-run setup, so the
+//! environment applies until a limit is saved through the settings API.
+
+use serde::Serialize;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use super::SettingsCache;
+
+pub const MAX_CLUSTER_DEVICES_KEY: &str = "max_cluster_devices";
+pub const PROBE_TIMEOUT_MS_KEY: &str = "probe_timeout_ms";
+pub const MEMORY_HEADROOM_PCT_KEY: &str = "memory_headroom_pct";
+pub const WS_BUFFER_KEY: &str = "ws_buffer";
+pub const PROXY_TIMEOUT_SECS_KEY: &str = "proxy_timeout_secs";
+
+/// One limit: where it is read from and the values it may take.
+pub struct LimitSpec {
+    pub key: &'static str,
+    pub env: &'static str,
+    pub default: u64,
+    pub min: u64,
+    pub max: u64,
+    /// Takes effect as soon as it is saved; otherwise on the next start
+    pub hot: bool,
+}
+
+pub const SPECS: &[LimitSpec] = &[
+    LimitSpec { key: MAX_CLUSTER_DEVICES_KEY, env: "MAX_CLUSTER_DEVICES", default: 20, min: 1, max: 256, hot: true },
+    LimitSpec { key: PROBE_TIMEOUT_MS_KEY, env: "PROBE_TIMEOUT_MS", default: 2000, min: 100, max: 30_000, hot: true },
+    LimitSpec { key: MEMORY_HEADROOM_PCT_KEY, env: "MEMORY_HEADROOM_PCT", default: 10, min: 0, max: 50, hot: true },
+    LimitSpec { key: WS_BUFFER_KEY, env: "WS_BUFFER", default: 1024, min: 16, max: 65_536, hot: false },
+    LimitSpec { key: PROXY_TIMEOUT_SECS_KEY, env: "PROXY_TIMEOUT_SECS", default: 120, min: 5, max: 3600, hot: false },
+];
+
+fn spec(key: &str) -> Option<&'static LimitSpec> {
+    SPECS.iter().find(|s| s.key == key)
+}
+
+/// `Some` with the outcome when `key` is a limit, `None` otherwise.
+pub fn validate(key: &str, value: &str) -> Option<Result<(), String>> {
+    let spec = spec(key)?;
+    Some(match value.trim().parse::<u64>() {
+        Ok(n) if (spec.min..=spec.max).contains(&n) => Ok(()),
+        _ => Err(format!("{} must be an integer between {} and {}", key, spec.min, spec.max)),
+    })
+}
+
+/// Where a limit's value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitSource {
+    Setting,
+    Env,
+    Default,
+}
+
+/// The value of `spec` given its setting and environment variable. Values
+/// that don't parse or are out of range are skipped with a warning.
+fn resolve(spec: &LimitSpec, setting: Option<&str>, env: Option<&str>) -> (u64, LimitSource) {
+    for (value, source) in [(setting, LimitSource::Setting), (env, LimitSource::Env)] {
+        let Some(value) = value else { continue };
+        match validate(spec.key, value) {
+            Some(Ok(())) => return (value.trim().parse().unwrap_or(spec.default), source),
+            Some(Err(e)) => tracing::warn!("Ignoring {} from {:?}: {}", spec.key, source, e),
+            None => {}
+        }
+    }
+    (spec.default, LimitSource::Default)
+}
+
+fn read(settings: &SettingsCache, spec: &LimitSpec) -> (u64, LimitSource) {
+    let env = std::env::var(spec.env).ok();
+    resolve(spec, settings.get(spec.key).as_deref(), env.as_deref())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Devices one inference session may use, named or through tags
+    pub max_cluster_devices: usize,
+    /// TCP connect timeout when probing a device's RPC server
+    pub probe_timeout_ms: u64,
+    /// Share of free memory the fit checks leave unused
+    pub memory_headroom_pct: u64,
+    /// Events buffered for each WebSocket client before it lags
+    pub ws_buffer: usize,
+    /// Timeout of requests proxied to llama-server
+    pub proxy_timeout_secs: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::from_values(|spec| spec.default)
+    }
+}
+
+impl Limits {
+    fn from_values(value: impl Fn(&LimitSpec) -> u64) -> Self {
+        let get = |key: &str| spec(key).map_or(0, &value);
+        Limits {
+            max_cluster_devices: get(MAX_CLUSTER_DEVICES_KEY) as usize,
+            probe_timeout_ms: get(PROBE_TIMEOUT_MS_KEY),
+            memory_headroom_pct: get(MEMORY_HEADROOM_PCT_KEY),
+            ws_buffer: get(WS_BUFFER_KEY) as usize,
+            proxy_timeout_secs: get(PROXY_TIMEOUT_SECS_KEY),
+        }
+    }
+
+    pub fn load(settings: &SettingsCache) -> Self {
+        Self::from_values(|spec| read(settings, spec).0)
+    }
+
+    fn value(&self, key: &str) -> u64 {
+        match key {
+            MAX_CLUSTER_DEVICES_KEY => self.max_cluster_devices as u64,
+            PROBE_TIMEOUT_MS_KEY => self.probe_timeout_ms,
+            MEMORY_HEADROOM_PCT_KEY => self.memory_headroom_pct,
+            WS_BUFFER_KEY => self.ws_buffer as u64,
+            _ => self.proxy_timeout_secs,
+        }
+    }
+
+    pub fn probe_timeout(&self) -> Duration {
+        Duration::from_millis(self.probe_timeout_ms)
+    }
+
+    pub fn proxy_timeout(&self) -> Duration {
+        Duration::from_secs(self.proxy_timeout_secs)
+    }
+}
+
+/// One limit as shown by GET /api/settings.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct EffectiveLimit {
+    pub key: &'static str,
+    /// The value in use
+    pub value: u64,
+    /// Where the configured value comes from
+    pub source: LimitSource,
+    pub min: u64,
+    pub max: u64,
+    /// Whether a change is picked up without restarting
+    pub hot: bool,
+    /// A saved value that waits for a restart to take effect
+    pub restart_required: Option<u64>,
+}
+
+/// The limits in use. Hot limits follow [`reload`](Self::reload); the rest
+/// keep the values read at startup.
+#[derive(Default)]
+pub struct LimitsCache {
+    current: RwLock<Limits>,
+}
+
+impl LimitsCache {
+    /// Read the limits at startup and apply the memory headroom.
+    pub fn load(settings: &SettingsCache) -> Self {
+        let limits = Limits::load(settings);
+        crate::llama_cpp::set_memory_headroom(limits.memory_headroom_pct);
+        LimitsCache { current: RwLock::new(limits) }
+    }
+
+    pub fn get(&self) -> Limits {
+        *self.current.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply the hot limits from `settings`, after one of them changed.
+    pub fn reload(&self, settings: &SettingsCache) {
+        let fresh = Limits::load(settings);
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        current.max_cluster_devices = fresh.max_cluster_devices;
+        current.probe_timeout_ms = fresh.probe_timeout_ms;
+        current.memory_headroom_pct = fresh.memory_headroom_pct;
+        crate::llama_cpp::set_memory_headroom(fresh.memory_headroom_pct);
+    }
+
+    pub fn effective(&self, settings: &SettingsCache) -> Vec<EffectiveLimit> {
+        let current = self.get();
+        SPECS
+            .iter()
+            .map(|spec| {
+                let (configured, source) = read(settings, spec);
+                let value = current.value(spec.key);
+                EffectiveLimit {
+                    key: spec.key,
+                    value,
+                    source,
+                    min: spec.min,
+                    max: spec.max,
+                    hot: spec.hot,
+                    restart_required: (configured != value).then_some(configured),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_win_over_env_and_bad_values_are_skipped() {
+        let spec = spec(MAX_CLUSTER_DEVICES_KEY).unwrap();
+        assert_eq!(resolve(spec, Some("8"), Some("12")), (8, LimitSource::Setting));
+        assert_eq!(resolve(spec, None, Some(" 12 ")), (12, LimitSource::Env));
+        assert_eq!(resolve(spec, Some("0"), Some("lots")), (20, LimitSource::Default));
+        assert_eq!(resolve(spec, Some("999"), Some("30")), (30, LimitSource::Env));
+
+        assert_eq!(validate(MEMORY_HEADROOM_PCT_KEY, "50"), Some(Ok(())));
+        assert!(matches!(validate(MEMORY_HEADROOM_PCT_KEY, "51"), Some(Err(_))));
+        assert_eq!(validate("mdns_enabled", "true"), None);
+        assert_eq!(Limits::default().max_cluster_devices, 20);
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use crate::db::queries;
+
+pub mod keys;
+pub mod limits;
+
+pub use keys::{get_bool, get_string, get_u64, Key};
+
+/// In-memory copy of the `settings` table.
+///
+/// Loaded once at startup and kept current by writing through [`set`](Self::set),
+/// so hot paths (the `/v1` proxy) read settings without touching SQLite.
+/// Anything that changes the table behind the cache's back must call
+/// [`refresh`](Self::refresh).
+pub struct SettingsCache {
+    pool: SqlitePool,
+    values: RwLock<HashMap<String, String>>,
+    /// Number of times the cache has read the settings table
+    db_reads: AtomicUsize,
+}
+
+impl SettingsCache {
+    pub async fn load(pool: SqlitePool) -> Result<Self> {
+        let cache = SettingsCache {
+            pool,
+            values: RwLock::new(HashMap::new()),
+            db_reads: AtomicUsize::new(0),
+        };
+        cache.refresh().await?;
+        Ok(cache)
+    }
+
+    /// Invalidation hook: re-read every setting from the database.
+    pub async fn refresh(&self) -> Result<()> {
+        self.db_reads.fetch_add(1, Ordering::Relaxed);
+        let rows = queries::list_settings(&self.pool).await?;
+        let fresh: HashMap<String, String> = rows.into_iter().map(|s| (s.key, s.value)).collect();
+        *self.values.write().unwrap_or_else(|e| e.into_inner()) = fresh;
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.values
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .cloned()
+    }
+
+    /// Persist a setting and update the cached copy.
+    pub async fn set(&self, key: &str, value: &str) -> Result<()> {
+        queries::set_setting(&self.pool, key, value).await?;
+        self.values
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    pub fn db_reads(&self) -> usize {
+        self.db_reads.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    /// Migrated in-memory database. One connection, since every `:memory:`
+    /// connection is a separate database.
+    pub(crate) async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        crate::db::MIGRATOR.run(&pool).await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn writes_are_visible_without_reloading() {
+        let cache = SettingsCache::load(test_pool().await).await.unwrap();
+        assert_eq!(cache.db_reads(), 1);
+        assert_eq!(cache.get("mdns_enabled").as_deref(), Some("true"));
+
+        cache.set("mdns_enabled", "false").await.unwrap();
+        assert!(!cache.get_bool(Key::MdnsEnabled));
+        assert_eq!(cache.db_reads(), 1);
+    }
+
+    #[tokio::test]
+    async fn refresh_picks_up_external_writes() {
+        let pool = test_pool().await;
+        let cache = SettingsCache::load(pool.clone()).await.unwrap();
+
+        queries::set_setting(&pool, "backend_url", "http://elsewhere:1234").await.unwrap();
+        assert_ne!(cache.get("backend_url").as_deref(), Some("http://elsewhere:1234"));
+
+        cache.refresh().await.unwrap();
+        assert_eq!(cache.get("backend_url").as_deref(), Some("http://elsewhere:1234"));
+        assert_eq!(cache.db_reads(), 2);
+    }
+}
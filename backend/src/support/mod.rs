@@ -0,0 +1,145 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::io::{Cursor, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use zip::write::SimpleFileOptions;
+
+use crate::api_keys::KEY_PREFIX;
+
+/// What a secret or an address is replaced with.
+pub const REDACTED: &str = "[redacted]";
+
+/// Whether a setting or field with this name holds a secret: tokens, API
+/// keys, password and hashes of any of them.
+pub fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["token", "_secret", "_hash", "api_key", "password"]
+        .iter()
+        .any(|s| key.ends_with(s))
+}
+
+/// Strip API keys and bearer tokens from free text, and with `ips` every
+/// IP address other than loopback and unspecified ones.
+pub fn redact_text(text: &str, ips: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix(KEY_PREFIX) {
+            out.push_str(REDACTED);
+            rest = after.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("Bearer ") {
+            out.push_str("Bearer ");
+            out.push_str(REDACTED);
+            rest = after.trim_start_matches(|c: char| !c.is_whitespace() && c != '"');
+            continue;
+        }
+        // An address is a run of hex digits, dots and colons; look at whole runs only
+        let run = rest
+            .find(|c: char| !(c.is_ascii_hexdigit() || c == '.' || c == ':'))
+            .unwrap_or(rest.len())
+            .max(rest.chars().next().map_or(1, char::len_utf8));
+        let (head, tail) = rest.split_at(run);
+        match routable_ip_len(head).filter(|_| ips) {
+            Some(len) => {
+                out.push_str(REDACTED);
+                out.push_str(&head[len..]);
+            }
+            None => out.push_str(head),
+        }
+        rest = tail;
+    }
+    out
+}
+
+/// Length of the IP address `run` starts with, if it is one and isn't
+/// loopback or unspecified. A trailing port or punctuation isn't counted.
+fn routable_ip_len(run: &str) -> Option<usize> {
+    let candidate = run.trim_end_matches(['.', ':']);
+    let (ip, len) = match candidate.parse::<Ipv6Addr>() {
+        Ok(v6) => (IpAddr::V6(v6), candidate.len()),
+        Err(_) => {
+            // IPv4, possibly followed by its port
+            let v4 = candidate.split_once(':').map_or(candidate, |(ip, _)| ip);
+            (IpAddr::V4(v4.parse::<Ipv4Addr>().ok()?), v4.len())
+        }
+    };
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(len)
+}
+
+/// Redact a JSON document in place: every value under a secret-looking key,
+/// and secrets (and with `ips`, addresses) inside every string.
+pub fn redact_json(value: &mut Value, ips: bool) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if is_secret_key(key) && !v.is_null() {
+                    *v = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(v, ips);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| redact_json(v, ips)),
+        Value::String(s) => *s = redact_text(s, ips),
+        _ => {}
+    }
+}
+
+/// Zip `files` (name, contents) in memory. They are small: JSON documents and
+/// at most a few thousand log lines.
+pub fn build_zip(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, contents) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(&contents)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn secrets_and_addresses_are_redacted() {
+        let mut settings = serde_json::json!({
+            "agent_enrollment_token": "abcdefghijklmnop",
+            "backend_api_key": "sk-live",
+            "admin_token_hash": "f00d",
+            "require_api_keys": "true",
+            "ollama_host": "http://192.168.1.20:11434",
+            "backend_url": "http://127.0.0.1:8000",
+            "unset_token": null,
+        });
+        redact_json(&mut settings, true);
+        assert_eq!(settings["agent_enrollment_token"], REDACTED);
+        assert_eq!(settings["backend_api_key"], REDACTED);
+        assert_eq!(settings["admin_token_hash"], REDACTED);
+        assert_eq!(settings["require_api_keys"], "true");
+        assert_eq!(settings["ollama_host"], "http://[redacted]:11434");
+        assert_eq!(settings["backend_url"], "http://127.0.0.1:8000");
+        assert!(settings["unset_token"].is_null());
+
+        let line = "2026-01-01T12:00:00Z key sk-sllm-Ab3_x9 from 10.0.0.7:50052, fe80::1 and ::1. Authorization: Bearer abc.def";
+        assert_eq!(
+            redact_text(line, true),
+            "2026-01-01T12:00:00Z key [redacted] from [redacted]:50052, [redacted] and ::1. Authorization: Bearer [redacted]"
+        );
+        // Addresses are kept unless asked for; a MAC or a version isn't one
+        assert!(redact_text("rpc 10.0.0.7 up", false).contains("10.0.0.7"));
+        assert_eq!(redact_text("mac aa:bb:cc:dd:ee:ff v1.2.3", true), "mac aa:bb:cc:dd:ee:ff v1.2.3");
+    }
+
+    #[test]
+    fn bundle_is_a_readable_zip() {
+        let bytes = build_zip(vec![("settings.json".to_string(), b"{}".to_vec())]).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut contents = String::new();
+        archive.by_name("settings.json").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "{}");
+    }
+}
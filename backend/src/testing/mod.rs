@@ -0,0 +1,1489 @@
+//! The app in-process, for HTTP-level tests: the real router and handlers
+//! over an in-memory database, with a GPU that reports canned numbers and a
+//! llama.cpp manager that starts no processes. Background tasks (sampling,
+//! watchdogs, discovery) are not started.
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+use tower::ServiceExt;
+
+use crate::llama_cpp::LlamaCppManager;
+use crate::memory::{hotplug::ProviderSet, remote::RemoteMemoryCache, sampler::MemorySampler, GpuKind, MemoryProvider};
+use crate::ollama::{pulls::PullQueue, OllamaManager};
+use crate::permissions::restart::RpcRestarts;
+use crate::process::{ManagedChild, Spawner};
+use crate::settings::{limits::LimitsCache, SettingsCache};
+use crate::webhooks::WebhookDispatcher;
+use crate::ws::{clients::WsClientRegistry, EventBus};
+use crate::{api_keys, build_router, errors::RecentErrors, federation, inventory, listen, logs, AppState};
+
+/// Total memory of the stub GPU.
+pub const STUB_TOTAL_MB: u64 = 24576;
+/// Free memory of the stub GPU.
+pub const STUB_FREE_MB: u64 = 16384;
+
+/// A GPU that always reports [`STUB_TOTAL_MB`] and [`STUB_FREE_MB`].
+pub struct StubProvider;
+
+impl MemoryProvider for StubProvider {
+    fn id(&self) -> &str {
+        "stub-gpu"
+    }
+    fn name(&self) -> &str {
+        "Stub GPU"
+    }
+    fn kind(&self) -> GpuKind {
+        GpuKind::Nvidia
+    }
+    fn snapshot(&self) -> Option<(u64, u64, u64)> {
+        Some((STUB_TOTAL_MB, STUB_TOTAL_MB - STUB_FREE_MB, STUB_FREE_MB))
+    }
+}
+
+/// Finds every binary and starts nothing; the processes it "starts" run
+/// until killed. Keeps the command lines it was asked to run.
+#[derive(Default)]
+pub struct StubSpawner {
+    started: Mutex<Vec<Vec<String>>>,
+    /// Binaries found at a real path (see `install`)
+    installed: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl StubSpawner {
+    /// Find `name` at `path` from now on, e.g. a script that answers
+    /// `--version` like a particular llama.cpp build.
+    pub fn install(&self, name: &str, path: PathBuf) {
+        self.installed.lock().unwrap().insert(name.to_string(), path);
+    }
+
+    /// Program and arguments of every start so far.
+    pub fn started(&self) -> Vec<Vec<String>> {
+        self.started.lock().unwrap().clone()
+    }
+}
+
+impl Spawner for StubSpawner {
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        let installed = self.installed.lock().unwrap().get(name).cloned();
+        Some(installed.unwrap_or_else(|| PathBuf::from(name)))
+    }
+
+    fn spawn(&self, program: &Path, command: &mut Command) -> io::Result<ManagedChild> {
+        let mut line = vec![program.display().to_string()];
+        line.extend(command.as_std().get_args().map(|a| a.to_string_lossy().into_owned()));
+        self.started.lock().unwrap().push(line);
+        Ok(ManagedChild::stub())
+    }
+}
+
+/// A port nothing listens on right now.
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+impl AppState {
+    /// State over a migrated in-memory database, with [`StubProvider`] as
+    /// the only memory provider and llama.cpp started through `spawner` on
+    /// free ports.
+    pub async fn for_test(spawner: Arc<dyn Spawner>) -> Arc<AppState> {
+        let pool = crate::settings::tests::test_pool().await;
+        let settings = Arc::new(SettingsCache::load(pool.clone()).await.unwrap());
+        let limits = Arc::new(LimitsCache::load(&settings));
+        let event_tx = EventBus::with_buffer(limits.get().ws_buffer);
+        let providers = Arc::new(ProviderSet::new(vec![Arc::new(StubProvider)]));
+        let ollama = Arc::new(OllamaManager::new(None, false));
+        let mut llama_cpp = LlamaCppManager::with_spawner(event_tx.clone(), spawner).with_limits(limits.clone());
+        llama_cpp.rpc_port = free_port();
+        llama_cpp.inference_port = free_port();
+        Arc::new(AppState {
+            pool: pool.clone(),
+            event_tx: event_tx.clone(),
+            providers: providers.clone(),
+            ollama: ollama.clone(),
+            pulls: Arc::new(PullQueue::new(pool.clone(), ollama, settings.clone(), event_tx)),
+            llama_cpp: Arc::new(llama_cpp),
+            webhooks: Arc::new(WebhookDispatcher::new(pool)),
+            settings: settings.clone(),
+            memory: Arc::new(MemorySampler::new(providers, settings)),
+            remote_memory: Arc::new(RemoteMemoryCache::default()),
+            rpc_restarts: Arc::new(RpcRestarts::default()),
+            start_keys: Arc::new(crate::api::idempotency::IdempotencyKeys::default()),
+            device_health: Arc::new(crate::llama_cpp::health::FlapTracker::default()),
+            limits,
+            uploads: Arc::new(crate::api::model_uploads::UploadRegistry::default()),
+            peers: Arc::new(federation::PeerCache::default()),
+            inventory: Arc::new(inventory::InventoryCache::default()),
+            logs: Arc::new(logs::LogBuffer::default()),
+            log_output: logs::output::LogOutput::detached(),
+            errors: Arc::new(RecentErrors::default()),
+            ws_clients: Arc::new(WsClientRegistry::default()),
+            key_limiter: Arc::new(api_keys::KeyRateLimiter::default()),
+            read_only: false,
+            port: listen::DEFAULT_PORT,
+            bind: listen::BindAddr { ip: listen::DEFAULT_BIND_ADDR, source: listen::BindSource::Default },
+        })
+    }
+}
+
+/// The router over a fresh [`AppState::for_test`].
+pub struct TestApp {
+    pub state: Arc<AppState>,
+    pub spawner: Arc<StubSpawner>,
+    router: Router,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        let spawner = Arc::new(StubSpawner::default());
+        let state = AppState::for_test(spawner.clone()).await;
+        let router = build_router(state.clone(), None);
+        TestApp { state, spawner, router }
+    }
+
+    /// Send a request (with a JSON body, if any) from loopback and return
+    /// the status and the body as JSON, or `Null` when it isn't JSON.
+    pub async fn request(&self, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+        self.request_as(SocketAddr::from(([127, 0, 0, 1], 40000)), None, method, uri, body).await
+    }
+
+    /// Like [`request`](Self::request), from `from` and with an optional
+    /// bearer token.
+    pub async fn request_as(
+        &self,
+        from: SocketAddr,
+        token: Option<&str>,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let body = match body {
+            Some(json) => {
+                request = request.header("content-type", "application/json");
+                Body::from(json.to_string())
+            }
+            None => Body::empty(),
+        };
+        let mut request = request.body(body).unwrap();
+        // Normally set by `into_make_service_with_connect_info`
+        request.extensions_mut().insert(ConnectInfo(from));
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        self.request(Method::GET, uri, None).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.request(Method::POST, uri, Some(body)).await
+    }
+
+    /// Serve the app on a loopback port, for clients that need a real
+    /// connection (WebSockets).
+    pub async fn serve(&self) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = self.router.clone().into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, service).await });
+        addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::WsEvent;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn device_lifecycle() {
+        let app = TestApp::new().await;
+        let (status, device) = app.post("/api/devices", json!({ "name": "rig", "ip": "192.0.2.10" })).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(device["status"], "pending");
+        let id = device["id"].as_str().unwrap();
+        let memory = format!("/api/devices/{}/memory", id);
+
+        // Nothing is handed to a device before it's approved
+        let (status, _) = app.request(Method::PATCH, &memory, Some(json!({ "memory_mb": 1024 }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let approve = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"] });
+        let (status, approved) = app.post(&format!("/api/devices/{}/approve", id), approve).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(approved["status"], "approved");
+        assert!(approved["agent_secret"].is_string());
+
+        let (status, _) = app.request(Method::PATCH, &memory, Some(json!({ "memory_mb": 1024 }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, device) = app.get(&format!("/api/devices/{}", id)).await;
+        assert_eq!(device["allocated_memory_mb"], 1024);
+
+        let (status, _) = app.post(&format!("/api/devices/{}/deny", id), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, device) = app.get(&format!("/api/devices/{}", id)).await;
+        assert_eq!(device["status"], "denied");
+        assert_eq!(device["allocated_memory_mb"], 0);
+    }
+
+    #[tokio::test]
+    async fn labs_are_imported_before_their_agents() {
+        let app = TestApp::new().await;
+        let (_, existing) = app.post("/api/devices", json!({ "name": "old", "ip": "10.9.0.4" })).await;
+
+        // Two rows for one machine stop the import before anything is written
+        let twice = json!([
+            { "name": "a", "ip": "10.9.0.1" },
+            { "name": "b", "ip": "10.9.0.2" },
+            { "name": "c", "ip": "10.9.0.001" },
+        ]);
+        let (status, error) = app.post("/api/devices/import", twice).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error["error"].as_str().unwrap().contains("10.9.0.1 (rows 1, 3)"), "{}", error);
+        assert_eq!(app.get("/api/devices").await.1["devices"].as_array().map(Vec::len), Some(1));
+
+        let rows = json!([
+            {
+                "name": "bench-1", "ip": "10.9.0.1", "mac": "AA-BB-CC-00-11-01", "role_id": "role-guest", "auto_approve": true,
+                "notes": "  left bench\u{7}  ", "labels": { "Rack": " a ", "spare": "" },
+            },
+            { "name": "bench-2", "ip": "10.9.0.2", "role_id": "role-guest" },
+            { "name": "bench-3", "ip": "10.9.0.3", "role_id": "role-nope", "auto_approve": true },
+            { "name": "bench-4", "ip": "10.9.0.4", "auto_approve": true },
+            { "name": "bench-5", "ip": "not an address!" },
+            { "name": "bench-6", "ip": "10.9.0.6", "labels": { "no spaces": "x" } },
+        ]);
+        let (status, report) = app.post("/api/devices/import", rows).await;
+        assert_eq!(status, StatusCode::OK, "{}", report);
+        assert_eq!((report["total"].as_u64(), report["imported"].as_u64()), (Some(6), Some(2)));
+        assert_eq!((report["existing"].as_u64(), report["failed"].as_u64()), (Some(1), Some(3)));
+        let results = report["results"].as_array().unwrap();
+        assert_eq!(results[0]["status"], "approved");
+        assert_eq!(results[1]["status"], "pending");
+        assert!(results[2]["error"].as_str().unwrap().contains("role-nope"));
+        assert_eq!((results[3]["created"].as_bool(), &results[3]["device_id"]), (Some(false), &existing["id"]));
+        assert_eq!(results[3]["status"], "pending");
+        assert_eq!(results[4]["ok"], false);
+        assert!(results[5]["error"].as_str().unwrap().contains("no spaces"));
+
+        let (_, bench1) = app.get(&format!("/api/devices/{}", results[0]["device_id"].as_str().unwrap())).await;
+        assert_eq!((bench1["role_id"].as_str(), bench1["mac"].as_str()), (Some("role-guest"), Some("aa:bb:cc:00:11:01")));
+        assert_eq!(bench1["discovery_method"], "import");
+        assert_eq!((&bench1["notes"], &bench1["labels"]), (&json!("left bench"), &json!({ "rack": "a" })));
+        // A pending import keeps its role for the approval
+        let uri = format!("/api/devices/{}/approve?verify=false", results[1]["device_id"].as_str().unwrap());
+        let (_, approved) = app.post(&uri, json!({})).await;
+        assert_eq!((approved["status"].as_str(), approved["role_id"].as_str()), (Some("approved"), Some("role-guest")));
+
+        let csv = "name,ip,auto_approve,labels\nbench-6,10.9.0.6,true,rack=b\n";
+        let request = Request::post("/api/devices/import")
+            .header("content-type", "text/csv")
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 9))))
+            .body(Body::from(csv))
+            .unwrap();
+        let response = app.router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let report: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!((report["imported"].as_u64(), &report["results"][0]["status"]), (Some(1), &json!("approved")));
+        let (_, bench6) = app.get(&format!("/api/devices/{}", report["results"][0]["device_id"].as_str().unwrap())).await;
+        assert_eq!(bench6["labels"], json!({ "rack": "b" }));
+
+        // Each import that reached its rows is kept with its counts; the rejected one is not
+        let (_, history) = app.get("/api/devices/imports").await;
+        let imports = history["imports"].as_array().unwrap();
+        assert_eq!(imports.len(), 2);
+        assert_eq!((&imports[0]["id"], &imports[0]["format"], &imports[0]["total"]), (&report["id"], &json!("csv"), &json!(1)));
+        assert_eq!((&imports[1]["format"], &imports[1]["total"], &imports[1]["failed"]), (&json!("json"), &json!(6), &json!(3)));
+    }
+
+    #[tokio::test]
+    async fn deleting_a_device_can_be_undone() {
+        let app = TestApp::new().await;
+        let (_, device) = app.post("/api/devices", json!({ "name": "rig", "ip": "192.0.2.30" })).await;
+        let id = device["id"].as_str().unwrap().to_string();
+        let approve = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"] });
+        app.post(&format!("/api/devices/{}/approve?verify=false", id), approve).await;
+        let (status, _) = app.request(Method::PATCH, &format!("/api/devices/{}/memory", id), Some(json!({ "memory_mb": 1024 }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let rev = app.get("/api/devices").await.1["rev"].as_i64().unwrap();
+
+        let (status, _) = app.request(Method::DELETE, &format!("/api/devices/{}", id), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.get(&format!("/api/devices/{}", id)).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(app.get("/api/devices").await.1["devices"], json!([]));
+        let (_, changes) = app.get(&format!("/api/devices?since_rev={}", rev)).await;
+        assert_eq!(changes["removed"], json!([id]));
+        let (_, all) = app.get("/api/devices?include_deleted=true").await;
+        assert!(all["devices"][0]["deleted_at"].is_string(), "{}", all);
+
+        // The manual add path is refused rather than making a new pending device
+        let (status, refused) = app.post("/api/devices", json!({ "name": "rig", "ip": "192.0.2.30" })).await;
+        assert_eq!((status, refused["device_id"].as_str()), (StatusCode::CONFLICT, Some(id.as_str())));
+
+        let (status, restored) = app.post(&format!("/api/devices/{}/restore", id), json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{}", restored);
+        assert_eq!((restored["status"].as_str(), restored["role_id"].as_str()), (Some("approved"), Some("role-user")));
+        assert_eq!((restored["allocated_memory_mb"].as_i64(), &restored["deleted_at"]), (Some(1024), &Value::Null));
+        assert_eq!(app.post(&format!("/api/devices/{}/restore", id), json!({})).await.0, StatusCode::CONFLICT);
+
+        let (status, _) = app.request(Method::DELETE, &format!("/api/devices/{}?purge=true", id), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(app.post(&format!("/api/devices/{}/restore", id), json!({})).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(app.get("/api/devices?include_deleted=true").await.1["devices"], json!([]));
+        assert_eq!(app.request(Method::DELETE, &format!("/api/devices/{}", id), None).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn device_detail_explains_its_allocation() {
+        let app = TestApp::new().await;
+        let (_, device) = app.post("/api/devices", json!({ "name": "rig", "ip": "192.0.2.11" })).await;
+        let id = device["id"].as_str().unwrap();
+        let uri = format!("/api/devices/{}", id);
+        let (_, pending) = app.get(&uri).await;
+        assert_eq!(pending["name"], "rig");
+        assert!(pending["detail"]["role"].is_null());
+        assert!(pending["detail"]["remaining_memory_mb"].is_null());
+        assert_eq!(pending["detail"]["allocations"], json!([]));
+
+        let approve = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"] });
+        app.post(&format!("{}/approve?verify=false", uri), approve).await;
+        for mb in [1024, 3072] {
+            let (status, _) = app.request(Method::PATCH, &format!("{}/memory", uri), Some(json!({ "memory_mb": mb }))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let (status, device) = app.get(&uri).await;
+        assert_eq!(status, StatusCode::OK);
+        // The flat fields are still there
+        assert_eq!((device["status"].clone(), device["allocated_memory_mb"].clone()), (json!("approved"), json!(3072)));
+        let detail = &device["detail"];
+        // A new grant replaces the last one
+        let allocations = detail["allocations"].as_array().unwrap();
+        assert_eq!(allocations.len(), 1);
+        assert_eq!(allocations[0]["memory_mb"], 3072);
+        assert_eq!(detail["by_provider"], json!([{ "provider": "system_ram", "memory_mb": 3072, "allocations": 1 }]));
+        assert_eq!(detail["role"]["name"], "user");
+        assert_eq!(detail["remaining_memory_mb"], 4096 - 3072);
+        assert!(detail["rpc"]["last_ready_at"].is_null());
+        assert!(detail["rpc"]["memory_read_secs_ago"].is_null());
+        let mut kinds: Vec<&str> = detail["recent_activity"].as_array().unwrap().iter().map(|a| a["kind"].as_str().unwrap()).collect();
+        kinds.sort();
+        assert_eq!(kinds, ["allocation_granted", "allocation_granted", "allocation_revoked"]);
+        let at = detail["recent_activity"][0]["at"].as_str().unwrap();
+        assert!(at.ends_with('Z') && !at.contains('.'), "{}", at);
+    }
+
+    #[tokio::test]
+    async fn approval_verifies_the_agent() {
+        let app = TestApp::new().await;
+        let rpc = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = rpc.local_addr().unwrap().port();
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let mut events = app.state.event_tx.subscribe_critical();
+        let approve = |ip: &'static str, port: u16, query: &'static str| {
+            let app = &app;
+            async move {
+                let (_, device) = app.post("/api/devices", json!({ "name": ip, "ip": ip })).await;
+                let uri = format!("/api/devices/{}", device["id"].as_str().unwrap());
+                app.request(Method::PATCH, &uri, Some(json!({ "rpc_port": port }))).await;
+                let body = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"] });
+                app.post(&format!("{}/approve{}", uri, query), body).await
+            }
+        };
+
+        let (status, approved) = approve("127.0.0.1", open_port, "").await;
+        assert_eq!(status, StatusCode::OK, "{}", approved);
+        assert_eq!(approved["verification"]["ok"], true);
+        assert_eq!(approved["rpc_status"], "ready");
+        let ready = loop {
+            match events.recv().await.unwrap() {
+                WsEvent::RpcDeviceReady { device_id, .. } => break device_id,
+                _ => continue,
+            }
+        };
+        assert_eq!(ready, approved["id"].as_str().unwrap());
+
+        // Approved all the same, with what failed
+        let (status, approved) = approve("127.0.0.2", closed_port, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(approved["status"], "approved");
+        assert_eq!(approved["verification"]["ok"], false);
+        assert_eq!(approved["verification"]["rpc_endpoints"][0]["address"], format!("127.0.0.2:{}", closed_port));
+        assert!(approved["verification"]["error"].as_str().unwrap().contains("didn't answer"));
+        assert_eq!(approved["rpc_status"], "offline");
+
+        let (status, approved) = approve("127.0.0.3", closed_port, "?verify=false").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(approved.get("verification").is_none());
+        drop(rpc);
+    }
+
+    #[tokio::test]
+    async fn memory_set_by_hand_outlasts_heartbeats() {
+        let app = TestApp::new().await;
+        let (_, device) = app.post("/api/devices", json!({ "name": "walled", "ip": "127.0.0.1" })).await;
+        let id = device["id"].as_str().unwrap().to_string();
+        let body = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"] });
+        let (_, approved) = app.post(&format!("/api/devices/{}/approve?verify=false", id), body).await;
+        let secret = approved["agent_secret"].as_str().unwrap().to_string();
+        let heartbeat = || {
+            let body = json!({
+                "device_id": id,
+                "memory_total_mb": 8192,
+                "memory_free_mb": 4096,
+                "rpc_port": 8181,
+                "rpc_running": false,
+            });
+            let local = SocketAddr::from(([127, 0, 0, 1], 40000));
+            let (app, secret) = (&app, secret.clone());
+            async move { app.request_as(local, Some(&secret), Method::POST, "/api/agent/heartbeat", Some(body)).await.0 }
+        };
+        let stats = format!("/api/devices/{}/memory-stats", id);
+        let set = |body: Value| app.request(Method::PATCH, &stats, Some(body));
+
+        let manual = json!({ "memory_total_mb": 16384, "memory_free_mb": 12000, "manual_override": true });
+        let (status, device) = set(manual.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", device);
+        assert_eq!(device["memory_manual_override"], true);
+        assert_eq!(heartbeat().await, StatusCode::OK);
+        let (_, device) = app.get(&format!("/api/devices/{}", id)).await;
+        assert_eq!((device["memory_total_mb"].clone(), device["memory_free_mb"].clone()), (json!(16384), json!(12000)));
+        let (_, cluster) = app.get("/api/cluster/status").await;
+        let listed = cluster["devices"].as_array().unwrap().iter().find(|d| d["id"] == id.as_str()).unwrap().clone();
+        assert_eq!(listed["memory_manual_override"], true);
+        assert_eq!(listed["memory_free_mb"], 12000);
+
+        let too_much = json!({ "memory_total_mb": 8192, "memory_free_mb": 9000, "manual_override": true });
+        assert_eq!(set(too_much).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(set(json!({ "manual_override": true })).await.0, StatusCode::BAD_REQUEST);
+        let missing = app.request(Method::PATCH, "/api/devices/nope/memory-stats", Some(manual)).await;
+        assert_eq!(missing.0, StatusCode::NOT_FOUND);
+
+        // Cleared: the next heartbeat's figures apply again
+        let (_, device) = set(json!({ "manual_override": false })).await;
+        assert_eq!(device["memory_manual_override"], false);
+        heartbeat().await;
+        let (_, device) = app.get(&format!("/api/devices/{}", id)).await;
+        assert_eq!((device["memory_total_mb"].clone(), device["memory_free_mb"].clone()), (json!(8192), json!(4096)));
+    }
+
+    #[tokio::test]
+    async fn agents_restart_their_rpc_server_on_a_heartbeat() {
+        let app = TestApp::new().await;
+        let rpc = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = rpc.local_addr().unwrap().port();
+        let (_, device) = app.post("/api/devices", json!({ "name": "rig", "ip": "127.0.0.1" })).await;
+        let id = device["id"].as_str().unwrap().to_string();
+        let restart = format!("/api/devices/{}/rpc/restart", id);
+        app.request(Method::PATCH, &format!("/api/devices/{}", id), Some(json!({ "rpc_port": port }))).await;
+
+        let (status, _) = app.post(&restart, json!({})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let body = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"] });
+        let (_, approved) = app.post(&format!("/api/devices/{}/approve", id), body).await;
+        let secret = approved["agent_secret"].as_str().unwrap().to_string();
+
+        let heartbeat = |can_restart: bool| {
+            let (app, id, secret) = (&app, id.clone(), secret.clone());
+            async move {
+                let body = json!({
+                    "device_id": id,
+                    "memory_total_mb": 8192,
+                    "memory_free_mb": 4096,
+                    "rpc_port": port,
+                    "rpc_running": true,
+                    "can_restart_rpc": can_restart,
+                });
+                let local = SocketAddr::from(([127, 0, 0, 1], 40000));
+                app.request_as(local, Some(&secret), Method::POST, "/api/agent/heartbeat", Some(body)).await.1
+            }
+        };
+        // Installed without the service: nothing to ask
+        heartbeat(false).await;
+        let (status, error) = app.post(&restart, json!({})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(error["error"].as_str().unwrap().contains("persistent-service"));
+
+        assert_eq!(heartbeat(true).await["restart_rpc"], false);
+        let ((status, restarted), _) = tokio::join!(app.post(&restart, json!({})), async {
+            while heartbeat(true).await["restart_rpc"] != true {
+                tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+            }
+        });
+        assert_eq!(status, StatusCode::OK, "{}", restarted);
+        assert_eq!(restarted["via"], "agent");
+        assert_eq!(restarted["rpc_port"], port);
+        let (_, device) = app.get(&format!("/api/devices/{}", id)).await;
+        assert_eq!(device["rpc_status"], "ready");
+        drop(rpc);
+    }
+
+    #[tokio::test]
+    async fn devices_are_found_by_notes_and_labels() {
+        let app = TestApp::new().await;
+        let (_, device) = app.post("/api/devices", json!({ "name": "dell", "ip": "192.168.1.42" })).await;
+        app.post("/api/devices", json!({ "name": "mac", "ip": "192.168.1.43" })).await;
+        let uri = format!("/api/devices/{}", device["id"].as_str().unwrap());
+
+        let edit = json!({ "notes": "Flaky PSU", "labels": { "Location": "basement" } });
+        let (status, updated) = app.request(Method::PATCH, &uri, Some(edit)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["notes"], "Flaky PSU");
+        assert_eq!(updated["labels"], json!({ "location": "basement" }));
+
+        for term in ["psu", "BASEMENT", "1.42"] {
+            let (_, list) = app.get(&format!("/api/devices?search={}", term)).await;
+            let names: Vec<_> = list["devices"].as_array().unwrap().iter().map(|d| d["name"].clone()).collect();
+            assert_eq!(names, vec![json!("dell")], "search {}", term);
+        }
+        let (_, list) = app.get("/api/devices?search=192.168").await;
+        assert_eq!(list["devices"].as_array().unwrap().len(), 2);
+
+        let bad = json!({ "labels": { "rack slot": "3" } });
+        assert_eq!(app.request(Method::PATCH, &uri, Some(bad)).await.0, StatusCode::BAD_REQUEST);
+        // Other edits leave the notes alone; an empty string clears them
+        let (_, updated) = app.request(Method::PATCH, &uri, Some(json!({ "name": "dell-basement" }))).await;
+        assert_eq!(updated["notes"], "Flaky PSU");
+        let (_, updated) = app.request(Method::PATCH, &uri, Some(json!({ "notes": "" }))).await;
+        assert!(updated["notes"].is_null());
+    }
+
+    #[tokio::test]
+    async fn settings_are_validated() {
+        let app = TestApp::new().await;
+        let put = |key: &str, value: &str| {
+            let uri = format!("/api/settings/{}", key);
+            let body = json!({ "value": value });
+            let app = &app;
+            async move { app.request(Method::PUT, &uri, Some(body)).await.0 }
+        };
+        assert_eq!(put("idle_timeout_minutes", "soon").await, StatusCode::BAD_REQUEST);
+        assert_eq!(put("no_such_setting", "1").await, StatusCode::BAD_REQUEST);
+        // The unauthenticated static root is chosen with FRONTEND_DIR only
+        assert_eq!(put("frontend_dir", "/").await, StatusCode::BAD_REQUEST);
+        // api_port reports the bound port; flags take only true or false
+        assert_eq!(put("api_port", "9000").await, StatusCode::BAD_REQUEST);
+        assert_eq!(put("trust_localhost", "yes").await, StatusCode::BAD_REQUEST);
+        assert_eq!(put("tls_fingerprint", "").await, StatusCode::OK);
+        assert_eq!(put("idle_timeout_minutes", "30").await, StatusCode::OK);
+        assert_eq!(app.state.settings.get("idle_timeout_minutes").as_deref(), Some("30"));
+        assert_eq!(put("default_role", "role-nope").await, StatusCode::BAD_REQUEST);
+        assert_eq!(put("default_role", "role-user").await, StatusCode::OK);
+        assert_eq!(put("bind_address", "192.0.2.1:8080").await, StatusCode::BAD_REQUEST);
+        assert_eq!(put("bind_address", "no-such-interface").await, StatusCode::BAD_REQUEST);
+
+        // Bound once at startup
+        let body = json!({ "value": "localhost" });
+        let (status, saved) = app.request(Method::PUT, "/api/settings/bind_address", Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(saved["restart_required"], true);
+    }
+
+    #[tokio::test]
+    async fn in_memory_databases_have_no_backups() {
+        let app = TestApp::new().await;
+        let (status, list) = app.get("/api/system/backups").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(list, json!({ "database": null, "backups": [], "keep": 3 }));
+        let restore = json!({ "name": "shared_memory.backup-20260101T120000Z.db" });
+        let (status, error) = app.post("/api/system/backups/restore", restore).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(error["error"].as_str().unwrap().contains("in memory"));
+    }
+
+    #[tokio::test]
+    async fn limits_apply_hot_or_after_a_restart() {
+        let app = TestApp::new().await;
+        let put = |key: &str, value: &str| {
+            let uri = format!("/api/settings/{}", key);
+            let body = json!({ "value": value });
+            let app = &app;
+            async move { app.request(Method::PUT, &uri, Some(body)).await }
+        };
+        assert_eq!(put("max_cluster_devices", "0").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(put("memory_headroom_pct", "80").await.0, StatusCode::BAD_REQUEST);
+
+        let (status, saved) = put("probe_timeout_ms", "500").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(saved["restart_required"], false);
+        assert_eq!(app.state.limits.get().probe_timeout_ms, 500);
+
+        let (_, saved) = put("ws_buffer", "4096").await;
+        assert_eq!(saved["restart_required"], true);
+        assert_eq!(app.state.limits.get().ws_buffer, 1024);
+
+        put("trust_localhost", "true").await;
+        let (_, settings) = app.get("/api/settings").await;
+        assert_eq!(settings["ws_buffer"], "4096");
+        let limits = settings["limits"].as_array().unwrap();
+        let limit = |key: &str| limits.iter().find(|l| l["key"] == key).unwrap().clone();
+        assert_eq!(limit("ws_buffer")["value"], 1024);
+        assert_eq!(limit("ws_buffer")["restart_required"], 4096);
+        assert_eq!(limit("probe_timeout_ms")["value"], 500);
+        assert!(limit("probe_timeout_ms")["restart_required"].is_null());
+        assert_eq!(limit("proxy_timeout_secs")["source"], "default");
+
+        // The device cap applies to the next start
+        assert_eq!(put("max_cluster_devices", "1").await.0, StatusCode::OK);
+        let start = json!({ "model_path": "/models/m.gguf", "device_ids": ["a", "b"] });
+        let (status, body) = app.post("/api/cluster/inference/start", start).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Too many device IDs (max 1)");
+    }
+
+    #[tokio::test]
+    async fn viewer_keys_only_read_the_dashboard() {
+        let app = TestApp::new().await;
+        let (status, created) = app
+            .post("/api/keys", json!({ "name": "wall tablet", "scope": "viewer" }))
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["scope"], "viewer");
+        let key = created["key"].as_str().unwrap().to_string();
+        let body = json!({ "value": "true" });
+        let (status, _) = app.request(Method::PUT, "/api/settings/require_dashboard_auth", Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let tablet = SocketAddr::from(([192, 0, 2, 50], 50000));
+        let viewer = Some(key.as_str());
+        let anonymous = app.request_as(tablet, None, Method::GET, "/api/devices", None).await;
+        assert_eq!(anonymous.0, StatusCode::UNAUTHORIZED);
+        let health = app.request_as(tablet, None, Method::GET, "/api/health", None).await;
+        assert_eq!(health.0, StatusCode::OK);
+        let devices = app.request_as(tablet, viewer, Method::GET, "/api/devices", None).await;
+        assert_eq!(devices.0, StatusCode::OK);
+
+        let (status, body) = app
+            .request_as(tablet, viewer, Method::POST, "/api/devices/d1/approve", None)
+            .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["missing_scope"], "admin");
+        let keys = app.request_as(tablet, viewer, Method::GET, "/api/keys", None).await;
+        assert_eq!(keys.0, StatusCode::FORBIDDEN);
+
+        // This host needs a key too, unless trust_localhost is on
+        assert_eq!(app.get("/api/keys").await.0, StatusCode::UNAUTHORIZED);
+        app.state.settings.set("trust_localhost", "true").await.unwrap();
+        assert_eq!(app.get("/api/keys").await.0, StatusCode::OK);
+
+        // ...and not for requests a reverse proxy on this host passes on
+        let proxied = Request::get("/api/keys")
+            .header("x-forwarded-for", "198.51.100.7")
+            .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.router.clone().oneshot(proxied).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn settings_keep_secrets_from_everyone_and_internals_from_strangers() {
+        let app = TestApp::new().await;
+        for (key, value) in [
+            ("mdns_enabled", "false"),
+            ("backend_type", "openai"),
+            ("backend_url", "http://10.0.0.7:8000"),
+            ("backend_api_key", "sk-upstream-0123456789"),
+            ("inference_api_key", "inference-key-0123456789"),
+            ("agent_enrollment_token", "enroll-0123456789abcdef"),
+        ] {
+            let (status, _) = app.request(Method::PUT, &format!("/api/settings/{}", key), Some(json!({ "value": value }))).await;
+            assert_eq!(status, StatusCode::OK, "{}", key);
+        }
+        let (_, created) = app.post("/api/keys", json!({ "name": "ops", "scope": "admin" })).await;
+        let admin_key = created["key"].as_str().unwrap().to_string();
+        let (_, created) = app.post("/api/keys", json!({ "name": "wall tablet", "scope": "viewer" })).await;
+        let viewer_key = created["key"].as_str().unwrap().to_string();
+
+        let lan = SocketAddr::from(([192, 0, 2, 60], 50000));
+        for token in [None, Some(viewer_key.as_str()), Some("sk-sllm-not-a-key")] {
+            let (status, settings) = app.request_as(lan, token, Method::GET, "/api/settings", None).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!((settings["mdns_enabled"].clone(), settings["backend_type"].clone()), (json!("false"), json!("openai")));
+            for hidden in ["backend_url", "backend_api_key_set", "inference_api_key_set", "agent_enrollment_token_set"] {
+                assert!(settings.get(hidden).is_none(), "{} shown to {:?}", hidden, token);
+            }
+            assert_eq!(settings["limits"], json!([]));
+            let body = settings.to_string();
+            assert!(!body.contains("0123456789") && !body.contains("10.0.0.7"), "{}", body);
+        }
+
+        // Admins get everything but the secrets themselves
+        app.request(Method::PUT, "/api/settings/trust_localhost", Some(json!({ "value": "true" }))).await;
+        let local = app.get("/api/settings").await.1;
+        let remote_admin = app.request_as(lan, Some(&admin_key), Method::GET, "/api/settings", None).await.1;
+        for settings in [local, remote_admin] {
+            assert_eq!(settings["backend_url"], "http://10.0.0.7:8000");
+            assert_eq!(settings["backend_api_key_set"], true);
+            assert_eq!(settings["agent_enrollment_token_set"], true);
+            assert!(!settings["limits"].as_array().unwrap().is_empty());
+            assert!(!settings.to_string().contains("0123456789"));
+        }
+    }
+
+    #[tokio::test]
+    async fn role_crud() {
+        let app = TestApp::new().await;
+        let role = json!({ "name": "lab", "max_memory_mb": 2048, "can_pull_models": false, "trust_level": 2 });
+        let (status, created) = app.post("/api/permissions/roles", role).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uri = format!("/api/permissions/roles/{}", created["id"].as_str().unwrap());
+
+        let role = json!({ "name": "lab", "max_memory_mb": 4096, "can_pull_models": true, "trust_level": 2 });
+        let (status, updated) = app.request(Method::PUT, &uri, Some(role)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["max_memory_mb"], 4096);
+        let (_, list) = app.get("/api/permissions/roles").await;
+        assert!(list["roles"].as_array().unwrap().iter().any(|r| r["name"] == "lab"));
+
+        assert_eq!(app.request(Method::DELETE, &uri, None).await.0, StatusCode::OK);
+        let (_, list) = app.get("/api/permissions/roles").await;
+        assert!(!list["roles"].as_array().unwrap().iter().any(|r| r["name"] == "lab"));
+        let builtin = app.request(Method::DELETE, "/api/permissions/roles/role-guest", None).await;
+        assert_eq!(builtin.0, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn model_check_reads_the_file_and_stub_memory() {
+        let app = TestApp::new().await;
+        let path = std::env::temp_dir().join(format!("sharedllm-{}.gguf", uuid::Uuid::new_v4()));
+        std::fs::write(&path, vec![0u8; 3 * 1024 * 1024]).unwrap();
+
+        let (status, analysis) = app.get(&format!("/api/cluster/model-check?path={}", path.display())).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(status, StatusCode::OK, "{}", analysis);
+        assert_eq!(analysis["model_size_mb"], 3);
+        assert_eq!(analysis["fit_status"], "fits_locally");
+
+        let (status, _) = app.get("/api/cluster/model-check?path=/etc/passwd.gguf").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn model_files_are_shared_with_ranges() {
+        let app = TestApp::new().await;
+        let path = std::env::temp_dir().join(format!("sharedllm-{}.gguf", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"GGUF shared model bytes").unwrap();
+        let alias = crate::db::models::ModelAlias {
+            alias: "shared".into(),
+            path: path.display().to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        crate::db::queries::insert_model_alias(&app.state.pool, &alias).await.unwrap();
+
+        assert_eq!(app.get("/api/models/files").await.0, StatusCode::FORBIDDEN);
+        let body = json!({ "value": "true" });
+        let (status, _) = app.request(Method::PUT, "/api/settings/share_models", Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        let stranger = SocketAddr::from(([192, 0, 2, 60], 50000));
+        let refused = app.request_as(stranger, None, Method::GET, "/api/models/files", None).await;
+        assert_eq!(refused.0, StatusCode::FORBIDDEN);
+
+        let (status, listing) = app.get("/api/models/files").await;
+        assert_eq!(status, StatusCode::OK);
+        let file = &listing["files"][0];
+        assert_eq!(file["alias"], "shared");
+        assert_eq!(file["size"], 23);
+        assert_eq!(file["sha256"], crate::api::model_files::hash_file(&path).unwrap());
+
+        let addr = app.serve().await;
+        let resp = reqwest::Client::new()
+            .get(format!("http://{}/api/models/files/shared", addr))
+            .header("range", "bytes=5-10")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.bytes().await.unwrap().as_ref(), b"shared");
+
+        let body = json!({ "peer_device_id": "nope", "alias": "other" });
+        assert_eq!(app.post("/api/models/fetch-from-peer", body).await.0, StatusCode::NOT_FOUND);
+        let body = json!({ "peer_device_id": "nope", "alias": "shared" });
+        assert_eq!(app.post("/api/models/fetch-from-peer", body).await.0, StatusCode::CONFLICT);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn models_are_uploaded_in_chunks() {
+        let app = TestApp::new().await;
+        let dir = std::env::temp_dir().join(format!("sharedllm-uploads-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Uploads go beside the first aliased model
+        let base = dir.join("base.gguf");
+        std::fs::write(&base, b"GGUF").unwrap();
+        let alias = crate::db::models::ModelAlias {
+            alias: "base".into(),
+            path: base.display().to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        crate::db::queries::insert_model_alias(&app.state.pool, &alias).await.unwrap();
+        let mut events = app.state.event_tx.subscribe_critical();
+
+        let init = json!({ "file_name": "tiny.gguf", "size": 12, "alias": "tiny" });
+        let (status, upload) = app.post("/api/models/upload", init).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", upload);
+        let uri = format!("/api/models/upload/{}", upload["upload_id"].as_str().unwrap());
+        let too_big = json!({ "file_name": "huge.gguf", "size": 1u64 << 50 });
+        assert_eq!(app.post("/api/models/upload", too_big).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+        let not_gguf = json!({ "file_name": "notes.txt", "size": 12 });
+        assert_eq!(app.post("/api/models/upload", not_gguf).await.0, StatusCode::BAD_REQUEST);
+
+        let addr = app.serve().await;
+        let client = reqwest::Client::new();
+        let put = |offset: u64, bytes: &'static [u8]| {
+            client
+                .put(format!("http://{}{}/chunk?offset={}", addr, uri, offset))
+                .body(bytes)
+                .send()
+        };
+        assert_eq!(put(0, b"GGUF\x03\x00").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(app.post(&format!("{}/finalize", uri), json!({})).await.0, StatusCode::CONFLICT);
+        // A retried chunk learns where to resume
+        let retried = put(0, b"GGUF\x03\x00").await.unwrap();
+        assert_eq!(retried.status(), StatusCode::CONFLICT);
+        assert_eq!(retried.json::<Value>().await.unwrap()["offset"], 6);
+        assert_eq!(put(6, b"\x00\x00\x00\x00\x00\x00").await.unwrap().status(), StatusCode::OK);
+        let (_, progress) = app.get(&uri).await;
+        assert_eq!((progress["received"].clone(), progress["complete"].clone()), (json!(12), json!(true)));
+
+        let (status, done) = app.post(&format!("{}/finalize", uri), json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{}", done);
+        let path = dir.join("tiny.gguf");
+        assert_eq!(done["path"], path.display().to_string());
+        assert_eq!(std::fs::read(&path).unwrap().len(), 12);
+        let (_, aliases) = app.get("/api/models/aliases").await;
+        assert!(aliases.to_string().contains("tiny"));
+        let available = loop {
+            if let WsEvent::ModelAvailable { alias, .. } = events.recv().await.unwrap() {
+                break alias;
+            }
+        };
+        assert_eq!(available.as_deref(), Some("tiny"));
+        assert_eq!(app.get(&uri).await.0, StatusCode::NOT_FOUND);
+
+        let (_, fake) = app.post("/api/models/upload", json!({ "file_name": "fake.gguf", "size": 4 })).await;
+        let uri = format!("/api/models/upload/{}", fake["upload_id"].as_str().unwrap());
+        let resp = client.put(format!("http://{}{}/chunk?offset=0", addr, uri)).body("NOPE").send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(app.post(&format!("{}/finalize", uri), json!({})).await.0, StatusCode::BAD_REQUEST);
+        assert!(!dir.join("fake.gguf").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overview_counts_devices_and_sessions() {
+        let app = TestApp::new().await;
+        for ip in ["192.0.2.20", "192.0.2.21"] {
+            let (status, _) = app.post("/api/devices", json!({ "name": ip, "ip": ip })).await;
+            assert_eq!(status, StatusCode::CREATED);
+        }
+        app.state.memory.refresh_all().await;
+
+        let (status, overview) = app.get("/api/overview").await;
+        assert_eq!(status, StatusCode::OK, "{}", overview);
+        assert_eq!(overview["devices"]["total"], 2);
+        assert_eq!(overview["devices"]["pending"], 2);
+        assert_eq!(overview["pending_approvals"], 2);
+        assert_eq!(overview["memory"]["total_mb"], STUB_TOTAL_MB);
+        assert_eq!(overview["memory"]["providers"], 1);
+        assert_eq!(overview["inference"]["running"], false);
+        assert_eq!(overview["ollama"]["running"], false);
+        assert_eq!(overview["ollama"]["resident_mb"], 0);
+        assert_eq!(overview["warnings"], json!([]));
+        assert_eq!(app.get("/api/overview?model=no-such-alias").await.0, StatusCode::BAD_REQUEST);
+
+        let event = crate::overview::compute(&app.state).await.unwrap().event();
+        assert!(matches!(event, WsEvent::Overview { pending_approvals: 2, .. }));
+    }
+
+    #[tokio::test]
+    async fn rpc_server_starts_through_the_spawner() {
+        let app = TestApp::new().await;
+        let (status, started) = app.post("/api/cluster/rpc/start", json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+        let port = app.state.llama_cpp.rpc_port.to_string();
+        let line = &app.spawner.started()[0];
+        assert_eq!(line[0], "llama-rpc-server");
+        assert!(line.windows(2).any(|w| w == ["--port", port.as_str()]));
+        assert!(app.state.llama_cpp.get_status().await.rpc_server_running);
+
+        assert_eq!(app.post("/api/cluster/rpc/stop", json!({})).await.0, StatusCode::OK);
+        assert!(!app.state.llama_cpp.get_status().await.rpc_server_running);
+    }
+
+    #[tokio::test]
+    async fn images_need_a_session_with_a_projector() {
+        let app = TestApp::new().await;
+        let dir = std::env::temp_dir().join(format!("sharedllm-llava-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let model = dir.join("llava-7b.gguf").display().to_string();
+        let mmproj = dir.join("mmproj-llava-7b-f16.gguf").display().to_string();
+        std::fs::write(&model, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        std::fs::write(&mmproj, b"GGUF").unwrap();
+
+        let (status, started) = app.post("/api/cluster/inference/start", json!({ "model_path": model })).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+        assert_eq!(started["session"]["supports_vision"], false);
+        let image = json!({ "type": "image_url", "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" } });
+        let chat = json!({ "model": "llava-7b", "messages": [{ "role": "user", "content": [image] }] });
+        let (status, refused) = app.post("/v1/chat/completions", chat).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(refused["error"]["code"], "vision_not_supported");
+
+        let elsewhere = std::env::temp_dir().join("mmproj-llava-7b-f16.gguf").display().to_string();
+        let start = json!({ "model_path": model, "mmproj_path": elsewhere });
+        assert_eq!(app.post("/api/cluster/inference/start", start).await.0, StatusCode::BAD_REQUEST);
+
+        let start = json!({ "model_path": model, "mmproj_path": mmproj });
+        let (status, started) = app.post("/api/cluster/inference/start", start).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+        assert_eq!(started["session"]["supports_vision"], true);
+        let line = app.spawner.started().pop().unwrap();
+        assert!(line.windows(2).any(|w| w == ["--mmproj", mmproj.as_str()]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn chat_template_and_system_prompt_reach_the_session() {
+        let app = TestApp::new().await;
+        let model = std::env::temp_dir().join(format!("sharedllm-template-{}.gguf", uuid::Uuid::new_v4()));
+        std::fs::write(&model, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        let model = model.display().to_string();
+
+        let start = json!({ "model_path": model, "chat_template": "/tmp/template.jinja" });
+        assert_eq!(app.post("/api/cluster/inference/start", start).await.0, StatusCode::BAD_REQUEST);
+
+        let start = json!({
+            "model_path": model,
+            "chat_template": "Llama3",
+            "system_prompt": " Answer in French. ",
+            "dry_run": true,
+        });
+        let (status, plan) = app.post("/api/cluster/inference/start", start.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", plan);
+        assert_eq!(plan["chat_template"], "llama3");
+        assert_eq!(plan["system_prompt"], "Answer in French.");
+        let args: Vec<&str> = plan["plan"]["args"].as_array().unwrap().iter().filter_map(Value::as_str).collect();
+        assert!(args.windows(2).any(|w| w == ["--chat-template", "llama3"]));
+
+        let mut start = start;
+        start["dry_run"] = json!(false);
+        let (status, started) = app.post("/api/cluster/inference/start", start).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+        assert_eq!(started["session"]["system_prompt"], "Answer in French.");
+        assert_eq!(started["session"]["chat_template"], "llama3");
+        std::fs::remove_file(&model).unwrap();
+    }
+
+    #[tokio::test]
+    async fn llama_server_only_answers_with_the_session_key() {
+        let app = TestApp::new().await;
+        let model = std::env::temp_dir().join(format!("sharedllm-key-{}.gguf", uuid::Uuid::new_v4()));
+        std::fs::write(&model, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        let model = model.display().to_string();
+
+        let (status, started) = app.post("/api/cluster/inference/start", json!({ "model_path": model })).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+        let line = app.spawner.started().pop().unwrap();
+        let key = line.windows(2).find(|w| w[0] == "--api-key").map(|w| w[1].clone()).unwrap();
+        let shown = started["session"]["api_key"].as_str().unwrap();
+        assert_eq!(shown, format!("****{}", &key[key.len() - 4..]));
+
+        // A stand-in llama-server that wants the key, as the real one does
+        let expected = format!("Bearer {}", key);
+        let llama = Router::new().route(
+            "/v1/chat/completions",
+            axum::routing::post(move |headers: axum::http::HeaderMap| async move {
+                if headers.get("authorization").is_some_and(|v| v == expected.as_str()) {
+                    (StatusCode::OK, axum::Json(json!({ "choices": [] })))
+                } else {
+                    (StatusCode::UNAUTHORIZED, axum::Json(json!({ "error": "Invalid API Key" })))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", app.state.llama_cpp.inference_port)).await.unwrap();
+        tokio::spawn(async move { axum::serve(listener, llama).await });
+
+        let name = std::path::Path::new(&model).file_stem().unwrap().to_str().unwrap();
+        let chat = json!({ "model": name, "messages": [{ "role": "user", "content": "hi" }] });
+        let (status, reply) = app.post("/v1/chat/completions", chat.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", reply);
+        let direct = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", app.state.llama_cpp.inference_base_url()))
+            .json(&chat)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(direct.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        // A configured key is used instead, and never shown whole
+        let put = |value: &str| app.request(Method::PUT, "/api/settings/inference_api_key", Some(json!({ "value": value })));
+        assert_eq!(put("short").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(put("configured-key-0123456789").await.0, StatusCode::OK);
+        app.request(Method::PUT, "/api/settings/trust_localhost", Some(json!({ "value": "true" }))).await;
+        let (_, settings) = app.get("/api/settings").await;
+        assert_eq!(settings["inference_api_key_set"], true);
+        assert!(settings.get("inference_api_key").is_none());
+        let start = json!({ "model_path": model, "dry_run": true });
+        let (_, plan) = app.post("/api/cluster/inference/start", start).await;
+        let args: Vec<&str> = plan["plan"]["args"].as_array().unwrap().iter().filter_map(Value::as_str).collect();
+        assert!(args.windows(2).any(|w| w == ["--api-key", "****6789"]), "{:?}", args);
+        std::fs::remove_file(&model).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ollama_native_mode_speaks_openai_both_ways() {
+        let app = TestApp::new().await;
+        let seen = Arc::new(std::sync::Mutex::new(Vec::<Value>::new()));
+        let log = seen.clone();
+        let ollama = Router::new().route(
+            "/api/chat",
+            axum::routing::post(move |axum::Json(body): axum::Json<Value>| {
+                log.lock().unwrap().push(body.clone());
+                async move {
+                    let line = |content: &str, done: bool| {
+                        let mut l = json!({ "model": body["model"], "message": { "role": "assistant", "content": content }, "done": done });
+                        if done {
+                            l["done_reason"] = json!("stop");
+                            l["prompt_eval_count"] = json!(5);
+                            l["eval_count"] = json!(2);
+                        }
+                        l
+                    };
+                    if body["stream"] == true {
+                        format!("{}\n{}\n{}\n", line("Hi", false), line(" there", false), line("", true))
+                    } else {
+                        line("Hi there", true).to_string()
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, ollama).await });
+        for (key, value) in [("backend_type", "ollama"), ("backend_url", url.as_str()), ("ollama_native_mode", "true")] {
+            let uri = format!("/api/settings/{}", key);
+            assert_eq!(app.request(Method::PUT, &uri, Some(json!({ "value": value }))).await.0, StatusCode::OK);
+        }
+
+        let chat = json!({ "model": "llama3", "messages": [{ "role": "user", "content": "hi" }], "max_tokens": 8, "num_ctx": 4096 });
+        let (status, reply) = app.post("/v1/chat/completions", chat.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", reply);
+        assert_eq!(reply["object"], "chat.completion");
+        assert_eq!(reply["choices"][0]["message"]["content"], "Hi there");
+        assert_eq!(reply["choices"][0]["finish_reason"], "stop");
+        assert_eq!(reply["usage"]["total_tokens"], 7);
+        let sent = seen.lock().unwrap()[0].clone();
+        assert_eq!(sent["options"], json!({ "num_predict": 8, "num_ctx": 4096 }));
+        assert_eq!(sent["stream"], false);
+
+        let addr = app.serve().await;
+        let mut streamed = chat.clone();
+        streamed["stream"] = json!(true);
+        let resp = reqwest::Client::new()
+            .post(format!("http://{}/v1/chat/completions", addr))
+            .json(&streamed)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["content-type"], "text/event-stream");
+        let sse = resp.text().await.unwrap();
+        let chunks: Vec<Value> = sse
+            .split("\n\n")
+            .filter_map(|e| e.strip_prefix("data: "))
+            .filter(|d| *d != "[DONE]")
+            .map(|d| serde_json::from_str(d).unwrap())
+            .collect();
+        let text: String = chunks.iter().filter_map(|c| c["choices"][0]["delta"]["content"].as_str()).collect();
+        assert_eq!(text, "Hi there");
+        assert_eq!(chunks.last().unwrap()["choices"][0]["finish_reason"], "stop");
+        assert!(sse.ends_with("data: [DONE]\n\n"));
+
+        // Nothing is dropped silently
+        let mut with_tools = chat;
+        with_tools["tools"] = json!([{ "type": "function", "function": { "name": "lookup" } }]);
+        let (status, error) = app.post("/v1/chat/completions", with_tools).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(error["error"]["code"], "unsupported_by_ollama_native");
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_pulls_end_with_a_code_and_are_recorded() {
+        let app = TestApp::new().await;
+        let ollama = Router::new().route(
+            "/api/pull",
+            axum::routing::post(|axum::Json(body): axum::Json<Value>| async move {
+                let lines = |lines: &[Value]| lines.iter().map(|l| format!("{}\n", l)).collect::<String>();
+                let manifest = json!({ "status": "pulling manifest" });
+                let layer = json!({ "status": "pulling 6a0746a1ec1a", "digest": "sha256:6a0746a1ec1a", "total": 100, "completed": 40 });
+                match body["name"].as_str().unwrap_or("") {
+                    "ghost" => (StatusCode::OK, lines(&[manifest, json!({ "error": "pull model manifest: file does not exist" })])),
+                    "huge" => (
+                        StatusCode::OK,
+                        lines(&[manifest, layer, json!({ "error": "write /root/.ollama/models/blobs/sha256-6a07-partial: no space left on device" })]),
+                    ),
+                    "flaky" => (StatusCode::OK, lines(&[manifest, layer])),
+                    "gone" => (StatusCode::NOT_FOUND, json!({ "error": "model 'gone' not found" }).to_string()),
+                    _ => (StatusCode::OK, lines(&[manifest, json!({ "status": "success" })])),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, ollama).await });
+        app.state.ollama.reconfigure(url, false).await.unwrap();
+
+        let addr = app.serve().await;
+        let pull = |name: &str| {
+            reqwest::Client::new()
+                .post(format!("http://{}/api/models/pull", addr))
+                .json(&json!({ "name": name }))
+                .send()
+        };
+        let last_line = |body: &str| -> Value { serde_json::from_str(body.lines().last().unwrap()).unwrap() };
+
+        let ok = pull("tinyllama").await.unwrap().text().await.unwrap();
+        assert_eq!(last_line(&ok), json!({ "status": "success" }));
+        for (name, code, lines) in [("ghost", "not_found", 2), ("huge", "disk_full", 3), ("flaky", "network", 3)] {
+            let resp = pull(name).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = resp.text().await.unwrap();
+            assert_eq!(body.lines().count(), lines, "{}", body);
+            let end = last_line(&body);
+            assert_eq!((end["code"].as_str(), end["done"].as_bool()), (Some(code), Some(true)), "{}", body);
+        }
+        let resp = pull("gone").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.json::<Value>().await.unwrap()["code"], "not_found");
+
+        // Names Ollama would reject never reach it
+        for name in ["../llama3", "llama3:", "a b"] {
+            assert_eq!(app.post("/api/models/pull", json!({ "name": name })).await.0, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let (_, queue) = app.get("/api/models/pull/queue").await;
+        let history = &queue["jobs"];
+        let recorded: std::collections::HashMap<&str, (&str, &Value)> = history
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| (p["name"].as_str().unwrap(), (p["status"].as_str().unwrap(), &p["error_code"])))
+            .collect();
+        assert_eq!(recorded.len(), 5);
+        assert_eq!(recorded["tinyllama"], ("completed", &Value::Null));
+        assert_eq!(recorded["huge"], ("failed", &json!("disk_full")));
+        assert_eq!(recorded["flaky"], ("failed", &json!("network")));
+        assert_eq!(recorded["gone"], ("failed", &json!("not_found")));
+        let huge = history.as_array().unwrap().iter().find(|p| p["name"] == "huge").unwrap();
+        assert_eq!((huge["completed_bytes"].as_i64(), huge["total_bytes"].as_i64()), (Some(40), Some(100)));
+    }
+
+    #[tokio::test]
+    async fn models_in_use_stay_and_others_go_to_the_trash() {
+        let app = TestApp::new().await;
+        let dir = std::env::temp_dir().join(format!("sharedllm-store-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (running, old) = (dir.join("running.gguf"), dir.join("old.gguf"));
+        std::fs::write(&running, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        std::fs::write(&old, vec![0u8; 1024 * 1024]).unwrap();
+        let (running, old) = (running.display().to_string(), old.display().to_string());
+        let alias = crate::db::models::ModelAlias {
+            alias: "old".into(),
+            path: old.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+        };
+        crate::db::queries::insert_model_alias(&app.state.pool, &alias).await.unwrap();
+        crate::db::queries::record_session_start(&app.state.pool, "s-old", &old, &[]).await.unwrap();
+        crate::db::queries::record_session_end(&app.state.pool, "s-old", "stopped").await.unwrap();
+        let (status, started) = app.post("/api/cluster/inference/start", json!({ "model_path": running })).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+
+        let (status, usage) = app.get("/api/cluster/models/usage").await;
+        assert_eq!(status, StatusCode::OK, "{}", usage);
+        let listed = usage["dirs"].as_array().unwrap().iter().find(|d| d["path"] == dir.display().to_string()).unwrap();
+        assert_eq!(listed["total_bytes"], 3 * 1024 * 1024);
+        let (first, second) = (&listed["files"][0], &listed["files"][1]);
+        assert_eq!((first["path"].as_str(), first["in_use"].as_bool()), (Some(running.as_str()), Some(true)));
+        assert_eq!(second["aliases"], json!(["old"]));
+        assert!(second["last_used_at"].is_string());
+
+        let delete = |path: &str| format!("/api/cluster/models?path={}", path);
+        assert_eq!(app.request(Method::DELETE, &delete(&running), None).await.0, StatusCode::CONFLICT);
+        assert_eq!(app.request(Method::DELETE, &delete("/etc/passwd.gguf"), None).await.0, StatusCode::BAD_REQUEST);
+        let mut events = app.state.event_tx.subscribe_critical();
+        let (status, deleted) = app.request(Method::DELETE, &delete(&old), None).await;
+        assert_eq!(status, StatusCode::OK, "{}", deleted);
+        assert_eq!(deleted["aliases"], json!(["old"]));
+        assert!(!std::path::Path::new(&old).exists());
+        assert!(matches!(events.recv().await.unwrap(), WsEvent::ModelRemoved { .. }));
+        let (_, usage) = app.get("/api/cluster/models/usage").await;
+        assert_eq!(usage["trash_bytes"], 1024 * 1024);
+
+        app.post("/api/cluster/inference/stop", json!({})).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn lora_adapters_become_flags_and_are_capped() {
+        let app = TestApp::new().await;
+        let dir = std::env::temp_dir().join(format!("sharedllm-lora-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let model = dir.join("base.Q4_K_M.gguf");
+        std::fs::write(&model, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        let adapter = dir.join("style-lora.gguf");
+        std::fs::write(&adapter, vec![0u8; 1024 * 1024]).unwrap();
+        let (model, adapter) = (model.display().to_string(), adapter.display().to_string());
+
+        let start = json!({
+            "model_path": model,
+            "lora_adapters": [{ "path": adapter }, { "path": adapter, "scale": 0.5 }],
+            "dry_run": true,
+        });
+        let (status, plan) = app.post("/api/cluster/inference/start", start).await;
+        assert_eq!(status, StatusCode::OK, "{}", plan);
+        assert_eq!(plan["lora_adapters"][1]["scale"], 0.5);
+        let args: Vec<&str> = plan["plan"]["args"].as_array().unwrap().iter().filter_map(Value::as_str).collect();
+        assert!(args.windows(2).any(|w| w == ["--lora", adapter.as_str()]));
+        assert!(args.windows(3).any(|w| w == ["--lora-scaled", adapter.as_str(), "0.5"]));
+
+        let five = vec![json!({ "path": adapter }); 5];
+        let start = json!({ "model_path": model, "lora_adapters": five, "dry_run": true });
+        assert_eq!(app.post("/api/cluster/inference/start", start).await.0, StatusCode::BAD_REQUEST);
+
+        app.post("/api/models/aliases", json!({ "alias": "base", "path": model })).await;
+        let (_, listed) = app.get("/api/models/aliases").await;
+        let adapters = listed["adapters"].as_array().unwrap();
+        assert!(adapters.iter().any(|a| a["path"] == adapter.as_str() && a["size_mb"] == 1));
+        assert!(!adapters.iter().any(|a| a["path"] == model.as_str()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn overlapping_starts_launch_llama_server_once() {
+        let app = TestApp::new().await;
+        let model = std::env::temp_dir().join(format!("sharedllm-race-{}.gguf", uuid::Uuid::new_v4()));
+        std::fs::write(&model, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        let model = model.display().to_string();
+        // Takes connections and never answers, so the first start waits in its probe
+        let wedged = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (_, device) = app.post("/api/devices", json!({ "name": "wedged", "ip": "127.0.0.1" })).await;
+        let id = device["id"].as_str().unwrap().to_string();
+        let uri = format!("/api/devices/{}", id);
+        let port = wedged.local_addr().unwrap().port();
+        app.request(Method::PATCH, &uri, Some(json!({ "rpc_port": port }))).await;
+        let body = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"], "verify": false });
+        assert_eq!(app.post(&format!("{}/approve", uri), body).await.0, StatusCode::OK);
+        let timeout = json!({ "value": "1000" });
+        app.request(Method::PUT, "/api/settings/probe_timeout_ms", Some(timeout)).await;
+
+        let start = |key: &str| {
+            let body = json!({ "model_path": model, "device_ids": [id], "skip_unreachable": true });
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("/api/cluster/inference/start")
+                .header("content-type", "application/json")
+                .header("idempotency-key", key)
+                .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))))
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            app.router.clone().oneshot(request)
+        };
+        let first = tokio::spawn(start("click-1"));
+        while !app.state.llama_cpp.get_status().await.starting {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        // The double-click waits for the first; a different start is refused
+        let (again, other) = tokio::join!(start("click-1"), start("click-2"));
+        let (first, again, other) = (first.await.unwrap().unwrap(), again.unwrap(), other.unwrap());
+        assert_eq!(other.status(), StatusCode::CONFLICT);
+        assert_eq!((first.status(), again.status()), (StatusCode::OK, StatusCode::OK));
+        assert!(first.headers().get("idempotent-replayed").is_none());
+        assert_eq!(again.headers()["idempotent-replayed"], "true");
+        let body = |r: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(r.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<Value>(&bytes).unwrap()
+        };
+        let (first, again) = (body(first).await, body(again).await);
+        assert_eq!(first["session"]["id"], again["session"]["id"]);
+        assert_eq!(first["devices"][0]["reachable"], false);
+        assert_eq!(app.spawner.started().len(), 1);
+        assert!(!app.state.llama_cpp.get_status().await.starting);
+
+        // A new key starts again
+        let start = json!({ "model_path": model, "idempotency_key": "click-3" });
+        let (status, started) = app.post("/api/cluster/inference/start", start).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+        assert_ne!(started["session"]["id"], first["session"]["id"]);
+        assert_eq!(app.spawner.started().len(), 2);
+        std::fs::remove_file(&model).unwrap();
+        drop(wedged);
+    }
+
+    #[tokio::test]
+    async fn unreliable_devices_are_left_out_of_tags() {
+        let app = TestApp::new().await;
+        let port = crate::llama_cpp::rpc_probe::stub_server().await.port();
+        let model = std::env::temp_dir().join(format!("sharedllm-health-{}.gguf", uuid::Uuid::new_v4()));
+        std::fs::write(&model, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        let model = model.display().to_string();
+
+        let (_, device) = app.post("/api/devices", json!({ "name": "wifi-box", "ip": "127.0.0.1" })).await;
+        let id = device["id"].as_str().unwrap().to_string();
+        let uri = format!("/api/devices/{}", id);
+        app.request(Method::PATCH, &uri, Some(json!({ "rpc_port": port }))).await;
+        app.request(Method::PATCH, &format!("{}/tags", uri), Some(json!({ "tags": ["lab"] }))).await;
+        let body = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"], "verify": false });
+        assert_eq!(app.post(&format!("{}/approve", uri), body).await.0, StatusCode::OK);
+        for _ in 0..3 {
+            let reason = crate::llama_cpp::health::HealthReason::EarlyCrash;
+            crate::db::queries::adjust_device_health(&app.state.pool, &id, -20, 100, reason).await.unwrap();
+        }
+
+        let (_, status) = app.get("/api/cluster/status").await;
+        assert_eq!(status["devices"][0]["unreliable"], true);
+        let by_tag = json!({ "model_path": model, "device_tags": ["lab"], "dry_run": true });
+        let (status, plan) = app.post("/api/cluster/inference/start", by_tag.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", plan);
+        assert_eq!(plan["devices"], json!([]));
+        let by_id = json!({ "model_path": model, "device_ids": [id], "dry_run": true });
+        let (_, plan) = app.post("/api/cluster/inference/start", by_id).await;
+        assert_eq!(plan["devices"].as_array().unwrap().len(), 1);
+        assert!(plan["warnings"][0].as_str().unwrap().contains("wifi-box"));
+        assert_eq!(plan["devices"][0]["depth"], "memory");
+        let (_, device) = app.get(&uri).await;
+        assert_eq!((device["rpc_probe_depth"].clone(), device["rpc_probe_ok"].clone()), (json!("memory"), json!(true)));
+
+        let (_, health) = app.get(&format!("{}/health", uri)).await;
+        assert_eq!((health["health_score"].clone(), health["unreliable"].clone()), (json!(40), json!(true)));
+        assert_eq!(health["history"][0]["reason"], "early_crash");
+        let (status, reset) = app.post(&format!("{}/reset-health", uri), json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reset["health_score"], 100);
+        let (_, plan) = app.post("/api/cluster/inference/start", by_tag).await;
+        assert_eq!(plan["devices"].as_array().unwrap().len(), 1);
+        assert_eq!(app.post("/api/devices/nope/reset-health", json!({})).await.0, StatusCode::NOT_FOUND);
+        std::fs::remove_file(&model).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn builds_without_rpc_refuse_devices_up_front() {
+        use std::os::unix::fs::PermissionsExt;
+        let app = TestApp::new().await;
+        let dir = std::env::temp_dir().join(format!("sharedllm-build-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server = dir.join("llama-server");
+        // A Vulkan build configured without GGML_RPC
+        let script = "#!/bin/sh\nif [ \"$1\" = --version ]; then echo 'ggml_vulkan: Found 1 Vulkan devices:' >&2; \
+            echo 'version: 4100 (0abe6c1)' >&2; echo 'built with cc for x86_64-linux-gnu' >&2; \
+            else echo '-m, --model FNAME'; echo '-ngl, --gpu-layers N'; fi\n";
+        std::fs::write(&server, script).unwrap();
+        std::fs::set_permissions(&server, std::fs::Permissions::from_mode(0o755)).unwrap();
+        app.spawner.install("llama-server", server.clone());
+
+        let (status, binaries) = app.get("/api/cluster/binaries").await;
+        assert_eq!(status, StatusCode::OK);
+        let llama_server = &binaries["llama_server"];
+        assert_eq!((llama_server["build"].as_u64(), llama_server["rpc"].as_bool()), (Some(4100), Some(false)));
+        assert_eq!(llama_server["backends"], json!(["Vulkan"]));
+        let (_, status) = app.get("/api/cluster/status").await;
+        assert_eq!(status["llama_cpp"]["binaries"], binaries);
+
+        let port = crate::llama_cpp::rpc_probe::stub_server().await.port();
+        let model = dir.join("tiny.gguf");
+        std::fs::write(&model, vec![0u8; 2 * 1024 * 1024]).unwrap();
+        let (_, device) = app.post("/api/devices", json!({ "name": "gpu-box", "ip": "127.0.0.1" })).await;
+        let uri = format!("/api/devices/{}", device["id"].as_str().unwrap());
+        app.request(Method::PATCH, &uri, Some(json!({ "rpc_port": port }))).await;
+        let body = json!({ "role_id": "role-user", "pairing_code": device["pairing_code"], "verify": false });
+        assert_eq!(app.post(&format!("{}/approve", uri), body).await.0, StatusCode::OK);
+
+        let start = json!({ "model_path": model, "device_ids": [device["id"]] });
+        let (status, error) = app.post("/api/cluster/inference/start", start).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", error);
+        assert_eq!((error["error"].as_str(), error["version"].as_str()), (Some("no_rpc_support"), Some("4100 (0abe6c1)")));
+        assert!(app.spawner.started().is_empty());
+        // Alone it still starts
+        let (status, started) = app.post("/api/cluster/inference/start", json!({ "model_path": model })).await;
+        assert_eq!(status, StatusCode::OK, "{}", started);
+        assert_eq!(app.spawner.started().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Send a text frame; a client's frames are masked, here with a zero key.
+    async fn send_text(stream: &mut TcpStream, text: &str) {
+        let mut frame = vec![0x81, 0x80 | text.len() as u8, 0, 0, 0, 0];
+        frame.extend_from_slice(text.as_bytes());
+        stream.write_all(&frame).await.unwrap();
+    }
+
+    /// Read the next frame from the server, which never masks them.
+    async fn read_text(stream: &mut TcpStream) -> Value {
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await.unwrap();
+        let len = match head[1] & 0x7f {
+            126 => stream.read_u16().await.unwrap() as usize,
+            127 => stream.read_u64().await.unwrap() as usize,
+            n => n as usize,
+        };
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await.unwrap();
+        assert_eq!(head[0] & 0x0f, 1, "expected a text frame");
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn websocket_forwards_events() {
+        let app = TestApp::new().await;
+        let addr = app.serve().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        assert!(response.starts_with(b"HTTP/1.1 101"));
+        send_text(&mut stream, r#"{"type":"hello","v":1}"#).await;
+
+        // Subscribed once it's listed
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
+        while app.state.ws_clients.list().is_empty() {
+            assert!(tokio::time::Instant::now() < deadline, "client never registered");
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        let _ = app.state.event_tx.send(WsEvent::RpcServerReady { port: 8181 });
+        // A v1 client gets the bare event
+        let event = read_text(&mut stream).await;
+        assert_eq!(event, json!({ "type": "rpc_server_ready", "port": 8181 }));
+        assert_eq!(app.state.ws_clients.list()[0].protocol_version, 1);
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::{sleep, Duration};
+
+use crate::db::{models::Webhook, queries};
+use crate::ws::{EventBus, WsEvent};
+
+/// Header carrying `sha256=<hex HMAC of the body>` when the webhook has a secret.
+pub const SIGNATURE_HEADER: &str = "X-SharedLLM-Signature";
+/// Header carrying the event type (e.g. `device_pending_approval`).
+pub const EVENT_HEADER: &str = "X-SharedLLM-Event";
+
+/// Retries after the first failed delivery (backoff 1 s, 2 s, 4 s).
+const MAX_RETRIES: u32 = 3;
+/// Per-webhook queue length; events beyond this are dropped, not awaited.
+const QUEUE_CAPACITY: usize = 64;
+
+/// High-frequency events that are never delivered to webhooks.
+const PERIODIC_EVENTS: &[&str] = &["memory_stats", "inference_metrics", "overview"];
+
+/// A rendered delivery: the target plus the signed JSON body.
+struct Delivery {
+    url: String,
+    secret: String,
+    event_type: String,
+    body: String,
+}
+
+/// Fans broadcast events out to configured webhooks.
+///
+/// Each webhook gets its own bounded queue and worker task, so a slow or dead
+/// endpoint only ever delays its own deliveries — the broadcast listener never
+/// waits on HTTP.
+pub struct WebhookDispatcher {
+    pool: SqlitePool,
+    client: Client,
+    queues: Mutex<HashMap<String, mpsc::Sender<Delivery>>>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(pool: SqlitePool) -> Self {
+        WebhookDispatcher {
+            pool,
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Subscribe to state-change events and dispatch matching ones.
+    pub fn spawn(self: Arc<Self>, event_tx: &EventBus) {
+        let mut rx = event_tx.subscribe_critical();
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(e) => e,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!("Webhook dispatcher lagged; skipped {} events", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                self.dispatch(&event).await;
+            }
+        });
+    }
+
+    async fn dispatch(&self, event: &WsEvent) {
+        let data = serde_json::to_value(event).unwrap_or_default();
+        let event_type = data["type"].as_str().unwrap_or("unknown").to_string();
+        if PERIODIC_EVENTS.contains(&event_type.as_str()) {
+            return;
+        }
+
+        let hooks = match queries::list_webhooks(&self.pool).await {
+            Ok(h) => h,
+            Err(e) => {
+                tracing::warn!("Failed to load webhooks: {}", e);
+                return;
+            }
+        };
+
+        for hook in hooks.into_iter().filter(|h| h.enabled) {
+            let filter = hook.events();
+            if !filter.is_empty() && !filter.contains(&event_type) {
+                continue;
+            }
+            self.enqueue(&hook, render(&event_type, &summarize(event), data.clone()))
+                .await;
+        }
+    }
+
+    /// Queue a delivery on the webhook's worker, starting the worker if needed.
+    async fn enqueue(&self, hook: &Webhook, (event_type, body): (String, String)) {
+        let delivery = Delivery {
+            url: hook.url.clone(),
+            secret: hook.secret.clone(),
+            event_type,
+            body,
+        };
+
+        let mut queues = self.queues.lock().await;
+        let tx = queues
+            .entry(hook.id.clone())
+            .or_insert_with(|| self.spawn_worker(hook.id.clone()));
+
+        match tx.try_send(delivery) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::warn!("Webhook {} queue is full; dropping event", hook.id);
+            }
+            Err(mpsc::error::TrySendError::Closed(delivery)) => {
+                // Worker exited — start a fresh one for this delivery
+                let tx = self.spawn_worker(hook.id.clone());
+                let _ = tx.try_send(delivery);
+                queues.insert(hook.id.clone(), tx);
+            }
+        }
+    }
+
+    fn spawn_worker(&self, webhook_id: String) -> mpsc::Sender<Delivery> {
+        let (tx, mut rx) = mpsc::channel::<Delivery>(QUEUE_CAPACITY);
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            while let Some(delivery) = rx.recv().await {
+                let mut attempt = 1;
+                loop {
+                    match send(&client, &delivery).await {
+                        Ok(_) => break,
+                        Err(e) if attempt > MAX_RETRIES => {
+                            tracing::warn!(
+                                "Webhook {} delivery of {} failed after {} attempts: {}",
+                                webhook_id,
+                                delivery.event_type,
+                                attempt,
+                                e
+                            );
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::debug!(
+                                "Webhook {} attempt {} failed: {}; retrying",
+                                webhook_id,
+                                attempt,
+                                e
+                            );
+                            sleep(Duration::from_secs(1 << (attempt - 1))).await;
+                            attempt += 1;
+                        }
+                    }
+                }
+            }
+        });
+        tx
+    }
+
+    /// Send a synthetic `webhook_test` event once, without retries, and return
+    /// the HTTP status the endpoint answered with.
+    pub async fn send_test(&self, hook: &Webhook) -> Result<u16> {
+        let data = serde_json::json!({
+            "type": "webhook_test",
+            "webhook_id": hook.id,
+        });
+        let (event_type, body) = render(
+            "webhook_test",
+            "SharedLLM test notification — your webhook is working.",
+            data,
+        );
+        send(
+            &self.client,
+            &Delivery {
+                url: hook.url.clone(),
+                secret: hook.secret.clone(),
+                event_type,
+                body,
+            },
+        )
+        .await
+    }
+}
+
+/// Build the JSON body for an event. `text` / `content` carry a one-line
+/// summary so Slack and Discord incoming webhooks render something useful.
+fn render(event_type: &str, summary: &str, data: serde_json::Value) -> (String, String) {
+    let body = serde_json::json!({
+        "id": uuid::Uuid::new_v4().to_string(),
+        "event": event_type,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "text": summary,
+        "content": summary,
+        "data": data,
+    });
+    (event_type.to_string(), body.to_string())
+}
+
+/// `sha256=<hex>` HMAC of `body` keyed with `secret`.
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn send(client: &Client, delivery: &Delivery) -> Result<u16> {
+    let mut req = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, &delivery.event_type);
+    if !delivery.secret.is_empty() {
+        req = req.header(SIGNATURE_HEADER, sign(&delivery.secret, &delivery.body));
+    }
+    let resp = req.body(delivery.body.clone()).send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(anyhow!("endpoint returned HTTP {}", status));
+    }
+    Ok(status.as_u16())
+}
+
+/// Human-readable one-liner for chat-style webhook targets.
+fn summarize(event: &WsEvent) -> String {
+    match event {
+        WsEvent::DevicePendingApproval { name, ip, .. } => {
+            format!("New device '{}' ({}) is waiting for approval", name, ip)
+        }
+        WsEvent::DeviceApproved { name, ip, .. } => {
+            format!("Device '{}' ({}) was approved", name, ip)
+        }
+        WsEvent::DeviceDenied { device_id } => format!("Device {} was denied", device_id),
+        WsEvent::DeviceSuspended { device_id } => format!("Device {} was suspended", device_id),
+        WsEvent::DeviceOffline { name, .. } => format!("Device '{}' went offline", name),
+        WsEvent::InferenceStarted { model, .. } => format!("Inference started: {}", model),
+        WsEvent::InferenceStopped { session_id, reason, .. } => match reason {
+            Some(reason) => format!("Inference session {} stopped ({})", session_id, reason),
+            None => format!("Inference session {} stopped", session_id),
+        },
+        WsEvent::InferenceRestarting { session_id, attempt } => format!(
+            "llama-server crashed; restarting session {} (attempt {})",
+            session_id, attempt
+        ),
+        WsEvent::InferenceRetrying { session_id, n_gpu_layers, .. } => format!(
+            "llama-server ran out of memory; retrying session {} with {} GPU layers",
+            session_id, n_gpu_layers
+        ),
+        WsEvent::InferenceFailed { session_id, reason } => {
+            format!("Inference session {} failed: {}", session_id, reason)
+        }
+        WsEvent::InferenceResumeFailed { reason, .. } => {
+            format!("Could not resume the last inference session: {}", reason)
+        }
+        WsEvent::OllamaStatus { running, host } => format!(
+            "Ollama at {} is {}",
+            host,
+            if *running { "running" } else { "down" }
+        ),
+        WsEvent::ModelRemoved { path, .. } => format!("Model {} was deleted", path),
+        WsEvent::BackendProfileActivated { name, backend_type, .. } => {
+            format!("Backend profile '{}' ({}) is now active", name, backend_type)
+        }
+        WsEvent::Error { source, message, .. } => format!("Error in {}: {}", source, message),
+        other => {
+            let data = serde_json::to_value(other).unwrap_or_default();
+            format!("SharedLLM event: {}", data["type"].as_str().unwrap_or("unknown"))
+        }
+    }
+}
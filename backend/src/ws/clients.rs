@@ -0,0 +1,185 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::Notify;
+
+use super::{EventBus, WsEvent, PROTOCOL_VERSION};
+
+/// Event channels every client receives; see [`EventBus`].
+const TOPICS: &[&str] = &["state", "stats"];
+
+/// One connected `/ws` client.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct WsClientInfo {
+    pub id: String,
+    /// `ip:port` of the connection, when known
+    pub remote_addr: Option<String>,
+    pub connected_at: String,
+    /// Protocol version from its hello, or the server's
+    pub protocol_version: u32,
+    /// Event channels it receives: `state` changes and periodic `stats`
+    pub topics: Vec<String>,
+    pub events_sent: u64,
+    /// Last message sent to or received from it (RFC 3339)
+    pub last_activity: String,
+}
+
+/// A registered connection: its metadata and a way to close it.
+pub struct WsClient {
+    info: Mutex<WsClientInfo>,
+    close: Notify,
+}
+
+impl WsClient {
+    pub fn id(&self) -> String {
+        self.info.lock().unwrap().id.clone()
+    }
+
+    pub fn event_sent(&self) {
+        let mut info = self.info.lock().unwrap();
+        info.events_sent += 1;
+        info.last_activity = chrono::Utc::now().to_rfc3339();
+    }
+
+    pub fn message_received(&self) {
+        self.info.lock().unwrap().last_activity = chrono::Utc::now().to_rfc3339();
+    }
+
+    pub fn set_protocol_version(&self, v: u32) {
+        self.info.lock().unwrap().protocol_version = v;
+    }
+
+    /// Resolves once the connection was asked to close.
+    pub async fn closed(&self) {
+        self.close.notified().await;
+    }
+}
+
+/// Every open `/ws` connection, keyed by client ID.
+#[derive(Default)]
+pub struct WsClientRegistry {
+    clients: RwLock<HashMap<String, Arc<WsClient>>>,
+}
+
+impl WsClientRegistry {
+    /// Add a connection and announce it. The client stays listed until the
+    /// returned guard is dropped, however its tasks end.
+    pub fn register(
+        self: &Arc<Self>,
+        remote_addr: Option<SocketAddr>,
+        events: &EventBus,
+    ) -> WsClientGuard {
+        let now = chrono::Utc::now().to_rfc3339();
+        let info = WsClientInfo {
+            id: uuid::Uuid::new_v4().to_string(),
+            remote_addr: remote_addr.map(|a| a.to_string()),
+            connected_at: now.clone(),
+            protocol_version: PROTOCOL_VERSION,
+            topics: TOPICS.iter().map(|t| t.to_string()).collect(),
+            events_sent: 0,
+            last_activity: now,
+        };
+        let client = Arc::new(WsClient { info: Mutex::new(info.clone()), close: Notify::new() });
+        self.clients.write().unwrap().insert(info.id.clone(), client.clone());
+        let _ = events.send(WsEvent::WsClientConnected {
+            client_id: info.id,
+            remote_addr: info.remote_addr,
+        });
+        WsClientGuard { registry: self.clone(), client, events: events.clone() }
+    }
+
+    /// Connected clients, oldest first.
+    pub fn list(&self) -> Vec<WsClientInfo> {
+        let mut clients: Vec<WsClientInfo> = self
+            .clients
+            .read()
+            .unwrap()
+            .values()
+            .map(|c| c.info.lock().unwrap().clone())
+            .collect();
+        clients.sort_by(|a, b| a.connected_at.cmp(&b.connected_at));
+        clients
+    }
+
+    /// Ask a connection to close. Returns false if no such client is connected.
+    pub fn close(&self, id: &str) -> bool {
+        match self.clients.read().unwrap().get(id) {
+            Some(client) => {
+                // Stores a permit, so a close requested between polls isn't lost
+                client.close.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Unregisters its client when dropped, including while unwinding from a panic.
+pub struct WsClientGuard {
+    registry: Arc<WsClientRegistry>,
+    client: Arc<WsClient>,
+    events: EventBus,
+}
+
+impl WsClientGuard {
+    pub fn client(&self) -> Arc<WsClient> {
+        self.client.clone()
+    }
+}
+
+impl Drop for WsClientGuard {
+    fn drop(&mut self) {
+        let info = self.client.info.lock().map(|i| i.clone());
+        let Ok(info) = info else {
+            return;
+        };
+        if let Ok(mut clients) = self.registry.clients.write() {
+            clients.remove(&info.id);
+        }
+        let _ = self.events.send(WsEvent::WsClientDisconnected {
+            client_id: info.id,
+            events_sent: info.events_sent,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn clients_are_listed_until_dropped() {
+        let registry = Arc::new(WsClientRegistry::default());
+        let events = EventBus::default();
+        let mut rx = events.subscribe_critical();
+
+        let guard = registry.register(Some("10.0.0.9:51000".parse().unwrap()), &events);
+        let client = guard.client();
+        client.event_sent();
+        client.set_protocol_version(2);
+        let listed = registry.list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].remote_addr.as_deref(), Some("10.0.0.9:51000"));
+        assert_eq!(listed[0].events_sent, 1);
+        assert_eq!(listed[0].protocol_version, 2);
+        assert!(matches!(rx.recv().await, Ok(WsEvent::WsClientConnected { .. })));
+
+        assert!(registry.close(&client.id()));
+        assert!(!registry.close("nope"));
+        // The permit was stored, so this returns at once
+        client.closed().await;
+
+        // A panicking connection task still unregisters
+        let task = tokio::spawn(async move {
+            let _guard = guard;
+            panic!("connection task failed");
+        });
+        assert!(task.await.is_err());
+        assert!(registry.list().is_empty());
+        assert!(matches!(
+            rx.recv().await,
+            Ok(WsEvent::WsClientDisconnected { events_sent: 1, .. })
+        ));
+    }
+}
@@ -1,7 +1,21 @@
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError, error::SendError};
+
+use crate::errors::Severity;
+use crate::settings::limits::Limits;
+
+pub mod clients;
+
+/// `/ws` wire protocol. v1 sent bare events; v2 wraps each in an `Envelope`.
+/// Bump this whenever a variant or field is added, and teach `encode` how to
+/// down-convert it.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// Buffer for periodic stats; only the latest one matters.
+const STATS_CAPACITY: usize = 64;
 
 /// All WebSocket events sent to connected browser clients
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WsEvent {
     /// A new device was discovered via mDNS
@@ -26,18 +40,81 @@ pub enum WsEvent {
     },
     /// A device was denied
     DeviceDenied { device_id: String },
-    /// A device went offline (mDNS removal)
-    DeviceOffline { name: String },
+    /// An approved device was suspended; its allocations were revoked
+    DeviceSuspended { device_id: String },
+    /// A device went offline: mDNS removal, or `device_id` not heard from
+    /// within `device_offline_after_secs`
+    DeviceOffline {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device_id: Option<String>,
+    },
     /// Memory was allocated to a device
     MemoryAllocated { device_id: String, memory_mb: i64 },
-    /// Periodic GPU/memory stats update
+    /// Periodic GPU/memory stats update, with every provider's snapshot.
+    /// Sent when a provider moved by `memory_stats_delta_mb`, and as a
+    /// keyframe at least every 30 s and when a client connects.
     MemoryStats {
         snapshots: Vec<crate::memory::MemorySnapshot>,
+        /// Providers that moved since they were last sent
+        #[serde(default)]
+        changed: Vec<String>,
+        /// Sent on schedule or on connect rather than because something moved
+        #[serde(default)]
+        keyframe: bool,
+    },
+    /// The headline numbers of `GET /api/overview`, sent when one of them
+    /// changes materially
+    Overview {
+        memory_total_mb: u64,
+        memory_free_mb: u64,
+        devices_approved: usize,
+        devices_offline: usize,
+        pending_approvals: usize,
+        inference_running: bool,
+        ollama_running: bool,
+        recent_errors: usize,
+    },
+    /// Memory providers were found or lost when detection ran again
+    ProvidersChanged {
+        added: Vec<crate::memory::hotplug::ProviderInfo>,
+        removed: Vec<crate::memory::hotplug::ProviderInfo>,
     },
     /// Ollama status changed
     OllamaStatus { running: bool, host: String },
-    /// Generic error notification
-    Error { message: String },
+    /// A problem in a background task (see `errors::notify_error`)
+    Error {
+        severity: Severity,
+        /// Subsystem that reported it, e.g. `ollama`, `llama_cpp`, `memory`
+        source: String,
+        message: String,
+    },
+    /// Progress of a queued model pull; `status` is Ollama's progress text
+    /// while it runs, then `completed`, `failed` or `cancelled`
+    ModelPullProgress {
+        job_id: String,
+        name: String,
+        status: String,
+        completed: Option<i64>,
+        total: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A model file arrived on this host, uploaded or fetched from a peer
+    ModelAvailable {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alias: Option<String>,
+    },
+    /// A model file was deleted (moved to the trash), with the aliases that
+    /// pointed at it
+    ModelRemoved { path: String, aliases: Vec<String> },
+    /// A saved backend profile became the active `/v1` backend
+    BackendProfileActivated {
+        profile_id: String,
+        name: String,
+        backend_type: String,
+    },
 
     // ─── Distributed inference (llama.cpp RPC) ────────────────────────────
 
@@ -58,17 +135,372 @@ pub enum WsEvent {
         session_id: String,
         model: String,
         devices: Vec<String>,
+        /// The HTTP request that started it, if any (see `x-request-id`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
     },
     /// llama-server inference process stopped
-    InferenceStopped { session_id: String },
+    InferenceStopped {
+        session_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+        /// Why the server stopped it on its own (`idle_timeout`); absent for
+        /// a requested stop or a crash
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// Graceful stop begun: new chat requests are refused until llama-server exits
+    InferenceDraining { session_id: String, in_flight: usize },
+    /// Watchdog is re-launching a crashed session (`auto_restart_inference`)
+    InferenceRestarting { session_id: String, attempt: u32 },
+    /// llama-server ran out of memory loading; starting again with fewer
+    /// GPU layers (`retry_on_oom`)
+    InferenceRetrying { session_id: String, attempt: u32, n_gpu_layers: i32 },
+    /// Watchdog gave up restarting a crashed session
+    InferenceFailed { session_id: String, reason: String },
+    /// The last session could not be resumed (on startup or via `resume-last`)
+    InferenceResumeFailed {
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    /// Periodic llama-server slot/queue metrics while a session is active
+    InferenceMetrics {
+        session_id: String,
+        slots_busy: u32,
+        slots_total: u32,
+        queue_depth: Option<u64>,
+        generated_tokens_per_sec: Option<f64>,
+    },
     /// Layer assignment across devices (informational)
     LayerAssignment {
         assignments: Vec<LayerAssignment>,
     },
+    /// This client missed `missed` state-change events; refetch via REST
+    Resync { missed: u64 },
+    /// A `/ws` client connected; never sent to that client itself
+    WsClientConnected {
+        client_id: String,
+        remote_addr: Option<String>,
+    },
+    /// A `/ws` client disconnected or was closed
+    WsClientDisconnected { client_id: String, events_sent: u64 },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl WsEvent {
+    /// High-frequency events that are superseded by the next one.
+    pub fn is_periodic(&self) -> bool {
+        matches!(
+            self,
+            WsEvent::MemoryStats { .. } | WsEvent::InferenceMetrics { .. } | WsEvent::Overview { .. }
+        )
+    }
+
+    /// Oldest protocol version that has this variant.
+    pub fn since_version(&self) -> u32 {
+        match self {
+            WsEvent::Resync { .. } => 2,
+            WsEvent::ModelPullProgress { .. } => 3,
+            WsEvent::WsClientConnected { .. } | WsEvent::WsClientDisconnected { .. } => 4,
+            _ => 1,
+        }
+    }
+
+    /// The `/ws` client this event is about, which must not receive it.
+    pub fn ws_client_id(&self) -> Option<&str> {
+        match self {
+            WsEvent::WsClientConnected { client_id, .. }
+            | WsEvent::WsClientDisconnected { client_id, .. } => Some(client_id),
+            _ => None,
+        }
+    }
+
+    /// The text message for a client speaking `version`, or `None` when the
+    /// event can't be represented in it. `ts` is the RFC 3339 send time.
+    pub fn encode(&self, version: u32, ts: &str) -> Option<String> {
+        if self.since_version() > version {
+            return None;
+        }
+        if version >= PROTOCOL_VERSION {
+            let envelope = Envelope {
+                v: PROTOCOL_VERSION,
+                ts: ts.to_string(),
+                event: self.clone(),
+            };
+            return serde_json::to_string(&envelope).ok();
+        }
+        let mut value = serde_json::to_value(self).ok()?;
+        // Fields newer than the client's version
+        if let Some(fields) = value.as_object_mut() {
+            if version < 2 {
+                fields.remove("request_id");
+            }
+            if version < 3 && matches!(self, WsEvent::Error { .. }) {
+                fields.remove("severity");
+                fields.remove("source");
+            }
+            if version < 5 && matches!(self, WsEvent::MemoryStats { .. }) {
+                fields.remove("changed");
+                fields.remove("keyframe");
+            }
+        }
+        if version >= 2 {
+            let envelope = serde_json::json!({ "v": version, "ts": ts, "event": value });
+            return serde_json::to_string(&envelope).ok();
+        }
+        serde_json::to_string(&value).ok()
+    }
+}
+
+/// What a v2 client receives for every event.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Envelope {
+    /// Protocol version the event is encoded in: the server's, or the
+    /// client's when it is older
+    pub v: u32,
+    /// When the event was sent (RFC 3339)
+    pub ts: String,
+    pub event: WsEvent,
+}
+
+/// First message a client sends after connecting: `{"type": "hello", "v": 1}`.
+/// Clients that don't send one within a moment get `PROTOCOL_VERSION`.
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Hello { v: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct LayerAssignment {
     pub device_id: String,
     pub layers: String, // e.g. "0-15"
 }
+
+// ─── Event bus ───────────────────────────────────────────────────────────────
+
+/// Broadcast bus for `WsEvent`s. Periodic stats and state changes travel on
+/// separate channels so a burst of stats can never push a state change out
+/// of a slow receiver's buffer.
+#[derive(Clone)]
+pub struct EventBus {
+    critical: broadcast::Sender<WsEvent>,
+    stats: broadcast::Sender<WsEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::with_buffer(Limits::default().ws_buffer)
+    }
+}
+
+impl EventBus {
+    /// A bus holding `critical` state changes (`ws_buffer`), generous so a
+    /// slow client never misses one.
+    pub fn with_buffer(critical: usize) -> Self {
+        Self::with_capacity(critical, STATS_CAPACITY)
+    }
+
+    pub fn with_capacity(critical: usize, stats: usize) -> Self {
+        Self {
+            critical: broadcast::channel(critical).0,
+            stats: broadcast::channel(stats).0,
+        }
+    }
+
+    // The error hands the event back, mirroring `broadcast::Sender::send`
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, event: WsEvent) -> Result<usize, SendError<WsEvent>> {
+        if event.is_periodic() {
+            self.stats.send(event)
+        } else {
+            self.critical.send(event)
+        }
+    }
+
+    /// Every event, state changes first.
+    pub fn subscribe(&self) -> EventReceiver {
+        EventReceiver {
+            critical: self.critical.subscribe(),
+            stats: self.stats.subscribe(),
+        }
+    }
+
+    /// State changes only, for consumers that ignore periodic stats.
+    pub fn subscribe_critical(&self) -> broadcast::Receiver<WsEvent> {
+        self.critical.subscribe()
+    }
+}
+
+pub struct EventReceiver {
+    critical: broadcast::Receiver<WsEvent>,
+    stats: broadcast::Receiver<WsEvent>,
+}
+
+impl EventReceiver {
+    /// Next event, preferring pending state changes over stats. Stats a slow
+    /// receiver fell behind on are skipped silently; `Lagged` is only
+    /// returned when state changes were lost.
+    pub async fn recv(&mut self) -> Result<WsEvent, RecvError> {
+        loop {
+            tokio::select! {
+                biased;
+                event = self.critical.recv() => return event,
+                event = self.stats.recv() => match event {
+                    Err(RecvError::Lagged(_)) => continue,
+                    other => return other,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> WsEvent {
+        WsEvent::MemoryStats { snapshots: Vec::new(), changed: vec!["nvidia-0".into()], keyframe: false }
+    }
+
+    #[tokio::test]
+    async fn state_changes_survive_a_flood_of_stats() {
+        let bus = EventBus::with_capacity(8, 4);
+        let mut slow = bus.subscribe();
+
+        for _ in 0..500 {
+            let _ = bus.send(stats());
+        }
+        let _ = bus.send(WsEvent::DevicePendingApproval {
+            device_id: "d1".into(),
+            name: "laptop".into(),
+            ip: "10.0.0.2".into(),
+            discovery_method: "mdns".into(),
+        });
+        for _ in 0..500 {
+            let _ = bus.send(stats());
+        }
+
+        let mut seen = Vec::new();
+        while let Ok(Ok(event)) =
+            tokio::time::timeout(std::time::Duration::from_millis(50), slow.recv()).await
+        {
+            seen.push(event);
+        }
+        assert!(matches!(seen[0], WsEvent::DevicePendingApproval { .. }));
+        assert!(seen[1..].iter().all(WsEvent::is_periodic));
+    }
+
+    const TS: &str = "2026-01-02T03:04:05+00:00";
+
+    fn started() -> WsEvent {
+        WsEvent::InferenceStarted {
+            session_id: "s1".into(),
+            model: "/models/m.gguf".into(),
+            devices: vec!["10.0.0.2:8181".into()],
+            request_id: Some("req-1".into()),
+        }
+    }
+
+    fn error() -> WsEvent {
+        WsEvent::Error {
+            severity: crate::errors::Severity::Error,
+            source: "ollama".into(),
+            message: "Failed to restart Ollama".into(),
+        }
+    }
+
+    #[test]
+    fn v5_wire_format() {
+        assert_eq!(
+            started().encode(5, TS).unwrap(),
+            r#"{"v":5,"ts":"2026-01-02T03:04:05+00:00","event":{"type":"inference_started","session_id":"s1","model":"/models/m.gguf","devices":["10.0.0.2:8181"],"request_id":"req-1"}}"#
+        );
+        assert_eq!(
+            WsEvent::Resync { missed: 3 }.encode(5, TS).unwrap(),
+            r#"{"v":5,"ts":"2026-01-02T03:04:05+00:00","event":{"type":"resync","missed":3}}"#
+        );
+        assert_eq!(
+            error().encode(5, TS).unwrap(),
+            r#"{"v":5,"ts":"2026-01-02T03:04:05+00:00","event":{"type":"error","severity":"error","source":"ollama","message":"Failed to restart Ollama"}}"#
+        );
+        assert_eq!(
+            stats().encode(5, TS).unwrap(),
+            r#"{"v":5,"ts":"2026-01-02T03:04:05+00:00","event":{"type":"memory_stats","snapshots":[],"changed":["nvidia-0"],"keyframe":false}}"#
+        );
+    }
+
+    #[test]
+    fn v4_wire_format() {
+        assert_eq!(
+            WsEvent::WsClientDisconnected { client_id: "c1".into(), events_sent: 12 }
+                .encode(4, TS)
+                .unwrap(),
+            r#"{"event":{"client_id":"c1","events_sent":12,"type":"ws_client_disconnected"},"ts":"2026-01-02T03:04:05+00:00","v":4}"#
+        );
+        assert_eq!(
+            stats().encode(4, TS).unwrap(),
+            r#"{"event":{"snapshots":[],"type":"memory_stats"},"ts":"2026-01-02T03:04:05+00:00","v":4}"#
+        );
+    }
+
+    #[test]
+    fn v3_wire_format() {
+        assert_eq!(
+            error().encode(3, TS).unwrap(),
+            r#"{"event":{"message":"Failed to restart Ollama","severity":"error","source":"ollama","type":"error"},"ts":"2026-01-02T03:04:05+00:00","v":3}"#
+        );
+        let connected = WsEvent::WsClientConnected { client_id: "c1".into(), remote_addr: None };
+        assert_eq!(connected.encode(3, TS), None);
+    }
+
+    #[test]
+    fn v2_wire_format() {
+        assert_eq!(
+            started().encode(2, TS).unwrap(),
+            r#"{"event":{"devices":["10.0.0.2:8181"],"model":"/models/m.gguf","request_id":"req-1","session_id":"s1","type":"inference_started"},"ts":"2026-01-02T03:04:05+00:00","v":2}"#
+        );
+        assert_eq!(
+            error().encode(2, TS).unwrap(),
+            r#"{"event":{"message":"Failed to restart Ollama","type":"error"},"ts":"2026-01-02T03:04:05+00:00","v":2}"#
+        );
+        let pull = WsEvent::ModelPullProgress {
+            job_id: "p1".into(),
+            name: "llama3".into(),
+            status: "completed".into(),
+            completed: None,
+            total: None,
+            error: None,
+        };
+        assert_eq!(pull.encode(2, TS), None);
+    }
+
+    #[test]
+    fn v1_wire_format() {
+        assert_eq!(
+            started().encode(1, TS).unwrap(),
+            r#"{"devices":["10.0.0.2:8181"],"model":"/models/m.gguf","session_id":"s1","type":"inference_started"}"#
+        );
+        assert_eq!(
+            WsEvent::DeviceDenied { device_id: "d1".into() }.encode(1, TS).unwrap(),
+            r#"{"device_id":"d1","type":"device_denied"}"#
+        );
+        assert_eq!(WsEvent::Resync { missed: 3 }.encode(1, TS), None);
+    }
+
+    #[test]
+    fn hello_is_parsed() {
+        let hello: ClientMessage = serde_json::from_str(r#"{"type":"hello","v":1}"#).unwrap();
+        assert!(matches!(hello, ClientMessage::Hello { v: 1 }));
+    }
+
+    #[tokio::test]
+    async fn lost_state_changes_are_reported() {
+        let bus = EventBus::with_capacity(2, 2);
+        let mut slow = bus.subscribe();
+        for _ in 0..5 {
+            let _ = bus.send(WsEvent::RpcServerOffline);
+        }
+        assert!(matches!(slow.recv().await, Err(RecvError::Lagged(3))));
+    }
+}
@@ -18,7 +18,7 @@ import { useWebSocket } from './hooks/useWebSocket'
 import { useDevices } from './hooks/useDevices'
 import { useMemory } from './hooks/useMemory'
 
-import type { WsEvent, Role, Settings } from './types'
+import type { Device, WsEvent, Role, Settings } from './types'
 import { api } from './lib/api'
 
 export default function App() {
@@ -67,6 +67,7 @@ export default function App() {
         refreshDevices()
         break
       case 'device_discovered':
+      case 'device_suspended':
       case 'device_offline':
       case 'memory_allocated':
         refreshDevices()
@@ -90,6 +91,20 @@ export default function App() {
       case 'inference_stopped':
         setInferenceRunning(false)
         break
+      case 'resync':
+        // Missed events: rebuild pending approvals and device state from REST
+        refreshDevices()
+        api.devices().then(d => {
+          const pending = (d.devices ?? []).filter((dev: Device) => dev.status === 'pending')
+          setApprovalRequests(pending.map((dev: Device) => ({
+            device_id: dev.id,
+            name: dev.name,
+            ip: dev.ip,
+            discovery_method: dev.discovery_method,
+            timestamp: Date.now(),
+          })))
+        }).catch(() => {})
+        break
     }
   }, [refreshDevices, updateFromWs])
 
@@ -99,7 +114,14 @@ export default function App() {
     // Default to the 'guest' role for quick-approvals from the toast notification.
     // Fall back to first role if no guest role exists.
     const guestRole = roles.find(r => r.name.toLowerCase() === 'guest') ?? roles[0]
-    await approve(id, guestRole?.id)
+    try {
+      await approve(id, guestRole?.id)
+    } catch (e) {
+      // Self-registered devices need their pairing code, entered on the Devices page
+      const error = e instanceof Error ? e.message : String(e)
+      setApprovalRequests(prev => prev.map(r => (r.device_id === id ? { ...r, error } : r)))
+      return
+    }
     setApprovalRequests(prev => prev.filter(r => r.device_id !== id))
   }, [approve, roles])
 
@@ -6,6 +6,8 @@ export interface ApprovalRequest {
   ip: string
   discovery_method: string
   timestamp: number
+  /** Why the quick approval failed, e.g. the device needs its pairing code */
+  error?: string
 }
 
 interface ApprovalToastProps {
@@ -50,6 +52,8 @@ export function ApprovalToast({ requests, onApprove, onDeny }: ApprovalToastProp
             </div>
           </div>
 
+          {req.error && <p className="text-xs text-danger mb-3">{req.error}</p>}
+
           {/* Actions */}
           <div className="flex gap-2">
             <button
@@ -1,12 +1,12 @@
 import { clsx } from 'clsx'
 import { Monitor, Wifi, WifiOff, Clock, HardDrive, Check, X, MoreHorizontal } from 'lucide-react'
-import type { Device, DeviceStatus, Role } from '../types'
+import type { ApprovedDevice, Device, DeviceStatus, Role } from '../types'
 import { useState, useEffect } from 'react'
 
 interface DeviceCardProps {
   device: Device
   roles: Role[]
-  onApprove: (id: string, roleId?: string) => void
+  onApprove: (id: string, roleId?: string, pairingCode?: string) => Promise<ApprovedDevice>
   onDeny: (id: string) => void
   onAllocate: (id: string, mb: number) => void
   onRemove: (id: string) => void
@@ -52,6 +52,22 @@ export function DeviceCard({ device, roles, onApprove, onDeny, onAllocate, onRem
   const [selectedRole, setSelectedRole] = useState(roles[0]?.id ?? '')
   const [memInput, setMemInput] = useState(String(device.allocated_memory_mb || 512))
   const [showActions, setShowActions] = useState(false)
+  const [pairingCode, setPairingCode] = useState('')
+  const [approveError, setApproveError] = useState<string | null>(null)
+  /** Why the agent didn't answer when the device was approved */
+  const [verifyError, setVerifyError] = useState<string | null>(null)
+  const needsCode = !!device.pairing_expires_at
+
+  const approve = async () => {
+    setApproveError(null)
+    try {
+      const approved = await onApprove(device.id, selectedRole, needsCode ? pairingCode.trim() : undefined)
+      setPairingCode('')
+      setVerifyError(approved.verification && !approved.verification.ok ? approved.verification.error ?? null : null)
+    } catch (e) {
+      setApproveError(e instanceof Error ? e.message : String(e))
+    }
+  }
 
   // Sync selectedRole when roles list loads asynchronously
   useEffect(() => {
@@ -125,9 +141,26 @@ export function DeviceCard({ device, roles, onApprove, onDeny, onAllocate, onRem
               ))}
             </select>
           </div>
+          {needsCode && (
+            <input
+              className="input text-xs py-1 font-mono tracking-widest"
+              placeholder="Pairing code shown by the agent"
+              inputMode="numeric"
+              maxLength={6}
+              value={pairingCode}
+              onChange={e => setPairingCode(e.target.value.replace(/\D/g, ''))}
+            />
+          )}
+          {device.pairing_locked && (
+            <p className="text-xs text-danger">
+              Locked after too many wrong pairing codes. It can be approved once its agent checks in with the enrollment token.
+            </p>
+          )}
+          {approveError && <p className="text-xs text-danger">{approveError}</p>}
           <div className="flex gap-2">
             <button
-              onClick={() => onApprove(device.id, selectedRole)}
+              onClick={approve}
+              disabled={device.pairing_locked || (needsCode && pairingCode.length !== 6)}
               className="btn-success flex-1 text-xs py-1.5"
             >
               <Check size={14} /> Approve
@@ -142,6 +175,10 @@ export function DeviceCard({ device, roles, onApprove, onDeny, onAllocate, onRem
         </div>
       )}
 
+      {verifyError && device.rpc_status !== 'ready' && (
+        <p className="text-xs text-warning mt-2">{verifyError}</p>
+      )}
+
       {/* Memory allocation (approved only) */}
       {device.status === 'approved' && showActions && (
         <div className="border-t border-border pt-3 space-y-2">
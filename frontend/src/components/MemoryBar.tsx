@@ -41,7 +41,10 @@ export function MemoryBar({ snapshot, compact = false }: MemoryBarProps) {
     <div className={clsx('space-y-2', compact ? 'py-1' : 'py-2')}>
       <div className="flex items-center justify-between text-sm">
         <span className="text-gray-200 font-medium">{snapshot.name}</span>
-        <span className="text-muted text-xs">{kindLabel[snapshot.kind]}</span>
+        <span className="text-muted text-xs">
+          {snapshot.stale && <span className="text-warning mr-2" title="The provider didn't answer the latest sample">stale</span>}
+          {kindLabel[snapshot.kind]}
+        </span>
       </div>
 
       {/* Used bar */}
@@ -76,6 +79,12 @@ export function MemoryBar({ snapshot, compact = false }: MemoryBarProps) {
         <span className="text-muted">Free</span>
         <span className="text-success">{fmt(snapshot.free_mb)}</span>
       </div>
+      {snapshot.usable_free_mb < snapshot.free_mb && (
+        <div className="flex justify-between text-xs">
+          <span className="text-muted">Usable for models</span>
+          <span className="text-gray-300">{fmt(snapshot.usable_free_mb)}</span>
+        </div>
+      )}
     </div>
   )
 }
@@ -33,7 +33,13 @@ export function RoleEditor({ roles, onRefresh }: RoleEditorProps) {
 
   const handleDelete = async (id: string) => {
     if (!confirm('Delete this role?')) return
-    await api.deleteRole(id)
+    try {
+      await api.deleteRole(id)
+    } catch (e) {
+      // Devices still use the role: offer to move them to guest first
+      if (!confirm(`${(e as Error).message}\n\nMove them to the guest role and delete?`)) return
+      await api.deleteRole(id, 'role-guest')
+    }
     onRefresh()
   }
 
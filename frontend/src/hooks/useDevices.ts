@@ -1,5 +1,5 @@
 import { useState, useEffect, useCallback } from 'react'
-import type { Device } from '../types'
+import type { AddedDevice, ApprovedDevice, Device } from '../types'
 import { api } from '../lib/api'
 
 export function useDevices() {
@@ -21,9 +21,13 @@ export function useDevices() {
 
   useEffect(() => { fetch() }, [fetch])
 
-  const approve = useCallback(async (id: string, role_id?: string) => {
-    await api.approveDevice(id, role_id)
-    await fetch()
+  const approve = useCallback(async (id: string, role_id?: string, pairing_code?: string): Promise<ApprovedDevice> => {
+    try {
+      return await api.approveDevice(id, role_id, pairing_code)
+    } finally {
+      // A wrong code still changes the device (attempts, lock)
+      await fetch()
+    }
   }, [fetch])
 
   const deny = useCallback(async (id: string) => {
@@ -41,9 +45,10 @@ export function useDevices() {
     await fetch()
   }, [fetch])
 
-  const add = useCallback(async (name: string, ip: string, mac?: string) => {
-    await api.addDevice({ name, ip, mac })
+  const add = useCallback(async (name: string, ip: string, mac?: string): Promise<AddedDevice> => {
+    const device = await api.addDevice({ name, ip, mac })
     await fetch()
+    return device
   }, [fetch])
 
   return { devices, loading, error, refresh: fetch, approve, deny, allocate, remove, add }
@@ -1,8 +1,12 @@
 import { useEffect, useRef, useCallback } from 'react'
-import type { WsEvent } from '../types'
+import type { WsEnvelope, WsEvent } from '../types'
+import { api } from '../lib/api'
 
-const WS_URL = (import.meta.env.VITE_API_URL ?? 'http://localhost:8080')
-  .replace(/^http/, 'ws') + '/ws'
+/** Carries the dashboard key, if any, as `?token=` */
+const WS_URL = api.ws
+
+/** /ws protocol this client speaks; events arrive wrapped in a WsEnvelope */
+const WS_PROTOCOL_VERSION = 5
 
 type Handler = (event: WsEvent) => void
 
@@ -19,12 +23,13 @@ export function useWebSocket(onEvent: Handler) {
 
       socket.onopen = () => {
         console.log('[WS] connected')
+        socket.send(JSON.stringify({ type: 'hello', v: WS_PROTOCOL_VERSION }))
       }
 
       socket.onmessage = (e) => {
         try {
-          const event = JSON.parse(e.data) as WsEvent
-          handlerRef.current(event)
+          const envelope = JSON.parse(e.data) as WsEnvelope
+          handlerRef.current(envelope.event)
         } catch {
           console.warn('[WS] invalid message:', e.data)
         }
@@ -1,6 +1,23 @@
 const API_BASE = import.meta.env.VITE_API_URL ?? 'http://localhost:8080'
 const WS_BASE = API_BASE.replace(/^http/, 'ws')
 
+/**
+ * Viewer or admin key for `require_dashboard_auth`. Open the dashboard once
+ * with `?token=<key>` (e.g. on a kiosk) and it is kept in localStorage.
+ */
+const TOKEN_STORAGE_KEY = 'sharedllm_token'
+const urlToken = new URLSearchParams(window.location.search).get('token')
+if (urlToken) localStorage.setItem(TOKEN_STORAGE_KEY, urlToken)
+const dashboardToken = urlToken ?? localStorage.getItem(TOKEN_STORAGE_KEY)
+
+/** fetch with the dashboard key, when there is one, as a bearer token */
+function apiFetch(input: string, init: RequestInit = {}): Promise<Response> {
+  if (!dashboardToken) return fetch(input, init)
+  const headers = new Headers(init.headers)
+  headers.set('Authorization', `Bearer ${dashboardToken}`)
+  return fetch(input, { ...init, headers })
+}
+
 /** Throw an error containing the response body if the HTTP status is not 2xx. */
 async function checkOk(r: Response): Promise<Response> {
   if (!r.ok) {
@@ -16,92 +33,334 @@ async function checkOk(r: Response): Promise<Response> {
 
 export const api = {
   base: API_BASE,
-  ws: `${WS_BASE}/ws`,
+  /** Browsers can't set headers on a WebSocket, so the key goes in the URL */
+  ws: dashboardToken ? `${WS_BASE}/ws?token=${encodeURIComponent(dashboardToken)}` : `${WS_BASE}/ws`,
 
   // Devices
-  devices: () => fetch(`${API_BASE}/api/devices`).then(checkOk).then(r => r.json()),
-  getDevice: (id: string) => fetch(`${API_BASE}/api/devices/${id}`).then(checkOk).then(r => r.json()),
+  /** Optionally only devices with `tag`, or matching `search` in name, hostname, IP, notes or labels;
+   *  `includeDeleted` adds soft-deleted devices after the others */
+  devices: (tag?: string, search?: string, includeDeleted = false) => {
+    const params = new URLSearchParams()
+    if (tag) params.set('tag', tag)
+    if (search) params.set('search', search)
+    if (includeDeleted) params.set('include_deleted', 'true')
+    const query = params.toString()
+    return apiFetch(`${API_BASE}/api/devices${query ? `?${query}` : ''}`).then(checkOk).then(r => r.json())
+  },
+  /** The device plus its allocations, role limits and recent activity: `DeviceWithDetail` */
+  getDevice: (id: string) => apiFetch(`${API_BASE}/api/devices/${id}`).then(checkOk).then(r => r.json()),
   addDevice: (body: { name: string; ip: string; mac?: string }) =>
-    fetch(`${API_BASE}/api/devices`, {
+    apiFetch(`${API_BASE}/api/devices`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify(body),
     }).then(checkOk).then(r => r.json()),
-  approveDevice: (id: string, role_id?: string) =>
-    fetch(`${API_BASE}/api/devices/${id}/approve`, {
+  /** A JSON array of rows, or CSV text with the header `name,ip,mac,role_id,auto_approve,notes,labels` */
+  importDevices: (
+    rows: {
+      name: string
+      ip: string
+      mac?: string
+      role_id?: string
+      auto_approve?: boolean
+      notes?: string
+      labels?: Record<string, string>
+    }[] | string,
+  ) =>
+    apiFetch(`${API_BASE}/api/devices/import`, {
+      method: 'POST',
+      headers: { 'Content-Type': typeof rows === 'string' ? 'text/csv' : 'application/json' },
+      body: typeof rows === 'string' ? rows : JSON.stringify(rows),
+    }).then(checkOk).then(r => r.json()),
+  /** `{imports: DeviceImport[]}`, newest first */
+  deviceImports: () =>
+    apiFetch(`${API_BASE}/api/devices/imports`).then(checkOk).then(r => r.json()),
+  /** Approve and, unless `verify` is false, probe the agent right away */
+  approveDevice: (id: string, role_id?: string, pairing_code?: string, verify?: boolean) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/approve`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({ role_id }),
+      body: JSON.stringify({ role_id, pairing_code, verify }),
     }).then(checkOk).then(r => r.json()),
   denyDevice: (id: string) =>
-    fetch(`${API_BASE}/api/devices/${id}/deny`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+    apiFetch(`${API_BASE}/api/devices/${id}/deny`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+  /** Take an approved device out of service and revoke its allocations; approve to restore */
+  suspendDevice: (id: string) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/suspend`, { method: 'POST' }).then(checkOk).then(r => r.json()),
   allocateMemory: (id: string, memory_mb: number) =>
-    fetch(`${API_BASE}/api/devices/${id}/memory`, {
+    apiFetch(`${API_BASE}/api/devices/${id}/memory`, {
       method: 'PATCH',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify({ memory_mb }),
     }).then(checkOk).then(r => r.json()),
-  deleteDevice: (id: string) =>
-    fetch(`${API_BASE}/api/devices/${id}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  /** Set the device's memory by hand (`manual_override: true`), or let probes update it again: `Device` */
+  setMemoryStats: (
+    id: string,
+    body: { memory_total_mb?: number; memory_free_mb?: number; manual_override: boolean },
+  ) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/memory-stats`, {
+      method: 'PATCH',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  setDeviceTags: (id: string, tags: string[]) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/tags`, {
+      method: 'PATCH',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ tags }),
+    }).then(checkOk).then(r => r.json()),
+  deviceHealth: (id: string) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/health`).then(checkOk).then(r => r.json()),
+  resetDeviceHealth: (id: string) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/reset-health`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+  /** Restart the device's llama-rpc-server: `RpcRestarted` */
+  restartDeviceRpc: (id: string) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/rpc/restart`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+  updateDevice: (
+    id: string,
+    body: {
+      name?: string
+      rpc_port?: number
+      extra_rpc_ports?: number[]
+      is_peer?: boolean
+      device_kind?: 'agent' | 'peer' | 'unknown'
+      /** Empty string clears them */
+      notes?: string
+      /** Replaces the set; an empty value drops a label */
+      labels?: Record<string, string>
+    },
+  ) =>
+    apiFetch(`${API_BASE}/api/devices/${id}`, {
+      method: 'PATCH',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  /** Soft-delete, so `restoreDevice` can undo it; `purge` deletes for good */
+  deleteDevice: (id: string, purge = false) =>
+    apiFetch(`${API_BASE}/api/devices/${id}${purge ? '?purge=true' : ''}`, { method: 'DELETE' })
+      .then(checkOk).then(r => r.json()),
+  /** Bring back a soft-deleted device with its status, role, tags and allocation */
+  restoreDevice: (id: string) =>
+    apiFetch(`${API_BASE}/api/devices/${id}/restore`, { method: 'POST' }).then(checkOk).then(r => r.json()),
 
   // GPU
-  gpuStats: () => fetch(`${API_BASE}/api/gpu`).then(checkOk).then(r => r.json()),
+  gpuStats: () => apiFetch(`${API_BASE}/api/gpu`).then(checkOk).then(r => r.json()),
+  refreshGpuStats: () =>
+    apiFetch(`${API_BASE}/api/gpu/refresh`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+  redetectProviders: () =>
+    apiFetch(`${API_BASE}/api/gpu/redetect`, { method: 'POST' }).then(checkOk).then(r => r.json()),
 
   // Models
-  models: () => fetch(`${API_BASE}/api/models`).then(checkOk).then(r => r.json()),
+  models: () => apiFetch(`${API_BASE}/api/models`).then(checkOk).then(r => r.json()),
+  modelDetails: (name: string) =>
+    apiFetch(`${API_BASE}/api/models/${encodeURIComponent(name)}/details`).then(checkOk).then(r => r.json()),
   /**
    * Pull a model — returns a ReadableStream of NDJSON progress lines.
    * Caller is responsible for reading the stream and parsing each line.
    */
   pullModel: (name: string) =>
-    fetch(`${API_BASE}/api/models/pull`, {
+    apiFetch(`${API_BASE}/api/models/pull`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify({ name }),
     }),
+  /** Queue a pull for the off-peak window and/or no earlier than `not_before` (RFC 3339). */
+  schedulePull: (name: string, opts: { off_peak?: boolean; not_before?: string }) =>
+    apiFetch(`${API_BASE}/api/models/pull`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        name,
+        schedule: opts.off_peak ? 'off_peak' : undefined,
+        not_before: opts.not_before,
+      }),
+    }).then(checkOk).then(r => r.json()),
+  pullQueue: () =>
+    apiFetch(`${API_BASE}/api/models/pull/queue`).then(checkOk).then(r => r.json()),
+  cancelPull: (id: string) =>
+    apiFetch(`${API_BASE}/api/models/pull/queue/${encodeURIComponent(id)}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  /** Models this host shares with approved devices (needs `share_models`). */
+  modelFiles: () =>
+    apiFetch(`${API_BASE}/api/models/files`).then(checkOk).then(r => r.json()),
+  /** Download a model from a peer host — returns a stream of NDJSON progress lines, like pullModel. */
+  fetchModelFromPeer: (peerDeviceId: string, alias: string) =>
+    apiFetch(`${API_BASE}/api/models/fetch-from-peer`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ peer_device_id: peerDeviceId, alias }),
+    }),
+  initUpload: (file_name: string, size: number, alias?: string) =>
+    apiFetch(`${API_BASE}/api/models/upload`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ file_name, size, alias }),
+    }).then(checkOk).then(r => r.json()),
+  uploadStatus: (id: string) =>
+    apiFetch(`${API_BASE}/api/models/upload/${encodeURIComponent(id)}`).then(checkOk).then(r => r.json()),
+  /** Raw fetch: a 409 carries the offset to resume from */
+  uploadChunk: (id: string, offset: number, chunk: Blob) =>
+    apiFetch(`${API_BASE}/api/models/upload/${encodeURIComponent(id)}/chunk?offset=${offset}`, {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/octet-stream' },
+      body: chunk,
+    }),
+  finalizeUpload: (id: string) =>
+    apiFetch(`${API_BASE}/api/models/upload/${encodeURIComponent(id)}/finalize`, { method: 'POST' })
+      .then(checkOk)
+      .then(r => r.json()),
   deleteModel: (name: string) =>
-    fetch(`${API_BASE}/api/models/${encodeURIComponent(name)}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
-  ollamaStatus: () => fetch(`${API_BASE}/api/ollama/status`).then(checkOk).then(r => r.json()),
+    apiFetch(`${API_BASE}/api/models/${encodeURIComponent(name)}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  /** Disk usage of the .gguf files in the model directories: `ModelUsage` */
+  modelUsage: () => apiFetch(`${API_BASE}/api/cluster/models/usage`).then(checkOk).then(r => r.json()),
+  /** Move a model file to the trash; 409 while the running session uses it */
+  deleteModelFile: (path: string) =>
+    apiFetch(`${API_BASE}/api/cluster/models?path=${encodeURIComponent(path)}`, { method: 'DELETE' })
+      .then(checkOk)
+      .then(r => r.json()),
+  modelAliases: () => apiFetch(`${API_BASE}/api/models/aliases`).then(checkOk).then(r => r.json()),
+  createModelAlias: (alias: string, path: string) =>
+    apiFetch(`${API_BASE}/api/models/aliases`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ alias, path }),
+    }).then(checkOk).then(r => r.json()),
+  updateModelAlias: (alias: string, path: string) =>
+    apiFetch(`${API_BASE}/api/models/aliases/${encodeURIComponent(alias)}`, {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ path }),
+    }).then(checkOk).then(r => r.json()),
+  deleteModelAlias: (alias: string) =>
+    apiFetch(`${API_BASE}/api/models/aliases/${encodeURIComponent(alias)}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  ollamaStatus: () => apiFetch(`${API_BASE}/api/ollama/status`).then(checkOk).then(r => r.json()),
+  /** Models Ollama keeps in memory: `{models: OllamaRunningModel[]}` */
+  ollamaPs: () => apiFetch(`${API_BASE}/api/ollama/ps`).then(checkOk).then(r => r.json()),
+  ollamaUnload: (model: string) =>
+    apiFetch(`${API_BASE}/api/ollama/unload`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ model }),
+    }).then(checkOk).then(r => r.json()),
 
   // Permissions
-  roles: () => fetch(`${API_BASE}/api/permissions/roles`).then(checkOk).then(r => r.json()),
+  roles: () => apiFetch(`${API_BASE}/api/permissions/roles`).then(checkOk).then(r => r.json()),
   createRole: (body: { name: string; max_memory_mb: number; can_pull_models: boolean; trust_level: number }) =>
-    fetch(`${API_BASE}/api/permissions/roles`, {
+    apiFetch(`${API_BASE}/api/permissions/roles`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify(body),
     }).then(checkOk).then(r => r.json()),
   updateRole: (id: string, body: { name: string; max_memory_mb: number; can_pull_models: boolean; trust_level: number }) =>
-    fetch(`${API_BASE}/api/permissions/roles/${id}`, {
+    apiFetch(`${API_BASE}/api/permissions/roles/${id}`, {
       method: 'PUT',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify(body),
     }).then(checkOk).then(r => r.json()),
-  deleteRole: (id: string) =>
-    fetch(`${API_BASE}/api/permissions/roles/${id}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  /** 409 while devices use the role, unless `reassign_to` names a role to move them to */
+  deleteRole: (id: string, reassign_to?: string) =>
+    apiFetch(`${API_BASE}/api/permissions/roles/${id}${reassign_to ? `?reassign_to=${encodeURIComponent(reassign_to)}` : ''}`, { method: 'DELETE' })
+      .then(checkOk).then(r => r.json()),
 
   // Settings
-  settings: () => fetch(`${API_BASE}/api/settings`).then(checkOk).then(r => r.json()),
+  settings: () => apiFetch(`${API_BASE}/api/settings`).then(checkOk).then(r => r.json()),
   updateSetting: (key: string, value: string) =>
-    fetch(`${API_BASE}/api/settings/${key}`, {
+    apiFetch(`${API_BASE}/api/settings/${key}`, {
       method: 'PUT',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify({ value }),
     }).then(checkOk).then(r => r.json()),
 
+  // First-run setup
+  setupStatus: () => apiFetch(`${API_BASE}/api/setup/status`).then(checkOk).then(r => r.json()),
+  setupInit: () =>
+    apiFetch(`${API_BASE}/api/setup/init`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+
+  // Webhooks
+  webhooks: () => apiFetch(`${API_BASE}/api/webhooks`).then(checkOk).then(r => r.json()),
+  createWebhook: (body: { url: string; secret?: string; events?: string[]; enabled?: boolean }) =>
+    apiFetch(`${API_BASE}/api/webhooks`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  updateWebhook: (id: string, body: { url: string; secret?: string; events?: string[]; enabled?: boolean }) =>
+    apiFetch(`${API_BASE}/api/webhooks/${id}`, {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  deleteWebhook: (id: string) =>
+    apiFetch(`${API_BASE}/api/webhooks/${id}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  testWebhook: (id: string) =>
+    apiFetch(`${API_BASE}/api/webhooks/${id}/test`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+
+  // API keys for /v1 (enforced while `require_api_keys` is "true")
+  apiKeys: () =>
+    apiFetch(`${API_BASE}/api/keys`).then(checkOk).then(r => r.json()),
+  createApiKey: (body: { name: string; rate_limit_rpm?: number; allowed_models?: string[]; scope?: 'inference' | 'viewer' | 'admin' }) =>
+    apiFetch(`${API_BASE}/api/keys`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  updateApiKey: (id: string, body: { name: string; rate_limit_rpm?: number; allowed_models?: string[]; scope?: 'inference' | 'viewer' | 'admin' }) =>
+    apiFetch(`${API_BASE}/api/keys/${id}`, {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  /** Revoke; the key stays listed with `revoked_at` set */
+  revokeApiKey: (id: string) =>
+    apiFetch(`${API_BASE}/api/keys/${id}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+
   // Cluster / Distributed inference
-  clusterStatus: () =>
-    fetch(`${API_BASE}/api/cluster/status`).then(checkOk).then(r => r.json()),
+  /** `sort` orders devices by reported hardware, highest first */
+  clusterStatus: (sort?: 'cpu_cores' | 'driver_version') =>
+    apiFetch(`${API_BASE}/api/cluster/status${sort ? `?sort=${sort}` : ''}`).then(checkOk).then(r => r.json()),
   inferenceStatus: () =>
-    fetch(`${API_BASE}/api/cluster/inference/status`).then(checkOk).then(r => r.json()),
+    apiFetch(`${API_BASE}/api/cluster/inference/status`).then(checkOk).then(r => r.json()),
+  /** Memory, devices, inference, Ollama and errors in one response: `Overview`.
+   *  With `model` (path or alias), warns if that session won't fit. */
+  overview: (model?: string, ctxSize?: number) => {
+    const q = new URLSearchParams()
+    if (model) q.set('model', model)
+    if (ctxSize) q.set('ctx_size', String(ctxSize))
+    const qs = q.toString()
+    return apiFetch(`${API_BASE}/api/overview${qs ? `?${qs}` : ''}`).then(checkOk).then(r => r.json())
+  },
+  /** The last 50 background problems, newest first: `{errors: ErrorEntry[]}` */
+  recentErrors: () => apiFetch(`${API_BASE}/api/errors/recent`).then(checkOk).then(r => r.json()),
+  /** Open /ws connections, oldest first */
+  wsClients: () => apiFetch(`${API_BASE}/api/ws/clients`).then(checkOk).then(r => r.json()),
+  closeWsClient: (id: string) =>
+    apiFetch(`${API_BASE}/api/ws/clients/${id}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  /** Recent backend log lines; pass a request's x-request-id to get its trail. */
+  recentLogs: (requestId?: string, limit?: number) => {
+    const params = new URLSearchParams()
+    if (requestId) params.set('request_id', requestId)
+    if (limit) params.set('limit', String(limit))
+    return apiFetch(`${API_BASE}/api/logs/recent?${params}`).then(checkOk).then(r => r.json())
+  },
+  inferenceMetrics: () =>
+    apiFetch(`${API_BASE}/api/cluster/inference/metrics`).then(checkOk).then(r => r.json()),
   /**
    * Check how a model fits into the available local + cluster memory.
    * Returns a ModelCheckResult with fit status, recommended settings, and warnings.
    */
-  modelCheck: (path: string, deviceIds: string[]) => {
+  modelCheck: (
+    path: string,
+    deviceIds: string[],
+    draftPath?: string,
+    fresh?: boolean,
+    slots?: { ctx_size?: number; parallel?: number },
+  ) => {
     const params = new URLSearchParams({ path })
     if (deviceIds.length > 0) params.set('device_ids', deviceIds.join(','))
-    return fetch(`${API_BASE}/api/cluster/model-check?${params}`)
+    if (draftPath) params.set('draft_path', draftPath)
+    if (fresh) params.set('fresh', 'true')
+    if (slots?.ctx_size) params.set('ctx_size', String(slots.ctx_size))
+    if (slots?.parallel) params.set('parallel', String(slots.parallel))
+    return apiFetch(`${API_BASE}/api/cluster/model-check?${params}`)
       .then(checkOk)
       .then(r => r.json())
   },
@@ -110,34 +369,119 @@ export const api = {
     device_ids: string[],
     n_gpu_layers?: number,
     ctx_size?: number,
+    device_tags?: string[],
+    gpu_selector?: {
+      cuda_visible_devices?: string
+      hip_visible_devices?: string
+      main_gpu?: number
+      split_mode?: 'none' | 'layer' | 'row'
+      metal_ndebug?: boolean
+    },
+    dry_run?: boolean,
+    parallel?: number,
+    draft?: { draft_model_path: string; draft_max?: number; draft_min?: number },
+    skip_unreachable?: boolean,
+    cont_batching?: boolean,
+    mmproj_path?: string,
+    /** Built-in chat template (e.g. llama3, chatml) and a system prompt the proxy adds */
+    prompting?: { chat_template?: string; system_prompt?: string },
+    /** A repeat with the same key within 10 minutes gets the first start's response */
+    idempotency_key?: string,
   ) =>
-    fetch(`${API_BASE}/api/cluster/inference/start`, {
+    apiFetch(`${API_BASE}/api/cluster/inference/start`, {
+      method: 'POST',
+      headers: {
+        'Content-Type': 'application/json',
+        ...(idempotency_key ? { 'Idempotency-Key': idempotency_key } : {}),
+      },
+      body: JSON.stringify({ model_path, device_ids, n_gpu_layers, ctx_size, device_tags, gpu_selector, dry_run, parallel, skip_unreachable, cont_batching, mmproj_path, ...draft, ...prompting }),
+    }).then(checkOk).then(r => r.json()),
+  /** Start the last recorded session again; `skipped` lists devices left out. */
+  resumeLastSession: () =>
+    apiFetch(`${API_BASE}/api/cluster/inference/resume-last`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+  listPresets: () =>
+    apiFetch(`${API_BASE}/api/cluster/presets`).then(checkOk).then(r => r.json()),
+  /** Save start parameters; the options (parallel, draft model, …) are top-level fields. */
+  createPreset: (preset: {
+    name: string
+    model_path: string
+    device_ids?: string[]
+    device_tags?: string[]
+    n_gpu_layers?: number
+    ctx_size?: number
+  }) =>
+    apiFetch(`${API_BASE}/api/cluster/presets`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(preset),
+    }).then(checkOk).then(r => r.json()),
+  deletePreset: (id: string) =>
+    apiFetch(`${API_BASE}/api/cluster/presets/${id}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  /** Start a preset; `skipped`, `skipped_draft` and `changed` say what differs from when it was saved. */
+  startPreset: (id: string, dry_run?: boolean) =>
+    apiFetch(`${API_BASE}/api/cluster/presets/${id}/start`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify({ model_path, device_ids, n_gpu_layers, ctx_size }),
+      body: JSON.stringify({ dry_run }),
+    }).then(checkOk).then(r => r.json()),
+  clusterPorts: () =>
+    apiFetch(`${API_BASE}/api/cluster/ports`).then(checkOk).then(r => r.json()),
+  clusterBinaries: () =>
+    apiFetch(`${API_BASE}/api/cluster/binaries`).then(checkOk).then(r => r.json()),
+  stopInference: (opts?: { force?: boolean; timeout_secs?: number }) =>
+    apiFetch(`${API_BASE}/api/cluster/inference/stop`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(opts ?? {}),
     }).then(checkOk).then(r => r.json()),
-  stopInference: () =>
-    fetch(`${API_BASE}/api/cluster/inference/stop`, { method: 'POST' }).then(checkOk).then(r => r.json()),
   startRpcServer: () =>
-    fetch(`${API_BASE}/api/cluster/rpc/start`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+    apiFetch(`${API_BASE}/api/cluster/rpc/start`, { method: 'POST' }).then(checkOk).then(r => r.json()),
   stopRpcServer: () =>
-    fetch(`${API_BASE}/api/cluster/rpc/stop`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+    apiFetch(`${API_BASE}/api/cluster/rpc/stop`, { method: 'POST' }).then(checkOk).then(r => r.json()),
   /**
    * Download + install llama-server and llama-rpc-server into ~/.sharedmem/bin/.
    * Returns a raw Response — caller reads the body as NDJSON progress stream.
    */
+  /** Free space where downloads land, and orphaned .partial files */
+  /** CPU, OS, GPU drivers and model-volume space of this host (cached a few minutes) */
+  systemInfo: () =>
+    apiFetch(`${API_BASE}/api/system/info`).then(checkOk).then(r => r.json()),
+  /** Self-test of binaries, ports, Ollama, mDNS and network; takes a few seconds */
+  diagnostics: () =>
+    apiFetch(`${API_BASE}/api/diagnostics`).then(checkOk).then(r => r.json()),
+  diskReport: () =>
+    apiFetch(`${API_BASE}/api/system/disk`).then(checkOk).then(r => r.json()),
+  diskCleanup: () =>
+    apiFetch(`${API_BASE}/api/system/disk/cleanup`, { method: 'POST' }).then(checkOk).then(r => r.json()),
+  /** Database backups: `BackupList` */
+  backups: () => apiFetch(`${API_BASE}/api/system/backups`).then(checkOk).then(r => r.json()),
+  /** Restore a backup; the server restarts when it can: `RestoreStarted` */
+  restoreBackup: (name: string) =>
+    apiFetch(`${API_BASE}/api/system/backups/restore`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ name }),
+    }).then(checkOk).then(r => r.json()),
   installBinaries: () =>
-    fetch(`${API_BASE}/api/cluster/install-binaries`, { method: 'POST' }),
+    apiFetch(`${API_BASE}/api/cluster/install-binaries`, { method: 'POST' }),
 
   // Agent install info
-  agentInfo: () =>
-    fetch(`${API_BASE}/agent/info`).then(checkOk).then(r => r.json()),
+  agentInfo: (host?: string, backend?: string, memMb?: number) => {
+    const params = new URLSearchParams()
+    if (host) params.set('host', host)
+    if (backend) params.set('backend', backend)
+    if (memMb) params.set('mem_mb', String(memMb))
+    const qs = params.toString()
+    return apiFetch(`${API_BASE}/agent/info${qs ? `?${qs}` : ''}`).then(checkOk).then(r => r.json())
+  },
   agentInstallUrl: (os: 'linux' | 'macos' | 'windows') =>
     `${API_BASE}/agent/install?os=${os}`,
+  supportBundleUrl: (redactIps = false) =>
+    `${API_BASE}/api/support-bundle${redactIps ? '?redact_ips=true' : ''}`,
 
   // OpenAI-compatible chat (proxied to llama-server)
   chatCompletions: (messages: Array<{ role: string; content: string }>, model = 'local', stream = false) =>
-    fetch(`${API_BASE}/v1/chat/completions`, {
+    apiFetch(`${API_BASE}/v1/chat/completions`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify({ model, messages, stream }),
@@ -145,16 +489,34 @@ export const api = {
 
   // Inference backend config
   backendConfig: () =>
-    fetch(`${API_BASE}/api/backends/config`).then(checkOk).then(r => r.json()),
+    apiFetch(`${API_BASE}/api/backends/config`).then(checkOk).then(r => r.json()),
   setBackendConfig: (config: { backend_type: string; url: string; model: string; api_key?: string }) =>
-    fetch(`${API_BASE}/api/backends/config`, {
+    apiFetch(`${API_BASE}/api/backends/config`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
       body: JSON.stringify(config),
     }).then(checkOk).then(r => r.json()),
+  backendProfiles: () =>
+    apiFetch(`${API_BASE}/api/backends/profiles`).then(checkOk).then(r => r.json()),
+  createBackendProfile: (body: { name: string; backend_type: string; url: string; model: string; api_key?: string }) =>
+    apiFetch(`${API_BASE}/api/backends/profiles`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  updateBackendProfile: (id: string, body: { name: string; backend_type: string; url: string; model: string; api_key?: string }) =>
+    apiFetch(`${API_BASE}/api/backends/profiles/${id}`, {
+      method: 'PUT',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify(body),
+    }).then(checkOk).then(r => r.json()),
+  deleteBackendProfile: (id: string) =>
+    apiFetch(`${API_BASE}/api/backends/profiles/${id}`, { method: 'DELETE' }).then(checkOk).then(r => r.json()),
+  activateBackendProfile: (id: string) =>
+    apiFetch(`${API_BASE}/api/backends/profiles/${id}/activate`, { method: 'POST' }).then(checkOk).then(r => r.json()),
   fetchBackendModels: (backend_type: string, url: string, api_key?: string) => {
     const params = new URLSearchParams({ type: backend_type, url })
     if (api_key) params.set('api_key', api_key)
-    return fetch(`${API_BASE}/api/backends/models?${params}`).then(checkOk).then(r => r.json()) as Promise<string[]>
+    return apiFetch(`${API_BASE}/api/backends/models?${params}`).then(checkOk).then(r => r.json()) as Promise<string[]>
   },
 }
@@ -3,7 +3,7 @@ import { Link } from 'react-router-dom'
 import { Copy, Check, Terminal, Monitor, Server } from 'lucide-react'
 import { clsx } from 'clsx'
 import { api } from '../lib/api'
-import type { AgentInfo } from '../types'
+import type { AgentInfo, RpcBackend } from '../types'
 
 // ─── Copy button ──────────────────────────────────────────────────────────────
 
@@ -55,18 +55,33 @@ const osTabs: { id: OsTab; label: string; icon: React.ElementType }[] = [
   { id: 'windows', label: 'Windows', icon: Server },
 ]
 
+// Backends each OS has a llama.cpp build for; '' keeps the build's default
+const backendOptions: Record<OsTab, RpcBackend[]> = {
+  linux: ['cuda', 'cpu'],
+  macos: ['metal', 'cpu'],
+  windows: ['cuda', 'cpu'],
+}
+
 // ─── Main Page ────────────────────────────────────────────────────────────────
 
 export function AgentPage() {
   const [info, setInfo] = useState<AgentInfo | null>(null)
   const [activeOs, setActiveOs] = useState<OsTab>('linux')
+  const [persist, setPersist] = useState(true)
+  const [backend, setBackend] = useState<RpcBackend | ''>('')
+  const [memMb, setMemMb] = useState('')
   const [error, setError] = useState<string | null>(null)
 
   useEffect(() => {
-    api.agentInfo()
-      .then(setInfo)
+    api.agentInfo(undefined, backend || undefined, Number(memMb) || undefined)
+      .then(info => { setInfo(info); setError(null) })
       .catch(e => setError(e.message))
-  }, [])
+  }, [backend, memMb])
+
+  function selectOs(os: OsTab) {
+    setActiveOs(os)
+    if (backend && !backendOptions[os].includes(backend)) setBackend('')
+  }
 
   return (
     <div className="p-6 space-y-6">
@@ -123,7 +138,7 @@ export function AgentPage() {
           {osTabs.map(({ id, label, icon: Icon }) => (
             <button
               key={id}
-              onClick={() => setActiveOs(id)}
+              onClick={() => selectOs(id)}
               className={clsx(
                 'flex items-center gap-1.5 px-3 py-1.5 rounded-md text-xs font-medium transition-colors',
                 activeOs === id
@@ -137,9 +152,52 @@ export function AgentPage() {
           ))}
         </div>
 
+        <label className="flex items-center gap-2 text-xs text-muted mb-3 cursor-pointer">
+          <input
+            type="checkbox"
+            checked={persist}
+            onChange={e => setPersist(e.target.checked)}
+          />
+          Install as a service (starts at boot, restarts on crash)
+        </label>
+
+        <div className="flex flex-wrap items-center gap-3 text-xs text-muted mb-3">
+          <label className="flex items-center gap-2">
+            Backend
+            <select
+              className="input text-xs py-1"
+              value={backend}
+              onChange={e => setBackend(e.target.value as RpcBackend | '')}
+            >
+              <option value="">Build default</option>
+              {backendOptions[activeOs].map(b => (
+                <option key={b} value={b}>{b.toUpperCase()}</option>
+              ))}
+            </select>
+          </label>
+          <label className="flex items-center gap-2">
+            Memory cap (MB)
+            <input
+              type="number"
+              min={1}
+              className="input text-xs py-1 w-28"
+              placeholder="No cap"
+              value={memMb}
+              onChange={e => setMemMb(e.target.value)}
+            />
+          </label>
+        </div>
+        {backend === 'cuda' && activeOs === 'linux' && (
+          <p className="text-xs text-muted mb-3">
+            llama.cpp publishes no Linux CUDA build: the script uses a local one, found in{' '}
+            <code className="font-mono">PATH</code> or given as{' '}
+            <code className="font-mono">LLAMA_RPC_SERVER=/path/to/rpc-server</code>.
+          </p>
+        )}
+
         {info && (
           <CodeBlock
-            code={info.install_commands[activeOs]}
+            code={(persist ? info.persistent_install_commands : info.install_commands)[activeOs]}
             label={`Run on the target ${activeOs} machine:`}
           />
         )}
@@ -153,6 +211,15 @@ export function AgentPage() {
             Run in PowerShell as Administrator.
           </p>
         )}
+
+        {info && (
+          <div className="mt-4">
+            <CodeBlock
+              code={info.uninstall_commands[activeOs]}
+              label="To remove the agent later:"
+            />
+          </div>
+        )}
       </div>
 
       {/* Manual install */}
@@ -1,6 +1,6 @@
 import { useState } from 'react'
 import { Plus, RefreshCw, Search } from 'lucide-react'
-import type { Device, Role } from '../types'
+import type { AddedDevice, ApprovedDevice, Device, Role } from '../types'
 import { DeviceCard } from '../components/DeviceCard'
 
 interface DevicesPageProps {
@@ -8,11 +8,11 @@ interface DevicesPageProps {
   roles: Role[]
   loading: boolean
   onRefresh: () => void
-  onApprove: (id: string, roleId?: string) => void
+  onApprove: (id: string, roleId?: string, pairingCode?: string) => Promise<ApprovedDevice>
   onDeny: (id: string) => void
   onAllocate: (id: string, mb: number) => void
   onRemove: (id: string) => void
-  onAdd: (name: string, ip: string, mac?: string) => void
+  onAdd: (name: string, ip: string, mac?: string) => Promise<AddedDevice>
 }
 
 const FILTERS = ['all', 'pending', 'approved', 'denied', 'offline'] as const
@@ -25,6 +25,7 @@ export function DevicesPage({
   const [showAdd, setShowAdd] = useState(false)
   const [addForm, setAddForm] = useState({ name: '', ip: '', mac: '' })
   const [adding, setAdding] = useState(false)
+  const [added, setAdded] = useState<AddedDevice | null>(null)
 
   const filtered = devices.filter(d => {
     const matchStatus = filter === 'all' || d.status === filter
@@ -48,7 +49,7 @@ export function DevicesPage({
     if (!addForm.name || !addForm.ip) return
     setAdding(true)
     try {
-      await onAdd(addForm.name, addForm.ip, addForm.mac || undefined)
+      setAdded(await onAdd(addForm.name, addForm.ip, addForm.mac || undefined))
       setShowAdd(false)
       setAddForm({ name: '', ip: '', mac: '' })
     } finally {
@@ -104,6 +105,17 @@ export function DevicesPage({
         </div>
       )}
 
+      {added?.pairing_code && (
+        <div className="card border-warning/40 flex items-center justify-between text-sm">
+          <span className="text-gray-300">
+            Pairing code for {added.name}:{' '}
+            <span className="font-mono text-warning text-base">{added.pairing_code}</span>
+            <span className="text-muted text-xs ml-2">enter it when approving; shown only once</span>
+          </span>
+          <button onClick={() => setAdded(null)} className="btn-ghost text-xs">Dismiss</button>
+        </div>
+      )}
+
       {/* Filters + search */}
       <div className="flex items-center gap-3 flex-wrap">
         <div className="flex bg-panel border border-border rounded-lg overflow-hidden">
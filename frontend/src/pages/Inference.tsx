@@ -1,9 +1,9 @@
 import { useState, useEffect, useRef, useCallback } from 'react'
 import { Link } from 'react-router-dom'
-import { Play, Square, Cpu, Wifi, WifiOff, Send, Loader2, RefreshCw, Download, Check, ChevronDown, AlertTriangle } from 'lucide-react'
+import { Play, Square, Cpu, Wifi, WifiOff, Send, Loader2, RefreshCw, Download, Check, ChevronDown, AlertTriangle, Bookmark, Trash2 } from 'lucide-react'
 import { clsx } from 'clsx'
 import { api } from '../lib/api'
-import type { BackendConfig, BackendType, ClusterStatus, ChatMessage, InferenceSessionInfo, ModelCheckResult, FitStatus } from '../types'
+import type { BackendConfig, BackendType, ClusterStatus, ChatMessage, InferencePreset, InferenceSessionInfo, ModelCheckResult, FitStatus, PresetStartResult } from '../types'
 
 // ─── Helpers ──────────────────────────────────────────────────────────────────
 
@@ -132,7 +132,11 @@ function ModelGuardrails({ modelPath, selectedDeviceIds, disabled, onSettingsCha
         </div>
         <div className="flex items-center gap-4 mt-1.5 text-xs text-muted">
           <span>~{analysis.estimated_layers} layers</span>
+          {analysis.shard_count > 1 && <span>{analysis.shard_count} parts</span>}
           <span>Local free: {fmt(analysis.local_free_mb)}</span>
+          {analysis.host_reserved_mb > 0 && (
+            <span>Kept for OS: {fmt(analysis.host_reserved_mb)}</span>
+          )}
           {analysis.cluster_free_mb > 0 && (
             <span>Cluster: {fmt(analysis.cluster_free_mb)}</span>
           )}
@@ -151,6 +155,17 @@ function ModelGuardrails({ modelPath, selectedDeviceIds, disabled, onSettingsCha
         </div>
       )}
 
+      {/* Suggestions for a model that doesn't fit */}
+      {analysis.suggestions.length > 0 && (
+        <div className="space-y-1">
+          {analysis.suggestions.map((s, i) => (
+            <div key={i} className="text-xs text-muted">
+              {s.message}
+            </div>
+          ))}
+        </div>
+      )}
+
       {/* GPU Layers slider */}
       <div>
         <div className="flex items-center justify-between mb-1">
@@ -438,9 +453,11 @@ function BackendSelector({ activeConfig, onActivated }: BackendSelectorProps) {
 
 // ─── Chat ─────────────────────────────────────────────────────────────────────
 
-function ChatPanel({ inferenceRunning, activeConfig }: {
+function ChatPanel({ inferenceRunning, activeConfig, sessionModel }: {
   inferenceRunning: boolean
   activeConfig: BackendConfig | null
+  /** Model loaded by llama-server; the proxy rejects any other name */
+  sessionModel?: string
 }) {
   const [messages, setMessages] = useState<ChatMessage[]>([])
   const [input, setInput] = useState('')
@@ -470,7 +487,10 @@ function ChatPanel({ inferenceRunning, activeConfig }: {
 
     const history: ChatMessage[] = [...messages, userMsg]
     const t0 = Date.now()
-    const modelName = activeConfig?.model || 'local'
+    const modelName =
+      (activeConfig?.backend_type ?? 'llamacpp') === 'llamacpp'
+        ? sessionModel ?? 'local'
+        : activeConfig?.model || 'local'
 
     try {
       const resp = await api.chatCompletions(history, modelName)
@@ -478,7 +498,7 @@ function ChatPanel({ inferenceRunning, activeConfig }: {
         const err = await resp.json().catch(() => ({ error: 'Unknown error' }))
         setMessages(prev => [
           ...prev,
-          { role: 'assistant', content: `Error: ${err.error ?? resp.status}` },
+          { role: 'assistant', content: `Error: ${err.error?.message ?? err.error ?? resp.status}` },
         ])
         return
       }
@@ -570,12 +590,155 @@ function ChatPanel({ inferenceRunning, activeConfig }: {
   )
 }
 
+// ─── Presets ──────────────────────────────────────────────────────────────────
+
+interface PresetsPanelProps {
+  modelPath: string
+  selectedDeviceIds: string[]
+  settings: { n_gpu_layers: number; ctx_size: number }
+  disabled: boolean
+  onStarted: (modelPath: string) => Promise<void>
+}
+
+function PresetsPanel({ modelPath, selectedDeviceIds, settings, disabled, onStarted }: PresetsPanelProps) {
+  const [presets, setPresets] = useState<InferencePreset[]>([])
+  const [name, setName] = useState('')
+  const [busy, setBusy] = useState<string | null>(null)
+  const [error, setError] = useState<string | null>(null)
+  const [notes, setNotes] = useState<string[]>([])
+
+  const load = useCallback(async () => {
+    try {
+      const data = await api.listPresets()
+      setPresets(data.presets ?? [])
+    } catch (e: unknown) {
+      console.error('Failed to fetch presets', e)
+    }
+  }, [])
+
+  useEffect(() => {
+    load()
+  }, [load])
+
+  async function handleSave() {
+    setBusy('save')
+    setError(null)
+    try {
+      await api.createPreset({
+        name: name.trim(),
+        model_path: modelPath.trim(),
+        device_ids: selectedDeviceIds,
+        n_gpu_layers: settings.n_gpu_layers,
+        ctx_size: settings.ctx_size,
+      })
+      setName('')
+      await load()
+    } catch (e: unknown) {
+      setError(e instanceof Error ? e.message : String(e))
+    } finally {
+      setBusy(null)
+    }
+  }
+
+  async function handleStart(preset: InferencePreset) {
+    setBusy(preset.id)
+    setError(null)
+    setNotes([])
+    try {
+      const result: PresetStartResult = await api.startPreset(preset.id)
+      const notes = result.skipped.map(s => `Skipped ${s.name ?? s.device_id}: ${s.reason}`)
+      if (result.skipped_draft) notes.push(`Draft model dropped: ${result.skipped_draft}`)
+      for (const c of result.changed) notes.push(`${c.name} changed since saved: ${c.changes.join(', ')}`)
+      setNotes(notes)
+      await onStarted(preset.model_path)
+    } catch (e: unknown) {
+      setError(e instanceof Error ? e.message : String(e))
+    } finally {
+      setBusy(null)
+    }
+  }
+
+  async function handleDelete(id: string) {
+    setBusy(id)
+    try {
+      await api.deletePreset(id)
+      await load()
+    } catch (e: unknown) {
+      setError(e instanceof Error ? e.message : String(e))
+    } finally {
+      setBusy(null)
+    }
+  }
+
+  return (
+    <div className="card space-y-2">
+      <div className="flex items-center gap-2">
+        <Bookmark size={14} className="text-accent" />
+        <span className="text-sm font-semibold text-gray-200">Presets</span>
+      </div>
+      {presets.length === 0 && (
+        <p className="text-xs text-muted">No presets yet. Save the current model and devices below.</p>
+      )}
+      {presets.map(p => (
+        <div key={p.id} className="flex items-center gap-2">
+          <div className="flex-1 min-w-0">
+            <p className="text-sm text-gray-200 truncate">{p.name}</p>
+            <p className="text-xs text-muted truncate">
+              {p.model_path}
+              {p.device_ids.length > 0 && ` · ${p.device_ids.length} device${p.device_ids.length === 1 ? '' : 's'}`}
+              {p.device_tags.length > 0 && ` · tags ${p.device_tags.join(', ')}`}
+            </p>
+          </div>
+          <button
+            onClick={() => handleStart(p)}
+            disabled={disabled || busy !== null}
+            className="btn-primary text-xs px-2 py-1 flex items-center gap-1 disabled:opacity-40"
+          >
+            {busy === p.id ? <Loader2 size={12} className="animate-spin" /> : <Play size={12} />}
+            Start
+          </button>
+          <button
+            onClick={() => handleDelete(p.id)}
+            disabled={busy !== null}
+            className="text-muted hover:text-danger disabled:opacity-40"
+            title="Delete preset"
+          >
+            <Trash2 size={13} />
+          </button>
+        </div>
+      ))}
+      <div className="flex gap-2 pt-1">
+        <input
+          value={name}
+          onChange={e => setName(e.target.value)}
+          placeholder="Preset name"
+          className="input flex-1 text-xs"
+        />
+        <button
+          onClick={handleSave}
+          disabled={!name.trim() || !modelPath.trim() || busy !== null}
+          className="text-xs px-2 py-1 rounded-lg bg-surface border border-border text-gray-300 hover:border-accent/50 disabled:opacity-40"
+        >
+          Save current
+        </button>
+      </div>
+      {notes.map((n, i) => (
+        <p key={i} className="text-xs text-warning">{n}</p>
+      ))}
+      {error && <p className="text-xs text-danger">{error}</p>}
+    </div>
+  )
+}
+
 // ─── Main Page ────────────────────────────────────────────────────────────────
 
 export function InferencePage() {
   const [clusterStatus, setClusterStatus] = useState<ClusterStatus | null>(null)
   const [selectedDeviceIds, setSelectedDeviceIds] = useState<string[]>([])
   const [modelPath, setModelPath] = useState('')
+  const [mmprojPath, setMmprojPath] = useState('')
+  const [chatTemplate, setChatTemplate] = useState('')
+  const [systemPrompt, setSystemPrompt] = useState('')
   const [inferenceSettings, setInferenceSettings] = useState({ n_gpu_layers: -1, ctx_size: 4096 })
   const [loading, setLoading] = useState(false)
   const [actionError, setActionError] = useState<string | null>(null)
@@ -627,6 +790,18 @@ export function InferencePage() {
         selectedDeviceIds,
         inferenceSettings.n_gpu_layers,
         inferenceSettings.ctx_size,
+        undefined,
+        undefined,
+        undefined,
+        undefined,
+        undefined,
+        undefined,
+        undefined,
+        mmprojPath.trim() || undefined,
+        {
+          chat_template: chatTemplate.trim() || undefined,
+          system_prompt: systemPrompt.trim() || undefined,
+        },
       )
       // Auto-activate llamacpp backend when inference starts
       const cfg: BackendConfig = { backend_type: 'llamacpp', url: '', model: modelPath.trim() }
@@ -640,6 +815,13 @@ export function InferencePage() {
     }
   }
 
+  async function handlePresetStarted(presetModel: string) {
+    const cfg: BackendConfig = { backend_type: 'llamacpp', url: '', model: presetModel }
+    await api.setBackendConfig(cfg)
+    setActiveBackend(cfg)
+    await refresh()
+  }
+
   async function handleStop() {
     setLoading(true)
     setActionError(null)
@@ -852,6 +1034,36 @@ export function InferencePage() {
             <p className="text-xs text-muted mt-1.5">
               Full path to a .gguf model file on this machine.
             </p>
+            <input
+              value={mmprojPath}
+              onChange={e => setMmprojPath(e.target.value)}
+              placeholder="/path/to/mmproj-model-f16.gguf (vision models, optional)"
+              disabled={inferenceRunning}
+              className="w-full mt-3 bg-surface border border-border rounded-lg px-3 py-2 text-sm text-gray-200 placeholder-muted disabled:opacity-50 focus:outline-none focus:border-accent font-mono"
+            />
+            <p className="text-xs text-muted mt-1.5">
+              {session?.supports_vision
+                ? 'Vision: chat requests may include images.'
+                : 'Projector of a LLaVA-style model, in the same directory. Without one, requests with images are refused.'}
+            </p>
+            <input
+              value={chatTemplate}
+              onChange={e => setChatTemplate(e.target.value)}
+              placeholder="Chat template override, e.g. llama3 or chatml (optional)"
+              disabled={inferenceRunning}
+              className="w-full mt-3 bg-surface border border-border rounded-lg px-3 py-2 text-sm text-gray-200 placeholder-muted disabled:opacity-50 focus:outline-none focus:border-accent font-mono"
+            />
+            <textarea
+              value={systemPrompt}
+              onChange={e => setSystemPrompt(e.target.value)}
+              placeholder="System prompt (optional)"
+              rows={2}
+              disabled={inferenceRunning}
+              className="w-full mt-3 bg-surface border border-border rounded-lg px-3 py-2 text-sm text-gray-200 placeholder-muted disabled:opacity-50 focus:outline-none focus:border-accent"
+            />
+            <p className="text-xs text-muted mt-1.5">
+              Added first to chat requests that have no system message of their own.
+            </p>
           </div>
 
           {/* Device selection */}
@@ -908,14 +1120,22 @@ export function InferencePage() {
                           <span className="text-sm text-gray-200 truncate">{device.name}</span>
                           <span className="text-xs text-muted font-mono">{device.ip}</span>
                         </div>
+                        {Object.keys(device.labels ?? {}).length > 0 && (
+                          <p className="text-xs text-muted mt-0.5 ml-5 truncate">
+                            {Object.entries(device.labels).map(([k, v]) => `${k}: ${v}`).join(' · ')}
+                          </p>
+                        )}
                         {device.memory_total_mb > 0 && (
                           <p className="text-xs text-muted mt-0.5 ml-5">
                             {fmt(device.memory_free_mb)} free / {fmt(device.memory_total_mb)} total
+                            {device.memory_manual_override && ' · manually configured'}
+                            {device.rpc_backend && ` · ${device.rpc_backend.toUpperCase()}`}
+                            {device.rpc_mem_mb != null && ` · capped at ${fmt(device.rpc_mem_mb)}`}
                           </p>
                         )}
                         {!ready && (
                           <p className="text-xs text-warning mt-0.5 ml-5">
-                            RPC unreachable — is llama-rpc-server running on this device?
+                            {device.diagnosis ?? 'RPC unreachable — is llama-rpc-server running on this device?'}
                           </p>
                         )}
                       </div>
@@ -944,6 +1164,14 @@ export function InferencePage() {
             onSettingsChange={setInferenceSettings}
           />
 
+          <PresetsPanel
+            modelPath={modelPath}
+            selectedDeviceIds={selectedDeviceIds}
+            settings={inferenceSettings}
+            disabled={inferenceRunning || !inferenceBinAvailable}
+            onStarted={handlePresetStarted}
+          />
+
           {/* Start/Stop */}
           {actionError && (
             <div className="text-xs text-danger bg-danger/10 border border-danger/20 rounded-lg px-3 py-2">
@@ -991,7 +1219,11 @@ export function InferencePage() {
 
         {/* Right: chat */}
         <div>
-          <ChatPanel inferenceRunning={inferenceRunning} activeConfig={activeBackend} />
+          <ChatPanel
+            inferenceRunning={inferenceRunning}
+            activeConfig={activeBackend}
+            sessionModel={session?.model_path}
+          />
 
           {/* Inference server info */}
           {inferenceRunning && (
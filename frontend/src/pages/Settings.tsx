@@ -1,5 +1,5 @@
 import { useState, useEffect } from 'react'
-import { Save, Info } from 'lucide-react'
+import { Save, Info, Download } from 'lucide-react'
 import type { Settings } from '../types'
 import { api } from '../lib/api'
 
@@ -34,6 +34,7 @@ interface SettingsPageProps {
 export function SettingsPage({ settings, onSettingsChange }: SettingsPageProps) {
   const [local, setLocal] = useState<Settings>(settings)
   const [saved, setSaved] = useState<Record<string, boolean>>({})
+  const [redactIps, setRedactIps] = useState(true)
 
   useEffect(() => { setLocal(settings) }, [settings])
 
@@ -99,6 +100,23 @@ export function SettingsPage({ settings, onSettingsChange }: SettingsPageProps)
         })}
       </div>
 
+      {/* Support bundle */}
+      <div className="card space-y-2">
+        <p className="text-sm font-medium text-gray-200">Support bundle</p>
+        <p className="text-xs text-muted">
+          Settings, devices, recent logs and versions in one zip for a bug report. Secrets are always removed.
+        </p>
+        <div className="flex items-center gap-3">
+          <label className="flex items-center gap-1.5 text-xs text-muted">
+            <input type="checkbox" checked={redactIps} onChange={e => setRedactIps(e.target.checked)} />
+            Remove IP addresses
+          </label>
+          <a href={api.supportBundleUrl(redactIps)} className="btn-ghost text-xs flex items-center gap-1.5">
+            <Download size={12} /> Download
+          </a>
+        </div>
+      </div>
+
       {/* About */}
       <div className="card border-border/50 bg-surface/50">
         <p className="text-xs text-muted text-center">
@@ -3,6 +3,12 @@
 export type DeviceStatus = 'pending' | 'approved' | 'denied' | 'suspended' | 'offline'
 export type DiscoveryMethod = 'mdns' | 'manual'
 export type RpcStatus = 'offline' | 'connecting' | 'ready' | 'error'
+/** What runs on the device: just the RPC agent, or a full SharedLLM backend */
+export type DeviceKind = 'agent' | 'peer' | 'unknown'
+/** Backend an agent's llama-rpc-server serves (`--device`) */
+export type RpcBackend = 'cuda' | 'metal' | 'cpu'
+/** How far an RPC probe got: connected, answered HELLO, reported its memory */
+export type ProbeDepth = 'tcp' | 'hello' | 'memory'
 
 export interface Device {
   id: string
@@ -23,6 +29,135 @@ export interface Device {
   rpc_status: RpcStatus
   memory_total_mb: number
   memory_free_mb: number
+  tags: string[]
+  /** Device revision of the last change to this device or its tags */
+  rev: number
+  /** When the RPC server was last seen becoming ready */
+  last_rpc_ready_at?: string | null
+  /** The agent runs llama-rpc-server with --cache */
+  rpc_cache_enabled: boolean
+  /** Cache size as of the last heartbeat */
+  rpc_cache_mb: number
+  /** Further llama-rpc-server ports on the same host, besides rpc_port */
+  extra_rpc_ports: number[]
+  /** Hardware reported by the agent heartbeat; null until an agent sends it */
+  cpu_model?: string | null
+  /** Physical cores */
+  cpu_cores?: number | null
+  os_version?: string | null
+  /** GPU driver version, or the macOS build on Apple Silicon */
+  gpu_driver?: string | null
+  disk_free_mb?: number | null
+  /** Runs its own SharedLLM backend; chat requests for its models are forwarded there */
+  is_peer: boolean
+  device_kind: DeviceKind
+  /** Free-text notes kept by the operator */
+  notes?: string | null
+  /** Operator-defined key/value metadata, e.g. { location: 'basement' } */
+  labels: Record<string, string>
+  /** Reliability as an RPC device, 0-100; below 50 it is left out of tag selection */
+  health_score: number
+  /** Launch options the agent registered with; null = the build's defaults */
+  rpc_backend?: RpcBackend | null
+  /** llama-rpc-server --mem cap, in MB */
+  rpc_mem_mb?: number | null
+  /** Approval needs the pairing code the agent printed until then */
+  pairing_expires_at?: string | null
+  /** Wrong pairing codes entered so far */
+  pairing_attempts: number
+  /** Too many wrong codes: approvable once the agent checks in with the enrollment token */
+  pairing_locked: boolean
+  /** While offline: the status it gets back when it answers again */
+  prior_status?: DeviceStatus | null
+  /** Memory figures were set by hand; probes and heartbeats leave them alone */
+  memory_manual_override: boolean
+  /** This host's last probe of its RPC server; null until first probed */
+  rpc_probe_depth?: ProbeDepth | null
+  rpc_probe_ok?: boolean | null
+  rpc_probe_detail?: string | null
+  rpc_probe_at?: string | null
+  /** Set only on soft-deleted devices, listed with `?include_deleted=true` */
+  deleted_at?: string | null
+  /** Seconds since last_seen, as of the request */
+  last_seen_secs_ago?: number | null
+}
+
+/** A memory grant or inference reservation */
+export interface Allocation {
+  id: string
+  /** null for inference reservations on this host */
+  device_id?: string | null
+  memory_mb: number
+  provider: string
+  /** 'device' (grant to a device) or 'inference' (held by a running session) */
+  purpose: string
+  session_id?: string | null
+  granted_at: string
+  revoked_at?: string | null
+}
+
+export interface DeviceActivity {
+  at: string
+  kind: 'allocation_granted' | 'allocation_revoked' | 'health_changed'
+  memory_mb?: number | null
+  detail?: string | null
+}
+
+/** What allocated_memory_mb is made of and what bounds it */
+export interface DeviceDetail {
+  /** In force, newest first */
+  allocations: Allocation[]
+  by_provider: { provider: string; memory_mb: number; allocations: number }[]
+  role?: Role | null
+  /** The role's max_memory_mb less allocated_memory_mb; null without a role */
+  remaining_memory_mb?: number | null
+  rpc: {
+    status: RpcStatus
+    last_ready_at?: string | null
+    last_ready_secs_ago?: number | null
+    last_seen_secs_ago?: number | null
+    /** null when memory_free_mb hasn't been read since the backend started */
+    memory_read_secs_ago?: number | null
+  }
+  /** Newest first */
+  recent_activity: DeviceActivity[]
+}
+
+/** GET /api/devices/:id */
+export interface DeviceWithDetail extends Device {
+  detail: DeviceDetail
+}
+
+/** GET /api/devices; `since_rev` / `removed` only on `?since_rev=` requests */
+export interface DeviceList {
+  rev: number
+  devices: Device[]
+  since_rev?: number
+  removed?: string[]
+}
+
+/** Response of POST /api/devices; the pairing code is shown only once. */
+export interface AddedDevice extends Device {
+  pairing_code?: string
+}
+
+/** Response of POST /api/devices/:id/approve; the agent secret is shown only once. */
+export interface ApprovedDevice extends Device {
+  agent_secret: string
+  /** What contacting the agent found; absent when approved with verify=false */
+  verification?: Verification
+}
+
+/** The RPC probe and memory reading made when a device is approved */
+export interface Verification {
+  /** At least one RPC endpoint answered */
+  ok: boolean
+  rpc_endpoints: { address: string; reachable: boolean }[]
+  /** From the device's /api/gpu; null for agents, which report memory by heartbeat */
+  memory_total_mb?: number | null
+  memory_free_mb?: number | null
+  /** What failed, when ok is false */
+  error?: string | null
 }
 
 // ─── Role ─────────────────────────────────────────────────────────────────────
@@ -36,6 +171,83 @@ export interface Role {
   created_at: string
 }
 
+// ─── Model aliases ────────────────────────────────────────────────────────────
+
+export interface ModelAlias {
+  alias: string
+  path: string
+  created_at: string
+}
+
+/** A LoRA adapter applied on top of the model (--lora / --lora-scaled) */
+export interface LoraAdapter {
+  /** Absolute .gguf or .bin path */
+  path: string
+  /** 1.0 applies it as trained */
+  scale?: number
+}
+
+/** An adapter file found beside the models, listed with the aliases */
+export interface AdapterFile {
+  path: string
+  size_mb: number
+}
+
+export type ModelPullStatus = 'pending' | 'active' | 'completed' | 'failed' | 'cancelled'
+
+/** Why a pull failed, read from Ollama's message */
+export type PullErrorCode = 'not_found' | 'disk_full' | 'network' | 'unknown'
+
+/** A pull queued for the off-peak window or a later time, or a finished direct pull */
+export interface ModelPull {
+  id: string
+  name: string
+  status: ModelPullStatus
+  off_peak: boolean
+  not_before: string | null
+  completed_bytes: number | null
+  total_bytes: number | null
+  error: string | null
+  error_code: PullErrorCode | null
+  created_at: string
+  started_at: string | null
+  finished_at: string | null
+}
+
+// ─── Webhooks ─────────────────────────────────────────────────────────────────
+
+export interface Webhook {
+  id: string
+  url: string
+  events: string[] // empty = all notable events
+  enabled: boolean
+  secret_set: boolean
+  created_at: string
+}
+
+// ─── API keys ─────────────────────────────────────────────────────────────────
+
+/** inference: /v1 only; viewer: dashboard reads and /ws stats; admin: everything */
+export type KeyScope = 'inference' | 'viewer' | 'admin'
+
+export interface ApiKey {
+  id: string
+  name: string
+  key_prefix: string // e.g. "sk-sllm-3f9a"
+  rate_limit_rpm: number | null // requests per minute; null = unlimited
+  allowed_models: string[] // empty = every model
+  scope: KeyScope
+  requests: number
+  last_used_at: string | null
+  created_at: string
+  revoked_at: string | null
+}
+
+/** `POST /api/keys` response; the only time `key` is returned */
+export interface CreatedApiKey extends ApiKey {
+  key: string
+}
+
 // ─── Memory / GPU ─────────────────────────────────────────────────────────────
 
 export type GpuKind = 'nvidia' | 'amd' | 'apple_silicon' | 'intel' | 'system_ram'
@@ -47,16 +259,74 @@ export interface MemorySnapshot {
   total_mb: number
   used_mb: number
   free_mb: number
+  /** Device allocations plus memory reserved by a running inference session
+   *  and by the models Ollama keeps loaded */
   allocated_mb: number
+  /** Part of allocated_mb held by Ollama's loaded models */
+  ollama_mb?: number
+  /** free_mb less the `host_reserved_mb` floor on system RAM / unified memory */
+  usable_free_mb: number
+  /** The provider didn't answer the latest sample in time; last good reading */
+  stale?: boolean
+}
+
+/** Response of GET /api/gpu and POST /api/gpu/refresh */
+export interface GpuStats {
+  providers: MemorySnapshot[]
+  count: number
+  /** Age in seconds of the oldest cached snapshot */
+  stale_secs: number
 }
 
 // ─── Ollama ───────────────────────────────────────────────────────────────────
 
+export interface OllamaModelDetails {
+  family: string | null
+  parameter_size: string | null
+  quantization_level: string | null
+  context_length: number | null
+  parameters: string | null
+  template: string | null
+}
+
+/** Progress of a chunked model upload */
+export interface UploadStatus {
+  upload_id: string
+  file_name: string
+  alias: string | null
+  size: number
+  /** Bytes received; the offset of the next chunk */
+  received: number
+  complete: boolean
+  created_at: string
+}
+
+/** A model a host shares, from GET /api/models/files */
+export interface SharedModelFile {
+  alias: string
+  file_name: string
+  size: number
+  sha256: string
+}
+
 export interface OllamaModel {
   name: string
   size: number
   digest: string
   modified_at: string
+  /** From Ollama's /api/show; null when it could not be fetched */
+  details?: OllamaModelDetails | null
+}
+
+/** A model Ollama holds in memory (GET /api/ollama/ps) */
+export interface OllamaRunningModel {
+  model: string
+  /** Bytes in GPU and system memory together */
+  size: number
+  /** Bytes of size in GPU memory */
+  size_vram: number
+  /** When Ollama unloads it unless it is used again */
+  expires_at: string
 }
 
 // ─── Distributed inference ────────────────────────────────────────────────────
@@ -64,9 +334,184 @@ export interface OllamaModel {
 export interface InferenceSessionInfo {
   id: string
   model_path: string
-  status: string // starting | running | stopped | error
+  status: string // starting | running | restarting | draining | stopped | error
   rpc_devices: string[] // "ip:port" strings
   started_at: string
+  n_gpu_layers: number
+  ctx_size: number
+  parallel: number
+  /** llama-server was started with --cont-batching */
+  cont_batching: boolean
+  restart_attempts: number
+  /** When the watchdog last re-launched it */
+  restarted_at?: string
+  gpu_selector: GpuSelector
+  /** Environment set on llama-server (from gpu_selector) */
+  env: Record<string, string>
+  /** Speculative-decoding draft model, loaded on the local GPU */
+  draft?: DraftModel
+  /** Multimodal projector (--mmproj) */
+  mmproj?: string
+  /** Started with a projector; chat requests may carry images */
+  supports_vision: boolean
+  /** Built-in chat template overriding the model's (--chat-template) */
+  chat_template?: string
+  /** Added as the first message of chat requests without a system message */
+  system_prompt?: string
+  lora_adapters?: LoraAdapter[]
+  /** Retry with fewer GPU layers if llama-server runs out of memory loading */
+  retry_on_oom: boolean
+  /** Set once it has; n_gpu_layers holds the layers actually used */
+  oom_fallback?: OomFallback
+  /** llama-server's `--api-key`, masked: `****` and its last four characters */
+  api_key: string
+}
+
+export interface OomFallback {
+  /** n_gpu_layers as asked for (-1 = all) */
+  requested_n_gpu_layers: number
+  /** Retries made with fewer layers */
+  attempts: number
+}
+
+export interface DraftModel {
+  path: string
+  /** --draft-max */
+  max?: number
+  /** --draft-min */
+  min?: number
+}
+
+/** Local GPU selection for llama-server; each field maps to one env var or flag */
+export interface GpuSelector {
+  cuda_visible_devices?: string
+  hip_visible_devices?: string
+  main_gpu?: number
+  split_mode?: 'none' | 'layer' | 'row'
+  metal_ndebug?: boolean
+}
+
+/** RPC probe of a selected device; `devices` of POST /api/cluster/inference/start */
+export interface DeviceProbe {
+  device_id: string
+  name: string
+  /** ip:port of its RPC server */
+  address: string
+  reachable: boolean
+  /** tcp when the server predates the RPC handshake or the running session holds it */
+  depth: ProbeDepth
+  detail: string
+}
+
+/** Response of POST /api/cluster/inference/resume-last */
+export interface ResumeLastResult {
+  ok: boolean
+  session: InferenceSessionInfo | null
+  /** Devices of the recorded session left out (gone, not approved/trusted, unreachable) */
+  skipped: { device_id: string; name: string | null; reason: string }[]
+}
+
+/** Saved start parameters; GET /api/cluster/presets */
+export interface InferencePreset {
+  id: string
+  name: string
+  /** Absolute .gguf path or a model alias */
+  model_path: string
+  device_ids: string[]
+  device_tags: string[]
+  n_gpu_layers: number | null
+  ctx_size: number | null
+  options: {
+    parallel: number | null
+    cont_batching: boolean
+    gpu_selector: GpuSelector | null
+    draft_model_path: string | null
+    draft_max: number | null
+    draft_min: number | null
+    mmproj_path: string | null
+    chat_template: string | null
+    system_prompt: string | null
+    lora_adapters?: LoraAdapter[]
+    retry_on_oom: boolean
+  }
+  /** device_ids as they were when the preset was saved */
+  saved_devices: { id: string; name: string; ip: string; rpc_ports: number[] }[]
+  created_at: string
+  updated_at: string
+}
+
+/** Response of POST /api/cluster/presets/:id/start (the start response plus these) */
+export interface PresetStartResult {
+  ok: boolean
+  session?: InferenceSessionInfo | null
+  devices?: DeviceProbe[]
+  preset_id: string
+  /** Devices left out: deleted, not approved/trusted, or unreachable */
+  skipped: { device_id: string; name: string | null; reason: string }[]
+  /** Why the draft model was dropped, if it was */
+  skipped_draft: string | null
+  devices_changed: boolean
+  changed: { device_id: string; name: string; changes: string[] }[]
+}
+
+/** Entry of GET /api/cluster/ports */
+export interface ServerPortStatus {
+  name: 'rpc' | 'inference'
+  port: number
+  available: boolean
+  /** Held by a server this backend started */
+  ours: boolean
+  /** "name (pid N)" when the holder could be identified (Linux) */
+  holder: string | null
+}
+
+/** Body of a 409 from POST /api/cluster/rpc/start or /inference/start */
+export interface PortInUseError {
+  error: 'port_in_use'
+  port: number
+  holder: string | null
+  message: string
+}
+
+/** `plan` of a dry-run POST /api/cluster/inference/start */
+export interface LaunchPlan {
+  binary: string | null
+  args: string[]
+  env: Record<string, string>
+}
+
+export interface InferenceMetrics {
+  slots_total: number
+  slots_busy: number
+  slots_idle: number
+  queue_depth?: number | null
+  prompt_tokens_total?: number | null
+  generated_tokens_total?: number | null
+  prompt_tokens_per_sec?: number | null
+  generated_tokens_per_sec?: number | null
+  proxy_in_flight: number
+  proxy_queued: number
+  /** proxy_queued by priority lane, highest first */
+  proxy_lanes?: LaneStats[]
+  source: 'metrics' | 'slots'
+}
+
+/** Admission lane of a chat request, from the caller's API key or device role */
+export type Priority = 'admin' | 'user' | 'guest' | 'anonymous'
+
+export interface LaneStats {
+  priority: Priority
+  queued: number
+  /** How long the front request has waited */
+  oldest_wait_ms: number | null
+}
+
+/** `completions` in GET /api/cluster/inference/status */
+export interface CompletionAdmission {
+  in_flight: number
+  queued: number
+  max_concurrent: number
+  queue_size: number
 }
 
 export interface LlamaCppStatus {
@@ -77,6 +522,33 @@ export interface LlamaCppStatus {
   rpc_port: number
   inference_port: number
   current_session?: InferenceSessionInfo
+  /** A start is checking devices or launching llama-server */
+  starting: boolean
+  /** In GET /api/cluster/status only */
+  binaries?: BinariesInfo
+}
+
+/** What an installed llama.cpp binary was built with */
+export interface BinaryInfo {
+  /** null when it isn't installed */
+  path: string | null
+  /** Build number and commit, e.g. `4589 (1a24c462)` */
+  version: string | null
+  build: number | null
+  compiler: string | null
+  target: string | null
+  /** e.g. CUDA, ROCm, Metal, Vulkan, SYCL, RPC, CPU */
+  backends: string[]
+  /** Takes `--rpc`; null when it couldn't be asked */
+  rpc: boolean | null
+  error: string | null
+}
+
+/** GET /api/cluster/binaries */
+export interface BinariesInfo {
+  llama_server: BinaryInfo
+  /** Only `path` is filled in */
+  llama_rpc_server: BinaryInfo
 }
 
 export interface ClusterDeviceStatus {
@@ -84,26 +556,150 @@ export interface ClusterDeviceStatus {
   name: string
   ip: string
   rpc_port: number
+  /** Probe of every RPC endpoint: rpc_port, then extra_rpc_ports */
+  rpc_endpoints: { port: number; reachable: boolean; probe_depth: ProbeDepth; probe_detail: string }[]
   rpc_status: RpcStatus
+  /** The probe rpc_status came from: its first passing endpoint, else its first */
+  rpc_probe_depth?: ProbeDepth | null
+  rpc_probe_detail?: string | null
   memory_total_mb: number
   memory_free_mb: number
+  /** Memory was set by hand ("manually configured") */
+  memory_manual_override: boolean
+  tags: string[]
+  labels: Record<string, string>
+  /** Role trust level (0 when the device has no role) */
+  trust_level: number
+  /** false when trust_level is below min_cluster_trust */
+  cluster_eligible: boolean
+  /** When the RPC server was last seen becoming ready */
+  last_rpc_ready_at?: string | null
+  rpc_cache_enabled: boolean
+  rpc_cache_mb: number
+  rpc_backend?: RpcBackend | null
+  rpc_mem_mb?: number | null
+  /** Hardware reported by the agent heartbeat; null until an agent sends it */
+  cpu_model?: string | null
+  /** Physical cores */
+  cpu_cores?: number | null
+  os_version?: string | null
+  /** GPU driver version, or the macOS build on Apple Silicon */
+  gpu_driver?: string | null
+  disk_free_mb?: number | null
+  /** Why RPC is unreachable, e.g. "firewall blocking 8181"; null when reachable */
+  diagnosis?: string | null
+  health_score: number
+  /** health_score is below 50: tags skip it, and picking it by ID warns */
+  unreliable: boolean
+}
+
+export type HealthReason = 'early_crash' | 'rpc_flapping' | 'long_session' | 'reset'
+
+/** One change of a device's health score */
+export interface DeviceHealthEvent {
+  id: number
+  device_id: string
+  delta: number
+  /** Score after the change */
+  score: number
+  reason: HealthReason
+  created_at: string
+}
+
+/** GET /api/devices/:id/health */
+export interface DeviceHealth {
+  device_id: string
+  health_score: number
+  unreliable: boolean
+  /** Newest first */
+  history: DeviceHealthEvent[]
+}
+
+/** POST /api/devices/:id/rpc/restart */
+export interface RpcRestarted {
+  device_id: string
+  via: 'peer' | 'agent'
+  rpc_port: number
+  /** From asking to the RPC port answering */
+  took_ms: number
+}
+
+/** A row of POST /api/devices/import */
+export interface DeviceImportRow {
+  name: string
+  ip: string
+  mac?: string
+  role_id?: string
+  auto_approve?: boolean
+  notes?: string
+  labels?: Record<string, string>
+}
+
+export interface DeviceImportResult {
+  /** 1-based, not counting the CSV header */
+  row: number
+  name: string
+  ip: string
+  ok: boolean
+  device_id: string | null
+  status: DeviceStatus | null
+  /** False when the address was already registered; that device is unchanged */
+  created: boolean
+  error: string | null
+}
+
+/** POST /api/devices/import */
+export interface DeviceImportReport {
+  /** The import's entry in GET /api/devices/imports */
+  id: number
+  total: number
+  imported: number
+  existing: number
+  failed: number
+  results: DeviceImportResult[]
+}
+
+/** GET /api/devices/imports */
+export interface DeviceImport {
+  id: number
+  format: 'json' | 'csv'
+  total: number
+  imported: number
+  existing: number
+  failed: number
+  created_at: string
 }
 
 export interface ClusterStatus {
+  /** Device revision after probing; pass as `?since_rev=` to get only changes */
+  rev: number
   devices: ClusterDeviceStatus[]
+  since_rev?: number
+  removed?: string[]
+  min_cluster_trust: number
+  min_stats_trust: number
   llama_cpp: LlamaCppStatus
   current_session?: InferenceSessionInfo
 }
 
+export interface AgentCommands {
+  linux: string
+  macos: string
+  windows: string
+}
+
 export interface AgentInfo {
   host_ip: string
   dashboard_port: string
+  /** Base URL baked into the scripts; https when a TLS fingerprint is pinned */
+  dashboard_url: string
   rpc_port: number
-  install_commands: {
-    linux: string
-    macos: string
-    windows: string
-  }
+  tls_fingerprint: string | null
+  enrollment_token_set: boolean
+  install_commands: AgentCommands
+  /** Installs a systemd / launchd / Scheduled Task service that survives reboots */
+  persistent_install_commands: AgentCommands
+  uninstall_commands: AgentCommands
   rpc_server_bin_available: boolean
 }
 
@@ -116,23 +712,47 @@ export interface ChatMessage {
 
 // ─── WebSocket Events ─────────────────────────────────────────────────────────
 
+/** Every /ws message for protocol v2 */
+export interface WsEnvelope {
+  v: number
+  /** RFC 3339 send time */
+  ts: string
+  event: WsEvent
+}
+
 export type WsEventType =
   | 'device_discovered'
   | 'device_pending_approval'
   | 'device_approved'
   | 'device_denied'
+  | 'device_suspended'
   | 'device_offline'
   | 'memory_allocated'
   | 'memory_stats'
   | 'ollama_status'
   | 'error'
+  | 'backend_profile_activated'
   | 'rpc_server_ready'
   | 'rpc_server_offline'
   | 'rpc_device_ready'
   | 'rpc_device_offline'
   | 'inference_started'
   | 'inference_stopped'
+  | 'inference_draining'
+  | 'inference_restarting'
+  | 'inference_failed'
+  | 'inference_resume_failed'
+  | 'inference_metrics'
   | 'layer_assignment'
+  | 'model_pull_progress'
+  | 'resync'
+  | 'ws_client_connected'
+  | 'ws_client_disconnected'
+  | 'providers_changed'
+  | 'model_available'
+  | 'inference_retrying'
+  | 'overview'
+  | 'model_removed'
 
 export interface WsEventDeviceDiscovered {
   type: 'device_discovered'
@@ -162,9 +782,16 @@ export interface WsEventDenied {
   device_id: string
 }
 
+export interface WsEventSuspended {
+  type: 'device_suspended'
+  device_id: string
+}
+
 export interface WsEventOffline {
   type: 'device_offline'
   name: string
+  /** Set when a registered device stopped answering; absent for mDNS removals */
+  device_id?: string
 }
 
 export interface WsEventMemoryAllocated {
@@ -175,7 +802,21 @@ export interface WsEventMemoryAllocated {
 
 export interface WsEventMemoryStats {
   type: 'memory_stats'
+  /** Every provider, moved or not */
   snapshots: MemorySnapshot[]
+  /** Provider IDs that moved since they were last sent */
+  changed: string[]
+  /** Sent on schedule or on connect rather than because something moved */
+  keyframe: boolean
+}
+
+/** managed = loopback host we start and restart; local = loopback, user-run; remote = never spawned */
+export type OllamaMode = 'managed' | 'local' | 'remote'
+
+export interface OllamaStatus {
+  running: boolean
+  host: string
+  mode: OllamaMode
 }
 
 export interface WsEventOllamaStatus {
@@ -184,11 +825,32 @@ export interface WsEventOllamaStatus {
   host: string
 }
 
+/** warning: listed only; error and critical: worth a toast */
+export type ErrorSeverity = 'warning' | 'error' | 'critical'
+
+/** A problem in a background task (watchdogs, discovery, memory sampling) */
 export interface WsEventError {
   type: 'error'
+  severity: ErrorSeverity
+  /** Subsystem that reported it, e.g. 'ollama', 'llama_cpp', 'memory' */
+  source: string
+  message: string
+}
+
+export interface ErrorEntry {
+  timestamp: string
+  severity: ErrorSeverity
+  source: string
   message: string
 }
 
+export interface WsEventBackendProfileActivated {
+  type: 'backend_profile_activated'
+  profile_id: string
+  name: string
+  backend_type: BackendType
+}
+
 export interface WsEventRpcServerReady {
   type: 'rpc_server_ready'
   port: number
@@ -215,11 +877,65 @@ export interface WsEventInferenceStarted {
   session_id: string
   model: string
   devices: string[]
+  /** x-request-id of the request that started it */
+  request_id?: string
 }
 
 export interface WsEventInferenceStopped {
   type: 'inference_stopped'
   session_id: string
+  request_id?: string
+  /** Set when the server stopped it on its own, e.g. 'idle_timeout' */
+  reason?: string
+}
+
+export interface WsEventInferenceDraining {
+  type: 'inference_draining'
+  session_id: string
+  in_flight: number
+}
+
+export interface WsEventInferenceRestarting {
+  type: 'inference_restarting'
+  session_id: string
+  attempt: number
+}
+
+/** llama-server ran out of memory loading; starting again with fewer GPU layers */
+export interface WsEventInferenceRetrying {
+  type: 'inference_retrying'
+  session_id: string
+  attempt: number
+  n_gpu_layers: number
+}
+
+export interface WsEventInferenceFailed {
+  type: 'inference_failed'
+  session_id: string
+  reason: string
+}
+
+export interface WsEventInferenceResumeFailed {
+  type: 'inference_resume_failed'
+  reason: string
+  request_id?: string
+}
+
+export interface LogLine {
+  timestamp: string
+  level: string
+  target: string
+  message: string
+  request_id: string | null
+}
+
+export interface WsEventInferenceMetrics {
+  type: 'inference_metrics'
+  session_id: string
+  slots_busy: number
+  slots_total: number
+  queue_depth?: number | null
+  generated_tokens_per_sec?: number | null
 }
 
 export interface LayerAssignment {
@@ -232,28 +948,238 @@ export interface WsEventLayerAssignment {
   assignments: LayerAssignment[]
 }
 
+/** `status` is a job status or, while pulling, Ollama's progress line */
+export interface WsEventModelPullProgress {
+  type: 'model_pull_progress'
+  job_id: string
+  name: string
+  status: string
+  completed: number | null
+  total: number | null
+  error?: string
+}
+
+/** Sent when this client missed state changes; refetch everything via REST */
+export interface WsEventResync {
+  type: 'resync'
+  missed: number
+}
+
+/** Another /ws client connected; a client never gets its own */
+export interface WsEventWsClientConnected {
+  type: 'ws_client_connected'
+  client_id: string
+  remote_addr: string | null
+}
+
+export interface WsEventWsClientDisconnected {
+  type: 'ws_client_disconnected'
+  client_id: string
+  events_sent: number
+}
+
+/** A detected memory provider, as listed by re-detection */
+export interface ProviderInfo {
+  provider_id: string
+  name: string
+  kind: GpuKind
+}
+
+export interface WsEventProvidersChanged {
+  type: 'providers_changed'
+  added: ProviderInfo[]
+  removed: ProviderInfo[]
+}
+
+/** A model file arrived: uploaded, or fetched from a peer */
+export interface WsEventModelAvailable {
+  type: 'model_available'
+  path: string
+  alias?: string
+}
+
+/** A model file was deleted; the aliases that pointed at it are gone */
+export interface WsEventModelRemoved {
+  type: 'model_removed'
+  path: string
+  aliases: string[]
+}
+
+export interface ModelFile {
+  path: string
+  file_name: string
+  size_bytes: number
+  modified_at: string | null
+  age_days: number | null
+  /** When a session with this model last ran */
+  last_used_at: string | null
+  /** Read by the running session; can't be deleted */
+  in_use: boolean
+  aliases: string[]
+}
+
+export interface TrashedModel {
+  trashed_path: string
+  original_path: string
+  size: number
+  deleted_at: string
+}
+
+/** GET /api/cluster/models/usage */
+export interface ModelUsage {
+  dirs: { path: string; total_bytes: number; files: ModelFile[] }[]
+  total_bytes: number
+  trash: TrashedModel[]
+  trash_bytes: number
+  trash_days: number
+}
+
+/** GET /api/overview */
+export interface Overview {
+  memory: {
+    total_mb: number
+    used_mb: number
+    free_mb: number
+    /** Local memory providers counted */
+    providers: number
+    /** Approved devices with their RPC server up */
+    cluster_devices: number
+  }
+  devices: {
+    total: number
+    pending: number
+    approved: number
+    denied: number
+    suspended: number
+    offline: number
+  }
+  inference: {
+    running: boolean
+    status: string | null
+    model: string | null
+    uptime_secs: number | null
+    /** RPC devices (`ip:port`) of the session */
+    devices: string[]
+  }
+  /** Plus what Ollama's loaded models hold on this host */
+  ollama: OllamaStatus & { resident_mb: number }
+  pending_approvals: number
+  /** `error` events in the last hour */
+  recent_errors: number
+  /** Problems with the session asked about in `?model=` */
+  warnings: string[]
+}
+
+/** The headline numbers of GET /api/overview, sent when one changes materially */
+export interface WsEventOverview {
+  type: 'overview'
+  memory_total_mb: number
+  memory_free_mb: number
+  devices_approved: number
+  devices_offline: number
+  pending_approvals: number
+  inference_running: boolean
+  ollama_running: boolean
+  recent_errors: number
+}
+
+/** POST /api/gpu/redetect */
+export interface RedetectResult {
+  providers: ProviderInfo[]
+  added: ProviderInfo[]
+  removed: ProviderInfo[]
+}
+
 export type WsEvent =
   | WsEventDeviceDiscovered
   | WsEventPendingApproval
   | WsEventApproved
   | WsEventDenied
+  | WsEventSuspended
   | WsEventOffline
   | WsEventMemoryAllocated
   | WsEventMemoryStats
   | WsEventOllamaStatus
   | WsEventError
+  | WsEventBackendProfileActivated
   | WsEventRpcServerReady
   | WsEventRpcServerOffline
   | WsEventRpcDeviceReady
   | WsEventRpcDeviceOffline
   | WsEventInferenceStarted
   | WsEventInferenceStopped
+  | WsEventInferenceDraining
+  | WsEventInferenceRestarting
+  | WsEventInferenceRetrying
+  | WsEventInferenceFailed
+  | WsEventInferenceResumeFailed
+  | WsEventInferenceMetrics
   | WsEventLayerAssignment
+  | WsEventModelPullProgress
+  | WsEventResync
+  | WsEventWsClientConnected
+  | WsEventWsClientDisconnected
+  | WsEventProvidersChanged
+  | WsEventModelAvailable
+  | WsEventOverview
+  | WsEventModelRemoved
+
+/** GET /api/ws/clients */
+export interface WsClientInfo {
+  id: string
+  remote_addr: string | null
+  connected_at: string
+  protocol_version: number
+  /** Event channels it receives: `state` and `stats` */
+  topics: string[]
+  events_sent: number
+  /** Last message sent to or received from it */
+  last_activity: string
+}
 
 // ─── Settings ─────────────────────────────────────────────────────────────────
 
 export type Settings = Record<string, string>
 
+/** A limit as GET /api/settings reports it under `limits` */
+export interface EffectiveLimit {
+  key: 'max_cluster_devices' | 'probe_timeout_ms' | 'memory_headroom_pct' | 'ws_buffer' | 'proxy_timeout_secs'
+  /** The value in use */
+  value: number
+  source: 'setting' | 'env' | 'default'
+  min: number
+  max: number
+  /** Picked up without a restart */
+  hot: boolean
+  /** A saved value that waits for a restart */
+  restart_required: number | null
+}
+
+/** GET /api/settings: every setting, plus the limits in effect. Non-admins get
+ *  only public settings and no limits; secrets appear only as `<key>_set` booleans. */
+export type SettingsResponse = Settings & { limits: EffectiveLimit[] }
+
+// ─── First-run setup ──────────────────────────────────────────────────────────
+
+export interface SetupStatus {
+  initialized: boolean
+  builtin_roles: Record<string, boolean>
+  builtin_roles_present: boolean
+  admin_token_generated: boolean
+  providers_detected: boolean
+  providers: string[]
+  llama_cpp: { installed: boolean; rpc_server: boolean; inference_server: boolean }
+}
+
+export interface SetupInitResult {
+  ok: boolean
+  already_initialized: boolean
+  created_roles: string[]
+  created_settings: string[]
+  /** Present only on the call that generated it */
+  admin_token?: string
+}
+
 // ─── Inference Backends ───────────────────────────────────────────────────────
 
 export type BackendType = 'llamacpp' | 'ollama' | 'lmstudio' | 'vllm' | 'openai' | 'custom'
@@ -263,6 +1189,21 @@ export interface BackendConfig {
   url: string
   model: string
   api_key?: string
+  api_key_set?: boolean
+  /** Profile the active configuration was copied from; null after a direct edit */
+  active_profile?: string | null
+  active_profile_id?: string | null
+}
+
+export interface BackendProfile {
+  id: string
+  name: string
+  backend_type: BackendType
+  url: string
+  model: string
+  api_key_set: boolean
+  active: boolean
+  created_at: string
 }
 
 // ─── Model memory analysis ────────────────────────────────────────────────────
@@ -270,9 +1211,18 @@ export interface BackendConfig {
 export type FitStatus = 'fits_locally' | 'fits_distributed' | 'partial_gpu' | 'too_large'
 
 export interface ModelCheckResult {
+  /** All parts together for a split model */
   model_size_mb: number
+  /** Files the model is made of; more than 1 for a split model */
+  shard_count: number
+  /** Draft model size, counted against local memory */
+  draft_size_mb: number
+  /** LoRA adapters, loaded with the model */
+  lora_size_mb: number
   estimated_layers: number
   local_free_mb: number
+  /** Kept free for the host OS; already left out of local_free_mb */
+  host_reserved_mb: number
   cluster_free_mb: number
   total_available_mb: number
   fit_status: FitStatus
@@ -280,4 +1230,132 @@ export interface ModelCheckResult {
   recommended_n_gpu_layers: number
   recommended_ctx_size: number
   warnings: string[]
+  /** Free memory of each selected device */
+  device_breakdown: DeviceMemory[]
+  /** Estimated KV cache for the checked ctx_size, shared by all slots */
+  kv_cache_mb: number
+  /** Ways to make a model that is too large fit */
+  suggestions: FitSuggestion[]
+}
+
+export interface FitSuggestion {
+  kind: 'quantize' | 'add_devices'
+  message: string
+  /** Quantization to use instead, for 'quantize' */
+  quant?: string
+  /** Estimated model size after the change */
+  model_size_mb: number
+  /** Cluster memory to add, for 'add_devices' */
+  additional_mb: number
+  fit_status: FitStatus
+}
+
+export interface DeviceMemory {
+  device_id: string
+  name: string
+  free_mb: number
+  /** Read from the device for this check, not reused from the last few seconds */
+  live: boolean
+  /** The device couldn't be read; free_mb is the last stored value */
+  stale: boolean
+  rpc_cache_enabled: boolean
+  rpc_cache_mb: number
+  /** The RPC cache is at least as large as this device's share of the model */
+  cache_likely: boolean
+  /** free_mb was set by hand ("manually configured") */
+  manual_override: boolean
+}
+
+// ─── Disk space ───────────────────────────────────────────────────────────────
+
+export interface DiskLocation {
+  kind: 'temp' | 'sharedmem' | 'models'
+  path: string
+  /** null when the filesystem can't be identified */
+  space: { mount_point: string; total_bytes: number; available_bytes: number } | null
+}
+
+export interface GpuDriver {
+  vendor: 'nvidia' | 'amd' | 'apple'
+  name?: string | null
+  version: string
+}
+
+/** GET /api/system/info */
+export interface SystemInfo {
+  hostname?: string | null
+  os_version?: string | null
+  kernel_version?: string | null
+  arch?: string | null
+  cpu_model?: string | null
+  cpu_cores?: number | null
+  /** Logical CPUs, counting hyper-threads */
+  cpu_threads: number
+  memory_total_mb: number
+  model_dir: string
+  /** null when the filesystem can't be identified */
+  model_disk: { mount_point: string; total_bytes: number; available_bytes: number } | null
+  gpu_drivers: GpuDriver[]
+  collected_at: string
+}
+
+// ─── Diagnostics ──────────────────────────────────────────────────────────────
+
+export type CheckStatus = 'pass' | 'warn' | 'fail'
+
+export interface DiagnosticCheck {
+  /** e.g. `database`, `port 8181`, `llama-server`, `provider nvidia` */
+  name: string
+  status: CheckStatus
+  detail: string
+  /** What to do about a warning or failure */
+  hint?: string | null
+  duration_ms: number
+}
+
+/** GET /api/diagnostics */
+export interface DiagnosticsReport {
+  checks: DiagnosticCheck[]
+  passed: number
+  warned: number
+  failed: number
+  /** Where the server listens */
+  listen?: {
+    bind_address: string
+    source: 'env' | 'setting' | 'default'
+    port: number
+    /** What mDNS and the agent scripts give out; null on loopback */
+    advertised_ip?: string | null
+    /** false on loopback: agents elsewhere need a reverse proxy */
+    remote_reachable: boolean
+  }
+}
+
+export interface DiskReport {
+  locations: DiskLocation[]
+  partial_files: { path: string; bytes: number }[]
+  reclaimable_bytes: number
+}
+
+/** A copy of the database taken before migrations ran */
+export interface DatabaseBackup {
+  name: string
+  size_bytes: number
+  created_at: string
+}
+
+/** GET /api/system/backups */
+export interface BackupList {
+  /** null for an in-memory database */
+  database: string | null
+  /** Newest first */
+  backups: DatabaseBackup[]
+  keep: number
+}
+
+/** POST /api/system/backups/restore */
+export interface RestoreStarted {
+  name: string
+  restarting: boolean
+  instructions: string | null
 }